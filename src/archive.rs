@@ -0,0 +1,386 @@
+use crate::budget::Group;
+use crate::content::{FileContent, FileRecord, SkipReason};
+use crate::provenance::Provenance;
+use crate::timestamp;
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Builds a tar archive holding the included files under `name_for(path)`,
+/// plus `TREE.txt` with the rendered tree and `MANIFEST.json` listing the
+/// skipped files and described images, the `--provenance` record when
+/// given, and what the limits in `budget` cut. Oversized files become stub
+/// entries noting their size.
+/// `fixed_mtime` replaces the time of every entry, for `--reproducible`.
+pub fn build_tar<F>(
+    tree: &str,
+    records: &[FileRecord],
+    provenance: Option<&Provenance>,
+    budget: &[Group],
+    fixed_mtime: Option<u64>,
+    name_for: F,
+) -> Result<Vec<u8>>
+where
+    F: Fn(&Path) -> String,
+{
+    let now = fixed_mtime.unwrap_or_else(|| timestamp::unix_now().max(0) as u64);
+    let mut archive = Vec::new();
+    append_entry(&mut archive, "TREE.txt", tree.as_bytes(), now)?;
+
+    let mut skipped = Vec::new();
+    for record in records {
+        let name = name_for(&record.path);
+        let mtime = fixed_mtime
+            .or_else(|| modified_time(&record.path))
+            .unwrap_or(now);
+        match &record.content {
+            FileContent::Text(text) => append_entry(&mut archive, &name, text.as_bytes(), mtime)?,
+            FileContent::Binary(bytes) => append_entry(&mut archive, &name, bytes, mtime)?,
+            // A description is no stand-in for the file in an archive.
+            FileContent::Image { size, .. } => skipped.push((name, "image", Some(*size))),
+            FileContent::Skipped(reason) => {
+                let size = match reason {
+                    SkipReason::TooLarge { size, .. } => {
+                        let stub = format!("{}\n", reason.marker());
+                        append_entry(&mut archive, &name, stub.as_bytes(), mtime)?;
+                        Some(*size)
+                    }
+                    _ => None,
+                };
+                skipped.push((name, reason.code(), size));
+            }
+        }
+    }
+
+    let manifest = render_manifest(&skipped, provenance, budget);
+    append_entry(&mut archive, "MANIFEST.json", manifest.as_bytes(), now)?;
+
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+    Ok(archive)
+}
+
+fn modified_time(path: &Path) -> Option<u64> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|elapsed| elapsed.as_secs())
+}
+
+/// `skipped` holds each file left without an entry: its name, reason
+/// code, and size when the reason notes one.
+fn render_manifest(
+    skipped: &[(String, &str, Option<u64>)],
+    provenance: Option<&Provenance>,
+    budget: &[Group],
+) -> String {
+    let items: Vec<String> = skipped
+        .iter()
+        .map(|(name, code, size)| {
+            let size = size.map_or_else(String::new, |size| format!(", \"size\": {}", size));
+            format!(
+                "    {{\"path\": {}, \"reason\": \"{}\"{}}}",
+                json_string(name),
+                code,
+                size
+            )
+        })
+        .collect();
+
+    let mut head = provenance.map_or_else(String::new, |provenance| {
+        format!("  \"provenance\": {},\n", render_provenance(provenance))
+    });
+    // One group per line, like the skipped files.
+    if !budget.is_empty() {
+        let groups: Vec<String> = budget
+            .iter()
+            .map(|group| {
+                format!(
+                    "    {}",
+                    serde_json::to_string(group).expect("a budget group serializes")
+                )
+            })
+            .collect();
+        head.push_str(&format!("  \"budget\": [\n{}\n  ],\n", groups.join(",\n")));
+    }
+    if items.is_empty() {
+        format!("{{\n{}  \"skipped\": []\n}}\n", head)
+    } else {
+        format!(
+            "{{\n{}  \"skipped\": [\n{}\n  ]\n}}\n",
+            head,
+            items.join(",\n")
+        )
+    }
+}
+
+/// The `--provenance` fields as one JSON object, lists as arrays.
+fn render_provenance(provenance: &Provenance) -> String {
+    let list = |values: &[String]| {
+        let items: Vec<String> = values.iter().map(|value| json_string(value)).collect();
+        format!("[{}]", items.join(", "))
+    };
+    let mut fields = vec![
+        format!("\"version\": {}", json_string(&provenance.version)),
+        format!("\"args\": {}", list(&provenance.args)),
+        format!("\"config\": {}", list(&provenance.config)),
+        format!("\"profiles\": {}", list(&provenance.profiles)),
+        format!("\"env\": {}", list(&provenance.env)),
+    ];
+    fields.extend(
+        provenance
+            .time
+            .as_ref()
+            .map(|time| format!("\"time\": {}", json_string(time))),
+    );
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Appends one regular-file entry. Names longer than the 100-byte ustar
+/// field are split into prefix/name, falling back to a GNU long-name record.
+fn append_entry(archive: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) -> Result<()> {
+    match split_ustar_name(name) {
+        Some((prefix, short_name)) => {
+            archive.extend_from_slice(&header(short_name, prefix, data.len() as u64, mtime, b'0')?);
+        }
+        None => {
+            let mut long_name = name.as_bytes().to_vec();
+            long_name.push(0);
+            archive.extend_from_slice(&header(
+                "././@LongLink",
+                "",
+                long_name.len() as u64,
+                0,
+                b'L',
+            )?);
+            append_padded(archive, &long_name);
+            let truncated = truncate_to_boundary(name, 100);
+            archive.extend_from_slice(&header(truncated, "", data.len() as u64, mtime, b'0')?);
+        }
+    }
+    append_padded(archive, data);
+    Ok(())
+}
+
+fn append_padded(archive: &mut Vec<u8>, data: &[u8]) {
+    archive.extend_from_slice(data);
+    let remainder = data.len() % BLOCK_SIZE;
+    if remainder != 0 {
+        archive.resize(archive.len() + BLOCK_SIZE - remainder, 0);
+    }
+}
+
+fn split_ustar_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(index, _)| (&name[..index], &name[index + 1..]))
+        .find(|(prefix, short_name)| {
+            prefix.len() <= 155 && short_name.len() <= 100 && !short_name.is_empty()
+        })
+}
+
+fn truncate_to_boundary(value: &str, max_len: usize) -> &str {
+    let mut end = value.len().min(max_len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+fn header(name: &str, prefix: &str, size: u64, mtime: u64, kind: u8) -> Result<[u8; BLOCK_SIZE]> {
+    if size >= 8u64.pow(11) {
+        bail!("File too large for a tar entry: {}", name);
+    }
+
+    let mut block = [0u8; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut block[100..108], 0o644);
+    write_octal(&mut block[108..116], 0);
+    write_octal(&mut block[116..124], 0);
+    write_octal(&mut block[124..136], size);
+    write_octal(&mut block[136..148], mtime.min(8u64.pow(11) - 1));
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&byte| u32::from(byte)).sum();
+    write_octal(&mut block[148..155], u64::from(checksum));
+    Ok(block)
+}
+
+/// Writes a zero-padded, NUL-terminated octal number filling `field`.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kind::FileKind;
+    use std::path::PathBuf;
+
+    /// Minimal reader for the subset of tar written above.
+    fn read_entries(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        let mut long_name = None;
+        while offset + BLOCK_SIZE <= archive.len() {
+            let block = &archive[offset..offset + BLOCK_SIZE];
+            if block.iter().all(|&byte| byte == 0) {
+                break;
+            }
+            let field = |range: std::ops::Range<usize>| {
+                let raw = &block[range];
+                let end = raw.iter().position(|&byte| byte == 0).unwrap_or(raw.len());
+                String::from_utf8(raw[..end].to_vec()).expect("utf-8 field")
+            };
+            let size = usize::from_str_radix(&field(124..135), 8).expect("octal size");
+            let data = archive[offset + BLOCK_SIZE..offset + BLOCK_SIZE + size].to_vec();
+            offset += BLOCK_SIZE + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+            if block[156] == b'L' {
+                long_name = Some(String::from_utf8(data[..size - 1].to_vec()).expect("name"));
+                continue;
+            }
+            let prefix = field(345..500);
+            let name = long_name.take().unwrap_or_else(|| {
+                if prefix.is_empty() {
+                    field(0..100)
+                } else {
+                    format!("{}/{}", prefix, field(0..100))
+                }
+            });
+            entries.push((name, data));
+        }
+        entries
+    }
+
+    fn record(path: &str, content: FileContent) -> FileRecord {
+        FileRecord {
+            path: PathBuf::from(path),
+            content,
+        }
+    }
+
+    #[test]
+    fn round_trips_files_tree_and_manifest() {
+        let long_path = format!("src/{}/lib.rs", "nested".repeat(30));
+        let records = vec![
+            record(
+                "src/main.rs",
+                FileContent::Text("fn main() {}\n".to_string()),
+            ),
+            record(
+                &long_path,
+                FileContent::Text("pub fn deep() {}\n".to_string()),
+            ),
+            record(
+                "assets/big.txt",
+                FileContent::Skipped(SkipReason::TooLarge {
+                    size: 20_000,
+                    limit: 16_384,
+                }),
+            ),
+            record(
+                "assets/logo.png",
+                FileContent::Skipped(SkipReason::Binary {
+                    kind: FileKind::Image,
+                }),
+            ),
+        ];
+
+        let provenance = Provenance {
+            version: "0.1.0".to_string(),
+            args: vec!["--format".to_string(), "tar".to_string(), "src".to_string()],
+            config: Vec::new(),
+            profiles: vec!["api".to_string()],
+            env: Vec::new(),
+            time: None,
+        };
+        let archive = build_tar(
+            ".\n└─ src\n",
+            &records,
+            Some(&provenance),
+            &[],
+            None,
+            |path| path.to_string_lossy().into_owned(),
+        )
+        .expect("build tar");
+        let entries = read_entries(&archive);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "TREE.txt",
+                "src/main.rs",
+                long_path.as_str(),
+                "assets/big.txt",
+                "MANIFEST.json"
+            ]
+        );
+        assert_eq!(entries[0].1, b".\n\xe2\x94\x94\xe2\x94\x80 src\n");
+        assert_eq!(entries[1].1, b"fn main() {}\n");
+        assert_eq!(entries[2].1, b"pub fn deep() {}\n");
+        assert_eq!(
+            entries[3].1,
+            b"<skipped reason=\"too-large\" size=\"20000\" limit=\"16384\"> file size 20000 bytes exceeds --max-file-bytes 16384\n"
+        );
+        let manifest = String::from_utf8(entries[4].1.clone()).expect("manifest utf-8");
+        assert!(manifest.contains(
+            "{\"path\": \"assets/big.txt\", \"reason\": \"too-large\", \"size\": 20000}"
+        ));
+        assert!(manifest.contains("{\"path\": \"assets/logo.png\", \"reason\": \"binary\"}"));
+        assert!(manifest.contains(
+            "\"provenance\": {\"version\": \"0.1.0\", \"args\": [\"--format\", \"tar\", \"src\"], \"config\": [], \"profiles\": [\"api\"], \"env\": []},\n"
+        ));
+    }
+
+    #[test]
+    fn header_checksum_matches_tar_definition() {
+        let block = header("a.txt", "", 3, 0, b'0').expect("header");
+        let stored = u32::from_str_radix(
+            std::str::from_utf8(&block[148..154]).expect("checksum digits"),
+            8,
+        )
+        .expect("octal checksum");
+        let mut blanked = block;
+        blanked[148..156].fill(b' ');
+        let expected: u32 = blanked.iter().map(|&byte| u32::from(byte)).sum();
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    fn escapes_manifest_strings() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+    }
+}
@@ -1,30 +1,1504 @@
-use clap::Parser;
+use crate::encode::Encoding;
+use crate::eol::Eol;
+use crate::filter;
+use crate::fit::Strategy;
+use crate::format::{Bare, Format};
+use crate::kind::FileKind;
+use crate::launch::Viewer;
+use crate::output::{ClipboardKind, Compression};
+use crate::post;
+use crate::report::StatusStream;
+use crate::restore::Verify;
+use crate::style::ColorChoice;
+use crate::walker::{GitDir, IgnoreRules};
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 
 /// A tool to copy the directory structure and file contents to the clipboard.
+///
+/// Without a subcommand, copytree behaves like `copytree copy`.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-pub struct Args {
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub copy: Args,
+}
+
+/// Options deciding which files are walked and which of them contribute
+/// contents, shared by `copy`, `tree`, and `stats`.
+#[derive(clap::Args, Debug, Clone)]
+pub struct WalkArgs {
     /// Paths to process (default: current directory).
     #[arg(default_value = ".")]
     pub paths: Vec<String>,
 
-    /// Glob patterns to exclude.
+    /// Glob patterns of files to leave out of both the tree and the contents
+    /// [env: COPYTREE_EXCLUDE, separated like PATH].
     #[arg(short = 'x', long, num_args = 1..)]
     pub exclude: Vec<String>,
 
-    /// Maximum size (in bytes) of file contents to include; use 0 to disable.
-    #[arg(long, value_name = "BYTES", default_value_t = 16 * 1024, env = "MAX_FILE_BYTES")]
-    pub max_file_bytes: usize,
+    /// Keep files matching GLOB in the tree but put a skip marker in place
+    /// of their contents. Repeatable.
+    #[arg(long, value_name = "GLOB")]
+    pub exclude_contents: Vec<String>,
+
+    /// Keep only the first N files (by name) of any single directory; the
+    /// tree notes how many more there are. Files named as paths are exempt.
+    #[arg(long, value_name = "N")]
+    pub max_per_dir: Option<NonZeroUsize>,
+
+    /// Keep N files picked uniformly at random among those not excluded;
+    /// files named as paths and always-included files come on top.
+    #[arg(long, value_name = "N")]
+    pub sample: Option<NonZeroUsize>,
+
+    /// Seed for --sample, to pick the same files again (default: the clock).
+    #[arg(long, value_name = "S", requires = "sample")]
+    pub seed: Option<u64>,
+
+    /// Do not descend into directories matching GLOB at all; a pattern is
+    /// tried against the directory's name and its path. Repeatable.
+    #[arg(long, value_name = "GLOB")]
+    pub prune: Vec<String>,
+
+    /// Enter symlinks to directories, through at most N of them along any
+    /// one path; a link past the limit is listed as skipped (default: 0,
+    /// links to directories are not entered).
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub max_symlink_depth: usize,
+
+    /// Keep only files of KIND, told from their first bytes and then their
+    /// name: text (which also covers code and config), code, config, image,
+    /// archive, document, or binary. Repeatable.
+    #[arg(long = "type", value_enum, value_name = "KIND")]
+    pub types: Vec<FileKind>,
+
+    /// Keep only files that differ from git ref REF in the working tree,
+    /// or are untracked; every repository the roots are in is compared
+    /// with the same ref. Files named as paths are exempt.
+    #[arg(long, value_name = "REF")]
+    pub changed_since: Option<String>,
+
+    /// Maximum size of file contents to include, e.g. 512, 16K, or 2MiB; use 0 to disable
+    /// [env: COPYTREE_MAX_FILE_BYTES, MAX_FILE_BYTES].
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "16KiB",
+        value_parser = parse_size,
+        env = "MAX_FILE_BYTES",
+        hide_env = true
+    )]
+    pub max_file_bytes: u64,
+
+    /// List the entries of zip and tar (optionally gzipped) files, up to N
+    /// per archive (default 100), instead of skipping them as binary.
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "100"
+    )]
+    pub list_archives: Option<NonZeroUsize>,
 
-    /// Do not respect .gitignore files.
+    /// Include binary files of at most MAXBYTES (default 64KiB) whole, as
+    /// base64 under a header noting their size, instead of skipping them;
+    /// `copytree restore` writes them back byte for byte.
+    #[arg(long, value_name = "base64[:MAXBYTES]", value_parser = parse_include_binary)]
+    pub include_binary: Option<u64>,
+
+    /// Leave the detected content type (`image/png`, `application/gzip`)
+    /// out of `copytree stats` and --report-json, which otherwise read the
+    /// first 8 KiB of every file; for slow file systems.
+    #[arg(long)]
+    pub no_sniff: bool,
+
+    /// Show paths with the platform's separators instead of forward
+    /// slashes; only changes the output on Windows.
+    #[arg(long)]
+    pub native_separators: bool,
+
+    /// Show the tree, headers, and logged paths relative to BASE instead of
+    /// the current directory; paths outside it are shown in full.
+    #[arg(long, value_name = "BASE")]
+    pub relative_to: Option<String>,
+
+    /// Show the tree, headers, and logged paths as canonical absolute
+    /// paths, with one tree per root labelled by its path; for bundling
+    /// unrelated repositories in one run.
+    #[arg(long)]
+    pub absolute_paths: bool,
+
+    /// With --absolute-paths, trim the directory PATH off the front of the
+    /// paths under it; other paths stay whole.
+    #[arg(long, value_name = "PATH")]
+    pub strip_prefix: Option<String>,
+
+    /// Do not respect .gitignore files [env: COPYTREE_NO_GITIGNORE].
     #[arg(long)]
     pub no_gitignore: bool,
 
-    /// Print to standard output instead of the clipboard.
+    /// Do not respect the repository's .git/info/exclude.
+    #[arg(long)]
+    pub no_git_exclude: bool,
+
+    /// Do not read ignore files from the directories above each path.
+    #[arg(long)]
+    pub no_parent_ignore: bool,
+
+    /// Apply .gitignore files only inside a git repository.
+    #[arg(long)]
+    pub require_git: bool,
+
+    /// Walk everything: no .gitignore, git excludes, or .ignore files, and
+    /// include hidden files. Overrides the narrower flags; --exclude still
+    /// applies.
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Walk .git directories too, except their objects/; `=full` includes
+    /// objects/ as well. Binary files are still skipped.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "metadata"
+    )]
+    pub include_git_dir: Option<GitDir>,
+
+    /// Fail when a path refers to an unset environment variable instead of
+    /// leaving `$VAR` as written.
+    #[arg(long)]
+    pub strict_vars: bool,
+
+    /// Do not load defaults from the user or project config files.
+    #[arg(long)]
+    pub no_config: bool,
+
+    /// Apply a `[profile.NAME]` table from the config files; repeatable,
+    /// later profiles override earlier ones.
+    #[arg(long, value_name = "NAME", conflicts_with = "no_config")]
+    pub profile: Vec<String>,
+
+    /// Always include files matching GLOB, even when ignored, hidden, or excluded; repeatable
+    #[arg(long, value_name = "GLOB")]
+    pub always_include: Vec<String>,
+
+    /// Files from the project config's `always_include`, exempt from
+    /// excludes and the size limit.
+    #[arg(skip)]
+    pub always_include_files: Vec<PathBuf>,
+
+    /// `--relative-to` resolved to a canonical path, once the roots are
+    /// made absolute to match it. Under `--absolute-paths` it is the
+    /// resolved `--strip-prefix`, or empty so the paths stay absolute.
+    #[arg(skip)]
+    pub relative_base: Option<PathBuf>,
+}
+
+impl Args {
+    /// Applies what `--reproducible` pins: settings whose effect on the
+    /// output depends on the platform, the terminal, or the clock.
+    pub fn pin_reproducible(&mut self) {
+        self.walk.native_separators = false;
+        self.no_timestamp = true;
+        self.color = ColorChoice::Never;
+    }
+
+    /// Where the output is served, from `--serve` or `--serve-addr`.
+    pub fn serve_address(&self) -> Option<SocketAddr> {
+        self.serve.or(self.serve_addr)
+    }
+}
+
+impl WalkArgs {
+    /// The directory displayed paths are relative to.
+    pub fn display_base<'a>(&'a self, current_dir: &'a Path) -> &'a Path {
+        self.relative_base.as_deref().unwrap_or(current_dir)
+    }
+
+    /// The ignore files the walk honors, from the `--no-gitignore` family
+    /// and `--include-git-dir`, and how far it follows directory symlinks.
+    pub fn ignore_rules(&self) -> IgnoreRules {
+        let rules = if self.no_ignore {
+            IgnoreRules::NONE
+        } else {
+            IgnoreRules {
+                gitignore: !self.no_gitignore,
+                git_exclude: !self.no_git_exclude,
+                parents: !self.no_parent_ignore,
+                require_git: self.require_git,
+                ..IgnoreRules::default()
+            }
+        };
+        IgnoreRules {
+            git_dir: self.include_git_dir,
+            max_symlink_depth: self.max_symlink_depth,
+            ..rules
+        }
+    }
+}
+
+/// Options of `copytree copy`, the default command.
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(flatten)]
+    pub walk: WalkArgs,
+
+    /// Output format [env: COPYTREE_FORMAT].
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    pub format: Format,
+
+    /// Shape the output with the template in FILE, or a built-in one named
+    /// `@prompt` or `@review`, instead of the plain format.
+    #[arg(long, value_name = "FILE")]
+    pub template: Option<String>,
+
+    /// Number of threads reading file contents (default: available CPUs).
+    #[arg(long, value_name = "N")]
+    pub read_threads: Option<NonZeroUsize>,
+
+    /// Reuse file contents from earlier runs while a file's size and
+    /// modification time are unchanged, keeping them in DIR (default: the
+    /// user cache directory).
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    pub cache: Option<Option<String>>,
+
+    /// Pipe each text file through the shell command CMD and emit what it
+    /// prints instead; `{}` or $COPYTREE_PATH gives the file's path. A
+    /// failing or slow command leaves the file as it was.
+    #[arg(long, value_name = "CMD")]
+    pub filter_cmd: Option<String>,
+
+    /// Like --filter-cmd for the files matching GLOB only, e.g.
+    /// `**/*.sql=sqlformat -`; the first matching rule wins. Repeatable.
+    #[arg(long, value_name = "GLOB=CMD")]
+    pub filter_cmd_for: Vec<String>,
+
+    /// Seconds a filter command may run before it is killed.
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub filter_timeout: u64,
+
+    /// Pick the files to include from a list in the terminal after the walk.
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// With --interactive, pre-check the files chosen last time.
+    #[arg(long, requires = "interactive")]
+    pub last: bool,
+
+    /// Print to standard output instead of the clipboard (the default when stdout is piped).
     #[arg(long)]
     pub stdout: bool,
 
-    /// Output to a file instead of the clipboard.
+    /// Output to a file instead of the clipboard ("-" means standard output); repeatable
+    /// [env: COPYTREE_OUT].
     #[arg(long, value_name = "FILE")]
-    pub out: Option<String>,
+    pub out: Vec<String>,
+
+    /// Compress --out files (inferred from a .gz or .zst extension when omitted).
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    pub compress: Option<Compression>,
+
+    /// Encode the whole output for transports that mangle whitespace or
+    /// non-ASCII text; `copytree decode` turns it back.
+    #[arg(long, value_enum, value_name = "ENCODING")]
+    pub encode: Option<Encoding>,
+
+    /// End the lines copytree writes (tree, headers, separators) in LF, CRLF,
+    /// or the platform's own; file bodies keep theirs unless
+    /// --output-eol-content is given.
+    #[arg(long, value_enum, value_name = "EOL", default_value_t = Eol::Lf)]
+    pub output_eol: Eol,
+
+    /// Give file bodies the --output-eol line ending as well; lines already
+    /// ending in CRLF are left alone.
+    #[arg(long)]
+    pub output_eol_content: bool,
+
+    /// Run the whole output through the shell command CMD (e.g. `gzip` or a
+    /// scrubber) and send what it prints to the sinks; a command that fails
+    /// fails the run.
+    #[arg(long, value_name = "CMD")]
+    pub pipe_cmd: Option<String>,
+
+    /// Seconds --pipe-cmd may run once it has the whole output before it is
+    /// killed.
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub pipe_timeout: u64,
+
+    /// Copy to the clipboard even when stdout is piped or --stdout/--out is given.
+    #[arg(long)]
+    pub clipboard: bool,
+
+    /// Serve the output over HTTP at `/` instead of copying it, printing the
+    /// address once listening; ADDR must be a loopback address (port 0 picks
+    /// a free one).
+    #[arg(
+        long,
+        value_name = "ADDR:PORT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "127.0.0.1:0",
+        conflicts_with = "serve_addr"
+    )]
+    pub serve: Option<SocketAddr>,
+
+    /// Like --serve, but on any address, e.g. 0.0.0.0:8080 to let other
+    /// machines fetch the output.
+    #[arg(long, value_name = "ADDR:PORT")]
+    pub serve_addr: Option<SocketAddr>,
+
+    /// Stop serving after the output has been fetched once, instead of
+    /// serving until interrupted.
+    #[arg(long)]
+    pub serve_once: bool,
+
+    /// POST the output to URL, e.g. a paste service, and print the reply;
+    /// if the request fails, the output is written to a temp file instead.
+    #[arg(long, value_name = "URL")]
+    pub post: Option<String>,
+
+    /// Add a `Name: value` header to the --post request, e.g. for
+    /// authorization; repeatable.
+    #[arg(long, value_name = "HEADER")]
+    pub post_header: Vec<String>,
+
+    /// Append to the --out file instead of overwriting it.
+    #[arg(long)]
+    pub append: bool,
+
+    /// Overwrite the --out file if it already exists.
+    #[arg(long)]
+    pub force: bool,
+
+    /// Create missing parent directories of the --out file.
+    #[arg(long)]
+    pub mkdirs: bool,
+
+    /// Start the output with a line recording the copytree version, the
+    /// settings in effect (config files and profiles applied), and the time.
+    #[arg(long)]
+    pub provenance: bool,
+
+    /// Leave the time out of the --provenance line, for reproducible snapshots.
+    #[arg(long)]
+    pub no_timestamp: bool,
+
+    /// Make the output byte-identical across machines, locales, and runs:
+    /// forward slashes, no time in --provenance or tar entries, no colors.
+    #[arg(long)]
+    pub reproducible: bool,
+
+    /// When exactly one file is included, output only its content: no tree,
+    /// header, or markers. More files are an error, or the normal output with --bare=loose.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "strict"
+    )]
+    pub bare: Option<Bare>,
+
+    /// Emit file contents in the order the walk found them, instead of the
+    /// order the tree lists them in.
+    #[arg(long)]
+    pub walk_order: bool,
+
+    /// Emit each directory's README files before its other files and subdirectories.
+    #[arg(long)]
+    pub readme_first: bool,
+
+    /// Print only the paths of the files a copy would include, one per line
+    /// in the order of their sections, without the tree or reading any file.
+    #[arg(long)]
+    pub list: bool,
+
+    /// Omit the directory tree (with --append, only when appending to existing content).
+    #[arg(long)]
+    pub no_tree: bool,
+
+    /// Annotate each file in the tree with its estimated tokens, and each
+    /// directory with the sum of its files.
+    #[arg(long)]
+    pub tree_tokens: bool,
+
+    /// Elide the middle of names so tree lines fit in N columns on stdout
+    /// (default: the terminal's width; 0 never elides). The clipboard and
+    /// --out files always get whole names.
+    #[arg(long, value_name = "N")]
+    pub tree_width: Option<usize>,
+
+    /// Put a link to each file on GitHub, GitLab, or Bitbucket at the
+    /// current commit under its header, from the `origin` remote.
+    #[arg(long)]
+    pub permalinks: bool,
+
+    /// Fit the files into N estimated tokens by dropping whole files, picked
+    /// by --fit-strategy; files named as paths and always-included files stay.
+    #[arg(long, value_name = "N")]
+    pub fit_tokens: Option<u64>,
+
+    /// Which files --fit-tokens drops first.
+    #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = Strategy::Largest)]
+    pub fit_strategy: Strategy,
+
+    /// Leave out the report closing the output on which files
+    /// --max-file-bytes, --fit-tokens, and --max-per-dir cut, and the values
+    /// that would have kept them.
+    #[arg(long)]
+    pub no_budget_report: bool,
+
+    /// Leave skipped files' sections out of the contents: no header, no skip
+    /// marker. They stay in the tree, the summary, and the JSON report.
+    #[arg(long)]
+    pub omit_skipped: bool,
+
+    /// After writing, open the output file in $VISUAL/$EDITOR (or a pager with --open=pager).
+    #[arg(
+        long,
+        value_enum,
+        value_name = "VIEWER",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "editor"
+    )]
+    pub open: Option<Viewer>,
+
+    /// Write a JSON report of included/skipped files and totals to FILE (stderr when omitted).
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "-")]
+    pub report_json: Option<String>,
+
+    /// End the run with one JSON line on stderr, or on stdout with
+    /// `--status-json=stdout`: the sinks, bytes written, included and
+    /// skipped files, duration, exit status, and any error.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STREAM",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "stderr"
+    )]
+    pub status_json: Option<StatusStream>,
+
+    /// Write a JSON manifest of the output to PATH (default: the --out file
+    /// with .manifest.json appended): each included file's size, SHA-256,
+    /// and byte range in the output, the skipped files, totals, and
+    /// provenance.
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    pub manifest: Option<Option<String>>,
+
+    /// Exit with status 2 when any file is skipped as binary, too large, or unreadable.
+    #[arg(long)]
+    pub fail_on_skip: bool,
+
+    /// On Ctrl-C, write the files read so far followed by an
+    /// `<interrupted: N files not processed>` line instead of nothing, and
+    /// exit with status 130 either way.
+    #[arg(long)]
+    pub partial_on_interrupt: bool,
+
+    /// Compare the output with the existing --out file instead of writing
+    /// it: exit 0 when they match, 3 when the file differs or is missing.
+    #[arg(long)]
+    pub check: bool,
+
+    /// With --check, rewrite an out-of-date --out file; still exits 3.
+    #[arg(long)]
+    pub fix: bool,
+
+    /// Suppress all non-error messages on stderr.
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also report every per-file decision on stderr.
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Fail instead of falling back to a temp file when the clipboard is unavailable.
+    #[arg(long)]
+    pub require_clipboard: bool,
+
+    /// Largest output copied to the clipboard as is; a larger one is written
+    /// to a temp file and its path copied instead, since some clipboards fail
+    /// or hang on big payloads.
+    #[arg(long, value_name = "SIZE", default_value = "8MiB", value_parser = parse_size)]
+    pub clipboard_limit: u64,
+
+    /// Copy the output to the clipboard however large it is.
+    #[arg(long)]
+    pub force_clipboard: bool,
+
+    /// Add the output to the clipboard after the text already there,
+    /// separated as with --append, instead of replacing it.
+    #[arg(long)]
+    pub clipboard_append: bool,
+
+    /// What puts the output on the clipboard: `auto` tries the system
+    /// clipboard, then --clipboard-cmd or a command found for the session
+    /// (wl-copy, xclip, xsel, pbcopy, clip.exe).
+    #[arg(long, value_enum, value_name = "BACKEND", default_value_t = ClipboardKind::Auto)]
+    pub clipboard_backend: ClipboardKind,
+
+    /// Program that receives the output on stdin to put it on the clipboard,
+    /// run through the shell, e.g. 'wl-copy' or 'xclip -selection clipboard'.
+    #[arg(long, value_name = "CMD")]
+    pub clipboard_cmd: Option<String>,
+
+    /// Most output held in memory for the clipboard; past it the output is
+    /// written to a temp file as it is assembled, and that file's path is
+    /// copied instead.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_memory: Option<u64>,
+
+    /// Color the tree on stdout and the sizes in summary lines; `auto` colors
+    /// terminals unless NO_COLOR is set. The clipboard and --out files never get colors.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+}
+
+/// Rejects flag combinations that clap cannot express or that only become
+/// nonsensical once config defaults are applied. `given` tells whether an
+/// option (by field name) was typed on the command line; options that merely
+/// sit unused in a config file are not errors.
+pub fn validate<F>(args: &Args, given: F) -> Result<()>
+where
+    F: Fn(&str) -> bool,
+{
+    let out_files: Vec<&str> = args
+        .out
+        .iter()
+        .map(String::as_str)
+        .filter(|file_path| *file_path != "-")
+        .collect();
+
+    if args.format == Format::Tar {
+        if out_files.is_empty() && args.serve_address().is_none() {
+            bail!(
+                "--format tar requires --out <FILE> or --serve, e.g. --format tar --out snapshot.tar"
+            );
+        }
+        if args.stdout || args.clipboard || args.out.len() != out_files.len() {
+            bail!(
+                "--format tar writes a binary archive and cannot be sent to the clipboard or \
+                 stdout; drop --stdout/--clipboard/--out - and read the --out file instead"
+            );
+        }
+        if args.append {
+            bail!(
+                "--format tar cannot be combined with --append; write a new archive with --force"
+            );
+        }
+        if args.open.is_some() {
+            bail!("--open cannot show a tar archive; use it with the plain format");
+        }
+        if given("bare") {
+            bail!("--bare only applies to the plain format; the archive always holds its TREE.txt");
+        }
+        if args.permalinks {
+            bail!("--permalinks annotates file sections, which --format tar does not have");
+        }
+    }
+    if args.format == Format::Html {
+        if args.append {
+            bail!("--format html writes a whole page and cannot be combined with --append");
+        }
+        if given("bare") {
+            bail!("--bare only applies to the plain format; the page always shows its tree");
+        }
+    }
+    if args.template.is_some() {
+        if args.format != Format::Plain {
+            bail!(
+                "--template replaces the plain format and cannot be combined with --format {}",
+                match args.format {
+                    Format::Tar => "tar",
+                    _ => "html",
+                }
+            );
+        }
+        if given("bare") {
+            bail!(
+                "--bare writes one file's body, which leaves nothing for --template; pass only one"
+            );
+        }
+        if args.permalinks {
+            bail!("--permalinks annotates the plain format's sections, which --template replaces");
+        }
+    }
+    if args.append && args.force {
+        bail!(
+            "--append and --force contradict each other: --append adds to the --out file, \
+             --force replaces it; pass only one"
+        );
+    }
+    if args.append && args.encode.is_some() {
+        bail!(
+            "--encode cannot be combined with --append; an encoded file holds one run, \
+             so write a new one with --force"
+        );
+    }
+    if args.output_eol_content
+        && args.output_eol == Eol::Lf
+        && (given("output_eol_content") || given("output_eol"))
+    {
+        bail!(
+            "--output-eol-content converts file bodies to the --output-eol line ending; \
+             pass --output-eol crlf or native as well"
+        );
+    }
+    if args.output_eol != Eol::Lf {
+        if args.format != Format::Plain || args.template.is_some() {
+            bail!("--output-eol applies to the plain output; drop --format and --template");
+        }
+        if args.encode.is_some() {
+            bail!(
+                "--encode writes lines of its own, which --output-eol would not reach; \
+                 pass only one"
+            );
+        }
+    }
+    if args.append && out_files.is_empty() {
+        bail!("--append needs a file to add to; pass --out <FILE> as well");
+    }
+    if args.check {
+        let other_sink =
+            args.stdout || args.clipboard || args.serve_address().is_some() || args.post.is_some();
+        if out_files.len() != 1 || args.out.len() != 1 || other_sink {
+            bail!(
+                "--check compares the output with one file; pass exactly one --out <FILE> \
+                 and no other sink"
+            );
+        }
+        if args.append {
+            bail!("--check compares whole files and cannot be combined with --append");
+        }
+        if args.open.is_some() {
+            bail!("--check writes no output for --open to show");
+        }
+        if out_files[0].contains('{') {
+            bail!(
+                "--check needs a fixed --out path; drop the placeholders from `{}`",
+                out_files[0]
+            );
+        }
+    }
+    if given("fix") && !args.check {
+        bail!("--fix only applies to --check; pass --check as well");
+    }
+    if args.reproducible {
+        if args.append {
+            bail!(
+                "--reproducible cannot be combined with --append; the separator line between \
+                 runs carries the time, so write a new snapshot with --force"
+            );
+        }
+        if args.walk.sample.is_some() && args.walk.seed.is_none() {
+            bail!("--reproducible needs a fixed --seed for --sample, e.g. --sample 20 --seed 1");
+        }
+        if given("native_separators") {
+            bail!("--reproducible always writes forward slashes; drop --native-separators");
+        }
+    }
+    if given("no_timestamp") && !args.provenance {
+        bail!("--no-timestamp only applies to the --provenance line; pass --provenance as well");
+    }
+    if args.bare == Some(Bare::Strict) && args.provenance && (given("bare") || given("provenance"))
+    {
+        bail!(
+            "--bare output is the file's content alone, with no room for the --provenance line; \
+             pass --bare=loose to keep it when more than one file is included"
+        );
+    }
+    if args.tree_tokens && args.no_tree && (given("tree_tokens") || given("no_tree")) {
+        bail!("--tree-tokens annotates the tree, which --no-tree leaves out; pass only one");
+    }
+    if args.tree_width.is_some() && args.no_tree && (given("tree_width") || given("no_tree")) {
+        bail!("--tree-width shapes the tree, which --no-tree leaves out; pass only one");
+    }
+    if given("fit_strategy") && args.fit_tokens.is_none() {
+        bail!("--fit-strategy only applies to --fit-tokens; pass --fit-tokens <N> as well");
+    }
+    if let Some(address) = args.serve.filter(|address| !address.ip().is_loopback()) {
+        bail!(
+            "--serve only listens on loopback addresses; use --serve-addr {} to accept \
+             connections from other machines",
+            address
+        );
+    }
+    if given("serve_once") && args.serve_address().is_none() {
+        bail!("--serve-once only applies to --serve; pass --serve as well");
+    }
+    if let Some(url) = args.post.as_deref() {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            bail!("--post needs an http:// or https:// URL, got `{}`", url);
+        }
+    }
+    if !args.post_header.is_empty() && args.post.is_none() {
+        bail!("--post-header only applies to --post; pass --post <URL> as well");
+    }
+    if let Some(header) = args
+        .post_header
+        .iter()
+        .find(|header| post::split_header(header).is_none())
+    {
+        bail!(
+            "--post-header `{}` is not a header; write it as `Name: value`",
+            header
+        );
+    }
+    if let Some(rule) = args
+        .filter_cmd_for
+        .iter()
+        .find(|rule| filter::split_rule(rule).is_none())
+    {
+        bail!(
+            "--filter-cmd-for `{}` is not a rule; write it as `GLOB=CMD`",
+            rule
+        );
+    }
+    if given("filter_timeout") && args.filter_cmd.is_none() && args.filter_cmd_for.is_empty() {
+        bail!("--filter-timeout only applies to --filter-cmd or --filter-cmd-for");
+    }
+    if given("pipe_timeout") && args.pipe_cmd.is_none() {
+        bail!("--pipe-timeout only applies to --pipe-cmd; pass --pipe-cmd <CMD> as well");
+    }
+    if args.clipboard_backend == ClipboardKind::Command && args.clipboard_cmd.is_none() {
+        bail!(
+            "--clipboard-backend command runs --clipboard-cmd; pass --clipboard-cmd <CMD> as well"
+        );
+    }
+    if args.clipboard_backend == ClipboardKind::Arboard && args.clipboard_cmd.is_some() {
+        bail!("--clipboard-backend arboard never runs --clipboard-cmd; pass only one");
+    }
+    if given("force_clipboard") && given("clipboard_limit") {
+        bail!("--force-clipboard ignores --clipboard-limit; pass only one");
+    }
+    if given("force_clipboard") && given("max_memory") {
+        bail!(
+            "--force-clipboard copies the output whole, which --max-memory keeps from being \
+             held; pass only one"
+        );
+    }
+    for (id, flag) in [
+        ("force", "--force"),
+        ("mkdirs", "--mkdirs"),
+        ("compress", "--compress"),
+    ] {
+        if given(id) && out_files.is_empty() {
+            bail!(
+                "{} only applies to --out files; pass --out <FILE> as well",
+                flag
+            );
+        }
+    }
+    for (index, file_path) in out_files.iter().enumerate() {
+        if out_files[..index].contains(file_path) {
+            bail!("--out {} is given more than once", file_path);
+        }
+    }
+    if let Some(report) = args.report_json.as_deref() {
+        if out_files.contains(&report) {
+            bail!(
+                "--report-json {} would overwrite the --out file of the same name; pick another path",
+                report
+            );
+        }
+    }
+    if args.status_json == Some(StatusStream::Stdout)
+        && (args.stdout || args.out.iter().any(|file_path| file_path == "-"))
+    {
+        bail!(
+            "--status-json=stdout would mix the status line into the output on stdout; \
+             use --status-json for stderr or send the output elsewhere"
+        );
+    }
+    if args.list {
+        if args.format != Format::Plain || args.template.is_some() {
+            bail!("--list prints one path per line; drop --format and --template");
+        }
+        for (id, flag) in [
+            ("bare", "--bare"),
+            ("tree_tokens", "--tree-tokens"),
+            ("fit_tokens", "--fit-tokens"),
+            ("permalinks", "--permalinks"),
+            ("provenance", "--provenance"),
+            ("manifest", "--manifest"),
+        ] {
+            if given(id) {
+                bail!(
+                    "{} shapes the contents, which --list does not write; pass only one",
+                    flag
+                );
+            }
+        }
+    }
+    if args.omit_skipped && (args.format != Format::Plain || args.template.is_some()) {
+        bail!("--omit-skipped drops sections of the plain output; drop --format and --template");
+    }
+    if args.partial_on_interrupt {
+        if args.format != Format::Plain || args.template.is_some() || args.bare.is_some() {
+            bail!(
+                "--partial-on-interrupt ends the plain output with a trailer line; drop \
+                 --format, --template, and --bare"
+            );
+        }
+        if args.check {
+            bail!(
+                "--check would compare partial output as if it were whole; drop \
+                 --partial-on-interrupt"
+            );
+        }
+    }
+    if let Some(manifest) = &args.manifest {
+        if args.format != Format::Plain || args.template.is_some() {
+            bail!(
+                "--manifest records where each file sits in the plain output; drop --format \
+                 and --template"
+            );
+        }
+        if args.bare.is_some() || args.append || args.check {
+            bail!(
+                "--manifest describes a whole output of its own; it cannot be combined with \
+                 --bare, --append, or --check"
+            );
+        }
+        if args.encode.is_some() || args.pipe_cmd.is_some() || args.output_eol != Eol::Lf {
+            bail!(
+                "--manifest ranges count the output as generated, which --encode, \
+                 --pipe-cmd, and --output-eol change; pass only one"
+            );
+        }
+        match manifest.as_deref() {
+            None if out_files.is_empty() => bail!(
+                "--manifest is named after the --out file; pass --out <FILE> or --manifest=PATH"
+            ),
+            Some(path)
+                if out_files.contains(&path) || args.report_json.as_deref() == Some(path) =>
+            {
+                bail!(
+                    "--manifest {} would overwrite another output of the same name; pick another path",
+                    path
+                )
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parses a byte count: a plain integer, or one with a `K`, `M`, or `G`
+/// suffix (case-insensitive). As in GNU tools, `K` and `KiB` are powers of
+/// 1024 while `KB` is a power of 1000.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    const EXPECTED: &str = "expected a size like 512, 16K, or 2MiB";
+    let trimmed = value.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (digits, suffix) = trimmed.split_at(split);
+    if digits.is_empty() {
+        return Err(format!("invalid size `{}`: {}", value, EXPECTED));
+    }
+    let multiplier: u64 = match suffix.trim_start().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "ki" | "kib" => 1 << 10,
+        "m" | "mi" | "mib" => 1 << 20,
+        "g" | "gi" | "gib" => 1 << 30,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        _ => return Err(format!("invalid size `{}`: {}", value, EXPECTED)),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(multiplier))
+        .ok_or_else(|| format!("size `{}` is too large", value))
+}
+
+/// Largest binary file `--include-binary` takes when no size is given.
+pub const DEFAULT_INCLUDE_BINARY: u64 = 64 << 10;
+
+/// Parses `--include-binary base64[:MAXBYTES]` into the size cap.
+pub fn parse_include_binary(value: &str) -> Result<u64, String> {
+    match value.trim().split_once(':') {
+        None if value.trim() == "base64" => Ok(DEFAULT_INCLUDE_BINARY),
+        Some(("base64", size)) => parse_size(size),
+        _ => Err(format!(
+            "invalid value `{}`: expected base64 or base64:MAXBYTES, e.g. base64:8KiB",
+            value
+        )),
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Copy the tree and file contents (the default when no subcommand is given).
+    Copy(Box<Args>),
+    /// Print only the directory tree to standard output.
+    Tree(TreeArgs),
+    /// Recreate files from copytree's plain-format output.
+    Restore(RestoreArgs),
+    /// Turn `--encode base64` output back into the original bytes.
+    Decode(DecodeArgs),
+    /// Show which files were added, removed, or changed between two outputs.
+    Diff(DiffArgs),
+    /// Count the files, bytes, and estimated tokens a copy would include.
+    Stats(StatsArgs),
+    /// Print a shell completion script to standard output.
+    Completions(CompletionsArgs),
+    /// Inspect the user configuration file.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Manage the file contents kept by `--cache`.
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// List the profiles defined in the config files and what each changes.
+    Profiles,
+    /// Check the clipboard, display, terminal, git, and config files, with a
+    /// hint for every problem found.
+    Doctor,
+    /// Render the man page from the CLI definition.
+    #[command(hide = true)]
+    Man(ManArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print where the user config file is expected.
+    Path,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Delete the cache, so the next `--cache` run reads every file.
+    Clear(CacheClearArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CacheClearArgs {
+    /// The directory given to `--cache`; the default one when omitted.
+    #[arg(value_name = "DIR")]
+    pub dir: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RestoreArgs {
+    /// A file written by `copytree --out` in the plain format.
+    #[arg(value_name = "INPUT")]
+    pub input: String,
+
+    /// Directory to recreate the files under.
+    #[arg(long, value_name = "DIR")]
+    pub dest: String,
+
+    /// Overwrite files that already exist under the destination, and write
+    /// paths that differ only in case even where the destination ignores case.
+    #[arg(long)]
+    pub force: bool,
+
+    /// List the files that would be written without writing them.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Check each restored file against the SHA-256 in the INPUT's
+    /// --manifest (INPUT.manifest.json) and list the ones that differ,
+    /// exiting with status 2; --verify=strict also deletes them.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "report"
+    )]
+    pub verify: Option<Verify>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DecodeArgs {
+    /// Encoded output to read; standard input when omitted or "-".
+    #[arg(value_name = "INPUT")]
+    pub input: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// The earlier plain-format output.
+    #[arg(value_name = "OLD")]
+    pub old: String,
+
+    /// The later plain-format output.
+    #[arg(value_name = "NEW")]
+    pub new: String,
+
+    /// Print to standard output instead of the clipboard (the default when stdout is piped).
+    #[arg(long)]
+    pub stdout: bool,
+
+    /// Output to a file instead of the clipboard ("-" means standard output); repeatable.
+    #[arg(long, value_name = "FILE")]
+    pub out: Vec<String>,
+
+    /// Copy to the clipboard even when stdout is piped or --stdout/--out is given.
+    #[arg(long)]
+    pub clipboard: bool,
+
+    /// Overwrite the --out file if it already exists.
+    #[arg(long)]
+    pub force: bool,
+
+    /// Create missing parent directories of the --out file.
+    #[arg(long)]
+    pub mkdirs: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TreeArgs {
+    #[command(flatten)]
+    pub walk: WalkArgs,
+
+    /// Color directories, symlinks, and skipped files; `auto` colors a
+    /// terminal unless NO_COLOR is set.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Elide the middle of names so lines fit in N columns (default: the
+    /// terminal's width; 0 never elides).
+    #[arg(long, value_name = "N")]
+    pub tree_width: Option<usize>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub walk: WalkArgs,
+
+    /// Print the report as JSON instead of tables.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to generate the script for.
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ManArgs {
+    /// Write copytree.1 and one page per subcommand into DIR instead of
+    /// printing the main page.
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    #[test]
+    fn plain_integers_are_bytes() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+    }
+
+    #[test]
+    fn binary_suffixes_are_powers_of_1024() {
+        assert_eq!(parse_size("16K"), Ok(16 * 1024));
+        assert_eq!(parse_size("16k"), Ok(16 * 1024));
+        assert_eq!(parse_size("16KiB"), Ok(16 * 1024));
+        assert_eq!(parse_size("2mib"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("2M"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("1Gi"), Ok(1 << 30));
+    }
+
+    #[test]
+    fn decimal_suffixes_are_powers_of_1000() {
+        assert_eq!(parse_size("16KB"), Ok(16_000));
+        assert_eq!(parse_size("2mb"), Ok(2_000_000));
+        assert_eq!(parse_size("1GB"), Ok(1_000_000_000));
+    }
+
+    #[test]
+    fn malformed_sizes_explain_the_format() {
+        for value in ["", "K", "16X", "1.5M", "-1", "16 KiB extra"] {
+            let err = parse_size(value).expect_err(value);
+            assert!(
+                err.ends_with("expected a size like 512, 16K, or 2MiB"),
+                "{value}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn include_binary_takes_base64_and_an_optional_cap() {
+        assert_eq!(parse_include_binary("base64"), Ok(DEFAULT_INCLUDE_BINARY));
+        assert_eq!(parse_include_binary("base64:2KiB"), Ok(2048));
+        assert!(parse_include_binary("hex").is_err());
+        assert!(parse_include_binary("base64:lots").is_err());
+    }
+
+    #[test]
+    fn overflowing_sizes_are_rejected() {
+        assert_eq!(
+            parse_size("99999999999999999999"),
+            Err("size `99999999999999999999` is too large".to_string())
+        );
+        assert_eq!(
+            parse_size("17179869184G"),
+            Err("size `17179869184G` is too large".to_string())
+        );
+        assert_eq!(parse_size("16777215G"), Ok(16_777_215 << 30));
+    }
+
+    #[test]
+    fn max_file_bytes_accepts_suffixes_and_defaults_to_16_kib() {
+        let args = Cli::parse_from(["copytree", "--max-file-bytes", "2M"]).copy;
+        assert_eq!(args.walk.max_file_bytes, 2 * 1024 * 1024);
+        let args = Cli::parse_from(["copytree"]).copy;
+        assert_eq!(args.walk.max_file_bytes, 16 * 1024);
+    }
+
+    fn validation_error(cli: &[&str]) -> Option<String> {
+        let matches = Cli::command()
+            .try_get_matches_from(std::iter::once("copytree").chain(cli.iter().copied()))
+            .unwrap_or_else(|err| panic!("{:?} should parse: {}", cli, err));
+        let args = Cli::from_arg_matches(&matches)
+            .expect("valid arguments")
+            .copy;
+        let given =
+            |id: &str| matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine);
+        validate(&args, given).err().map(|err| err.to_string())
+    }
+
+    #[test]
+    fn rejects_contradictory_combinations() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["--format", "tar"], "--format tar requires --out <FILE>"),
+            (
+                &["--format", "tar", "--out", "-"],
+                "--format tar requires --out <FILE>",
+            ),
+            (
+                &["--format", "tar", "--out", "a.tar", "--stdout"],
+                "cannot be sent to the clipboard or stdout",
+            ),
+            (
+                &["--format", "tar", "--out", "a.tar", "--clipboard"],
+                "cannot be sent to the clipboard or stdout",
+            ),
+            (
+                &["--format", "tar", "--out", "a.tar", "--out", "-"],
+                "cannot be sent to the clipboard or stdout",
+            ),
+            (
+                &["--format", "tar", "--out", "a.tar", "--append"],
+                "--format tar cannot be combined with --append",
+            ),
+            (
+                &["--format", "tar", "--out", "a.tar", "--open"],
+                "--open cannot show a tar archive",
+            ),
+            (
+                &["--format", "tar", "--out", "a.tar", "--bare"],
+                "--bare only applies to the plain format",
+            ),
+            (
+                &["--format", "tar", "--out", "a.tar", "--permalinks"],
+                "--permalinks annotates file sections",
+            ),
+            (
+                &["--reproducible", "--out", "a.txt", "--append"],
+                "--reproducible cannot be combined with --append",
+            ),
+            (
+                &["--reproducible", "--sample", "3"],
+                "--reproducible needs a fixed --seed",
+            ),
+            (
+                &["--reproducible", "--native-separators"],
+                "--reproducible always writes forward slashes",
+            ),
+            (
+                &["--template", "@prompt", "--format", "html"],
+                "cannot be combined with --format html",
+            ),
+            (
+                &["--template", "@prompt", "--bare"],
+                "leaves nothing for --template",
+            ),
+            (
+                &["--filter-cmd-for", "sqlformat -"],
+                "write it as `GLOB=CMD`",
+            ),
+            (
+                &["--filter-timeout", "5"],
+                "--filter-timeout only applies to --filter-cmd",
+            ),
+            (
+                &["--pipe-timeout", "5"],
+                "--pipe-timeout only applies to --pipe-cmd",
+            ),
+            (
+                &["--bare", "--provenance"],
+                "no room for the --provenance line",
+            ),
+            (
+                &["--tree-tokens", "--no-tree"],
+                "--tree-tokens annotates the tree, which --no-tree leaves out",
+            ),
+            (
+                &["--fit-strategy", "tests-first"],
+                "--fit-strategy only applies to --fit-tokens",
+            ),
+            (
+                &["--format", "html", "--out", "a.html", "--append"],
+                "--format html writes a whole page",
+            ),
+            (
+                &["--format", "html", "--bare"],
+                "the page always shows its tree",
+            ),
+            (
+                &["--no-tree", "--tree-width", "80"],
+                "--tree-width shapes the tree",
+            ),
+            (
+                &["--force-clipboard", "--clipboard-limit", "1M"],
+                "--force-clipboard ignores --clipboard-limit",
+            ),
+            (
+                &["--clipboard-backend", "command"],
+                "pass --clipboard-cmd <CMD> as well",
+            ),
+            (
+                &[
+                    "--clipboard-backend",
+                    "arboard",
+                    "--clipboard-cmd",
+                    "wl-copy",
+                ],
+                "--clipboard-backend arboard never runs --clipboard-cmd",
+            ),
+            (
+                &["--force-clipboard", "--max-memory", "64M"],
+                "--force-clipboard copies the output whole",
+            ),
+            (&["--serve=0.0.0.0:8080"], "use --serve-addr 0.0.0.0:8080"),
+            (&["--serve-once"], "--serve-once only applies to --serve"),
+            (
+                &["--post", "paste.local"],
+                "--post needs an http:// or https:// URL",
+            ),
+            (
+                &["--post-header", "Authorization: Bearer x"],
+                "--post-header only applies to --post",
+            ),
+            (
+                &["--post", "https://paste.local", "--post-header", "Bearer x"],
+                "write it as `Name: value`",
+            ),
+            (
+                &["--format", "html", "--out", "a.html", "--omit-skipped"],
+                "--omit-skipped drops sections of the plain output",
+            ),
+            (
+                &["--output-eol-content"],
+                "pass --output-eol crlf or native as well",
+            ),
+            (
+                &[
+                    "--output-eol",
+                    "crlf",
+                    "--format",
+                    "html",
+                    "--out",
+                    "a.html",
+                ],
+                "--output-eol applies to the plain output",
+            ),
+            (
+                &["--output-eol", "crlf", "--encode", "base64"],
+                "--encode writes lines of its own",
+            ),
+            (
+                &[
+                    "--output-eol=crlf",
+                    "--output-eol-content",
+                    "--out",
+                    "a.txt",
+                    "--manifest",
+                ],
+                "--encode, --pipe-cmd, and --output-eol change",
+            ),
+            (
+                &["--output-eol", "crlf", "--out", "a.txt", "--manifest"],
+                "--encode, --pipe-cmd, and --output-eol change",
+            ),
+            (&["--manifest"], "pass --out <FILE> or --manifest=PATH"),
+            (
+                &["--list", "--format", "html", "--out", "a.html"],
+                "--list prints one path per line",
+            ),
+            (
+                &["--list", "--fit-tokens", "1000"],
+                "--fit-tokens shapes the contents, which --list does not write",
+            ),
+            (
+                &["--partial-on-interrupt", "--format", "html"],
+                "--partial-on-interrupt ends the plain output with a trailer line",
+            ),
+            (
+                &["--partial-on-interrupt", "--bare"],
+                "drop --format, --template, and --bare",
+            ),
+            (
+                &["--partial-on-interrupt", "--check", "--out", "a.txt"],
+                "--check would compare partial output",
+            ),
+            (
+                &["--status-json=stdout", "--stdout"],
+                "--status-json=stdout would mix the status line into the output",
+            ),
+            (
+                &["--status-json=stdout", "--out", "-"],
+                "use --status-json for stderr or send the output elsewhere",
+            ),
+            (
+                &["--out", "a.txt", "--manifest=a.txt"],
+                "--manifest a.txt would overwrite another output",
+            ),
+            (
+                &["--out", "a.html", "--format", "html", "--manifest"],
+                "--manifest records where each file sits in the plain output",
+            ),
+            (
+                &["--out", "a.txt", "--manifest", "--bare"],
+                "cannot be combined with --bare, --append, or --check",
+            ),
+            (
+                &["--out", "a.txt", "--manifest", "--encode", "base64"],
+                "--encode, --pipe-cmd, and --output-eol change",
+            ),
+            (
+                &["--out", "a.txt", "--append", "--force"],
+                "--append and --force contradict each other",
+            ),
+            (&["--append"], "--append needs a file to add to"),
+            (&["--check"], "pass exactly one --out <FILE>"),
+            (
+                &["--check", "--out", "a.txt", "--stdout"],
+                "pass exactly one --out <FILE>",
+            ),
+            (
+                &["--check", "--out", "a.txt", "--append"],
+                "--check compares whole files",
+            ),
+            (
+                &["--check", "--out", "ctx-{date}.txt"],
+                "--check needs a fixed --out path",
+            ),
+            (
+                &["--out", "a.txt", "--fix"],
+                "--fix only applies to --check",
+            ),
+            (&["--stdout", "--append"], "--append needs a file to add to"),
+            (&["--force"], "--force only applies to --out files"),
+            (
+                &["--mkdirs", "--out", "-"],
+                "--mkdirs only applies to --out files",
+            ),
+            (
+                &["--compress", "gzip"],
+                "--compress only applies to --out files",
+            ),
+            (
+                &["--out", "a.txt", "--out", "a.txt"],
+                "--out a.txt is given more than once",
+            ),
+            (
+                &["--out", "a.txt", "--report-json", "a.txt"],
+                "--report-json a.txt would overwrite the --out file",
+            ),
+        ];
+        for (cli, expected) in cases {
+            let err = validation_error(cli).unwrap_or_else(|| panic!("{:?} was accepted", cli));
+            assert!(err.contains(expected), "{:?}: {}", cli, err);
+        }
+    }
+
+    #[test]
+    fn clap_rejects_flag_conflicts() {
+        let cases: &[&[&str]] = &[
+            &["--quiet", "--verbose"],
+            &["--last"],
+            &["--no-config", "--profile", "api"],
+        ];
+        for cli in cases {
+            let parsed = Cli::command()
+                .try_get_matches_from(std::iter::once("copytree").chain(cli.iter().copied()));
+            assert!(parsed.is_err(), "{:?} was accepted", cli);
+        }
+    }
+
+    #[test]
+    fn accepts_sensible_combinations() {
+        let cases: &[&[&str]] = &[
+            &[],
+            &["--stdout", "--out", "a.txt", "--clipboard"],
+            &["--out", "a.txt", "--append", "--mkdirs"],
+            &["--out", "a.txt.gz", "--compress", "gzip", "--force"],
+            &["--format", "tar", "--out", "a.tar"],
+            &["--out", "a.txt", "--report-json", "report.json"],
+        ];
+        for cli in cases {
+            assert_eq!(validation_error(cli), None, "{:?}", cli);
+        }
+    }
+
+    #[test]
+    fn unused_config_defaults_are_not_errors() {
+        let matches = Cli::command()
+            .try_get_matches_from(["copytree", "--stdout"])
+            .expect("valid arguments");
+        let mut args = Cli::from_arg_matches(&matches)
+            .expect("valid arguments")
+            .copy;
+        args.force = true;
+        args.mkdirs = true;
+        assert!(validate(&args, |_| false).is_ok());
+    }
 }
@@ -0,0 +1,366 @@
+use crate::args::Args;
+use crate::content::{FileContent, FileRecord, SkipReason};
+use crate::fit::{self, Strategy};
+use crate::output::{approximate_count, human_size};
+use crate::separators;
+use crate::stats;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Starts the report on what the limits cut, which closes plain output;
+/// snapshot parsing stops there.
+pub const HEADING: &str = "=== cut by limits ===";
+
+/// A limit that leaves content out, in the order the report lists them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Limit {
+    MaxFileBytes,
+    FitTokens,
+    MaxPerDir,
+}
+
+impl Limit {
+    pub fn flag(self) -> &'static str {
+        match self {
+            Self::MaxFileBytes => "--max-file-bytes",
+            Self::FitTokens => "--fit-tokens",
+            Self::MaxPerDir => "--max-per-dir",
+        }
+    }
+
+    /// The value of this limit as written on the command line.
+    fn value(self, value: u64) -> String {
+        match self {
+            Self::MaxFileBytes => size_value(value),
+            Self::FitTokens | Self::MaxPerDir => value.to_string(),
+        }
+    }
+
+    /// How much one cut amounts to, e.g. `96 KiB` or `~12k tokens`.
+    fn amount(self, amount: u64) -> String {
+        match self {
+            Self::MaxFileBytes => human_size(amount),
+            Self::FitTokens => format!("~{} tokens", approximate_count(amount)),
+            Self::MaxPerDir => stats::file_count(amount),
+        }
+    }
+}
+
+/// Everything one limit cut, with the value that would have let it all in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    pub limit: Limit,
+    /// The limit in this run.
+    pub value: u64,
+    /// The smallest value that would have cut nothing.
+    pub suggested: u64,
+    /// What the amounts count: `bytes`, `tokens`, or `files`.
+    pub unit: String,
+    /// The `--fit-tokens` strategy that picked the files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<Strategy>,
+    /// Largest first.
+    pub cut: Vec<Cut>,
+}
+
+/// A file cut by a limit, or for `--max-per-dir` a directory some of whose
+/// files were.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cut {
+    pub path: String,
+    pub amount: u64,
+    /// Why `--fit-tokens` dropped the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Collects what the limits of a run cut, from the same records the output
+/// is written from.
+#[derive(Debug, Default)]
+pub struct Budget {
+    max_file_bytes: u64,
+    too_large: Vec<Cut>,
+    fit: Option<(u64, Strategy)>,
+    /// Tokens of the included files, which `--fit-tokens` kept.
+    included_tokens: u64,
+    dropped: Vec<Cut>,
+    max_per_dir: Option<u64>,
+    per_dir: Vec<Cut>,
+}
+
+impl Budget {
+    /// `dropped_per_dir` holds how many files `--max-per-dir` left out of
+    /// each directory, which never become records.
+    pub fn new(args: &Args, dropped_per_dir: &BTreeMap<PathBuf, usize>) -> Self {
+        Self {
+            max_file_bytes: args.walk.max_file_bytes,
+            fit: args.fit_tokens.map(|budget| (budget, args.fit_strategy)),
+            max_per_dir: args.walk.max_per_dir.map(|limit| limit.get() as u64),
+            per_dir: dropped_per_dir
+                .iter()
+                .map(|(dir, &count)| Cut {
+                    path: format!("{}/", separators::display(dir)),
+                    amount: count as u64,
+                    reason: None,
+                })
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Notes the files `--max-file-bytes` kept out, and the tokens of the
+    /// ones included.
+    pub fn record_files<D>(&mut self, records: &[FileRecord], display: D)
+    where
+        D: Fn(&Path) -> String,
+    {
+        for record in records {
+            match &record.content {
+                FileContent::Skipped(SkipReason::TooLarge { size, .. }) => {
+                    self.too_large.push(Cut {
+                        path: display(&record.path),
+                        amount: *size,
+                        reason: None,
+                    });
+                }
+                FileContent::Skipped(_) => {}
+                content => self.included_tokens += content.estimated_tokens() as u64,
+            }
+        }
+    }
+
+    /// Notes the files `--fit-tokens` dropped.
+    pub fn record_fit(&mut self, dropped: &[fit::Dropped]) {
+        self.dropped.extend(dropped.iter().map(|file| Cut {
+            path: file.path.clone(),
+            amount: file.tokens,
+            reason: Some(file.reason.to_string()),
+        }));
+    }
+
+    /// One group per limit that cut something.
+    pub fn groups(&self) -> Vec<Group> {
+        let group = |limit: Limit, value: u64, suggested: u64, unit: &str, cut: &[Cut]| {
+            let mut cut = cut.to_vec();
+            cut.sort_by(|a, b| {
+                Reverse(a.amount)
+                    .cmp(&Reverse(b.amount))
+                    .then_with(|| a.path.cmp(&b.path))
+            });
+            Group {
+                limit,
+                value,
+                suggested,
+                unit: unit.to_string(),
+                strategy: None,
+                cut,
+            }
+        };
+        let mut groups = Vec::new();
+        if let Some(largest) = self.too_large.iter().map(|cut| cut.amount).max() {
+            groups.push(group(
+                Limit::MaxFileBytes,
+                self.max_file_bytes,
+                round_up_size(largest),
+                "bytes",
+                &self.too_large,
+            ));
+        }
+        if let Some((budget, strategy)) = self.fit.filter(|_| !self.dropped.is_empty()) {
+            let dropped: u64 = self.dropped.iter().map(|cut| cut.amount).sum();
+            groups.push(Group {
+                strategy: Some(strategy),
+                ..group(
+                    Limit::FitTokens,
+                    budget,
+                    self.included_tokens + dropped,
+                    "tokens",
+                    &self.dropped,
+                )
+            });
+        }
+        if let Some(limit) = self.max_per_dir {
+            if let Some(most) = self.per_dir.iter().map(|cut| cut.amount).max() {
+                groups.push(group(
+                    Limit::MaxPerDir,
+                    limit,
+                    limit + most,
+                    "files",
+                    &self.per_dir,
+                ));
+            }
+        }
+        groups
+    }
+}
+
+/// The report closing plain output: a line per limit saying what it cut
+/// and what to rerun with, then what it cut as a table, largest first.
+/// Empty when nothing was cut.
+pub fn render(groups: &[Group]) -> String {
+    if groups.is_empty() {
+        return String::new();
+    }
+    let mut text = format!("{}\n", HEADING);
+    for group in groups {
+        text.push_str(&format!("{}\n", summary(group)));
+        text.push_str(&stats::table(&rows(group), [true, false, false]));
+    }
+    text
+}
+
+/// What `group`'s limit cut and the value to rerun with, e.g. `--fit-tokens
+/// 500 (largest-first) cut 1 file; rerun with --fit-tokens 1300 to include
+/// it`.
+pub fn summary(group: &Group) -> String {
+    let limit = group.limit;
+    let strategy = group.strategy.map_or_else(String::new, |strategy| {
+        let value = strategy.to_possible_value().expect("no variant is skipped");
+        format!(" ({})", value.get_name())
+    });
+    let count = group.cut.len() as u64;
+    let (what, them) = match limit {
+        Limit::MaxPerDir => (
+            format!(
+                "{} from {} director{}",
+                stats::file_count(group.cut.iter().map(|cut| cut.amount).sum()),
+                count,
+                if count == 1 { "y" } else { "ies" }
+            ),
+            "them",
+        ),
+        _ => (
+            stats::file_count(count),
+            if count == 1 { "it" } else { "them" },
+        ),
+    };
+    format!(
+        "{} {}{} cut {}; rerun with {} {} to include {}",
+        limit.flag(),
+        limit.value(group.value),
+        strategy,
+        what,
+        limit.flag(),
+        limit.value(group.suggested),
+        them
+    )
+}
+
+/// One row per cut: the amount, the path, and why `--fit-tokens` picked it.
+pub fn rows(group: &Group) -> Vec<[String; 3]> {
+    group
+        .cut
+        .iter()
+        .map(|cut| {
+            [
+                group.limit.amount(cut.amount),
+                cut.path.clone(),
+                cut.reason.clone().unwrap_or_default(),
+            ]
+        })
+        .collect()
+}
+
+/// `bytes` as `--max-file-bytes` takes it, in the largest unit it is a
+/// whole number of.
+fn size_value(bytes: u64) -> String {
+    match bytes {
+        0 => "0".to_string(),
+        _ if bytes.is_multiple_of(1 << 20) => format!("{}MiB", bytes >> 20),
+        _ if bytes.is_multiple_of(1 << 10) => format!("{}KiB", bytes >> 10),
+        _ => bytes.to_string(),
+    }
+}
+
+/// `bytes` rounded up to a whole KiB, or MiB from 1 MiB on.
+fn round_up_size(bytes: u64) -> u64 {
+    let unit = if bytes >= 1 << 20 { 1 << 20 } else { 1 << 10 };
+    bytes.div_ceil(unit) * unit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fit::Reason;
+
+    fn record(path: &str, content: FileContent) -> FileRecord {
+        FileRecord {
+            path: PathBuf::from(path),
+            content,
+        }
+    }
+
+    #[test]
+    fn every_limit_gets_a_group_with_the_value_to_rerun_with() {
+        let mut budget = Budget {
+            max_file_bytes: 16 << 10,
+            fit: Some((500, Strategy::Largest)),
+            max_per_dir: Some(2),
+            per_dir: vec![Cut {
+                path: "gen/".to_string(),
+                amount: 3,
+                reason: None,
+            }],
+            ..Budget::default()
+        };
+        let too_large = |size| {
+            FileContent::Skipped(SkipReason::TooLarge {
+                size,
+                limit: 16 << 10,
+            })
+        };
+        budget.record_files(
+            &[
+                record("src/mid.txt", too_large(20_000)),
+                record("src/main.rs", FileContent::Text("x".repeat(400))),
+                record("src/big.txt", too_large(200_000)),
+            ],
+            |path| path.to_string_lossy().into_owned(),
+        );
+        budget.record_fit(&[fit::Dropped {
+            path: "src/lib.rs".to_string(),
+            tokens: 1200,
+            reason: Reason::Largest,
+        }]);
+
+        let groups = budget.groups();
+        let suggested: Vec<(Limit, u64)> = groups
+            .iter()
+            .map(|group| (group.limit, group.suggested))
+            .collect();
+        assert_eq!(
+            suggested,
+            [
+                (Limit::MaxFileBytes, 196 << 10),
+                (Limit::FitTokens, 1300),
+                (Limit::MaxPerDir, 5),
+            ]
+        );
+        assert_eq!(
+            render(&groups),
+            "=== cut by limits ===\n\
+             --max-file-bytes 16KiB cut 2 files; rerun with --max-file-bytes 196KiB to include them\n\
+             \x20 195 KiB  src/big.txt\n\
+             \x20  20 KiB  src/mid.txt\n\
+             --fit-tokens 500 (largest-first) cut 1 file; rerun with --fit-tokens 1300 to include it\n\
+             \x20 ~1.2k tokens  src/lib.rs  largest remaining file\n\
+             --max-per-dir 2 cut 3 files from 1 directory; rerun with --max-per-dir 5 to include them\n\
+             \x20 3 files  gen/\n"
+        );
+        assert_eq!(render(&Budget::default().groups()), "");
+    }
+
+    #[test]
+    fn sizes_are_written_in_the_largest_whole_unit() {
+        assert_eq!(size_value(16 << 10), "16KiB");
+        assert_eq!(size_value(2 << 20), "2MiB");
+        assert_eq!(size_value(1500), "1500");
+        assert_eq!(round_up_size(1500), 2 << 10);
+        assert_eq!(round_up_size((1 << 20) + 1), 2 << 20);
+        assert_eq!(round_up_size(4 << 10), 4 << 10);
+    }
+}
@@ -0,0 +1,452 @@
+use crate::content::FileContent;
+use crate::logger;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The cache file inside the cache directory.
+const FILE_NAME: &str = "contents.bin";
+
+/// A file modified this recently may change again within the same mtime
+/// tick, so its body is not cached.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// The first line of a cache file. A cache written by another version is
+/// started over, since it may have read files differently.
+fn magic() -> String {
+    format!("copytree-cache 1 {}\n", env!("CARGO_PKG_VERSION"))
+}
+
+/// Where `--cache` keeps its file when no directory is given.
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("copytree"))
+}
+
+/// What a file looked like when its body was cached, and where the body is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    /// The read settings the body was produced under; see [`Cache::read`].
+    settings: String,
+    /// FNV-1a of the body, so a damaged cache file is read as a miss.
+    hash: u64,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    entries: BTreeMap<String, Entry>,
+}
+
+/// A file's identity and modification stamp, taken before reading it.
+struct Stamp {
+    key: String,
+    size: u64,
+    mtime: Duration,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        let canonical = fs::canonicalize(path).ok()?;
+        Some(Self {
+            key: canonical.to_string_lossy().into_owned(),
+            size: metadata.len(),
+            mtime,
+        })
+    }
+
+    fn matches(&self, entry: &Entry, settings: &str) -> bool {
+        entry.size == self.size
+            && entry.mtime_secs == self.mtime.as_secs()
+            && entry.mtime_nanos == self.mtime.subsec_nanos()
+            && entry.settings == settings
+    }
+
+    fn is_recent(&self) -> bool {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(true, |now| now.saturating_sub(self.mtime) < RACY_WINDOW)
+    }
+}
+
+/// The previous cache file, read from, and the next one, written as the
+/// run goes so that bodies need not stay in memory.
+struct State {
+    old: Option<File>,
+    old_entries: HashMap<String, Entry>,
+    new: Option<BufWriter<File>>,
+    new_path: PathBuf,
+    new_len: u64,
+    new_entries: BTreeMap<String, Entry>,
+    /// The first failure writing the next file; it is then not saved.
+    error: Option<io::Error>,
+}
+
+/// File bodies from earlier runs, keyed by canonical path and reused while
+/// a file's size and modification time are unchanged (`--cache`).
+pub struct Cache {
+    path: PathBuf,
+    state: Mutex<State>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl Cache {
+    /// Opens the cache in `dir`, creating the directory. A missing cache
+    /// starts empty, and so does an unreadable one, with a warning.
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create the cache directory {}", dir.display()))?;
+        let path = dir.join(FILE_NAME);
+        let (old, old_entries) = match File::open(&path) {
+            Ok(mut file) => match read_index(&mut file) {
+                Ok(Some(index)) => (Some(file), index.entries.into_iter().collect()),
+                Ok(None) => (None, HashMap::new()),
+                Err(err) => {
+                    logger::warn(format_args!(
+                        "ignoring the unreadable cache {}: {err}",
+                        path.display()
+                    ));
+                    (None, HashMap::new())
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (None, HashMap::new()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to open {}", path.display()))
+            }
+        };
+        let new_path = dir.join(format!("{}.{}.tmp", FILE_NAME, std::process::id()));
+        let mut new = File::create(&new_path)
+            .map(BufWriter::new)
+            .with_context(|| format!("Failed to create {}", new_path.display()))?;
+        let magic = magic();
+        new.write_all(magic.as_bytes())
+            .with_context(|| format!("Failed to write {}", new_path.display()))?;
+        Ok(Self {
+            path,
+            state: Mutex::new(State {
+                old,
+                old_entries,
+                new: Some(new),
+                new_path,
+                new_len: magic.len() as u64,
+                new_entries: BTreeMap::new(),
+                error: None,
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+
+    /// The content of `path`: the cached body when the file's size and
+    /// modification time match the entry and it was produced under the same
+    /// `settings` (the flags that change how a file is read, such as the size
+    /// limit), otherwise whatever `read` returns. Text read anew refreshes
+    /// the entry; skipped files are not cached.
+    pub fn read<F>(&self, path: &Path, settings: &str, read: F) -> FileContent
+    where
+        F: FnOnce() -> FileContent,
+    {
+        let Some(stamp) = Stamp::of(path) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return read();
+        };
+        {
+            let mut state = self.state.lock().expect("cache lock poisoned");
+            let cached = state
+                .old_entries
+                .get(&stamp.key)
+                .filter(|entry| stamp.matches(entry, settings))
+                .cloned();
+            if let Some(text) = cached.and_then(|entry| state.body(&entry)) {
+                state.store(&stamp, settings, &text);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return FileContent::Text(text);
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let content = read();
+        if let FileContent::Text(text) = &content {
+            if !stamp.is_recent() {
+                let mut state = self.state.lock().expect("cache lock poisoned");
+                state.store(&stamp, settings, text);
+            }
+        }
+        content
+    }
+
+    /// Files served from the cache and files read, so far.
+    pub fn counts(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Writes the next cache file over the previous one. Entries this run
+    /// did not touch are kept while their files exist, so one cache serves
+    /// several projects.
+    pub fn save(self) -> Result<()> {
+        let mut state = self.state.into_inner().expect("cache lock poisoned");
+        let untouched: Vec<(String, Entry)> = state
+            .old_entries
+            .iter()
+            .filter(|(key, _)| !state.new_entries.contains_key(*key) && Path::new(key).exists())
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        for (key, entry) in untouched {
+            if let Some(text) = state.body(&entry) {
+                state.append(key, entry, &text);
+            }
+        }
+        if let Some(err) = state.error.take() {
+            return Err(err)
+                .with_context(|| format!("Failed to write {}", state.new_path.display()));
+        }
+        let index = Index {
+            entries: std::mem::take(&mut state.new_entries),
+        };
+        let index_offset = state.new_len;
+        let mut new = state.new.take().expect("cache file open until saved");
+        let written = serde_json::to_writer(&mut new, &index)
+            .map_err(io::Error::from)
+            .and_then(|()| new.write_all(&index_offset.to_le_bytes()))
+            .and_then(|()| new.into_inner().map_err(|err| err.into_error()))
+            .and_then(|file| file.sync_all())
+            .and_then(|()| fs::rename(&state.new_path, &self.path));
+        if let Err(err) = written {
+            let _ = fs::remove_file(&state.new_path);
+            return Err(err).with_context(|| format!("Failed to save {}", self.path.display()));
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    /// The body of `entry` in the previous file, if it reads back intact.
+    fn body(&mut self, entry: &Entry) -> Option<String> {
+        let old = self.old.as_mut()?;
+        let mut body = vec![0; usize::try_from(entry.len).ok()?];
+        old.seek(SeekFrom::Start(entry.offset)).ok()?;
+        old.read_exact(&mut body).ok()?;
+        if fnv1a(&body) != entry.hash {
+            return None;
+        }
+        String::from_utf8(body).ok()
+    }
+
+    fn store(&mut self, stamp: &Stamp, settings: &str, text: &str) {
+        let entry = Entry {
+            size: stamp.size,
+            mtime_secs: stamp.mtime.as_secs(),
+            mtime_nanos: stamp.mtime.subsec_nanos(),
+            settings: settings.to_string(),
+            hash: fnv1a(text.as_bytes()),
+            offset: 0,
+            len: 0,
+        };
+        self.append(stamp.key.clone(), entry, text);
+    }
+
+    /// Writes `text` to the next file and records `entry` pointing at it.
+    fn append(&mut self, key: String, mut entry: Entry, text: &str) {
+        if self.error.is_some() || self.new_entries.contains_key(&key) {
+            return;
+        }
+        let Some(new) = self.new.as_mut() else {
+            return;
+        };
+        if let Err(err) = new.write_all(text.as_bytes()) {
+            self.error = Some(err);
+            return;
+        }
+        entry.offset = self.new_len;
+        entry.len = text.len() as u64;
+        self.new_len += entry.len;
+        self.new_entries.insert(key, entry);
+    }
+}
+
+impl Drop for State {
+    /// A cache dropped unsaved leaves no temp file behind.
+    fn drop(&mut self) {
+        if self.new.take().is_some() {
+            let _ = fs::remove_file(&self.new_path);
+        }
+    }
+}
+
+/// The index of a cache file: JSON after the bodies, located by the 8-byte
+/// offset that ends the file. `None` when another version wrote the file.
+fn read_index(file: &mut File) -> Result<Option<Index>> {
+    let magic = magic();
+    let mut head = vec![0; magic.len()];
+    if file.read_exact(&mut head).is_err() || !head.starts_with(b"copytree-cache ") {
+        bail!("not a copytree cache file");
+    }
+    if head != magic.as_bytes() {
+        return Ok(None);
+    }
+    let end = file.seek(SeekFrom::End(-8))?;
+    let mut offset = [0; 8];
+    file.read_exact(&mut offset)?;
+    let offset = u64::from_le_bytes(offset);
+    if offset < magic.len() as u64 || offset > end {
+        bail!("index offset {} is out of range", offset);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut index = Vec::new();
+    Read::by_ref(file)
+        .take(end - offset)
+        .read_to_end(&mut index)?;
+    Ok(Some(serde_json::from_slice(&index)?))
+}
+
+/// Removes the cache file in `dir`, returning its size, or `None` when
+/// there was none.
+pub fn clear(dir: &Path) -> Result<Option<u64>> {
+    let path = dir.join(FILE_NAME);
+    let size = match fs::metadata(&path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    Ok(Some(size))
+}
+
+/// 64-bit FNV-1a: stable across builds, unlike std's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content;
+    use std::cell::RefCell;
+
+    fn fixture() -> tempfile::TempDir {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).expect("create fixture dir");
+        for (name, text) in [
+            ("src/main.rs", "fn main() {}\n"),
+            ("src/lib.rs", "pub fn lib() {}\n"),
+            ("README.md", "# Fixture\n"),
+        ] {
+            write_old(&root.join(name), text);
+        }
+        temp
+    }
+
+    /// Writes `text` with a modification time outside the racy window.
+    fn write_old(path: &Path, text: &str) {
+        fs::write(path, text).expect("write fixture file");
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::now() - Duration::from_secs(60)))
+            .expect("set mtime");
+    }
+
+    /// Reads every fixture file through a fresh cache, saves it, and
+    /// returns the contents with the files actually read.
+    fn run(root: &Path, settings: &str) -> (Vec<FileContent>, Vec<PathBuf>) {
+        let cache = Cache::open(&root.join("cache")).expect("open cache");
+        let reads = RefCell::new(Vec::new());
+        let contents = ["README.md", "src/lib.rs", "src/main.rs"]
+            .iter()
+            .map(|name| {
+                let path = root.join(name);
+                cache.read(&path, settings, || {
+                    reads.borrow_mut().push(path.clone());
+                    content::read_file(&path, None, 0, None)
+                })
+            })
+            .collect();
+        let (hits, misses) = cache.counts();
+        assert_eq!(hits + misses, 3);
+        cache.save().expect("save cache");
+        (contents, reads.into_inner())
+    }
+
+    #[test]
+    fn only_changed_files_are_read_again() {
+        let fixture = fixture();
+        let root = fixture.path();
+        let (first, reads) = run(root, "16384");
+        assert_eq!(reads.len(), 3);
+
+        let (second, reads) = run(root, "16384");
+        assert_eq!(second, first);
+        assert!(reads.is_empty(), "{reads:?}");
+
+        write_old(&root.join("src/lib.rs"), "pub fn changed() {}\n");
+        let (third, reads) = run(root, "16384");
+        assert_eq!(reads, [root.join("src/lib.rs")]);
+        assert_eq!(
+            third[1],
+            FileContent::Text("pub fn changed() {}\n".to_string())
+        );
+        assert_eq!(third[0], first[0]);
+    }
+
+    #[test]
+    fn other_settings_never_get_cached_bodies() {
+        let fixture = fixture();
+        let root = fixture.path();
+        run(root, "16384");
+        let (_, reads) = run(root, "0");
+        assert_eq!(reads.len(), 3);
+        // The entries now hold the bodies read under the new settings.
+        let (_, reads) = run(root, "0");
+        assert!(reads.is_empty());
+    }
+
+    #[test]
+    fn recently_modified_files_are_not_cached() {
+        let fixture = fixture();
+        let root = fixture.path();
+        fs::write(root.join("README.md"), "# Just written\n").expect("rewrite");
+        run(root, "16384");
+        let (_, reads) = run(root, "16384");
+        assert_eq!(reads, [root.join("README.md")]);
+    }
+
+    #[test]
+    fn a_damaged_cache_starts_over_and_clear_removes_it() {
+        let fixture = fixture();
+        let root = fixture.path();
+        let dir = root.join("cache");
+        run(root, "16384");
+        let file = dir.join(FILE_NAME);
+        let mut bytes = fs::read(&file).expect("read cache");
+        let body = magic().len();
+        bytes[body] ^= 0xff;
+        fs::write(&file, &bytes).expect("damage body");
+        let (contents, reads) = run(root, "16384");
+        assert_eq!(reads, [root.join("README.md")]);
+        assert_eq!(contents[0], FileContent::Text("# Fixture\n".to_string()));
+
+        fs::write(&file, "garbage").expect("damage file");
+        let (_, reads) = run(root, "16384");
+        assert_eq!(reads.len(), 3);
+
+        assert!(clear(&dir).expect("clear").is_some());
+        assert_eq!(clear(&dir).expect("clear again"), None);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// `name` with case folded away, so names that only differ in case compare
+/// equal. Upper- then lowercasing is full Unicode folding for names: `ß`
+/// and `SS` meet at `ss`, and final `ς` at `σ`. Bytes that are not UTF-8
+/// are kept as they are, so two such names never meet by both turning
+/// into the replacement character.
+pub fn fold(name: &OsStr) -> Vec<u8> {
+    let mut folded = Vec::new();
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        folded.extend_from_slice(chunk.valid().to_uppercase().to_lowercase().as_bytes());
+        folded.extend_from_slice(chunk.invalid());
+    }
+    folded
+}
+
+/// The groups of sibling entries among `paths` and their ancestor
+/// directories whose names are equal once case is folded, e.g. `README.md`
+/// and `Readme.md`, or the directories `Docs` and `docs`. Each group is
+/// sorted, and so is the list.
+pub fn collisions<'a, I>(paths: I) -> Vec<Vec<PathBuf>>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let mut nodes = BTreeSet::new();
+    for path in paths {
+        nodes.extend(
+            path.ancestors()
+                .take_while(|ancestor| !ancestor.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        );
+    }
+    let mut siblings: BTreeMap<(&Path, Vec<u8>), Vec<PathBuf>> = BTreeMap::new();
+    for node in &nodes {
+        if let (Some(parent), Some(name)) = (node.parent(), node.file_name()) {
+            siblings
+                .entry((parent, fold(name)))
+                .or_default()
+                .push(node.clone());
+        }
+    }
+    siblings
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+/// Whether names in `dir` (or its closest existing ancestor) ignore case,
+/// found by creating a lowercase file there and looking for it in
+/// uppercase. `false` when nothing can be created.
+pub fn is_case_insensitive(dir: &Path) -> bool {
+    let Some(existing) = dir.ancestors().find(|ancestor| ancestor.is_dir()) else {
+        return false;
+    };
+    let name = format!(".copytree-case-probe-{}", std::process::id());
+    let probe = existing.join(&name);
+    if std::fs::write(&probe, b"").is_err() {
+        return false;
+    }
+    let insensitive = existing.join(name.to_uppercase()).exists();
+    let _ = std::fs::remove_file(&probe);
+    insensitive
+}
+
+/// The paths of a group, for messages.
+pub fn describe(group: &[PathBuf]) -> String {
+    group
+        .iter()
+        .map(|path| crate::separators::display(path))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(paths: &[&str]) -> Vec<Vec<String>> {
+        collisions(paths.iter().map(Path::new))
+            .into_iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn siblings_differing_only_in_case_collide() {
+        assert_eq!(
+            groups(&[
+                "src/README.md",
+                "src/Readme.md",
+                "src/main.rs",
+                "Docs/a.md",
+                "docs/b.md",
+                "other/readme.md",
+            ]),
+            [vec!["Docs", "docs"], vec!["src/README.md", "src/Readme.md"]]
+        );
+        assert!(groups(&["a/x.rs", "b/X.rs", "x.rs"]).is_empty());
+    }
+
+    #[test]
+    fn folding_is_unicode_aware() {
+        assert_eq!(
+            fold(OsStr::new("STRASSE.md")),
+            fold(OsStr::new("straße.md"))
+        );
+        assert_eq!(fold(OsStr::new("ΣΟΦΟΣ")), fold(OsStr::new("σοφος")));
+        assert_eq!(fold(OsStr::new("ÉTÉ")), fold(OsStr::new("été")));
+        assert_eq!(
+            groups(&["notes/Übung.txt", "notes/übung.txt"]),
+            [vec!["notes/Übung.txt", "notes/übung.txt"]]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_utf8_only_collide_on_their_own_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = |bytes: &[u8]| fold(OsStr::from_bytes(bytes));
+        assert_ne!(name(b"bad\xfe.txt"), name(b"bad\xff.txt"));
+        assert_eq!(name(b"BAD\xff.TXT"), name(b"bad\xff.txt"));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::args::Args;
+use crate::diff;
+use crate::logger;
+use crate::output;
+use crate::provenance::Provenance;
+use crate::snapshot::Snapshot;
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// `--check`: the run writes to a scratch file, which is then compared with
+/// the `--out` file it stands in for.
+#[derive(Debug)]
+pub struct Check {
+    target: PathBuf,
+    scratch: PathBuf,
+}
+
+/// How the `--out` file compared with the output of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    UpToDate,
+    /// The file differs or does not exist.
+    OutOfDate,
+}
+
+impl Check {
+    /// Points the single `--out` file of `args` at a scratch file and keeps
+    /// the time out of the provenance line, so two runs over the same tree
+    /// match.
+    pub fn stage(args: &mut Args) -> Self {
+        let target = PathBuf::from(&args.out[0]);
+        let scratch = output::scratch_path(target.file_name().unwrap_or(OsStr::new("output")));
+        args.out = vec![scratch.to_string_lossy().into_owned()];
+        args.force = true;
+        args.no_timestamp = true;
+        Self { target, scratch }
+    }
+
+    /// Compares the output with the `--out` file and reports the files
+    /// whose sections differ; with `fix`, an out-of-date file is rewritten.
+    pub fn compare(&self, fix: bool) -> Result<Outcome> {
+        let new = fs::read(&self.scratch).context("Failed to read the output to check")?;
+        let shown = self.target.display();
+        let old = match fs::read(&self.target) {
+            Ok(old) => Some(old),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err).with_context(|| format!("Failed to read {}", shown)),
+        };
+        match &old {
+            Some(old) if without_time(old) == without_time(&new) => {
+                logger::info(format_args!("{} is up to date", shown));
+                return Ok(Outcome::UpToDate);
+            }
+            Some(old) => {
+                logger::warn(format_args!("{} is out of date", shown));
+                let summary = changed_sections(old, &new);
+                if summary.is_empty() {
+                    logger::info("  no file section changed; the rest of the output did");
+                }
+                for line in summary.lines() {
+                    logger::info(format_args!("  {}", line));
+                }
+            }
+            None => logger::warn(format_args!("{} does not exist", shown)),
+        }
+        if fix {
+            fs::write(&self.target, &new).with_context(|| format!("Failed to write {}", shown))?;
+            logger::info(format_args!("Regenerated {}", shown));
+        }
+        Ok(Outcome::OutOfDate)
+    }
+}
+
+impl Drop for Check {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.scratch);
+    }
+}
+
+/// `output` with the time dropped from a leading provenance line: a file
+/// written without `--no-timestamp` still matches a run over the same tree.
+fn without_time(output: &[u8]) -> Cow<'_, [u8]> {
+    let end = output
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .unwrap_or(output.len());
+    let provenance = std::str::from_utf8(&output[..end])
+        .ok()
+        .and_then(Provenance::parse)
+        .filter(|provenance| provenance.time.is_some());
+    match provenance {
+        Some(provenance) => {
+            let mut bytes = Provenance {
+                time: None,
+                ..provenance
+            }
+            .line()
+            .into_bytes();
+            bytes.extend_from_slice(&output[end..]);
+            Cow::Owned(bytes)
+        }
+        None => Cow::Borrowed(output),
+    }
+}
+
+/// One line per file whose section differs; empty unless both outputs are
+/// text.
+fn changed_sections(old: &[u8], new: &[u8]) -> String {
+    match (std::str::from_utf8(old), std::str::from_utf8(new)) {
+        (Ok(old), Ok(new)) => {
+            let (old, new) = (Snapshot::parse(old), Snapshot::parse(new));
+            diff::summary(&diff::changes(&old, &new))
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_provenance_time_is_ignored() {
+        let stamped = "# copytree-provenance version=0.1.0 args=src time=2026-10-16T09:30:00Z\n\
+                       --- src/a.rs ---\nfn a() {}\n";
+        let unstamped = "# copytree-provenance version=0.1.0 args=src\n\
+                         --- src/a.rs ---\nfn a() {}\n";
+        assert_eq!(
+            without_time(stamped.as_bytes()),
+            without_time(unstamped.as_bytes())
+        );
+        let other_args = unstamped.replace("args=src", "args=lib");
+        assert_ne!(
+            without_time(stamped.as_bytes()),
+            without_time(other_args.as_bytes())
+        );
+        assert_eq!(without_time(b"--- a ---\n"), Cow::Borrowed(b"--- a ---\n"));
+    }
+
+    #[test]
+    fn changed_sections_names_the_files() {
+        let old = "--- src/a.rs ---\nfn a() {}\n\n--- src/b.rs ---\nfn b() {}\n";
+        let new = "--- src/a.rs ---\nfn a() { 1 }\n\n--- src/c.rs ---\nfn c() {}\n";
+        assert_eq!(
+            changed_sections(old.as_bytes(), new.as_bytes()),
+            "changed  src/a.rs\nremoved  src/b.rs\nadded    src/c.rs\n"
+        );
+        assert_eq!(changed_sections(&[0xff], new.as_bytes()), "");
+    }
+}
@@ -0,0 +1,1721 @@
+use crate::args::{parse_include_binary, parse_size, Args, TreeArgs, WalkArgs};
+use crate::encode::Encoding;
+use crate::eol::Eol;
+use crate::expand;
+use crate::fit::Strategy;
+use crate::format::{Bare, Format};
+use crate::kind::FileKind;
+use crate::launch::Viewer;
+use crate::output::{ClipboardKind, Compression};
+use crate::report::StatusStream;
+use crate::style::ColorChoice;
+use crate::walker::{self, GitDir};
+use anyhow::{anyhow, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
+/// Name of the per-repository config file looked up from each root.
+pub const PROJECT_FILE_NAME: &str = ".copytree.toml";
+
+/// Defaults read from a `config.toml` or `.copytree.toml`; every key mirrors
+/// the long flag of the same name, and absent keys leave the built-in default
+/// in place.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub exclude: Option<Vec<String>>,
+    pub exclude_contents: Option<Vec<String>>,
+    pub prune: Option<Vec<String>>,
+    #[serde(rename = "type")]
+    pub types: Option<Vec<FileKind>>,
+    pub changed_since: Option<String>,
+    pub max_per_dir: Option<NonZeroUsize>,
+    pub max_symlink_depth: Option<usize>,
+    pub sample: Option<NonZeroUsize>,
+    pub seed: Option<u64>,
+    pub format: Option<Format>,
+    pub template: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_file_bytes: Option<u64>,
+    pub list_archives: Option<NonZeroUsize>,
+    #[serde(default, deserialize_with = "deserialize_include_binary")]
+    pub include_binary: Option<u64>,
+    pub native_separators: Option<bool>,
+    pub no_sniff: Option<bool>,
+    pub relative_to: Option<String>,
+    pub absolute_paths: Option<bool>,
+    pub strip_prefix: Option<String>,
+    pub read_threads: Option<NonZeroUsize>,
+    pub cache: Option<CacheSetting>,
+    pub filter_cmd: Option<String>,
+    pub filter_cmd_for: Option<Vec<String>>,
+    pub filter_timeout: Option<NonZeroU64>,
+    pub interactive: Option<bool>,
+    pub last: Option<bool>,
+    pub no_gitignore: Option<bool>,
+    pub no_git_exclude: Option<bool>,
+    pub no_parent_ignore: Option<bool>,
+    pub require_git: Option<bool>,
+    pub no_ignore: Option<bool>,
+    pub include_git_dir: Option<GitDir>,
+    pub stdout: Option<bool>,
+    pub out: Option<Vec<String>>,
+    pub compress: Option<Compression>,
+    pub encode: Option<Encoding>,
+    pub output_eol: Option<Eol>,
+    pub output_eol_content: Option<bool>,
+    pub pipe_cmd: Option<String>,
+    pub pipe_timeout: Option<NonZeroU64>,
+    pub clipboard: Option<bool>,
+    pub append: Option<bool>,
+    pub force: Option<bool>,
+    pub mkdirs: Option<bool>,
+    pub provenance: Option<bool>,
+    pub no_timestamp: Option<bool>,
+    pub reproducible: Option<bool>,
+    pub bare: Option<Bare>,
+    pub walk_order: Option<bool>,
+    pub readme_first: Option<bool>,
+    pub no_tree: Option<bool>,
+    pub tree_tokens: Option<bool>,
+    pub tree_width: Option<usize>,
+    pub permalinks: Option<bool>,
+    pub fit_tokens: Option<u64>,
+    pub fit_strategy: Option<Strategy>,
+    pub no_budget_report: Option<bool>,
+    pub list: Option<bool>,
+    pub omit_skipped: Option<bool>,
+    pub open: Option<Viewer>,
+    pub report_json: Option<String>,
+    pub status_json: Option<StatusStream>,
+    pub manifest: Option<ManifestSetting>,
+    pub fail_on_skip: Option<bool>,
+    pub partial_on_interrupt: Option<bool>,
+    pub check: Option<bool>,
+    pub fix: Option<bool>,
+    pub quiet: Option<bool>,
+    pub verbose: Option<bool>,
+    pub require_clipboard: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub clipboard_limit: Option<u64>,
+    pub force_clipboard: Option<bool>,
+    pub clipboard_append: Option<bool>,
+    pub clipboard_backend: Option<ClipboardKind>,
+    pub clipboard_cmd: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_memory: Option<u64>,
+    pub serve: Option<SocketAddr>,
+    pub serve_addr: Option<SocketAddr>,
+    pub serve_once: Option<bool>,
+    pub post: Option<String>,
+    pub post_header: Option<Vec<String>>,
+    pub color: Option<ColorChoice>,
+    /// Project config only: files, relative to the config's directory, that
+    /// are always part of the output.
+    pub always_include: Option<Vec<PathBuf>>,
+    /// `[profile.NAME]` tables, applied on top by `--profile NAME`.
+    #[serde(skip_serializing)]
+    pub profile: Option<BTreeMap<String, Config>>,
+}
+
+impl Config {
+    /// Layers `over` on top of `self`: keys set in `over` win, exclude,
+    /// exclude-contents, and prune patterns from both are kept (`self`'s first), and profiles of
+    /// the same name are layered the same way.
+    pub fn overlay(self, over: Config) -> Config {
+        let combine = |base: Option<Vec<String>>, extra: Option<Vec<String>>| match (base, extra) {
+            (Some(mut base), Some(extra)) => {
+                base.extend(extra);
+                Some(base)
+            }
+            (base, extra) => extra.or(base),
+        };
+        let exclude = combine(self.exclude, over.exclude);
+        let exclude_contents = combine(self.exclude_contents, over.exclude_contents);
+        let prune = combine(self.prune, over.prune);
+        let profile = match (self.profile, over.profile) {
+            (Some(mut base), Some(extra)) => {
+                for (name, profile) in extra {
+                    let merged = match base.remove(&name) {
+                        Some(existing) => existing.overlay(profile),
+                        None => profile,
+                    };
+                    base.insert(name, merged);
+                }
+                Some(base)
+            }
+            (base, extra) => extra.or(base),
+        };
+        Config {
+            exclude,
+            exclude_contents,
+            prune,
+            types: over.types.or(self.types),
+            changed_since: over.changed_since.or(self.changed_since),
+            max_per_dir: over.max_per_dir.or(self.max_per_dir),
+            max_symlink_depth: over.max_symlink_depth.or(self.max_symlink_depth),
+            sample: over.sample.or(self.sample),
+            seed: over.seed.or(self.seed),
+            format: over.format.or(self.format),
+            template: over.template.or(self.template),
+            max_file_bytes: over.max_file_bytes.or(self.max_file_bytes),
+            list_archives: over.list_archives.or(self.list_archives),
+            include_binary: over.include_binary.or(self.include_binary),
+            native_separators: over.native_separators.or(self.native_separators),
+            no_sniff: over.no_sniff.or(self.no_sniff),
+            relative_to: over.relative_to.or(self.relative_to),
+            absolute_paths: over.absolute_paths.or(self.absolute_paths),
+            strip_prefix: over.strip_prefix.or(self.strip_prefix),
+            read_threads: over.read_threads.or(self.read_threads),
+            cache: over.cache.or(self.cache),
+            filter_cmd: over.filter_cmd.or(self.filter_cmd),
+            filter_cmd_for: over.filter_cmd_for.or(self.filter_cmd_for),
+            filter_timeout: over.filter_timeout.or(self.filter_timeout),
+            interactive: over.interactive.or(self.interactive),
+            last: over.last.or(self.last),
+            no_gitignore: over.no_gitignore.or(self.no_gitignore),
+            no_git_exclude: over.no_git_exclude.or(self.no_git_exclude),
+            no_parent_ignore: over.no_parent_ignore.or(self.no_parent_ignore),
+            require_git: over.require_git.or(self.require_git),
+            no_ignore: over.no_ignore.or(self.no_ignore),
+            include_git_dir: over.include_git_dir.or(self.include_git_dir),
+            stdout: over.stdout.or(self.stdout),
+            out: over.out.or(self.out),
+            compress: over.compress.or(self.compress),
+            encode: over.encode.or(self.encode),
+            output_eol: over.output_eol.or(self.output_eol),
+            output_eol_content: over.output_eol_content.or(self.output_eol_content),
+            pipe_cmd: over.pipe_cmd.or(self.pipe_cmd),
+            pipe_timeout: over.pipe_timeout.or(self.pipe_timeout),
+            clipboard: over.clipboard.or(self.clipboard),
+            append: over.append.or(self.append),
+            force: over.force.or(self.force),
+            mkdirs: over.mkdirs.or(self.mkdirs),
+            provenance: over.provenance.or(self.provenance),
+            no_timestamp: over.no_timestamp.or(self.no_timestamp),
+            reproducible: over.reproducible.or(self.reproducible),
+            bare: over.bare.or(self.bare),
+            walk_order: over.walk_order.or(self.walk_order),
+            readme_first: over.readme_first.or(self.readme_first),
+            no_tree: over.no_tree.or(self.no_tree),
+            tree_tokens: over.tree_tokens.or(self.tree_tokens),
+            tree_width: over.tree_width.or(self.tree_width),
+            permalinks: over.permalinks.or(self.permalinks),
+            fit_tokens: over.fit_tokens.or(self.fit_tokens),
+            fit_strategy: over.fit_strategy.or(self.fit_strategy),
+            no_budget_report: over.no_budget_report.or(self.no_budget_report),
+            list: over.list.or(self.list),
+            omit_skipped: over.omit_skipped.or(self.omit_skipped),
+            open: over.open.or(self.open),
+            report_json: over.report_json.or(self.report_json),
+            status_json: over.status_json.or(self.status_json),
+            manifest: over.manifest.or(self.manifest),
+            fail_on_skip: over.fail_on_skip.or(self.fail_on_skip),
+            partial_on_interrupt: over.partial_on_interrupt.or(self.partial_on_interrupt),
+            check: over.check.or(self.check),
+            fix: over.fix.or(self.fix),
+            quiet: over.quiet.or(self.quiet),
+            verbose: over.verbose.or(self.verbose),
+            require_clipboard: over.require_clipboard.or(self.require_clipboard),
+            clipboard_limit: over.clipboard_limit.or(self.clipboard_limit),
+            force_clipboard: over.force_clipboard.or(self.force_clipboard),
+            clipboard_append: over.clipboard_append.or(self.clipboard_append),
+            clipboard_backend: over.clipboard_backend.or(self.clipboard_backend),
+            clipboard_cmd: over.clipboard_cmd.or(self.clipboard_cmd),
+            max_memory: over.max_memory.or(self.max_memory),
+            serve: over.serve.or(self.serve),
+            serve_addr: over.serve_addr.or(self.serve_addr),
+            serve_once: over.serve_once.or(self.serve_once),
+            post: over.post.or(self.post),
+            post_header: over.post_header.or(self.post_header),
+            color: over.color.or(self.color),
+            always_include: over.always_include.or(self.always_include),
+            profile,
+        }
+    }
+
+    /// Layers the named profiles on top, in order. Unknown names are an
+    /// error listing the profiles that exist.
+    pub fn with_profiles(self, names: &[String]) -> Result<Config> {
+        let mut config = self;
+        for name in names {
+            let Some(profile) = config
+                .profile
+                .as_ref()
+                .and_then(|profiles| profiles.get(name))
+                .cloned()
+            else {
+                let available = config
+                    .profile
+                    .iter()
+                    .flat_map(|profiles| profiles.keys())
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                return Err(if available.is_empty() {
+                    anyhow!("unknown profile `{}`: no profiles are defined", name)
+                } else {
+                    anyhow!(
+                        "unknown profile `{}`; available profiles: {}",
+                        name,
+                        available.join(", ")
+                    )
+                });
+            };
+            config = config.overlay(profile);
+        }
+        Ok(config)
+    }
+
+    /// The keys this config sets, as `key = value` on one line.
+    pub fn summary(&self) -> String {
+        let table = match toml::Table::try_from(self) {
+            Ok(table) => table,
+            Err(err) => return format!("<{}>", err),
+        };
+        if table.is_empty() {
+            return "(no changes)".to_string();
+        }
+        table
+            .iter()
+            .map(|(key, value)| format!("{} = {}", key, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Environment variables read by [`from_env`].
+pub const ENV_EXCLUDE: &str = "COPYTREE_EXCLUDE";
+pub const ENV_MAX_FILE_BYTES: &str = "COPYTREE_MAX_FILE_BYTES";
+pub const ENV_FORMAT: &str = "COPYTREE_FORMAT";
+pub const ENV_NO_GITIGNORE: &str = "COPYTREE_NO_GITIGNORE";
+pub const ENV_OUT: &str = "COPYTREE_OUT";
+
+/// Defaults from the `COPYTREE_*` environment variables, the lowest layer
+/// above the built-in defaults.
+pub fn from_env() -> Result<Config> {
+    from_vars(|name| std::env::var_os(name))
+}
+
+/// Builds the environment layer from `lookup`; invalid values are errors
+/// naming the variable.
+pub fn from_vars<F>(lookup: F) -> Result<Config>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let text = |name: &str| -> Result<Option<String>> {
+        match lookup(name) {
+            None => Ok(None),
+            Some(value) => value
+                .into_string()
+                .map(Some)
+                .map_err(|_| anyhow!("{} is not valid UTF-8", name)),
+        }
+    };
+    let mut config = Config::default();
+    if let Some(value) = lookup(ENV_EXCLUDE) {
+        let patterns = std::env::split_paths(&value)
+            .filter(|pattern| !pattern.as_os_str().is_empty())
+            .map(|pattern| {
+                pattern
+                    .into_os_string()
+                    .into_string()
+                    .map_err(|_| anyhow!("{} is not valid UTF-8", ENV_EXCLUDE))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        config.exclude = Some(patterns).filter(|patterns| !patterns.is_empty());
+    }
+    if let Some(value) = text(ENV_MAX_FILE_BYTES)? {
+        let bytes = parse_size(&value).map_err(|err| anyhow!("{}: {}", ENV_MAX_FILE_BYTES, err))?;
+        config.max_file_bytes = Some(bytes);
+    }
+    if let Some(value) = text(ENV_FORMAT)? {
+        let format = Format::from_str(value.trim(), true).map_err(|_| {
+            anyhow!(
+                "{}: unknown format `{}` (expected plain or tar)",
+                ENV_FORMAT,
+                value
+            )
+        })?;
+        config.format = Some(format);
+    }
+    if let Some(value) = text(ENV_NO_GITIGNORE)? {
+        config.no_gitignore = Some(parse_bool(&value).ok_or_else(|| {
+            anyhow!(
+                "{}: expected true/false, 1/0, yes/no, or on/off, got `{}`",
+                ENV_NO_GITIGNORE,
+                value
+            )
+        })?);
+    }
+    if let Some(value) = text(ENV_OUT)?.filter(|value| !value.is_empty()) {
+        config.out = Some(vec![value]);
+    }
+    Ok(config)
+}
+
+/// The `cache` key: `true` for the default directory, or a directory.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CacheSetting {
+    Enabled(bool),
+    Dir(String),
+}
+
+/// The `manifest` key: `true` to write it next to the `--out` file, or a
+/// path.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ManifestSetting {
+    Enabled(bool),
+    Path(String),
+}
+
+/// Accepts a byte count as a TOML integer or a size string like `"16K"`.
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct SizeVisitor;
+
+    impl Visitor<'_> for SizeVisitor {
+        type Value = u64;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a byte count like 512 or a size like \"16K\"")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+            Ok(value)
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<u64, E> {
+            u64::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+            parse_size(value).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(SizeVisitor).map(Some)
+}
+
+/// Accepts `--include-binary`'s `"base64"` or `"base64:8KiB"`.
+fn deserialize_include_binary<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    parse_include_binary(&value)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "" | "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Where the user config is read from: `~/.config/copytree/config.toml`
+/// when that file exists, otherwise the platform config directory.
+pub fn user_config_path() -> Option<PathBuf> {
+    let dot_config =
+        dirs::home_dir().map(|home| home.join(".config").join("copytree").join("config.toml"));
+    if let Some(path) = dot_config.as_ref().filter(|path| path.is_file()) {
+        return Some(path.clone());
+    }
+    dirs::config_dir()
+        .map(|dir| dir.join("copytree").join("config.toml"))
+        .or(dot_config)
+}
+
+/// Reads the user config at `path`; a missing file is not an error.
+pub fn load_user(path: &Path) -> Result<Option<Config>> {
+    let config = load(path)?;
+    if config
+        .as_ref()
+        .is_some_and(|config| config.always_include.is_some())
+    {
+        return Err(anyhow!(
+            "{}: `always_include` is only supported in {}",
+            path.display(),
+            PROJECT_FILE_NAME
+        ));
+    }
+    Ok(config)
+}
+
+/// Reads the project config at `path`, expanding `~` and environment
+/// variables in `always_include` and resolving it against the directory
+/// holding the file.
+pub fn load_project(path: &Path) -> Result<Option<Config>> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let Some(mut config) = load(path)? else {
+        return Ok(None);
+    };
+    if let Some(files) = &mut config.always_include {
+        for file in files.iter_mut() {
+            let expanded = expand::expand(&file.to_string_lossy(), false)?;
+            let joined = base.join(&expanded);
+            *file = if walker::is_glob_path(&joined.to_string_lossy()) {
+                // Keep metacharacters in the directory name literal.
+                Path::new(&globset::escape(&base.to_string_lossy())).join(expanded)
+            } else {
+                joined
+            };
+        }
+    }
+    Ok(Some(config))
+}
+
+/// The `.copytree.toml` that applies to `root`: the nearest one in `root` or
+/// an ancestor, searching no higher than the enclosing git repository. Outside
+/// a repository only `root` itself is checked.
+pub fn find_project_config(root: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let start = if root.is_dir() {
+        root.as_path()
+    } else {
+        root.parent()?
+    };
+    let mut candidates = Vec::new();
+    for dir in start.ancestors() {
+        candidates.push(dir.join(PROJECT_FILE_NAME));
+        if dir.join(".git").exists() {
+            return candidates.into_iter().find(|path| path.is_file());
+        }
+    }
+    Some(start.join(PROJECT_FILE_NAME)).filter(|path| path.is_file())
+}
+
+/// The single project config shared by all `roots`. Roots governed by
+/// different `.copytree.toml` files are rejected rather than mixed.
+pub fn project_config_for(roots: &[String]) -> Result<Option<PathBuf>> {
+    let mut found: Option<(PathBuf, &str)> = None;
+    for root in roots {
+        let base = if walker::is_glob_path(root) {
+            walker::glob_base(root)
+        } else {
+            PathBuf::from(root)
+        };
+        let Some(path) = find_project_config(&base) else {
+            continue;
+        };
+        match &found {
+            Some((first, first_root)) if *first != path => {
+                return Err(anyhow!(
+                    "roots {} and {} use different project configs ({} and {}); \
+                     run them separately or pass --no-config",
+                    first_root,
+                    root,
+                    first.display(),
+                    path.display()
+                ));
+            }
+            Some(_) => {}
+            None => found = Some((path, root)),
+        }
+    }
+    Ok(found.map(|(path, _)| path))
+}
+
+fn load(path: &Path) -> Result<Option<Config>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read config {}", path.display()))
+        }
+    };
+    parse(&text, path).map(Some)
+}
+
+/// Parses config text, naming `path` and the offending key on errors.
+pub fn parse(text: &str, path: &Path) -> Result<Config> {
+    let config: Config =
+        serde_path_to_error::deserialize(toml::Deserializer::new(text)).map_err(|err| {
+            let key = err.path().to_string();
+            let inner = err.into_inner();
+            let location = match inner.span() {
+                Some(span) => format!("{}:{}", path.display(), line_of(text, span.start)),
+                None => path.display().to_string(),
+            };
+            if key == "." {
+                anyhow!("{}: {}", location, inner.message())
+            } else {
+                anyhow!("{}: invalid `{}`: {}", location, key, inner.message())
+            }
+        })?;
+    validate(&config, path, "")?;
+    for (name, profile) in config.profile.iter().flatten() {
+        let table = format!("profile.{}.", name);
+        if profile.profile.is_some() {
+            return Err(anyhow!(
+                "{}: `{}profile`: profiles cannot be nested",
+                path.display(),
+                table
+            ));
+        }
+        validate(profile, path, &table)?;
+    }
+    Ok(config)
+}
+
+fn validate(config: &Config, path: &Path, table: &str) -> Result<()> {
+    if config.quiet == Some(true) && config.verbose == Some(true) {
+        return Err(anyhow!(
+            "{}: `{}quiet` and `{}verbose` cannot both be true",
+            path.display(),
+            table,
+            table
+        ));
+    }
+    Ok(())
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// Fills in every option the command line left unset from `config`, the
+/// user and project configs already layered with [`Config::overlay`].
+///
+/// Precedence is CLI over config over built-in defaults, decided per option,
+/// with three exceptions: exclude patterns from both sources are combined
+/// (config first), the sink options (`stdout`, `out`, `clipboard`) are taken
+/// from the config only when none of them was given on the command line, and
+/// `quiet`/`verbose` likewise count as one setting.
+pub fn apply(config: &Config, args: &mut Args, matches: &ArgMatches) {
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    apply_walk(config, &mut args.walk, matches);
+    if let Some(format) = config.format.filter(|_| !from_cli("format")) {
+        args.format = format;
+    }
+    if let Some(template) = config.template.as_ref().filter(|_| !from_cli("template")) {
+        args.template = Some(template.clone());
+    }
+    if let Some(threads) = config.read_threads.filter(|_| !from_cli("read_threads")) {
+        args.read_threads = Some(threads);
+    }
+    match config.cache.as_ref().filter(|_| !from_cli("cache")) {
+        Some(CacheSetting::Enabled(true)) => args.cache = Some(None),
+        Some(CacheSetting::Enabled(false)) => args.cache = None,
+        Some(CacheSetting::Dir(dir)) => args.cache = Some(Some(dir.clone())),
+        None => {}
+    }
+    if let Some(command) = config
+        .filter_cmd
+        .as_ref()
+        .filter(|_| !from_cli("filter_cmd"))
+    {
+        args.filter_cmd = Some(command.clone());
+    }
+    if let Some(rules) = config
+        .filter_cmd_for
+        .as_ref()
+        .filter(|_| !from_cli("filter_cmd_for"))
+    {
+        args.filter_cmd_for = rules.clone();
+    }
+    if let Some(seconds) = config
+        .filter_timeout
+        .filter(|_| !from_cli("filter_timeout"))
+    {
+        args.filter_timeout = seconds.get();
+    }
+    if let Some(compress) = config.compress.filter(|_| !from_cli("compress")) {
+        args.compress = Some(compress);
+    }
+    if let Some(encoding) = config.encode.filter(|_| !from_cli("encode")) {
+        args.encode = Some(encoding);
+    }
+    if let Some(eol) = config.output_eol.filter(|_| !from_cli("output_eol")) {
+        args.output_eol = eol;
+    }
+    if let Some(command) = config.pipe_cmd.as_ref().filter(|_| !from_cli("pipe_cmd")) {
+        args.pipe_cmd = Some(command.clone());
+    }
+    if let Some(seconds) = config.pipe_timeout.filter(|_| !from_cli("pipe_timeout")) {
+        args.pipe_timeout = seconds.get();
+    }
+
+    if let Some(color) = config.color.filter(|_| !from_cli("color")) {
+        args.color = color;
+    }
+    if let Some(bare) = config.bare.filter(|_| !from_cli("bare")) {
+        args.bare = Some(bare);
+    }
+    if let Some(width) = config.tree_width.filter(|_| !from_cli("tree_width")) {
+        args.tree_width = Some(width);
+    }
+    if let Some(budget) = config.fit_tokens.filter(|_| !from_cli("fit_tokens")) {
+        args.fit_tokens = Some(budget);
+    }
+    if let Some(strategy) = config.fit_strategy.filter(|_| !from_cli("fit_strategy")) {
+        args.fit_strategy = strategy;
+    }
+    if let Some(bytes) = config
+        .clipboard_limit
+        .filter(|_| !from_cli("clipboard_limit"))
+    {
+        args.clipboard_limit = bytes;
+    }
+    if let Some(kind) = config
+        .clipboard_backend
+        .filter(|_| !from_cli("clipboard_backend"))
+    {
+        args.clipboard_backend = kind;
+    }
+    if let Some(command) = config
+        .clipboard_cmd
+        .as_ref()
+        .filter(|_| !from_cli("clipboard_cmd"))
+    {
+        args.clipboard_cmd = Some(command.clone());
+    }
+    if let Some(bytes) = config.max_memory.filter(|_| !from_cli("max_memory")) {
+        args.max_memory = Some(bytes);
+    }
+    if let Some(headers) = config
+        .post_header
+        .as_ref()
+        .filter(|_| !from_cli("post_header"))
+    {
+        args.post_header = headers.clone();
+    }
+    if let Some(viewer) = config.open.filter(|_| !from_cli("open")) {
+        args.open = Some(viewer);
+    }
+    if let Some(target) = config
+        .report_json
+        .as_ref()
+        .filter(|_| !from_cli("report_json"))
+    {
+        args.report_json = Some(target.clone());
+    }
+    if let Some(stream) = config.status_json.filter(|_| !from_cli("status_json")) {
+        args.status_json = Some(stream);
+    }
+    match config.manifest.as_ref().filter(|_| !from_cli("manifest")) {
+        Some(ManifestSetting::Enabled(true)) => args.manifest = Some(None),
+        Some(ManifestSetting::Enabled(false)) => args.manifest = None,
+        Some(ManifestSetting::Path(path)) => args.manifest = Some(Some(path.clone())),
+        None => {}
+    }
+
+    let flags: [(&str, Option<bool>, &mut bool); 25] = [
+        ("interactive", config.interactive, &mut args.interactive),
+        ("last", config.last, &mut args.last),
+        ("append", config.append, &mut args.append),
+        ("force", config.force, &mut args.force),
+        (
+            "require_clipboard",
+            config.require_clipboard,
+            &mut args.require_clipboard,
+        ),
+        (
+            "force_clipboard",
+            config.force_clipboard,
+            &mut args.force_clipboard,
+        ),
+        (
+            "clipboard_append",
+            config.clipboard_append,
+            &mut args.clipboard_append,
+        ),
+        ("serve_once", config.serve_once, &mut args.serve_once),
+        ("mkdirs", config.mkdirs, &mut args.mkdirs),
+        ("provenance", config.provenance, &mut args.provenance),
+        ("no_timestamp", config.no_timestamp, &mut args.no_timestamp),
+        ("reproducible", config.reproducible, &mut args.reproducible),
+        ("walk_order", config.walk_order, &mut args.walk_order),
+        ("readme_first", config.readme_first, &mut args.readme_first),
+        ("no_tree", config.no_tree, &mut args.no_tree),
+        ("tree_tokens", config.tree_tokens, &mut args.tree_tokens),
+        ("permalinks", config.permalinks, &mut args.permalinks),
+        (
+            "no_budget_report",
+            config.no_budget_report,
+            &mut args.no_budget_report,
+        ),
+        ("list", config.list, &mut args.list),
+        ("omit_skipped", config.omit_skipped, &mut args.omit_skipped),
+        (
+            "output_eol_content",
+            config.output_eol_content,
+            &mut args.output_eol_content,
+        ),
+        ("fail_on_skip", config.fail_on_skip, &mut args.fail_on_skip),
+        (
+            "partial_on_interrupt",
+            config.partial_on_interrupt,
+            &mut args.partial_on_interrupt,
+        ),
+        ("check", config.check, &mut args.check),
+        ("fix", config.fix, &mut args.fix),
+    ];
+    for (id, value, target) in flags {
+        if let Some(value) = value.filter(|_| !from_cli(id)) {
+            *target = value;
+        }
+    }
+
+    if !["stdout", "out", "clipboard", "serve", "serve_addr", "post"]
+        .into_iter()
+        .any(from_cli)
+    {
+        args.stdout = config.stdout.unwrap_or(args.stdout);
+        args.clipboard = config.clipboard.unwrap_or(args.clipboard);
+        args.serve = config.serve.or(args.serve);
+        args.serve_addr = config.serve_addr.or(args.serve_addr);
+        if let Some(url) = &config.post {
+            args.post = Some(url.clone());
+        }
+        if let Some(out) = &config.out {
+            args.out = out.clone();
+        }
+    }
+    if !from_cli("quiet") && !from_cli("verbose") {
+        args.quiet = config.quiet.unwrap_or(args.quiet);
+        args.verbose = config.verbose.unwrap_or(args.verbose);
+    }
+}
+
+/// [`apply`] for `copytree tree`, which only has the walk options and `color`.
+pub fn apply_tree(config: &Config, args: &mut TreeArgs, matches: &ArgMatches) {
+    apply_walk(config, &mut args.walk, matches);
+    if let Some(color) = config
+        .color
+        .filter(|_| matches.value_source("color") != Some(ValueSource::CommandLine))
+    {
+        args.color = color;
+    }
+}
+
+/// The part of [`apply`] that `tree` and `stats` share with `copy`: the
+/// options deciding which files are walked and read.
+pub fn apply_walk(config: &Config, args: &mut WalkArgs, matches: &ArgMatches) {
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    if let Some(exclude) = &config.exclude {
+        args.exclude.splice(0..0, exclude.iter().cloned());
+    }
+    if let Some(exclude_contents) = &config.exclude_contents {
+        args.exclude_contents
+            .splice(0..0, exclude_contents.iter().cloned());
+    }
+    if let Some(prune) = &config.prune {
+        args.prune.splice(0..0, prune.iter().cloned());
+    }
+    if let Some(types) = config.types.as_ref().filter(|_| !from_cli("types")) {
+        args.types = types.clone();
+    }
+    if let Some(reference) = config
+        .changed_since
+        .as_ref()
+        .filter(|_| !from_cli("changed_since"))
+    {
+        args.changed_since = Some(reference.clone());
+    }
+    if let Some(limit) = config.max_per_dir.filter(|_| !from_cli("max_per_dir")) {
+        args.max_per_dir = Some(limit);
+    }
+    if let Some(depth) = config
+        .max_symlink_depth
+        .filter(|_| !from_cli("max_symlink_depth"))
+    {
+        args.max_symlink_depth = depth;
+    }
+    if let Some(size) = config.sample.filter(|_| !from_cli("sample")) {
+        args.sample = Some(size);
+    }
+    if let Some(seed) = config.seed.filter(|_| !from_cli("seed")) {
+        args.seed = Some(seed);
+    }
+    if let Some(bytes) = config
+        .max_file_bytes
+        .filter(|_| !from_cli("max_file_bytes"))
+    {
+        args.max_file_bytes = bytes;
+    }
+    if let Some(entries) = config.list_archives.filter(|_| !from_cli("list_archives")) {
+        args.list_archives = Some(entries);
+    }
+    if let Some(bytes) = config
+        .include_binary
+        .filter(|_| !from_cli("include_binary"))
+    {
+        args.include_binary = Some(bytes);
+    }
+    if let Some(base) = config
+        .relative_to
+        .as_ref()
+        .filter(|_| !from_cli("relative_to"))
+    {
+        args.relative_to = Some(base.clone());
+    }
+    if let Some(prefix) = config
+        .strip_prefix
+        .as_ref()
+        .filter(|_| !from_cli("strip_prefix"))
+    {
+        args.strip_prefix = Some(prefix.clone());
+    }
+    let flags: [(&str, Option<bool>, &mut bool); 8] = [
+        ("no_gitignore", config.no_gitignore, &mut args.no_gitignore),
+        (
+            "no_git_exclude",
+            config.no_git_exclude,
+            &mut args.no_git_exclude,
+        ),
+        (
+            "no_parent_ignore",
+            config.no_parent_ignore,
+            &mut args.no_parent_ignore,
+        ),
+        ("require_git", config.require_git, &mut args.require_git),
+        ("no_ignore", config.no_ignore, &mut args.no_ignore),
+        (
+            "native_separators",
+            config.native_separators,
+            &mut args.native_separators,
+        ),
+        ("no_sniff", config.no_sniff, &mut args.no_sniff),
+        (
+            "absolute_paths",
+            config.absolute_paths,
+            &mut args.absolute_paths,
+        ),
+    ];
+    for (id, value, target) in flags {
+        if let Some(value) = value.filter(|_| !from_cli(id)) {
+            *target = value;
+        }
+    }
+    if let Some(mode) = config
+        .include_git_dir
+        .filter(|_| !from_cli("include_git_dir"))
+    {
+        args.include_git_dir = Some(mode);
+    }
+    if let Some(files) = &config.always_include {
+        // Entries with glob metacharacters are patterns like `--always-include`.
+        let (patterns, files): (Vec<&PathBuf>, Vec<&PathBuf>) = files
+            .iter()
+            .partition(|file| walker::is_glob_path(&file.to_string_lossy()));
+        args.always_include.splice(
+            0..0,
+            patterns
+                .into_iter()
+                .map(|pattern| pattern.to_string_lossy().into_owned()),
+        );
+        args.always_include_files = files.into_iter().cloned().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Cli;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn resolve(cli: &[&str], config: &Config) -> Args {
+        let matches = Cli::command()
+            .try_get_matches_from(std::iter::once("copytree").chain(cli.iter().copied()))
+            .expect("valid arguments");
+        let mut args = Cli::from_arg_matches(&matches)
+            .expect("valid arguments")
+            .copy;
+        apply(config, &mut args, &matches);
+        args
+    }
+
+    fn parse_str(text: &str) -> Result<Config> {
+        parse(text, Path::new("config.toml"))
+    }
+
+    /// Layers user and project text the way `main` does, then applies the
+    /// command line on top.
+    fn resolve_layers(cli: &[&str], user: &str, project: &str) -> Args {
+        resolve_with_profiles(cli, user, project, &[]).expect("known profiles")
+    }
+
+    fn resolve_with_profiles(
+        cli: &[&str],
+        user: &str,
+        project: &str,
+        profiles: &[&str],
+    ) -> Result<Args> {
+        let names: Vec<String> = profiles.iter().map(|name| name.to_string()).collect();
+        let merged = Config::default()
+            .overlay(parse_str(user).expect("valid user config"))
+            .overlay(parse_str(project).expect("valid project config"))
+            .with_profiles(&names)?;
+        Ok(resolve(cli, &merged))
+    }
+
+    #[test]
+    fn parses_every_key() {
+        let config = parse_str(
+            r#"
+            exclude = ["target/**", "*.lock"]
+            exclude_contents = ["fixtures/**"]
+            prune = ["node_modules"]
+            type = ["code", "config"]
+            max_per_dir = 20
+            max_symlink_depth = 8
+            sample = 50
+            seed = 42
+            format = "tar"
+            template = "@review"
+            max_file_bytes = 4096
+            list_archives = 20
+            include_binary = "base64:8KiB"
+            native_separators = true
+            no_sniff = true
+            relative_to = "~/work"
+            absolute_paths = true
+            strip_prefix = "~/work"
+            changed_since = "origin/main"
+            read_threads = 2
+            cache = "~/.cache/copytree-work"
+            filter_cmd = "cat"
+            filter_cmd_for = ["**/*.sql=sqlformat -"]
+            filter_timeout = 30
+            no_gitignore = true
+            no_git_exclude = true
+            no_parent_ignore = true
+            require_git = true
+            no_ignore = true
+            include_git_dir = "full"
+            stdout = false
+            out = ["ctx.txt"]
+            compress = "zstd"
+            encode = "base64"
+            output_eol = "crlf"
+            output_eol_content = true
+            pipe_cmd = "gzip"
+            pipe_timeout = 60
+            clipboard = true
+            require_clipboard = true
+            clipboard_limit = "4MiB"
+            force_clipboard = true
+            clipboard_append = true
+            clipboard_backend = "command"
+            clipboard_cmd = "wl-copy"
+            max_memory = "256MiB"
+            serve = "127.0.0.1:8000"
+            serve_addr = "0.0.0.0:8080"
+            serve_once = true
+            post = "https://paste.example/api"
+            post_header = ["Authorization: Bearer token"]
+            mkdirs = true
+            provenance = true
+            no_timestamp = true
+            reproducible = true
+            walk_order = true
+            readme_first = true
+            no_tree = true
+            tree_tokens = true
+            tree_width = 100
+            permalinks = true
+            fit_tokens = 8000
+            fit_strategy = "tests-first"
+            no_budget_report = true
+            list = true
+            omit_skipped = true
+            fail_on_skip = true
+            partial_on_interrupt = true
+            check = true
+            fix = true
+            quiet = true
+            verbose = false
+            interactive = true
+            last = true
+            append = true
+            force = true
+            open = "pager"
+            bare = "loose"
+            report_json = "report.json"
+            status_json = "stdout"
+            manifest = "ctx.manifest.json"
+            color = "never"
+
+            [profile.full]
+            max_file_bytes = 0
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(
+            config.exclude,
+            Some(vec!["target/**".to_string(), "*.lock".to_string()])
+        );
+        assert_eq!(
+            config.exclude_contents,
+            Some(vec!["fixtures/**".to_string()])
+        );
+        assert_eq!(config.prune, Some(vec!["node_modules".to_string()]));
+        assert_eq!(config.types, Some(vec![FileKind::Code, FileKind::Config]));
+        assert_eq!(config.max_per_dir, NonZeroUsize::new(20));
+        assert_eq!(config.max_symlink_depth, Some(8));
+        assert_eq!(config.sample, NonZeroUsize::new(50));
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.format, Some(Format::Tar));
+        assert_eq!(config.template.as_deref(), Some("@review"));
+        assert_eq!(config.max_file_bytes, Some(4096));
+        assert_eq!(config.list_archives, NonZeroUsize::new(20));
+        assert_eq!(config.include_binary, Some(8 * 1024));
+        assert_eq!(config.native_separators, Some(true));
+        assert_eq!(config.no_sniff, Some(true));
+        assert_eq!(config.relative_to.as_deref(), Some("~/work"));
+        assert_eq!(config.absolute_paths, Some(true));
+        assert_eq!(config.strip_prefix.as_deref(), Some("~/work"));
+        assert_eq!(config.changed_since.as_deref(), Some("origin/main"));
+        assert_eq!(config.read_threads, NonZeroUsize::new(2));
+        assert_eq!(
+            config.cache,
+            Some(CacheSetting::Dir("~/.cache/copytree-work".to_string()))
+        );
+        assert_eq!(config.filter_cmd.as_deref(), Some("cat"));
+        assert_eq!(
+            config.filter_cmd_for,
+            Some(vec!["**/*.sql=sqlformat -".to_string()])
+        );
+        assert_eq!(config.filter_timeout, NonZeroU64::new(30));
+        assert_eq!(config.out, Some(vec!["ctx.txt".to_string()]));
+        assert_eq!(config.compress, Some(Compression::Zstd));
+        assert_eq!(config.encode, Some(Encoding::Base64));
+        assert_eq!(config.output_eol, Some(Eol::Crlf));
+        assert_eq!(config.output_eol_content, Some(true));
+        assert_eq!(config.pipe_cmd.as_deref(), Some("gzip"));
+        assert_eq!(config.pipe_timeout, NonZeroU64::new(60));
+        assert_eq!(config.provenance, Some(true));
+        assert_eq!(config.no_timestamp, Some(true));
+        assert_eq!(config.reproducible, Some(true));
+        assert_eq!(config.walk_order, Some(true));
+        assert_eq!(config.readme_first, Some(true));
+        assert_eq!(config.tree_tokens, Some(true));
+        assert_eq!(config.tree_width, Some(100));
+        assert_eq!(config.permalinks, Some(true));
+        assert_eq!(config.clipboard_limit, Some(4 * 1024 * 1024));
+        assert_eq!(config.force_clipboard, Some(true));
+        assert_eq!(config.clipboard_append, Some(true));
+        assert_eq!(config.clipboard_backend, Some(ClipboardKind::Command));
+        assert_eq!(config.clipboard_cmd.as_deref(), Some("wl-copy"));
+        assert_eq!(config.max_memory, Some(256 * 1024 * 1024));
+        assert_eq!(config.serve, "127.0.0.1:8000".parse().ok());
+        assert_eq!(config.serve_addr, "0.0.0.0:8080".parse().ok());
+        assert_eq!(config.serve_once, Some(true));
+        assert_eq!(config.post.as_deref(), Some("https://paste.example/api"));
+        assert_eq!(
+            config.post_header,
+            Some(vec!["Authorization: Bearer token".to_string()])
+        );
+        assert_eq!(config.fit_tokens, Some(8000));
+        assert_eq!(config.fit_strategy, Some(Strategy::Tests));
+        assert_eq!(config.no_budget_report, Some(true));
+        assert_eq!(config.list, Some(true));
+        assert_eq!(config.omit_skipped, Some(true));
+        assert_eq!(config.partial_on_interrupt, Some(true));
+        assert_eq!(config.check, Some(true));
+        assert_eq!(config.fix, Some(true));
+        assert_eq!(config.quiet, Some(true));
+        assert_eq!(config.open, Some(Viewer::Pager));
+        assert_eq!(config.bare, Some(Bare::Loose));
+        assert_eq!(config.report_json.as_deref(), Some("report.json"));
+        assert_eq!(config.status_json, Some(StatusStream::Stdout));
+        assert_eq!(
+            config.manifest,
+            Some(ManifestSetting::Path("ctx.manifest.json".to_string()))
+        );
+        assert_eq!(config.color, Some(ColorChoice::Never));
+        assert_eq!(config.include_git_dir, Some(GitDir::Full));
+        let profiles = config.profile.expect("profiles");
+        assert_eq!(profiles["full"].max_file_bytes, Some(0));
+    }
+
+    #[test]
+    fn type_errors_name_file_line_and_key() {
+        let err = parse_str("format = \"plain\"\nmax_file_bytes = \"big\"\n")
+            .expect_err("string is not a byte count")
+            .to_string();
+        assert!(
+            err.starts_with("config.toml:2: invalid `max_file_bytes`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn sizes_accept_integers_and_suffixed_strings() {
+        let config = parse_str("max_file_bytes = \"16K\"\n").expect("valid size");
+        assert_eq!(config.max_file_bytes, Some(16 * 1024));
+        let config = parse_str("max_file_bytes = 0\n").expect("valid size");
+        assert_eq!(config.max_file_bytes, Some(0));
+        assert!(parse_str("max_file_bytes = -1\n").is_err());
+
+        let env = env_layer(&[(ENV_MAX_FILE_BYTES, "2MiB")]).expect("valid size");
+        assert_eq!(env.max_file_bytes, Some(2 * 1024 * 1024));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = parse_str("max_bytes = 10\n")
+            .expect_err("unknown key")
+            .to_string();
+        assert!(err.starts_with("config.toml:1:"), "{err}");
+        assert!(err.contains("max_bytes"), "{err}");
+    }
+
+    #[test]
+    fn quiet_and_verbose_together_are_rejected() {
+        assert!(parse_str("quiet = true\nverbose = true\n").is_err());
+    }
+
+    #[test]
+    fn missing_file_loads_as_none() {
+        let path = std::env::temp_dir().join("copytree_config_missing/config.toml");
+        assert_eq!(load(&path).expect("missing is fine"), None);
+    }
+
+    #[test]
+    fn empty_config_keeps_built_in_defaults() {
+        let args = resolve(&[], &Config::default());
+        assert!(args.walk.exclude.is_empty());
+        assert_eq!(args.format, Format::Plain);
+        assert_eq!(args.walk.max_file_bytes, 16 * 1024);
+        assert!(!args.no_tree);
+    }
+
+    #[test]
+    fn exclude_combines_config_and_cli() {
+        let config = Config {
+            exclude: Some(vec!["fixtures/**".to_string()]),
+            ..Config::default()
+        };
+        assert_eq!(resolve(&[], &config).walk.exclude, ["fixtures/**"]);
+        assert_eq!(
+            resolve(&["-x", "*.md"], &config).walk.exclude,
+            ["fixtures/**", "*.md"]
+        );
+    }
+
+    #[test]
+    fn format_cli_wins_over_config() {
+        let config = Config {
+            format: Some(Format::Tar),
+            ..Config::default()
+        };
+        assert_eq!(resolve(&[], &config).format, Format::Tar);
+        assert_eq!(
+            resolve(&["--format", "plain"], &config).format,
+            Format::Plain
+        );
+    }
+
+    #[test]
+    fn max_file_bytes_cli_wins_over_config() {
+        let config = Config {
+            max_file_bytes: Some(8192),
+            ..Config::default()
+        };
+        assert_eq!(resolve(&[], &config).walk.max_file_bytes, 8192);
+        assert_eq!(
+            resolve(&["--max-file-bytes", "16384"], &config)
+                .walk
+                .max_file_bytes,
+            16384
+        );
+    }
+
+    #[test]
+    fn read_threads_cli_wins_over_config() {
+        let config = Config {
+            read_threads: NonZeroUsize::new(4),
+            ..Config::default()
+        };
+        assert_eq!(resolve(&[], &config).read_threads, NonZeroUsize::new(4));
+        assert_eq!(
+            resolve(&["--read-threads", "1"], &config).read_threads,
+            NonZeroUsize::new(1)
+        );
+    }
+
+    #[test]
+    fn cache_key_takes_a_bool_or_a_directory() {
+        let config = parse_str("cache = true").expect("bool parses");
+        assert_eq!(resolve(&[], &config).cache, Some(None));
+        assert_eq!(
+            resolve(&["--cache=/tmp/c"], &config).cache,
+            Some(Some("/tmp/c".to_string()))
+        );
+        let config = parse_str("cache = false").expect("bool parses");
+        assert_eq!(resolve(&[], &config).cache, None);
+        let config = parse_str("cache = \"/var/c\"").expect("string parses");
+        assert_eq!(
+            resolve(&[], &config).cache,
+            Some(Some("/var/c".to_string()))
+        );
+    }
+
+    #[test]
+    fn manifest_key_takes_a_bool_or_a_path() {
+        let config = parse_str("manifest = true").expect("bool parses");
+        assert_eq!(resolve(&[], &config).manifest, Some(None));
+        assert_eq!(
+            resolve(&["--manifest=m.json"], &config).manifest,
+            Some(Some("m.json".to_string()))
+        );
+        let config = parse_str("manifest = \"out/m.json\"").expect("string parses");
+        assert_eq!(
+            resolve(&[], &config).manifest,
+            Some(Some("out/m.json".to_string()))
+        );
+    }
+
+    #[test]
+    fn compress_cli_wins_over_config() {
+        let config = Config {
+            compress: Some(Compression::Zstd),
+            ..Config::default()
+        };
+        assert_eq!(resolve(&[], &config).compress, Some(Compression::Zstd));
+        assert_eq!(
+            resolve(&["--compress", "gzip"], &config).compress,
+            Some(Compression::Gzip)
+        );
+    }
+
+    #[test]
+    fn color_cli_wins_over_config() {
+        let config = Config {
+            color: Some(ColorChoice::Always),
+            ..Config::default()
+        };
+        assert_eq!(resolve(&[], &config).color, ColorChoice::Always);
+        assert_eq!(
+            resolve(&["--color", "never"], &config).color,
+            ColorChoice::Never
+        );
+    }
+
+    #[test]
+    fn boolean_flags_come_from_config_unless_given() {
+        let config = Config {
+            no_gitignore: Some(true),
+            no_git_exclude: Some(true),
+            require_git: Some(true),
+            require_clipboard: Some(true),
+            mkdirs: Some(true),
+            no_tree: Some(true),
+            fail_on_skip: Some(true),
+            ..Config::default()
+        };
+        let args = resolve(&[], &config);
+        assert!(args.walk.no_gitignore);
+        assert!(args.walk.no_git_exclude);
+        assert!(!args.walk.no_parent_ignore);
+        assert!(args.walk.require_git);
+        assert!(args.require_clipboard);
+        assert!(args.mkdirs);
+        assert!(args.no_tree);
+        assert!(args.fail_on_skip);
+
+        let config = Config {
+            no_tree: Some(false),
+            ..Config::default()
+        };
+        assert!(resolve(&["--no-tree"], &config).no_tree);
+    }
+
+    #[test]
+    fn config_sinks_apply_only_without_cli_sinks() {
+        let config = Config {
+            out: Some(vec!["ctx.txt".to_string()]),
+            clipboard: Some(true),
+            ..Config::default()
+        };
+        let args = resolve(&[], &config);
+        assert_eq!(args.out, ["ctx.txt"]);
+        assert!(args.clipboard);
+
+        let args = resolve(&["--stdout"], &config);
+        assert!(args.stdout);
+        assert!(args.out.is_empty());
+        assert!(!args.clipboard);
+    }
+
+    #[test]
+    fn verbosity_from_cli_replaces_config_verbosity() {
+        let config = Config {
+            quiet: Some(true),
+            ..Config::default()
+        };
+        assert!(resolve(&[], &config).quiet);
+        let args = resolve(&["--verbose"], &config);
+        assert!(args.verbose);
+        assert!(!args.quiet);
+    }
+
+    #[test]
+    fn project_config_overrides_user_config_and_cli_overrides_both() {
+        let user = "max_file_bytes = 1000\nformat = \"tar\"\nno_tree = true\n";
+        let project = "max_file_bytes = 8192\n";
+
+        let args = resolve_layers(&[], user, project);
+        assert_eq!(args.walk.max_file_bytes, 8192);
+        assert_eq!(args.format, Format::Tar);
+        assert!(args.no_tree);
+
+        let args = resolve_layers(&["--max-file-bytes", "5"], user, project);
+        assert_eq!(args.walk.max_file_bytes, 5);
+
+        let args = resolve_layers(&[], "", "");
+        assert_eq!(args.walk.max_file_bytes, 16 * 1024);
+    }
+
+    #[test]
+    fn excludes_accumulate_across_all_layers() {
+        let args = resolve_layers(
+            &["-x", "*.md"],
+            "exclude = [\"target/**\"]\n",
+            "exclude = [\"fixtures/**\"]\n",
+        );
+        assert_eq!(args.walk.exclude, ["target/**", "fixtures/**", "*.md"]);
+
+        let args = resolve_layers(
+            &["--exclude-contents", "*.json"],
+            "exclude_contents = [\"*.lock\"]\n",
+            "exclude_contents = [\"fixtures/**\"]\n",
+        );
+        assert_eq!(
+            args.walk.exclude_contents,
+            ["*.lock", "fixtures/**", "*.json"]
+        );
+    }
+
+    #[test]
+    fn always_include_is_resolved_against_the_project_directory() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path().canonicalize().expect("canonical temp dir");
+        let path = dir.join(PROJECT_FILE_NAME);
+        fs::write(
+            &path,
+            "always_include = [\"Cargo.toml\", \"docs/README.md\"]\n",
+        )
+        .expect("write config");
+
+        let config = load_project(&path).expect("valid").expect("present");
+        assert_eq!(
+            config.always_include,
+            Some(vec![dir.join("Cargo.toml"), dir.join("docs/README.md")])
+        );
+    }
+
+    #[test]
+    fn always_include_patterns_join_the_command_line_patterns() {
+        let config = Config {
+            always_include: Some(vec![
+                PathBuf::from("/repo/Cargo.toml"),
+                PathBuf::from("/repo/.github/**/*.yml"),
+            ]),
+            ..Config::default()
+        };
+        let args = resolve(&["--always-include", "README.md"], &config);
+        assert_eq!(
+            args.walk.always_include,
+            ["/repo/.github/**/*.yml", "README.md"]
+        );
+        assert_eq!(
+            args.walk.always_include_files,
+            [PathBuf::from("/repo/Cargo.toml")]
+        );
+    }
+
+    #[test]
+    fn always_include_is_rejected_in_the_user_config() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path().canonicalize().expect("canonical temp dir");
+        let path = dir.join("config.toml");
+        fs::write(&path, "always_include = [\"README.md\"]\n").expect("write config");
+
+        let err = load_user(&path).expect_err("project-only key").to_string();
+        assert!(err.contains("always_include"), "{err}");
+    }
+
+    #[test]
+    fn project_config_is_found_up_to_the_git_root() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let repo = temp.path().canonicalize().expect("canonical temp dir");
+        fs::create_dir_all(repo.join(".git")).expect("create .git");
+        fs::create_dir_all(repo.join("crates/api/src")).expect("create nested dirs");
+        fs::write(repo.join(PROJECT_FILE_NAME), "").expect("write config");
+
+        assert_eq!(
+            find_project_config(&repo.join("crates/api/src")),
+            Some(repo.join(PROJECT_FILE_NAME))
+        );
+
+        fs::write(repo.join("crates/api").join(PROJECT_FILE_NAME), "").expect("write config");
+        assert_eq!(
+            find_project_config(&repo.join("crates/api/src")),
+            Some(repo.join("crates/api").join(PROJECT_FILE_NAME))
+        );
+    }
+
+    #[test]
+    fn outside_a_repository_only_the_root_is_checked() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let parent = temp.path().canonicalize().expect("canonical temp dir");
+        fs::create_dir_all(parent.join("child")).expect("create child");
+        fs::write(parent.join(PROJECT_FILE_NAME), "").expect("write config");
+
+        assert_eq!(find_project_config(&parent.join("child")), None);
+        assert_eq!(
+            find_project_config(&parent),
+            Some(parent.join(PROJECT_FILE_NAME))
+        );
+    }
+
+    #[test]
+    fn roots_with_different_project_configs_are_rejected() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path().canonicalize().expect("canonical temp dir");
+        for repo in ["backend", "frontend"] {
+            fs::create_dir_all(dir.join(repo).join(".git")).expect("create repo");
+            fs::write(dir.join(repo).join(PROJECT_FILE_NAME), "").expect("write config");
+        }
+        fs::create_dir_all(dir.join("backend/src")).expect("create src");
+        let root = |relative: &str| dir.join(relative).to_string_lossy().into_owned();
+
+        let shared = project_config_for(&[root("backend"), root("backend/src")])
+            .expect("same config for both roots");
+        assert_eq!(shared, Some(dir.join("backend").join(PROJECT_FILE_NAME)));
+
+        let err = project_config_for(&[root("backend"), root("frontend")])
+            .expect_err("conflicting configs")
+            .to_string();
+        assert!(err.contains("different project configs"), "{err}");
+    }
+
+    const PROFILES: &str = r#"
+        [profile.full]
+        max_file_bytes = 0
+        no_gitignore = true
+
+        [profile.api]
+        max_file_bytes = 2048
+        exclude = ["tests/**"]
+        no_tree = true
+    "#;
+
+    #[test]
+    fn profiles_override_every_config_layer_and_cli_overrides_profiles() {
+        let user = "max_file_bytes = 1000\nformat = \"tar\"\n";
+        let project = format!("max_file_bytes = 8192\n{}", PROFILES);
+
+        let args = resolve_with_profiles(&[], user, &project, &["api"]).expect("known");
+        assert_eq!(args.walk.max_file_bytes, 2048);
+        assert_eq!(args.format, Format::Tar);
+        assert!(args.no_tree);
+        assert_eq!(args.walk.exclude, ["tests/**"]);
+
+        let args = resolve_with_profiles(&["--max-file-bytes", "7"], user, &project, &["api"])
+            .expect("known");
+        assert_eq!(args.walk.max_file_bytes, 7);
+    }
+
+    #[test]
+    fn later_profiles_override_earlier_ones() {
+        let args = resolve_with_profiles(&[], "", PROFILES, &["full", "api"]).expect("known");
+        assert_eq!(args.walk.max_file_bytes, 2048);
+        assert!(args.walk.no_gitignore);
+
+        let args = resolve_with_profiles(&[], "", PROFILES, &["api", "full"]).expect("known");
+        assert_eq!(args.walk.max_file_bytes, 0);
+        assert!(args.no_tree);
+    }
+
+    #[test]
+    fn project_profiles_extend_user_profiles_of_the_same_name() {
+        let user = "[profile.full]\nmax_file_bytes = 1\nmkdirs = true\n";
+        let args = resolve_with_profiles(&[], user, PROFILES, &["full"]).expect("known");
+        assert_eq!(args.walk.max_file_bytes, 0);
+        assert!(args.mkdirs);
+    }
+
+    #[test]
+    fn unknown_profiles_list_the_available_ones() {
+        let err = resolve_with_profiles(&[], "", PROFILES, &["tiny"])
+            .expect_err("unknown profile")
+            .to_string();
+        assert_eq!(err, "unknown profile `tiny`; available profiles: api, full");
+
+        let err = resolve_with_profiles(&[], "", "", &["tiny"])
+            .expect_err("no profiles")
+            .to_string();
+        assert!(err.contains("no profiles are defined"), "{err}");
+    }
+
+    #[test]
+    fn nested_profiles_are_rejected() {
+        let err = parse_str("[profile.a.profile.b]\nquiet = true\n")
+            .expect_err("nested")
+            .to_string();
+        assert!(err.contains("profile.a.profile"), "{err}");
+    }
+
+    #[test]
+    fn profiles_can_set_every_flag() {
+        let project = r#"
+            [profile.all]
+            exclude = ["*.md"]
+            format = "tar"
+            max_file_bytes = 1
+            read_threads = 3
+            interactive = true
+            last = true
+            no_gitignore = true
+            out = ["ctx.tar"]
+            compress = "gzip"
+            clipboard = true
+            append = true
+            force = true
+            mkdirs = true
+            no_tree = true
+            open = "editor"
+            report_json = "-"
+            fail_on_skip = true
+            verbose = true
+            require_clipboard = true
+            color = "always"
+        "#;
+        let args = resolve_with_profiles(&[], "", project, &["all"]).expect("known");
+        assert_eq!(args.walk.exclude, ["*.md"]);
+        assert_eq!(args.format, Format::Tar);
+        assert_eq!(args.walk.max_file_bytes, 1);
+        assert_eq!(args.read_threads, NonZeroUsize::new(3));
+        assert!(args.interactive && args.last);
+        assert!(args.walk.no_gitignore);
+        assert_eq!(args.out, ["ctx.tar"]);
+        assert_eq!(args.compress, Some(Compression::Gzip));
+        assert!(args.clipboard);
+        assert!(args.append && args.force && args.mkdirs && args.no_tree);
+        assert_eq!(args.open, Some(Viewer::Editor));
+        assert_eq!(args.report_json.as_deref(), Some("-"));
+        assert!(args.fail_on_skip && args.verbose && args.require_clipboard);
+        assert_eq!(args.color, ColorChoice::Always);
+    }
+
+    #[test]
+    fn summary_lists_the_keys_a_profile_sets() {
+        let config = parse_str(PROFILES).expect("valid");
+        let profiles = config.profile.expect("profiles");
+        assert_eq!(
+            profiles["api"].summary(),
+            "exclude = [\"tests/**\"], max_file_bytes = 2048, no_tree = true"
+        );
+        assert_eq!(Config::default().summary(), "(no changes)");
+    }
+
+    fn env_layer(vars: &[(&str, &str)]) -> Result<Config> {
+        from_vars(|name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| OsString::from(value))
+        })
+    }
+
+    #[test]
+    fn env_vars_fill_their_options() {
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        let config = env_layer(&[
+            (ENV_EXCLUDE, &format!("target/**{}*.lock", separator)),
+            (ENV_MAX_FILE_BYTES, "4096"),
+            (ENV_FORMAT, "TAR"),
+            (ENV_NO_GITIGNORE, "yes"),
+            (ENV_OUT, "ctx.txt"),
+        ])
+        .expect("valid variables");
+        assert_eq!(
+            config.exclude,
+            Some(vec!["target/**".to_string(), "*.lock".to_string()])
+        );
+        assert_eq!(config.max_file_bytes, Some(4096));
+        assert_eq!(config.format, Some(Format::Tar));
+        assert_eq!(config.no_gitignore, Some(true));
+        assert_eq!(config.out, Some(vec!["ctx.txt".to_string()]));
+
+        assert_eq!(env_layer(&[]).expect("no variables"), Config::default());
+    }
+
+    #[test]
+    fn invalid_env_values_name_the_variable() {
+        let err = env_layer(&[(ENV_MAX_FILE_BYTES, "16 bytes")])
+            .expect_err("not a size")
+            .to_string();
+        assert_eq!(
+            err,
+            "COPYTREE_MAX_FILE_BYTES: invalid size `16 bytes`: expected a size like 512, 16K, or 2MiB"
+        );
+
+        let err = env_layer(&[(ENV_FORMAT, "zip")])
+            .expect_err("unknown format")
+            .to_string();
+        assert!(err.starts_with("COPYTREE_FORMAT:"), "{err}");
+
+        let err = env_layer(&[(ENV_NO_GITIGNORE, "maybe")])
+            .expect_err("not a boolean")
+            .to_string();
+        assert!(err.starts_with("COPYTREE_NO_GITIGNORE:"), "{err}");
+    }
+
+    #[test]
+    fn config_files_and_cli_override_env_vars() {
+        let env = env_layer(&[(ENV_MAX_FILE_BYTES, "10"), (ENV_FORMAT, "tar")]).expect("valid");
+
+        let args = resolve(&[], &env.clone());
+        assert_eq!(args.walk.max_file_bytes, 10);
+        assert_eq!(args.format, Format::Tar);
+
+        let layered = env
+            .clone()
+            .overlay(parse_str("max_file_bytes = 20\n").expect("valid"));
+        let args = resolve(&[], &layered);
+        assert_eq!(args.walk.max_file_bytes, 20);
+        assert_eq!(args.format, Format::Tar);
+
+        let args = resolve(&["--max-file-bytes", "30"], &layered);
+        assert_eq!(args.walk.max_file_bytes, 30);
+    }
+
+    #[test]
+    fn from_env_reads_the_process_environment() {
+        std::env::set_var(ENV_MAX_FILE_BYTES, "2048");
+        std::env::set_var(ENV_OUT, "from-env.txt");
+        let set = from_env();
+        std::env::remove_var(ENV_MAX_FILE_BYTES);
+        std::env::remove_var(ENV_OUT);
+        let unset = from_env();
+
+        let args = resolve(&[], &set.expect("valid variables"));
+        assert_eq!(args.walk.max_file_bytes, 2048);
+        assert_eq!(args.out, ["from-env.txt"]);
+        let args = resolve(&[], &unset.expect("no variables"));
+        assert_eq!(args.walk.max_file_bytes, 16 * 1024);
+        assert!(args.out.is_empty());
+    }
+}
@@ -0,0 +1,757 @@
+use crate::encode;
+use crate::image::{self, ImageInfo};
+use crate::interrupt::Interrupt;
+use crate::kind::{self, FileKind};
+use crate::listing::{self, Listing};
+use crate::marker::Marker;
+use crate::output::{approximate_count, grouped_count, human_size};
+use crate::separators;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Why a file's body was left out of the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    Excluded {
+        pattern: String,
+    },
+    TooLarge {
+        size: u64,
+        limit: u64,
+    },
+    /// Not text; `kind` names what it looks like instead.
+    Binary {
+        kind: FileKind,
+    },
+    /// A zip or tar file, listed instead under `--list-archives`.
+    Archive {
+        listing: Listing,
+        size: u64,
+    },
+    /// A file that looked like an archive but could not be listed.
+    BadArchive {
+        error: String,
+    },
+    /// A symlink whose target does not exist.
+    BrokenSymlink {
+        target: PathBuf,
+    },
+    /// A symlink to a directory that `--max-symlink-depth` kept the walk
+    /// out of.
+    SymlinkDepth {
+        limit: usize,
+    },
+    /// A symlink to a directory enclosing it, which would repeat the walk.
+    SymlinkLoop {
+        target: PathBuf,
+    },
+    /// A file whose symlink target was already emitted under `first`.
+    SameAs {
+        first: PathBuf,
+    },
+    Permission,
+    /// Dropped by `--fit-tokens` to bring the output under the budget.
+    OverBudget {
+        tokens: u64,
+    },
+}
+
+impl SkipReason {
+    pub const EXCLUDED_CODE: &'static str = "excluded";
+    pub const SAME_AS_CODE: &'static str = "same-content";
+    pub const OVER_BUDGET_CODE: &'static str = "budget";
+    pub const ARCHIVE_CODE: &'static str = "archive";
+
+    /// The inline note written in place of the file body: the [`Marker`]
+    /// line, followed for an archive by its listing.
+    pub fn marker(&self) -> String {
+        let line = Marker::new(self.code(), self.fields(), self.describe()).to_string();
+        match self {
+            Self::Archive { listing, .. } if !listing.entry_lines().is_empty() => {
+                format!("{}\n{}", line, listing.entry_lines())
+            }
+            _ => line,
+        }
+    }
+
+    /// Human-readable explanation, including the rule responsible.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Excluded { pattern } => format!("excluded by pattern {}", pattern),
+            Self::TooLarge { size, limit } => format!(
+                "file size {} bytes exceeds --max-file-bytes {}",
+                size, limit
+            ),
+            Self::Binary { kind } => format!("{} file", kind),
+            Self::Archive { listing, size } => {
+                format!("archive: {}, {}", listing.summary(), human_size(*size))
+            }
+            Self::BadArchive { error } => format!("archive file, not listed: {}", error),
+            Self::BrokenSymlink { target } => {
+                format!("broken symlink -> {}", separators::display(target))
+            }
+            Self::SymlinkDepth { limit } => format!(
+                "symlinked directory not entered, past --max-symlink-depth {}",
+                limit
+            ),
+            Self::SymlinkLoop { target } => format!(
+                "symlinked directory not entered, leads back to an enclosing directory -> {}",
+                separators::display(target)
+            ),
+            Self::SameAs { first } => {
+                format!("same content as {} (symlink)", separators::display(first))
+            }
+            Self::Permission => "permission denied".to_string(),
+            Self::OverBudget { tokens } => format!(
+                "dropped to fit --fit-tokens, ~{} tokens",
+                approximate_count(*tokens)
+            ),
+        }
+    }
+
+    /// The reason as one of a closed set of codes, shared by the skip
+    /// marker, `--report-json`, `stats`, and the tar manifest.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Excluded { .. } => Self::EXCLUDED_CODE,
+            Self::TooLarge { .. } => "too-large",
+            Self::Binary { .. } => "binary",
+            Self::Archive { .. } => Self::ARCHIVE_CODE,
+            Self::BadArchive { .. } => "bad-archive",
+            Self::BrokenSymlink { .. } => "broken-symlink",
+            Self::SymlinkDepth { .. } => "symlink-depth",
+            Self::SymlinkLoop { .. } => "symlink-loop",
+            Self::SameAs { .. } => Self::SAME_AS_CODE,
+            Self::Permission => "permission",
+            Self::OverBudget { .. } => Self::OVER_BUDGET_CODE,
+        }
+    }
+
+    /// The details the marker carries after the code, as exact values.
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::Excluded { pattern } => vec![("pattern", pattern.clone())],
+            Self::TooLarge { size, limit } => {
+                vec![("size", size.to_string()), ("limit", limit.to_string())]
+            }
+            Self::Binary { kind } => vec![("kind", kind.to_string())],
+            Self::Archive { listing, size } => vec![
+                ("format", listing.format.to_string()),
+                ("size", size.to_string()),
+            ],
+            Self::BrokenSymlink { target } => vec![("target", separators::display(target))],
+            Self::SymlinkDepth { limit } => vec![("limit", limit.to_string())],
+            Self::SymlinkLoop { target } => vec![("target", separators::display(target))],
+            Self::SameAs { first } => vec![("first", separators::display(first))],
+            Self::OverBudget { tokens } => vec![("tokens", tokens.to_string())],
+            Self::BadArchive { .. } | Self::Permission => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileContent {
+    Text(String),
+    /// A binary file kept whole under `--include-binary`, written as
+    /// base64.
+    Binary(Vec<u8>),
+    /// An image, binary or too large, described from its header in place
+    /// of its bytes.
+    Image {
+        info: ImageInfo,
+        size: u64,
+    },
+    Skipped(SkipReason),
+}
+
+/// What a section header adds after the path of a binary file:
+/// `--- favicon.ico (binary, base64, 1,844 bytes) ---`.
+const BINARY_NOTE: (&str, &str) = (" (binary, base64, ", " bytes)");
+
+impl FileContent {
+    /// Whether the file has a section of its own in the output: its text,
+    /// its base64, or an image's description.
+    pub fn is_included(&self) -> bool {
+        !matches!(self, Self::Skipped(_))
+    }
+
+    /// The section header's text for a file displayed as `path`: the path,
+    /// noted for a binary file.
+    pub fn header(&self, path: &str) -> String {
+        match self {
+            Self::Binary(bytes) => format!(
+                "{}{}{}{}",
+                path,
+                BINARY_NOTE.0,
+                grouped_count(bytes.len() as u64),
+                BINARY_NOTE.1
+            ),
+            _ => path.to_string(),
+        }
+    }
+
+    /// What a section holds: the text, the base64 lines of a binary file,
+    /// an image's stanza, or the skip marker.
+    pub fn body(&self) -> Cow<'_, str> {
+        match self {
+            Self::Text(text) => Cow::Borrowed(text),
+            Self::Binary(bytes) => Cow::Owned(encode::base64_lines(bytes)),
+            Self::Image { info, size } => Cow::Owned(info.stanza(*size)),
+            Self::Skipped(reason) => Cow::Owned(reason.marker()),
+        }
+    }
+
+    /// Tokens this file adds to the output: its text, base64, or image
+    /// stanza, or the listing that stands in for an archive; 0 for any
+    /// other skip.
+    pub fn estimated_tokens(&self) -> usize {
+        match self {
+            Self::Text(text) => estimate_tokens(text),
+            Self::Binary(_) | Self::Image { .. } => estimate_tokens(&self.body()),
+            Self::Skipped(reason @ SkipReason::Archive { .. }) => estimate_tokens(&reason.marker()),
+            Self::Skipped(_) => 0,
+        }
+    }
+}
+
+/// Splits a header written by [`FileContent::header`] for a binary file
+/// into the path and the byte count; `None` for any other header.
+pub fn split_binary_header(header: &str) -> Option<(&str, u64)> {
+    let (path, count) = header
+        .strip_suffix(BINARY_NOTE.1)?
+        .rsplit_once(BINARY_NOTE.0)?;
+    let digits = count.replace(',', "");
+    if path.is_empty() || digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((path, digits.parse().ok()?))
+}
+
+/// Under `--include-binary`, the bytes of a file that `read_file` found to
+/// be binary or an image, or left unread as too large without it being
+/// text, when it has at most `max_bytes`; `content` otherwise.
+pub fn include_binary(path: &Path, content: FileContent, max_bytes: u64) -> FileContent {
+    let size = match &content {
+        FileContent::Image { size, .. } => Some(*size),
+        FileContent::Skipped(SkipReason::Binary { .. }) => {
+            fs::metadata(path).ok().map(|metadata| metadata.len())
+        }
+        FileContent::Skipped(SkipReason::TooLarge { size, .. })
+            if kind::sniff(path).is_ok_and(|kind| !kind.is_text()) =>
+        {
+            Some(*size)
+        }
+        _ => None,
+    };
+    if size.is_none_or(|size| size > max_bytes) {
+        return content;
+    }
+    match fs::read(path) {
+        Ok(bytes) if bytes.len() as u64 <= max_bytes && std::str::from_utf8(&bytes).is_err() => {
+            FileContent::Binary(bytes)
+        }
+        _ => content,
+    }
+}
+
+/// A walked file together with the decision about its body.
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub content: FileContent,
+}
+
+/// A walked file with the size the walker saw, when it had one.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkedFile<'a> {
+    pub path: &'a Path,
+    pub len: Option<u64>,
+}
+
+/// Reads `path` as text (UTF-8, or UTF-16 with a byte order mark),
+/// honoring the per-file size limit (0 disables it). `known_len` comes from
+/// the walk; without it the file is stat'ed once. The limit is also
+/// enforced on the bytes actually read, so a file that grew after the walk
+/// is still caught. With `archive_entries`, zip and tar files of any size
+/// are listed up to that many entries instead.
+pub fn read_file(
+    path: &Path,
+    known_len: Option<u64>,
+    max_file_bytes: u64,
+    archive_entries: Option<usize>,
+) -> FileContent {
+    let len = known_len.or_else(|| fs::metadata(path).ok().map(|metadata| metadata.len()));
+    let limit = (max_file_bytes > 0).then_some(max_file_bytes);
+    if let (Some(len), Some(limit)) = (len, limit) {
+        if len > limit {
+            return match archive_entries.and_then(|entries| list_archive(path, len, entries)) {
+                Some(reason) => FileContent::Skipped(reason),
+                None => too_large(path, len, max_file_bytes),
+            };
+        }
+    }
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            return FileContent::Skipped(SkipReason::Permission)
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            if let Ok(target) = fs::read_link(path) {
+                return FileContent::Skipped(SkipReason::BrokenSymlink { target });
+            }
+            return FileContent::Skipped(SkipReason::Binary {
+                kind: FileKind::Binary,
+            });
+        }
+        Err(_) => {
+            return FileContent::Skipped(SkipReason::Binary {
+                kind: FileKind::Binary,
+            })
+        }
+    };
+    let mut buffer = Vec::with_capacity(len.unwrap_or(0) as usize);
+    // `take` also keeps std from stat'ing the file again for a size hint.
+    let read = file
+        .take(limit.map_or(u64::MAX, |limit| limit + 1))
+        .read_to_end(&mut buffer);
+    match read {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            return FileContent::Skipped(SkipReason::Permission)
+        }
+        Err(_) => {
+            return FileContent::Skipped(SkipReason::Binary {
+                kind: FileKind::Binary,
+            })
+        }
+    }
+    if limit.is_some_and(|limit| buffer.len() as u64 > limit) {
+        let size = len.unwrap_or(0).max(buffer.len() as u64);
+        return too_large(path, size, max_file_bytes);
+    }
+    // A tar of plain text files is valid UTF-8, so archives go first.
+    if let Some(entries) = archive_entries.filter(|_| listing::is_archive(&buffer)) {
+        if let Some(reason) = list_archive(path, buffer.len() as u64, entries) {
+            return FileContent::Skipped(reason);
+        }
+    }
+    match String::from_utf8(buffer) {
+        Ok(text) => FileContent::Text(text),
+        Err(err) => {
+            let bytes = err.into_bytes();
+            if let Some(text) = kind::decode_utf16(&bytes) {
+                return FileContent::Text(text);
+            }
+            let size = bytes.len() as u64;
+            match image::parse(path, &bytes) {
+                Some(info) => FileContent::Image { info, size },
+                None => FileContent::Skipped(SkipReason::Binary {
+                    kind: kind::detect(path, &bytes),
+                }),
+            }
+        }
+    }
+}
+
+/// The listing of the archive at `path`, or a note when it claims to be one
+/// but cannot be read; `None` when it is not an archive after all.
+fn list_archive(path: &Path, size: u64, entries: usize) -> Option<SkipReason> {
+    match listing::list_file(path, entries) {
+        Ok(listing) => listing.map(|listing| SkipReason::Archive { listing, size }),
+        Err(err)
+            if matches!(
+                err.kind(),
+                ErrorKind::InvalidData | ErrorKind::UnexpectedEof
+            ) =>
+        {
+            Some(SkipReason::BadArchive {
+                error: err.to_string(),
+            })
+        }
+        Err(_) => None,
+    }
+}
+
+/// The skip for a file over the size limit. Files named like images are
+/// described from their header instead; other files are not opened.
+fn too_large(path: &Path, size: u64, limit: u64) -> FileContent {
+    let info = if image::has_image_extension(path) {
+        image::read_info(path).ok().flatten()
+    } else {
+        None
+    };
+    match info {
+        Some(info) => FileContent::Image { info, size },
+        None => FileContent::Skipped(SkipReason::TooLarge { size, limit }),
+    }
+}
+
+/// Classifies `files` on up to `threads` worker threads and hands the
+/// records to `emit` in the original order. Workers run ahead by at most a
+/// few records per thread; `emit` stops the run early by returning an error.
+///
+/// Once `interrupt` trips, no further record is read or emitted. Returns
+/// how many were emitted, all of `files` unless interrupted.
+pub fn read_in_order<C, E, T>(
+    files: &[WalkedFile<'_>],
+    threads: usize,
+    interrupt: &Interrupt,
+    classify: C,
+    mut emit: E,
+) -> Result<usize, T>
+where
+    C: Fn(&WalkedFile<'_>) -> FileContent + Sync,
+    E: FnMut(usize, FileRecord) -> Result<(), T>,
+{
+    let record = |file: &WalkedFile<'_>| FileRecord {
+        path: file.path.to_path_buf(),
+        content: classify(file),
+    };
+    if threads <= 1 || files.len() <= 1 {
+        for (index, file) in files.iter().enumerate() {
+            if interrupt.is_tripped() {
+                return Ok(index);
+            }
+            emit(index, record(file))?;
+        }
+        return Ok(files.len());
+    }
+
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let workers = threads.min(files.len());
+        let (sender, receiver) = mpsc::sync_channel(workers * 4);
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (next, record) = (&next, &record);
+            scope.spawn(move || loop {
+                if interrupt.is_tripped() {
+                    break;
+                }
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(index) else {
+                    break;
+                };
+                // A closed channel means the assembler gave up.
+                if sender.send((index, record(file))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (index, record) in receiver {
+            pending.insert(index, record);
+            while let Some(record) = pending.remove(&expected) {
+                if interrupt.is_tripped() {
+                    return Ok(expected);
+                }
+                emit(expected, record)?;
+                expected += 1;
+            }
+        }
+        Ok(expected)
+    })
+}
+
+/// Rough token count (about four characters per token), good enough for
+/// sizing output against a model's context window.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn walked_files(paths: &[PathBuf]) -> Vec<WalkedFile<'_>> {
+        paths
+            .iter()
+            .map(|path| WalkedFile { path, len: None })
+            .collect()
+    }
+
+    /// Writes `contents` to `name` in a fresh directory, which is removed
+    /// when the returned guard drops.
+    fn temp_file(name: &str, contents: &[u8]) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join(name);
+        fs::write(&path, contents).expect("write temp file");
+        (dir, path)
+    }
+
+    #[test]
+    fn walker_size_decides_without_reading() {
+        let (_dir, path) = temp_file("known_len", b"short");
+        assert_eq!(
+            read_file(&path, Some(20_000), 16_384, None),
+            FileContent::Skipped(SkipReason::TooLarge {
+                size: 20_000,
+                limit: 16_384
+            })
+        );
+    }
+
+    #[test]
+    fn file_grown_since_the_walk_is_still_too_large() {
+        let (_dir, path) = temp_file("grown", &[b'a'; 300]);
+        assert_eq!(
+            read_file(&path, Some(10), 100, None),
+            FileContent::Skipped(SkipReason::TooLarge {
+                size: 101,
+                limit: 100
+            })
+        );
+    }
+
+    #[test]
+    fn missing_walker_size_falls_back_to_stat() {
+        let (_dir, path) = temp_file("stat", &[b'a'; 300]);
+        assert_eq!(
+            read_file(&path, None, 100, None),
+            FileContent::Skipped(SkipReason::TooLarge {
+                size: 300,
+                limit: 100
+            })
+        );
+        assert_eq!(
+            read_file(&path, None, 0, None),
+            FileContent::Text("a".repeat(300))
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_is_binary() {
+        let (_dir, path) = temp_file("binary", b"\xff\xfe\x00");
+        assert_eq!(
+            read_file(&path, Some(3), 100, None),
+            FileContent::Skipped(SkipReason::Binary {
+                kind: FileKind::Binary
+            })
+        );
+    }
+
+    #[test]
+    fn binary_skips_name_the_detected_kind() {
+        let (_dir, path) = temp_file("image.bmp", b"BM\xff\x00");
+        let content = read_file(&path, None, 100, None);
+        assert_eq!(
+            content,
+            FileContent::Skipped(SkipReason::Binary {
+                kind: FileKind::Image
+            })
+        );
+        if let FileContent::Skipped(reason) = content {
+            assert_eq!(
+                reason.marker(),
+                r#"<skipped reason="binary" kind="image"> image file"#
+            );
+        }
+    }
+
+    #[test]
+    fn images_are_described_even_past_the_size_limit() {
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00\x02\x00\x00\x00\x02\x00";
+        let (_dir, path) = temp_file("image.png", &[&png[..], &[0; 2048]].concat());
+        for limit in [0, 100] {
+            let content = read_file(&path, None, limit, None);
+            assert!(content.is_included(), "{content:?}");
+            assert_eq!(
+                content.body(),
+                r#"<image format="png" width="512" height="512" size="2072"> PNG, 512×512, 2.0 KiB"#
+            );
+        }
+    }
+
+    #[test]
+    fn every_code_round_trips_through_its_marker() {
+        let listing = Listing {
+            format: "zip",
+            entries: vec![listing::Entry {
+                name: "a.txt".to_string(),
+                size: 3,
+                is_dir: false,
+            }],
+            more: None,
+        };
+        let reasons = [
+            SkipReason::Excluded {
+                pattern: "\"*.lock\"".to_string(),
+            },
+            SkipReason::TooLarge {
+                size: 20_000,
+                limit: 16_384,
+            },
+            SkipReason::Binary {
+                kind: FileKind::Binary,
+            },
+            SkipReason::Archive { listing, size: 180 },
+            SkipReason::BadArchive {
+                error: "unexpected end of file".to_string(),
+            },
+            SkipReason::BrokenSymlink {
+                target: PathBuf::from("it's \\ gone"),
+            },
+            SkipReason::SymlinkDepth { limit: 8 },
+            SkipReason::SymlinkLoop {
+                target: PathBuf::from(".."),
+            },
+            SkipReason::SameAs {
+                first: PathBuf::from("src/main.rs"),
+            },
+            SkipReason::Permission,
+            SkipReason::OverBudget { tokens: 3_000 },
+        ];
+        let mut codes = Vec::new();
+        for reason in &reasons {
+            let marker = reason.marker();
+            let first_line = marker.lines().next().unwrap_or_default();
+            let parsed = Marker::parse(first_line).expect("a marker line");
+            assert_eq!(
+                parsed,
+                Marker::new(reason.code(), reason.fields(), reason.describe())
+            );
+            codes.push(parsed.reason);
+        }
+        assert_eq!(
+            codes,
+            [
+                "excluded",
+                "too-large",
+                "binary",
+                "archive",
+                "bad-archive",
+                "broken-symlink",
+                "symlink-depth",
+                "symlink-loop",
+                "same-content",
+                "permission",
+                "budget",
+            ]
+        );
+        assert_eq!(
+            reasons[3].marker(),
+            "<skipped reason=\"archive\" format=\"zip\" size=\"180\"> archive: zip, 1 entry, 180 B\na.txt (3 B)"
+        );
+    }
+
+    #[test]
+    fn utf16_with_a_byte_order_mark_is_read_as_text() {
+        let (_dir, path) = temp_file("utf16", b"\xff\xfeh\x00i\x00");
+        assert_eq!(
+            read_file(&path, None, 100, None),
+            FileContent::Text("hi".to_string())
+        );
+    }
+
+    #[test]
+    fn parallel_reads_are_emitted_in_input_order() {
+        let paths: Vec<PathBuf> = (0..64)
+            .map(|index| PathBuf::from(index.to_string()))
+            .collect();
+        let walked = walked_files(&paths);
+
+        let mut emitted = Vec::new();
+        read_in_order(
+            &walked,
+            8,
+            &Interrupt::default(),
+            |file| {
+                // Early paths finish last so workers complete out of order.
+                let index: u64 = file.path.to_str().unwrap().parse().unwrap();
+                thread::sleep(Duration::from_micros((64 - index) * 50));
+                FileContent::Text(file.path.display().to_string())
+            },
+            |index, record| {
+                emitted.push((index, record));
+                Ok::<(), ()>(())
+            },
+        )
+        .expect("read in order");
+
+        for (position, (index, record)) in emitted.iter().enumerate() {
+            assert_eq!(*index, position);
+            assert_eq!(record.path, paths[position]);
+            assert_eq!(record.content, FileContent::Text(position.to_string()));
+        }
+        assert_eq!(emitted.len(), paths.len());
+    }
+
+    #[test]
+    fn emit_error_stops_the_run() {
+        let paths: Vec<PathBuf> = (0..100)
+            .map(|index| PathBuf::from(index.to_string()))
+            .collect();
+        let walked = walked_files(&paths);
+
+        let mut emitted = 0;
+        let result = read_in_order(
+            &walked,
+            4,
+            &Interrupt::default(),
+            |_| {
+                FileContent::Skipped(SkipReason::Binary {
+                    kind: FileKind::Binary,
+                })
+            },
+            |index, _| {
+                emitted += 1;
+                if index == 10 {
+                    Err("sink closed")
+                } else {
+                    Ok(())
+                }
+            },
+        );
+
+        assert_eq!(result, Err("sink closed"));
+        assert_eq!(emitted, 11);
+    }
+
+    #[test]
+    fn an_interrupt_stops_the_reads_after_the_record_being_emitted() {
+        let paths: Vec<PathBuf> = (0..100)
+            .map(|index| PathBuf::from(index.to_string()))
+            .collect();
+        let walked = walked_files(&paths);
+
+        for threads in [1, 4] {
+            let interrupt = Interrupt::default();
+            let mut emitted = Vec::new();
+            let read = read_in_order(
+                &walked,
+                threads,
+                &interrupt,
+                |file| FileContent::Text(file.path.display().to_string()),
+                |index, _| {
+                    emitted.push(index);
+                    if index == 10 {
+                        interrupt.trip();
+                    }
+                    Ok::<(), ()>(())
+                },
+            );
+            assert_eq!(read, Ok(11), "{threads} thread(s)");
+            assert_eq!(emitted, (0..=10).collect::<Vec<_>>());
+
+            let read = read_in_order(
+                &walked,
+                threads,
+                &interrupt,
+                |_| unreachable!("nothing is read once interrupted"),
+                |_, _| Ok::<(), ()>(()),
+            );
+            assert_eq!(read, Ok(0), "{threads} thread(s)");
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::snapshot::{Body, Snapshot};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+
+/// How one file differs between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<'a> {
+    Added(&'a Body),
+    Removed(&'a Body),
+    /// The body changed, or the file went from included to skipped (or the
+    /// other way round), or was skipped for a different reason.
+    Changed {
+        old: &'a Body,
+        new: &'a Body,
+    },
+}
+
+/// The files whose sections differ between `old` and `new`, by path.
+pub fn changes<'a>(old: &'a Snapshot, new: &'a Snapshot) -> BTreeMap<&'a str, Change<'a>> {
+    let bodies = |snapshot: &'a Snapshot| -> BTreeMap<&'a str, &'a Body> {
+        snapshot
+            .sections
+            .iter()
+            .map(|section| (section.path.as_str(), &section.body))
+            .collect()
+    };
+    let (old, new) = (bodies(old), bodies(new));
+    let mut changes = BTreeMap::new();
+    for (&path, &old_body) in &old {
+        match new.get(path) {
+            None => {
+                changes.insert(path, Change::Removed(old_body));
+            }
+            Some(&new_body) if new_body != old_body => {
+                changes.insert(
+                    path,
+                    Change::Changed {
+                        old: old_body,
+                        new: new_body,
+                    },
+                );
+            }
+            Some(_) => {}
+        }
+    }
+    for (&path, &new_body) in &new {
+        if !old.contains_key(path) {
+            changes.insert(path, Change::Added(new_body));
+        }
+    }
+    changes
+}
+
+/// One line per differing file, e.g. `changed  src/big.txt: was included,
+/// now <skipped: ...>`, then a unified diff of every body that changed.
+pub fn render(changes: &BTreeMap<&str, Change<'_>>) -> String {
+    let mut text = summary(changes);
+    for (path, change) in changes {
+        if let Change::Changed {
+            old: Body::Text(old),
+            new: Body::Text(new),
+        } = change
+        {
+            text.push('\n');
+            let old_header = format!("a/{}", path);
+            let new_header = format!("b/{}", path);
+            let diff = TextDiff::from_lines(old.as_str(), new.as_str());
+            let mut unified = diff.unified_diff();
+            unified.header(&old_header, &new_header);
+            text.push_str(&unified.to_string());
+        }
+    }
+    text
+}
+
+/// Just the one line per differing file that [`render`] starts with.
+pub fn summary(changes: &BTreeMap<&str, Change<'_>>) -> String {
+    let mut text = String::new();
+    for (path, change) in changes {
+        let line = match change {
+            Change::Added(body) => format!("added    {}{}", path, skipped_note(body)),
+            Change::Removed(body) => format!("removed  {}{}", path, skipped_note(body)),
+            Change::Changed {
+                old: Body::Text(_),
+                new: Body::Text(_),
+            } => format!("changed  {}", path),
+            Change::Changed {
+                old: Body::Binary { .. },
+                new: Body::Binary { .. },
+            } => format!("changed  {} (binary)", path),
+            Change::Changed { old, new } => {
+                format!("changed  {}: was {}, now {}", path, state(old), state(new))
+            }
+        };
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+/// `included`, `included as base64`, an image's stanza, or the first line
+/// of the skip marker.
+fn state(body: &Body) -> String {
+    match body {
+        Body::Text(_) => "included".to_string(),
+        Body::Binary { .. } => "included as base64".to_string(),
+        Body::Image(stanza) => stanza.clone(),
+        Body::Skipped(marker) => marker.lines().next().unwrap_or_default().to_string(),
+    }
+}
+
+fn skipped_note(body: &Body) -> String {
+    match body {
+        Body::Text(_) => String::new(),
+        Body::Binary { .. } => " (binary)".to_string(),
+        Body::Image(_) | Body::Skipped(_) => format!(" ({})", state(body)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(text: &str) -> Snapshot {
+        Snapshot::parse(text)
+    }
+
+    #[test]
+    fn lists_added_removed_and_changed_files_by_path() {
+        let old = snapshot(
+            "--- a.rs ---\nfn a() {}\n\n\n--- b.rs ---\nfn b() {}\n\n\n--- same.rs ---\nx\n\n",
+        );
+        let new = snapshot(
+            "--- a.rs ---\nfn a() { 1 }\n\n\n--- c.rs ---\nfn c() {}\n\n\n--- same.rs ---\nx\n\n",
+        );
+        let changes = changes(&old, &new);
+        assert_eq!(
+            changes.keys().copied().collect::<Vec<_>>(),
+            ["a.rs", "b.rs", "c.rs"]
+        );
+        assert_eq!(
+            render(&changes),
+            "changed  a.rs\nremoved  b.rs\nadded    c.rs\n\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-fn a() {}\n+fn a() { 1 }\n"
+        );
+    }
+
+    #[test]
+    fn skip_markers_are_states_of_their_own() {
+        let binary = r#"<skipped reason="binary" kind="binary"> binary file"#;
+        let too_large = r#"<skipped reason="too-large" size="20000" limit="16384"> file size 20000 bytes exceeds --max-file-bytes 16384"#;
+        let image = r#"<skipped reason="image" format="png" width="2" height="2" size="64"> image: PNG, 2×2, 64 B"#;
+        let old = snapshot(&format!("--- big.txt ---\nsmall\n\n--- logo.png ---\n{binary}\n\n--- data.bin ---\n{binary}\n\n"));
+        let new = snapshot(&format!("--- big.txt ---\n{too_large}\n\n--- logo.png ---\n{image}\n\n--- data.bin ---\n{binary}\n\n"));
+        assert_eq!(
+            render(&changes(&old, &new)),
+            format!(
+                "changed  big.txt: was included, now {too_large}\n\
+                 changed  logo.png: was {binary}, now {image}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn missing_final_newline_is_marked() {
+        let old = snapshot("--- a.txt ---\none\n\n");
+        let new = snapshot("--- a.txt ---\ntwo\n\n\n");
+        assert_eq!(
+            render(&changes(&old, &new)),
+            "changed  a.txt\n\n--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-one\n\\ No newline at end of file\n+two\n"
+        );
+    }
+}
@@ -0,0 +1,456 @@
+use crate::config;
+use crate::git;
+use arboard::Clipboard;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Outcome of one check. Only `Fail` means the default copy would not work;
+/// `Warn` points at something that may matter for other ways of running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// The parts of the environment the checks decide on, gathered once by
+/// [`Session::detect`] so the decisions can be tested with made-up values.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// `std::env::consts::OS` of the running binary.
+    pub os: &'static str,
+    pub display: Option<String>,
+    pub wayland_display: Option<String>,
+    /// Where `WAYLAND_DISPLAY` points, if the socket exists.
+    pub wayland_socket: Option<PathBuf>,
+    pub ssh: bool,
+    pub wsl: bool,
+    pub term: Option<String>,
+    pub tmux: bool,
+}
+
+impl Session {
+    pub fn detect() -> Self {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let wayland_display = var("WAYLAND_DISPLAY");
+        let wayland_socket = wayland_display.as_deref().and_then(|name| {
+            let path = match var("XDG_RUNTIME_DIR") {
+                Some(runtime) if !Path::new(name).is_absolute() => Path::new(&runtime).join(name),
+                _ => PathBuf::from(name),
+            };
+            path.exists().then_some(path)
+        });
+        let wsl = var("WSL_DISTRO_NAME").is_some()
+            || fs::read_to_string("/proc/sys/kernel/osrelease")
+                .is_ok_and(|release| release.to_ascii_lowercase().contains("microsoft"));
+        Self {
+            os: env::consts::OS,
+            display: var("DISPLAY"),
+            wayland_display,
+            wayland_socket,
+            ssh: var("SSH_CONNECTION").is_some() || var("SSH_TTY").is_some(),
+            wsl,
+            term: var("TERM"),
+            tmux: var("TMUX").is_some(),
+        }
+    }
+
+    /// Whether the clipboard goes through X11 or Wayland on this platform.
+    fn needs_display(&self) -> bool {
+        !matches!(self.os, "macos" | "windows" | "ios" | "android")
+    }
+}
+
+/// Runs every check against the real environment.
+pub fn run_checks() -> Vec<Check> {
+    let session = Session::detect();
+    let mut checks = vec![
+        check_display(&session),
+        check_clipboard(&session, probe_clipboard()),
+        check_osc52(&session),
+        check_git(probe_git()),
+    ];
+    let user_path = config::user_config_path();
+    checks.push(check_config(
+        "user config",
+        user_path.as_deref(),
+        user_path.as_deref().map(|path| {
+            config::load_user(path)
+                .map(|loaded| loaded.is_some())
+                .map_err(|err| format!("{err:#}"))
+        }),
+    ));
+    let project_path = config::find_project_config(Path::new("."));
+    checks.push(check_config(
+        "project config",
+        project_path.as_deref(),
+        project_path.as_deref().map(|path| {
+            config::load_project(path)
+                .map(|loaded| loaded.is_some())
+                .map_err(|err| format!("{err:#}"))
+        }),
+    ));
+    checks
+}
+
+/// Writes one line per check to `out`, with its hint indented below, and
+/// returns whether the default copy to the clipboard would work.
+pub fn report(checks: &[Check], out: &mut impl Write) -> io::Result<bool> {
+    for check in checks {
+        writeln!(out, "[{}] {}: {}", check.status, check.name, check.detail)?;
+        if let Some(hint) = &check.hint {
+            writeln!(out, "       hint: {}", hint)?;
+        }
+    }
+    out.flush()?;
+    Ok(checks.iter().all(|check| check.status != Status::Fail))
+}
+
+fn check_display(session: &Session) -> Check {
+    const NAME: &str = "display";
+    if !session.needs_display() {
+        return Check::pass(NAME, format!("not needed on {}", session.os));
+    }
+    if let Some(socket) = &session.wayland_socket {
+        return Check::pass(NAME, format!("Wayland socket {}", socket.display()));
+    }
+    if let Some(display) = &session.display {
+        let mut check = Check::pass(NAME, format!("X11 display {}", display));
+        if let Some(wayland) = &session.wayland_display {
+            check.detail = format!(
+                "{} (WAYLAND_DISPLAY={} has no socket)",
+                check.detail, wayland
+            );
+        }
+        return check;
+    }
+    if let Some(wayland) = &session.wayland_display {
+        return Check::fail(
+            NAME,
+            format!("WAYLAND_DISPLAY={} but its socket does not exist", wayland),
+            "check XDG_RUNTIME_DIR, or run copytree from a terminal inside the graphical session",
+        );
+    }
+    let hint = if session.wsl {
+        "update WSL so WSLg provides a display, or pipe the output to Windows: \
+         copytree --stdout | clip.exe"
+    } else if session.ssh {
+        "connect with `ssh -X` to forward the display, or use --stdout/--out on a remote host"
+    } else {
+        "start copytree from a graphical session, or use --stdout/--out"
+    };
+    Check::fail(NAME, "neither DISPLAY nor WAYLAND_DISPLAY is set", hint)
+}
+
+/// `probe` is the result of a set/get round trip on the system clipboard.
+fn check_clipboard(session: &Session, probe: Result<(), String>) -> Check {
+    const NAME: &str = "clipboard";
+    match probe {
+        Ok(()) => Check::pass(NAME, "set/get round trip succeeded"),
+        Err(err) => {
+            let hint = if session.wsl {
+                "WSL needs WSLg for the clipboard; meanwhile use copytree --stdout | clip.exe"
+            } else if session.ssh {
+                "the clipboard lives on the remote host; use --stdout or --out and copy locally"
+            } else {
+                "use --stdout or --out; without them copytree writes a temp file and prints its path"
+            };
+            Check::fail(NAME, err, hint)
+        }
+    }
+}
+
+/// Whether the terminal can probably set the local clipboard through the
+/// OSC 52 escape sequence, the usual way to copy from an SSH session.
+fn check_osc52(session: &Session) -> Check {
+    const NAME: &str = "osc 52";
+    let Some(term) = session.term.as_deref().filter(|term| *term != "dumb") else {
+        return Check::warn(
+            NAME,
+            "TERM is unset or dumb, so no terminal will interpret OSC 52",
+            "run from an interactive terminal to copy with OSC 52 helpers",
+        );
+    };
+    if session.tmux || term.starts_with("screen") || term.starts_with("tmux") {
+        return Check::warn(
+            NAME,
+            format!("TERM={} inside a multiplexer", term),
+            "tmux forwards OSC 52 only with `set -g set-clipboard on`",
+        );
+    }
+    Check::pass(
+        NAME,
+        format!("TERM={} (most emulators accept OSC 52)", term),
+    )
+}
+
+/// `probe` is the first line of `git --version`.
+fn check_git(probe: Result<String, String>) -> Check {
+    const NAME: &str = "git";
+    match probe {
+        Ok(version) => Check::pass(NAME, version),
+        Err(err) => Check::warn(
+            NAME,
+            err,
+            "install git and put it on PATH; .gitignore rules are still honored without it",
+        ),
+    }
+}
+
+/// `loaded` is `Ok(true)` when the file parsed, `Ok(false)` when it does not
+/// exist, and `None` when no file was found to look at.
+fn check_config(
+    name: &'static str,
+    path: Option<&Path>,
+    loaded: Option<Result<bool, String>>,
+) -> Check {
+    match (path, loaded) {
+        (Some(path), Some(Ok(true))) => Check::pass(name, format!("{} parsed", path.display())),
+        (Some(path), Some(Ok(false))) => {
+            Check::pass(name, format!("{} not present (optional)", path.display()))
+        }
+        (_, Some(Err(err))) => Check::fail(
+            name,
+            err,
+            "fix the file or pass --no-config; every copy fails until then",
+        ),
+        _ => Check::pass(name, "none found (optional)"),
+    }
+}
+
+fn probe_clipboard() -> Result<(), String> {
+    const PROBE: &str = "copytree doctor clipboard probe";
+    let mut clipboard = Clipboard::new().map_err(|err| err.to_string())?;
+    let previous = clipboard.get_text().ok();
+    let result = clipboard
+        .set_text(PROBE)
+        .and_then(|()| clipboard.get_text())
+        .map_err(|err| err.to_string())
+        .and_then(|text| {
+            if text == PROBE {
+                Ok(())
+            } else {
+                Err("the clipboard returned different text than was set".to_string())
+            }
+        });
+    if let Some(previous) = previous {
+        let _ = clipboard.set_text(previous);
+    }
+    result
+}
+
+fn probe_git() -> Result<String, String> {
+    git::output(None, &["--version"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linux() -> Session {
+        Session {
+            os: "linux",
+            term: Some("xterm-256color".to_string()),
+            ..Session::default()
+        }
+    }
+
+    #[test]
+    fn display_is_not_needed_on_macos_and_windows() {
+        for os in ["macos", "windows"] {
+            let session = Session { os, ..linux() };
+            assert_eq!(check_display(&session).status, Status::Pass);
+        }
+    }
+
+    #[test]
+    fn wayland_socket_or_x11_display_passes() {
+        let wayland = Session {
+            wayland_display: Some("wayland-0".to_string()),
+            wayland_socket: Some(PathBuf::from("/run/user/1000/wayland-0")),
+            ..linux()
+        };
+        assert_eq!(check_display(&wayland).status, Status::Pass);
+        let x11 = Session {
+            display: Some(":0".to_string()),
+            wayland_display: Some("wayland-1".to_string()),
+            ..linux()
+        };
+        let check = check_display(&x11);
+        assert_eq!(check.status, Status::Pass);
+        assert!(
+            check.detail.contains("wayland-1 has no socket"),
+            "{check:?}"
+        );
+    }
+
+    #[test]
+    fn missing_display_fails_with_an_environment_specific_hint() {
+        let stale = Session {
+            wayland_display: Some("wayland-0".to_string()),
+            ..linux()
+        };
+        assert_eq!(check_display(&stale).status, Status::Fail);
+
+        let cases = [
+            (
+                Session {
+                    wsl: true,
+                    ..linux()
+                },
+                "clip.exe",
+            ),
+            (
+                Session {
+                    ssh: true,
+                    ..linux()
+                },
+                "ssh -X",
+            ),
+            (linux(), "graphical session"),
+        ];
+        for (session, expected) in cases {
+            let check = check_display(&session);
+            assert_eq!(check.status, Status::Fail);
+            assert!(check.hint.unwrap().contains(expected));
+        }
+    }
+
+    #[test]
+    fn clipboard_result_decides_the_check() {
+        assert_eq!(check_clipboard(&linux(), Ok(())).status, Status::Pass);
+        let check = check_clipboard(
+            &Session {
+                ssh: true,
+                ..linux()
+            },
+            Err("X11 server connection timed out".to_string()),
+        );
+        assert_eq!(check.status, Status::Fail);
+        assert_eq!(check.detail, "X11 server connection timed out");
+        assert!(check.hint.unwrap().contains("remote host"));
+    }
+
+    #[test]
+    fn osc52_warns_without_a_terminal_or_inside_tmux() {
+        assert_eq!(check_osc52(&linux()).status, Status::Pass);
+        for session in [
+            Session {
+                term: None,
+                ..linux()
+            },
+            Session {
+                term: Some("dumb".to_string()),
+                ..linux()
+            },
+            Session {
+                tmux: true,
+                ..linux()
+            },
+            Session {
+                term: Some("screen-256color".to_string()),
+                ..linux()
+            },
+        ] {
+            assert_eq!(check_osc52(&session).status, Status::Warn, "{session:?}");
+        }
+    }
+
+    #[test]
+    fn missing_git_is_only_a_warning() {
+        assert_eq!(
+            check_git(Ok("git version 2.43.0".to_string())),
+            Check::pass("git", "git version 2.43.0")
+        );
+        assert_eq!(
+            check_git(Err("git could not be run".to_string())).status,
+            Status::Warn
+        );
+    }
+
+    #[test]
+    fn config_parse_errors_fail_and_missing_files_pass() {
+        let path = Path::new("/home/me/.config/copytree/config.toml");
+        assert_eq!(
+            check_config("user config", Some(path), Some(Ok(true))).status,
+            Status::Pass
+        );
+        let missing = check_config("user config", Some(path), Some(Ok(false)));
+        assert_eq!(missing.status, Status::Pass);
+        assert!(missing.detail.ends_with("not present (optional)"));
+        let broken = check_config(
+            "user config",
+            Some(path),
+            Some(Err("config.toml:2: invalid `format`".to_string())),
+        );
+        assert_eq!(broken.status, Status::Fail);
+        assert_eq!(
+            check_config("project config", None, None).status,
+            Status::Pass
+        );
+    }
+
+    #[test]
+    fn any_failure_means_the_default_copy_would_not_work() {
+        let pass = Check::pass("git", "git version 2.43.0");
+        let warn = check_osc52(&Session {
+            tmux: true,
+            ..linux()
+        });
+        let mut out = Vec::new();
+        assert!(report(&[pass.clone(), warn], &mut out).expect("written"));
+        let fail = check_clipboard(&linux(), Err("no display".to_string()));
+        assert!(!report(&[pass, fail], &mut out).expect("written"));
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Text encoding applied to the whole output before it reaches any sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Standard base64 in lines of 76 characters.
+    Base64,
+}
+
+/// The plain-text line written before the encoded output.
+pub const PREAMBLE: &str = "copytree output in base64: decode with `copytree decode FILE`, or drop this line and use `base64 -d`\n";
+
+/// How `decode` recognizes the preamble, even if the transport changed
+/// the rest of the line.
+const PREAMBLE_START: &str = "copytree output";
+
+/// Characters per encoded line, as in MIME.
+pub const LINE_WIDTH: usize = 76;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes a stream of bytes as wrapped base64. Input may arrive in chunks
+/// of any size; up to two bytes are held back until the next chunk
+/// completes a group.
+#[derive(Debug, Default)]
+pub struct Base64Lines {
+    pending: Vec<u8>,
+    column: usize,
+}
+
+impl Base64Lines {
+    /// Encodes the complete groups of `bytes` into `out`.
+    pub fn push(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+        let mut bytes = bytes;
+        if !self.pending.is_empty() {
+            let needed = (3 - self.pending.len()).min(bytes.len());
+            self.pending.extend_from_slice(&bytes[..needed]);
+            bytes = &bytes[needed..];
+            if self.pending.len() < 3 {
+                return;
+            }
+            let group = std::mem::take(&mut self.pending);
+            self.emit(&group, out);
+        }
+        let mut groups = bytes.chunks_exact(3);
+        for group in &mut groups {
+            self.emit(group, out);
+        }
+        self.pending.extend_from_slice(groups.remainder());
+    }
+
+    /// Encodes the held-back bytes with padding and ends the last line.
+    pub fn finish(mut self, out: &mut Vec<u8>) {
+        let group = std::mem::take(&mut self.pending);
+        if !group.is_empty() {
+            self.emit(&group, out);
+        }
+        if self.column > 0 {
+            out.push(b'\n');
+        }
+    }
+
+    /// Writes one group of one to three bytes as four characters.
+    fn emit(&mut self, group: &[u8], out: &mut Vec<u8>) {
+        let bits = group
+            .iter()
+            .chain([0, 0].iter())
+            .take(3)
+            .fold(0u32, |bits, &byte| bits << 8 | byte as u32);
+        for index in 0..4 {
+            let char = if index <= group.len() {
+                ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize]
+            } else {
+                b'='
+            };
+            out.push(char);
+        }
+        self.column += 4;
+        if self.column == LINE_WIDTH {
+            out.push(b'\n');
+            self.column = 0;
+        }
+    }
+}
+
+/// `bytes` as base64 in lines of [`LINE_WIDTH`] characters, each ending
+/// in a newline; empty for no bytes.
+pub fn base64_lines(bytes: &[u8]) -> String {
+    let mut out = Vec::with_capacity(bytes.len().div_ceil(3) * 4 * 77 / 76 + 1);
+    let mut lines = Base64Lines::default();
+    lines.push(bytes, &mut out);
+    lines.finish(&mut out);
+    String::from_utf8(out).expect("base64 is ASCII")
+}
+
+/// Encodes `bytes` whole, with the preamble and line wrapping of `--encode`.
+#[cfg(test)]
+pub fn encode(bytes: &[u8]) -> Vec<u8> {
+    let mut out = PREAMBLE.as_bytes().to_vec();
+    let mut lines = Base64Lines::default();
+    lines.push(bytes, &mut out);
+    lines.finish(&mut out);
+    out
+}
+
+/// Decodes `--encode base64` output. The preamble line is dropped when
+/// present and whitespace anywhere is ignored, so text rewrapped or
+/// indented by the transport still decodes.
+pub fn decode(input: &[u8]) -> Result<Vec<u8>> {
+    let start = input
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(input.len());
+    let body_start = match input[start..].iter().position(|&byte| byte == b'\n') {
+        Some(end) if input[start..].starts_with(PREAMBLE_START.as_bytes()) => start + end + 1,
+        _ => 0,
+    };
+    let mut out = Vec::with_capacity(input.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut padding = 0;
+    for (offset, &byte) in input.iter().enumerate().skip(body_start) {
+        if byte.is_ascii_whitespace() {
+            continue;
+        }
+        let value = match byte {
+            b'=' => {
+                padding += 1;
+                0
+            }
+            _ if padding > 0 => bail!("data after the `=` padding at byte {}", offset),
+            _ => match ALPHABET.iter().position(|&char| char == byte) {
+                Some(value) => value as u32,
+                None => bail!(
+                    "`{}` at byte {} is not a base64 character",
+                    byte.escape_ascii(),
+                    offset
+                ),
+            },
+        };
+        bits = bits << 6 | value;
+        count += 1;
+        if count == 4 {
+            let group = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+            if padding > 2 {
+                bail!("too much `=` padding at byte {}", offset);
+            }
+            out.extend_from_slice(&group[..3 - padding]);
+            bits = 0;
+            count = 0;
+        }
+    }
+    if count != 0 {
+        bail!("the input ends in the middle of a base64 group; was it cut off?");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_standard_alphabet_and_padding() {
+        let body = |bytes: &[u8]| {
+            String::from_utf8(encode(bytes)).expect("ascii")[PREAMBLE.len()..].to_string()
+        };
+        assert_eq!(body(b""), "");
+        assert_eq!(body(b"f"), "Zg==\n");
+        assert_eq!(body(b"fo"), "Zm8=\n");
+        assert_eq!(body(b"foo"), "Zm9v\n");
+        assert_eq!(body(b"foobar"), "Zm9vYmFy\n");
+        assert_eq!(body(&[0xfb, 0xff]), "+/8=\n");
+    }
+
+    #[test]
+    fn lines_wrap_at_76_columns() {
+        let encoded = encode(&[b'x'; 200]);
+        let lines: Vec<&[u8]> = encoded[PREAMBLE.len()..]
+            .split(|&byte| byte == b'\n')
+            .collect();
+        assert_eq!(
+            lines.iter().map(|line| line.len()).collect::<Vec<_>>(),
+            [76, 76, 76, 40, 0]
+        );
+    }
+
+    #[test]
+    fn chunked_pushes_match_one_push() {
+        let text = "tree\n└─ é.rs\n\u{fffd}\u{fffd} lossy bytes\r\n\0 tail ".repeat(300);
+        let whole = encode(text.as_bytes());
+        for chunk in [1, 2, 4, 5, 7, 76, 1000] {
+            let mut out = PREAMBLE.as_bytes().to_vec();
+            let mut lines = Base64Lines::default();
+            for piece in text.as_bytes().chunks(chunk) {
+                lines.push(piece, &mut out);
+            }
+            lines.finish(&mut out);
+            assert_eq!(out, whole, "chunks of {}", chunk);
+        }
+    }
+
+    #[test]
+    fn round_trips_megabytes_of_every_byte_value() {
+        let bytes: Vec<u8> = (0..3 * 1024 * 1024 + 1)
+            .map(|index: u32| (index.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        assert_eq!(decode(&encode(&bytes)).expect("decode"), bytes);
+    }
+
+    #[test]
+    fn decode_ignores_whitespace_and_a_missing_preamble() {
+        assert_eq!(decode(b"  Zm9v\r\n YmFy\n\n").expect("decode"), b"foobar");
+        assert_eq!(decode(b"Zg==").expect("decode"), b"f");
+        assert_eq!(
+            decode(b"\n copytree output in base64 (rewrapped)\nZm9v").expect("decode"),
+            b"foo"
+        );
+    }
+
+    #[test]
+    fn decode_rejects_damaged_input() {
+        let message = |input: &[u8]| decode(input).unwrap_err().to_string();
+        assert_eq!(
+            message(b"Zm9v*mFy"),
+            "`*` at byte 4 is not a base64 character"
+        );
+        assert!(message(b"Zm9vYm").contains("cut off"));
+        assert!(message(b"Zg==Zm9v").contains("after the `=` padding"));
+    }
+}
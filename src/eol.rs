@@ -0,0 +1,73 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// `--output-eol`: how the lines of the output end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eol {
+    /// `\n`, as everything is generated.
+    Lf,
+    /// `\r\n`.
+    Crlf,
+    /// `\r\n` on Windows, `\n` elsewhere.
+    Native,
+}
+
+impl Eol {
+    /// Whether lines end in `\r\n` on this platform.
+    pub fn is_crlf(self) -> bool {
+        match self {
+            Self::Lf => false,
+            Self::Crlf => true,
+            Self::Native => cfg!(windows),
+        }
+    }
+}
+
+/// Turns every `\n` not already preceded by `\r` into `\r\n`, over a stream
+/// of writes: a `\r` ending one write pairs with a `\n` starting the next,
+/// so CRLF text is never converted twice.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Crlf {
+    after_cr: bool,
+}
+
+impl Crlf {
+    /// `bytes` with its lone `\n`s converted.
+    pub fn convert(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut converted = Vec::with_capacity(bytes.len() + bytes.len() / 32);
+        for &byte in bytes {
+            if byte == b'\n' && !self.after_cr {
+                converted.push(b'\r');
+            }
+            converted.push(byte);
+            self.after_cr = byte == b'\r';
+        }
+        converted
+    }
+
+    /// Notes `bytes` as written unconverted, so the next write pairs with a
+    /// `\r` it ends in.
+    pub fn pass(&mut self, bytes: &[u8]) {
+        if let Some(&last) = bytes.last() {
+            self.after_cr = last == b'\r';
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_lone_line_feeds_are_converted() {
+        let mut crlf = Crlf::default();
+        assert_eq!(crlf.convert(b"a\nb\r\nc\rd\n\n"), b"a\r\nb\r\nc\rd\r\n\r\n");
+        assert_eq!(crlf.convert(b"ends in cr\r"), b"ends in cr\r");
+        assert_eq!(crlf.convert(b"\nnext"), b"\nnext");
+
+        let mut crlf = Crlf::default();
+        crlf.pass(b"body\r");
+        assert_eq!(crlf.convert(b"\n\n"), b"\n\r\n");
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// Expands a leading `~` to the home directory and `$VAR`/`${VAR}` (and on
+/// Windows `%VAR%`) to environment values. Unset variables are left as
+/// written unless `strict`, in which case they are an error.
+pub fn expand(value: &str, strict: bool) -> Result<String> {
+    let home = dirs::home_dir();
+    expand_with(
+        value,
+        |name| std::env::var(name).ok(),
+        home.as_deref(),
+        strict,
+        cfg!(windows),
+    )
+}
+
+/// [`expand`] with the environment, home directory, and `%VAR%` support
+/// supplied by the caller. Substituted values are not expanded again.
+fn expand_with<F>(
+    value: &str,
+    lookup: F,
+    home: Option<&Path>,
+    strict: bool,
+    percent: bool,
+) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    if let Some(after) = rest.strip_prefix('~') {
+        let ends_name =
+            after.is_empty() || after.starts_with('/') || (percent && after.starts_with('\\'));
+        match home.filter(|_| ends_name) {
+            Some(home) => {
+                expanded.push_str(&home.to_string_lossy());
+                rest = after;
+            }
+            None if ends_name && strict => {
+                bail!("cannot expand ~ in {}: no home directory found", value)
+            }
+            None => {}
+        }
+    }
+
+    while let Some(index) = rest.find(|c| c == '$' || (percent && c == '%')) {
+        expanded.push_str(&rest[..index]);
+        let reference = &rest[index..];
+        match parse_reference(reference, percent) {
+            Some((name, len)) => {
+                match lookup(name) {
+                    Some(found) => expanded.push_str(&found),
+                    None if strict => {
+                        bail!("environment variable {} in {} is not set", name, value)
+                    }
+                    None => expanded.push_str(&reference[..len]),
+                }
+                rest = &reference[len..];
+            }
+            None => {
+                expanded.push_str(&reference[..1]);
+                rest = &reference[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The variable name referenced at the start of `text` and the length of
+/// the whole reference, or `None` when the `$` or `%` is a literal.
+fn parse_reference(text: &str, percent: bool) -> Option<(&str, usize)> {
+    let is_name = |name: &str| {
+        name.chars()
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if let Some(braced) = text.strip_prefix("${") {
+        let end = braced.find('}')?;
+        let name = &braced[..end];
+        return is_name(name).then_some((name, end + 3));
+    }
+    if let Some(plain) = text.strip_prefix('$') {
+        let end = plain
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(plain.len());
+        let name = &plain[..end];
+        return is_name(name).then_some((name, end + 1));
+    }
+    if percent {
+        let inner = text.strip_prefix('%')?;
+        let end = inner.find('%')?;
+        let name = &inner[..end];
+        return is_name(name).then_some((name, end + 2));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_in(value: &str, strict: bool, percent: bool) -> Result<String> {
+        let vars = |name: &str| match name {
+            "HOME" => Some("/home/me".to_string()),
+            "PROJECT" => Some("copytree".to_string()),
+            "OUTER" => Some("$PROJECT".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        expand_with(value, vars, Some(Path::new("/home/me")), strict, percent)
+    }
+
+    #[test]
+    fn tilde_alone_and_with_a_path_become_home() {
+        assert_eq!(expand_in("~", false, false).unwrap(), "/home/me");
+        assert_eq!(
+            expand_in("~/notes/ctx.txt", false, false).unwrap(),
+            "/home/me/notes/ctx.txt"
+        );
+        assert_eq!(
+            expand_in("~\\notes", false, true).unwrap(),
+            "/home/me\\notes"
+        );
+    }
+
+    #[test]
+    fn tilde_elsewhere_is_literal() {
+        assert_eq!(expand_in("~alice/x", false, false).unwrap(), "~alice/x");
+        assert_eq!(expand_in("a/~/b", false, false).unwrap(), "a/~/b");
+        assert_eq!(expand_in("~\\notes", false, false).unwrap(), "~\\notes");
+    }
+
+    #[test]
+    fn plain_and_braced_variables_expand() {
+        assert_eq!(
+            expand_in("$HOME/src/${PROJECT}.txt", false, false).unwrap(),
+            "/home/me/src/copytree.txt"
+        );
+        assert_eq!(
+            expand_in("${PROJECT}${PROJECT}", false, false).unwrap(),
+            "copytreecopytree"
+        );
+        assert_eq!(expand_in("a${EMPTY}b", false, false).unwrap(), "ab");
+    }
+
+    #[test]
+    fn variable_values_are_not_expanded_again() {
+        assert_eq!(expand_in("$OUTER/x", false, false).unwrap(), "$PROJECT/x");
+        assert_eq!(
+            expand_in("~/$OUTER", false, false).unwrap(),
+            "/home/me/$PROJECT"
+        );
+    }
+
+    #[test]
+    fn literal_dollar_signs_are_kept() {
+        for value in ["cost$", "$5.txt", "a$-b", "${", "${1X}", "$"] {
+            assert_eq!(expand_in(value, true, false).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn unset_variables_are_kept_unless_strict() {
+        assert_eq!(
+            expand_in("$MISSING/${MISSING}", false, false).unwrap(),
+            "$MISSING/${MISSING}"
+        );
+        let err = expand_in("out/${MISSING}.txt", true, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "environment variable MISSING in out/${MISSING}.txt is not set"
+        );
+    }
+
+    #[test]
+    fn percent_variables_expand_only_on_windows() {
+        assert_eq!(
+            expand_in("%PROJECT%\\ctx.txt", false, true).unwrap(),
+            "copytree\\ctx.txt"
+        );
+        assert_eq!(
+            expand_in("%PROJECT%\\ctx.txt", false, false).unwrap(),
+            "%PROJECT%\\ctx.txt"
+        );
+        assert_eq!(expand_in("100%", true, true).unwrap(), "100%");
+        assert_eq!(expand_in("%MISSING%", false, true).unwrap(), "%MISSING%");
+    }
+}
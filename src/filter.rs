@@ -0,0 +1,249 @@
+use crate::logger;
+use crate::separators;
+use crate::walker;
+use anyhow::{anyhow, bail, Context, Result};
+use globset::GlobMatcher;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Stands for the file's path in a filter command.
+const PLACEHOLDER: &str = "{}";
+
+/// How often a running filter is checked for having exited.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Commands that text bodies are piped through: `--filter-cmd` for every
+/// file and `--filter-cmd-for` for the files matching a glob, the first
+/// matching rule winning over `--filter-cmd`.
+#[derive(Debug)]
+pub struct Filters {
+    default: Option<String>,
+    rules: Vec<(GlobMatcher, String)>,
+    timeout: Duration,
+}
+
+/// Splits a `--filter-cmd-for` rule into its glob and command.
+pub fn split_rule(rule: &str) -> Option<(&str, &str)> {
+    let (glob, command) = rule.split_once('=')?;
+    let (glob, command) = (glob.trim(), command.trim());
+    (!glob.is_empty() && !command.is_empty()).then_some((glob, command))
+}
+
+impl Filters {
+    /// The filters to run, or `None` when no command is given.
+    pub fn new(default: Option<&str>, rules: &[String], timeout: Duration) -> Result<Option<Self>> {
+        if default.is_none() && rules.is_empty() {
+            return Ok(None);
+        }
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let (glob, command) = split_rule(rule)
+                    .ok_or_else(|| anyhow!("--filter-cmd-for `{}` is not GLOB=CMD", rule))?;
+                let matcher = walker::glob(glob)
+                    .with_context(|| format!("Invalid filter-cmd-for glob: {}", glob))?
+                    .compile_matcher();
+                Ok((matcher, command.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(Self {
+            default: default.map(str::to_string),
+            rules,
+            timeout,
+        }))
+    }
+
+    /// The command for `path`, whose globs are tried against it as walked
+    /// and as `relative` to the current directory.
+    fn command_for(&self, path: &Path, relative: &Path) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(path) || matcher.is_match(relative))
+            .map(|(_, command)| command.as_str())
+            .or(self.default.as_deref())
+    }
+
+    /// Pipes `body` through the command for `path` and returns its output.
+    /// A command that fails, times out, or prints something other than
+    /// UTF-8 leaves the body as it was, with a warning.
+    pub fn apply(&self, path: &Path, relative: &Path, body: String) -> String {
+        let Some(command) = self.command_for(path, relative) else {
+            return body;
+        };
+        let shown = separators::display(path);
+        match run(command, &shown, body.as_bytes(), self.timeout) {
+            Ok(output) => output,
+            Err(err) => {
+                logger::warn(format_args!(
+                    "filter for {} failed: {:#}; keeping its contents unchanged",
+                    shown, err
+                ));
+                body
+            }
+        }
+    }
+}
+
+/// Runs `command` in the shell with `input` on stdin and returns its
+/// stdout. `{}` in the command and `$COPYTREE_PATH` give `path`; the
+/// command is killed once `timeout` passes.
+fn run(command: &str, path: &str, input: &[u8], timeout: Duration) -> Result<String> {
+    let deadline = Instant::now() + timeout;
+    let line = command.replace(PLACEHOLDER, &shell_quote(path));
+    let mut child = shell(&line)
+        .env("COPYTREE_PATH", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start `{}`", command))?;
+
+    // Each pipe gets a thread, so a command that writes before it has read
+    // everything cannot block on a full pipe. Threads still held up by a
+    // process the command left behind are not waited for.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    thread::spawn(move || {
+        if let Err(err) = stdin.write_all(&input) {
+            // A command may well exit without reading all of its input.
+            if err.kind() != ErrorKind::BrokenPipe {
+                logger::verbose(format_args!("could not write to filter: {err}"));
+            }
+        }
+    });
+    let stdout = drain(child.stdout.take().expect("stdout is piped"));
+    let stderr = drain(child.stderr.take().expect("stderr is piped"));
+
+    let status = wait(&mut child, deadline)?
+        .ok_or_else(|| anyhow!("`{}` timed out after {:?}", command, timeout))?;
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let output = stdout
+        .recv_timeout(remaining)
+        .map_err(|_| anyhow!("`{}` timed out after {:?}", command, timeout))?
+        .context("Failed to read the filter output")?;
+    if !status.success() {
+        let message = stderr
+            .recv_timeout(POLL)
+            .ok()
+            .and_then(Result::ok)
+            .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string())
+            .filter(|message| !message.is_empty());
+        match message {
+            Some(message) => bail!("`{}` exited with {}: {}", command, status, message),
+            None => bail!("`{}` exited with {}", command, status),
+        }
+    }
+    String::from_utf8(output)
+        .map_err(|_| anyhow!("`{}` printed something other than UTF-8", command))
+}
+
+/// Waits for `child` until `deadline`, killing it then and returning `None`.
+pub fn wait(child: &mut Child, deadline: Instant) -> Result<Option<std::process::ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for the filter")? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(POLL);
+    }
+}
+
+/// Reads `pipe` to its end on a thread and sends what it held.
+fn drain<R: Read + Send + 'static>(mut pipe: R) -> mpsc::Receiver<std::io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = sender.send(pipe.read_to_end(&mut bytes).map(|_| bytes));
+    });
+    receiver
+}
+
+#[cfg(not(windows))]
+pub fn shell(line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(line);
+    command
+}
+
+#[cfg(windows)]
+pub fn shell(line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(line);
+    command
+}
+
+/// `path` as one word of a shell command line.
+#[cfg(not(windows))]
+fn shell_quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn shell_quote(path: &str) -> String {
+    format!("\"{}\"", path)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn output_of_the_command_replaces_the_body() {
+        assert_eq!(
+            run("tr a-z A-Z", "a.txt", b"hello", SECOND).expect("run"),
+            "HELLO"
+        );
+    }
+
+    #[test]
+    fn the_path_is_given_quoted_and_in_the_environment() {
+        let path = "it's here.txt";
+        let output = run("printf '%s|%s' {} \"$COPYTREE_PATH\"", path, b"", SECOND).expect("run");
+        assert_eq!(output, format!("{path}|{path}"));
+    }
+
+    #[test]
+    fn failures_and_timeouts_are_errors() {
+        let err = run("echo oops >&2; exit 3", "a.txt", b"", SECOND).expect_err("exit 3");
+        let message = format!("{err:#}");
+        assert!(message.contains("exit status: 3"), "{message}");
+        assert!(message.ends_with("oops"), "{message}");
+
+        let started = Instant::now();
+        let err = run("sleep 5", "a.txt", b"", Duration::from_millis(200)).expect_err("slow");
+        assert_eq!(format!("{err:#}"), "`sleep 5` timed out after 200ms");
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins_over_the_default() {
+        let rules = [
+            "**/*.sql=sqlformat -".to_string(),
+            "*.sql=other".to_string(),
+        ];
+        let filters = Filters::new(Some("cat"), &rules, SECOND)
+            .expect("valid")
+            .expect("some");
+        assert_eq!(
+            filters.command_for(Path::new("./db/q.sql"), Path::new("db/q.sql")),
+            Some("sqlformat -")
+        );
+        assert_eq!(
+            filters.command_for(Path::new("src/a.rs"), Path::new("src/a.rs")),
+            Some("cat")
+        );
+        assert!(Filters::new(None, &[], SECOND).expect("valid").is_none());
+        assert!(split_rule("=cat").is_none());
+        assert!(split_rule("*.sql").is_none());
+    }
+}
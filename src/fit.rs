@@ -0,0 +1,276 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fmt;
+use std::path::Path;
+
+/// Started the list of dropped files that closed `--fit-tokens` output
+/// before the budget report took its place; snapshot parsing still stops
+/// there.
+pub const TRAILER_PREFIX: &str = "=== dropped by --fit-tokens";
+
+/// Which files `--fit-tokens` drops first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+pub enum Strategy {
+    /// The files with the most tokens.
+    #[value(name = "largest-first")]
+    #[serde(rename = "largest-first")]
+    Largest,
+    /// The files modified longest ago, largest first among equals.
+    #[value(name = "oldest-first")]
+    #[serde(rename = "oldest-first")]
+    Oldest,
+    /// Test files, largest first, then the largest of the rest.
+    #[value(name = "tests-first")]
+    #[serde(rename = "tests-first")]
+    Tests,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Test,
+    Other,
+}
+
+/// A file competing for the budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub path: String,
+    pub tokens: u64,
+    /// Seconds since the Unix epoch; `None` when the time is unknown, which
+    /// `oldest-first` treats as newest.
+    pub modified: Option<u64>,
+    pub category: Category,
+    /// Named on the command line or always included: never dropped.
+    pub pinned: bool,
+}
+
+/// Why a file was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Largest,
+    Oldest,
+    Test,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Largest => "largest remaining file",
+            Self::Oldest => "least recently modified",
+            Self::Test => "test file",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fit {
+    /// Indices into the candidates, in the order they were dropped.
+    pub dropped: Vec<(usize, Reason)>,
+    /// Tokens of the files that were kept.
+    pub total: u64,
+}
+
+impl Fit {
+    pub fn fits(&self, budget: u64) -> bool {
+        self.total <= budget
+    }
+}
+
+/// A file `--fit-tokens` dropped, for the budget report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dropped {
+    pub path: String,
+    pub tokens: u64,
+    pub reason: Reason,
+}
+
+/// Drops files in the order `strategy` gives until the rest fit in
+/// `budget` tokens, or only pinned and empty files are left.
+pub fn fit(candidates: &[Candidate], budget: u64, strategy: Strategy) -> Fit {
+    let mut total: u64 = candidates.iter().map(|candidate| candidate.tokens).sum();
+    let mut order: Vec<usize> = (0..candidates.len())
+        .filter(|&index| !candidates[index].pinned && candidates[index].tokens > 0)
+        .collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&candidates[a], &candidates[b]);
+        let first = match strategy {
+            Strategy::Largest => std::cmp::Ordering::Equal,
+            Strategy::Oldest => {
+                let age = |candidate: &Candidate| candidate.modified.unwrap_or(u64::MAX);
+                age(a).cmp(&age(b))
+            }
+            Strategy::Tests => (a.category != Category::Test).cmp(&(b.category != Category::Test)),
+        };
+        first
+            .then_with(|| Reverse(a.tokens).cmp(&Reverse(b.tokens)))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    let mut dropped = Vec::new();
+    for index in order {
+        if total <= budget {
+            break;
+        }
+        let reason = match (strategy, candidates[index].category) {
+            (Strategy::Largest, _) | (Strategy::Tests, Category::Other) => Reason::Largest,
+            (Strategy::Oldest, _) => Reason::Oldest,
+            (Strategy::Tests, Category::Test) => Reason::Test,
+        };
+        total -= candidates[index].tokens;
+        dropped.push((index, reason));
+    }
+    Fit { dropped, total }
+}
+
+/// Whether `path` looks like a test: under a `tests`, `test`, `__tests__`,
+/// or `spec` directory, or named like `test_x.py`, `x_test.go`, or
+/// `x.test.ts`/`x.spec.ts`.
+pub fn is_test_path(path: &str) -> bool {
+    let path = Path::new(path);
+    let in_test_dir = path
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .any(|component| {
+            matches!(
+                component.as_os_str().to_str(),
+                Some("tests" | "test" | "__tests__" | "spec" | "specs")
+            )
+        });
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    in_test_dir
+        || stem.starts_with("test_")
+        || ["_test", "_tests", "_spec", ".test", ".spec"]
+            .iter()
+            .any(|suffix| stem.ends_with(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(path: &str, tokens: u64, modified: u64) -> Candidate {
+        Candidate {
+            path: path.to_string(),
+            tokens,
+            modified: Some(modified),
+            category: if is_test_path(path) {
+                Category::Test
+            } else {
+                Category::Other
+            },
+            pinned: false,
+        }
+    }
+
+    fn dropped_paths(candidates: &[Candidate], fit: &Fit) -> Vec<String> {
+        fit.dropped
+            .iter()
+            .map(|(index, _)| candidates[*index].path.clone())
+            .collect()
+    }
+
+    fn sample() -> Vec<Candidate> {
+        vec![
+            candidate("src/main.rs", 400, 300),
+            candidate("src/big.rs", 900, 200),
+            candidate("src/old.rs", 100, 100),
+            candidate("tests/cli.rs", 500, 400),
+        ]
+    }
+
+    #[test]
+    fn nothing_is_dropped_when_everything_fits() {
+        let fit = fit(&sample(), 1900, Strategy::Largest);
+        assert!(fit.dropped.is_empty());
+        assert_eq!(fit.total, 1900);
+        assert!(fit.fits(1900));
+    }
+
+    #[test]
+    fn largest_first_drops_until_the_rest_fit() {
+        let candidates = sample();
+        let fit = fit(&candidates, 600, Strategy::Largest);
+        assert_eq!(
+            dropped_paths(&candidates, &fit),
+            ["src/big.rs", "tests/cli.rs"]
+        );
+        assert_eq!(fit.total, 500);
+        assert!(fit
+            .dropped
+            .iter()
+            .all(|(_, reason)| *reason == Reason::Largest));
+    }
+
+    #[test]
+    fn oldest_first_follows_modification_time() {
+        let candidates = sample();
+        let fit = fit(&candidates, 1000, Strategy::Oldest);
+        assert_eq!(
+            dropped_paths(&candidates, &fit),
+            ["src/old.rs", "src/big.rs"]
+        );
+        assert_eq!(fit.dropped[0].1, Reason::Oldest);
+    }
+
+    #[test]
+    fn oldest_first_keeps_files_of_unknown_age_for_last() {
+        let mut candidates = sample();
+        candidates[2].modified = None;
+        let fit = fit(&candidates, 1500, Strategy::Oldest);
+        assert_eq!(dropped_paths(&candidates, &fit), ["src/big.rs"]);
+    }
+
+    #[test]
+    fn tests_first_drops_tests_before_the_largest_source() {
+        let candidates = sample();
+        let fit = fit(&candidates, 1000, Strategy::Tests);
+        assert_eq!(fit.dropped, [(3, Reason::Test), (1, Reason::Largest)]);
+    }
+
+    #[test]
+    fn pinned_and_empty_files_are_never_dropped() {
+        let mut candidates = sample();
+        candidates[1].pinned = true;
+        candidates.push(candidate("src/empty.rs", 0, 0));
+        let fit = fit(&candidates, 0, Strategy::Oldest);
+        assert_eq!(
+            dropped_paths(&candidates, &fit),
+            ["src/old.rs", "src/main.rs", "tests/cli.rs"]
+        );
+        assert_eq!(fit.total, 900);
+        assert!(!fit.fits(0));
+    }
+
+    #[test]
+    fn ties_are_broken_by_path() {
+        let candidates = vec![
+            candidate("b.rs", 100, 0),
+            candidate("a.rs", 100, 0),
+            candidate("c.rs", 100, 0),
+        ];
+        let fit = fit(&candidates, 150, Strategy::Oldest);
+        assert_eq!(dropped_paths(&candidates, &fit), ["a.rs", "b.rs"]);
+    }
+
+    #[test]
+    fn recognizes_test_paths() {
+        for path in [
+            "tests/cli.rs",
+            "src/__tests__/app.js",
+            "pkg/server_test.go",
+            "test_parser.py",
+            "web/app.spec.ts",
+            "web/button.test.tsx",
+        ] {
+            assert!(is_test_path(path), "{path}");
+        }
+        for path in ["src/main.rs", "src/testing.rs", "contest/main.rs", "tests"] {
+            assert!(!is_test_path(path), "{path}");
+        }
+    }
+}
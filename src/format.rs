@@ -0,0 +1,345 @@
+use crate::content::FileContent;
+use crate::output::{approximate_count, human_size};
+use crate::permalink::Permalink;
+use crate::style::{Role, StyledWrite};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// `3 files, 1.2 KiB (~300 tokens); 1 skipped`, counting the included
+/// files and the skipped ones among `contents`.
+pub fn summary<'a>(contents: impl IntoIterator<Item = &'a FileContent>) -> String {
+    let mut files = 0u64;
+    let mut skipped = 0u64;
+    let mut bytes = 0u64;
+    let mut tokens = 0u64;
+    for content in contents {
+        match content {
+            FileContent::Text(text) => {
+                files += 1;
+                bytes += text.len() as u64;
+                tokens += content.estimated_tokens() as u64;
+            }
+            FileContent::Binary(binary) => {
+                files += 1;
+                bytes += binary.len() as u64;
+                tokens += content.estimated_tokens() as u64;
+            }
+            FileContent::Image { .. } => {
+                files += 1;
+                bytes += content.body().len() as u64;
+                tokens += content.estimated_tokens() as u64;
+            }
+            FileContent::Skipped(_) => skipped += 1,
+        }
+    }
+    let mut line = format!(
+        "{} file{}, {} (~{} tokens)",
+        files,
+        if files == 1 { "" } else { "s" },
+        human_size(bytes),
+        approximate_count(tokens)
+    );
+    if skipped > 0 {
+        line.push_str(&format!("; {} skipped", skipped));
+    }
+    line
+}
+
+/// Overall shape of the generated output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Tree followed by `--- path ---` sections.
+    Plain,
+    /// A tar archive of the included files plus TREE.txt and MANIFEST.json.
+    Tar,
+    /// One self-contained HTML page: a collapsible tree linking to the
+    /// files, each highlighted.
+    Html,
+}
+
+impl Format {
+    /// The media type the output is sent with over HTTP.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Plain => "text/plain; charset=utf-8",
+            Self::Tar => "application/x-tar",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// How `--bare` treats a run that includes more than one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bare {
+    /// Fail unless exactly one file is included.
+    Strict,
+    /// Fall back to the normal output.
+    Loose,
+}
+
+/// A rendered directory tree, kept as lines so the names can be styled
+/// without the styling becoming part of the text.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tree {
+    pub lines: Vec<TreeLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeLine {
+    /// Indentation and connector, e.g. `│  └─ `.
+    pub prefix: String,
+    pub name: String,
+    pub role: Option<Role>,
+    /// Shown after the name, e.g. `(~1.8k tok)`; the notes of a tree start
+    /// in one column.
+    pub note: Option<String>,
+    /// For files, the path sections are keyed by, so the HTML tree can link
+    /// to them.
+    pub path: Option<String>,
+}
+
+impl Tree {
+    /// The tree as text, one line per node with a trailing newline.
+    pub fn plain(&self) -> String {
+        let mut text = String::new();
+        let column = self.note_column();
+        for line in &self.lines {
+            text.push_str(&line.prefix);
+            text.push_str(&line.name);
+            text.push_str(&line.padded_note(column));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Writes the tree with each name styled by its role.
+    pub fn write<W: StyledWrite>(&self, out: &mut W) -> io::Result<()> {
+        let column = self.note_column();
+        for line in &self.lines {
+            out.write_all(line.prefix.as_bytes())?;
+            match line.role {
+                Some(role) => out.write_styled(role, &line.name)?,
+                None => out.write_all(line.name.as_bytes())?,
+            }
+            out.write_all(line.padded_note(column).as_bytes())?;
+            out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// The tree with names elided in the middle so each line fits in `width`
+    /// terminal columns, e.g. `veryLongNa…Hash.js`; extensions stay visible.
+    pub fn fit_width(&self, width: usize) -> Tree {
+        let lines = self
+            .lines
+            .iter()
+            .map(|line| {
+                let note = line.note.as_deref().map_or(0, |note| note.width() + 2);
+                let room = width.saturating_sub(line.prefix.width() + note);
+                let name = if line.name.width() > room {
+                    elide(&line.name, room, line.role != Some(Role::Directory))
+                } else {
+                    line.name.clone()
+                };
+                TreeLine {
+                    name,
+                    ..line.clone()
+                }
+            })
+            .collect();
+        Tree { lines }
+    }
+
+    /// Where the notes start: two columns past the widest noted line.
+    fn note_column(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|line| line.note.is_some())
+            .map(|line| line.width() + 2)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl TreeLine {
+    /// Terminal columns, so wide characters count twice.
+    fn width(&self) -> usize {
+        self.prefix.width() + self.name.width()
+    }
+
+    fn padded_note(&self, column: usize) -> String {
+        match &self.note {
+            Some(note) => format!("{}{}", " ".repeat(column - self.width()), note),
+            None => String::new(),
+        }
+    }
+}
+
+/// Shortens `name` to `room` columns by replacing its middle with `…`,
+/// keeping the extension whole when `keep_extension` is set and it fits.
+fn elide(name: &str, room: usize, keep_extension: bool) -> String {
+    let budget = room.saturating_sub(1);
+    let extension = name
+        .rfind('.')
+        .filter(|&dot| dot > 0 && keep_extension)
+        .map_or(0, |dot| name[dot..].width());
+    let tail = (budget / 2).max(extension).min(budget);
+    let mut head_width = 0;
+    let head: String = name
+        .chars()
+        .take_while(|c| {
+            head_width += c.width().unwrap_or(0);
+            head_width <= budget - tail
+        })
+        .collect();
+    let mut tail_width = 0;
+    let mut tail: Vec<char> = name
+        .chars()
+        .rev()
+        .take_while(|c| {
+            tail_width += c.width().unwrap_or(0);
+            tail_width <= tail
+        })
+        .collect();
+    tail.reverse();
+    format!("{}…{}", head, tail.into_iter().collect::<String>())
+}
+
+/// A writer that tells file bodies apart from the text around them, so
+/// `--output-eol` can leave the bodies as they are.
+pub trait BodyWrite: Write {
+    fn write_body(&mut self, body: &[u8]) -> io::Result<()>;
+}
+
+/// Writes one `--- path ---` section of the default text layout, headed by
+/// `path` as displayed (noted for a binary file) and, with `--permalinks`,
+/// the file's link. With `--omit-skipped` a skipped file gets no section at
+/// all. Returns how many bytes of the section come before the body, for
+/// `--manifest`.
+pub fn write_section<W: BodyWrite>(
+    out: &mut W,
+    path: &str,
+    permalink: Option<&Permalink>,
+    content: &FileContent,
+    omit_skipped: bool,
+) -> io::Result<usize> {
+    if omit_skipped && !content.is_included() {
+        return Ok(0);
+    }
+    let mut head = format!("--- {} ---\n", content.header(path));
+    if let Some(permalink) = permalink {
+        head.push_str(&format!("{}\n", permalink));
+    }
+    out.write_all(head.as_bytes())?;
+    out.write_body(content.body().as_bytes())?;
+    out.write_all(b"\n\n")?;
+    Ok(head.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::style::{Painter, Styler};
+
+    fn sample() -> Tree {
+        let line = |prefix: &str, name: &str, role| TreeLine {
+            prefix: prefix.to_string(),
+            name: name.to_string(),
+            role,
+            note: None,
+            path: None,
+        };
+        Tree {
+            lines: vec![
+                line("", "src", Some(Role::Directory)),
+                line("├─ ", "main.rs", None),
+                line("└─ ", "logo.png", Some(Role::Skipped)),
+            ],
+        }
+    }
+
+    fn written(styler: Styler) -> String {
+        let mut out = Vec::new();
+        sample()
+            .write(&mut Painter::new(&mut out, styler))
+            .expect("write tree");
+        String::from_utf8(out).expect("tree is UTF-8")
+    }
+
+    #[test]
+    fn plain_tree_has_no_escapes() {
+        assert_eq!(sample().plain(), "src\n├─ main.rs\n└─ logo.png\n");
+        assert_eq!(written(Styler::new(false)), sample().plain());
+    }
+
+    #[test]
+    fn styled_tree_colors_names_only() {
+        assert_eq!(
+            written(Styler::new(true)),
+            "\x1b[34msrc\x1b[0m\n├─ main.rs\n└─ \x1b[2mlogo.png\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn fit_width_elides_the_middle_of_long_names() {
+        let tree = |name: &str| Tree {
+            lines: vec![TreeLine {
+                prefix: "├─ ".to_string(),
+                name: name.to_string(),
+                role: None,
+                note: None,
+                path: None,
+            }],
+        };
+        let long = tree("veryLongNameForTheGeneratedChunkHash.js");
+        assert_eq!(long.fit_width(80), long);
+        assert_eq!(long.fit_width(21).plain(), "├─ veryLongN…kHash.js\n");
+        assert_eq!(long.fit_width(10).plain(), "├─ ver….js\n");
+        assert_eq!(long.fit_width(5).plain(), "├─ …s\n");
+        // Wide characters take two columns each.
+        assert_eq!(
+            tree("日本語のとても長いファイル名.txt")
+                .fit_width(20)
+                .plain(),
+            "├─ 日本語の…ル名.txt\n"
+        );
+    }
+
+    #[test]
+    fn fit_width_leaves_room_for_notes() {
+        let mut tree = sample();
+        tree.lines[2].name = "a_rather_long_image_name.png".to_string();
+        tree.lines[2].note = Some("(~0 tok)".to_string());
+        assert_eq!(
+            tree.fit_width(24).plain(),
+            "src\n├─ main.rs\n└─ a_rat…e.png  (~0 tok)\n"
+        );
+    }
+
+    #[test]
+    fn notes_start_in_one_column() {
+        let mut tree = sample();
+        for (line, note) in tree
+            .lines
+            .iter_mut()
+            .zip(["(~1.8k tok)", "(~1.8k tok)", "(~0 tok)"])
+        {
+            line.note = Some(note.to_string());
+        }
+        assert_eq!(
+            tree.plain(),
+            "src          (~1.8k tok)\n├─ main.rs   (~1.8k tok)\n└─ logo.png  (~0 tok)\n"
+        );
+        // Styling does not count toward the column.
+        let mut out = Vec::new();
+        tree.write(&mut Painter::new(&mut out, Styler::new(true)))
+            .expect("write tree");
+        assert!(String::from_utf8(out)
+            .expect("tree is UTF-8")
+            .starts_with("\x1b[34msrc\x1b[0m          (~1.8k tok)\n"));
+    }
+}
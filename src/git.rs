@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Runs `git` with `args`, inside `dir` when given, and returns its trimmed
+/// stdout. The error says why git could not run or how it exited.
+pub fn output(dir: Option<&Path>, args: &[&str]) -> Result<String, String> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command
+        .args(args)
+        .output()
+        .map_err(|err| format!("git could not be run: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} exited with {}",
+            args.join(" "),
+            output.status
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Where a repository's working tree stood when the output was generated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoState {
+    /// Top-level directory of the working tree, as git prints it.
+    pub root: String,
+    /// Short hash of `HEAD`; absent before the first commit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Checked-out branch; absent on a detached `HEAD`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Whether `git status` listed any change, untracked files included.
+    pub dirty: bool,
+}
+
+/// The state of the repository containing `dir`, or `None` when `dir` is
+/// not inside one or git is unavailable.
+pub fn repo_state(dir: &Path) -> Option<RepoState> {
+    let root = output(Some(dir), &["rev-parse", "--show-toplevel"]).ok()?;
+    let commit = output(Some(dir), &["rev-parse", "--short", "HEAD"]).ok();
+    let branch = output(Some(dir), &["rev-parse", "--abbrev-ref", "HEAD"])
+        .ok()
+        .filter(|branch| branch != "HEAD");
+    let status = output(Some(dir), &["status", "--porcelain"]).ok()?;
+    Some(RepoState {
+        root,
+        commit,
+        branch,
+        dirty: !status.is_empty(),
+    })
+}
+
+/// One state per repository the `roots` are in, in the order first reached.
+/// Roots outside any repository add nothing; a file root counts for the
+/// directory that holds it.
+pub fn repositories(roots: &[String]) -> Vec<RepoState> {
+    let mut states: Vec<RepoState> = Vec::new();
+    for root in root_list(roots) {
+        let dir = root_dir(root);
+        // Roots in one repository share a top level; ask git once for it.
+        let Ok(top) = output(Some(dir), &["rev-parse", "--show-toplevel"]) else {
+            continue;
+        };
+        if states.iter().any(|state| state.root == top) {
+            continue;
+        }
+        states.extend(repo_state(dir));
+    }
+    states
+}
+
+/// The files of the repositories holding `roots` that differ from
+/// `reference` in the working tree (under their new name when renamed) or
+/// are untracked and not ignored, as paths under each top level. Every
+/// repository is compared with the same ref name.
+pub fn changed_since(roots: &[String], reference: &str) -> Result<HashSet<PathBuf>, String> {
+    if reference.starts_with('-') {
+        return Err(format!("`{}` is not a git ref", reference));
+    }
+    let mut tops: Vec<String> = Vec::new();
+    for root in root_list(roots) {
+        let top = output(Some(root_dir(root)), &["rev-parse", "--show-toplevel"])
+            .map_err(|_| format!("{} is not inside a git repository", root))?;
+        if !tops.contains(&top) {
+            tops.push(top);
+        }
+    }
+    let mut changed = HashSet::new();
+    for top in &tops {
+        let top = Path::new(top);
+        let commit = format!("{}^{{commit}}", reference);
+        output(Some(top), &["rev-parse", "--verify", "--quiet", &commit]).map_err(|_| {
+            format!(
+                "`{}` does not name a commit in {}",
+                reference,
+                top.display()
+            )
+        })?;
+        let modified = output(
+            Some(top),
+            &[
+                "diff",
+                "--name-only",
+                "-z",
+                "--find-renames",
+                reference,
+                "--",
+            ],
+        )?;
+        let untracked = output(
+            Some(top),
+            &["ls-files", "--others", "--exclude-standard", "-z"],
+        )?;
+        changed.extend(
+            modified
+                .split('\0')
+                .chain(untracked.split('\0'))
+                .filter(|name| !name.is_empty())
+                .map(|name| top.join(name)),
+        );
+    }
+    Ok(changed)
+}
+
+/// `roots`, or the current directory when there are none.
+fn root_list(roots: &[String]) -> Vec<&str> {
+    if roots.is_empty() {
+        vec!["."]
+    } else {
+        roots.iter().map(String::as_str).collect()
+    }
+}
+
+/// The directory to ask git about `root`: the root itself, or the
+/// directory holding a file root.
+fn root_dir(root: &str) -> &Path {
+    let path = Path::new(root);
+    if path.is_dir() {
+        path
+    } else {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn git(dir: &Path, args: &[&str]) {
+        output(Some(dir), args).expect("git command");
+    }
+
+    fn init_repo(dir: &Path) {
+        git(dir, &["init", "-q", "-b", "main"]);
+        git(dir, &["config", "user.email", "dev@example.com"]);
+        git(dir, &["config", "user.name", "Dev"]);
+        git(dir, &["config", "commit.gpgsign", "false"]);
+    }
+
+    #[test]
+    fn reports_commit_branch_and_dirty_state() {
+        if output(None, &["--version"]).is_err() {
+            return;
+        }
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        init_repo(dir);
+        let fresh = repo_state(dir).expect("repository");
+        assert_eq!(fresh.commit, None);
+        assert!(!fresh.dirty);
+
+        fs::write(dir.join("lib.rs"), "pub fn lib() {}\n").expect("write file");
+        assert!(repo_state(dir).expect("repository").dirty);
+        git(dir, &["add", "lib.rs"]);
+        git(dir, &["commit", "-q", "-m", "Add lib"]);
+        let committed = repo_state(dir).expect("repository");
+        let head = output(Some(dir), &["rev-parse", "--short", "HEAD"]).expect("head");
+        assert_eq!(committed.commit, Some(head));
+        assert_eq!(committed.branch.as_deref(), Some("main"));
+        assert!(!committed.dirty);
+
+        git(dir, &["checkout", "-q", "--detach"]);
+        assert_eq!(repo_state(dir).expect("repository").branch, None);
+    }
+
+    #[test]
+    fn changed_since_lists_modified_renamed_and_untracked_files() {
+        if output(None, &["--version"]).is_err() {
+            return;
+        }
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        init_repo(dir);
+        fs::create_dir_all(dir.join("src")).expect("create dir");
+        for name in ["kept.rs", "edited.rs", "old.rs"] {
+            fs::write(dir.join("src").join(name), format!("// {}\n", name)).expect("write");
+        }
+        fs::write(dir.join(".gitignore"), "*.log\n").expect("write");
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "-q", "-m", "Start"]);
+        git(dir, &["tag", "start"]);
+
+        fs::write(dir.join("src/edited.rs"), "// changed\n").expect("write");
+        git(dir, &["mv", "src/old.rs", "src/new.rs"]);
+        fs::write(dir.join("src/fresh.rs"), "\n").expect("write");
+        fs::write(dir.join("build.log"), "\n").expect("write");
+
+        let roots = [dir.join("src").to_string_lossy().into_owned()];
+        let top =
+            PathBuf::from(output(Some(dir), &["rev-parse", "--show-toplevel"]).expect("top level"));
+        let mut changed: Vec<PathBuf> = changed_since(&roots, "start")
+            .expect("changed files")
+            .into_iter()
+            .collect();
+        changed.sort();
+        assert_eq!(
+            changed,
+            ["src/edited.rs", "src/fresh.rs", "src/new.rs"].map(|name| top.join(name))
+        );
+
+        let err = changed_since(&roots, "no-such-ref").expect_err("unknown ref");
+        assert!(
+            err.starts_with("`no-such-ref` does not name a commit in "),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn lists_each_repository_once_and_skips_plain_directories() {
+        if output(None, &["--version"]).is_err() {
+            return;
+        }
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let base = temp.path();
+        for name in ["first", "second", "plain"] {
+            fs::create_dir_all(base.join(name).join("src")).expect("create dir");
+        }
+        init_repo(&base.join("first"));
+        init_repo(&base.join("second"));
+        fs::write(base.join("first/src/main.rs"), "fn main() {}\n").expect("write file");
+        let root = |name: &str| base.join(name).to_string_lossy().into_owned();
+        let roots = [
+            root("first/src"),
+            root("first/src/main.rs"),
+            root("second"),
+            root("plain"),
+        ];
+
+        let states = repositories(&roots);
+        let tops: Vec<String> = ["first", "second"]
+            .iter()
+            .map(|name| output(Some(&base.join(name)), &["rev-parse", "--show-toplevel"]).unwrap())
+            .collect();
+        let found: Vec<&str> = states.iter().map(|state| state.root.as_str()).collect();
+        if output(Some(&base.join("plain")), &["rev-parse", "--show-toplevel"]).is_ok() {
+            // The temp directory itself sits inside a repository.
+            assert_eq!(found[..2], [tops[0].as_str(), tops[1].as_str()]);
+        } else {
+            assert_eq!(found, [tops[0].as_str(), tops[1].as_str()]);
+        }
+        assert!(states[0].dirty);
+    }
+}
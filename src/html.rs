@@ -0,0 +1,386 @@
+use crate::budget::{self, Group};
+use crate::content::FileContent;
+use crate::format::{self, Tree, TreeLine};
+use crate::permalink::{Permalink, DIFFERS_NOTE};
+use crate::style::Role;
+use std::collections::HashMap;
+
+/// Layout of the page; the highlighting theme is appended after it.
+const STYLE: &str = "\
+body{font-family:system-ui,sans-serif;margin:2rem auto;max-width:60rem;padding:0 1rem;color:#24292f}
+header{border-bottom:1px solid #d0d7de;margin-bottom:1rem}
+.summary{color:#57606a}
+.tree,.tree ul{list-style:none;margin:0;padding-left:1.2rem}
+.tree{padding-left:0;font-family:ui-monospace,monospace}
+.tree summary{cursor:pointer;color:#0550ae}
+.tree .skipped,.skipped{color:#8c959f}
+.note{color:#8c959f;margin-left:1ch}
+section h2{font-family:ui-monospace,monospace;font-size:1rem;background:#f6f8fa;padding:.4rem;margin-top:2rem}
+.permalink{font-family:system-ui,sans-serif;font-size:.8rem;margin-left:1ch}
+pre{overflow-x:auto;padding:.5rem;background:#fafbfc;border:1px solid #d0d7de}
+.budget td{padding:0 1ch;font-family:ui-monospace,monospace}
+.budget td.amount{text-align:right}
+";
+
+/// One file section of the page: `anchor` matches the path the tree links
+/// by, `heading` is the path as plain output shows it.
+pub struct Section<'a> {
+    pub anchor: String,
+    pub heading: String,
+    pub permalink: Option<Permalink>,
+    pub content: &'a FileContent,
+}
+
+/// A copy as one self-contained HTML page: the summary, the tree as nested
+/// `<details>` linking to the files, then each file highlighted, and last
+/// what the limits in `budget` cut. Everything is inline, so the page works
+/// offline, and there is no script at all.
+pub fn render(
+    title: &str,
+    tree: &Tree,
+    sections: &[Section],
+    provenance: Option<&str>,
+    budget: &[Group],
+) -> String {
+    let anchors: HashMap<&str, usize> = sections
+        .iter()
+        .enumerate()
+        .map(|(index, section)| (section.anchor.as_str(), index))
+        .collect();
+    let mut page = String::from("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n");
+    page.push_str("<meta charset=\"utf-8\">\n");
+    page.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    page.push_str(&format!("<title>{}</title>\n", escape(title)));
+    page.push_str(&format!("<style>\n{}{}</style>\n", STYLE, highlight_css()));
+    page.push_str("</head>\n<body>\n<header>\n");
+    page.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+    page.push_str(&format!(
+        "<p class=\"summary\">{}</p>\n",
+        escape(&summary(sections))
+    ));
+    if let Some(line) = provenance {
+        page.push_str(&format!(
+            "<p class=\"provenance\"><code>{}</code></p>\n",
+            escape(line)
+        ));
+    }
+    page.push_str("</header>\n<nav>\n");
+    page.push_str(&tree_html(tree, &anchors));
+    page.push_str("</nav>\n<main>\n");
+    for (index, section) in sections.iter().enumerate() {
+        page.push_str(&section_html(index, section));
+    }
+    if !budget.is_empty() {
+        page.push_str(&budget_html(budget));
+    }
+    page.push_str("</main>\n</body>\n</html>\n");
+    page
+}
+
+fn summary(sections: &[Section]) -> String {
+    format::summary(sections.iter().map(|section| section.content))
+}
+
+/// The tree as nested lists, each directory a `<details>` open by default.
+/// Nesting follows the connectors: every level of a tree line's prefix is
+/// three columns wide.
+fn tree_html(tree: &Tree, anchors: &HashMap<&str, usize>) -> String {
+    let depth = |line: &TreeLine| line.prefix.chars().count() / 3;
+    let mut html = String::from("<ul class=\"tree\">\n");
+    let mut open: Vec<usize> = Vec::new();
+    for (index, line) in tree.lines.iter().enumerate() {
+        let level = depth(line);
+        while open.last().is_some_and(|&parent| parent >= level) {
+            open.pop();
+            html.push_str("</ul></details></li>\n");
+        }
+        let has_children = tree
+            .lines
+            .get(index + 1)
+            .is_some_and(|next| depth(next) > level);
+        let note = line.note.as_deref().map_or_else(String::new, |note| {
+            format!("<span class=\"note\">{}</span>", escape(note))
+        });
+        if has_children {
+            html.push_str(&format!(
+                "<li><details open><summary>{}{}</summary><ul>\n",
+                escape(&line.name),
+                note
+            ));
+            open.push(level);
+            continue;
+        }
+        let class = match line.role {
+            Some(Role::Directory) => " class=\"directory\"",
+            Some(Role::Symlink) => " class=\"symlink\"",
+            Some(Role::Skipped) => " class=\"skipped\"",
+            _ => "",
+        };
+        let name = match line.path.as_deref().and_then(|path| anchors.get(path)) {
+            Some(index) => format!(
+                "<a href=\"#file-{}\"{}>{}</a>",
+                index,
+                class,
+                escape(&line.name)
+            ),
+            None => format!("<span{}>{}</span>", class, escape(&line.name)),
+        };
+        html.push_str(&format!("<li>{}{}</li>\n", name, note));
+    }
+    for _ in open {
+        html.push_str("</ul></details></li>\n");
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+fn section_html(index: usize, section: &Section) -> String {
+    let body = match section.content {
+        FileContent::Text(text) => format!(
+            "<pre><code>{}</code></pre>",
+            highlight(&section.heading, text).unwrap_or_else(|| escape(text))
+        ),
+        FileContent::Binary(_) => format!(
+            "<pre><code>{}</code></pre>",
+            escape(&section.content.body())
+        ),
+        FileContent::Image { .. } => format!("<p>{}</p>", escape(&section.content.body())),
+        FileContent::Skipped(reason) => {
+            format!("<p class=\"skipped\">{}</p>", escape(&reason.marker()))
+        }
+    };
+    let link = section
+        .permalink
+        .as_ref()
+        .map(|permalink| {
+            let differs = if permalink.differs {
+                format!("<span class=\"note\">{}</span>", DIFFERS_NOTE.trim())
+            } else {
+                String::new()
+            };
+            format!(
+                " <a class=\"permalink\" href=\"{}\">permalink</a>{}",
+                escape(&permalink.url),
+                differs
+            )
+        })
+        .unwrap_or_default();
+    format!(
+        "<section id=\"file-{index}\">\n<h2><a href=\"#file-{index}\">{}</a>{}</h2>\n{}\n</section>\n",
+        escape(&section.content.header(&section.heading)),
+        link,
+        body
+    )
+}
+
+/// The report on what the limits cut: per limit its summary line, then a
+/// table of what it cut.
+fn budget_html(groups: &[Group]) -> String {
+    let mut html = String::from("<section class=\"budget\">\n<h2>Cut by limits</h2>\n");
+    for group in groups {
+        html.push_str(&format!(
+            "<p>{}</p>\n<table>\n",
+            escape(&budget::summary(group))
+        ));
+        for [amount, path, reason] in budget::rows(group) {
+            html.push_str(&format!(
+                "<tr><td class=\"amount\">{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape(&amount),
+                escape(&path),
+                escape(&reason)
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</section>\n");
+    html
+}
+
+/// Escapes text for use in element content and quoted attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(feature = "highlight")]
+const CLASS_STYLE: syntect::html::ClassStyle =
+    syntect::html::ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// `text` as classed spans for the syntax its path or first line suggests;
+/// `None` when no syntax matches. The spans' text is escaped.
+#[cfg(feature = "highlight")]
+fn highlight(path: &str, text: &str) -> Option<String> {
+    use syntect::html::ClassedHTMLGenerator;
+    use syntect::parsing::SyntaxSet;
+    use syntect::util::LinesWithEndings;
+
+    static SYNTAXES: std::sync::OnceLock<SyntaxSet> = std::sync::OnceLock::new();
+    let syntaxes = SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines);
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str());
+    let syntax = extension
+        .and_then(|extension| syntaxes.find_syntax_by_extension(extension))
+        .or_else(|| syntaxes.find_syntax_by_first_line(text.lines().next()?))
+        .filter(|syntax| syntax.name != "Plain Text")?;
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CLASS_STYLE);
+    for line in LinesWithEndings::from(text) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .ok()?;
+    }
+    Some(generator.finalize())
+}
+
+#[cfg(not(feature = "highlight"))]
+fn highlight(_path: &str, _text: &str) -> Option<String> {
+    None
+}
+
+/// The colors for [`highlight`]'s classes.
+#[cfg(feature = "highlight")]
+fn highlight_css() -> String {
+    let themes = syntect::highlighting::ThemeSet::load_defaults();
+    themes
+        .themes
+        .get("InspiredGitHub")
+        .and_then(|theme| syntect::html::css_for_theme_with_class_style(theme, CLASS_STYLE).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "highlight"))]
+fn highlight_css() -> String {
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::SkipReason;
+
+    fn line(prefix: &str, name: &str, role: Option<Role>, path: Option<&str>) -> TreeLine {
+        TreeLine {
+            prefix: prefix.to_string(),
+            name: name.to_string(),
+            role,
+            note: None,
+            path: path.map(str::to_string),
+        }
+    }
+
+    fn sample_tree() -> Tree {
+        Tree {
+            lines: vec![
+                line("", "src", Some(Role::Directory), None),
+                line("├─ ", "data.bin", Some(Role::Skipped), Some("src/data.bin")),
+                line("├─ ", "notes.xyz", None, Some("src/notes.xyz")),
+                line("└─ ", "util", Some(Role::Directory), None),
+                line("   └─ ", "mod.xyz", None, Some("src/util/mod.xyz")),
+            ],
+        }
+    }
+
+    fn section<'a>(path: &str, content: &'a FileContent) -> Section<'a> {
+        Section {
+            anchor: path.to_string(),
+            heading: path.to_string(),
+            permalink: None,
+            content,
+        }
+    }
+
+    #[test]
+    fn tree_nests_directories_and_links_files_to_sections() {
+        let denied = FileContent::Skipped(SkipReason::Permission);
+        let notes = FileContent::Text("a < b\n".to_string());
+        let sections = [
+            section("src/data.bin", &denied),
+            section("src/notes.xyz", &notes),
+        ];
+        let anchors = sections
+            .iter()
+            .enumerate()
+            .map(|(index, section)| (section.anchor.as_str(), index))
+            .collect();
+        assert_eq!(
+            tree_html(&sample_tree(), &anchors),
+            "<ul class=\"tree\">\n\
+             <li><details open><summary>src</summary><ul>\n\
+             <li><a href=\"#file-0\" class=\"skipped\">data.bin</a></li>\n\
+             <li><a href=\"#file-1\">notes.xyz</a></li>\n\
+             <li><details open><summary>util</summary><ul>\n\
+             <li><span>mod.xyz</span></li>\n\
+             </ul></details></li>\n\
+             </ul></details></li>\n\
+             </ul>\n"
+        );
+        assert_eq!(
+            section_html(0, &sections[0]),
+            "<section id=\"file-0\">\n<h2><a href=\"#file-0\">src/data.bin</a></h2>\n\
+             <p class=\"skipped\">&lt;skipped reason=&quot;permission&quot;&gt; permission denied</p>\n</section>\n"
+        );
+        assert_eq!(
+            section_html(1, &sections[1]),
+            "<section id=\"file-1\">\n<h2><a href=\"#file-1\">src/notes.xyz</a></h2>\n\
+             <pre><code>a &lt; b\n</code></pre>\n</section>\n"
+        );
+        assert_eq!(summary(&sections), "1 file, 6 B (~2 tokens); 1 skipped");
+    }
+
+    #[test]
+    fn hostile_names_and_contents_stay_text() {
+        let hostile = "</code></pre></script><script>alert('x')</script><img src=x onerror=\"y\">";
+        let contents = [
+            FileContent::Text(format!("{}\n", hostile)),
+            FileContent::Text(format!("// {}\nfn main() {{}}\n", hostile)),
+        ];
+        let sections = [
+            section(&format!("{}.xyz", hostile), &contents[0]),
+            section("src/main.rs", &contents[1]),
+        ];
+        let tree = Tree {
+            lines: vec![line(
+                "",
+                hostile,
+                Some(Role::Directory),
+                Some(&sections[0].anchor),
+            )],
+        };
+        let page = render(hostile, &tree, &sections, Some(hostile), &[]);
+        for raw in ["<script", "</script", "<img", "onerror=\""] {
+            assert!(!page.contains(raw), "{raw} was not escaped");
+        }
+        assert!(page.contains("&lt;/script&gt;&lt;script&gt;alert(&#39;x&#39;)"));
+        assert_eq!(page.matches("<pre>").count(), 2);
+        assert_eq!(page.matches("</pre>").count(), 2);
+        assert!(!page.contains("http"), "the page must not load anything");
+    }
+
+    #[cfg(feature = "highlight")]
+    #[test]
+    fn known_languages_are_highlighted() {
+        let code = FileContent::Text("fn main() {}\n".to_string());
+        let page = render(
+            "src",
+            &Tree::default(),
+            &[section("main.rs", &code)],
+            None,
+            &[],
+        );
+        assert!(
+            page.contains("<span class=\"hl-source hl-rust\">"),
+            "{page}"
+        );
+        assert!(page.contains(".hl-"), "the theme is inlined");
+    }
+}
@@ -0,0 +1,342 @@
+use crate::output::human_size;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How many leading bytes are read to describe an image; JPEG headers can
+/// put an EXIF thumbnail before the frame size.
+pub const HEADER_BYTES: u64 = 64 * 1024;
+
+const EXTENSIONS: &[&str] = &["gif", "jpeg", "jpg", "png", "svg", "webp"];
+
+/// Starts every line written by [`ImageInfo::stanza`].
+const STANZA_OPEN: &str = "<image format=\"";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Svg,
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::Gif => "GIF",
+            Self::Webp => "WebP",
+            Self::Svg => "SVG",
+        })
+    }
+}
+
+/// What an image's header says about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    /// Width and height in pixels, when the header has them.
+    pub dimensions: Option<(u32, u32)>,
+    /// The root `<svg>` element's attributes as written, without `xmlns`
+    /// declarations, e.g. `viewBox="0 0 24 24"`.
+    pub attributes: Option<String>,
+}
+
+impl ImageInfo {
+    /// One line for a file of `size` bytes: `PNG, 512×512, 34 KiB`.
+    pub fn summary(&self, size: u64) -> String {
+        let mut parts = vec![self.format.to_string()];
+        if let Some((width, height)) = self.dimensions {
+            parts.push(format!("{}×{}", width, height));
+        }
+        parts.push(human_size(size));
+        parts.extend(self.attributes.clone());
+        parts.join(", ")
+    }
+
+    /// The line written in place of the image's bytes:
+    /// `<image format="png" width="512" height="512" size="34816">`, then a
+    /// space and the [`Self::summary`].
+    pub fn stanza(&self, size: u64) -> String {
+        let mut tag = format!(
+            "{}{}\"",
+            STANZA_OPEN,
+            self.format.to_string().to_lowercase()
+        );
+        if let Some((width, height)) = self.dimensions {
+            tag.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+        }
+        format!("{} size=\"{}\"> {}", tag, size, self.summary(size))
+    }
+}
+
+/// Whether `body` is a single line written by [`ImageInfo::stanza`].
+pub fn is_stanza(body: &str) -> bool {
+    body.starts_with(STANZA_OPEN) && body.contains("\"> ") && !body.contains('\n')
+}
+
+/// Whether `path` is named like an image this module can describe.
+pub fn has_image_extension(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        EXTENSIONS.contains(&extension.to_string_lossy().to_ascii_lowercase().as_str())
+    })
+}
+
+/// Describes the image at `path` from its first [`HEADER_BYTES`].
+pub fn read_info(path: &Path) -> io::Result<Option<ImageInfo>> {
+    let mut head = Vec::with_capacity(HEADER_BYTES as usize);
+    File::open(path)?
+        .take(HEADER_BYTES)
+        .read_to_end(&mut head)?;
+    Ok(parse(path, &head))
+}
+
+/// Describes an image from `head`, its leading bytes: binary formats by
+/// their magic bytes, SVG by its `<svg>` element. `None` when `head` is not
+/// one of the supported formats.
+pub fn parse(path: &Path, head: &[u8]) -> Option<ImageInfo> {
+    let binary = |format, dimensions| ImageInfo {
+        format,
+        dimensions,
+        attributes: None,
+    };
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(binary(ImageFormat::Png, png_dimensions(head)))
+    } else if head.starts_with(b"\xff\xd8\xff") {
+        Some(binary(ImageFormat::Jpeg, jpeg_dimensions(head)))
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some(binary(ImageFormat::Gif, gif_dimensions(head)))
+    } else if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        Some(binary(ImageFormat::Webp, webp_dimensions(head)))
+    } else if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"))
+    {
+        svg_info(head)
+    } else {
+        None
+    }
+}
+
+fn u16_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u24_le(bytes: &[u8], at: usize) -> Option<u32> {
+    let bytes = bytes.get(at..at + 3)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+
+/// The IHDR chunk always comes first, right after the signature.
+fn png_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    if head.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(head.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(head.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+/// Walks the segments up to the first start-of-frame marker.
+fn jpeg_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *head.get(at)? != 0xff {
+            return None;
+        }
+        let marker = *head.get(at + 1)?;
+        match marker {
+            // Fill bytes before a marker.
+            0xff => at += 1,
+            // Markers without a length.
+            0x01 | 0xd0..=0xd8 => at += 2,
+            // SOF0 to SOF15, except DHT, JPG, and DAC.
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let height = u16_be(head, at + 5)?;
+                let width = u16_be(head, at + 7)?;
+                return Some((width, height));
+            }
+            // Start of scan or end of image without a frame header.
+            0xd9 | 0xda => return None,
+            _ => at += 2 + u16_be(head, at + 2)? as usize,
+        }
+    }
+}
+
+fn gif_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    Some((u16_le(head, 6)?, u16_le(head, 8)?))
+}
+
+/// Reads the first chunk: extended (`VP8X`), lossless (`VP8L`), or lossy
+/// (`VP8 `).
+fn webp_dimensions(head: &[u8]) -> Option<(u32, u32)> {
+    match head.get(12..16)? {
+        b"VP8X" => Some((u24_le(head, 24)? + 1, u24_le(head, 27)? + 1)),
+        b"VP8L" => {
+            if *head.get(20)? != 0x2f {
+                return None;
+            }
+            let bits = u32::from_le_bytes(head.get(21..25)?.try_into().ok()?);
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        b"VP8 " => {
+            if head.get(23..26)? != b"\x9d\x01\x2a" {
+                return None;
+            }
+            Some((u16_le(head, 26)? & 0x3fff, u16_le(head, 28)? & 0x3fff))
+        }
+        _ => None,
+    }
+}
+
+/// Reads the root `<svg>` element's attributes. Dimensions come from
+/// `width` and `height` when both are plain numbers, else from `viewBox`.
+fn svg_info(head: &[u8]) -> Option<ImageInfo> {
+    let text = String::from_utf8_lossy(head);
+    let start = text.find("<svg")? + "<svg".len();
+    let end = start + text[start..].find('>')?;
+    let attributes = parse_attributes(text[start..end].trim_end_matches('/'));
+    let value = |name: &str| {
+        attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let length = |value: &str| {
+        let number: f64 = value.trim().trim_end_matches("px").parse().ok()?;
+        (number > 0.0).then(|| number.round() as u32)
+    };
+    let from_size = value("width")
+        .and_then(length)
+        .zip(value("height").and_then(length));
+    let from_view_box = value("viewBox").and_then(|view_box| {
+        let numbers: Vec<&str> = view_box
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|number| !number.is_empty())
+            .collect();
+        match numbers[..] {
+            [_, _, width, height] => length(width).zip(length(height)),
+            _ => None,
+        }
+    });
+    let written: Vec<String> = attributes
+        .iter()
+        .filter(|(key, _)| key != "xmlns" && !key.starts_with("xmlns:"))
+        .map(|(key, value)| format!("{}=\"{}\"", key, value))
+        .collect();
+    Some(ImageInfo {
+        format: ImageFormat::Svg,
+        dimensions: from_size.or(from_view_box),
+        attributes: (!written.is_empty()).then(|| written.join(" ")),
+    })
+}
+
+/// Splits `name="value"` pairs, quoted with either `"` or `'`.
+fn parse_attributes(source: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = source;
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().to_string();
+        let after = rest[eq + 1..].trim_start();
+        let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(close) = after[1..].find(quote) else {
+            break;
+        };
+        attributes.push((name, after[1..close + 1].to_string()));
+        rest = &after[close + 2..];
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] =
+        b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00\x02\x00\x00\x00\x01\x00\x08\x06";
+    const GIF: &[u8] = b"GIF89a\x10\x00\x20\x00\x80\x00\x00";
+    const JPEG: &[u8] = b"\xff\xd8\xff\xe0\x00\x04JF\xff\xc0\x00\x11\x08\x00\x30\x00\x40\x03";
+    const WEBP_LOSSY: &[u8] =
+        b"RIFF\x00\x00\x00\x00WEBPVP8 \x00\x00\x00\x00\x00\x00\x00\x9d\x01\x2a\x80\x02\xe0\x01";
+    const WEBP_LOSSLESS: &[u8] =
+        b"RIFF\x00\x00\x00\x00WEBPVP8L\x00\x00\x00\x00\x2f\x09\x40\x03\x00";
+    const WEBP_EXTENDED: &[u8] =
+        b"RIFF\x00\x00\x00\x00WEBPVP8X\x0a\x00\x00\x00\x10\x00\x00\x00\xff\x01\x00\x7f\x00\x00";
+
+    fn dimensions(head: &[u8]) -> Option<(u32, u32)> {
+        parse(Path::new("image"), head).and_then(|info| info.dimensions)
+    }
+
+    #[test]
+    fn reads_binary_headers() {
+        assert_eq!(dimensions(PNG), Some((512, 256)));
+        assert_eq!(dimensions(GIF), Some((16, 32)));
+        assert_eq!(dimensions(JPEG), Some((64, 48)));
+        assert_eq!(dimensions(WEBP_LOSSY), Some((640, 480)));
+        assert_eq!(dimensions(WEBP_LOSSLESS), Some((10, 14)));
+        assert_eq!(dimensions(WEBP_EXTENDED), Some((512, 128)));
+    }
+
+    #[test]
+    fn truncated_headers_keep_the_format() {
+        let info = parse(Path::new("logo.png"), &PNG[..12]).expect("png signature");
+        assert_eq!(info.format, ImageFormat::Png);
+        assert_eq!(info.dimensions, None);
+        assert_eq!(parse(Path::new("logo.png"), b"not an image"), None);
+    }
+
+    #[test]
+    fn svg_keeps_root_attributes_without_namespaces() {
+        let svg = br#"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" fill='none'>
+  <path d="M0 0"/>
+</svg>"#;
+        let info = parse(Path::new("icon.svg"), svg).expect("svg");
+        assert_eq!(info.dimensions, Some((24, 24)));
+        assert_eq!(
+            info.attributes.as_deref(),
+            Some(r#"viewBox="0 0 24 24" fill="none""#)
+        );
+        assert_eq!(
+            info.summary(2_048),
+            r#"SVG, 24×24, 2.0 KiB, viewBox="0 0 24 24" fill="none""#
+        );
+
+        let sized = parse(
+            Path::new("logo.svg"),
+            br#"<svg width="120px" height="40"/>"#,
+        );
+        assert_eq!(sized.and_then(|info| info.dimensions), Some((120, 40)));
+        assert_eq!(parse(Path::new("notes.txt"), b"<svg>"), None);
+    }
+
+    #[test]
+    fn summary_names_format_dimensions_and_size() {
+        let info = parse(Path::new("logo.png"), PNG).expect("png");
+        assert_eq!(info.summary(34 * 1024), "PNG, 512×256, 34 KiB");
+    }
+
+    #[test]
+    fn stanza_carries_the_fields_and_the_summary() {
+        let info = parse(Path::new("logo.png"), PNG).expect("png");
+        let stanza = info.stanza(34 * 1024);
+        assert_eq!(
+            stanza,
+            "<image format=\"png\" width=\"512\" height=\"256\" size=\"34816\"> PNG, 512×256, 34 KiB"
+        );
+        assert!(is_stanza(&stanza));
+        assert!(!is_stanza(
+            "<skipped reason=\"binary\" kind=\"binary\"> binary file"
+        ));
+        assert!(!is_stanza(&format!("{}\nmore", stanza)));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::logger;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Exit status of a run stopped by Ctrl-C, as a shell reports one killed
+/// by SIGINT.
+pub const EXIT_STATUS: u8 = 130;
+
+/// Starts the line `--partial-on-interrupt` ends its output with; snapshot
+/// parsing stops there.
+pub const TRAILER_PREFIX: &str = "<interrupted: ";
+
+/// Ctrl-C during a run. The first press trips the flag, which the walk and
+/// the reads check between files so the run can stop cleanly; a second
+/// press, or one once [`Interrupt::quit_on_next`] was called, quits at once.
+///
+/// Clones share the flag. One made with `default` is never tripped by
+/// Ctrl-C, only by [`Interrupt::trip`].
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<State>);
+
+#[derive(Debug, Default)]
+struct State {
+    tripped: AtomicBool,
+    quit_on_next: AtomicBool,
+}
+
+impl Interrupt {
+    /// A flag Ctrl-C trips. When the handler cannot be installed, Ctrl-C
+    /// kills the process as it would without one.
+    pub fn install() -> Self {
+        let interrupt = Self::default();
+        let pressed = interrupt.clone();
+        // The handler runs on a thread of its own, one press at a time.
+        let installed = ctrlc::set_handler(move || {
+            if pressed.is_tripped() || pressed.0.quit_on_next.load(Ordering::SeqCst) {
+                std::process::exit(i32::from(EXIT_STATUS));
+            }
+            pressed.trip();
+            logger::warn(format_args!(
+                "interrupted; stopping after the file being written (Ctrl-C again quits at once)"
+            ));
+        });
+        if let Err(err) = installed {
+            logger::verbose(format_args!("Ctrl-C handler not installed: {err}"));
+        }
+        interrupt
+    }
+
+    pub fn trip(&self) {
+        self.0.tripped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.0.tripped.load(Ordering::SeqCst)
+    }
+
+    /// Makes the next Ctrl-C quit at once, for a stage with nothing to
+    /// finish cleanly, such as serving the output.
+    pub fn quit_on_next(&self) {
+        self.0.quit_on_next.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The line closing output cut short by Ctrl-C under
+/// `--partial-on-interrupt`: `<interrupted: 3 files not processed>`.
+pub fn trailer(unprocessed: u64) -> String {
+    format!(
+        "{}{} file{} not processed>\n",
+        TRAILER_PREFIX,
+        unprocessed,
+        if unprocessed == 1 { "" } else { "s" }
+    )
+}
@@ -0,0 +1,289 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How many leading bytes `--type` reads to tell a file's kind.
+pub const SNIFF_BYTES: u64 = 8 * 1024;
+
+/// What a file holds, from its leading bytes and then its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    /// Any file emitted as text; as a `--type` it also matches code and config.
+    Text,
+    Code,
+    Config,
+    Image,
+    Archive,
+    Document,
+    /// Not text, and not one of the binary kinds above.
+    Binary,
+}
+
+impl FileKind {
+    /// Whether the file's contents end up in the output as text.
+    pub fn is_text(self) -> bool {
+        matches!(self, Self::Text | Self::Code | Self::Config)
+    }
+
+    /// Whether a file of kind `detected` passes `--type self`.
+    pub fn admits(self, detected: FileKind) -> bool {
+        self == detected || (self == Self::Text && detected.is_text())
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.to_possible_value().expect("no variant is skipped");
+        f.write_str(name.get_name())
+    }
+}
+
+/// Leading bytes that tell a binary file's kind and media type.
+const MAGIC: &[(&[u8], FileKind, &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", FileKind::Image, "image/png"),
+    (b"\xff\xd8\xff", FileKind::Image, "image/jpeg"),
+    (b"GIF87a", FileKind::Image, "image/gif"),
+    (b"GIF89a", FileKind::Image, "image/gif"),
+    (b"%PDF-", FileKind::Document, "application/pdf"),
+    (b"\x1f\x8b", FileKind::Archive, "application/gzip"),
+    (b"PK\x03\x04", FileKind::Archive, "application/zip"),
+    (b"\xfd7zXZ\x00", FileKind::Archive, "application/x-xz"),
+    (b"BZh", FileKind::Archive, "application/x-bzip2"),
+    (
+        b"7z\xbc\xaf\x27\x1c",
+        FileKind::Archive,
+        "application/x-7z-compressed",
+    ),
+    (b"\x28\xb5\x2f\xfd", FileKind::Archive, "application/zstd"),
+];
+
+/// The media type of files with no magic bytes to go on.
+const UNKNOWN_TYPE: &str = "application/octet-stream";
+
+const CODE_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cpp", "cs", "css", "go", "h", "hpp", "html", "java", "js", "jsx", "kt", "lua", "m",
+    "mjs", "php", "pl", "py", "rb", "rs", "scala", "scss", "sh", "sql", "swift", "ts", "tsx",
+    "vue", "zsh",
+];
+const CONFIG_EXTENSIONS: &[&str] = &[
+    "cfg",
+    "conf",
+    "env",
+    "ini",
+    "json",
+    "lock",
+    "properties",
+    "toml",
+    "xml",
+    "yaml",
+    "yml",
+];
+const CONFIG_NAMES: &[&str] = &[
+    ".editorconfig",
+    ".gitattributes",
+    ".gitignore",
+    ".ignore",
+    "Dockerfile",
+    "Makefile",
+];
+const IMAGE_EXTENSIONS: &[&str] = &["bmp", "gif", "ico", "jpeg", "jpg", "png", "tiff", "webp"];
+const ARCHIVE_EXTENSIONS: &[&str] = &["7z", "bz2", "gz", "jar", "tar", "tgz", "xz", "zip", "zst"];
+const DOCUMENT_EXTENSIONS: &[&str] = &["doc", "docx", "odt", "pdf", "ppt", "pptx", "xls", "xlsx"];
+
+/// Tells the kind of `path` from `head`, its leading bytes. Text is decided
+/// by the bytes alone (UTF-8, or UTF-16 with a byte order mark) and then
+/// refined by name; other files by their magic bytes, then by extension.
+pub fn detect(path: &Path, head: &[u8]) -> FileKind {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    let has_extension = |list: &[&str]| extension.as_deref().is_some_and(|ext| list.contains(&ext));
+
+    if is_text(head) {
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        return if has_extension(CODE_EXTENSIONS) || head.starts_with(b"#!") {
+            FileKind::Code
+        } else if has_extension(CONFIG_EXTENSIONS)
+            || name.is_some_and(|name| CONFIG_NAMES.contains(&name.as_ref()))
+        {
+            FileKind::Config
+        } else {
+            FileKind::Text
+        };
+    }
+    if let Some(&(_, kind, _)) = MAGIC.iter().find(|(magic, ..)| head.starts_with(magic)) {
+        return kind;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return FileKind::Image;
+    }
+    if has_extension(IMAGE_EXTENSIONS) {
+        FileKind::Image
+    } else if has_extension(ARCHIVE_EXTENSIONS) {
+        FileKind::Archive
+    } else if has_extension(DOCUMENT_EXTENSIONS) {
+        FileKind::Document
+    } else {
+        FileKind::Binary
+    }
+}
+
+/// Reads the first [`SNIFF_BYTES`] of `path` and tells its kind.
+pub fn sniff(path: &Path) -> io::Result<FileKind> {
+    let mut head = Vec::with_capacity(SNIFF_BYTES as usize);
+    File::open(path)?.take(SNIFF_BYTES).read_to_end(&mut head)?;
+    Ok(detect(path, &head))
+}
+
+/// The media type of a file from `head`, its leading bytes: `text/plain`
+/// (with `; charset=utf-16` for UTF-16 text), a type such as `image/png`
+/// or `application/gzip` for the magic bytes [`detect`] knows, and
+/// `application/octet-stream` for any other binary, whatever its name.
+pub fn content_type(head: &[u8]) -> &'static str {
+    // A tar of text files is valid UTF-8 too.
+    if head.get(257..262) == Some(b"ustar") {
+        return "application/x-tar";
+    }
+    if is_text(head) {
+        return if head.starts_with(b"\xff\xfe") || head.starts_with(b"\xfe\xff") {
+            "text/plain; charset=utf-16"
+        } else {
+            "text/plain"
+        };
+    }
+    if let Some(&(_, _, content_type)) = MAGIC.iter().find(|(magic, ..)| head.starts_with(magic)) {
+        return content_type;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    UNKNOWN_TYPE
+}
+
+/// Reads the first [`SNIFF_BYTES`] of `path` and tells its media type.
+pub fn sniff_content_type(path: &Path) -> io::Result<&'static str> {
+    let mut head = Vec::with_capacity(SNIFF_BYTES as usize);
+    File::open(path)?.take(SNIFF_BYTES).read_to_end(&mut head)?;
+    Ok(content_type(&head))
+}
+
+/// Decodes UTF-16 text that starts with a byte order mark, dropping the mark.
+pub fn decode_utf16(bytes: &[u8]) -> Option<String> {
+    let from_bytes: fn([u8; 2]) -> u16 = match bytes.get(..2)? {
+        b"\xff\xfe" => u16::from_le_bytes,
+        b"\xfe\xff" => u16::from_be_bytes,
+        _ => return None,
+    };
+    let body = &bytes[2..];
+    if !body.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = body
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<_, _>>().ok()
+}
+
+/// Whether `head` reads as text. A UTF-8 character cut off at the end of a
+/// sniffed prefix still counts.
+fn is_text(head: &[u8]) -> bool {
+    if head.starts_with(b"\xff\xfe") || head.starts_with(b"\xfe\xff") {
+        return decode_utf16(head).is_some();
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x00\x01";
+    const GZIP: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03\xcb\x48\xcd\xc9\xc9\x07\x00";
+    const PDF: &[u8] = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n1 0 obj\n";
+    const UTF16_LE: &[u8] = b"\xff\xfeh\x00i\x00\n\x00";
+
+    #[test]
+    fn magic_bytes_win_over_the_extension() {
+        assert_eq!(detect(Path::new("logo.png"), PNG), FileKind::Image);
+        assert_eq!(detect(Path::new("notes.txt"), PNG), FileKind::Image);
+        assert_eq!(detect(Path::new("backup"), GZIP), FileKind::Archive);
+        assert_eq!(detect(Path::new("paper.txt"), PDF), FileKind::Document);
+    }
+
+    #[test]
+    fn text_is_refined_by_name_and_shebang() {
+        assert_eq!(
+            detect(Path::new("README"), "héllo\n".as_bytes()),
+            FileKind::Text
+        );
+        assert_eq!(
+            detect(Path::new("src/main.rs"), b"fn main() {}\n"),
+            FileKind::Code
+        );
+        assert_eq!(
+            detect(Path::new("deploy"), b"#!/bin/sh\necho\n"),
+            FileKind::Code
+        );
+        assert_eq!(
+            detect(Path::new("Cargo.toml"), b"[package]\n"),
+            FileKind::Config
+        );
+        assert_eq!(detect(Path::new("Makefile"), b"all:\n"), FileKind::Config);
+    }
+
+    #[test]
+    fn utf16_with_a_byte_order_mark_is_text() {
+        assert_eq!(detect(Path::new("notes.txt"), UTF16_LE), FileKind::Text);
+        assert_eq!(decode_utf16(UTF16_LE).as_deref(), Some("hi\n"));
+        assert_eq!(decode_utf16(b"\xfe\xff\x00h\x00i").as_deref(), Some("hi"));
+        assert_eq!(decode_utf16(b"hi"), None);
+    }
+
+    #[test]
+    fn unknown_binary_falls_back_to_the_extension() {
+        assert_eq!(
+            detect(Path::new("photo.bmp"), b"BM\xff\x00"),
+            FileKind::Image
+        );
+        assert_eq!(
+            detect(Path::new("main.rs"), b"\x00\xff\x00"),
+            FileKind::Binary
+        );
+        assert_eq!(detect(Path::new("data.bin"), b"\xc3\x28"), FileKind::Binary);
+    }
+
+    #[test]
+    fn a_character_cut_off_by_the_sniff_is_still_text() {
+        let head = &"日本".as_bytes()[..4];
+        assert_eq!(detect(Path::new("notes.md"), head), FileKind::Text);
+    }
+
+    #[test]
+    fn content_types_come_from_the_bytes_alone() {
+        assert_eq!(content_type(PNG), "image/png");
+        assert_eq!(content_type(GZIP), "application/gzip");
+        assert_eq!(content_type(PDF), "application/pdf");
+        assert_eq!(content_type(UTF16_LE), "text/plain; charset=utf-16");
+        assert_eq!(content_type(b"fn main() {}\n"), "text/plain");
+        assert_eq!(content_type(b"BM\xff\x00"), "application/octet-stream");
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(content_type(&tar), "application/x-tar");
+    }
+
+    #[test]
+    fn text_type_admits_code_and_config() {
+        assert!(FileKind::Text.admits(FileKind::Code));
+        assert!(FileKind::Text.admits(FileKind::Config));
+        assert!(!FileKind::Text.admits(FileKind::Image));
+        assert!(!FileKind::Code.admits(FileKind::Text));
+        assert_eq!(FileKind::Archive.to_string(), "archive");
+    }
+}
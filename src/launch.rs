@@ -0,0 +1,214 @@
+use crate::logger;
+use crate::output::Destination;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What `--open` launches on the output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Viewer {
+    /// `$VISUAL`, then `$EDITOR`.
+    Editor,
+    /// `$PAGER`, falling back to `less`.
+    Pager,
+}
+
+/// Editors that take over the terminal, so copytree waits for them to exit.
+/// Anything else is assumed to open its own window and is left running.
+const TERMINAL_PROGRAMS: &[&str] = &[
+    "vi", "vim", "nvim", "view", "nano", "pico", "emacs", "micro", "hx", "helix", "kak", "joe",
+    "ne", "mg", "ed", "less", "more", "most", "bat",
+];
+
+/// A program invocation, built separately from running it so the command
+/// line can be checked without launching anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Wait for the process to exit instead of detaching from it.
+    pub wait: bool,
+}
+
+pub trait Launcher {
+    fn launch(&mut self, command: &LaunchCommand) -> Result<()>;
+}
+
+pub struct SystemLauncher;
+
+impl Launcher for SystemLauncher {
+    fn launch(&mut self, command: &LaunchCommand) -> Result<()> {
+        let mut process = Command::new(&command.program);
+        process.args(&command.args);
+        if command.wait {
+            process
+                .status()
+                .with_context(|| format!("Failed to run {}", command.program))?;
+        } else {
+            process
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .with_context(|| format!("Failed to start {}", command.program))?;
+        }
+        Ok(())
+    }
+}
+
+/// Opens the first file the output went to. Problems are only warnings:
+/// the copy itself already succeeded.
+pub fn open_output(viewer: Viewer, destinations: &[Destination], launcher: &mut dyn Launcher) {
+    let Some(path) = viewable_path(destinations) else {
+        logger::warn("--open needs an output file (use --out), nothing opened");
+        return;
+    };
+    let result = build_command(viewer, path, |name| env::var(name).ok())
+        .and_then(|command| launcher.launch(&command));
+    if let Err(err) = result {
+        logger::warn(format_args!("could not open {}: {err:#}", path.display()));
+    }
+}
+
+fn viewable_path(destinations: &[Destination]) -> Option<&PathBuf> {
+    destinations
+        .iter()
+        .find_map(|destination| match destination {
+            Destination::File(path) | Destination::TempFile(path) => Some(path),
+            Destination::Stdout
+            | Destination::Clipboard
+            | Destination::Http(_)
+            | Destination::Post(_) => None,
+        })
+}
+
+/// Builds the command line from the environment read through `var`.
+/// Variables may carry arguments, as in `EDITOR="code --wait"`.
+pub fn build_command<F>(viewer: Viewer, path: &Path, var: F) -> Result<LaunchCommand>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let setting = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+    let configured = match viewer {
+        Viewer::Editor => setting("VISUAL").or_else(|| setting("EDITOR")),
+        Viewer::Pager => Some(setting("PAGER").unwrap_or_else(|| "less".to_string())),
+    };
+    let Some(configured) = configured else {
+        bail!("neither $VISUAL nor $EDITOR is set");
+    };
+
+    let mut words = configured.split_whitespace().map(str::to_string);
+    let program = words.next().expect("checked non-empty above");
+    let mut args: Vec<String> = words.collect();
+    args.push(path.to_string_lossy().into_owned());
+
+    let wait = viewer == Viewer::Pager || is_terminal_program(&program);
+    Ok(LaunchCommand {
+        program,
+        args,
+        wait,
+    })
+}
+
+fn is_terminal_program(program: &str) -> bool {
+    let name = Path::new(program)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    TERMINAL_PROGRAMS.contains(&name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            pairs
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingLauncher(Vec<LaunchCommand>);
+
+    impl Launcher for RecordingLauncher {
+        fn launch(&mut self, command: &LaunchCommand) -> Result<()> {
+            self.0.push(command.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn visual_wins_over_editor_and_terminal_editors_wait() {
+        let command = build_command(
+            Viewer::Editor,
+            Path::new("ctx.txt"),
+            vars(&[("VISUAL", "/usr/bin/nvim"), ("EDITOR", "nano")]),
+        )
+        .expect("command");
+        assert_eq!(
+            command,
+            LaunchCommand {
+                program: "/usr/bin/nvim".to_string(),
+                args: vec!["ctx.txt".to_string()],
+                wait: true,
+            }
+        );
+    }
+
+    #[test]
+    fn gui_editor_keeps_its_arguments_and_detaches() {
+        let command = build_command(
+            Viewer::Editor,
+            Path::new("out/ctx.txt"),
+            vars(&[("EDITOR", "code --reuse-window")]),
+        )
+        .expect("command");
+        assert_eq!(command.program, "code");
+        assert_eq!(command.args, vec!["--reuse-window", "out/ctx.txt"]);
+        assert!(!command.wait);
+    }
+
+    #[test]
+    fn pager_defaults_to_less() {
+        let command =
+            build_command(Viewer::Pager, Path::new("ctx.txt"), vars(&[])).expect("command");
+        assert_eq!(command.program, "less");
+        assert!(command.wait);
+    }
+
+    #[test]
+    fn missing_editor_is_an_error() {
+        let err = build_command(
+            Viewer::Editor,
+            Path::new("ctx.txt"),
+            vars(&[("EDITOR", " ")]),
+        )
+        .expect_err("no editor configured");
+        assert!(err.to_string().contains("$EDITOR"));
+    }
+
+    #[test]
+    fn opens_the_first_file_destination() {
+        let mut launcher = RecordingLauncher::default();
+        let destinations = [
+            Destination::Clipboard,
+            Destination::File(PathBuf::from("a.txt")),
+            Destination::File(PathBuf::from("b.txt")),
+        ];
+        open_output(Viewer::Pager, &destinations, &mut launcher);
+        assert_eq!(launcher.0.len(), 1);
+        assert_eq!(launcher.0[0].args.last().map(String::as_str), Some("a.txt"));
+
+        let mut launcher = RecordingLauncher::default();
+        open_output(Viewer::Pager, &[Destination::Stdout], &mut launcher);
+        assert!(launcher.0.is_empty());
+    }
+}
@@ -0,0 +1,8 @@
+//! Parts of copytree usable from other tools.
+//!
+//! The `copytree` binary walks, filters, and copies; what it can share
+//! without any of that lives here.
+
+pub mod order;
+pub mod transform;
+pub mod tree;
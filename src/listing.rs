@@ -0,0 +1,389 @@
+use crate::output::{grouped_count, human_size};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const BLOCK: usize = 512;
+/// The end-of-central-directory record sits within this many bytes of the
+/// end of a zip file: 22 bytes plus a comment of at most 64 KiB.
+const EOCD_SEARCH: u64 = 22 + 0xffff;
+
+/// One file or directory inside an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    /// Uncompressed size in bytes; 0 for directories.
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// The first entries of an archive, read without extracting anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    /// `zip`, `tar`, or `tar.gz`.
+    pub format: &'static str,
+    pub entries: Vec<Entry>,
+    /// Entries beyond the listed ones, `None` when every entry was listed.
+    pub more: Option<More>,
+}
+
+/// Zip directories say how many entries there are; tar streams do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum More {
+    Counted(u64),
+    Uncounted,
+}
+
+fn entries(count: u64) -> String {
+    format!(
+        "{} entr{}",
+        grouped_count(count),
+        if count == 1 { "y" } else { "ies" }
+    )
+}
+
+impl Listing {
+    /// The format and entry count, e.g. `zip, 4 entries`.
+    pub fn summary(&self) -> String {
+        let listed = self.entries.len() as u64;
+        let count = match self.more {
+            None => entries(listed),
+            Some(More::Counted(more)) => entries(listed + more),
+            Some(More::Uncounted) => format!("first {}", entries(listed)),
+        };
+        format!("{}, {}", self.format, count)
+    }
+
+    /// What follows the archive's skip marker: one line per entry, e.g.
+    /// `src/main.rs (13 B)`, and a last one for any entries not listed.
+    pub fn entry_lines(&self) -> String {
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| match entry.is_dir {
+                true => entry.name.clone(),
+                false => format!("{} ({})", entry.name, human_size(entry.size)),
+            })
+            .collect();
+        match self.more {
+            Some(More::Counted(more)) => lines.push(format!(
+                "… {} more entr{}",
+                grouped_count(more),
+                if more == 1 { "y" } else { "ies" }
+            )),
+            Some(More::Uncounted) => lines.push("… more entries".to_string()),
+            None => {}
+        }
+        lines.join("\n")
+    }
+}
+
+/// Whether `head` starts like a zip, gzip, or tar file.
+pub fn is_archive(head: &[u8]) -> bool {
+    head.starts_with(b"PK\x03\x04")
+        || head.starts_with(b"PK\x05\x06")
+        || head.starts_with(b"\x1f\x8b")
+        || head.get(257..262) == Some(b"ustar")
+}
+
+/// Lists up to `limit` entries of the zip or tar (optionally gzipped) file
+/// at `path`, reading only as far as needed. `Ok(None)` when the file is not
+/// one of those formats; an error when it is but cannot be read as one.
+pub fn list_file(path: &Path, limit: usize) -> io::Result<Option<Listing>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 4];
+    let read = read_up_to(&mut file, &mut magic)?;
+    file.rewind()?;
+    match &magic[..read] {
+        b"PK\x03\x04" | b"PK\x05\x06" => list_zip(BufReader::new(file), limit).map(Some),
+        [0x1f, 0x8b, ..] => list_gzipped_tar(file, limit),
+        _ => list_tar(BufReader::new(file), limit, "tar"),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn list_gzipped_tar(file: File, limit: usize) -> io::Result<Option<Listing>> {
+    list_tar(
+        BufReader::new(flate2::read::GzDecoder::new(BufReader::new(file))),
+        limit,
+        "tar.gz",
+    )
+}
+
+/// Without the `gzip` feature, gzipped tars are plain binary files.
+#[cfg(not(feature = "gzip"))]
+fn list_gzipped_tar(_file: File, _limit: usize) -> io::Result<Option<Listing>> {
+    Ok(None)
+}
+
+/// Reads the central directory, found through the end-of-central-directory
+/// record at the end of the file; local entries are never decompressed.
+pub fn list_zip<R: Read + Seek>(mut reader: R, limit: usize) -> io::Result<Listing> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let start = len.saturating_sub(EOCD_SEARCH);
+    reader.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| tail[at..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| corrupt("no end of central directory"))?;
+    let record = &tail[eocd..];
+    let total = u16_le(record, 10) as u64;
+    let offset = u32_le(record, 16) as u64;
+    if total == 0xffff || offset == 0xffff_ffff {
+        return Err(corrupt("ZIP64 archives are not listed"));
+    }
+
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut entries = Vec::new();
+    let mut header = [0u8; 46];
+    while (entries.len() as u64) < total && entries.len() < limit {
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| corrupt("central directory cut short"))?;
+        if !header.starts_with(b"PK\x01\x02") {
+            return Err(corrupt("bad central directory entry"));
+        }
+        let size = u32_le(&header, 24) as u64;
+        let name_len = u16_le(&header, 28) as usize;
+        let skip = u16_le(&header, 30) as i64 + u16_le(&header, 32) as i64;
+        let mut name = vec![0; name_len];
+        reader
+            .read_exact(&mut name)
+            .map_err(|_| corrupt("central directory cut short"))?;
+        reader.seek(SeekFrom::Current(skip))?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        entries.push(Entry {
+            is_dir: name.ends_with('/'),
+            name,
+            size,
+        });
+    }
+    let more = total - entries.len() as u64;
+    Ok(Listing {
+        format: "zip",
+        entries,
+        more: (more > 0).then_some(More::Counted(more)),
+    })
+}
+
+/// Reads tar headers in order, skipping over each entry's data. `Ok(None)`
+/// when the first block is not a ustar or GNU tar header.
+pub fn list_tar<R: Read>(
+    mut reader: R,
+    limit: usize,
+    format: &'static str,
+) -> io::Result<Option<Listing>> {
+    let mut entries = Vec::new();
+    let mut long_name = None;
+    let mut block = [0u8; BLOCK];
+    loop {
+        let read = read_up_to(&mut reader, &mut block)?;
+        let is_header = read == BLOCK && &block[257..262] == b"ustar";
+        if entries.is_empty() && long_name.is_none() && !is_header {
+            return Ok(None);
+        }
+        if read < BLOCK {
+            return Err(corrupt("archive cut short"));
+        }
+        if block.iter().all(|&byte| byte == 0) {
+            break;
+        }
+        if !is_header {
+            return Err(corrupt("bad tar header"));
+        }
+        if !checksum_matches(&block) {
+            return Err(corrupt("bad tar header checksum"));
+        }
+        if entries.len() == limit {
+            return Ok(Some(Listing {
+                format,
+                entries,
+                more: Some(More::Uncounted),
+            }));
+        }
+
+        let size = octal(&block[124..136]).ok_or_else(|| corrupt("bad tar entry size"))?;
+        let mut data = (&mut reader).take(size.div_ceil(BLOCK as u64) * BLOCK as u64);
+        match block[156] {
+            // A GNU long name, or pax attributes, for the next entry.
+            b'L' | b'x' => {
+                let mut extension = Vec::new();
+                (&mut data).take(size).read_to_end(&mut extension)?;
+                if block[156] == b'L' {
+                    long_name = Some(field(&extension));
+                } else if let Some(path) = pax_path(&extension) {
+                    long_name = Some(path);
+                }
+            }
+            // Global pax attributes carry no entry.
+            b'g' => {}
+            kind => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let prefix = field(&block[345..500]);
+                    let name = field(&block[..100]);
+                    if prefix.is_empty() {
+                        name
+                    } else {
+                        format!("{}/{}", prefix, name)
+                    }
+                });
+                let is_dir = kind == b'5' || name.ends_with('/');
+                entries.push(Entry {
+                    name: if is_dir && !name.ends_with('/') {
+                        name + "/"
+                    } else {
+                        name
+                    },
+                    size,
+                    is_dir,
+                });
+            }
+        }
+        io::copy(&mut data, &mut io::sink())?;
+        if data.limit() > 0 {
+            return Err(corrupt("archive cut short"));
+        }
+    }
+    Ok(Some(Listing {
+        format,
+        entries,
+        more: None,
+    }))
+}
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Fills as much of `buffer` as the reader has, returning how much that was.
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// A NUL-terminated header field.
+fn field(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn octal(bytes: &[u8]) -> Option<u64> {
+    let digits = field(bytes);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// The header checksum counts its own field as eight spaces.
+fn checksum_matches(block: &[u8; BLOCK]) -> bool {
+    let sum: u64 = block
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| {
+            if (148..156).contains(&index) {
+                b' ' as u64
+            } else {
+                byte as u64
+            }
+        })
+        .sum();
+    octal(&block[148..156]) == Some(sum)
+}
+
+/// The `path` record of pax attributes, written as `LEN path=VALUE\n`.
+fn pax_path(records: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(records)
+        .lines()
+        .find_map(|line| line.split_once(" path=").map(|(_, path)| path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const ZIP: &[u8] = include_bytes!("../tests/fixtures/archives/sample.zip");
+    const TAR: &[u8] = include_bytes!("../tests/fixtures/archives/sample.tar");
+
+    const LISTED: &str =
+        "docs/\ndocs/readme.md (10 B)\nsrc/main.rs (13 B)\ndata/table.csv (1.2 KiB)";
+
+    #[test]
+    fn lists_zip_entries_from_the_central_directory() {
+        let listing = list_zip(Cursor::new(ZIP), 10).expect("valid zip");
+        assert_eq!(
+            (listing.summary().as_str(), listing.entry_lines().as_str()),
+            ("zip, 4 entries", LISTED)
+        );
+    }
+
+    #[test]
+    fn lists_tar_entries() {
+        let listing = list_tar(TAR, 10, "tar").expect("valid tar").expect("a tar");
+        assert_eq!(
+            (listing.summary().as_str(), listing.entry_lines().as_str()),
+            ("tar, 4 entries", LISTED)
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn lists_gzipped_tar_entries() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/archives/sample.tar.gz");
+        let listing = list_file(&path, 10).expect("valid tar.gz").expect("a tar");
+        assert_eq!(
+            (listing.summary().as_str(), listing.entry_lines().as_str()),
+            ("tar.gz, 4 entries", LISTED)
+        );
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let zip = list_zip(Cursor::new(ZIP), 2).expect("valid zip");
+        assert_eq!(zip.summary(), "zip, 4 entries");
+        assert_eq!(
+            zip.entry_lines(),
+            "docs/\ndocs/readme.md (10 B)\n… 2 more entries"
+        );
+        let tar = list_tar(TAR, 1, "tar").expect("valid tar").expect("a tar");
+        assert_eq!(tar.summary(), "tar, first 1 entry");
+        assert_eq!(tar.entry_lines(), "docs/\n… more entries");
+    }
+
+    #[test]
+    fn corrupt_archives_are_errors_and_other_files_are_not_archives() {
+        assert!(list_zip(Cursor::new(&ZIP[..ZIP.len() - 30]), 10).is_err());
+        assert!(list_tar(&TAR[..700], 10, "tar").is_err());
+        let mut bad_checksum = TAR.to_vec();
+        bad_checksum[0] = b'x';
+        assert!(list_tar(&bad_checksum[..], 10, "tar").is_err());
+        assert_eq!(
+            list_tar(&b"plain text"[..], 10, "tar").expect("readable"),
+            None
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much informational output goes to stderr. Errors are always shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Quiet,
+        1 => Level::Normal,
+        _ => Level::Verbose,
+    }
+}
+
+/// Status lines such as skip notices and success messages.
+pub fn info(message: impl Display) {
+    if level() >= Level::Normal {
+        line(format_args!("{}", message));
+    }
+}
+
+/// Per-file decisions, shown only with `--verbose`.
+pub fn verbose(message: impl Display) {
+    if level() >= Level::Verbose {
+        line(format_args!("{}", message));
+    }
+}
+
+pub fn warn(message: impl Display) {
+    if level() >= Level::Normal {
+        line(format_args!("Warning: {}", message));
+    }
+}
+
+pub fn error(message: impl Display) {
+    line(format_args!("Error: {}", message));
+}
+
+/// Writes one line to stderr. Unlike `eprintln!`, a closed stderr does not
+/// panic: the message is dropped, as there is nowhere left to show it.
+fn line(message: std::fmt::Arguments) {
+    let _ = writeln!(std::io::stderr(), "{}", message);
+}
@@ -1,208 +1,2124 @@
+mod archive;
 mod args;
+mod budget;
+mod cache;
+mod casefold;
+mod check;
+mod config;
+mod content;
+mod diff;
+mod doctor;
+mod encode;
+mod eol;
+mod expand;
+mod filter;
+mod fit;
+mod format;
+mod git;
+mod html;
+mod image;
+mod interrupt;
+mod kind;
+mod launch;
+mod listing;
+mod logger;
+mod manifest;
+mod manual;
+mod marker;
 mod output;
+mod pathtree;
+mod patterns;
+mod permalink;
+mod picker;
+mod pipe;
+mod post;
+mod progress;
+mod provenance;
+mod report;
+mod restore;
+mod sample;
+mod separators;
+mod serve;
+mod snapshot;
+mod spill;
+mod stats;
+mod style;
+mod template;
+mod timestamp;
 mod walker;
 
-use anyhow::{Context, Result};
-use clap::Parser;
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches};
+use content::{FileContent, FileRecord, SkipReason};
+use copytree::order;
+use format::{Bare, BodyWrite, Format};
 use ignore::DirEntry;
-use std::collections::{BTreeMap, BTreeSet};
+use pathtree::{NodeId, PathTree};
+use patterns::Patterns;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsString;
-use std::fs;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
 use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use style::Role;
 
-fn main() -> Result<()> {
+/// Exit status when every requested file made it into the output, or when
+/// skips are tolerated.
+const EXIT_SUCCESS: u8 = 0;
+/// Exit status for hard errors: bad roots, invalid globs, failed sinks.
+const EXIT_FAILURE: u8 = 1;
+/// Exit status under `--fail-on-skip` when a file was skipped as binary,
+/// too large, or unreadable.
+const EXIT_SKIPPED: u8 = 2;
+/// Exit status under `restore --verify` when a restored file does not
+/// match the digest its manifest recorded.
+const EXIT_MISMATCH: u8 = 2;
+/// Exit status under `--check` when the `--out` file differs from the
+/// output or does not exist.
+const EXIT_CHANGED: u8 = 3;
+/// Exit status when Ctrl-C stopped the run before every file was read.
+const EXIT_INTERRUPTED: u8 = interrupt::EXIT_STATUS;
+/// Exit status when stdout's reader went away before the output was
+/// written, as a shell reports one killed by SIGPIPE.
+const EXIT_BROKEN_PIPE: u8 = 141;
+
+fn main() -> ExitCode {
     load_env_file();
-    let args = args::Args::parse();
-    let exclude_set = build_exclude_set(&args.exclude)?;
-    let entries = walker::walk_paths(&args.paths, args.no_gitignore)?;
+    let matches = args::Cli::command().get_matches();
+    let cli = args::Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    match cli.command {
+        None => copy(cli.copy, &matches),
+        Some(args::Command::Copy(args)) => {
+            let matches = matches
+                .subcommand_matches("copy")
+                .expect("copy subcommand was parsed");
+            copy(*args, matches)
+        }
+        Some(args::Command::Tree(mut tree_args)) => {
+            let matches = matches
+                .subcommand_matches("tree")
+                .expect("tree subcommand was parsed");
+            exit_status(layered_config(&tree_args.walk).and_then(|config| {
+                config::apply_tree(&config, &mut tree_args, matches);
+                separators::set_native(tree_args.walk.native_separators);
+                let matchers = resolve_roots(&mut tree_args.walk)?;
+                print_tree(
+                    &tree_args.walk,
+                    &matchers,
+                    tree_args.color,
+                    tree_args.tree_width,
+                )
+            }))
+        }
+        Some(args::Command::Stats(mut stats_args)) => {
+            let matches = matches
+                .subcommand_matches("stats")
+                .expect("stats subcommand was parsed");
+            exit_status(layered_config(&stats_args.walk).and_then(|config| {
+                config::apply_walk(&config, &mut stats_args.walk, matches);
+                separators::set_native(stats_args.walk.native_separators);
+                let matchers = resolve_roots(&mut stats_args.walk)?;
+                print_stats(&stats_args.walk, &matchers, stats_args.json)
+            }))
+        }
+        Some(args::Command::Restore(restore_args)) => match restore::restore(
+            Path::new(&restore_args.input),
+            Path::new(&restore_args.dest),
+            restore_args.force,
+            restore_args.dry_run,
+            restore_args.verify,
+        ) {
+            Ok(restore::Outcome::Restored) => ExitCode::from(EXIT_SUCCESS),
+            Ok(restore::Outcome::Mismatched) => ExitCode::from(EXIT_MISMATCH),
+            Err(err) => exit_status(Err(err)),
+        },
+        Some(args::Command::Decode(decode_args)) => {
+            exit_status(decode_output(decode_args.input.as_deref()))
+        }
+        Some(args::Command::Diff(diff_args)) => exit_status(diff_outputs(&diff_args)),
+        Some(args::Command::Completions(completions_args)) => {
+            exit_status(print_completions(completions_args.shell))
+        }
+        Some(args::Command::Config(args::ConfigCommand::Path)) => {
+            match config::user_config_path() {
+                Some(path) => exit_status(
+                    writeln!(std::io::stdout().lock(), "{}", path.display())
+                        .map_err(|err| output::stdout_error(err, "Failed to write the path")),
+                ),
+                None => {
+                    logger::error(format_args!("no config directory found for this platform"));
+                    ExitCode::from(EXIT_FAILURE)
+                }
+            }
+        }
+        Some(args::Command::Cache(args::CacheCommand::Clear(clear_args))) => {
+            exit_status(clear_cache(clear_args.dir.as_deref()))
+        }
+        Some(args::Command::Profiles) => exit_status(
+            load_config_files(&cli.copy.walk.paths).and_then(|config| print_profiles(&config)),
+        ),
+        Some(args::Command::Doctor) => {
+            match doctor::report(&doctor::run_checks(), &mut std::io::stdout().lock()) {
+                Ok(true) => ExitCode::from(EXIT_SUCCESS),
+                Ok(false) => ExitCode::from(EXIT_FAILURE),
+                Err(err) => {
+                    exit_status(Err(output::stdout_error(err, "Failed to write the checks")))
+                }
+            }
+        }
+        Some(args::Command::Man(man_args)) => {
+            exit_status(manual::generate(man_args.dir.as_deref()))
+        }
+    }
+}
+
+/// Logs a failed command and maps its outcome to an exit status. A closed
+/// stdout is not logged: its reader stopped on purpose.
+fn exit_status(result: Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::from(EXIT_SUCCESS),
+        Err(err) if err.is::<output::StdoutClosed>() => ExitCode::from(EXIT_BROKEN_PIPE),
+        Err(err) => {
+            logger::error(format_args!("{err:#}"));
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+/// Writes the completion script for `shell` to stdout. The script is built
+/// in memory first because clap_complete panics when its writer fails.
+fn print_completions(shell: clap_complete::Shell) -> Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut args::Cli::command(), "copytree", &mut script);
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&script)
+        .and_then(|()| stdout.flush())
+        .map_err(|err| output::stdout_error(err, "Failed to write the completion script"))
+}
+
+/// `copytree decode`: writes the bytes behind `--encode base64` output,
+/// read from `input` or stdin, to stdout.
+fn decode_output(input: Option<&str>) -> Result<()> {
+    let input = input.filter(|input| *input != "-");
+    let encoded = match input {
+        Some(path) => std::fs::read(path).with_context(|| format!("Failed to read {}", path))?,
+        None => {
+            let mut encoded = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut encoded)
+                .context("Failed to read standard input")?;
+            encoded
+        }
+    };
+    let decoded = encode::decode(&encoded).with_context(|| {
+        format!(
+            "{} is not valid copytree --encode base64 output",
+            input.unwrap_or("standard input")
+        )
+    })?;
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&decoded)
+        .and_then(|()| stdout.flush())
+        .map_err(|err| output::stdout_error(err, "Failed to write to stdout"))
+}
+
+/// `copytree cache clear`: deletes the cache in `dir` or the default
+/// directory.
+fn clear_cache(dir: Option<&str>) -> Result<()> {
+    let dir = match dir {
+        Some(dir) => PathBuf::from(expand::expand(dir, false)?),
+        None => cache::default_dir()
+            .ok_or_else(|| anyhow::anyhow!("no cache directory found for this platform"))?,
+    };
+    match cache::clear(&dir)? {
+        Some(size) => logger::info(format_args!(
+            "Removed the cache in {} ({})",
+            dir.display(),
+            output::human_size(size)
+        )),
+        None => logger::info(format_args!("No cache in {}", dir.display())),
+    }
+    Ok(())
+}
+
+/// `copytree diff`: lists the files added, removed, or changed between two
+/// plain-format outputs, with a unified diff of each changed body.
+fn diff_outputs(diff_args: &args::DiffArgs) -> Result<()> {
+    let old = snapshot::Snapshot::read(Path::new(&diff_args.old))?;
+    let new = snapshot::Snapshot::read(Path::new(&diff_args.new))?;
+    if let (Some(old), Some(new)) = (&old.provenance, &new.provenance) {
+        if old.args != new.args || old.version != new.version {
+            logger::warn(format_args!(
+                "the outputs were made with different settings ({} `{}` vs {} `{}`); some changes may come from that",
+                old.version,
+                old.command(),
+                new.version,
+                new.command()
+            ));
+        }
+    }
+    let changes = diff::changes(&old, &new);
+    if changes.is_empty() {
+        logger::info(format_args!(
+            "No differences between {} and {}",
+            diff_args.old, diff_args.new
+        ));
+        return Ok(());
+    }
+    let text = diff::render(&changes);
+    let mut options = output::OutputOptions::for_sinks(
+        diff_args.stdout,
+        &diff_args.out,
+        diff_args.clipboard,
+        std::io::stdout().is_terminal(),
+    );
+    options.force = diff_args.force;
+    options.mkdirs = diff_args.mkdirs;
+    let mut stream = output::OutputStream::open(&options, text.len());
+    stream.write_all(text.as_bytes())?;
+    stream.finish(output::OutputStats {
+        files: changes.len() as u64,
+        tokens: content::estimate_tokens(&text) as u64,
+        sampled: None,
+    })?;
+    Ok(())
+}
+
+/// The environment layer with, unless `--no-config`, the config files and
+/// selected profiles on top.
+fn layered_config(walk: &args::WalkArgs) -> Result<config::Config> {
+    let env_config = config::from_env()?;
+    if walk.no_config {
+        return Ok(env_config);
+    }
+    let files = load_config_files(&walk.paths)?;
+    env_config.overlay(files).with_profiles(&walk.profile)
+}
+
+/// Expands `~` and environment variables in the roots, then replaces glob
+/// patterns among them with their matches. Runs after the config is applied
+/// so its prune and exclude patterns can cut the search short, and returns
+/// those patterns compiled for the rest of the run.
+fn resolve_roots(walk: &mut args::WalkArgs) -> Result<patterns::Matchers> {
+    for path in &mut walk.paths {
+        *path = expand::expand(path, walk.strict_vars)?;
+    }
     let current_dir = std::env::current_dir()?;
+    let matchers = patterns::Matchers::new(walk, &current_dir)?;
+    walk.paths = walker::expand_globs(
+        &walk.paths,
+        walk.ignore_rules(),
+        matchers.prune.as_ref(),
+        matchers.exclude.as_ref(),
+    )?;
+    if let Some(base) = &mut walk.relative_to {
+        *base = expand::expand(base, walk.strict_vars)?;
+        let resolved = Path::new(base.as_str())
+            .canonicalize()
+            .with_context(|| format!("Cannot use --relative-to {}", base))?;
+        // Roots are made absolute so every walked path can be shown relative
+        // to the base, whichever directory copytree runs in.
+        for path in &mut walk.paths {
+            let absolute = lexical_absolute(Path::new(path.as_str()), &current_dir);
+            if !absolute.starts_with(&resolved) {
+                logger::warn(format_args!(
+                    "{} is outside --relative-to {}; its paths are shown in full",
+                    path, base
+                ));
+            }
+            *path = absolute.to_string_lossy().into_owned();
+        }
+        walk.relative_base = Some(resolved);
+    }
+    if walk.strip_prefix.is_some() && !walk.absolute_paths {
+        bail!("--strip-prefix only applies to --absolute-paths; pass it as well");
+    }
+    if walk.absolute_paths {
+        if walk.relative_to.is_some() {
+            bail!(
+                "--absolute-paths and --relative-to both set how paths are shown; pass only one \
+                 (--strip-prefix trims absolute paths)"
+            );
+        }
+        let prefix = match &mut walk.strip_prefix {
+            Some(prefix) => {
+                *prefix = expand::expand(prefix, walk.strict_vars)?;
+                Path::new(prefix.as_str())
+                    .canonicalize()
+                    .with_context(|| format!("Cannot use --strip-prefix {}", prefix))?
+            }
+            None => PathBuf::new(),
+        };
+        // A root that cannot be resolved is left for the walk to report.
+        for path in &mut walk.paths {
+            let absolute = Path::new(path.as_str())
+                .canonicalize()
+                .unwrap_or_else(|_| lexical_absolute(Path::new(path.as_str()), &current_dir));
+            *path = absolute.to_string_lossy().into_owned();
+        }
+        walk.relative_base = Some(prefix);
+    }
+    Ok(matchers)
+}
 
-    let tree_text = render_tree(&entries, &args.paths, &current_dir)?;
+/// `path` joined to `current_dir`, with `.` and `..` resolved by name so the
+/// result can be compared against a canonical base.
+fn lexical_absolute(path: &Path, current_dir: &Path) -> PathBuf {
+    let mut absolute = PathBuf::new();
+    for component in current_dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            other => absolute.push(other.as_os_str()),
+        }
+    }
+    absolute
+}
 
-    let mut output_text = tree_text;
-    output_text.push_str("\n");
+/// Expands `~` and environment variables in the `--out`, `--report-json`,
+/// `--cache`, and `--template` paths, whether they came from the command
+/// line or a config file.
+fn resolve_outputs(args: &mut args::Args) -> Result<()> {
+    let strict = args.walk.strict_vars;
+    for file_path in &mut args.out {
+        *file_path = expand::expand(file_path, strict)?;
+    }
+    if let Some(target) = &mut args.report_json {
+        *target = expand::expand(target, strict)?;
+    }
+    if let Some(Some(target)) = &mut args.manifest {
+        *target = expand::expand(target, strict)?;
+    }
+    if let Some(Some(dir)) = &mut args.cache {
+        *dir = expand::expand(dir, strict)?;
+    }
+    if let Some(file) = args.template.as_mut().filter(|spec| !spec.starts_with('@')) {
+        *file = expand::expand(file, strict)?;
+    }
+    Ok(())
+}
 
-    // Append file contents
-    for entry in entries {
-        let path = entry.path();
-        if !path.is_file() {
-            continue;
+/// `copytree copy`, also run when no subcommand is given. `matches` are the
+/// ones `args` was parsed from, so command-line values can win over config.
+/// Every way the run ends passes through here, so `--status-json` can close
+/// it with the status it exits with.
+fn copy(mut args: args::Args, matches: &clap::ArgMatches) -> ExitCode {
+    let started = std::time::Instant::now();
+    let mut run_report = report::RunReport::new();
+    let status = match prepare_copy(&mut args, matches) {
+        Ok(matchers) => copy_files(&mut args, &matchers, &mut run_report),
+        Err(err) => {
+            logger::error(format_args!("{err:#}"));
+            run_report.finish(EXIT_FAILURE, Some(&err));
+            EXIT_FAILURE
         }
+    };
+    if let Some(stream) = args.status_json {
+        let line = report::StatusLine::of(&run_report, started.elapsed());
+        if let Err(err) = report::write_status(&line, stream) {
+            logger::error(format_args!("{err:#}"));
+        }
+    }
+    ExitCode::from(status)
+}
 
-        let header = format!("--- {} ---\n", path.display());
+/// Applies the configuration to `args`, checks them, and compiles the
+/// pattern flags.
+fn prepare_copy(args: &mut args::Args, matches: &clap::ArgMatches) -> Result<patterns::Matchers> {
+    let config = layered_config(&args.walk)?;
+    config::apply(&config, args, matches);
+    logger::set_level(if args.quiet {
+        logger::Level::Quiet
+    } else if args.verbose {
+        logger::Level::Verbose
+    } else {
+        logger::Level::Normal
+    });
 
-        if exclude_set
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    resolve_outputs(args)?;
+    args::validate(args, given)?;
+    if args.reproducible {
+        args.pin_reproducible();
+    }
+    separators::set_native(args.walk.native_separators);
+    resolve_roots(&mut args.walk)
+}
+
+/// Runs `copy` with checked `args`, finishing `run_report` with the exit
+/// status it returns and writing it for `--report-json`.
+fn copy_files(
+    args: &mut args::Args,
+    matchers: &patterns::Matchers,
+    run_report: &mut report::RunReport,
+) -> u8 {
+    let check = args.check.then(|| check::Check::stage(args));
+    if args.report_json.is_some() || args.manifest.is_some() {
+        // Before the run, so the output file cannot make the tree look dirty.
+        run_report.repositories = git::repositories(&args.walk.paths);
+        run_report.sniff = !args.walk.no_sniff;
+    }
+    let interrupt = interrupt::Interrupt::install();
+    let result = run(args, matchers, &interrupt, run_report).and_then(|()| {
+        check
             .as_ref()
-            .map_or(false, |set| is_excluded(path, set, &current_dir))
-        {
-            output_text.push_str(&header);
-            output_text.push_str("<skipped: excluded by pattern>\n\n");
-            log_skipped_file(path, &current_dir);
-            continue;
+            .filter(|_| run_report.unprocessed.is_none())
+            .map(|check| check.compare(args.fix))
+            .transpose()
+    });
+    let status = run_status(args, &result, run_report);
+    run_report.finish(status, result.as_ref().err());
+    if let Some(target) = &args.report_json {
+        if let Err(err) = report::write_report(run_report, target) {
+            logger::error(format_args!("{err:#}"));
         }
+    }
+    status
+}
 
-        if args.max_file_bytes > 0 {
-            if let Ok(metadata) = fs::metadata(path) {
-                if metadata.len() as usize > args.max_file_bytes {
-                    let note = format!(
-                        "<skipped: file size {} bytes exceeds --max-file-bytes {}>\n\n",
-                        metadata.len(),
-                        args.max_file_bytes
-                    );
-                    output_text.push_str(&header);
-                    output_text.push_str(&note);
-                    log_skipped_file(path, &current_dir);
-                    continue;
-                }
+/// The exit status of a `copy` run, logging why it is not success.
+fn run_status(
+    args: &args::Args,
+    result: &Result<Option<check::Outcome>>,
+    run_report: &report::RunReport,
+) -> u8 {
+    match result {
+        Err(err) if err.is::<output::StdoutClosed>() => EXIT_BROKEN_PIPE,
+        Err(err) => {
+            logger::error(format_args!("{err:#}"));
+            EXIT_FAILURE
+        }
+        Ok(_) if run_report.unprocessed.is_some() => {
+            let unprocessed = stats::file_count(run_report.unprocessed.unwrap_or_default());
+            if args.partial_on_interrupt {
+                logger::warn(format_args!(
+                    "interrupted; {} not processed, the output holds the files read before",
+                    unprocessed
+                ));
+            } else {
+                logger::warn(format_args!(
+                    "interrupted; {} not processed and nothing written \
+                     (--partial-on-interrupt writes the files read so far)",
+                    unprocessed
+                ));
             }
+            EXIT_INTERRUPTED
+        }
+        Ok(Some(check::Outcome::OutOfDate)) => EXIT_CHANGED,
+        Ok(_) if args.fail_on_skip && run_report.unintended_skips() > 0 => {
+            logger::warn(format_args!(
+                "{} file(s) skipped; exiting with status {} (--fail-on-skip)",
+                run_report.unintended_skips(),
+                EXIT_SKIPPED
+            ));
+            EXIT_SKIPPED
         }
+        Ok(_) => EXIT_SUCCESS,
+    }
+}
+
+/// Walks, reads, and writes the output. Once `interrupt` trips, files are
+/// no longer read; the files read so far are written followed by a trailer
+/// under `--partial-on-interrupt`, and nothing is written otherwise. Either
+/// way `run_report.unprocessed` tells how many were left.
+fn run(
+    args: &args::Args,
+    matchers: &patterns::Matchers,
+    interrupt: &interrupt::Interrupt,
+    run_report: &mut report::RunReport,
+) -> Result<()> {
+    let contents_excluded = matchers.exclude_contents.as_ref();
+    // Parsed before the walk, so a broken template fails fast.
+    let template = args.template.as_deref().map(template::load).transpose()?;
+    let current_dir = std::env::current_dir()?;
+    let mut output_options =
+        output::OutputOptions::from_args(args, std::io::stdout().is_terminal());
+    output_options.summary_style = args.color.styler(std::io::stderr().is_terminal());
+    output_options.interrupt = interrupt.clone();
+    if args.open.is_some() {
+        output_options.ensure_out_file();
+    }
+    let values = output::OutPlaceholders {
+        unix_time: timestamp::unix_now(),
+        root: root_name(&args.walk.paths, &current_dir),
+    };
+    for file_path in &mut output_options.out_files {
+        *file_path = output::expand_out_path(file_path, &values, |candidate| {
+            Path::new(candidate).exists()
+        })?;
+    }
+    let manifest_target = args.manifest.as_ref().map(|target| {
+        target
+            .clone()
+            .unwrap_or_else(|| manifest::default_path(&output_options.out_files[0]))
+    });
+    let mut placements = manifest_target
+        .is_some()
+        .then(manifest::Placements::default);
+    // A progress line on the same terminal would be mixed into the output.
+    let stdout_on_terminal = output_options.to_stdout && std::io::stdout().is_terminal();
+    let mut progress = progress::Progress::for_stderr(args.quiet || stdout_on_terminal);
+    let Selection {
+        mut entries,
+        requested_paths,
+        always_include,
+        dropped_per_dir,
+        sampled,
+    } = select_entries(&args.walk, matchers, &current_dir, interrupt, |found| {
+        progress.walking(found)
+    })?;
+    let walk_interrupted = interrupt.is_tripped();
+    let mut budget = budget::Budget::new(args, &dropped_per_dir);
+    let out_paths = output::existing_out_paths(&output_options);
+    entries.retain(|entry| {
+        !out_paths
+            .iter()
+            .any(|out_path| is_same_file(entry.path(), out_path))
+    });
+    let cache_dir = args
+        .cache
+        .as_ref()
+        .and_then(|dir| dir.as_ref().map(PathBuf::from).or_else(cache::default_dir));
+    // A cache inside a walked directory is not part of the project.
+    if let Some(dir) = &cache_dir {
+        let dir = lexical_absolute(dir, &current_dir);
+        entries.retain(|entry| !lexical_absolute(entry.path(), &current_dir).starts_with(&dir));
+    }
+    if args.interactive {
+        progress.clear();
+        let chosen = pick_entries(&entries, args.last, &current_dir)?;
+        entries.retain(|entry| chosen.contains(&absolute_path(entry.path(), &current_dir)));
+    }
 
-        match fs::read_to_string(path) {
-            Ok(content) => {
-                output_text.push_str(&header);
-                output_text.push_str(&content);
-                output_text.push_str("\n\n");
+    let base = args.walk.display_base(&current_dir);
+    let display = |path: &Path| separators::display(&make_relative_path(path, base));
+    // Headers show paths as walked, unless --relative-to asks for its base.
+    let header = |path: &Path| match args.walk.relative_base {
+        Some(_) => display(path),
+        None => separators::display(path),
+    };
+    // Sections follow the tree: comparing display paths component by
+    // component visits files in the tree's depth-first, name-sorted order.
+    if !args.walk_order {
+        let mut keyed: Vec<(PathBuf, DirEntry)> = entries
+            .into_iter()
+            .map(|entry| (make_relative_path(entry.path(), base), entry))
+            .collect();
+        keyed.sort_by(|a, b| order::paths(&a.0, &b.0));
+        entries = keyed.into_iter().map(|(_, entry)| entry).collect();
+    }
+    if args.readme_first {
+        walker::hoist_readmes(&mut entries);
+    }
+    // --list stops at the walk's decision: the paths the headers would
+    // show, in their order, with no tree and no file read.
+    if args.list {
+        progress.clear();
+        if walk_interrupted {
+            run_report.unprocessed = Some(0);
+            if !args.partial_on_interrupt {
+                return Ok(());
             }
-            Err(_) => {
-                output_text.push_str(&header);
-                output_text.push_str("<skipped: binary file>\n\n");
-                log_skipped_file(path, &current_dir);
+        }
+        let list: String = entries
+            .iter()
+            .map(|entry| header(entry.path()) + "\n")
+            .collect();
+        output_options.no_final_newline = true;
+        let mut stream = output::OutputStream::open(&output_options, list.len());
+        stream.write_all(list.as_bytes())?;
+        let output_bytes = stream.bytes_written();
+        let stats = output::OutputStats {
+            files: entries.len() as u64,
+            tokens: content::estimate_tokens(&list) as u64,
+            sampled,
+        };
+        let destinations = stream.finish(stats)?;
+        run_report.record_output(output_bytes, &destinations);
+        if let Some(viewer) = args.open {
+            launch::open_output(viewer, &destinations, &mut launch::SystemLauncher);
+        }
+        return Ok(());
+    }
+
+    // One stat per file, shared by the size estimate and the size limit.
+    let files: Vec<content::WalkedFile> = entries
+        .iter()
+        .map(|entry| content::WalkedFile {
+            path: entry.path(),
+            len: target_len(entry),
+        })
+        .collect();
+    // Files after the last one read when Ctrl-C came are left unprocessed;
+    // those a cut-short walk never found are not counted.
+    let unprocessed =
+        |read: usize| (read < files.len() || walk_interrupted).then(|| (files.len() - read) as u64);
+    let tree_with = |records: Option<&[FileRecord]>| {
+        render_tree(
+            &entries,
+            TreeRoots::of(&args.walk, &requested_paths),
+            &dropped_per_dir,
+            base,
+            records,
+            args.tree_tokens,
+            |entry| skipped_before_reading(entry, &args.walk, contents_excluded, &always_include),
+        )
+    };
+    let tree = tree_with(None)?;
+    let tree_text = tree.plain();
+
+    let permalinks = args
+        .permalinks
+        .then(|| permalink::Permalinks::discover(&args.walk.paths));
+    let permalink = |path: &Path| permalinks.as_ref().and_then(|links| links.get(path));
+
+    let repeats = first_occurrences(&entries, args.walk.relative_base.as_deref(), |path| {
+        is_excluded(path, contents_excluded, &always_include)
+    });
+    let cache = cache_dir.as_ref().and_then(|dir| {
+        cache::Cache::open(dir)
+            .map_err(|err| logger::warn(format_args!("{err:#}; reading every file")))
+            .ok()
+    });
+    let filters = filter::Filters::new(
+        args.filter_cmd.as_deref(),
+        &args.filter_cmd_for,
+        std::time::Duration::from_secs(args.filter_timeout),
+    )?;
+    // Each read thread runs one filter at a time, so --read-threads bounds
+    // the commands running at once.
+    let classify = |file: &content::WalkedFile| {
+        let content = classify_file(
+            file,
+            &args.walk,
+            contents_excluded,
+            &always_include,
+            &repeats,
+            cache.as_ref(),
+        );
+        match (&filters, content) {
+            (Some(filters), FileContent::Text(text)) => {
+                let relative = make_relative_path(file.path, &current_dir);
+                FileContent::Text(filters.apply(file.path, &relative, text))
             }
+            (_, content) => content,
         }
+    };
+    let read_threads = args.read_threads.map_or_else(
+        || std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+        |threads| threads.get(),
+    );
+    let mut bytes_read = 0u64;
+    let mut note_record = |index: usize, record: &FileRecord| {
+        bytes_read += match &record.content {
+            FileContent::Text(text) => text.len() as u64,
+            FileContent::Binary(bytes) => bytes.len() as u64,
+            FileContent::Image { .. } | FileContent::Skipped(_) => 0,
+        };
+        log_file_decision(&record.path, &record.content, base, &mut progress);
+        progress.reading(index + 1, files.len(), bytes_read);
+    };
+
+    let provenance = args
+        .provenance
+        .then(|| run_provenance(args, base, sampled.map(|sampled| sampled.seed)));
+    run_report.provenance = provenance.clone();
+    let destinations = match (args.format, template) {
+        (Format::Plain, Some(template)) => {
+            let mut records = Vec::with_capacity(files.len());
+            let read = content::read_in_order(
+                &files,
+                read_threads,
+                interrupt,
+                classify,
+                |index, record| {
+                    note_record(index, &record);
+                    records.push(record);
+                    Ok::<(), std::convert::Infallible>(())
+                },
+            )?;
+            progress.clear();
+            run_report.unprocessed = unprocessed(read);
+            if run_report.unprocessed.is_some() {
+                return Ok(());
+            }
+            let dropped = fit_to_budget(
+                args,
+                &mut records,
+                |index| {
+                    let entry = &entries[index];
+                    entry.depth() == 0 || always_include.contains(entry.path())
+                },
+                display,
+            );
+            run_report.record_files(&records, display);
+            budget.record_files(&records, display);
+            budget.record_fit(&dropped);
+            let tree_text = if args.tree_tokens || !dropped.is_empty() {
+                tree_with(Some(&records))?.plain()
+            } else {
+                tree_text
+            };
+            let files = records
+                .iter()
+                .enumerate()
+                .map(|(index, record)| {
+                    template::file_value(index, &header(&record.path), &record.content)
+                })
+                .collect();
+            let text = |text: String| template::Value::Str(text);
+            let context = BTreeMap::from([
+                (
+                    "tree".to_string(),
+                    text(if args.no_tree {
+                        String::new()
+                    } else {
+                        tree_text
+                    }),
+                ),
+                (
+                    "summary".to_string(),
+                    text(format::summary(
+                        records.iter().map(|record| &record.content),
+                    )),
+                ),
+                (
+                    "provenance".to_string(),
+                    provenance
+                        .as_ref()
+                        .map_or(template::Value::None, |provenance| text(provenance.line())),
+                ),
+                ("files".to_string(), template::Value::List(files)),
+                (
+                    "budget".to_string(),
+                    Some(budget::render(&budget_groups(args, &budget)))
+                        .filter(|report| !report.is_empty())
+                        .map_or(template::Value::None, text),
+                ),
+            ]);
+            let rendered = template.render(&context).with_context(|| {
+                format!(
+                    "Failed to render template {}",
+                    args.template.as_deref().unwrap_or_default()
+                )
+            })?;
+            let mut stream = output::OutputStream::open(&output_options, rendered.len());
+            stream.write_all(rendered.as_bytes())?;
+            let output_bytes = stream.bytes_written();
+            let destinations = stream.finish(output_stats(run_report, sampled))?;
+            run_report.record_output(output_bytes, &destinations);
+            destinations
+        }
+        (Format::Plain, None)
+            if args.bare.is_some() || args.tree_tokens || args.fit_tokens.is_some() =>
+        {
+            // Nothing is written until every file is classified, since the
+            // shape of --bare output depends on how many were included,
+            // --fit-tokens weighs every file against the others, and
+            // --tree-tokens annotates the tree that comes first.
+            let mut records: Vec<FileRecord> = Vec::with_capacity(files.len());
+            let read = content::read_in_order(
+                &files,
+                read_threads,
+                interrupt,
+                classify,
+                |index, record| {
+                    note_record(index, &record);
+                    if args.bare == Some(Bare::Strict) && record.content.is_included() {
+                        if let Some(first) =
+                            records.iter().find(|first| first.content.is_included())
+                        {
+                            bail!(
+                                "--bare takes exactly one file, but {} and {} are both included; \
+                             pass --bare=loose to get the normal output instead",
+                                display(&first.path),
+                                display(&record.path)
+                            );
+                        }
+                    }
+                    records.push(record);
+                    Ok(())
+                },
+            );
+            progress.clear();
+            run_report.unprocessed = unprocessed(read?);
+            if run_report.unprocessed.is_some() && !args.partial_on_interrupt {
+                return Ok(());
+            }
+            let dropped = fit_to_budget(
+                args,
+                &mut records,
+                |index| {
+                    let entry = &entries[index];
+                    entry.depth() == 0 || always_include.contains(entry.path())
+                },
+                display,
+            );
+            run_report.record_files(&records, display);
+            budget.record_files(&records, display);
+            budget.record_fit(&dropped);
+            let mut included = records
+                .iter()
+                .filter(|record| record.content.is_included())
+                .map(|record| record.content.body());
+            let single = match (included.next(), included.next()) {
+                (Some(body), None) if args.bare.is_some() => Some(body),
+                _ => None,
+            };
+            if single.is_none() && args.bare == Some(Bare::Strict) {
+                bail!("--bare takes exactly one file, but no file was included");
+            }
+            let tree = if args.tree_tokens || !dropped.is_empty() {
+                tree_with(Some(&records))?
+            } else {
+                tree
+            };
+
+            let size_hint = match &single {
+                _ if !output_options.uses_clipboard() => 0,
+                Some(body) => body.len(),
+                None => tree.plain().len() + run_report.totals.content_bytes as usize,
+            };
+            output_options.no_final_newline = single.is_some();
+            let mut stream = output::OutputStream::open(&output_options, size_hint);
+            match single {
+                Some(body) => stream.write_body(body.as_bytes())?,
+                None => {
+                    write_plain_head(
+                        &mut stream,
+                        args,
+                        &output_options,
+                        provenance.as_ref(),
+                        &tree,
+                    )?;
+                    for record in &records {
+                        let start = stream.bytes_written();
+                        let header = header(&record.path);
+                        let head = format::write_section(
+                            &mut stream,
+                            &header,
+                            permalink(&record.path).as_ref(),
+                            &record.content,
+                            args.omit_skipped,
+                        )?;
+                        if let Some(placements) = &mut placements {
+                            placements.record(
+                                display(&record.path),
+                                header,
+                                start + head,
+                                &record.content,
+                            );
+                        }
+                    }
+                    let report = budget::render(&budget_groups(args, &budget));
+                    stream.write_all(report.as_bytes())?;
+                    if let Some(unprocessed) = run_report.unprocessed {
+                        stream.write_all(interrupt::trailer(unprocessed).as_bytes())?;
+                    }
+                }
+            }
+            let output_bytes = stream.bytes_written();
+            let destinations = stream.finish(output_stats(run_report, sampled))?;
+            run_report.record_output(output_bytes, &destinations);
+            destinations
+        }
+        (Format::Plain, None) => {
+            // Each file is read, written to the sinks, and dropped before the
+            // next one, so only the clipboard ever holds the whole output.
+            let size_hint = if output_options.uses_clipboard() {
+                tree_text.len() + estimated_content_size(&files, args.walk.max_file_bytes)
+            } else {
+                0
+            };
+            let mut stream = output::OutputStream::open(&output_options, size_hint);
+            write_plain_head(
+                &mut stream,
+                args,
+                &output_options,
+                provenance.as_ref(),
+                &tree,
+            )?;
+            let read = content::read_in_order(
+                &files,
+                read_threads,
+                interrupt,
+                classify,
+                |index, record| {
+                    // With stdout the only sink, reading on after its reader
+                    // left would only waste the work.
+                    if stream.reader_gone() {
+                        return Err(anyhow::Error::new(output::StdoutClosed));
+                    }
+                    note_record(index, &record);
+                    let start = stream.bytes_written();
+                    let header = header(&record.path);
+                    let head = format::write_section(
+                        &mut stream,
+                        &header,
+                        permalink(&record.path).as_ref(),
+                        &record.content,
+                        args.omit_skipped,
+                    )?;
+                    if let Some(placements) = &mut placements {
+                        placements.record(
+                            display(&record.path),
+                            header,
+                            start + head,
+                            &record.content,
+                        );
+                    }
+                    run_report.record_files(std::slice::from_ref(&record), display);
+                    budget.record_files(std::slice::from_ref(&record), display);
+                    Ok::<(), anyhow::Error>(())
+                },
+            )?;
+            progress.clear();
+            run_report.unprocessed = unprocessed(read);
+            if run_report.unprocessed.is_some() && !args.partial_on_interrupt {
+                stream.discard();
+                return Ok(());
+            }
+            let report = budget::render(&budget_groups(args, &budget));
+            stream.write_all(report.as_bytes())?;
+            if let Some(unprocessed) = run_report.unprocessed {
+                stream.write_all(interrupt::trailer(unprocessed).as_bytes())?;
+            }
+            let output_bytes = stream.bytes_written();
+            let destinations = stream.finish(output_stats(run_report, sampled))?;
+            run_report.record_output(output_bytes, &destinations);
+            destinations
+        }
+        (Format::Html, _) => {
+            let mut records = Vec::with_capacity(files.len());
+            let read = content::read_in_order(
+                &files,
+                read_threads,
+                interrupt,
+                classify,
+                |index, record| {
+                    note_record(index, &record);
+                    records.push(record);
+                    Ok::<(), std::convert::Infallible>(())
+                },
+            )?;
+            progress.clear();
+            run_report.unprocessed = unprocessed(read);
+            if run_report.unprocessed.is_some() {
+                return Ok(());
+            }
+            let dropped = fit_to_budget(
+                args,
+                &mut records,
+                |index| {
+                    let entry = &entries[index];
+                    entry.depth() == 0 || always_include.contains(entry.path())
+                },
+                display,
+            );
+            run_report.record_files(&records, display);
+            budget.record_files(&records, display);
+            budget.record_fit(&dropped);
+            let tree = if args.tree_tokens || !dropped.is_empty() {
+                tree_with(Some(&records))?
+            } else {
+                tree
+            };
+            let sections: Vec<html::Section> = records
+                .iter()
+                .map(|record| html::Section {
+                    anchor: display(&record.path),
+                    heading: header(&record.path),
+                    permalink: permalink(&record.path),
+                    content: &record.content,
+                })
+                .collect();
+            let title = tree.lines.first().map_or(".", |line| line.name.as_str());
+            let provenance_line = provenance.as_ref().map(|provenance| provenance.line());
+            let page = html::render(
+                title,
+                &tree,
+                &sections,
+                provenance_line.as_deref(),
+                &budget_groups(args, &budget),
+            );
+            let mut stream = output::OutputStream::open(&output_options, page.len());
+            stream.write_all(page.as_bytes())?;
+            let output_bytes = stream.bytes_written();
+            let destinations = stream.finish(output_stats(run_report, sampled))?;
+            run_report.record_output(output_bytes, &destinations);
+            destinations
+        }
+        (Format::Tar, _) => {
+            let mut records = Vec::with_capacity(files.len());
+            let read = content::read_in_order(
+                &files,
+                read_threads,
+                interrupt,
+                classify,
+                |index, record| {
+                    note_record(index, &record);
+                    records.push(record);
+                    Ok::<(), std::convert::Infallible>(())
+                },
+            )?;
+            progress.clear();
+            run_report.unprocessed = unprocessed(read);
+            if run_report.unprocessed.is_some() {
+                return Ok(());
+            }
+            let dropped = fit_to_budget(
+                args,
+                &mut records,
+                |index| {
+                    let entry = &entries[index];
+                    entry.depth() == 0 || always_include.contains(entry.path())
+                },
+                display,
+            );
+            run_report.record_files(&records, display);
+            budget.record_files(&records, display);
+            budget.record_fit(&dropped);
+            let tree_text = if args.tree_tokens || !dropped.is_empty() {
+                tree_with(Some(&records))?.plain()
+            } else {
+                tree_text
+            };
+            let archive = archive::build_tar(
+                &tree_text,
+                &records,
+                provenance.as_ref(),
+                &budget_groups(args, &budget),
+                args.reproducible.then_some(0),
+                |path| archive_entry_name(path, base),
+            )?;
+            let destinations = output::handle_archive_output(
+                &archive,
+                &output_options,
+                output_stats(run_report, sampled),
+            )?;
+            run_report.record_output(archive.len(), &destinations);
+            destinations
+        }
+    };
+    run_report.budget = budget.groups();
+    if let (Some(target), Some(placements)) = (&manifest_target, placements) {
+        let provenance = provenance
+            .unwrap_or_else(|| run_provenance(args, base, sampled.map(|sampled| sampled.seed)));
+        let manifest = manifest::Manifest::new(
+            run_report,
+            placements,
+            provenance,
+            output_options.out_files.first().cloned(),
+        );
+        manifest::write(&manifest, target)?;
+    }
+    if let Some(cache) = cache {
+        let (hits, misses) = cache.counts();
+        logger::verbose(format_args!(
+            "Cache: {} file(s) reused, {} read",
+            hits, misses
+        ));
+        if let Err(err) = cache.save() {
+            logger::warn(format_args!("{err:#}"));
+        }
+    }
+    if let Some(viewer) = args.open {
+        launch::open_output(viewer, &destinations, &mut launch::SystemLauncher);
+    }
+    Ok(())
+}
+
+/// Under `--fit-tokens`, drops whole files by `--fit-strategy` until the
+/// rest fit the budget, turning their records into skips. Files for which
+/// `pinned` holds are kept. Returns the dropped files in the order dropped.
+fn fit_to_budget<P, D>(
+    args: &args::Args,
+    records: &mut [FileRecord],
+    pinned: P,
+    display: D,
+) -> Vec<fit::Dropped>
+where
+    P: Fn(usize) -> bool,
+    D: Fn(&Path) -> String,
+{
+    let Some(budget) = args.fit_tokens else {
+        return Vec::new();
+    };
+    let candidates: Vec<fit::Candidate> = records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| {
+            let path = display(&record.path);
+            fit::Candidate {
+                category: if fit::is_test_path(&path) {
+                    fit::Category::Test
+                } else {
+                    fit::Category::Other
+                },
+                path,
+                tokens: record.content.estimated_tokens() as u64,
+                modified: std::fs::metadata(&record.path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|elapsed| elapsed.as_secs()),
+                pinned: pinned(index),
+            }
+        })
+        .collect();
+    let fit = fit::fit(&candidates, budget, args.fit_strategy);
+    if !fit.fits(budget) {
+        logger::warn(format_args!(
+            "the files that cannot be dropped need ~{} tokens, more than --fit-tokens {}",
+            output::approximate_count(fit.total),
+            budget
+        ));
+    }
+    let dropped: Vec<fit::Dropped> = fit
+        .dropped
+        .iter()
+        .map(|&(index, reason)| {
+            let tokens = candidates[index].tokens;
+            records[index].content = FileContent::Skipped(SkipReason::OverBudget { tokens });
+            fit::Dropped {
+                path: candidates[index].path.clone(),
+                tokens,
+                reason,
+            }
+        })
+        .collect();
+    if !dropped.is_empty() {
+        logger::info(format_args!(
+            "Dropped {} file{} (~{} tokens) to fit --fit-tokens {}.",
+            dropped.len(),
+            if dropped.len() == 1 { "" } else { "s" },
+            output::approximate_count(dropped.iter().map(|file| file.tokens).sum()),
+            budget
+        ));
     }
+    dropped
+}
 
-    output::handle_output(&output_text, args.stdout, args.out)?;
+/// What the limits cut, for the report closing the output; none under
+/// `--no-budget-report`.
+fn budget_groups(args: &args::Args, budget: &budget::Budget) -> Vec<budget::Group> {
+    if args.no_budget_report {
+        Vec::new()
+    } else {
+        budget.groups()
+    }
+}
+
+/// Writes what precedes the file sections of the plain format: the
+/// `--provenance` line and the tree, each left out where it does not belong.
+fn write_plain_head(
+    stream: &mut output::OutputStream,
+    args: &args::Args,
+    output_options: &output::OutputOptions,
+    provenance: Option<&provenance::Provenance>,
+    tree: &format::Tree,
+) -> std::io::Result<()> {
+    let appends_to_existing = output_options.appends_to_existing();
+    // The line belongs at the top of a document, not in the middle of one
+    // being appended to.
+    if let Some(provenance) = provenance.filter(|_| !appends_to_existing) {
+        writeln!(stream, "{}", provenance.line())?;
+    }
+    if !(args.no_tree && (!args.append || appends_to_existing)) {
+        stream.write_tree(tree)?;
+        stream.write_all(b"\n")?;
+    }
     Ok(())
 }
 
-fn load_env_file() {
-    match dotenvy::from_filename(".env") {
-        Ok(_) => {}
-        Err(dotenvy::Error::Io(io_err)) if io_err.kind() == ErrorKind::NotFound => {}
-        Err(err) => eprintln!("Warning: failed to load .env: {err}"),
+/// What `--provenance` records for this run: the normalized settings and
+/// the layers they came from. `seed` is the one `--sample` used.
+fn run_provenance(args: &args::Args, base: &Path, seed: Option<u64>) -> provenance::Provenance {
+    // Under --reproducible, config files are named relative to the base or
+    // the home directory, which differ between checkouts and users.
+    let home = dirs::home_dir().filter(|_| args.reproducible);
+    let config_label = |path: &PathBuf| {
+        if !args.reproducible {
+            return separators::display(path);
+        }
+        if let Ok(relative) = path.strip_prefix(base) {
+            return separators::display(relative);
+        }
+        match home
+            .as_deref()
+            .and_then(|home| path.strip_prefix(home).ok())
+        {
+            Some(relative) => format!("~/{}", separators::display(relative)),
+            None => separators::display(path),
+        }
+    };
+    let mut config = Vec::new();
+    if !args.walk.no_config {
+        config.extend(config::user_config_path().filter(|path| path.is_file()));
+        config.extend(config::project_config_for(&args.walk.paths).ok().flatten());
+    }
+    let env = [
+        config::ENV_EXCLUDE,
+        config::ENV_MAX_FILE_BYTES,
+        config::ENV_FORMAT,
+        config::ENV_NO_GITIGNORE,
+        config::ENV_OUT,
+        "MAX_FILE_BYTES",
+    ]
+    .into_iter()
+    .filter(|name| std::env::var_os(name).is_some())
+    .map(str::to_string)
+    .collect();
+    provenance::Provenance {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        args: provenance::normalized_args(args, seed),
+        config: config.iter().map(config_label).collect(),
+        profiles: args.walk.profile.clone(),
+        env,
+        time: (!args.no_timestamp).then(|| timestamp::UtcDateTime::now().rfc3339()),
+    }
+}
+
+/// The entries one invocation covers: the walk of the roots plus the
+/// `--always-include` matches and `always_include` files the walk did not
+/// reach.
+struct Selection {
+    entries: Vec<DirEntry>,
+    /// The roots as given, followed by the unwalked `always_include` files,
+    /// which the tree shows as extra roots.
+    requested_paths: Vec<String>,
+    always_include: AlwaysInclude,
+    /// Files dropped by `--max-per-dir`, counted per directory relative to
+    /// the current directory.
+    dropped_per_dir: BTreeMap<PathBuf, usize>,
+    /// How `--sample` narrowed the files, when it left any out.
+    sampled: Option<sample::Sampled>,
+}
+
+/// The files exempt from ignore rules and exclude patterns.
+struct AlwaysInclude {
+    /// Canonical paths of the project config's `always_include` files, which
+    /// are also exempt from the size limit.
+    files: HashSet<PathBuf>,
+    /// `--always-include` patterns, matched like excludes and also against
+    /// the absolute path for patterns from `.copytree.toml`.
+    patterns: Option<Patterns>,
+    current_dir: PathBuf,
+}
+
+impl AlwaysInclude {
+    fn new(walk: &args::WalkArgs, patterns: Option<Patterns>, current_dir: &Path) -> Self {
+        Self {
+            files: canonical_always_include(&walk.always_include_files),
+            patterns,
+            current_dir: current_dir.to_path_buf(),
+        }
+    }
+
+    /// Whether `path` bypasses exclude patterns.
+    fn contains(&self, path: &Path) -> bool {
+        self.matches_pattern(path) || self.is_listed_file(path)
+    }
+
+    /// Whether `path` is an `always_include` file, which bypasses the size
+    /// limit as well.
+    fn is_listed_file(&self, path: &Path) -> bool {
+        is_always_included(path, &self.files)
+    }
+
+    fn matches_pattern(&self, path: &Path) -> bool {
+        let Some(patterns) = &self.patterns else {
+            return false;
+        };
+        let relative = make_relative_path(path, &self.current_dir);
+        patterns.is_match(path)
+            || patterns.is_match_as_given(&absolute_path(&relative, &self.current_dir))
+    }
+}
+
+fn select_entries<F>(
+    walk: &args::WalkArgs,
+    matchers: &patterns::Matchers,
+    current_dir: &Path,
+    interrupt: &interrupt::Interrupt,
+    on_found: F,
+) -> Result<Selection>
+where
+    F: FnMut(usize),
+{
+    let prune = matchers.prune.as_ref();
+    let mut entries =
+        walker::walk_paths(&walk.paths, walk.ignore_rules(), prune, interrupt, on_found)?;
+    let always_include = AlwaysInclude::new(walk, matchers.always_include.clone(), current_dir);
+    if always_include.patterns.is_some() {
+        if let Some(prune) = prune {
+            warn_pruned_always_include(&walk.always_include, prune);
+        }
+        let walked: HashSet<PathBuf> = entries
+            .iter()
+            .map(|entry| entry.path().to_path_buf())
+            .collect();
+        entries.extend(walker::walk_unfiltered(
+            &walk.paths,
+            prune,
+            &walked,
+            interrupt,
+            |path| always_include.matches_pattern(path),
+        )?);
+    }
+    // Excluded files leave the selection before any limit counts them, so
+    // they are neither listed nor read; --exclude-contents keeps them listed.
+    if let Some(exclude) = &matchers.exclude {
+        let base = walk.display_base(current_dir);
+        entries.retain(|entry| {
+            let path = entry.path();
+            let pattern = exclude
+                .first_match(path)
+                .filter(|_| !always_include.contains(path));
+            if let Some(pattern) = pattern {
+                logger::verbose(format_args!(
+                    "Excluded {} (pattern {})",
+                    separators::display(&make_relative_path(path, base)),
+                    pattern
+                ));
+            }
+            pattern.is_none()
+        });
+    }
+    // Files named as paths or always included are kept whatever the limits.
+    let exempt = |entry: &DirEntry| entry.depth() == 0 || always_include.contains(entry.path());
+    if !walk.types.is_empty() {
+        entries.retain(|entry| exempt(entry) || is_wanted_type(entry.path(), &walk.types));
+    }
+    if let Some(reference) = &walk.changed_since {
+        let changed = git::changed_since(&walk.paths, reference)
+            .map_err(|err| anyhow::anyhow!("--changed-since: {}", err))?;
+        // Git prints real paths, so the walked ones are resolved to match;
+        // only the directory is, so a symlinked file stays itself.
+        let mut real_dirs: HashMap<PathBuf, Option<PathBuf>> = HashMap::new();
+        entries.retain(|entry| {
+            let path = entry.path();
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            let real_dir = real_dirs
+                .entry(dir.unwrap_or(Path::new(".")).to_path_buf())
+                .or_insert_with_key(|dir| std::fs::canonicalize(dir).ok());
+            exempt(entry)
+                || real_dir.as_ref().is_some_and(|real_dir| {
+                    path.file_name()
+                        .is_some_and(|name| changed.contains(&real_dir.join(name)))
+                })
+        });
+    }
+    let dropped_per_dir = match walk.max_per_dir {
+        Some(limit) => walker::limit_per_dir(&mut entries, limit.get(), exempt)
+            .into_iter()
+            .map(|(dir, count)| {
+                (
+                    make_relative_path(&dir, walk.display_base(current_dir)),
+                    count,
+                )
+            })
+            .collect(),
+        None => BTreeMap::new(),
+    };
+    let sampled = match walk.sample {
+        Some(size) => sample_entries(&mut entries, size.get(), walk, exempt)?,
+        None => None,
+    };
+    let mut requested_paths = walk.paths.clone();
+    let unwalked = unwalked_paths(
+        &entries,
+        &always_include.files,
+        current_dir,
+        walk.relative_base.is_none(),
+    );
+    if !unwalked.is_empty() {
+        entries.extend(walker::walk_paths(
+            &unwalked,
+            walker::IgnoreRules::NONE,
+            None,
+            interrupt,
+            |_| {},
+        )?);
+        requested_paths.extend(unwalked);
+    }
+    let base = walk.display_base(current_dir);
+    let relative: Vec<PathBuf> = entries
+        .iter()
+        .map(|entry| make_relative_path(entry.path(), base))
+        .collect();
+    for group in casefold::collisions(relative.iter().map(PathBuf::as_path)) {
+        logger::warn(format_args!(
+            "names differ only in case and clash on case-insensitive file systems: {}",
+            casefold::describe(&group)
+        ));
     }
+    Ok(Selection {
+        entries,
+        requested_paths,
+        always_include,
+        dropped_per_dir,
+        sampled,
+    })
 }
 
-fn build_exclude_set(patterns: &[String]) -> Result<Option<GlobSet>> {
-    if patterns.is_empty() {
+/// Narrows `entries` to `size` files picked at random, plus every `exempt`
+/// one. Returns `None` when nothing had to be left out.
+fn sample_entries<F>(
+    entries: &mut Vec<DirEntry>,
+    size: usize,
+    walk: &args::WalkArgs,
+    exempt: F,
+) -> Result<Option<sample::Sampled>>
+where
+    F: Fn(&DirEntry) -> bool,
+{
+    let candidates = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !exempt(entry));
+    let seed = walk.seed.unwrap_or_else(sample::seed_from_time);
+    let (chosen, total) = sample::reservoir(candidates, size, &mut sample::Rng::new(seed));
+    if total <= size {
         return Ok(None);
     }
+    let chosen: HashSet<usize> = chosen.into_iter().map(|(index, _)| index).collect();
+    let mut index = 0;
+    entries.retain(|entry| {
+        index += 1;
+        chosen.contains(&(index - 1)) || exempt(entry)
+    });
+    Ok(Some(sample::Sampled {
+        shown: size,
+        total,
+        seed,
+    }))
+}
+
+/// Whether `path` is of a kind asked for with `--type`. Files that cannot
+/// be read are kept, so the read reports why.
+fn is_wanted_type(path: &Path, types: &[kind::FileKind]) -> bool {
+    kind::sniff(path).map_or(true, |detected| {
+        types.iter().any(|wanted| wanted.admits(detected))
+    })
+}
 
-    let mut builder = GlobSetBuilder::new();
+/// `--prune` wins over `--always-include`; says so for patterns that could
+/// only match inside a pruned directory.
+fn warn_pruned_always_include(patterns: &[String], prune: &Patterns) {
     for pattern in patterns {
-        let glob =
-            Glob::new(pattern).with_context(|| format!("Invalid exclude glob: {}", pattern))?;
-        builder.add(glob);
+        if let Some(dir) = walker::pruned_ancestor(pattern, prune) {
+            logger::warn(format_args!(
+                "--always-include {} points inside {}, which --prune skips; it will not match there",
+                pattern,
+                dir.display()
+            ));
+        }
+    }
+}
+
+/// Decides whether a walked file contributes its contents, reading it if so.
+/// Files in `repeats` point at the first file with the same target instead.
+fn classify_file(
+    file: &content::WalkedFile,
+    walk: &args::WalkArgs,
+    contents_excluded: Option<&Patterns>,
+    always_include: &AlwaysInclude,
+    repeats: &HashMap<PathBuf, PathBuf>,
+    cache: Option<&cache::Cache>,
+) -> FileContent {
+    // The walk only returns directories it did not enter.
+    if walk.max_symlink_depth > 0 && file.path.is_dir() {
+        return FileContent::Skipped(unentered_link(file.path, walk));
+    }
+    if let Some(first) = repeats.get(file.path) {
+        return FileContent::Skipped(SkipReason::SameAs {
+            first: first.clone(),
+        });
+    }
+    let archive_entries = walk.list_archives.map(NonZeroUsize::get);
+    // The cache keys each body on the settings that change how it is read.
+    let read = |max_file_bytes: u64| {
+        let read = || content::read_file(file.path, file.len, max_file_bytes, archive_entries);
+        let content = match cache {
+            Some(cache) => {
+                let settings = format!(
+                    "max_file_bytes={} list_archives={:?}",
+                    max_file_bytes, archive_entries
+                );
+                cache.read(file.path, &settings, read)
+            }
+            None => read(),
+        };
+        match walk.include_binary {
+            Some(max_bytes) => content::include_binary(file.path, content, max_bytes),
+            None => content,
+        }
+    };
+    if always_include.is_listed_file(file.path) {
+        return read(0);
+    }
+    match contents_excluded
+        .filter(|_| !always_include.contains(file.path))
+        .and_then(|patterns| patterns.first_match(file.path))
+    {
+        Some(pattern) => FileContent::Skipped(SkipReason::Excluded {
+            pattern: pattern.to_string(),
+        }),
+        None => read(walk.max_file_bytes),
     }
+}
 
-    builder
-        .build()
-        .map(Some)
-        .with_context(|| "Failed to build exclude glob set".to_string())
+/// Whether a file's contents will be left out for a reason known from the
+/// walk alone: an `--exclude-contents` pattern or its size. Binary files are only found
+/// when read.
+fn skipped_before_reading(
+    entry: &DirEntry,
+    walk: &args::WalkArgs,
+    contents_excluded: Option<&Patterns>,
+    always_include: &AlwaysInclude,
+) -> bool {
+    skip_before_reading(entry, walk, contents_excluded, always_include).is_some()
 }
 
-fn is_excluded(path: &Path, set: &GlobSet, current_dir: &Path) -> bool {
-    if set.is_match(path) {
-        return true;
+/// The reason [`skipped_before_reading`] found, if any.
+fn skip_before_reading(
+    entry: &DirEntry,
+    walk: &args::WalkArgs,
+    contents_excluded: Option<&Patterns>,
+    always_include: &AlwaysInclude,
+) -> Option<SkipReason> {
+    if walk.max_symlink_depth > 0 && entry.path_is_symlink() && entry.path().is_dir() {
+        return Some(unentered_link(entry.path(), walk));
+    }
+    if always_include.is_listed_file(entry.path()) {
+        return None;
+    }
+    let pattern = contents_excluded
+        .filter(|_| !always_include.contains(entry.path()))
+        .and_then(|patterns| patterns.first_match(entry.path()));
+    if let Some(pattern) = pattern {
+        return Some(SkipReason::Excluded {
+            pattern: pattern.to_string(),
+        });
     }
+    let limit = walk.max_file_bytes;
+    target_len(entry)
+        .filter(|&len| limit > 0 && len > limit)
+        .map(|size| SkipReason::TooLarge { size, limit })
+}
 
-    let relative = make_relative_path(path, current_dir);
-    set.is_match(relative)
+/// Why the walk left the symlinked directory at `path` out.
+fn unentered_link(path: &Path, walk: &args::WalkArgs) -> SkipReason {
+    if walker::loops_back(path) {
+        SkipReason::SymlinkLoop {
+            target: std::fs::read_link(path).unwrap_or_default(),
+        }
+    } else {
+        SkipReason::SymlinkDepth {
+            limit: walk.max_symlink_depth,
+        }
+    }
 }
 
-fn render_tree(
-    entries: &[DirEntry],
-    requested_paths: &[String],
-    current_dir: &Path,
-) -> Result<String> {
-    let mut children: BTreeMap<PathBuf, BTreeSet<PathBuf>> = BTreeMap::new();
+/// Whether an `--exclude-contents` pattern leaves out the contents of
+/// `path`.
+fn is_excluded(
+    path: &Path,
+    contents_excluded: Option<&Patterns>,
+    always_include: &AlwaysInclude,
+) -> bool {
+    contents_excluded
+        .is_some_and(|patterns| !always_include.contains(path) && patterns.is_match(path))
+}
+
+/// The size of the file `entry` refers to; for a symlink, its target's.
+fn target_len(entry: &DirEntry) -> Option<u64> {
+    if entry.path_is_symlink() {
+        std::fs::metadata(entry.path())
+            .ok()
+            .map(|metadata| metadata.len())
+    } else {
+        entry.metadata().ok().map(|metadata| metadata.len())
+    }
+}
 
+/// Maps each file that resolves to the same target as another to the path
+/// that carries the contents, so they are emitted once: the first path
+/// that is not itself a symlink, or the first path when all of them are.
+/// Files whose contents are excluded take no part, and nothing is resolved
+/// unless some file is a symlink. Under `--relative-to`, the first paths
+/// are made relative to that `base`.
+fn first_occurrences<F>(
+    entries: &[DirEntry],
+    base: Option<&Path>,
+    is_excluded: F,
+) -> HashMap<PathBuf, PathBuf>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut repeats = HashMap::new();
+    if !entries.iter().any(DirEntry::path_is_symlink) {
+        return repeats;
+    }
+    let mut resolved = Vec::new();
+    let mut firsts: HashMap<PathBuf, &DirEntry> = HashMap::new();
     for entry in entries {
-        let relative = make_relative_path(entry.path(), &current_dir);
-        if relative.components().count() == 0 {
+        if is_excluded(entry.path()) {
             continue;
         }
+        // Broken links resolve to nothing and are reported on their own.
+        let Ok(target) = std::fs::canonicalize(entry.path()) else {
+            continue;
+        };
+        // A link that sorts before its target hands the contents back to
+        // the real file.
+        let first = firsts.entry(target.clone()).or_insert(entry);
+        if first.path_is_symlink() && !entry.path_is_symlink() {
+            *first = entry;
+        }
+        resolved.push((entry.path(), target));
+    }
+    for (path, target) in resolved {
+        let first = firsts[&target].path();
+        if first != path {
+            let first = base.map_or_else(
+                || first.to_path_buf(),
+                |base| make_relative_path(first, base),
+            );
+            repeats.insert(path.to_path_buf(), first);
+        }
+    }
+    repeats
+}
+
+/// `copytree tree`: the tree a copy would start with, on stdout.
+fn print_tree(
+    walk: &args::WalkArgs,
+    matchers: &patterns::Matchers,
+    color: style::ColorChoice,
+    tree_width: Option<usize>,
+) -> Result<()> {
+    let contents_excluded = matchers.exclude_contents.as_ref();
+    let current_dir = std::env::current_dir()?;
+    let selection = select_entries(
+        walk,
+        matchers,
+        &current_dir,
+        &interrupt::Interrupt::default(),
+        |_| {},
+    )?;
+    let tree = render_tree(
+        &selection.entries,
+        TreeRoots::of(walk, &selection.requested_paths),
+        &selection.dropped_per_dir,
+        walk.display_base(&current_dir),
+        None,
+        false,
+        |entry| skipped_before_reading(entry, walk, contents_excluded, &selection.always_include),
+    )?;
+    let stdout = std::io::stdout();
+    let styler = color.styler(stdout.is_terminal());
+    let tree = match output::tree_width(tree_width, stdout.is_terminal()) {
+        Some(width) => tree.fit_width(width),
+        None => tree,
+    };
+    let mut out = style::Painter::new(stdout.lock(), styler);
+    tree.write(&mut out)
+        .and_then(|()| out.flush())
+        .map_err(|err| output::stdout_error(err, "Failed to write the tree"))?;
+    if let Some(sampled) = selection.sampled {
+        logger::info(format_args!("Showing {}.", sampled));
+    }
+    Ok(())
+}
 
-        let mut cursor = PathBuf::new();
-        for component in relative.components() {
-            let next = cursor.join(component.as_os_str());
-            children
-                .entry(cursor.clone())
-                .or_default()
-                .insert(next.clone());
-            cursor = next;
+/// `copytree stats`: sizes by extension and top-level directory, the
+/// largest files, and what would be skipped, from the walk's metadata and,
+/// unless `--no-sniff`, each file's leading bytes for its media type.
+/// Binary files are only found when read, so they count as included.
+fn print_stats(walk: &args::WalkArgs, matchers: &patterns::Matchers, json: bool) -> Result<()> {
+    let contents_excluded = matchers.exclude_contents.as_ref();
+    let current_dir = std::env::current_dir()?;
+    let selection = select_entries(
+        walk,
+        matchers,
+        &current_dir,
+        &interrupt::Interrupt::default(),
+        |_| {},
+    )?;
+    let repeats = first_occurrences(&selection.entries, walk.relative_base.as_deref(), |path| {
+        is_excluded(path, contents_excluded, &selection.always_include)
+    });
+    let base = walk.display_base(&current_dir);
+    let entries: Vec<stats::Entry> = selection
+        .entries
+        .iter()
+        .map(|entry| {
+            let len = target_len(entry);
+            let skip = if repeats.contains_key(entry.path()) {
+                Some(SkipReason::SAME_AS_CODE)
+            } else if len.is_none() && entry.path_is_symlink() {
+                Some("broken_symlink")
+            } else {
+                skip_before_reading(entry, walk, contents_excluded, &selection.always_include)
+                    .map(|reason| reason.code())
+            };
+            stats::Entry {
+                path: make_relative_path(entry.path(), base),
+                bytes: len.unwrap_or(0),
+                skip,
+                content_type: (!walk.no_sniff)
+                    .then(|| kind::sniff_content_type(entry.path()).ok())
+                    .flatten(),
+            }
+        })
+        .collect();
+    let stats = stats::Stats::collect(&entries, selection.sampled);
+    let text = if json {
+        serde_json::to_string_pretty(&stats).context("Failed to serialize stats")? + "\n"
+    } else {
+        stats.render()
+    };
+    std::io::stdout()
+        .lock()
+        .write_all(text.as_bytes())
+        .map_err(|err| output::stdout_error(err, "Failed to write the stats"))
+}
+
+/// Lets the user narrow `entries` in the picker; the choice is remembered
+/// as absolute paths for `--last`.
+fn pick_entries(entries: &[DirEntry], last: bool, current_dir: &Path) -> Result<HashSet<PathBuf>> {
+    let cache = picker::selection_cache_path();
+    let previous = match (&cache, last) {
+        (Some(cache), true) => Some(picker::load_selection(cache)?),
+        (None, true) => bail!("--last needs a cache directory, and none was found"),
+        (_, false) => None,
+    };
+    let candidates = entries
+        .iter()
+        .map(|entry| picker::Candidate {
+            path: absolute_path(entry.path(), current_dir),
+            label: separators::display(&make_relative_path(entry.path(), current_dir)),
+            size: target_len(entry),
+        })
+        .collect();
+    let chosen = picker::pick(candidates, previous.as_ref())?;
+    if let Some(cache) = &cache {
+        if let Err(err) = picker::save_selection(cache, &chosen) {
+            logger::warn(format_args!("could not remember the selection: {err:#}"));
         }
     }
+    Ok(chosen.into_iter().collect())
+}
 
-    let (root_label, root_path) = determine_root_scope(requested_paths, &current_dir);
+/// Resolves the project's `always_include` files, warning about any that do
+/// not exist.
+fn canonical_always_include(files: &[PathBuf]) -> HashSet<PathBuf> {
+    files
+        .iter()
+        .filter_map(|file| match file.canonicalize() {
+            Ok(path) if path.is_file() => Some(path),
+            _ => {
+                logger::warn(format_args!(
+                    "always_include file {} not found",
+                    file.display()
+                ));
+                None
+            }
+        })
+        .collect()
+}
+
+fn is_always_included(path: &Path, always_include: &HashSet<PathBuf>) -> bool {
+    // Compare names first so only likely matches are canonicalized.
+    always_include
+        .iter()
+        .any(|file| file.file_name() == path.file_name())
+        && path
+            .canonicalize()
+            .is_ok_and(|resolved| always_include.contains(&resolved))
+}
+
+/// `always_include` files the walk did not reach (outside the roots or
+/// ignored), as paths relative to the current directory when `relative`
+/// and possible, otherwise absolute like the roots under `--relative-to`.
+fn unwalked_paths(
+    entries: &[DirEntry],
+    always_include: &HashSet<PathBuf>,
+    current_dir: &Path,
+    relative: bool,
+) -> Vec<String> {
+    let base = current_dir
+        .canonicalize()
+        .unwrap_or_else(|_| current_dir.to_path_buf());
+    let mut missing: Vec<String> = always_include
+        .iter()
+        .filter(|file| {
+            !entries.iter().any(|entry| {
+                entry.file_name() == file.file_name().unwrap_or_default()
+                    && is_same_file(entry.path(), file)
+            })
+        })
+        .map(|file| {
+            if relative {
+                separators::display(&make_relative_path(file, &base))
+            } else {
+                file.to_string_lossy().into_owned()
+            }
+        })
+        .collect();
+    missing.sort();
+    missing
+}
 
-    if children.is_empty() {
-        return Ok(format!("{}\n", root_label));
+fn absolute_path(path: &Path, current_dir: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        current_dir.join(path.strip_prefix(".").unwrap_or(path))
     }
+}
 
-    let mut sorted_children: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
-    for (parent, set) in children {
-        let nodes: Vec<PathBuf> = set.into_iter().collect();
-        sorted_children.insert(parent, nodes);
+fn output_stats(
+    run_report: &report::RunReport,
+    sampled: Option<sample::Sampled>,
+) -> output::OutputStats {
+    output::OutputStats {
+        files: run_report.totals.included_files,
+        tokens: run_report.totals.estimated_tokens,
+        sampled,
     }
+}
 
-    let mut lines = Vec::new();
-    lines.push(root_label);
+/// Rough upper bound on the bytes of file content that will be emitted.
+fn estimated_content_size(files: &[content::WalkedFile], max_file_bytes: u64) -> usize {
+    files
+        .iter()
+        .filter_map(|file| file.len)
+        .filter(|&len| max_file_bytes == 0 || len <= max_file_bytes)
+        .map(|len| len as usize)
+        .sum()
+}
 
-    let mut rendered = false;
+/// The user config with the roots' project config layered on top; profiles
+/// and command-line flags are applied by the caller.
+fn load_config_files(paths: &[String]) -> Result<config::Config> {
+    let mut merged = config::Config::default();
+    if let Some(path) = config::user_config_path() {
+        if let Some(user_config) = config::load_user(&path)? {
+            merged = merged.overlay(user_config);
+        }
+    }
+    if let Some(path) = config::project_config_for(paths)? {
+        if let Some(project_config) = config::load_project(&path)? {
+            merged = merged.overlay(project_config);
+        }
+    }
+    Ok(merged)
+}
+
+fn print_profiles(config: &config::Config) -> Result<()> {
+    let Some(profiles) = config
+        .profile
+        .as_ref()
+        .filter(|profiles| !profiles.is_empty())
+    else {
+        logger::info(format_args!("No profiles are defined."));
+        return Ok(());
+    };
+    let width = profiles.keys().map(String::len).max().unwrap_or(0);
+    let mut out = std::io::stdout().lock();
+    for (name, profile) in profiles {
+        writeln!(out, "{:width$}  {}", name, profile.summary(), width = width)
+            .map_err(|err| output::stdout_error(err, "Failed to write the profiles"))?;
+    }
+    Ok(())
+}
 
-    if let Some(ref root_node) = root_path {
-        if let Some(root_children) = sorted_children.get(root_node) {
-            for (index, child) in root_children.iter().enumerate() {
-                let is_last = index == root_children.len() - 1;
-                render_tree_node(child, "", is_last, &sorted_children, &mut lines);
+fn load_env_file() {
+    match dotenvy::from_filename(".env") {
+        Ok(_) => {}
+        Err(dotenvy::Error::Io(io_err)) if io_err.kind() == ErrorKind::NotFound => {}
+        Err(err) => logger::warn(format_args!("failed to load .env: {err}")),
+    }
+}
+
+fn is_same_file(path: &Path, canonical: &Path) -> bool {
+    path.canonicalize()
+        .map(|resolved| resolved == canonical)
+        .unwrap_or(false)
+}
+
+/// Renders the walked files as a tree, or under `--absolute-paths` one tree
+/// per root. Directories, symlinks, and files for which `is_skipped` holds
+/// carry a style role; the text itself is plain, with symlinks written as
+/// `name -> target`.
+/// Directories in `dropped_per_dir` end with a note of how many files
+/// `--max-per-dir` left out.
+fn render_tree<F>(
+    entries: &[DirEntry],
+    roots: TreeRoots,
+    dropped_per_dir: &BTreeMap<PathBuf, usize>,
+    current_dir: &Path,
+    records: Option<&[FileRecord]>,
+    tree_tokens: bool,
+    is_skipped: F,
+) -> Result<format::Tree>
+where
+    F: Fn(&DirEntry) -> bool,
+{
+    let build = |within: &dyn Fn(&Path) -> bool| {
+        let mut paths = PathTree::default();
+        let mut relatives = Vec::new();
+        for entry in entries {
+            let relative = make_relative_path(entry.path(), current_dir);
+            if relative.components().next().is_none() || !within(&relative) {
+                continue;
+            }
+            let id = paths.insert(&relative);
+            relatives.push(relative);
+            if entry.path_is_symlink() {
+                if let Ok(target) = std::fs::read_link(entry.path()) {
+                    paths.node_mut(id).link_target = Some(target);
+                }
+                paths.node_mut(id).role = Some(Role::Symlink);
+            } else if is_skipped(entry) {
+                paths.node_mut(id).role = Some(Role::Skipped);
+            }
+        }
+        for (dir, &count) in dropped_per_dir {
+            if let Some(id) = paths.find(dir) {
+                paths.node_mut(id).dropped = Some(count);
             }
-            rendered = true;
         }
-    }
 
-    if !rendered {
-        if let Some(root_children) = sorted_children.get(&PathBuf::new()) {
-            for (index, child) in root_children.iter().enumerate() {
-                let is_last = index == root_children.len() - 1;
-                render_tree_node(child, "", is_last, &sorted_children, &mut lines);
+        // With `records`, files dropped by --fit-tokens are marked, and with
+        // `tree_tokens` every node is annotated with the tokens of the files
+        // under it, directories holding the sum of their contents.
+        if let Some(records) = records {
+            let mut tokens: HashMap<NodeId, u64> = HashMap::new();
+            for record in records {
+                let relative = make_relative_path(&record.path, current_dir);
+                if !within(&relative) {
+                    continue;
+                }
+                if let FileContent::Skipped(SkipReason::OverBudget { .. }) = record.content {
+                    if let Some(id) = paths.find(&relative) {
+                        let node = paths.node_mut(id);
+                        node.role = Some(Role::Skipped);
+                        node.note = Some("(dropped)".to_string());
+                    }
+                }
+                let count = record.content.estimated_tokens() as u64;
+                for id in paths.lineage(&relative) {
+                    *tokens.entry(id).or_default() += count;
+                }
+            }
+            if tree_tokens {
+                for (id, count) in tokens {
+                    paths.node_mut(id).note.get_or_insert_with(|| {
+                        format!("(~{} tok)", output::approximate_count(count))
+                    });
+                }
             }
         }
-    }
 
-    Ok(lines.join("\n") + "\n")
+        for group in casefold::collisions(relatives.iter().map(PathBuf::as_path)) {
+            for path in group {
+                if let Some(id) = paths.find(&path) {
+                    let note = &mut paths.node_mut(id).note;
+                    *note = Some(match note.take() {
+                        Some(note) => format!("{} [case-collision]", note),
+                        None => "[case-collision]".to_string(),
+                    });
+                }
+            }
+        }
+        paths
+    };
+
+    match roots {
+        TreeRoots::Common(requested_paths) => {
+            let paths = build(&|_| true);
+            let (root_label, root_path) = determine_root_scope(requested_paths, current_dir);
+            let root = root_path
+                .and_then(|root_path| paths.find(&root_path))
+                .filter(|&root| paths.is_dir(root))
+                .unwrap_or(pathtree::ROOT);
+            Ok(paths.render(root_label, root))
+        }
+        TreeRoots::PerRoot(requested_paths) => {
+            let mut tree = format::Tree::default();
+            for dir in root_dirs(requested_paths, current_dir) {
+                // Each root gets a tree of its own, so a root shown relative
+                // to --strip-prefix holds none of the paths shown whole.
+                let paths = build(&|path| is_within(path, &dir));
+                let root = paths.find(&dir).unwrap_or(pathtree::ROOT);
+                let label = match separators::display(&dir) {
+                    label if label.is_empty() => ".".to_string(),
+                    label => label,
+                };
+                tree.lines.extend(paths.render(label, root).lines);
+            }
+            Ok(tree)
+        }
+    }
 }
 
-fn render_tree_node(
-    node: &PathBuf,
-    prefix: &str,
-    is_last: bool,
-    children: &BTreeMap<PathBuf, Vec<PathBuf>>,
-    lines: &mut Vec<String>,
-) {
-    let connector = if is_last { "└─ " } else { "├─ " };
-    let name = display_name(node);
-    lines.push(format!("{}{}{}", prefix, connector, name));
+/// What the tree of a run is drawn from, out of the roots as requested.
+#[derive(Clone, Copy)]
+enum TreeRoots<'a> {
+    /// One tree below the directory the roots share.
+    Common(&'a [String]),
+    /// `--absolute-paths`: one tree per root, headed by its path.
+    PerRoot(&'a [String]),
+}
 
-    if let Some(child_nodes) = children.get(node) {
-        if child_nodes.is_empty() {
-            return;
-        }
-        let next_prefix = format!("{}{}", prefix, if is_last { "   " } else { "│  " });
-        for (index, child) in child_nodes.iter().enumerate() {
-            let last = index == child_nodes.len() - 1;
-            render_tree_node(child, &next_prefix, last, children, lines);
+impl<'a> TreeRoots<'a> {
+    fn of(walk: &args::WalkArgs, requested_paths: &'a [String]) -> Self {
+        if walk.absolute_paths {
+            Self::PerRoot(requested_paths)
+        } else {
+            Self::Common(requested_paths)
         }
     }
 }
 
-fn display_name(path: &Path) -> String {
-    path.file_name()
-        .map(|name| name.to_string_lossy().into_owned())
-        .filter(|name| !name.is_empty())
-        .unwrap_or_else(|| path.to_string_lossy().into_owned())
+/// The directories heading the per-root trees, as displayed and in path
+/// order: each root, or the directory of a root that is a file. A root
+/// inside another one is drawn as part of it.
+fn root_dirs(requested_paths: &[String], base: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = requested_paths
+        .iter()
+        .map(|root| {
+            let relative = make_relative_path(Path::new(root), base);
+            if Path::new(root).is_dir() {
+                relative
+            } else {
+                relative.parent().map(Path::to_path_buf).unwrap_or_default()
+            }
+        })
+        .collect();
+    dirs.sort_by(|a, b| order::paths(a, b));
+    dirs.dedup();
+    let nested = |dir: &PathBuf| {
+        dirs.iter()
+            .any(|other| other != dir && is_within(dir, other))
+    };
+    dirs.iter().filter(|dir| !nested(dir)).cloned().collect()
+}
+
+/// Whether the displayed `path` lies in the displayed directory `dir`; an
+/// empty `dir`, the --strip-prefix itself, holds only the trimmed paths.
+fn is_within(path: &Path, dir: &Path) -> bool {
+    path.is_absolute() == dir.is_absolute() && path.starts_with(dir)
 }
 
 fn make_relative_path(path: &Path, current_dir: &Path) -> PathBuf {
@@ -279,7 +2195,7 @@ fn determine_root_scope(paths: &[String], current_dir: &Path) -> (String, Option
         for component in prefix_components {
             root_path.push(component);
         }
-        let label = root_path.to_string_lossy().into_owned();
+        let label = separators::display(&root_path);
         if label.is_empty() {
             (".".to_string(), None)
         } else {
@@ -288,9 +2204,59 @@ fn determine_root_scope(paths: &[String], current_dir: &Path) -> (String, Option
     }
 }
 
-fn log_skipped_file(path: &Path, current_dir: &Path) {
-    let relative = make_relative_path(path, current_dir);
-    eprintln!("Skipped {}", relative.display());
+/// Final component of the common root, used for the `{root}` placeholder.
+fn root_name(paths: &[String], current_dir: &Path) -> String {
+    let (_, root_path) = determine_root_scope(paths, current_dir);
+    root_path
+        .as_deref()
+        .unwrap_or(current_dir)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_string())
+}
+
+/// Path of a file inside the tar archive: relative to the current directory,
+/// with any root or prefix components dropped so entries never escape.
+fn archive_entry_name(path: &Path, current_dir: &Path) -> String {
+    make_relative_path(path, current_dir)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn log_file_decision(
+    path: &Path,
+    content: &FileContent,
+    current_dir: &Path,
+    progress: &mut progress::Progress,
+) {
+    if logger::level() >= logger::Level::Verbose || matches!(content, FileContent::Skipped(_)) {
+        progress.clear();
+    }
+    let relative = separators::display(&make_relative_path(path, current_dir));
+    match content {
+        FileContent::Text(text) => {
+            logger::verbose(format_args!("Included {} ({} bytes)", relative, text.len()))
+        }
+        FileContent::Binary(bytes) => logger::verbose(format_args!(
+            "Included {} (binary, base64, {} bytes)",
+            relative,
+            bytes.len()
+        )),
+        FileContent::Image { info, size } => logger::verbose(format_args!(
+            "Described {} (image: {})",
+            relative,
+            info.summary(*size)
+        )),
+        FileContent::Skipped(reason) if logger::level() >= logger::Level::Verbose => {
+            logger::verbose(format_args!("Skipped {} ({})", relative, reason.describe()))
+        }
+        FileContent::Skipped(_) => logger::info(format_args!("Skipped {}", relative)),
+    }
 }
 
 #[cfg(test)]
@@ -331,32 +2297,176 @@ mod tests {
     }
 
     #[test]
-    fn exclude_matches_relative_path() {
-        let pattern = vec!["src/*".to_string()];
-        let set = build_exclude_set(&pattern).expect("exclude set");
-        assert!(set.is_some());
+    fn archive_entry_names_are_relative_and_contained() {
         let current_dir = Path::new("/project");
-        let path = Path::new("/project/src/main.rs");
-        assert!(is_excluded(path, set.as_ref().unwrap(), current_dir));
+        assert_eq!(
+            archive_entry_name(Path::new("/project/src/main.rs"), current_dir),
+            "src/main.rs"
+        );
+        assert_eq!(
+            archive_entry_name(Path::new("./src/main.rs"), current_dir),
+            "src/main.rs"
+        );
+        assert_eq!(
+            archive_entry_name(Path::new("/elsewhere/../lib.rs"), current_dir),
+            "elsewhere/lib.rs"
+        );
     }
 
     #[test]
-    fn exclude_matches_with_leading_dot() {
-        let pattern = vec!["src/*".to_string()];
-        let set = build_exclude_set(&pattern).expect("exclude set");
-        assert!(set.is_some());
-        let current_dir = Path::new("/project");
-        let path = Path::new("./src/main.rs");
-        assert!(is_excluded(path, set.as_ref().unwrap(), current_dir));
+    fn an_interrupted_run_writes_nothing_unless_partial_output_is_asked_for() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = temp.path();
+        let source = root.join("src");
+        std::fs::create_dir_all(&source).expect("create fixture");
+        std::fs::write(source.join("main.rs"), "fn main() {}\n").expect("write fixture file");
+        let out = root.join("ctx.txt");
+        std::fs::write(&out, "earlier output\n").expect("write earlier output");
+
+        let interrupt = interrupt::Interrupt::default();
+        interrupt.trip();
+        let run_with = |extra: &[&str]| {
+            use clap::Parser;
+
+            let mut argv = vec![
+                "copytree",
+                source.to_str().expect("utf-8 path"),
+                "--out",
+                out.to_str().expect("utf-8 path"),
+                "--force",
+                "--quiet",
+            ];
+            argv.extend_from_slice(extra);
+            let args = args::Cli::parse_from(argv).copy;
+            let matchers = patterns::Matchers::new(&args.walk, root).expect("no patterns");
+            let mut run_report = report::RunReport::new();
+            let result = run(&args, &matchers, &interrupt, &mut run_report).map(|()| None);
+            (run_status(&args, &result, &run_report), run_report)
+        };
+
+        let (status, run_report) = run_with(&[]);
+        assert_eq!(status, EXIT_INTERRUPTED);
+        assert_eq!(run_report.unprocessed, Some(0));
+        assert_eq!(
+            std::fs::read_to_string(&out).expect("output kept"),
+            "earlier output\n"
+        );
+        let left: Vec<_> = std::fs::read_dir(root)
+            .expect("list fixture")
+            .map(|entry| entry.expect("entry").file_name())
+            .collect();
+        assert_eq!(left.len(), 2, "temp files left behind: {left:?}");
+
+        let (status, run_report) = run_with(&["--partial-on-interrupt"]);
+        assert_eq!(status, EXIT_INTERRUPTED);
+        assert_eq!(run_report.unprocessed, Some(0));
+        let written = std::fs::read_to_string(&out).expect("partial output written");
+        assert!(
+            written.ends_with("\n<interrupted: 0 files not processed>\n"),
+            "{written}"
+        );
+    }
+
+    /// Tracks live heap bytes per thread, so a test can measure its own peak
+    /// without interference from tests running in parallel.
+    struct CountingAllocator;
+
+    thread_local! {
+        static LIVE_BYTES: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+        static PEAK_BYTES: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+    }
+
+    fn track_allocation(delta: isize) {
+        let _ = LIVE_BYTES.try_with(|live| {
+            live.set(live.get() + delta);
+            let _ = PEAK_BYTES.try_with(|peak| peak.set(peak.get().max(live.get())));
+        });
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let ptr = std::alloc::System.alloc(layout);
+            if !ptr.is_null() {
+                track_allocation(layout.size() as isize);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout);
+            track_allocation(-(layout.size() as isize));
+        }
+
+        unsafe fn realloc(
+            &self,
+            ptr: *mut u8,
+            layout: std::alloc::Layout,
+            new_size: usize,
+        ) -> *mut u8 {
+            let new_ptr = std::alloc::System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                track_allocation(new_size as isize - layout.size() as isize);
+            }
+            new_ptr
+        }
     }
 
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
     #[test]
-    fn exclude_matches_plain_relative_path() {
-        let pattern = vec!["src/*".to_string()];
-        let set = build_exclude_set(&pattern).expect("exclude set");
-        assert!(set.is_some());
-        let current_dir = Path::new("/project");
-        let path = Path::new("src/main.rs");
-        assert!(is_excluded(path, set.as_ref().unwrap(), current_dir));
+    fn large_out_file_run_streams_with_flat_memory() {
+        use clap::Parser;
+
+        const FILE_COUNT: usize = 100;
+        const FILE_BYTES: usize = 2 * 1024 * 1024;
+
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = temp.path();
+        let source = root.join("src");
+        std::fs::create_dir_all(&source).expect("create fixture");
+        let body = "0123456789abcdef\n".repeat(FILE_BYTES / 17);
+        for index in 0..FILE_COUNT {
+            std::fs::write(source.join(format!("file{:03}.txt", index)), &body)
+                .expect("write fixture file");
+        }
+        drop(body);
+        let out = root.join("ctx.txt");
+
+        let args = args::Cli::parse_from([
+            "copytree",
+            source.to_str().expect("utf-8 path"),
+            "--out",
+            out.to_str().expect("utf-8 path"),
+            "--max-file-bytes",
+            "0",
+            // Allocations are counted per thread, so read on this one.
+            "--read-threads",
+            "1",
+            "--quiet",
+        ])
+        .copy;
+        let matchers = patterns::Matchers::new(&args.walk, root).expect("no patterns");
+        let mut run_report = report::RunReport::new();
+        let baseline = LIVE_BYTES.with(|live| live.get());
+        PEAK_BYTES.with(|peak| peak.set(baseline));
+        run(
+            &args,
+            &matchers,
+            &interrupt::Interrupt::default(),
+            &mut run_report,
+        )
+        .expect("run succeeds");
+        let peak = PEAK_BYTES.with(|peak| peak.get()) - baseline;
+
+        let written = std::fs::metadata(&out).expect("output written").len() as usize;
+        assert!(written > FILE_COUNT * (FILE_BYTES - 17));
+        // A buffered run would need at least the whole output in memory.
+        assert!(
+            peak < 4 * FILE_BYTES as isize,
+            "peak allocation {} bytes for {} bytes of output",
+            peak,
+            written
+        );
     }
 }
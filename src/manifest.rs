@@ -0,0 +1,225 @@
+use crate::content::FileContent;
+use crate::git::RepoState;
+use crate::provenance::Provenance;
+use crate::report::{IncludedFile, RunReport, SkippedFile, Totals};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+
+/// Bumped whenever a field is renamed or removed, or its values change
+/// meaning.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The `encoding` of a range holding an image's stanza instead of the
+/// image.
+pub const IMAGE_ENCODING: &str = "image";
+
+/// `--manifest`: the files of a run and where each one's content sits in
+/// the output, written next to it as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub schema_version: u32,
+    /// The `--out` file the ranges point into; absent when the output only
+    /// went to other sinks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub provenance: Provenance,
+    /// The git repositories the roots are in; empty outside any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<RepoState>,
+    pub included: Vec<PlacedFile>,
+    pub skipped: Vec<SkippedFile>,
+    pub totals: Totals,
+}
+
+/// An included file and the bytes of the output that hold its content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlacedFile {
+    #[serde(flatten)]
+    pub file: IncludedFile,
+    /// The path as the section's `--- path ---` header writes it. `path` is
+    /// the one `--report-json` shows, relative to the display base, so the
+    /// two differ for a root such as `.`: `README.md` is headed
+    /// `./README.md`.
+    #[serde(default)]
+    pub header: String,
+    /// Of the bytes in the range: the text after any `--filter-cmd`, a
+    /// binary file's base64 lines, or an image's stanza.
+    pub sha256: String,
+    /// Where the content starts in the output, in bytes.
+    pub offset: u64,
+    /// How many bytes of the output it takes.
+    pub length: u64,
+    /// `base64` when the range holds the file's bytes encoded, `image` when
+    /// it holds the stanza describing an image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+/// Where each included file's content landed, noted as the sections are
+/// written.
+#[derive(Debug, Default)]
+pub struct Placements {
+    by_path: HashMap<String, Placement>,
+}
+
+#[derive(Debug)]
+struct Placement {
+    header: String,
+    sha256: String,
+    offset: u64,
+    length: u64,
+    encoding: Option<String>,
+}
+
+impl Placements {
+    /// Notes that the body of `content`, the file displayed as `path` and
+    /// headed `header`, starts at `offset`. Skipped files hold no content
+    /// and are not noted.
+    pub fn record(&mut self, path: String, header: String, offset: usize, content: &FileContent) {
+        let encoding = match content {
+            FileContent::Text(_) => None,
+            FileContent::Binary(_) => Some("base64".to_string()),
+            FileContent::Image { .. } => Some(IMAGE_ENCODING.to_string()),
+            FileContent::Skipped(_) => return,
+        };
+        let body = content.body();
+        self.by_path.insert(
+            path,
+            Placement {
+                header,
+                sha256: hex_digest(body.as_bytes()),
+                offset: offset as u64,
+                length: body.len() as u64,
+                encoding,
+            },
+        );
+    }
+}
+
+impl Manifest {
+    /// The manifest of a run from its report, which names the same files,
+    /// and the placements noted while its output was written.
+    pub fn new(
+        report: &RunReport,
+        mut placements: Placements,
+        provenance: Provenance,
+        output: Option<String>,
+    ) -> Self {
+        let included = report
+            .included
+            .iter()
+            .filter_map(|file| {
+                let placement = placements.by_path.remove(&file.path)?;
+                Some(PlacedFile {
+                    file: file.clone(),
+                    header: placement.header,
+                    sha256: placement.sha256,
+                    offset: placement.offset,
+                    length: placement.length,
+                    encoding: placement.encoding,
+                })
+            })
+            .collect();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            output,
+            provenance,
+            repositories: report.repositories.clone(),
+            included,
+            skipped: report.skipped.clone(),
+            totals: report.totals.clone(),
+        }
+    }
+}
+
+/// The SHA-256 digest of `bytes` as 64 lowercase hex digits, as
+/// `sha256sum` prints it.
+pub fn hex_digest(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Where `--manifest` without a path writes: next to the `--out` file,
+/// e.g. `ctx.txt.manifest.json`.
+pub fn default_path(out_file: &str) -> String {
+    format!("{}.manifest.json", out_file)
+}
+
+/// Writes the manifest as pretty JSON to `target`.
+pub fn write(manifest: &Manifest, target: &str) -> Result<()> {
+    let json = serde_json::to_string_pretty(manifest).context("Failed to serialize manifest")?;
+    fs::write(target, json + "\n").with_context(|| format!("Failed to write manifest: {}", target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::{FileRecord, SkipReason};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn included_files_carry_their_range_and_hash() {
+        let records = vec![
+            FileRecord {
+                path: PathBuf::from("src/main.rs"),
+                content: FileContent::Text("fn main() {}\n".to_string()),
+            },
+            FileRecord {
+                path: PathBuf::from("logo.bin"),
+                content: FileContent::Binary(b"abc".to_vec()),
+            },
+            FileRecord {
+                path: PathBuf::from("secret.env"),
+                content: FileContent::Skipped(SkipReason::Permission),
+            },
+        ];
+        let mut report = RunReport::new();
+        let display = |path: &Path| path.to_string_lossy().into_owned();
+        report.record_files(&records, display);
+        let mut placements = Placements::default();
+        for (offset, record) in [40, 80, 120].into_iter().zip(&records) {
+            let header = format!("./{}", display(&record.path));
+            placements.record(display(&record.path), header, offset, &record.content);
+        }
+        let provenance = Provenance {
+            version: "0.1.0".to_string(),
+            args: vec!["src".to_string()],
+            config: Vec::new(),
+            profiles: Vec::new(),
+            env: Vec::new(),
+            time: None,
+        };
+
+        let manifest = Manifest::new(&report, placements, provenance, Some("ctx.txt".to_string()));
+        let placed: Vec<(&str, u64, u64, Option<&str>)> = manifest
+            .included
+            .iter()
+            .map(|placed| {
+                (
+                    placed.file.path.as_str(),
+                    placed.offset,
+                    placed.length,
+                    placed.encoding.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            placed,
+            [
+                ("src/main.rs", 40, 13, None),
+                ("logo.bin", 80, 5, Some("base64")),
+            ]
+        );
+        assert_eq!(manifest.included[0].header, "./src/main.rs");
+        // Of the base64 lines in the range, `YWJj\n`, not of `abc`.
+        assert_eq!(manifest.included[1].sha256, hex_digest(b"YWJj\n"));
+        assert_eq!(manifest.skipped.len(), 1);
+
+        let json = serde_json::to_value(&manifest).expect("serializes");
+        assert_eq!(json["included"][0]["bytes"], 13);
+        assert_eq!(json["included"][0]["offset"], 40);
+        assert_eq!(json["output"], "ctx.txt");
+    }
+}
@@ -0,0 +1,264 @@
+use crate::args::Cli;
+use anyhow::{Context, Result};
+use clap::CommandFactory;
+use clap_mangen::roff::{bold, italic, roman, Roff};
+use clap_mangen::Man;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A command line shown in the EXAMPLES section.
+pub struct Example {
+    /// Full argument vector, starting with `copytree`.
+    pub args: &'static [&'static str],
+    pub description: &'static str,
+}
+
+/// The documented workflows; each one must parse with the real CLI.
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        args: &["copytree"],
+        description: "Copy the tree and contents of the current directory to the clipboard.",
+    },
+    Example {
+        args: &["copytree", "src", "tests"],
+        description: "Copy two directories under one tree.",
+    },
+    Example {
+        args: &[
+            "copytree",
+            ".",
+            "-x",
+            "target/**",
+            "*.lock",
+            "--out",
+            "ctx.txt",
+        ],
+        description: "Leave build output and lock files out of the contents and write to a file.",
+    },
+    Example {
+        args: &[
+            "copytree",
+            "--mkdirs",
+            "--out",
+            "snapshots/{root}-{date}-{n}.txt",
+        ],
+        description: "Keep dated snapshots in a directory that is created on demand.",
+    },
+    Example {
+        args: &["copytree", "src", "--stdout"],
+        description: "Print to standard output instead of the clipboard.",
+    },
+    Example {
+        args: &["copytree", "--out", "ctx.txt", "--clipboard"],
+        description: "Write a file and fill the clipboard from the same run.",
+    },
+    Example {
+        args: &["copytree", "--format", "tar", "--out", "snapshot.tar.gz"],
+        description: "Archive the included files as a compressed tarball.",
+    },
+    Example {
+        args: &["copytree", "--profile", "api"],
+        description: "Apply the [profile.api] table from the configuration files.",
+    },
+    Example {
+        args: &["copytree", "tree", "src"],
+        description: "Print only the directory tree of src.",
+    },
+    Example {
+        args: &["copytree", "stats", "-x", "target/**"],
+        description: "Count the files, bytes, and tokens a copy would include, without copying.",
+    },
+    Example {
+        args: &["copytree", "doctor"],
+        description: "Diagnose why nothing reaches the clipboard.",
+    },
+    Example {
+        args: &["copytree", "restore", "ctx.txt", "--dest", "restored"],
+        description: "Recreate the files recorded in a plain-format output file.",
+    },
+    Example {
+        args: &["copytree", "--encode", "base64", "--out", "ctx.b64"],
+        description: "Wrap the output in base64 for a channel that mangles text; \
+                      copytree decode ctx.b64 turns it back.",
+    },
+];
+
+/// Exit statuses and what they mean, in the order the page lists them.
+const EXIT_STATUSES: &[(u8, &str)] = &[
+    (
+        crate::EXIT_SUCCESS,
+        "Success. Skipped files do not change this unless --fail-on-skip is set.",
+    ),
+    (
+        crate::EXIT_FAILURE,
+        "Hard error: a root that does not exist, an invalid glob or configuration, \
+         or an output that could not be written.",
+    ),
+    (
+        crate::EXIT_SKIPPED,
+        "With --fail-on-skip, at least one file was skipped as binary, too large, \
+         or unreadable. The output is still written.",
+    ),
+];
+
+/// Writes the manual to stdout, or one page per command into `dir`.
+pub fn generate(dir: Option<&Path>) -> Result<()> {
+    let Some(dir) = dir else {
+        let mut page = Vec::new();
+        render(&mut page)?;
+        let mut stdout = io::stdout().lock();
+        return stdout
+            .write_all(&page)
+            .and_then(|()| stdout.flush())
+            .map_err(|err| crate::output::stdout_error(err, "Failed to write the manual"));
+    };
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    for path in write_pages(dir)? {
+        crate::logger::info(format_args!("Wrote {}", path.display()));
+    }
+    Ok(())
+}
+
+fn write_pages(dir: &Path) -> Result<Vec<PathBuf>> {
+    let main_page = dir.join("copytree.1");
+    write_page(&main_page, render)?;
+    let mut written = vec![main_page];
+    let command = command();
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        // `build` named the subcommand `copytree-<name>` for display.
+        let page = Man::new(subcommand.clone());
+        let path = dir.join(page.get_filename());
+        write_page(&path, |out| page.render(out).map_err(Into::into))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn write_page<F>(path: &Path, render_page: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    render_page(&mut out)?;
+    out.flush()
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The CLI definition the manual is rendered from.
+fn command() -> clap::Command {
+    let mut command = Cli::command().disable_help_subcommand(true);
+    command.build();
+    command
+}
+
+/// Renders `copytree(1)`: the sections clap_mangen derives from the CLI
+/// definition plus EXAMPLES and EXIT STATUS.
+pub fn render(out: &mut dyn Write) -> Result<()> {
+    let man = Man::new(command());
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    man.render_options_section(out)?;
+    man.render_subcommands_section(out)?;
+
+    let mut roff = Roff::default();
+    roff.control("SH", ["EXAMPLES"]);
+    for example in EXAMPLES {
+        roff.control("TP", []);
+        roff.text([bold(command_line(example.args))]);
+        roff.text([roman(example.description)]);
+    }
+    roff.control("SH", ["EXIT STATUS"]);
+    for (code, meaning) in EXIT_STATUSES {
+        roff.control("TP", []);
+        roff.text([bold(code.to_string())]);
+        roff.text([roman(*meaning)]);
+    }
+    roff.control("SH", ["FILES"]);
+    roff.control("TP", []);
+    roff.text([italic("~/.config/copytree/config.toml")]);
+    roff.text([roman(
+        "User defaults; `copytree config path` prints the location on this platform.",
+    )]);
+    roff.control("TP", []);
+    roff.text([italic(".copytree.toml")]);
+    roff.text([roman(
+        "Project defaults, looked up from each root to the enclosing git repository.",
+    )]);
+    roff.to_writer(out)?;
+
+    man.render_version_section(out)?;
+    Ok(())
+}
+
+/// Joins `args` as a shell command, quoting arguments the shell would expand.
+fn command_line(args: &[&str]) -> String {
+    args.iter()
+        .map(|arg| {
+            if arg.contains(['*', '?', '[', '{', ' ']) {
+                format!("'{}'", arg)
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered() -> String {
+        let mut out = Vec::new();
+        render(&mut out).expect("render manual");
+        String::from_utf8(out).expect("manual is UTF-8")
+    }
+
+    #[test]
+    fn every_example_parses_with_the_real_cli() {
+        for example in EXAMPLES {
+            assert_eq!(example.args[0], "copytree");
+            if let Err(err) = Cli::command().try_get_matches_from(example.args) {
+                panic!("example {:?} does not parse: {}", example.args, err);
+            }
+        }
+    }
+
+    #[test]
+    fn page_documents_flags_examples_and_exit_statuses() {
+        let page = rendered();
+        assert!(page.contains("\n.TH copytree 1 "), "{page}");
+        assert!(page.contains("max\\-file\\-bytes"));
+        assert!(page.contains(".SH EXAMPLES"));
+        assert!(page.contains("\\-x \\*(Aqtarget/**\\*(Aq \\*(Aq*.lock\\*(Aq \\-\\-out ctx.txt"));
+        assert!(page.contains(".SH \"EXIT STATUS\""));
+        assert!(page.contains("\\-\\-fail\\-on\\-skip, at least one file"));
+    }
+
+    #[test]
+    fn hidden_man_subcommand_is_not_documented() {
+        let page = rendered();
+        assert!(page.contains("copytree\\-restore(1)"), "{page}");
+        assert!(!page.contains("copytree\\-man"));
+    }
+
+    #[test]
+    fn dir_receives_a_page_per_visible_command() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let written = write_pages(dir).expect("write pages");
+
+        let names: Vec<_> = written
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names[0], "copytree.1");
+        assert!(names.contains(&"copytree-restore.1".to_string()));
+        assert!(!names.contains(&"copytree-man.1".to_string()));
+    }
+}
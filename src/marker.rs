@@ -0,0 +1,160 @@
+use std::fmt;
+
+/// Starts every skip marker.
+const OPEN: &str = "<skipped reason=\"";
+
+/// The line written in place of a skipped file's body:
+/// `<skipped reason="too-large" size="20000" limit="16384">`, then a space
+/// and the same in prose. The reason is one of the codes of
+/// [`crate::content::SkipReason::code`]; the fields depend on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    pub reason: String,
+    /// `(name, value)` in the order written.
+    pub fields: Vec<(String, String)>,
+    /// What follows the tag, without the separating space; may be empty.
+    pub prose: String,
+}
+
+impl Marker {
+    pub fn new(reason: &str, fields: Vec<(&str, String)>, prose: String) -> Self {
+        Self {
+            reason: reason.to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            prose,
+        }
+    }
+
+    /// The tag alone, e.g. `<skipped reason="binary" kind="binary">`.
+    pub fn tag(&self) -> String {
+        let mut tag = format!("{}{}\"", OPEN, escape(&self.reason));
+        for (name, value) in &self.fields {
+            tag.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+        tag.push('>');
+        tag
+    }
+
+    /// Reads back a line written by [`Marker`]'s `Display`; `None` for any
+    /// other line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.strip_prefix("<skipped")?;
+        let mut attributes = Vec::new();
+        loop {
+            rest = rest.strip_prefix(' ')?;
+            let (name, after) = rest.split_once("=\"")?;
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid_name {
+                return None;
+            }
+            let (value, after) = unescape_until_quote(after)?;
+            attributes.push((name.to_string(), value));
+            if let Some(after) = after.strip_prefix('>') {
+                rest = after;
+                break;
+            }
+            rest = after;
+        }
+        let prose = match rest {
+            "" => "",
+            rest => rest.strip_prefix(' ')?,
+        };
+        let mut attributes = attributes.into_iter();
+        let (name, reason) = attributes.next()?;
+        (name == "reason").then(|| Self {
+            reason,
+            fields: attributes.collect(),
+            prose: prose.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.tag())?;
+        if !self.prose.is_empty() {
+            write!(f, " {}", self.prose)?;
+        }
+        Ok(())
+    }
+}
+
+/// Backslash-escapes quotes, backslashes, and line breaks, so a value stays
+/// on the marker's line.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The value up to its closing quote, unescaped, and what follows the quote.
+fn unescape_until_quote(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                c @ ('"' | '\\') => value.push(c),
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_and_prose_round_trip() {
+        let marker = Marker::new(
+            "broken-symlink",
+            vec![("target", "../it's a \"dir\"\\x\nnext".to_string())],
+            "broken symlink -> ../elsewhere".to_string(),
+        );
+        let line = marker.to_string();
+        assert_eq!(
+            line,
+            r#"<skipped reason="broken-symlink" target="../it's a \"dir\"\\x\nnext"> broken symlink -> ../elsewhere"#
+        );
+        assert_eq!(Marker::parse(&line), Some(marker));
+
+        let bare = Marker::new("permission", Vec::new(), String::new());
+        assert_eq!(bare.to_string(), r#"<skipped reason="permission">"#);
+        assert_eq!(Marker::parse(&bare.to_string()), Some(bare));
+    }
+
+    #[test]
+    fn other_lines_are_not_markers() {
+        for line in [
+            "<skipped: binary file>",
+            "<skipped reason=binary>",
+            r#"<skipped kind="binary" reason="binary">"#,
+            r#"<skipped reason="binary""#,
+            r#"<skipped reason="binary">trailing"#,
+            r#"<skipped reason="a\q">"#,
+            "fn main() {}",
+        ] {
+            assert_eq!(Marker::parse(line), None, "{line}");
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! The one order copytree lists names and paths in.
+//!
+//! Names compare by their raw bytes, never through a lossy `String`, so the
+//! order does not depend on the locale or the platform's idea of text, and
+//! names that are not valid UTF-8 keep apart from each other. On Unix the
+//! bytes are the name's own. On Windows they are its WTF-8 encoding, which
+//! orders by Unicode code point, with an unpaired surrogate in a name
+//! ordered by its value. No normalization happens: `é` written as one code
+//! point and as `e` with a combining accent are two names.
+//!
+//! Paths compare component by component, which lists a directory's
+//! contents right after it, in the order a tree draws them:
+//!
+//! ```
+//! use copytree::order;
+//! use std::path::Path;
+//!
+//! let mut paths = vec![Path::new("a.txt"), Path::new("a/b.txt"), Path::new("B.txt")];
+//! paths.sort_by(|a, b| order::paths(a, b));
+//! assert_eq!(paths, [Path::new("B.txt"), Path::new("a/b.txt"), Path::new("a.txt")]);
+//! ```
+
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// Orders two names by their bytes.
+pub fn names(a: &OsStr, b: &OsStr) -> Ordering {
+    a.as_encoded_bytes().cmp(b.as_encoded_bytes())
+}
+
+/// Orders two paths component by component, each by [`names`].
+pub fn paths(a: &Path, b: &Path) -> Ordering {
+    component_bytes(a).cmp(component_bytes(b))
+}
+
+fn component_bytes(path: &Path) -> impl Iterator<Item = &[u8]> {
+    path.components()
+        .map(|component| component.as_os_str().as_encoded_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_order_by_bytes_without_normalizing() {
+        let composed = OsStr::new("\u{e9}.txt");
+        let decomposed = OsStr::new("e\u{301}.txt");
+        assert_eq!(names(decomposed, OsStr::new("f.txt")), Ordering::Less);
+        assert_eq!(names(OsStr::new("f.txt"), composed), Ordering::Less);
+        assert_eq!(
+            names(OsStr::new("Z.txt"), OsStr::new("a.txt")),
+            Ordering::Less
+        );
+        assert_ne!(names(composed, decomposed), Ordering::Equal);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_utf8_names_stay_distinct() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let low = Path::new(OsStr::from_bytes(b"dir\xfe/a.txt"));
+        let high = Path::new(OsStr::from_bytes(b"dir\xff/a.txt"));
+        assert_eq!(low.to_string_lossy(), high.to_string_lossy());
+        assert_eq!(paths(low, high), Ordering::Less);
+        assert_eq!(paths(high, low), Ordering::Greater);
+    }
+}
@@ -1,18 +1,3055 @@
-use anyhow::{Context, Result};
+use crate::args::Args;
+use crate::doctor::Session;
+use crate::encode::{Base64Lines, Encoding, PREAMBLE};
+use crate::eol::Crlf;
+use crate::filter;
+use crate::format::{BodyWrite, Tree};
+use crate::interrupt::Interrupt;
+use crate::logger;
+use crate::pipe::Pipe;
+use crate::post::{self, Post, Reply};
+use crate::report::StatusStream;
+use crate::sample::Sampled;
+use crate::serve::{self, Serve};
+use crate::spill::{SpillBuffer, Spilled};
+use crate::style::{Painter, Role, StyledWrite, Styler};
+use crate::timestamp::UtcDateTime;
+use anyhow::{anyhow, bail, Context, Result};
 use arboard::Clipboard;
-use std::fs;
-
-pub fn handle_output(text: &str, to_stdout: bool, out_file: Option<String>) -> Result<()> {
-    if to_stdout {
-        println!("{}", text);
-    } else if let Some(file_path) = out_file {
-        fs::write(&file_path, text)
-            .with_context(|| format!("Failed to write to file: {}", file_path))?;
-        println!("Output written to {}.", file_path);
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Minimal clipboard interface so callers can swap in a fake backend.
+pub trait ClipboardBackend {
+    fn set_text(&mut self, text: &str) -> Result<()>;
+    /// The clipboard's text; an error when it is empty or holds something
+    /// else, such as an image.
+    fn get_text(&mut self) -> Result<String>;
+}
+
+struct SystemClipboard(Clipboard);
+
+impl ClipboardBackend for SystemClipboard {
+    fn set_text(&mut self, text: &str) -> Result<()> {
+        self.0.set_text(text)?;
+        Ok(())
+    }
+
+    fn get_text(&mut self) -> Result<String> {
+        Ok(self.0.get_text()?)
+    }
+}
+
+fn open_system_clipboard() -> Result<Box<dyn ClipboardBackend>> {
+    let clipboard = Clipboard::new()?;
+    Ok(Box::new(SystemClipboard(clipboard)))
+}
+
+/// `--clipboard-backend`: what puts the output on the clipboard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardKind {
+    /// The system clipboard, falling back to `--clipboard-cmd` or a command
+    /// found for the session.
+    #[default]
+    Auto,
+    /// The system clipboard only, through the arboard crate.
+    Arboard,
+    /// `--clipboard-cmd` only.
+    Command,
+}
+
+/// An external program that takes the text on stdin, such as `wl-copy`,
+/// run through the shell. `paste` prints the clipboard back, for
+/// `--clipboard-append`; a `--clipboard-cmd` has none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandClipboard {
+    copy: String,
+    paste: Option<String>,
+}
+
+impl CommandClipboard {
+    pub fn new(copy: &str) -> Self {
+        Self {
+            copy: copy.to_string(),
+            paste: None,
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("command `{}`", self.copy)
+    }
+}
+
+impl ClipboardBackend for CommandClipboard {
+    fn set_text(&mut self, text: &str) -> Result<()> {
+        // Tools like wl-copy and xclip stay behind to serve the selection,
+        // holding whatever output they inherit, so only the exit is awaited.
+        let mut child = filter::shell(&self.copy)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("failed to start")?;
+        let written = child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(text.as_bytes());
+        let status = child.wait().context("failed to wait for it")?;
+        if !status.success() {
+            bail!("exited with {}", status);
+        }
+        written.context("failed to write the text")
+    }
+
+    fn get_text(&mut self) -> Result<String> {
+        let Some(paste) = &self.paste else {
+            bail!("cannot read the clipboard back");
+        };
+        let output = filter::shell(paste)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| format!("failed to start `{}`", paste))?;
+        if !output.status.success() || output.stdout.is_empty() {
+            bail!("the clipboard holds no text");
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// The command `auto` falls back to for the session: `clip.exe` on WSL,
+/// `pbcopy` on macOS, `wl-copy` on Wayland, then `xclip` or `xsel` on X11.
+/// `on_path` tells whether a program can be run.
+pub fn detect_command<F>(session: &Session, on_path: F) -> Option<CommandClipboard>
+where
+    F: Fn(&str) -> bool,
+{
+    let (copy, paste) = if session.wsl && on_path("clip.exe") {
+        (
+            "clip.exe",
+            "powershell.exe -NoProfile -Command Get-Clipboard",
+        )
+    } else if session.os == "macos" && on_path("pbcopy") {
+        ("pbcopy", "pbpaste")
+    } else if session.wayland_display.is_some() && on_path("wl-copy") {
+        ("wl-copy", "wl-paste --no-newline")
+    } else if session.display.is_some() && on_path("xclip") {
+        (
+            "xclip -selection clipboard",
+            "xclip -selection clipboard -o",
+        )
+    } else if session.display.is_some() && on_path("xsel") {
+        ("xsel --clipboard --input", "xsel --clipboard --output")
     } else {
-        let mut clipboard = Clipboard::new()?;
+        return None;
+    };
+    Some(CommandClipboard {
+        copy: copy.to_string(),
+        paste: Some(paste.to_string()),
+    })
+}
+
+/// Whether `program` is a file in one of the `PATH` directories.
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+/// The backends `--clipboard-backend` picked, each tried in turn until one
+/// works. An error names every backend tried and how it failed.
+struct Backends(Vec<(String, Result<Box<dyn ClipboardBackend>>)>);
+
+impl Backends {
+    fn first<T, F>(&mut self, mut attempt: F) -> Result<T>
+    where
+        F: FnMut(&mut dyn ClipboardBackend) -> Result<T>,
+    {
+        let mut failures = Vec::new();
+        for (label, backend) in &mut self.0 {
+            let result = match backend {
+                Ok(backend) => attempt(backend.as_mut()),
+                Err(err) => Err(anyhow!("{err:#}")),
+            };
+            match result {
+                Ok(value) => return Ok(value),
+                Err(err) => failures.push(format!("{}: {:#}", label, err)),
+            }
+        }
+        bail!("{}", failures.join("; "))
+    }
+}
+
+impl ClipboardBackend for Backends {
+    fn set_text(&mut self, text: &str) -> Result<()> {
+        self.first(|backend| backend.set_text(text))
+    }
+
+    fn get_text(&mut self) -> Result<String> {
+        self.first(|backend| backend.get_text())
+    }
+}
+
+/// The clipboard `kind` names; `command` is `--clipboard-cmd`, which `auto`
+/// falls back to in place of a detected command.
+fn open_clipboard(
+    kind: ClipboardKind,
+    command: Option<&str>,
+    detect: impl FnOnce() -> Option<CommandClipboard>,
+) -> Box<dyn ClipboardBackend> {
+    let mut backends = Vec::new();
+    if kind != ClipboardKind::Command {
+        backends.push(("arboard".to_string(), open_system_clipboard()));
+    }
+    if kind != ClipboardKind::Arboard {
+        match command.map(CommandClipboard::new).or_else(detect) {
+            Some(command) => {
+                let backend: Box<dyn ClipboardBackend> = Box::new(command.clone());
+                backends.push((command.label(), Ok(backend)));
+            }
+            None => backends.push((
+                "command".to_string(),
+                Err(anyhow!("none found for this session; pass --clipboard-cmd")),
+            )),
+        }
+    }
+    Box::new(Backends(backends))
+}
+
+/// Compression applied to `--out` files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn from_extension(file_path: &str) -> Option<Self> {
+        match Path::new(file_path).extension()?.to_str()? {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    fn feature_name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    fn is_available(self) -> bool {
+        match self {
+            Self::Gzip => cfg!(feature = "gzip"),
+            Self::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+}
+
+/// Sink selection and write behavior derived from the command line.
+#[derive(Debug, Default)]
+pub struct OutputOptions {
+    pub to_stdout: bool,
+    pub to_clipboard: bool,
+    /// `--clipboard` was given, so a clipboard that fails fails the run
+    /// rather than being skipped with a warning.
+    pub clipboard_requested: bool,
+    pub out_files: Vec<String>,
+    pub require_clipboard: bool,
+    /// Largest output put on the clipboard as is; a larger one goes to a
+    /// temp file whose path is copied instead. `None` under
+    /// `--force-clipboard`.
+    pub clipboard_limit: Option<u64>,
+    /// Output held in memory for the clipboard at most; past it the output
+    /// spills to a temp file whose path is copied instead.
+    pub max_memory: Option<u64>,
+    /// Serve the output over HTTP once every other sink has it.
+    pub serve: Option<Serve>,
+    /// Send the output in a POST request.
+    pub post: Option<Post>,
+    pub append: bool,
+    /// Put the output on the clipboard after the text already there.
+    pub clipboard_append: bool,
+    /// What puts the output on the clipboard.
+    pub clipboard_backend: ClipboardKind,
+    /// The external program for the clipboard (`--clipboard-cmd`).
+    pub clipboard_cmd: Option<String>,
+    pub force: bool,
+    pub mkdirs: bool,
+    /// Explicit compression; otherwise inferred per file from `.gz`/`.zst`.
+    pub compress: Option<Compression>,
+    /// Encoding applied to the output before any sink, compression included.
+    pub encode: Option<Encoding>,
+    /// Shell command the output runs through before encoding and the sinks.
+    pub pipe_cmd: Option<String>,
+    /// How long the command may run once it has the whole output.
+    pub pipe_timeout: Duration,
+    /// Paths of the current run, recorded in the separator line when appending.
+    pub run_paths: Vec<String>,
+    /// Styling for the stdout sink; every other sink receives plain text.
+    pub stdout_style: Styler,
+    /// Columns the tree is elided to on stdout; every other sink receives
+    /// it whole. `None` keeps stdout whole as well.
+    pub stdout_tree_width: Option<usize>,
+    /// Styling for the summary lines on stderr.
+    pub summary_style: Styler,
+    /// Leave out the blank line stdout otherwise gets after the output, so
+    /// `--bare` prints the file exactly.
+    pub no_final_newline: bool,
+    /// Print no summary lines; `--check` reports on its comparison instead.
+    pub no_summary: bool,
+    /// End the lines copytree writes in `\r\n` (`--output-eol`).
+    pub crlf: bool,
+    /// Convert the lines of file bodies as well (`--output-eol-content`).
+    pub crlf_content: bool,
+    /// Ctrl-C, made to quit at once while the output is served.
+    pub interrupt: Interrupt,
+}
+
+impl OutputOptions {
+    /// When stdout is not a terminal and no sink was chosen explicitly, the
+    /// output goes to stdout so pipes and redirections receive it.
+    pub fn from_args(args: &Args, stdout_is_terminal: bool) -> Self {
+        let serve = args.serve_address().map(|address| Serve {
+            address,
+            once: args.serve_once,
+            content_type: args.format.content_type(),
+        });
+        let post = args.post.clone().map(|url| Post {
+            url,
+            headers: args.post_header.clone(),
+            content_type: args.format.content_type(),
+        });
+        let network_sink = serve.is_some() || post.is_some();
+        let status_on_stdout = args.status_json == Some(StatusStream::Stdout);
+        Self {
+            require_clipboard: args.require_clipboard,
+            clipboard_limit: (!args.force_clipboard).then_some(args.clipboard_limit),
+            max_memory: args.max_memory,
+            append: args.append,
+            clipboard_append: args.clipboard_append,
+            clipboard_backend: args.clipboard_backend,
+            clipboard_cmd: args.clipboard_cmd.clone(),
+            force: args.force,
+            mkdirs: args.mkdirs,
+            compress: args.compress,
+            encode: args.encode,
+            pipe_cmd: args.pipe_cmd.clone(),
+            pipe_timeout: Duration::from_secs(args.pipe_timeout),
+            run_paths: args.walk.paths.clone(),
+            stdout_style: args.color.styler(stdout_is_terminal),
+            stdout_tree_width: tree_width(args.tree_width, stdout_is_terminal),
+            summary_style: Styler::default(),
+            no_final_newline: false,
+            no_summary: args.check,
+            crlf: args.output_eol.is_crlf(),
+            crlf_content: args.output_eol.is_crlf() && args.output_eol_content,
+            serve,
+            post,
+            // Serving and posting are sinks of their own, and
+            // `--status-json=stdout` keeps stdout for its line, so a piped
+            // stdout only gets the output when asked for it.
+            ..Self::for_sinks(
+                args.stdout,
+                &args.out,
+                args.clipboard,
+                stdout_is_terminal || network_sink || status_on_stdout,
+            )
+        }
+    }
+
+    /// Just the sinks: `--stdout`, `--out` (where "-" is stdout), and
+    /// `--clipboard`, with the same piped default as [`Self::from_args`].
+    pub fn for_sinks(
+        stdout: bool,
+        out: &[String],
+        clipboard: bool,
+        stdout_is_terminal: bool,
+    ) -> Self {
+        let out_is_stdout = out.iter().any(|file_path| file_path == "-");
+        let piped_by_default = !stdout_is_terminal && !stdout && !clipboard && out.is_empty();
+        Self {
+            to_stdout: stdout || out_is_stdout || piped_by_default,
+            to_clipboard: clipboard,
+            clipboard_requested: clipboard,
+            out_files: out
+                .iter()
+                .filter(|file_path| file_path.as_str() != "-")
+                .cloned()
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Whether this run will append after content left by an earlier run in
+    /// every output file.
+    pub fn appends_to_existing(&self) -> bool {
+        self.append
+            && !self.to_stdout
+            && !self.out_files.is_empty()
+            && self
+                .out_files
+                .iter()
+                .all(|file_path| has_content(file_path))
+    }
+
+    /// Gives `--open` a file to show when no `--out` was given, keeping the
+    /// clipboard if it would otherwise have received the output.
+    pub fn ensure_out_file(&mut self) {
+        if self.out_files.is_empty() && !self.append {
+            self.to_clipboard = self.uses_clipboard();
+            self.out_files
+                .push(temp_output_path().to_string_lossy().into_owned());
+        }
+    }
+
+    /// The clipboard is used when requested, or when no other sink is.
+    pub fn uses_clipboard(&self) -> bool {
+        self.to_clipboard
+            || (!self.to_stdout
+                && self.out_files.is_empty()
+                && self.serve.is_none()
+                && self.post.is_none())
+    }
+
+    /// The end of a line copytree writes itself.
+    fn line_break(&self) -> &'static str {
+        if self.crlf {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+}
+
+/// The width the tree is elided to on stdout: `--tree-width` when given,
+/// with 0 meaning none, otherwise the terminal's width when stdout is one.
+pub fn tree_width(flag: Option<usize>, stdout_is_terminal: bool) -> Option<usize> {
+    match flag {
+        Some(0) => None,
+        Some(width) => Some(width),
+        None if stdout_is_terminal => crossterm::terminal::size()
+            .ok()
+            .map(|(columns, _)| usize::from(columns)),
+        None => None,
+    }
+}
+
+fn has_content(file_path: &str) -> bool {
+    fs::metadata(file_path).is_ok_and(|metadata| metadata.len() > 0)
+}
+
+/// Values substituted into `{...}` placeholders of the `--out` path.
+pub struct OutPlaceholders {
+    pub unix_time: i64,
+    pub root: String,
+}
+
+const OUT_PLACEHOLDERS: &[&str] = &["date", "time", "timestamp", "root", "n"];
+
+enum OutSegment {
+    Literal(String),
+    Counter,
+}
+
+/// Expands `{date}`, `{time}`, `{timestamp}`, `{root}`, and `{n}` in an
+/// `--out` path; `{{` and `}}` produce literal braces. `{n}` becomes the
+/// smallest positive integer for which `exists` reports no file.
+pub fn expand_out_path<F>(template: &str, values: &OutPlaceholders, exists: F) -> Result<String>
+where
+    F: Fn(&str) -> bool,
+{
+    let time = UtcDateTime::from_unix(values.unix_time);
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(next) => name.push(next),
+                        None => bail!("Unclosed '{{' in --out path: {}", template),
+                    }
+                }
+                match name.as_str() {
+                    "date" => literal.push_str(&time.date()),
+                    "time" => literal.push_str(&time.file_time()),
+                    "timestamp" => literal.push_str(&values.unix_time.to_string()),
+                    "root" => literal.push_str(&values.root),
+                    "n" => {
+                        segments.push(OutSegment::Literal(std::mem::take(&mut literal)));
+                        segments.push(OutSegment::Counter);
+                    }
+                    _ => bail!(
+                        "Unknown placeholder {{{}}} in --out path (valid placeholders: {})",
+                        name,
+                        OUT_PLACEHOLDERS
+                            .iter()
+                            .map(|valid| format!("{{{}}}", valid))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }
+            }
+            '}' => bail!(
+                "Unmatched '}}' in --out path (write '}}}}' for a literal brace): {}",
+                template
+            ),
+            _ => literal.push(ch),
+        }
+    }
+    segments.push(OutSegment::Literal(literal));
+
+    let render = |n: u64| {
+        segments
+            .iter()
+            .map(|segment| match segment {
+                OutSegment::Literal(text) => text.clone(),
+                OutSegment::Counter => n.to_string(),
+            })
+            .collect::<String>()
+    };
+
+    if !segments
+        .iter()
+        .any(|segment| matches!(segment, OutSegment::Counter))
+    {
+        return Ok(render(0));
+    }
+    (1..)
+        .map(render)
+        .find(|candidate| !exists(candidate))
+        .context("Ran out of candidates for {n} in --out path")
+}
+
+/// Where the output ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Stdout,
+    File(PathBuf),
+    Clipboard,
+    TempFile(PathBuf),
+    /// The URL the output was served at.
+    Http(String),
+    /// The URL the output was posted to.
+    Post(String),
+}
+
+impl Destination {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::File(_) => "file",
+            Self::Clipboard => "clipboard",
+            Self::TempFile(_) => "temp_file",
+            Self::Http(_) => "http",
+            Self::Post(_) => "post",
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) | Self::TempFile(path) => Some(path),
+            Self::Stdout | Self::Clipboard | Self::Http(_) | Self::Post(_) => None,
+        }
+    }
+}
+
+/// Writes a binary payload (such as a tar archive) to every `--out` file.
+pub fn handle_archive_output(
+    payload: &[u8],
+    options: &OutputOptions,
+    stats: OutputStats,
+) -> Result<Vec<Destination>> {
+    let mut stream = OutputStream::open(options, 0);
+    stream.write_all(payload)?;
+    stream.finish(stats)
+}
+
+/// What went into the output, for the summary line printed per sink.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OutputStats {
+    pub files: u64,
+    pub tokens: u64,
+    pub sampled: Option<Sampled>,
+}
+
+/// A sink that received the output, with what the summary needs to know.
+pub struct SinkReport {
+    destination: Destination,
+    appended: bool,
+    compressed_bytes: Option<u64>,
+    /// What the clipboard holds instead of the output: the path of this
+    /// file when the output was over `--clipboard-limit`, or the URL a
+    /// `--post` reply named.
+    copied: Option<&'static str>,
+    /// The server's answer to `--post`.
+    reply: Option<Reply>,
+}
+
+impl From<Destination> for SinkReport {
+    fn from(destination: Destination) -> Self {
+        Self {
+            destination,
+            appended: false,
+            compressed_bytes: None,
+            copied: None,
+            reply: None,
+        }
+    }
+}
+
+/// The status line for one sink, e.g.
+/// `Copied 182 files, 96 KiB (~24k tokens) to clipboard.`
+fn summary_line(
+    stats: OutputStats,
+    bytes: usize,
+    delivered: &SinkReport,
+    styler: Styler,
+) -> String {
+    let verb = match (&delivered.destination, delivered.appended) {
+        (Destination::Clipboard, _) => "Copied",
+        (Destination::Http(_), _) => "Serving",
+        (Destination::Post(_), _) => "Posted",
+        (_, true) => "Appended",
+        (_, false) => "Wrote",
+    };
+    let target = match &delivered.destination {
+        Destination::Stdout => "stdout".to_string(),
+        Destination::Clipboard => "clipboard".to_string(),
+        Destination::File(path) | Destination::TempFile(path) => path.display().to_string(),
+        Destination::Http(url) | Destination::Post(url) => url.clone(),
+    };
+    let preposition = match delivered.destination {
+        Destination::Http(_) => "at",
+        _ => "to",
+    };
+    let mut line = format!(
+        "{} {} file{}, {} (~{} tokens) {} {}",
+        verb,
+        stats.files,
+        if stats.files == 1 { "" } else { "s" },
+        styler.paint(Role::Size, &human_size(bytes as u64)),
+        approximate_count(stats.tokens),
+        preposition,
+        target
+    );
+    if let Some(compressed) = delivered.compressed_bytes {
+        line.push_str(&format!(
+            " ({} compressed)",
+            styler.paint(Role::Size, &human_size(compressed))
+        ));
+    }
+    if let Some(reply) = &delivered.reply {
+        line.push_str(&format!(" ({})", reply));
+    }
+    if let Some(copied) = delivered.copied {
+        line.push_str(&format!(" ({} copied to clipboard)", copied));
+    }
+    if let Some(sampled) = stats.sampled {
+        line.push_str(&format!(", showing {}", sampled));
+    }
+    line.push('.');
+    line
+}
+
+/// The warning printed when the output is over `--clipboard-limit`.
+fn oversized_clipboard_warning(bytes: u64, limit: u64, path: &Path) -> String {
+    format!(
+        "output is {}, over --clipboard-limit {}, which some clipboards fail or hang on; \
+         wrote it to {} and copied that path instead (--force-clipboard copies the output)",
+        human_size(bytes),
+        human_size(limit),
+        path.display()
+    )
+}
+
+/// The warning printed when `--clipboard-append` would have put more than
+/// `--clipboard-limit` on the clipboard.
+fn refused_append_warning(bytes: u64, limit: u64, path: &Path) -> String {
+    format!(
+        "appending would leave {} on the clipboard, over --clipboard-limit {}; \
+         left the clipboard as it was and wrote the output to {} instead",
+        human_size(bytes),
+        human_size(limit),
+        path.display()
+    )
+}
+
+/// How [`copy_text`] left the clipboard.
+#[derive(Debug, PartialEq, Eq)]
+enum Copied {
+    /// It holds the output, after what was already there when appending.
+    Text,
+    /// Appending would have put this many bytes on it, over the limit, so
+    /// it was left as it was.
+    OverLimit(u64),
+}
+
+/// Puts `text` on the clipboard. `append` carries the run's paths under
+/// `--clipboard-append`: the clipboard's text then stays in front,
+/// separated from the output by the line `--append` uses, with lines ending
+/// in `line_break`. A clipboard without text is replaced, with a warning.
+fn copy_text(
+    clipboard: &mut dyn ClipboardBackend,
+    text: &str,
+    append: Option<&[String]>,
+    limit: Option<u64>,
+    line_break: &str,
+    reporter: &mut dyn Reporter,
+) -> Result<Copied> {
+    let existing = match append.map(|_| clipboard.get_text()) {
+        Some(Ok(existing)) if !existing.trim().is_empty() => Some(existing),
+        Some(Err(err)) => {
+            reporter.warn(&format_args!(
+                "the clipboard holds no text to append to ({err:#}); replacing it"
+            ));
+            None
+        }
+        _ => None,
+    };
+    let Some((existing, paths)) = existing.zip(append) else {
         clipboard.set_text(text)?;
-        println!("Copied to clipboard.");
+        return Ok(Copied::Text);
+    };
+    let combined = format!(
+        "{}{eol}{eol}{}{eol}{eol}{}",
+        existing.trim_end_matches(['\n', '\r']),
+        append_separator(&UtcDateTime::now(), paths),
+        text,
+        eol = line_break
+    );
+    if limit.is_some_and(|limit| combined.len() as u64 > limit) {
+        return Ok(Copied::OverLimit(combined.len() as u64));
+    }
+    clipboard.set_text(&combined)?;
+    Ok(Copied::Text)
+}
+
+/// The warning printed when the output outgrew `--max-memory`.
+fn spilled_clipboard_warning(bytes: u64, limit: u64, path: &Path) -> String {
+    format!(
+        "output is {}, over --max-memory {}, so it went to {} as it was assembled; \
+         copied that path instead",
+        human_size(bytes),
+        human_size(limit),
+        path.display()
+    )
+}
+
+/// Puts `path` on the clipboard in place of the output written there. The
+/// file keeps the output either way; a clipboard that fails is only an
+/// error under `--require-clipboard`.
+fn copy_path_instead(
+    path: PathBuf,
+    open_clipboard: OpenClipboard,
+    require_clipboard: bool,
+    reporter: &mut dyn Reporter,
+) -> Result<SinkReport> {
+    let notice = path.display().to_string();
+    let copied = match open_clipboard().and_then(|mut clipboard| clipboard.set_text(&notice)) {
+        Ok(()) => true,
+        Err(err) if require_clipboard => {
+            return Err(
+                err.context("Failed to initialize the clipboard (--require-clipboard is set)")
+            );
+        }
+        Err(err) => {
+            reporter.warn(&format_args!("clipboard unavailable: {err:#}"));
+            false
+        }
+    };
+    Ok(SinkReport {
+        copied: copied.then_some("path"),
+        ..SinkReport::from(Destination::TempFile(path))
+    })
+}
+
+/// Stdout's reader went away before the output was written, as `head` does
+/// once it has the lines it wants. Runs end quietly on it.
+#[derive(Debug)]
+pub struct StdoutClosed;
+
+impl fmt::Display for StdoutClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("stdout was closed before the output was written")
+    }
+}
+
+impl std::error::Error for StdoutClosed {}
+
+/// A failed write to stdout: [`StdoutClosed`] when its reader went away,
+/// otherwise `err` with `context`.
+pub fn stdout_error(err: io::Error, context: &str) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        anyhow::Error::new(StdoutClosed)
+    } else {
+        anyhow::Error::new(err).context(context.to_string())
+    }
+}
+
+/// Formats a byte count with binary units: `512 B`, `1.5 KiB`, `96 KiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{:.1} {}", value, UNITS[unit])
+    } else {
+        format!("{:.0} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats a count compactly: `950`, `1.5k`, `24k`, `1.2M`.
+pub fn approximate_count(count: u64) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=9_999 => format!("{:.1}k", count as f64 / 1_000.0),
+        10_000..=999_999 => format!("{:.0}k", count as f64 / 1_000.0),
+        _ => format!("{:.1}M", count as f64 / 1_000_000.0),
+    }
+}
+
+/// Formats a count with thousands separators: `950`, `1,212`, `48,000`.
+pub fn grouped_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Where the sinks' status lines go: the logger in a run, a list in tests.
+pub trait Reporter {
+    fn info(&mut self, message: &dyn fmt::Display);
+    fn warn(&mut self, message: &dyn fmt::Display);
+    fn error(&mut self, message: &dyn fmt::Display);
+}
+
+/// Reports through [`logger`], so `--quiet` and `--verbose` apply.
+pub struct LogReporter;
+
+impl Reporter for LogReporter {
+    fn info(&mut self, message: &dyn fmt::Display) {
+        logger::info(message);
+    }
+
+    fn warn(&mut self, message: &dyn fmt::Display) {
+        logger::warn(message);
+    }
+
+    fn error(&mut self, message: &dyn fmt::Display) {
+        logger::error(message);
+    }
+}
+
+/// One destination of the output. [`OutputStream`] hands each sink every
+/// chunk as it is produced, then finishes the sinks in turn.
+///
+/// A sink whose `write` fails is discarded and gets nothing more; its error
+/// is reported once the others have finished.
+pub trait OutputSink {
+    /// Takes the next chunk of the output.
+    fn write(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// Completes the output, with any status lines going to `reporter`.
+    /// `None` when the sink stood aside without failing the run, as a
+    /// missing clipboard does beside other sinks.
+    fn finish(self: Box<Self>, reporter: &mut dyn Reporter) -> Result<Option<SinkReport>>;
+
+    /// Abandons the output. Nothing to undo by default.
+    fn discard(self: Box<Self>) {}
+
+    /// Whether the sink gets the styled output rather than plain text.
+    fn styled(&self) -> bool {
+        false
+    }
+}
+
+/// Opens the clipboard, once the output is complete.
+pub type OpenClipboard = Box<dyn FnOnce() -> Result<Box<dyn ClipboardBackend>>>;
+
+/// The clipboard `--clipboard-backend` and `--clipboard-cmd` select.
+fn system_clipboard(options: &OutputOptions) -> OpenClipboard {
+    let kind = options.clipboard_backend;
+    let command = options.clipboard_cmd.clone();
+    Box::new(move || {
+        Ok(open_clipboard(kind, command.as_deref(), || {
+            detect_command(&Session::detect(), on_path)
+        }))
+    })
+}
+
+/// The sinks written as the output arrives, opened from `options`: stdout
+/// first, then each `--out` file. A file that cannot be opened is an error
+/// in its place.
+fn open_sinks(options: &OutputOptions) -> Vec<Result<Box<dyn OutputSink>>> {
+    let stdout = options.to_stdout.then(|| {
+        Ok(Box::new(StdoutSink {
+            writer: BufWriter::new(io::stdout()),
+            final_newline: if options.no_final_newline {
+                ""
+            } else {
+                options.line_break()
+            },
+        }) as Box<dyn OutputSink>)
+    });
+    let files = options.out_files.iter().map(|file_path| {
+        FileSink::open(file_path, options).map(|sink| Box::new(sink) as Box<dyn OutputSink>)
+    });
+    stdout.into_iter().chain(files).collect()
+}
+
+/// Stdout, written through as the output arrives.
+struct StdoutSink {
+    writer: BufWriter<io::Stdout>,
+    /// The line break after the output, unless `--bare` asked for none.
+    final_newline: &'static str,
+}
+
+impl OutputSink for StdoutSink {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.writer
+            .write_all(chunk)
+            .map_err(|err| stdout_error(err, "Failed to write to stdout"))
+    }
+
+    fn finish(mut self: Box<Self>, _: &mut dyn Reporter) -> Result<Option<SinkReport>> {
+        self.writer
+            .write_all(self.final_newline.as_bytes())
+            .and_then(|()| self.writer.flush())
+            .map_err(|err| stdout_error(err, "Failed to write to stdout"))?;
+        Ok(Some(SinkReport::from(Destination::Stdout)))
+    }
+
+    fn styled(&self) -> bool {
+        true
+    }
+}
+
+/// The clipboard, which needs the whole text at once: the output is kept
+/// in memory up to `--max-memory` and in a temp file past it.
+struct ClipboardSink {
+    buffer: SpillBuffer,
+    open: OpenClipboard,
+    require: bool,
+    /// Whether `--clipboard` asked for it, so failing is an error.
+    requested: bool,
+    limit: Option<u64>,
+    max_memory: Option<u64>,
+    /// The run's paths under `--clipboard-append`.
+    append: Option<Vec<String>>,
+    line_break: &'static str,
+    /// Whether no other sink gets the output, so a missing clipboard must
+    /// leave it in a temp file rather than nowhere.
+    standalone: bool,
+}
+
+impl ClipboardSink {
+    fn new(
+        options: &OutputOptions,
+        size_hint: usize,
+        open: OpenClipboard,
+        standalone: bool,
+    ) -> Self {
+        Self {
+            buffer: SpillBuffer::new(options.max_memory, size_hint, temp_output_path()),
+            open,
+            require: options.require_clipboard,
+            requested: options.clipboard_requested,
+            limit: options.clipboard_limit,
+            max_memory: options.max_memory,
+            append: options.clipboard_append.then(|| options.run_paths.clone()),
+            line_break: options.line_break(),
+            standalone,
+        }
+    }
+
+    /// Copies `link` in place of the output. Whether it was copied; a
+    /// missing clipboard is only an error under `--require-clipboard` or an
+    /// explicit `--clipboard`.
+    fn copy_link(self, link: &str, reporter: &mut dyn Reporter) -> Result<bool> {
+        match (self.open)().and_then(|mut clipboard| clipboard.set_text(link)) {
+            Ok(()) => Ok(true),
+            Err(err) if self.require => {
+                Err(err.context("Failed to initialize the clipboard (--require-clipboard is set)"))
+            }
+            Err(err) if self.requested => Err(err.context("Failed to copy to the clipboard")),
+            Err(err) => {
+                reporter.warn(&format_args!("clipboard unavailable, skipped: {err:#}"));
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl OutputSink for ClipboardSink {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.buffer.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>, reporter: &mut dyn Reporter) -> Result<Option<SinkReport>> {
+        let Self {
+            buffer,
+            open,
+            require,
+            requested,
+            limit,
+            max_memory,
+            append,
+            line_break,
+            standalone,
+        } = *self;
+        let bytes = buffer.len();
+        let text = match buffer.finish()? {
+            Spilled::File(path) => {
+                let limit = max_memory.unwrap_or_default();
+                reporter.warn(&spilled_clipboard_warning(bytes, limit, &path));
+                return copy_path_instead(path, open, require, reporter).map(Some);
+            }
+            Spilled::Memory(buffer) => String::from_utf8(buffer)
+                .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        };
+        if let Some(limit) = limit.filter(|&limit| text.len() as u64 > limit) {
+            let path = write_fallback_file(&text)?;
+            reporter.warn(&oversized_clipboard_warning(
+                text.len() as u64,
+                limit,
+                &path,
+            ));
+            return copy_path_instead(path, open, require, reporter).map(Some);
+        }
+        let copied = open().and_then(|mut clipboard| {
+            copy_text(
+                clipboard.as_mut(),
+                &text,
+                append.as_deref(),
+                limit,
+                line_break,
+                reporter,
+            )
+        });
+        match copied {
+            Ok(Copied::Text) => Ok(Some(SinkReport::from(Destination::Clipboard))),
+            Ok(Copied::OverLimit(combined)) => {
+                let path = write_fallback_file(&text)?;
+                reporter.warn(&refused_append_warning(
+                    combined,
+                    limit.unwrap_or_default(),
+                    &path,
+                ));
+                Ok(Some(SinkReport::from(Destination::TempFile(path))))
+            }
+            Err(err) if require => {
+                Err(err.context("Failed to initialize the clipboard (--require-clipboard is set)"))
+            }
+            // Asked for by name, a clipboard that fails fails the run; the
+            // output is still kept when nothing else has it.
+            Err(err) if requested && standalone => {
+                let path = write_fallback_file(&text)?;
+                Err(err.context(format!(
+                    "Failed to copy to the clipboard; wrote the output to {} instead",
+                    path.display()
+                )))
+            }
+            Err(err) if requested => Err(err.context("Failed to copy to the clipboard")),
+            Err(err) if standalone => {
+                reporter.warn(&format_args!("clipboard unavailable: {err:#}"));
+                let path = write_fallback_file(&text)?;
+                Ok(Some(SinkReport::from(Destination::TempFile(path))))
+            }
+            Err(err) => {
+                reporter.warn(&format_args!("clipboard unavailable, skipped: {err:#}"));
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Fans the output out to every selected sink while it is being produced.
+/// Stdout and files are written through as the data arrives; the clipboard
+/// needs the whole text at once, so it keeps a copy in memory, as do
+/// `--serve` and `--post`.
+///
+/// Writes never fail: a sink that errors is marked failed and skipped from
+/// then on, and the failure is reported by `finish` after every other sink
+/// has had its chance.
+pub struct OutputStream<'a> {
+    options: &'a OutputOptions,
+    /// Stdout and the `--out` files, in the order they finish.
+    sinks: Vec<Sink<Box<dyn OutputSink>>>,
+    /// Finished after `--post`, whose link it copies instead of the output.
+    clipboard: Option<Sink<Box<ClipboardSink>>>,
+    /// The output kept whole for `--serve` and `--post`.
+    kept: Option<Vec<u8>>,
+    /// `--pipe-cmd`; failed when the command could not be started, in
+    /// which case nothing reaches the sinks.
+    pipe: Option<Sink<Pipe>>,
+    encoder: Option<Base64Lines>,
+    /// Converts line endings under `--output-eol crlf`.
+    crlf: Option<Crlf>,
+    bytes_written: usize,
+    sink_count: usize,
+}
+
+struct Sink<W> {
+    writer: Option<W>,
+    error: Option<anyhow::Error>,
+}
+
+impl<W> Sink<W> {
+    fn ready(writer: W) -> Self {
+        Self {
+            writer: Some(writer),
+            error: None,
+        }
+    }
+
+    fn failed(error: anyhow::Error) -> Self {
+        Self {
+            writer: None,
+            error: Some(error),
+        }
+    }
+}
+
+impl<S: OutputSink + ?Sized> Sink<Box<S>> {
+    /// Hands `plain` or, to a sink that shows styling, `styled` to the sink;
+    /// one that fails is discarded and gets nothing more.
+    fn write(&mut self, plain: &[u8], styled: &[u8]) {
+        let written = self.writer.as_mut().map(|sink| {
+            let chunk = if sink.styled() { styled } else { plain };
+            sink.write(chunk)
+        });
+        if let Some(Err(err)) = written {
+            self.writer.take().expect("writer checked above").discard();
+            self.error = Some(err);
+        }
+    }
+
+    fn discard(self) {
+        if let Some(sink) = self.writer {
+            sink.discard();
+        }
+    }
+
+    fn finish(self, reporter: &mut dyn Reporter) -> Result<Option<SinkReport>> {
+        match self.writer {
+            Some(sink) => sink.finish(reporter),
+            None => Err(self.error.unwrap_or_else(|| anyhow!("output unavailable"))),
+        }
+    }
+}
+
+impl<'a> OutputStream<'a> {
+    /// Opens every sink up front. `size_hint` sizes the clipboard buffer.
+    pub fn open(options: &'a OutputOptions, size_hint: usize) -> Self {
+        let sinks = open_sinks(options);
+        Self::with_sinks(options, size_hint, sinks, system_clipboard(options))
+    }
+
+    /// A stream to `sinks`, and to the clipboard `open_clipboard` opens
+    /// when `options` uses one.
+    fn with_sinks(
+        options: &'a OutputOptions,
+        size_hint: usize,
+        sinks: Vec<Result<Box<dyn OutputSink>>>,
+        open_clipboard: OpenClipboard,
+    ) -> Self {
+        let sink_count = sinks.len()
+            + usize::from(options.uses_clipboard())
+            + usize::from(options.serve.is_some())
+            + usize::from(options.post.is_some());
+        let sinks = sinks
+            .into_iter()
+            .map(|sink| match sink {
+                Ok(sink) => Sink::ready(sink),
+                Err(err) => Sink::failed(err),
+            })
+            .collect();
+        let clipboard = options.uses_clipboard().then(|| {
+            Sink::ready(Box::new(ClipboardSink::new(
+                options,
+                size_hint,
+                open_clipboard,
+                sink_count == 1,
+            )))
+        });
+        let kept = (options.serve.is_some() || options.post.is_some())
+            .then(|| Vec::with_capacity(size_hint));
+        let pipe = options.pipe_cmd.as_deref().map(|command| {
+            match Pipe::spawn(command, options.pipe_timeout) {
+                Ok(pipe) => Sink::ready(pipe),
+                Err(err) => Sink::failed(err),
+            }
+        });
+        let mut stream = Self {
+            options,
+            sinks,
+            clipboard,
+            kept,
+            pipe,
+            encoder: None,
+            crlf: options.crlf.then(Crlf::default),
+            bytes_written: 0,
+            sink_count,
+        };
+        if let Some(Encoding::Base64) = options.encode {
+            stream.send(PREAMBLE.as_bytes(), PREAMBLE.as_bytes());
+            stream.encoder = Some(Base64Lines::default());
+        }
+        stream
+    }
+
+    /// Bytes of output produced so far (before any encoding or compression).
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// Whether stdout is the only sink and its reader went away, so nothing
+    /// more is worth producing.
+    pub fn reader_gone(&self) -> bool {
+        self.sink_count == 1
+            && self.sinks.iter().any(|sink| {
+                sink.error
+                    .as_ref()
+                    .is_some_and(|err| err.is::<StdoutClosed>())
+            })
+    }
+
+    /// Abandons the output: new and replaced files are left as they were
+    /// and nothing is copied. Bytes already appended to a file or printed
+    /// to stdout stay.
+    pub fn discard(self) {
+        for sink in self.sinks {
+            sink.discard();
+        }
+    }
+
+    pub fn finish(self, stats: OutputStats) -> Result<Vec<Destination>> {
+        self.finish_with(stats, &mut LogReporter)
+    }
+
+    /// Completes every sink: flushes stdout, commits files, and fills the
+    /// clipboard, then reports one summary line per sink that received the
+    /// output. A missing clipboard only counts as a failure when it was the
+    /// sole sink and no fallback applies. With `--serve`, the output is then
+    /// served until interrupted or, with `--serve-once`, fetched.
+    ///
+    /// A `--pipe-cmd` that fails fails the run before any sink completes:
+    /// files are left as they were, and nothing is copied.
+    fn finish_with(
+        mut self,
+        stats: OutputStats,
+        reporter: &mut dyn Reporter,
+    ) -> Result<Vec<Destination>> {
+        if let Some(sink) = self.pipe.take() {
+            let result = match sink.writer {
+                Some(pipe) => pipe.finish(|printed| self.pass_on(printed, printed)),
+                None => Err(sink
+                    .error
+                    .unwrap_or_else(|| anyhow!("--pipe-cmd unavailable"))),
+            };
+            if let Err(err) = result {
+                self.discard();
+                return Err(err);
+            }
+        }
+        if let Some(encoder) = self.encoder.take() {
+            let mut tail = Vec::new();
+            encoder.finish(&mut tail);
+            self.send(&tail, &tail);
+        }
+        let mut delivered = Vec::new();
+        let mut failures = Vec::new();
+        let mut stdout_closed = None;
+
+        for sink in self.sinks {
+            match sink.finish(reporter) {
+                Ok(report) => delivered.extend(report),
+                // Not a failure of the other sinks, which still complete;
+                // the run ends quietly once they have.
+                Err(err) if err.is::<StdoutClosed>() => stdout_closed = Some(err),
+                Err(err) => failures.push(err),
+            }
+        }
+
+        // A failed post must not lose the output, so it falls back to a
+        // temp file like a missing clipboard, but the run still fails.
+        let mut posted_link = None;
+        if let Some(post) = &self.options.post {
+            let body = self.kept.as_deref().unwrap_or_default();
+            match post::send(post, body) {
+                Ok(reply) => {
+                    posted_link = reply.link().map(|link| (delivered.len(), link.to_string()));
+                    delivered.push(SinkReport {
+                        reply: Some(reply),
+                        ..SinkReport::from(Destination::Post(post.url.clone()))
+                    });
+                }
+                Err(err) => {
+                    let path = write_fallback_file(body)?;
+                    failures.push(anyhow!(
+                        "{err:#}; wrote the output to {} instead",
+                        path.display()
+                    ));
+                    delivered.push(SinkReport::from(Destination::TempFile(path)));
+                }
+            }
+        }
+
+        // With a paste URL to hand, the clipboard gets that instead.
+        if let Some(sink) = self.clipboard.take() {
+            let finished = match (posted_link, sink) {
+                (
+                    Some((index, link)),
+                    Sink {
+                        writer: Some(clipboard),
+                        ..
+                    },
+                ) => clipboard.copy_link(&link, reporter).map(|copied| {
+                    if copied {
+                        delivered[index].copied = Some("URL");
+                    }
+                    None
+                }),
+                (_, sink) => sink.finish(reporter),
+            };
+            match finished {
+                Ok(report) => delivered.extend(report),
+                Err(err) => failures.push(err),
+            }
+        }
+
+        // Serving blocks until interrupted, so it only starts once every
+        // other sink has succeeded and its failures cannot go unreported.
+        let mut server = None;
+        if let Some(serve) = self.options.serve.filter(|_| failures.is_empty()) {
+            let bound = serve::bind(serve.address).and_then(|listener| {
+                let address = listener.local_addr()?;
+                Ok((listener, address))
+            });
+            match bound {
+                Ok((listener, address)) => {
+                    let url = format!("http://{}/", address);
+                    delivered.push(SinkReport::from(Destination::Http(url)));
+                    server = Some((listener, serve));
+                }
+                Err(err) => failures.push(err),
+            }
+        }
+
+        for sink in delivered.iter().filter(|_| !self.options.no_summary) {
+            reporter.info(&summary_line(
+                stats,
+                self.bytes_written,
+                sink,
+                self.options.summary_style,
+            ));
+            if let Some(reply) = sink
+                .reply
+                .as_ref()
+                .filter(|reply| !reply.body.trim().is_empty())
+            {
+                reporter.info(&reply.body.trim_end());
+            }
+        }
+        if let Some((listener, serve)) = server {
+            self.options.interrupt.quit_on_next();
+            let body = self.kept.take().unwrap_or_default();
+            serve::run(listener, &body, serve.content_type, serve.once)?;
+        }
+        let delivered = delivered.into_iter().map(|sink| sink.destination).collect();
+        if failures.is_empty() {
+            return match stdout_closed {
+                Some(err) => Err(err),
+                None => Ok(delivered),
+            };
+        }
+        if self.sink_count == 1 {
+            return Err(failures.remove(0));
+        }
+        for err in &failures {
+            reporter.error(&format_args!("{err:#}"));
+        }
+        bail!("{} of {} outputs failed", failures.len(), self.sink_count)
+    }
+}
+
+impl OutputStream<'_> {
+    /// Sends `styled` to stdout and `plain` to every other sink, with their
+    /// line endings converted under `--output-eol crlf`.
+    fn write_split(&mut self, plain: &[u8], styled: &[u8]) {
+        self.write_lines(plain, styled, true);
+    }
+
+    /// Converts the line endings of `plain` and `styled` when `convert` is
+    /// set and `--output-eol crlf` asks for it; either way the conversion
+    /// remembers whether the output so far ends in `\r`.
+    fn write_lines(&mut self, plain: &[u8], styled: &[u8], convert: bool) {
+        match self.crlf.as_mut() {
+            Some(crlf) if convert => {
+                let mut styled_crlf = *crlf;
+                let plain = crlf.convert(plain);
+                let styled = styled_crlf.convert(styled);
+                self.produce(&plain, &styled);
+            }
+            Some(crlf) => {
+                crlf.pass(plain);
+                self.produce(plain, styled);
+            }
+            None => self.produce(plain, styled),
+        }
+    }
+
+    /// Only the plain bytes count as output. With `--pipe-cmd` every sink
+    /// gets what the command prints for the plain bytes instead.
+    fn produce(&mut self, plain: &[u8], styled: &[u8]) {
+        self.bytes_written += plain.len();
+        match self.pipe.as_mut() {
+            Some(sink) => {
+                if let Some(printed) = sink.writer.as_mut().map(|pipe| pipe.push(plain)) {
+                    self.pass_on(&printed, &printed);
+                }
+            }
+            None => self.pass_on(plain, styled),
+        }
+    }
+
+    /// With `--encode` every sink gets the encoded plain bytes.
+    fn pass_on(&mut self, plain: &[u8], styled: &[u8]) {
+        match self.encoder.as_mut() {
+            Some(encoder) => {
+                let mut encoded = Vec::with_capacity(plain.len() / 3 * 4 + 8);
+                encoder.push(plain, &mut encoded);
+                self.send(&encoded, &encoded);
+            }
+            None => self.send(plain, styled),
+        }
+    }
+
+    fn send(&mut self, plain: &[u8], styled: &[u8]) {
+        for sink in &mut self.sinks {
+            sink.write(plain, styled);
+        }
+        if let Some(sink) = self.clipboard.as_mut() {
+            sink.write(plain, styled);
+        }
+        if let Some(buffer) = self.kept.as_mut() {
+            buffer.extend_from_slice(plain);
+        }
+    }
+}
+
+impl OutputStream<'_> {
+    /// Writes `tree` whole to every sink but stdout, which gets it elided to
+    /// [`OutputOptions::stdout_tree_width`] and styled.
+    pub fn write_tree(&mut self, tree: &Tree) -> io::Result<()> {
+        let Some(width) = self.options.stdout_tree_width else {
+            return tree.write(self);
+        };
+        let mut shown = Vec::new();
+        tree.fit_width(width)
+            .write(&mut Painter::new(&mut shown, self.options.stdout_style))?;
+        self.write_split(tree.plain().as_bytes(), &shown);
+        Ok(())
+    }
+}
+
+impl StyledWrite for OutputStream<'_> {
+    fn write_styled(&mut self, role: Role, text: &str) -> io::Result<()> {
+        let styled = self.options.stdout_style.paint(role, text);
+        self.write_split(text.as_bytes(), styled.as_bytes());
+        Ok(())
+    }
+}
+
+impl BodyWrite for OutputStream<'_> {
+    fn write_body(&mut self, body: &[u8]) -> io::Result<()> {
+        let convert = self.options.crlf_content;
+        self.write_lines(body, body, convert);
+        Ok(())
+    }
+}
+
+impl Write for OutputStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_split(buf, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// One `--out` file being written: either a temp file that replaces the
+/// target on commit, or the target itself opened for appending.
+struct FileSink {
+    file_path: String,
+    compression: Option<Compression>,
+    encoder: Encoder,
+    target: FileTarget,
+    /// Size of the file before this run, so a compressed append reports
+    /// only what it added.
+    start_len: u64,
+}
+
+enum FileTarget {
+    Atomic {
+        temp: AtomicFile,
+        /// Set when the target name was claimed empty and must be released
+        /// again if the write fails.
+        reserved: bool,
+    },
+    Append,
+}
+
+impl FileSink {
+    fn open(file_path: &str, options: &OutputOptions) -> Result<Self> {
+        let compression = options
+            .compress
+            .or_else(|| Compression::from_extension(file_path));
+        if let Some(compression) = compression.filter(|compression| !compression.is_available()) {
+            bail!(
+                "Cannot write {}: copytree was built without the `{}` feature",
+                file_path,
+                compression.feature_name()
+            );
+        }
+
+        let target = Path::new(file_path);
+        prepare_parent_dirs(target, options.mkdirs)?;
+        if options.append {
+            let start_len = fs::metadata(file_path).map_or(0, |metadata| metadata.len());
+            let separator =
+                (start_len > 0).then(|| append_separator(&UtcDateTime::now(), &options.run_paths));
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path)
+                .with_context(|| format!("Failed to open file for appending: {}", file_path))?;
+            let mut encoder = Encoder::new(file, compression)
+                .with_context(|| format!("Failed to append to file: {}", file_path))?;
+            if let Some(separator) = separator {
+                let eol = options.line_break();
+                write!(encoder, "{}{}{}", separator, eol, eol)
+                    .with_context(|| format!("Failed to append to file: {}", file_path))?;
+            }
+            return Ok(Self {
+                file_path: file_path.to_string(),
+                compression,
+                encoder,
+                target: FileTarget::Append,
+                start_len,
+            });
+        }
+
+        let reserved = !options.force;
+        if reserved {
+            reserve_new_file(target)?;
+        }
+        let opened = AtomicFile::create(target).and_then(|(temp, file)| {
+            match Encoder::new(file, compression) {
+                Ok(encoder) => Ok((temp, encoder)),
+                Err(err) => {
+                    temp.discard();
+                    Err(err.into())
+                }
+            }
+        });
+        let (temp, encoder) = match opened {
+            Ok(opened) => opened,
+            Err(err) => {
+                if reserved {
+                    let _ = fs::remove_file(target);
+                }
+                return Err(err.context(format!("Failed to write to file: {}", file_path)));
+            }
+        };
+        Ok(Self {
+            file_path: file_path.to_string(),
+            compression,
+            encoder,
+            target: FileTarget::Atomic { temp, reserved },
+            start_len: 0,
+        })
+    }
+
+    fn commit(self) -> Result<SinkReport> {
+        let Self {
+            file_path,
+            compression,
+            encoder,
+            target,
+            start_len,
+        } = self;
+        let appended = match target {
+            FileTarget::Atomic { temp, reserved } => {
+                let result = encoder.finish().and_then(|file| temp.commit(file));
+                if result.is_err() && reserved {
+                    let _ = fs::remove_file(&file_path);
+                }
+                result.with_context(|| format!("Failed to write to file: {}", file_path))?;
+                false
+            }
+            FileTarget::Append => {
+                encoder
+                    .finish()
+                    .with_context(|| format!("Failed to append to file: {}", file_path))?;
+                true
+            }
+        };
+
+        let compressed_bytes = compression.map(|_| {
+            fs::metadata(&file_path)
+                .map_or(0, |metadata| metadata.len())
+                .saturating_sub(start_len)
+        });
+        Ok(SinkReport {
+            destination: Destination::File(PathBuf::from(file_path)),
+            appended,
+            compressed_bytes,
+            copied: None,
+            reply: None,
+        })
+    }
+}
+
+impl OutputSink for FileSink {
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.encoder.write_all(chunk).with_context(|| {
+            let verb = match self.target {
+                FileTarget::Atomic { .. } => "write to",
+                FileTarget::Append => "append to",
+            };
+            format!("Failed to {} file: {}", verb, self.file_path)
+        })
+    }
+
+    fn finish(self: Box<Self>, _: &mut dyn Reporter) -> Result<Option<SinkReport>> {
+        self.commit().map(Some)
+    }
+
+    /// Drops what was written to a new or replaced file; bytes already
+    /// appended stay.
+    fn discard(self: Box<Self>) {
+        if let FileTarget::Atomic { temp, reserved } = self.target {
+            temp.discard();
+            if reserved {
+                let _ = fs::remove_file(&self.file_path);
+            }
+        }
+    }
+}
+
+/// Buffered writer over an output file, through an encoder when compression
+/// is set. Appends get a new gzip member or zstd frame per run, which
+/// decoders read back as one continuous stream.
+enum Encoder {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Encoder {
+    fn new(file: File, compression: Option<Compression>) -> io::Result<Self> {
+        let writer = BufWriter::new(file);
+        match compression {
+            None => Ok(Self::Plain(writer)),
+            #[cfg(feature = "gzip")]
+            Some(Compression::Gzip) => Ok(Self::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => zstd::Encoder::new(writer, 0).map(Self::Zstd),
+            #[allow(unreachable_patterns)]
+            Some(compression) => Err(io::Error::other(format!(
+                "built without the `{}` feature",
+                compression.feature_name()
+            ))),
+        }
+    }
+
+    /// Writes any trailer and hands back the underlying file.
+    fn finish(self) -> io::Result<File> {
+        let writer = match self {
+            Self::Plain(writer) => writer,
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Makes sure the directory that will hold `target` exists, creating the
+/// missing chain when `create` is set.
+fn prepare_parent_dirs(target: &Path, create: bool) -> Result<()> {
+    let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    if parent.is_dir() {
+        return Ok(());
+    }
+
+    let mut missing = Vec::new();
+    for ancestor in parent.ancestors() {
+        if ancestor.as_os_str().is_empty() || ancestor.is_dir() {
+            break;
+        }
+        if ancestor.exists() {
+            bail!(
+                "Cannot create directory {}: a non-directory file is in the way",
+                ancestor.display()
+            );
+        }
+        missing.push(ancestor);
+    }
+
+    if !create {
+        bail!(
+            "Directory does not exist: {} (pass --mkdirs to create it)",
+            parent.display()
+        );
+    }
+
+    for directory in missing.into_iter().rev() {
+        fs::create_dir(directory)
+            .with_context(|| format!("Failed to create directory: {}", directory.display()))?;
     }
     Ok(())
 }
+
+/// Claims the target name with `create_new` so an existing file is never
+/// clobbered, even if it appears between a check and the write.
+fn reserve_new_file(target: &Path) -> Result<()> {
+    match OpenOptions::new().write(true).create_new(true).open(target) {
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => bail!(
+            "Refusing to overwrite existing file: {} (pass --force to overwrite it or --append to add to it)",
+            target.display()
+        ),
+        Err(err) => Err(err)
+            .with_context(|| format!("Failed to create file: {}", target.display())),
+    }
+}
+
+/// A temp file in the target's directory that is renamed over the target
+/// once complete, so an interrupted run never leaves a truncated file behind.
+struct AtomicFile {
+    target: PathBuf,
+    temp_path: PathBuf,
+}
+
+impl AtomicFile {
+    fn create(target: &Path) -> Result<(Self, File)> {
+        let file_name = target
+            .file_name()
+            .with_context(|| format!("Not a file path: {}", target.display()))?;
+        let directory = match target.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let temp_path = directory.join(format!(
+            ".{}.{}.tmp",
+            file_name.to_string_lossy(),
+            unique_suffix()
+        ));
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .with_context(|| format!("Failed to create temp file: {}", temp_path.display()))?;
+        let temp = Self {
+            target: target.to_path_buf(),
+            temp_path,
+        };
+        if let Ok(metadata) = fs::metadata(target) {
+            if let Err(err) = file.set_permissions(metadata.permissions()) {
+                temp.discard();
+                return Err(err.into());
+            }
+        }
+        Ok((temp, file))
+    }
+
+    fn commit(self, file: File) -> io::Result<()> {
+        let result = file.sync_all().and_then(|()| {
+            drop(file);
+            fs::rename(&self.temp_path, &self.target)
+        });
+        if result.is_err() {
+            self.discard();
+        }
+        result
+    }
+
+    fn discard(self) {
+        let _ = fs::remove_file(&self.temp_path);
+    }
+}
+
+fn unique_suffix() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("copytree-{}-{}", std::process::id(), nanos)
+}
+
+fn append_separator(time: &UtcDateTime, paths: &[String]) -> String {
+    format!("=== copytree {} {} ===", time.rfc3339(), paths.join(" "))
+}
+
+/// Resolves the `--out` targets that already exist to canonical paths, so
+/// the walk can avoid reading the output back into itself.
+pub fn existing_out_paths(options: &OutputOptions) -> Vec<PathBuf> {
+    options
+        .out_files
+        .iter()
+        .filter_map(|file_path| Path::new(file_path).canonicalize().ok())
+        .collect()
+}
+
+/// A fresh path in the system temp directory for output with no `--out`.
+pub fn temp_output_path() -> PathBuf {
+    std::env::temp_dir().join(format!("{}.txt", unique_suffix()))
+}
+
+/// A fresh path in the temp directory ending in `file_name`, so the
+/// compression inferred from its extension stays the same.
+pub fn scratch_path(file_name: &std::ffi::OsStr) -> PathBuf {
+    let mut name = std::ffi::OsString::from(format!("{}-", unique_suffix()));
+    name.push(file_name);
+    std::env::temp_dir().join(name)
+}
+
+/// Keeps `text` in a new file in the temp directory. The file must not
+/// exist yet, so nothing placed at a guessed name is written through.
+fn write_fallback_file(text: impl AsRef<[u8]>) -> Result<PathBuf> {
+    let (path, mut file) = create_fresh_file(temp_output_path)?;
+    file.write_all(text.as_ref())
+        .with_context(|| format!("Failed to write fallback file: {}", path.display()))?;
+    Ok(path)
+}
+
+/// Creates the first path from `next_path` that is not taken yet.
+fn create_fresh_file(mut next_path: impl FnMut() -> PathBuf) -> Result<(PathBuf, File)> {
+    loop {
+        let path = next_path();
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to create fallback file: {}", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Sends `text` to every selected sink in one piece.
+    fn deliver<F>(
+        text: &str,
+        options: &OutputOptions,
+        open_clipboard: F,
+    ) -> Result<Vec<Destination>>
+    where
+        F: FnOnce() -> Result<Box<dyn ClipboardBackend>> + 'static,
+    {
+        let mut stream = open_stream(options, text.len(), open_clipboard);
+        stream.write_all(text.as_bytes())?;
+        stream.finish_with(OutputStats::default(), &mut Recorded::default())
+    }
+
+    /// A stream to the sinks `options` selects, with `open_clipboard` for
+    /// the system clipboard.
+    fn open_stream<F>(
+        options: &OutputOptions,
+        size_hint: usize,
+        open_clipboard: F,
+    ) -> OutputStream<'_>
+    where
+        F: FnOnce() -> Result<Box<dyn ClipboardBackend>> + 'static,
+    {
+        OutputStream::with_sinks(
+            options,
+            size_hint,
+            open_sinks(options),
+            Box::new(open_clipboard),
+        )
+    }
+
+    /// The status lines reported, prefixed with their level.
+    #[derive(Debug, Default)]
+    struct Recorded(Vec<String>);
+
+    impl Reporter for Recorded {
+        fn info(&mut self, message: &dyn fmt::Display) {
+            self.0.push(format!("info: {message}"));
+        }
+
+        fn warn(&mut self, message: &dyn fmt::Display) {
+            self.0.push(format!("warning: {message}"));
+        }
+
+        fn error(&mut self, message: &dyn fmt::Display) {
+            self.0.push(format!("error: {message}"));
+        }
+    }
+
+    /// What a [`MockSink`] was given and how it ended.
+    #[derive(Debug, Default)]
+    struct Mock {
+        received: Vec<u8>,
+        finished: bool,
+        discarded: bool,
+    }
+
+    /// A sink recording into a shared [`Mock`], whose writes fail with
+    /// `fail` when set.
+    struct MockSink {
+        mock: Rc<RefCell<Mock>>,
+        styled: bool,
+        fail: Option<fn() -> anyhow::Error>,
+    }
+
+    fn mock() -> Rc<RefCell<Mock>> {
+        Rc::default()
+    }
+
+    impl MockSink {
+        fn new(mock: &Rc<RefCell<Mock>>) -> Self {
+            Self {
+                mock: Rc::clone(mock),
+                styled: false,
+                fail: None,
+            }
+        }
+    }
+
+    impl OutputSink for MockSink {
+        fn write(&mut self, chunk: &[u8]) -> Result<()> {
+            if let Some(fail) = self.fail {
+                return Err(fail());
+            }
+            self.mock.borrow_mut().received.extend_from_slice(chunk);
+            Ok(())
+        }
+
+        fn finish(self: Box<Self>, _: &mut dyn Reporter) -> Result<Option<SinkReport>> {
+            self.mock.borrow_mut().finished = true;
+            Ok(Some(SinkReport::from(Destination::Stdout)))
+        }
+
+        fn discard(self: Box<Self>) {
+            self.mock.borrow_mut().discarded = true;
+        }
+
+        fn styled(&self) -> bool {
+            self.styled
+        }
+    }
+
+    /// Options selecting stdout, which the mock sinks stand in for, so
+    /// that no clipboard is used.
+    fn mock_options() -> OutputOptions {
+        OutputOptions {
+            to_stdout: true,
+            ..OutputOptions::default()
+        }
+    }
+
+    /// A stream to `sinks` alone, given options from [`mock_options`].
+    fn mock_stream(options: &OutputOptions, sinks: Vec<MockSink>) -> OutputStream<'_> {
+        let sinks = sinks
+            .into_iter()
+            .map(|sink| Ok(Box::new(sink) as Box<dyn OutputSink>))
+            .collect();
+        OutputStream::with_sinks(options, 0, sinks, Box::new(failing_clipboard))
+    }
+
+    fn failing_clipboard() -> Result<Box<dyn ClipboardBackend>> {
+        Err(anyhow!("no display server"))
+    }
+
+    struct RecordingClipboard(Rc<RefCell<Option<String>>>);
+
+    impl ClipboardBackend for RecordingClipboard {
+        fn set_text(&mut self, text: &str) -> Result<()> {
+            *self.0.borrow_mut() = Some(text.to_string());
+            Ok(())
+        }
+
+        fn get_text(&mut self) -> Result<String> {
+            self.0
+                .borrow()
+                .clone()
+                .ok_or_else(|| anyhow!("the clipboard holds no text"))
+        }
+    }
+
+    #[test]
+    fn falls_back_to_temp_file_when_clipboard_is_unavailable() {
+        let destinations = deliver("hello\n", &OutputOptions::default(), failing_clipboard)
+            .expect("fallback failed");
+
+        let [Destination::TempFile(path)] = destinations.as_slice() else {
+            panic!("expected a temp file destination, got {destinations:?}");
+        };
+        assert!(path.starts_with(std::env::temp_dir()));
+        assert_eq!(fs::read_to_string(path).expect("read fallback"), "hello\n");
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn require_clipboard_keeps_hard_failure() {
+        let options = OutputOptions {
+            require_clipboard: true,
+            ..OutputOptions::default()
+        };
+        let err = deliver("hello\n", &options, failing_clipboard)
+            .expect_err("expected clipboard failure");
+        assert!(format!("{err:#}").contains("no display server"));
+    }
+
+    #[test]
+    fn oversized_output_goes_to_a_temp_file_whose_path_is_copied() {
+        let recorded = Rc::new(RefCell::new(None));
+        let clipboard = Rc::clone(&recorded);
+        let options = OutputOptions {
+            clipboard_limit: Some(8),
+            ..OutputOptions::default()
+        };
+        let destinations = deliver("more than eight\n", &options, move || {
+            Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+        })
+        .expect("oversized output still succeeds");
+
+        let [Destination::TempFile(path)] = destinations.as_slice() else {
+            panic!("expected a temp file destination, got {destinations:?}");
+        };
+        assert_eq!(
+            recorded.borrow().as_deref(),
+            Some(path.display().to_string().as_str())
+        );
+        assert_eq!(
+            fs::read_to_string(path).expect("read temp file"),
+            "more than eight\n"
+        );
+        assert_eq!(
+            oversized_clipboard_warning(
+                30 * 1024 * 1024,
+                8 * 1024 * 1024,
+                Path::new("/tmp/out.txt")
+            ),
+            "output is 30 MiB, over --clipboard-limit 8.0 MiB, which some clipboards fail or \
+             hang on; wrote it to /tmp/out.txt and copied that path instead (--force-clipboard \
+             copies the output)"
+        );
+        let delivered = SinkReport {
+            copied: Some("path"),
+            reply: None,
+            ..SinkReport::from(Destination::TempFile(PathBuf::from("/tmp/out.txt")))
+        };
+        assert_eq!(
+            summary_line(OutputStats::default(), 16, &delivered, Styler::default()),
+            "Wrote 0 files, 16 B (~0 tokens) to /tmp/out.txt (path copied to clipboard)."
+        );
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn clipboard_append_keeps_what_the_clipboard_held() {
+        let state = Rc::new(RefCell::new(None));
+        let options = OutputOptions {
+            clipboard_append: true,
+            clipboard_limit: Some(120),
+            run_paths: vec!["src".to_string()],
+            ..OutputOptions::default()
+        };
+        let open = |state: &Rc<RefCell<Option<String>>>| {
+            let state = Rc::clone(state);
+            move || Ok(Box::new(RecordingClipboard(state)) as Box<dyn ClipboardBackend>)
+        };
+
+        // Nothing to append to: a plain set.
+        deliver("--- a.rs ---\nfn a() {}\n", &options, open(&state)).expect("first copy");
+        assert_eq!(state.borrow().as_deref(), Some("--- a.rs ---\nfn a() {}\n"));
+
+        let destinations =
+            deliver("--- b.rs ---\nfn b() {}\n", &options, open(&state)).expect("append");
+        assert!(matches!(destinations.as_slice(), [Destination::Clipboard]));
+        let held = state.borrow().clone().expect("clipboard text");
+        let (first, rest) = held.split_once("\n\n=== copytree ").expect("separator");
+        assert_eq!(first, "--- a.rs ---\nfn a() {}");
+        assert!(
+            rest.ends_with(" src ===\n\n--- b.rs ---\nfn b() {}\n"),
+            "{rest}"
+        );
+
+        // Past the limit the clipboard keeps what it held.
+        let destinations = deliver(&"c".repeat(60), &options, open(&state)).expect("refused");
+        let [Destination::TempFile(path)] = destinations.as_slice() else {
+            panic!("expected a temp file destination, got {destinations:?}");
+        };
+        assert_eq!(state.borrow().as_deref(), Some(held.as_str()));
+        assert_eq!(
+            fs::read_to_string(path).expect("read temp file"),
+            "c".repeat(60)
+        );
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn output_over_max_memory_is_spilled_and_its_path_copied() {
+        let recorded = Rc::new(RefCell::new(None));
+        let clipboard = Rc::clone(&recorded);
+        let options = OutputOptions {
+            max_memory: Some(8),
+            ..OutputOptions::default()
+        };
+        let mut stream = open_stream(&options, 1 << 20, move || {
+            Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+        });
+        for piece in ["more ", "than ", "eight\n"] {
+            stream.write_all(piece.as_bytes()).expect("write");
+        }
+        let mut reported = Recorded::default();
+        let destinations = stream
+            .finish_with(OutputStats::default(), &mut reported)
+            .expect("spilled output still succeeds");
+        assert!(
+            reported.0[0].starts_with("warning: output is 16 B, over --max-memory 8 B"),
+            "{reported:?}"
+        );
+
+        let [Destination::TempFile(path)] = destinations.as_slice() else {
+            panic!("expected a temp file destination, got {destinations:?}");
+        };
+        assert_eq!(
+            recorded.borrow().as_deref(),
+            Some(path.display().to_string().as_str())
+        );
+        assert_eq!(
+            fs::read_to_string(path).expect("read spill file"),
+            "more than eight\n"
+        );
+        assert_eq!(
+            spilled_clipboard_warning(600 * 1024 * 1024, 256 * 1024 * 1024, Path::new("/tmp/o")),
+            "output is 600 MiB, over --max-memory 256 MiB, so it went to /tmp/o as it was \
+             assembled; copied that path instead"
+        );
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn output_within_the_limit_or_forced_goes_to_the_clipboard() {
+        for clipboard_limit in [Some(64), None] {
+            let recorded = Rc::new(RefCell::new(None));
+            let clipboard = Rc::clone(&recorded);
+            let options = OutputOptions {
+                clipboard_limit,
+                ..OutputOptions::default()
+            };
+            let destinations = deliver("more than eight\n", &options, move || {
+                Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+            })
+            .expect("copied");
+            assert_eq!(destinations, [Destination::Clipboard]);
+            assert_eq!(recorded.borrow().as_deref(), Some("more than eight\n"));
+        }
+    }
+
+    #[test]
+    fn clipboard_gets_the_tree_whole_whatever_the_stdout_width() {
+        let recorded = Rc::new(RefCell::new(None));
+        let clipboard = Rc::clone(&recorded);
+        let options = OutputOptions {
+            to_clipboard: true,
+            stdout_tree_width: Some(8),
+            ..OutputOptions::default()
+        };
+        let tree = Tree {
+            lines: vec![crate::format::TreeLine {
+                prefix: String::new(),
+                name: "a_long_generated_name.js".to_string(),
+                role: None,
+                note: None,
+                path: None,
+            }],
+        };
+        let mut stream = open_stream(&options, 0, move || {
+            Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+        });
+        stream.write_tree(&tree).expect("write tree");
+        stream
+            .finish_with(OutputStats::default(), &mut Recorded::default())
+            .expect("copied");
+        assert_eq!(
+            recorded.borrow().as_deref(),
+            Some("a_long_generated_name.js\n")
+        );
+    }
+
+    #[test]
+    fn appends_two_runs_with_separator() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let path = temp.path().join("ctx.txt");
+        let options = OutputOptions {
+            out_files: vec![path.to_string_lossy().into_owned()],
+            append: true,
+            run_paths: vec!["src".to_string()],
+            ..OutputOptions::default()
+        };
+
+        deliver("first\n\n", &options, failing_clipboard).expect("first append");
+        deliver("second\n\n", &options, failing_clipboard).expect("second append");
+
+        let combined = fs::read_to_string(&path).expect("read appended file");
+        let sections: Vec<&str> = combined.split("\n\n").collect();
+        assert_eq!(sections.len(), 4);
+        assert_eq!(sections[0], "first");
+        assert!(sections[1].starts_with("=== copytree "));
+        assert!(sections[1].ends_with(" src ==="));
+        assert_eq!(sections[2], "second");
+        assert_eq!(sections[3], "");
+    }
+
+    #[test]
+    fn append_to_missing_file_writes_without_separator() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let path = temp.path().join("ctx.txt");
+        let options = OutputOptions {
+            out_files: vec![path.to_string_lossy().into_owned()],
+            append: true,
+            ..OutputOptions::default()
+        };
+
+        deliver("only\n", &options, failing_clipboard).expect("append");
+
+        assert_eq!(fs::read_to_string(&path).expect("read file"), "only\n");
+    }
+
+    #[test]
+    fn fallback_file_moves_past_a_name_already_taken() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let taken = temp.path().join("taken.txt");
+        fs::write(&taken, "someone else's").expect("seed taken file");
+
+        let mut names = [taken.clone(), temp.path().join("fresh.txt")].into_iter();
+        let (path, mut file) =
+            create_fresh_file(|| names.next().expect("another name")).expect("create file");
+        file.write_all(b"output").expect("write file");
+
+        assert_eq!(path, temp.path().join("fresh.txt"));
+        assert_eq!(fs::read_to_string(&path).expect("read file"), "output");
+        assert_eq!(
+            fs::read_to_string(&taken).expect("read taken file"),
+            "someone else's"
+        );
+    }
+
+    #[test]
+    fn atomic_write_replaces_existing_file() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("out.txt");
+        fs::write(&target, "old").expect("seed target");
+
+        let (temp, mut file) = AtomicFile::create(&target).expect("create temp file");
+        file.write_all(b"new").expect("write temp file");
+        temp.commit(file).expect("atomic write");
+
+        assert_eq!(fs::read_to_string(&target).expect("read target"), "new");
+        assert_eq!(fs::read_dir(dir).expect("list dir").count(), 1);
+    }
+
+    #[test]
+    fn failed_write_preserves_original_and_removes_temp_file() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("out.txt");
+        fs::write(&target, "original").expect("seed target");
+
+        let options = OutputOptions {
+            force: true,
+            ..options_for(&target)
+        };
+        let mut sink = FileSink::open(&target.to_string_lossy(), &options).expect("open sink");
+        sink.write(b"partial").expect("write partial");
+        Box::new(sink).discard();
+
+        assert_eq!(
+            fs::read_to_string(&target).expect("read target"),
+            "original"
+        );
+        assert_eq!(fs::read_dir(dir).expect("list dir").count(), 1);
+    }
+
+    fn options_for(target: &Path) -> OutputOptions {
+        OutputOptions {
+            out_files: vec![target.to_string_lossy().into_owned()],
+            ..OutputOptions::default()
+        }
+    }
+
+    #[test]
+    fn refuses_to_overwrite_existing_file_without_force() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("notes.txt");
+        fs::write(&target, "keep me").expect("seed target");
+
+        let err =
+            deliver("new", &options_for(&target), failing_clipboard).expect_err("expected refusal");
+
+        assert!(format!("{err:#}").contains("--force"));
+        assert_eq!(fs::read_to_string(&target).expect("read target"), "keep me");
+    }
+
+    #[test]
+    fn force_overwrites_existing_file() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("notes.txt");
+        fs::write(&target, "old").expect("seed target");
+
+        let options = OutputOptions {
+            force: true,
+            ..options_for(&target)
+        };
+        deliver("new", &options, failing_clipboard).expect("forced write");
+
+        assert_eq!(fs::read_to_string(&target).expect("read target"), "new");
+    }
+
+    #[test]
+    fn append_to_existing_file_needs_no_force() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("notes.txt");
+        fs::write(&target, "old\n\n").expect("seed target");
+
+        let options = OutputOptions {
+            append: true,
+            ..options_for(&target)
+        };
+        deliver("new\n", &options, failing_clipboard).expect("append");
+
+        let combined = fs::read_to_string(&target).expect("read target");
+        assert!(combined.starts_with("old\n\n=== copytree "));
+        assert!(combined.ends_with("new\n"));
+    }
+
+    #[test]
+    fn writes_new_file_without_force() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("notes.txt");
+
+        deliver("fresh", &options_for(&target), failing_clipboard).expect("write");
+
+        assert_eq!(fs::read_to_string(&target).expect("read target"), "fresh");
+        assert_eq!(fs::read_dir(dir).expect("list dir").count(), 1);
+    }
+
+    #[test]
+    fn mkdirs_creates_missing_parent_chain() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path().join("out");
+        let target = dir.join("snapshots").join("2024-06").join("context.txt");
+
+        let options = OutputOptions {
+            mkdirs: true,
+            ..options_for(&target)
+        };
+        deliver("nested", &options, failing_clipboard).expect("write with mkdirs");
+
+        assert_eq!(fs::read_to_string(&target).expect("read target"), "nested");
+    }
+
+    #[test]
+    fn missing_parent_without_mkdirs_names_directory() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path().join("out");
+        let target = dir.join("snapshots").join("context.txt");
+
+        let err = deliver("nested", &options_for(&target), failing_clipboard)
+            .expect_err("expected missing directory error");
+
+        let message = format!("{err:#}");
+        assert!(message.contains(&dir.join("snapshots").display().to_string()));
+        assert!(message.contains("--mkdirs"));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn mkdirs_reports_file_blocking_the_parent_path() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let blocker = dir.join("snapshots");
+        fs::write(&blocker, "not a directory").expect("write blocker");
+        let target = blocker.join("2024-06").join("context.txt");
+
+        let options = OutputOptions {
+            mkdirs: true,
+            ..options_for(&target)
+        };
+        let err = deliver("nested", &options, failing_clipboard).expect_err("expected failure");
+
+        let message = format!("{err:#}");
+        assert!(message.contains(&format!("Cannot create directory {}", blocker.display())));
+    }
+
+    fn placeholders() -> OutPlaceholders {
+        OutPlaceholders {
+            unix_time: 1_709_210_096,
+            root: "copytree".to_string(),
+        }
+    }
+
+    fn expand(template: &str) -> Result<String> {
+        expand_out_path(template, &placeholders(), |_| false)
+    }
+
+    #[test]
+    fn expands_date_placeholder() {
+        assert_eq!(expand("ctx-{date}.txt").unwrap(), "ctx-2024-02-29.txt");
+    }
+
+    #[test]
+    fn expands_time_placeholder() {
+        assert_eq!(expand("ctx-{time}.txt").unwrap(), "ctx-12-34-56.txt");
+    }
+
+    #[test]
+    fn expands_timestamp_placeholder() {
+        assert_eq!(expand("ctx-{timestamp}.txt").unwrap(), "ctx-1709210096.txt");
+    }
+
+    #[test]
+    fn expands_root_placeholder() {
+        assert_eq!(
+            expand("snapshots/{root}.txt").unwrap(),
+            "snapshots/copytree.txt"
+        );
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(
+            expand("{{date}}-{date}}}.txt").unwrap(),
+            "{date}-2024-02-29}.txt"
+        );
+    }
+
+    #[test]
+    fn counter_picks_smallest_unused_number() {
+        let taken = ["ctx-1.txt", "ctx-2.txt", "ctx-4.txt"];
+        let expanded = expand_out_path("ctx-{n}.txt", &placeholders(), |candidate| {
+            taken.contains(&candidate)
+        })
+        .unwrap();
+        assert_eq!(expanded, "ctx-3.txt");
+    }
+
+    #[test]
+    fn unknown_placeholder_lists_valid_ones() {
+        let message = format!("{:#}", expand("ctx-{branch}.txt").unwrap_err());
+        assert!(message.contains("{branch}"));
+        assert!(message.contains("{date}, {time}, {timestamp}, {root}, {n}"));
+    }
+
+    #[test]
+    fn unclosed_placeholder_is_an_error() {
+        assert!(expand("ctx-{date.txt").is_err());
+        assert!(expand("ctx-}.txt").is_err());
+    }
+
+    #[test]
+    fn file_and_clipboard_receive_identical_bytes() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("ctx.txt");
+        let recorded = Rc::new(RefCell::new(None));
+
+        let options = OutputOptions {
+            to_clipboard: true,
+            ..options_for(&target)
+        };
+        let clipboard = Rc::clone(&recorded);
+        let destinations = deliver("same bytes\n", &options, move || {
+            Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+        })
+        .expect("deliver to both sinks");
+
+        assert_eq!(
+            destinations,
+            vec![Destination::File(target.clone()), Destination::Clipboard]
+        );
+        let written = fs::read_to_string(&target).expect("read target");
+        assert_eq!(recorded.borrow().as_deref(), Some(written.as_str()));
+    }
+
+    #[test]
+    fn styling_never_reaches_file_or_clipboard() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("ctx.txt");
+        let recorded = Rc::new(RefCell::new(None));
+
+        let options = OutputOptions {
+            to_clipboard: true,
+            stdout_style: Styler::new(true),
+            ..options_for(&target)
+        };
+        let clipboard = Rc::clone(&recorded);
+        let mut stream = open_stream(&options, 0, move || {
+            Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+        });
+        stream
+            .write_styled(Role::Directory, "src")
+            .and_then(|()| stream.write_all(b"\n"))
+            .expect("write styled text");
+        assert_eq!(stream.bytes_written(), 4);
+        stream
+            .finish_with(OutputStats::default(), &mut Recorded::default())
+            .expect("deliver to both sinks");
+
+        assert_eq!(fs::read_to_string(&target).expect("read target"), "src\n");
+        assert_eq!(recorded.borrow().as_deref(), Some("src\n"));
+    }
+
+    #[test]
+    fn styled_output_only_reaches_sinks_that_show_it() {
+        let (shown, plain) = (mock(), mock());
+        let options = OutputOptions {
+            stdout_style: Styler::new(true),
+            ..mock_options()
+        };
+        let sinks = vec![
+            MockSink {
+                styled: true,
+                ..MockSink::new(&shown)
+            },
+            MockSink::new(&plain),
+        ];
+        let mut stream = mock_stream(&options, sinks);
+        stream
+            .write_styled(Role::Directory, "src")
+            .expect("write styled text");
+        stream
+            .finish_with(OutputStats::default(), &mut Recorded::default())
+            .expect("finished");
+
+        assert_ne!(shown.borrow().received, b"src");
+        assert!(String::from_utf8_lossy(&shown.borrow().received).contains("src"));
+        assert_eq!(plain.borrow().received, b"src");
+        assert!(shown.borrow().finished && plain.borrow().finished);
+    }
+
+    #[test]
+    fn a_sink_that_fails_a_write_is_discarded_and_reported_last() {
+        let (failing, working) = (mock(), mock());
+        let sinks = vec![
+            MockSink {
+                fail: Some(|| anyhow!("disk full")),
+                ..MockSink::new(&failing)
+            },
+            MockSink::new(&working),
+        ];
+        let options = mock_options();
+        let mut stream = mock_stream(&options, sinks);
+        stream.write_all(b"first ").expect("write");
+        stream.write_all(b"second").expect("write");
+        let mut reported = Recorded::default();
+        let err = stream
+            .finish_with(OutputStats::default(), &mut reported)
+            .expect_err("one sink failed");
+
+        assert_eq!(format!("{err:#}"), "1 of 2 outputs failed");
+        assert!(failing.borrow().discarded && !failing.borrow().finished);
+        assert_eq!(working.borrow().received, b"first second");
+        assert!(working.borrow().finished);
+        assert_eq!(
+            reported.0,
+            [
+                "info: Wrote 0 files, 12 B (~0 tokens) to stdout.",
+                "error: disk full"
+            ]
+        );
+    }
+
+    #[test]
+    fn a_closed_stdout_lets_the_other_sinks_finish() {
+        let closed = || anyhow::Error::new(StdoutClosed);
+        let (stdout, file) = (mock(), mock());
+        let options = mock_options();
+        let sinks = vec![
+            MockSink {
+                fail: Some(closed),
+                ..MockSink::new(&stdout)
+            },
+            MockSink::new(&file),
+        ];
+        let mut stream = mock_stream(&options, sinks);
+        stream.write_all(b"text").expect("write");
+        assert!(!stream.reader_gone());
+        let err = stream
+            .finish_with(OutputStats::default(), &mut Recorded::default())
+            .expect_err("stdout closed");
+        assert!(err.is::<StdoutClosed>());
+        assert!(file.borrow().finished);
+
+        let alone = mock();
+        let sinks = vec![MockSink {
+            fail: Some(closed),
+            ..MockSink::new(&alone)
+        }];
+        let mut stream = mock_stream(&options, sinks);
+        stream.write_all(b"text").expect("write");
+        assert!(stream.reader_gone());
+    }
+
+    #[test]
+    fn unavailable_clipboard_does_not_fail_when_file_was_written() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("ctx.txt");
+
+        let options = OutputOptions {
+            to_clipboard: true,
+            ..options_for(&target)
+        };
+        let destinations =
+            deliver("text", &options, failing_clipboard).expect("file sink succeeded");
+
+        assert_eq!(destinations, vec![Destination::File(target.clone())]);
+        assert_eq!(dir.read_dir().expect("list dir").count(), 1);
+    }
+
+    #[test]
+    fn requested_clipboard_fails_the_run_after_the_file_is_written() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("ctx.txt");
+
+        let options = OutputOptions {
+            to_clipboard: true,
+            clipboard_requested: true,
+            ..options_for(&target)
+        };
+        let err = deliver("text", &options, failing_clipboard).expect_err("clipboard failed");
+
+        assert_eq!(format!("{err:#}"), "1 of 2 outputs failed");
+        assert_eq!(fs::read_to_string(&target).expect("read target"), "text");
+    }
+
+    #[test]
+    fn failed_file_sink_is_reported_after_other_sinks_run() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let existing = dir.join("existing.txt");
+        fs::write(&existing, "keep").expect("seed existing");
+        let fresh = dir.join("fresh.txt");
+
+        let options = OutputOptions {
+            out_files: vec![
+                existing.to_string_lossy().into_owned(),
+                fresh.to_string_lossy().into_owned(),
+            ],
+            ..OutputOptions::default()
+        };
+        let err = deliver("text", &options, failing_clipboard).expect_err("one sink failed");
+
+        assert_eq!(format!("{err:#}"), "1 of 2 outputs failed");
+        assert_eq!(
+            fs::read_to_string(&existing).expect("read existing"),
+            "keep"
+        );
+        assert_eq!(fs::read_to_string(&fresh).expect("read fresh"), "text");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pipe_cmd_output_reaches_every_sink_and_a_failure_reaches_none() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join("ctx.txt");
+        let recorded = Rc::new(RefCell::new(None));
+
+        let options = OutputOptions {
+            to_clipboard: true,
+            force: true,
+            pipe_cmd: Some("tr a-z A-Z".to_string()),
+            pipe_timeout: Duration::from_secs(5),
+            ..options_for(&target)
+        };
+        let clipboard = Rc::clone(&recorded);
+        let destinations = deliver("--- a.rs ---\nfn a() {}\n", &options, move || {
+            Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+        })
+        .expect("deliver through the command");
+        assert_eq!(
+            destinations,
+            vec![Destination::File(target.clone()), Destination::Clipboard]
+        );
+        assert_eq!(
+            fs::read_to_string(&target).expect("read target"),
+            "--- A.RS ---\nFN A() {}\n"
+        );
+        assert_eq!(
+            recorded.borrow().as_deref(),
+            Some("--- A.RS ---\nFN A() {}\n")
+        );
+
+        *recorded.borrow_mut() = None;
+        let options = OutputOptions {
+            pipe_cmd: Some("cat >/dev/null; exit 4".to_string()),
+            ..options
+        };
+        let clipboard = Rc::clone(&recorded);
+        let err = deliver("new text\n", &options, move || {
+            Ok(Box::new(RecordingClipboard(clipboard)) as Box<dyn ClipboardBackend>)
+        })
+        .expect_err("the command failed");
+        assert!(
+            format!("{err:#}").ends_with("exited with exit status: 4"),
+            "{err:#}"
+        );
+        assert_eq!(
+            fs::read_to_string(&target).expect("read target"),
+            "--- A.RS ---\nFN A() {}\n"
+        );
+        assert_eq!(recorded.borrow().as_deref(), None);
+        assert_eq!(dir.read_dir().expect("list dir").count(), 1);
+    }
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn write_and_read_back(file_name: &str, options: OutputOptions) -> Vec<u8> {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let dir = temp.path();
+        let target = dir.join(file_name);
+        let options = OutputOptions {
+            out_files: vec![target.to_string_lossy().into_owned()],
+            ..options
+        };
+        deliver(SAMPLE_OUTPUT, &options, failing_clipboard).expect("write");
+
+        fs::read(&target).expect("read target")
+    }
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    const SAMPLE_OUTPUT: &str = "src\n└─ main.rs\n\n--- src/main.rs ---\nfn main() {}\n\n";
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gz_extension_compresses_with_gzip() {
+        use std::io::Read;
+
+        let plain = write_and_read_back("ctx.txt", OutputOptions::default());
+        let compressed = write_and_read_back("ctx.txt.gz", OutputOptions::default());
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .expect("decode gzip");
+        assert_ne!(compressed, plain);
+        assert_eq!(decoded, plain);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn explicit_zstd_compresses_regardless_of_extension() {
+        let plain = write_and_read_back("ctx.txt", OutputOptions::default());
+        let options = OutputOptions {
+            compress: Some(Compression::Zstd),
+            ..OutputOptions::default()
+        };
+        let compressed = write_and_read_back("ctx.snapshot", options);
+
+        let decoded = zstd::decode_all(compressed.as_slice()).expect("decode zstd");
+        assert_eq!(decoded, plain);
+    }
+
+    fn parse_args(cli: &[&str]) -> Args {
+        use clap::Parser;
+        crate::args::Cli::parse_from(std::iter::once("copytree").chain(cli.iter().copied())).copy
+    }
+
+    #[test]
+    fn piped_stdout_defaults_to_stdout_sink() {
+        let options = OutputOptions::from_args(&parse_args(&[]), false);
+        assert!(options.to_stdout);
+        assert!(!options.uses_clipboard());
+    }
+
+    #[test]
+    fn terminal_stdout_defaults_to_clipboard() {
+        let options = OutputOptions::from_args(&parse_args(&[]), true);
+        assert!(!options.to_stdout);
+        assert!(options.uses_clipboard());
+    }
+
+    #[test]
+    fn open_without_out_adds_a_temp_file_and_keeps_clipboard() {
+        let mut options = OutputOptions::from_args(&parse_args(&["--open"]), true);
+        options.ensure_out_file();
+        assert_eq!(options.out_files.len(), 1);
+        assert!(Path::new(&options.out_files[0]).starts_with(std::env::temp_dir()));
+        assert!(options.uses_clipboard());
+
+        let mut piped = OutputOptions::from_args(&parse_args(&["--open"]), false);
+        piped.ensure_out_file();
+        assert!(piped.to_stdout);
+        assert!(!piped.uses_clipboard());
+    }
+
+    #[test]
+    fn explicit_sinks_win_over_pipe_detection() {
+        let clipboard = OutputOptions::from_args(&parse_args(&["--clipboard"]), false);
+        assert!(!clipboard.to_stdout);
+        assert!(clipboard.uses_clipboard());
+
+        let file = OutputOptions::from_args(&parse_args(&["--out", "ctx.txt"]), false);
+        assert!(!file.to_stdout);
+        assert!(!file.uses_clipboard());
+        assert_eq!(file.out_files, vec!["ctx.txt".to_string()]);
+    }
+
+    #[test]
+    fn summary_line_reports_files_size_and_tokens() {
+        let stats = OutputStats {
+            files: 182,
+            tokens: 24_000,
+            sampled: None,
+        };
+        assert_eq!(
+            summary_line(
+                stats,
+                96 * 1024,
+                &SinkReport::from(Destination::Clipboard),
+                Styler::default()
+            ),
+            "Copied 182 files, 96 KiB (~24k tokens) to clipboard."
+        );
+        assert_eq!(
+            summary_line(
+                stats,
+                96 * 1024,
+                &SinkReport::from(Destination::Stdout),
+                Styler::default()
+            ),
+            "Wrote 182 files, 96 KiB (~24k tokens) to stdout."
+        );
+
+        let single = OutputStats {
+            files: 1,
+            tokens: 4,
+            sampled: None,
+        };
+        let compressed = SinkReport {
+            destination: Destination::File(PathBuf::from("ctx.txt.gz")),
+            appended: true,
+            compressed_bytes: Some(600),
+            copied: None,
+            reply: None,
+        };
+        assert_eq!(
+            summary_line(single, 1536, &compressed, Styler::default()),
+            "Appended 1 file, 1.5 KiB (~4 tokens) to ctx.txt.gz (600 B compressed)."
+        );
+
+        let sampled = OutputStats {
+            sampled: Some(Sampled {
+                shown: 50,
+                total: 18_400,
+                seed: 42,
+            }),
+            ..stats
+        };
+        assert_eq!(
+            summary_line(
+                sampled,
+                96 * 1024,
+                &SinkReport::from(Destination::Stdout),
+                Styler::default()
+            ),
+            "Wrote 182 files, 96 KiB (~24k tokens) to stdout, showing 50 of 18,400 files, seed 42."
+        );
+    }
+
+    #[test]
+    fn formats_sizes_and_counts() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(96 * 1024), "96 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(approximate_count(950), "950");
+        assert_eq!(approximate_count(1_500), "1.5k");
+        assert_eq!(approximate_count(24_400), "24k");
+        assert_eq!(approximate_count(1_200_000), "1.2M");
+        assert_eq!(grouped_count(950), "950");
+        assert_eq!(grouped_count(1_212), "1,212");
+        assert_eq!(grouped_count(1_200_000), "1,200,000");
+    }
+
+    #[test]
+    fn separator_records_time_and_paths() {
+        let time = UtcDateTime::from_unix(0);
+        let paths = vec!["src".to_string(), "tests".to_string()];
+        assert_eq!(
+            append_separator(&time, &paths),
+            "=== copytree 1970-01-01T00:00:00Z src tests ==="
+        );
+    }
+
+    #[test]
+    fn auto_detects_a_command_for_the_session() {
+        let session = |os, wayland: bool, x11: bool, wsl| Session {
+            os,
+            wayland_display: wayland.then(|| "wayland-0".to_string()),
+            display: x11.then(|| ":0".to_string()),
+            wsl,
+            ..Session::default()
+        };
+        let copy = |session: &Session, installed: &[&str]| {
+            detect_command(session, |program| installed.contains(&program))
+                .map(|command| command.copy)
+        };
+        let all = ["clip.exe", "pbcopy", "wl-copy", "xclip", "xsel"];
+        assert_eq!(
+            copy(&session("linux", true, true, true), &all).as_deref(),
+            Some("clip.exe")
+        );
+        assert_eq!(
+            copy(&session("macos", false, false, false), &all).as_deref(),
+            Some("pbcopy")
+        );
+        assert_eq!(
+            copy(&session("linux", true, true, false), &all).as_deref(),
+            Some("wl-copy")
+        );
+        assert_eq!(
+            copy(&session("linux", true, true, false), &["xsel"]).as_deref(),
+            Some("xsel --clipboard --input")
+        );
+        assert_eq!(
+            copy(&session("linux", false, true, false), &all).as_deref(),
+            Some("xclip -selection clipboard")
+        );
+        assert_eq!(copy(&session("linux", false, false, false), &all), None);
+        assert_eq!(copy(&session("linux", true, true, false), &[]), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_failing_backend_falls_back_to_the_next() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let target = temp.path().join("clipboard.txt");
+        let command = CommandClipboard::new(&format!("cat > '{}'", target.display()));
+        let mut backends = Backends(vec![
+            ("arboard".to_string(), Err(anyhow!("no display server"))),
+            (command.label(), Ok(Box::new(command))),
+        ]);
+        backends.set_text("hello\n").expect("the command copies");
+        assert_eq!(fs::read_to_string(&target).expect("copied"), "hello\n");
+        let err = backends.get_text().expect_err("cat cannot paste");
+        assert!(
+            format!("{err:#}").ends_with("`: cannot read the clipboard back"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn failures_name_every_backend_tried() {
+        let mut backends = Backends(vec![
+            ("arboard".to_string(), Err(anyhow!("no display server"))),
+            (
+                "command `exit 3`".to_string(),
+                Ok(Box::new(CommandClipboard::new("exit 3"))),
+            ),
+        ]);
+        let err = backends.set_text("hello\n").expect_err("nothing works");
+        let message = format!("{err:#}");
+        assert!(
+            message.starts_with("arboard: no display server; command `exit 3`: exited with "),
+            "{message}"
+        );
+
+        let mut none_found = open_clipboard(ClipboardKind::Command, None, || None);
+        let err = none_found.set_text("hello\n").expect_err("no command");
+        assert_eq!(
+            format!("{err:#}"),
+            "command: none found for this session; pass --clipboard-cmd"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use crate::format::{Tree, TreeLine};
+use crate::output;
+use crate::separators;
+use crate::style::Role;
+use copytree::tree::{self, TreeOptions};
+use std::path::{Path, PathBuf};
+
+pub use copytree::tree::{NodeId, ROOT};
+
+/// What the tree line of a file or directory shows besides its name.
+#[derive(Debug, Default)]
+pub struct Node {
+    /// Style role of a file; directories are always [`Role::Directory`].
+    pub role: Option<Role>,
+    /// What a symlink points at, shown as `name -> target`.
+    pub link_target: Option<PathBuf>,
+    /// Shown after the name: `--tree-tokens` counts and `--fit-tokens` drops.
+    pub note: Option<String>,
+    /// Files `--max-per-dir` left out of this directory.
+    pub dropped: Option<usize>,
+}
+
+/// The walked paths, each with its [`Node`].
+#[derive(Default)]
+pub struct PathTree {
+    paths: tree::PathTree<Node>,
+}
+
+impl PathTree {
+    /// Adds `path` and its ancestors and returns the node of `path`.
+    pub fn insert(&mut self, path: &Path) -> NodeId {
+        self.paths.insert(path)
+    }
+
+    /// The node of `path`, if it was inserted.
+    pub fn find(&self, path: &Path) -> Option<NodeId> {
+        self.paths.find(path)
+    }
+
+    /// The root and then each node on the way to `path`, as far as the
+    /// tree has them.
+    pub fn lineage<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = NodeId> + 'a {
+        self.paths.lineage(path)
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.paths.get_mut(id)
+    }
+
+    pub fn is_dir(&self, id: NodeId) -> bool {
+        self.paths.is_dir(id)
+    }
+
+    fn display_name(&self, id: NodeId) -> String {
+        Path::new(self.paths.name(id))
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| separators::display(&self.paths.path(id)))
+    }
+
+    /// Renders the tree below `root` under a first line reading
+    /// `root_label`. Children are listed in path order, each directory's
+    /// `--max-per-dir` note last.
+    pub fn render(&self, root_label: String, root: NodeId) -> Tree {
+        let mut tree = Tree::default();
+        tree.lines.push(TreeLine {
+            prefix: String::new(),
+            name: root_label,
+            role: Some(Role::Directory),
+            note: self.paths.get(root).note.clone(),
+            path: None,
+        });
+        if self.paths.is_empty() {
+            return tree;
+        }
+
+        let rows = self
+            .paths
+            .rows(root, &TreeOptions::default(), |node| node.dropped.is_some());
+        for row in rows {
+            let node = self.paths.get(row.id);
+            if row.trailer {
+                let count = node.dropped.unwrap_or_default();
+                tree.lines.push(TreeLine {
+                    prefix: row.prefix,
+                    name: format!(
+                        "… {} more file{} in this directory",
+                        output::grouped_count(count as u64),
+                        if count == 1 { "" } else { "s" }
+                    ),
+                    role: Some(Role::Skipped),
+                    note: None,
+                    path: None,
+                });
+                continue;
+            }
+            let is_dir = self.is_dir(row.id);
+            tree.lines.push(TreeLine {
+                prefix: row.prefix,
+                name: match &node.link_target {
+                    Some(target) => format!(
+                        "{} -> {}",
+                        self.display_name(row.id),
+                        separators::display(target)
+                    ),
+                    None => self.display_name(row.id),
+                },
+                role: if is_dir {
+                    Some(Role::Directory)
+                } else {
+                    node.role
+                },
+                note: node.note.clone(),
+                path: (!is_dir).then(|| separators::display(&self.paths.path(row.id))),
+            });
+        }
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_in_path_order_with_links_notes_and_drops() {
+        let mut tree = PathTree::default();
+        for path in [
+            "src/main.rs",
+            "src/B.rs",
+            "src/deep/z.rs",
+            "README.md",
+            ".hidden",
+            "../other/o.rs",
+            "link.rs",
+        ] {
+            tree.insert(Path::new(path));
+        }
+        let link = tree.find(Path::new("link.rs")).expect("inserted");
+        tree.node_mut(link).role = Some(Role::Symlink);
+        tree.node_mut(link).link_target = Some(PathBuf::from("src/main.rs"));
+        let src = tree.find(Path::new("src")).expect("inserted");
+        tree.node_mut(src).dropped = Some(1_200);
+        tree.node_mut(src).note = Some("(~40 tok)".to_string());
+
+        let rendered = tree.render(".".to_string(), ROOT);
+        assert_eq!(
+            rendered.plain(),
+            "\
+.
+├─ ..
+│  └─ other
+│     └─ o.rs
+├─ .hidden
+├─ README.md
+├─ link.rs -> src/main.rs
+└─ src  (~40 tok)
+   ├─ B.rs
+   ├─ deep
+   │  └─ z.rs
+   ├─ main.rs
+   └─ … 1,200 more files in this directory
+"
+        );
+        let leaf = rendered.lines.iter().find(|line| line.name == "o.rs");
+        assert_eq!(
+            leaf.and_then(|line| line.path.as_deref()),
+            Some("../other/o.rs")
+        );
+    }
+}
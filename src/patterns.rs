@@ -0,0 +1,354 @@
+use crate::args::WalkArgs;
+use crate::walker;
+use anyhow::{Context, Result};
+use globset::{GlobSet, GlobSetBuilder};
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// The glob patterns of one flag, compiled once per run. Clones share the
+/// compiled set, so the walk of every root and every later check of a
+/// file's contents consult the same matcher, and a pattern cannot mean one
+/// thing to the walk and another to the contents.
+///
+/// As in `.gitignore`, a pattern without a `/` also matches any one
+/// component of the path below its root, so `target` leaves out
+/// `src/target/x`; the directories a root sits in never count. A pattern
+/// with a `/` stays anchored to the path as a whole.
+#[derive(Debug, Clone)]
+pub struct Patterns {
+    patterns: Arc<[String]>,
+    set: Arc<GlobSet>,
+    /// The patterns without a `/`, matched against each component, and the
+    /// index of each in `patterns`.
+    names: Arc<GlobSet>,
+    name_indices: Arc<[usize]>,
+    /// The roots of the walk, absolute; a glob root by the directory it
+    /// searches.
+    roots: Arc<[PathBuf]>,
+    current_dir: Arc<Path>,
+}
+
+impl Patterns {
+    /// The patterns of `flag` as one matcher, or `None` when there are
+    /// none. Paths are also tried relative to `current_dir`, and their
+    /// components below the deepest of the absolute `roots` holding them.
+    pub fn new(
+        patterns: &[String],
+        flag: &str,
+        roots: &[PathBuf],
+        current_dir: &Path,
+    ) -> Result<Option<Self>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = GlobSetBuilder::new();
+        let mut names = GlobSetBuilder::new();
+        let mut name_indices = Vec::new();
+        for (index, pattern) in patterns.iter().enumerate() {
+            let glob = walker::glob(pattern)
+                .with_context(|| format!("Invalid {} glob: {}", flag, pattern))?;
+            if !pattern.contains('/') {
+                names.add(glob.clone());
+                name_indices.push(index);
+            }
+            builder.add(glob);
+        }
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .with_context(|| format!("Failed to build {} glob set", flag))
+        };
+        Ok(Some(Self {
+            patterns: patterns.into(),
+            set: Arc::new(build(builder)?),
+            names: Arc::new(build(names)?),
+            name_indices: name_indices.into(),
+            roots: roots.into(),
+            current_dir: current_dir.into(),
+        }))
+    }
+
+    /// The first pattern matching `path`, tried as given and relative to
+    /// the current directory, or for a pattern without a `/`, against a
+    /// component of the path below its root.
+    pub fn first_match(&self, path: &Path) -> Option<&str> {
+        let first = |candidate: &Path| self.set.matches(candidate).into_iter().min();
+        let whole = first(path).or_else(|| first(&self.relative(path)));
+        let component = self.component_match(path);
+        whole
+            .into_iter()
+            .chain(component)
+            .min()
+            .map(|index| self.patterns[index].as_str())
+    }
+
+    /// Whether some pattern matches `path`, as [`Self::first_match`] tries it.
+    pub fn is_match(&self, path: &Path) -> bool {
+        self.set.is_match(path)
+            || self.set.is_match(self.relative(path))
+            || self.component_match(path).is_some()
+    }
+
+    /// Whether some pattern matches `path` exactly as given.
+    pub fn is_match_as_given(&self, path: &Path) -> bool {
+        self.set.is_match(path)
+    }
+
+    /// Whether some pattern matches the bare file name `name`.
+    pub fn matches_name(&self, name: &OsStr) -> bool {
+        self.set.is_match(name)
+    }
+
+    /// The first pattern without a `/` that matches a component of `path`
+    /// below its root.
+    fn component_match(&self, path: &Path) -> Option<usize> {
+        self.below_root(path)
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .flat_map(|name| self.names.matches(name))
+            .map(|index| self.name_indices[index])
+            .min()
+    }
+
+    /// The part of `path` below the deepest root holding it, or when no
+    /// root does, `path` relative to the current directory.
+    fn below_root(&self, path: &Path) -> PathBuf {
+        let absolute = crate::lexical_absolute(path, &self.current_dir);
+        self.roots
+            .iter()
+            .filter_map(|root| absolute.strip_prefix(root).ok())
+            .min_by_key(|below| below.components().count())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| self.relative(path))
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.current_dir)
+            .unwrap_or(path)
+            .components()
+            .filter(|component| !matches!(component, Component::CurDir))
+            .collect()
+    }
+}
+
+/// Every pattern flag of a walk, compiled once after the configuration is
+/// applied and shared by root expansion, the walk, and the content checks.
+#[derive(Debug)]
+pub struct Matchers {
+    pub prune: Option<Patterns>,
+    pub exclude: Option<Patterns>,
+    pub exclude_contents: Option<Patterns>,
+    pub always_include: Option<Patterns>,
+}
+
+impl Matchers {
+    pub fn new(walk: &WalkArgs, current_dir: &Path) -> Result<Self> {
+        let roots: Vec<PathBuf> = walk
+            .paths
+            .iter()
+            .map(|path| {
+                let root = if walker::is_glob_path(path) {
+                    walker::glob_base(path)
+                } else {
+                    PathBuf::from(path)
+                };
+                crate::lexical_absolute(&root, current_dir)
+            })
+            .collect();
+        let patterns =
+            |patterns: &[String], flag: &str| Patterns::new(patterns, flag, &roots, current_dir);
+        Ok(Self {
+            prune: patterns(&walk.prune, "--prune")?,
+            exclude: patterns(&walk.exclude, "--exclude")?,
+            exclude_contents: patterns(&walk.exclude_contents, "--exclude-contents")?,
+            always_include: patterns(&walk.always_include, "--always-include")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Patterns {
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        Patterns::new(&patterns, "--exclude", &[], Path::new("/project"))
+            .expect("valid patterns")
+            .expect("a pattern")
+    }
+
+    #[test]
+    fn exclude_matches_relative_path() {
+        let pattern = vec!["src/*".to_string()];
+        let current_dir = Path::new("/project");
+        let set = Patterns::new(&pattern, "--exclude", &[], current_dir).expect("exclude set");
+        assert!(set.is_some());
+        let path = Path::new("/project/src/main.rs");
+        assert!(set.as_ref().unwrap().first_match(path).is_some());
+    }
+
+    #[test]
+    fn exclude_matches_with_leading_dot() {
+        let pattern = vec!["src/*".to_string()];
+        let current_dir = Path::new("/project");
+        let set = Patterns::new(&pattern, "--exclude", &[], current_dir).expect("exclude set");
+        assert!(set.is_some());
+        let path = Path::new("./src/main.rs");
+        assert!(set.as_ref().unwrap().first_match(path).is_some());
+    }
+
+    #[test]
+    fn exclude_matches_plain_relative_path() {
+        let pattern = vec!["src/*".to_string()];
+        let current_dir = Path::new("/project");
+        let set = Patterns::new(&pattern, "--exclude", &[], current_dir).expect("exclude set");
+        assert!(set.is_some());
+        let path = Path::new("src/main.rs");
+        assert!(set.as_ref().unwrap().first_match(path).is_some());
+    }
+
+    #[test]
+    fn exclude_ignores_paths_outside_the_current_directory() {
+        let set = patterns(&["src/*"]);
+        assert_eq!(set.first_match(Path::new("/elsewhere/src/main.rs")), None);
+        assert!(!set.is_match(Path::new("/elsewhere/src/main.rs")));
+    }
+
+    #[test]
+    fn exclude_names_the_pattern_that_matched() {
+        let set = patterns(&["src/*", "*.lock"]);
+        assert_eq!(set.first_match(Path::new("src/main.rs")), Some("src/*"));
+        assert_eq!(set.first_match(Path::new("Cargo.lock")), Some("*.lock"));
+    }
+
+    #[test]
+    fn is_match_as_given_takes_the_path_literally() {
+        let set = patterns(&["src/*"]);
+        assert!(set.is_match_as_given(Path::new("src/main.rs")));
+        assert!(!set.is_match_as_given(Path::new("./src/main.rs")));
+    }
+
+    #[test]
+    fn the_first_pattern_given_wins() {
+        let set = patterns(&["*.rs", "src/*"]);
+        assert_eq!(set.first_match(Path::new("src/main.rs")), Some("*.rs"));
+    }
+
+    #[test]
+    fn patterns_without_a_slash_match_any_component() {
+        let set = patterns(&["target", "*.log"]);
+        for path in [
+            "target",
+            "src/target/x",
+            "./crates/a/target/debug/app",
+            "/project/target/release/app",
+        ] {
+            assert_eq!(set.first_match(Path::new(path)), Some("target"), "{path}");
+        }
+        for path in ["app.log", "logs/2026/app.log", "/project/out.log/part.txt"] {
+            assert_eq!(set.first_match(Path::new(path)), Some("*.log"), "{path}");
+        }
+        for path in ["src/targets/x", "src/my-target/x", "log/app.txt"] {
+            assert!(!set.is_match(Path::new(path)), "{path}");
+        }
+    }
+
+    #[test]
+    fn components_above_the_current_directory_do_not_match() {
+        let set = patterns(&["project"]);
+        assert!(!set.is_match(Path::new("/project/src/main.rs")));
+        assert!(set.is_match(Path::new("/project/project/main.rs")));
+    }
+
+    #[test]
+    fn components_only_count_below_the_root() {
+        use clap::Parser;
+
+        let args = crate::args::Cli::parse_from([
+            "copytree",
+            "/work/build/app",
+            "../project",
+            "-x",
+            "build",
+            "work",
+        ])
+        .copy;
+        let matchers = Matchers::new(&args.walk, Path::new("/work/cwd")).expect("valid patterns");
+        let set = matchers.exclude.expect("a pattern");
+        for path in ["/work/build/app/src/main.rs", "../project/src/main.rs"] {
+            assert_eq!(set.first_match(Path::new(path)), None, "{path}");
+        }
+        for path in ["/work/build/app/build/out.o", "../project/work/notes.md"] {
+            assert!(set.is_match(Path::new(path)), "{path}");
+        }
+    }
+
+    #[test]
+    fn patterns_with_a_slash_stay_anchored() {
+        let set = patterns(&["docs/internal"]);
+        for path in ["docs/internal", "./docs/internal", "/project/docs/internal"] {
+            assert!(set.is_match(Path::new(path)), "{path}");
+        }
+        for path in ["src/docs/internal", "internal", "docs"] {
+            assert!(!set.is_match(Path::new(path)), "{path}");
+        }
+    }
+
+    #[test]
+    fn the_first_pattern_given_wins_over_a_component_match() {
+        let set = patterns(&["*.rs", "target"]);
+        assert_eq!(set.first_match(Path::new("target/x.rs")), Some("*.rs"));
+        let set = patterns(&["target", "*.rs"]);
+        assert_eq!(set.first_match(Path::new("target/x.rs")), Some("target"));
+    }
+
+    #[test]
+    fn backslashes_in_patterns_escape_metacharacters() {
+        for (pattern, literal, wildcard) in [
+            (r"what\?.md", "what?.md", "whats.md"),
+            (r"\*.log", "*.log", "app.log"),
+            (r"\[draft\] notes.txt", "[draft] notes.txt", "d notes.txt"),
+        ] {
+            let set = patterns(&[pattern]);
+            assert!(
+                set.is_match(Path::new(literal)),
+                "{pattern} should match {literal}"
+            );
+            assert!(
+                !set.is_match(Path::new(wildcard)),
+                "{pattern} should not match {wildcard}"
+            );
+        }
+
+        // A backslash in a name is only a path separator on Windows.
+        #[cfg(unix)]
+        {
+            let set = patterns(&[r"a\\b.txt"]);
+            assert!(set.is_match(Path::new(r"a\b.txt")));
+            assert!(!set.is_match(Path::new("a/b.txt")));
+        }
+
+        let err = Patterns::new(
+            &[r"notes\".to_string()],
+            "--exclude",
+            &[],
+            Path::new("/project"),
+        )
+        .expect_err("dangling escape");
+        assert!(
+            format!("{err:#}").starts_with(r"Invalid --exclude glob: notes\"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn clones_share_one_compiled_set() {
+        let set = patterns(&["*.rs"]);
+        let clone = set.clone();
+        assert!(Arc::ptr_eq(&set.set, &clone.set));
+    }
+}
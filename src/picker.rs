@@ -0,0 +1,365 @@
+use crate::output::human_size;
+use anyhow::{bail, Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, style, terminal};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// A file offered in the picker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub path: PathBuf,
+    /// Path as shown to the user (relative to the current directory).
+    pub label: String,
+    pub size: Option<u64>,
+}
+
+/// Keys the picker reacts to, independent of the terminal backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Char(char),
+    Backspace,
+    Enter,
+    Esc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Continue,
+    Confirm,
+    Cancel,
+}
+
+/// Check state, cursor, and filter of the picker, with no terminal I/O.
+#[derive(Debug)]
+pub struct Selection {
+    candidates: Vec<Candidate>,
+    checked: Vec<bool>,
+    /// Position within the filtered list.
+    cursor: usize,
+    filter: String,
+    editing_filter: bool,
+}
+
+impl Selection {
+    /// Starts with every file checked, or only `previous` when given.
+    pub fn new(candidates: Vec<Candidate>, previous: Option<&HashSet<PathBuf>>) -> Self {
+        let checked = candidates
+            .iter()
+            .map(|candidate| previous.is_none_or(|paths| paths.contains(&candidate.path)))
+            .collect();
+        Self {
+            candidates,
+            checked,
+            cursor: 0,
+            filter: String::new(),
+            editing_filter: false,
+        }
+    }
+
+    /// Indices of the candidates matching the filter, in list order.
+    pub fn visible(&self) -> Vec<usize> {
+        let needle = self.filter.to_lowercase();
+        (0..self.candidates.len())
+            .filter(|&index| {
+                self.candidates[index]
+                    .label
+                    .to_lowercase()
+                    .contains(&needle)
+            })
+            .collect()
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> Outcome {
+        if self.editing_filter {
+            match key {
+                Key::Char(ch) => self.filter.push(ch),
+                Key::Backspace => {
+                    self.filter.pop();
+                }
+                Key::Enter | Key::Esc => self.editing_filter = false,
+                Key::Up | Key::Down => {}
+            }
+            self.cursor = self.cursor.min(self.visible().len().saturating_sub(1));
+            return Outcome::Continue;
+        }
+
+        let visible = self.visible();
+        match key {
+            Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down | Key::Char('j') => {
+                self.cursor = (self.cursor + 1).min(visible.len().saturating_sub(1))
+            }
+            Key::Char(' ') => {
+                if let Some(&index) = visible.get(self.cursor) {
+                    self.checked[index] = !self.checked[index];
+                }
+            }
+            Key::Char('a') => {
+                let all_checked = visible.iter().all(|&index| self.checked[index]);
+                for index in visible {
+                    self.checked[index] = !all_checked;
+                }
+            }
+            Key::Char('/') => self.editing_filter = true,
+            Key::Enter => return Outcome::Confirm,
+            Key::Esc | Key::Char('q') => return Outcome::Cancel,
+            Key::Char(_) | Key::Backspace => {}
+        }
+        Outcome::Continue
+    }
+
+    pub fn chosen(&self) -> Vec<PathBuf> {
+        self.candidates
+            .iter()
+            .zip(&self.checked)
+            .filter(|(_, &checked)| checked)
+            .map(|(candidate, _)| candidate.path.clone())
+            .collect()
+    }
+
+    /// The lines to draw: a status line followed by the visible window of
+    /// at most `rows` candidates around the cursor.
+    pub fn render_lines(&self, rows: usize) -> Vec<String> {
+        let visible = self.visible();
+        let checked_count = self.checked.iter().filter(|&&checked| checked).count();
+        let mut lines = vec![format!(
+            "{}/{} selected  [space] toggle  [a] all  [/] filter{}  [enter] confirm  [esc] cancel",
+            checked_count,
+            self.candidates.len(),
+            if self.editing_filter || !self.filter.is_empty() {
+                format!(": {}", self.filter)
+            } else {
+                String::new()
+            }
+        )];
+        let rows = rows.max(1);
+        let start = self.cursor.saturating_sub(rows - 1);
+        for (position, &index) in visible.iter().enumerate().skip(start).take(rows) {
+            let candidate = &self.candidates[index];
+            lines.push(format!(
+                "{} [{}] {} ({})",
+                if position == self.cursor { '>' } else { ' ' },
+                if self.checked[index] { 'x' } else { ' ' },
+                candidate.label,
+                candidate.size.map_or_else(|| "?".to_string(), human_size)
+            ));
+        }
+        lines
+    }
+}
+
+/// Runs the picker on the terminal and returns the confirmed paths.
+pub fn pick(
+    candidates: Vec<Candidate>,
+    previous: Option<&HashSet<PathBuf>>,
+) -> Result<Vec<PathBuf>> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        bail!("--interactive needs a terminal; stdin or stderr is not a TTY");
+    }
+    let mut selection = Selection::new(candidates, previous);
+
+    let mut screen = io::stderr();
+    terminal::enable_raw_mode().context("Failed to enable raw terminal mode")?;
+    let result = execute!(screen, terminal::EnterAlternateScreen, cursor::Hide)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| event_loop(&mut selection, &mut screen));
+    let _ = execute!(screen, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+
+    match result? {
+        Outcome::Confirm => Ok(selection.chosen()),
+        _ => bail!("Selection cancelled"),
+    }
+}
+
+fn event_loop(selection: &mut Selection, screen: &mut impl Write) -> Result<Outcome> {
+    loop {
+        let (_, height) = terminal::size().unwrap_or((80, 24));
+        queue!(
+            screen,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+        for (row, line) in selection
+            .render_lines(usize::from(height).saturating_sub(1))
+            .iter()
+            .enumerate()
+        {
+            queue!(screen, cursor::MoveTo(0, row as u16), style::Print(line))?;
+        }
+        screen.flush()?;
+
+        let Event::Key(key_event) = event::read()? else {
+            continue;
+        };
+        let Some(key) = map_key(key_event) else {
+            continue;
+        };
+        let outcome = selection.handle_key(key);
+        if outcome != Outcome::Continue {
+            return Ok(outcome);
+        }
+    }
+}
+
+fn map_key(event: KeyEvent) -> Option<Key> {
+    if event.kind == KeyEventKind::Release {
+        return None;
+    }
+    if event.modifiers.contains(KeyModifiers::CONTROL) && event.code == KeyCode::Char('c') {
+        return Some(Key::Esc);
+    }
+    match event.code {
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Char(ch) => Some(Key::Char(ch)),
+        KeyCode::Backspace => Some(Key::Backspace),
+        KeyCode::Enter => Some(Key::Enter),
+        KeyCode::Esc => Some(Key::Esc),
+        _ => None,
+    }
+}
+
+/// Where the last confirmed selection is kept for `--last`.
+pub fn selection_cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("copytree").join("last-selection.txt"))
+}
+
+pub fn load_selection(cache: &Path) -> Result<HashSet<PathBuf>> {
+    let text = fs::read_to_string(cache)
+        .with_context(|| format!("No previous selection found at {}", cache.display()))?;
+    Ok(text.lines().map(PathBuf::from).collect())
+}
+
+pub fn save_selection(cache: &Path, chosen: &[PathBuf]) -> Result<()> {
+    if let Some(parent) = cache.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let mut text = String::new();
+    for path in chosen {
+        text.push_str(&path.to_string_lossy());
+        text.push('\n');
+    }
+    fs::write(cache, text).with_context(|| format!("Failed to write {}", cache.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(labels: &[&str]) -> Vec<Candidate> {
+        labels
+            .iter()
+            .map(|label| Candidate {
+                path: PathBuf::from(label),
+                label: label.to_string(),
+                size: Some(10),
+            })
+            .collect()
+    }
+
+    fn press(selection: &mut Selection, keys: &[Key]) -> Outcome {
+        keys.iter()
+            .map(|&key| selection.handle_key(key))
+            .find(|&outcome| outcome != Outcome::Continue)
+            .unwrap_or(Outcome::Continue)
+    }
+
+    #[test]
+    fn toggling_and_moving_changes_the_chosen_set() {
+        let mut selection = Selection::new(candidates(&["a.rs", "b.rs", "c.rs"]), None);
+        let outcome = press(
+            &mut selection,
+            &[
+                Key::Char(' '),
+                Key::Down,
+                Key::Down,
+                Key::Char(' '),
+                Key::Enter,
+            ],
+        );
+        assert_eq!(outcome, Outcome::Confirm);
+        assert_eq!(selection.chosen(), vec![PathBuf::from("b.rs")]);
+    }
+
+    #[test]
+    fn filter_limits_toggle_all_to_matches() {
+        let mut selection = Selection::new(
+            candidates(&["src/main.rs", "src/lib.rs", "README.md"]),
+            None,
+        );
+        press(&mut selection, &[Key::Char('a'), Key::Char('a')]);
+        assert_eq!(selection.chosen().len(), 3);
+
+        press(
+            &mut selection,
+            &[
+                Key::Char('a'),
+                Key::Char('/'),
+                Key::Char('.'),
+                Key::Char('r'),
+                Key::Char('s'),
+            ],
+        );
+        assert_eq!(selection.visible(), vec![0, 1]);
+        press(&mut selection, &[Key::Enter, Key::Char('a')]);
+        assert_eq!(
+            selection.chosen(),
+            vec![PathBuf::from("src/main.rs"), PathBuf::from("src/lib.rs")]
+        );
+    }
+
+    #[test]
+    fn filter_keystrokes_do_not_trigger_commands() {
+        let mut selection = Selection::new(candidates(&["queue.rs", "b.rs"]), None);
+        let outcome = press(
+            &mut selection,
+            &[
+                Key::Char('/'),
+                Key::Char('q'),
+                Key::Char('a'),
+                Key::Backspace,
+            ],
+        );
+        assert_eq!(outcome, Outcome::Continue);
+        assert_eq!(selection.chosen().len(), 2);
+        assert_eq!(selection.visible(), vec![0]);
+    }
+
+    #[test]
+    fn previous_selection_is_prechecked_and_esc_cancels() {
+        let previous: HashSet<PathBuf> = [PathBuf::from("b.rs")].into_iter().collect();
+        let mut selection = Selection::new(candidates(&["a.rs", "b.rs"]), Some(&previous));
+        assert_eq!(selection.chosen(), vec![PathBuf::from("b.rs")]);
+        assert_eq!(press(&mut selection, &[Key::Esc]), Outcome::Cancel);
+    }
+
+    #[test]
+    fn render_shows_cursor_checks_and_sizes() {
+        let mut selection = Selection::new(candidates(&["a.rs", "b.rs"]), None);
+        press(&mut selection, &[Key::Down, Key::Char(' ')]);
+        let lines = selection.render_lines(10);
+        assert!(lines[0].starts_with("1/2 selected"));
+        assert_eq!(lines[1], "  [x] a.rs (10 B)");
+        assert_eq!(lines[2], "> [ ] b.rs (10 B)");
+    }
+
+    #[test]
+    fn selection_cache_round_trips() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let cache = temp.path().join("picker").join("last-selection.txt");
+        let chosen = vec![
+            PathBuf::from("/repo/src/main.rs"),
+            PathBuf::from("/repo/a b.rs"),
+        ];
+        save_selection(&cache, &chosen).expect("save");
+        let loaded = load_selection(&cache).expect("load");
+        assert_eq!(loaded, chosen.into_iter().collect());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::filter;
+use crate::logger;
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{self, ErrorKind, Read, Write};
+use std::process::{Child, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How much of the command's output is read at a time.
+const CHUNK: usize = 64 << 10;
+
+/// `--pipe-cmd`: a shell command the whole output runs through on its way to
+/// the sinks. One thread feeds its stdin and another reads its stdout, so the
+/// run never waits on a full pipe; what the command has printed is picked up
+/// with every write. Its stderr is the run's own.
+pub struct Pipe {
+    command: String,
+    child: Child,
+    input: Option<mpsc::Sender<Vec<u8>>>,
+    output: mpsc::Receiver<io::Result<Vec<u8>>>,
+    /// The first error reading the output, reported by [`Pipe::finish`].
+    error: Option<io::Error>,
+    timeout: Duration,
+}
+
+impl Pipe {
+    /// Starts `command` in the shell. `timeout` counts from the moment it
+    /// has the whole output, so a long walk does not eat into it.
+    pub fn spawn(command: &str, timeout: Duration) -> Result<Self> {
+        let mut child = filter::shell(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Failed to start --pipe-cmd `{}`", command))?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let (input, chunks) = mpsc::channel::<Vec<u8>>();
+        thread::spawn(move || {
+            for chunk in chunks {
+                if let Err(err) = stdin.write_all(&chunk) {
+                    // A command may well exit without reading all of its input.
+                    if err.kind() != ErrorKind::BrokenPipe {
+                        logger::verbose(format_args!("could not write to --pipe-cmd: {err}"));
+                    }
+                    return;
+                }
+            }
+        });
+
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK];
+            match stdout.read(&mut chunk) {
+                Ok(0) => return,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    if sender.send(Ok(chunk)).is_err() {
+                        return;
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            command: command.to_string(),
+            child,
+            input: Some(input),
+            output,
+            error: None,
+            timeout,
+        })
+    }
+
+    /// Hands `bytes` to the command and returns what it has printed so far.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<u8> {
+        if let Some(input) = &self.input {
+            // Once the command stops reading, the rest of the input is dropped;
+            // its exit status tells whether that was a failure.
+            let _ = input.send(bytes.to_vec());
+        }
+        let mut printed = Vec::new();
+        while let Ok(chunk) = self.output.try_recv() {
+            self.take(chunk, &mut printed);
+        }
+        printed
+    }
+
+    /// Closes the command's input and passes the rest of its output to
+    /// `deliver` as it arrives. Fails when the command exits with a nonzero
+    /// status or is still running `timeout` after its input closed, in which
+    /// case it is killed.
+    pub fn finish<F>(mut self, mut deliver: F) -> Result<()>
+    where
+        F: FnMut(&[u8]),
+    {
+        self.input = None;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.output.recv_timeout(remaining) {
+                Ok(chunk) => {
+                    let mut printed = Vec::new();
+                    self.take(chunk, &mut printed);
+                    deliver(&printed);
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = self.child.kill();
+                    let _ = self.child.wait();
+                    return Err(self.timed_out());
+                }
+            }
+        }
+        let status = filter::wait(&mut self.child, deadline)?.ok_or_else(|| self.timed_out())?;
+        if !status.success() {
+            bail!("--pipe-cmd `{}` exited with {}", self.command, status);
+        }
+        match self.error {
+            Some(err) => Err(anyhow::Error::new(err)
+                .context(format!("Failed to read the output of `{}`", self.command))),
+            None => Ok(()),
+        }
+    }
+
+    fn take(&mut self, chunk: io::Result<Vec<u8>>, printed: &mut Vec<u8>) {
+        match chunk {
+            Ok(chunk) => printed.extend_from_slice(&chunk),
+            Err(err) => {
+                self.error.get_or_insert(err);
+            }
+        }
+    }
+
+    fn timed_out(&self) -> anyhow::Error {
+        anyhow!(
+            "--pipe-cmd `{}` timed out after {:?}",
+            self.command,
+            self.timeout
+        )
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn run(command: &str, input: &[&[u8]], timeout: Duration) -> Result<Vec<u8>> {
+        let mut pipe = Pipe::spawn(command, timeout)?;
+        let mut printed = Vec::new();
+        for bytes in input {
+            printed.extend(pipe.push(bytes));
+        }
+        pipe.finish(|chunk| printed.extend_from_slice(chunk))?;
+        Ok(printed)
+    }
+
+    #[test]
+    fn the_output_comes_back_transformed() {
+        let printed = run("tr a-z A-Z", &[b"hello ", b"world\n"], SECOND).expect("run");
+        assert_eq!(printed, b"HELLO WORLD\n");
+
+        let large = vec![b'x'; 1 << 20];
+        let printed = run("tr x y", &[&large, &large], SECOND).expect("run");
+        assert_eq!(printed.len(), 2 << 20);
+        assert!(printed.iter().all(|&byte| byte == b'y'));
+    }
+
+    #[test]
+    fn failures_and_timeouts_are_errors() {
+        let err = run("cat >/dev/null; exit 3", &[b"text"], SECOND).expect_err("exit 3");
+        assert_eq!(
+            format!("{err:#}"),
+            "--pipe-cmd `cat >/dev/null; exit 3` exited with exit status: 3"
+        );
+
+        let started = Instant::now();
+        let err = run("sleep 5", &[b"text"], Duration::from_millis(200)).expect_err("slow");
+        assert_eq!(
+            format!("{err:#}"),
+            "--pipe-cmd `sleep 5` timed out after 200ms"
+        );
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+}
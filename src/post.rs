@@ -0,0 +1,196 @@
+use anyhow::{bail, Result};
+use std::fmt;
+
+/// Where `--post` sends the output and with which headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Post {
+    pub url: String,
+    /// `Name: value` pairs from `--post-header`.
+    pub headers: Vec<String>,
+    pub content_type: &'static str,
+}
+
+/// What the server answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub status: u16,
+    pub reason: String,
+    pub body: String,
+}
+
+impl Reply {
+    /// The URL a paste service answered with: the body's first line when
+    /// it is a lone `http://` or `https://` URL.
+    pub fn link(&self) -> Option<&str> {
+        let line = self.body.trim().lines().next()?.trim();
+        let is_url = (line.starts_with("https://") || line.starts_with("http://"))
+            && !line.contains(char::is_whitespace);
+        is_url.then_some(line)
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.reason)
+    }
+}
+
+/// Splits a `--post-header` value at its first colon into a name and a
+/// value with surrounding spaces trimmed; `None` when there is no colon or
+/// the name is empty or holds spaces.
+pub fn split_header(header: &str) -> Option<(&str, &str)> {
+    let (name, value) = header.split_once(':')?;
+    let name = name.trim();
+    (!name.is_empty() && !name.contains(char::is_whitespace)).then_some((name, value.trim()))
+}
+
+/// Sends `body` to `post.url`. A status outside 2xx is an error carrying
+/// the reply, so the caller falls back as for a network error.
+#[cfg(feature = "post")]
+pub fn send(post: &Post, body: &[u8]) -> Result<Reply> {
+    use anyhow::Context;
+    use std::time::Duration;
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(Duration::from_secs(60)))
+        .build()
+        .into();
+    let mut request = agent.post(&post.url);
+    let headers: Vec<(&str, &str)> = post
+        .headers
+        .iter()
+        .filter_map(|header| split_header(header))
+        .collect();
+    if !headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+    {
+        request = request.header("Content-Type", post.content_type);
+    }
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let mut response = request
+        .send(body)
+        .with_context(|| format!("Failed to post to {}", post.url))?;
+    let status = response.status();
+    let reply = Reply {
+        status: status.as_u16(),
+        reason: status.canonical_reason().unwrap_or_default().to_string(),
+        body: response.body_mut().read_to_string().unwrap_or_default(),
+    };
+    if !status.is_success() {
+        let body = reply.body.trim();
+        if body.is_empty() {
+            bail!("{} answered {}", post.url, reply);
+        }
+        bail!("{} answered {}: {}", post.url, reply, body);
+    }
+    Ok(reply)
+}
+
+#[cfg(not(feature = "post"))]
+pub fn send(post: &Post, _body: &[u8]) -> Result<Reply> {
+    bail!(
+        "Cannot post to {}: copytree was built without the `post` feature",
+        post.url
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(body: &str) -> Reply {
+        Reply {
+            status: 201,
+            reason: "Created".to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn link_is_a_lone_url_on_the_first_line() {
+        assert_eq!(
+            reply("https://paste.example/abc\n").link(),
+            Some("https://paste.example/abc")
+        );
+        assert_eq!(
+            reply("  http://p/1  \nexpires in 7 days\n").link(),
+            Some("http://p/1")
+        );
+        assert_eq!(reply("created https://paste.example/abc").link(), None);
+        assert_eq!(reply("{\"url\": \"https://p/1\"}").link(), None);
+        assert_eq!(reply("").link(), None);
+    }
+
+    #[test]
+    fn headers_split_at_the_first_colon() {
+        assert_eq!(
+            split_header("Authorization: Bearer a:b"),
+            Some(("Authorization", "Bearer a:b"))
+        );
+        assert_eq!(split_header("X-Empty:"), Some(("X-Empty", "")));
+        assert_eq!(split_header("Authorization Bearer"), None);
+        assert_eq!(split_header(": value"), None);
+        assert_eq!(split_header("Bad Name: value"), None);
+    }
+
+    #[cfg(feature = "post")]
+    #[test]
+    fn send_posts_the_body_with_the_headers() {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind loopback");
+        let address = listener.local_addr().expect("local address");
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("read request");
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().expect("numeric length");
+                }
+                head.push(line);
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).expect("read body");
+            reader
+                .into_inner()
+                .write_all(
+                    b"HTTP/1.1 201 Created\r\nContent-Length: 22\r\nConnection: close\r\n\r\n\
+                      https://paste.local/7\n",
+                )
+                .expect("reply");
+            (head, body)
+        });
+
+        let post = Post {
+            url: format!("http://{}/paste", address),
+            headers: vec!["Authorization: Bearer secret".to_string()],
+            content_type: "text/plain; charset=utf-8",
+        };
+        let reply = send(&post, b"src\nfn main() {}\n").expect("post succeeds");
+        assert_eq!(reply.to_string(), "201 Created");
+        assert_eq!(reply.link(), Some("https://paste.local/7"));
+
+        let (head, body) = server.join().expect("server thread");
+        assert_eq!(head[0], "POST /paste HTTP/1.1\r\n");
+        let has = |expected: &str| head.iter().any(|line| line.eq_ignore_ascii_case(expected));
+        assert!(has("authorization: Bearer secret\r\n"), "{head:?}");
+        assert!(
+            has("content-type: text/plain; charset=utf-8\r\n"),
+            "{head:?}"
+        );
+        assert_eq!(body, b"src\nfn main() {}\n");
+    }
+}
@@ -0,0 +1,157 @@
+use crate::output::human_size;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Single-line progress display on stderr. Built without a sink it does
+/// nothing, which is the case whenever stderr is not a terminal.
+pub struct Progress {
+    sink: Option<Box<dyn Write>>,
+    last_draw: Option<Instant>,
+    drawn: bool,
+    frame: usize,
+}
+
+impl Progress {
+    pub fn new(sink: Option<Box<dyn Write>>) -> Self {
+        Self {
+            sink,
+            last_draw: None,
+            drawn: false,
+            frame: 0,
+        }
+    }
+
+    /// Draws to stderr only when it is a terminal and `quiet` is off.
+    pub fn for_stderr(quiet: bool) -> Self {
+        let enabled = !quiet && io::stderr().is_terminal();
+        Self::new(enabled.then(|| Box::new(io::stderr()) as Box<dyn Write>))
+    }
+
+    /// Spinner with a running count while the walk is in progress.
+    pub fn walking(&mut self, found: usize) {
+        if self.should_draw() {
+            let spinner = SPINNER[self.frame % SPINNER.len()];
+            self.frame += 1;
+            self.draw(&format!("{} Scanning... {} files", spinner, found));
+        }
+    }
+
+    /// Counter shown while file bodies are read.
+    pub fn reading(&mut self, done: usize, total: usize, bytes: u64) {
+        if self.should_draw() || done == total {
+            self.draw(&format!(
+                "Reading {}/{} files ({})",
+                done,
+                total,
+                human_size(bytes)
+            ));
+        }
+    }
+
+    /// Erases the progress line so that the next message starts clean.
+    pub fn clear(&mut self) {
+        if !self.drawn {
+            return;
+        }
+        if let Some(sink) = self.sink.as_mut() {
+            let _ = write!(sink, "\r\x1b[2K");
+            let _ = sink.flush();
+        }
+        self.drawn = false;
+        // Redraw on the next update rather than waiting out the interval.
+        self.last_draw = None;
+    }
+
+    fn should_draw(&self) -> bool {
+        self.sink.is_some()
+            && self
+                .last_draw
+                .is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL)
+    }
+
+    fn draw(&mut self, line: &str) {
+        let Some(sink) = self.sink.as_mut() else {
+            return;
+        };
+        let _ = write!(sink, "\r\x1b[2K{}", line);
+        let _ = sink.flush();
+        self.drawn = true;
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).expect("utf-8 progress")
+        }
+    }
+
+    #[test]
+    fn disabled_progress_emits_nothing() {
+        let mut progress = Progress::new(None);
+        progress.walking(10);
+        progress.reading(10, 10, 4096);
+        progress.clear();
+        assert!(progress.sink.is_none());
+        assert!(!progress.drawn);
+    }
+
+    #[test]
+    fn draws_then_clears_the_line() {
+        let buffer = SharedBuffer::default();
+        let mut progress = Progress::new(Some(Box::new(buffer.clone())));
+
+        progress.walking(3);
+        assert_eq!(buffer.text(), "\r\x1b[2K| Scanning... 3 files");
+
+        progress.clear();
+        progress.reading(2, 2, 2048);
+        progress.clear();
+        assert!(buffer
+            .text()
+            .ends_with("\r\x1b[2KReading 2/2 files (2.0 KiB)\r\x1b[2K"));
+    }
+
+    #[test]
+    fn throttles_intermediate_updates() {
+        let buffer = SharedBuffer::default();
+        let mut progress = Progress::new(Some(Box::new(buffer.clone())));
+
+        progress.reading(1, 100, 10);
+        progress.reading(2, 100, 20);
+        progress.reading(100, 100, 1000);
+        assert_eq!(
+            buffer.text(),
+            "\r\x1b[2KReading 1/100 files (10 B)\r\x1b[2KReading 100/100 files (1000 B)"
+        );
+    }
+}
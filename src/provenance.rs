@@ -0,0 +1,410 @@
+use crate::args::{Args, WalkArgs};
+use crate::eol::Eol;
+use crate::format::Format;
+use crate::separators;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Starts the line `--provenance` writes at the top of the output.
+pub const PREFIX: &str = "# copytree-provenance";
+
+/// `--max-file-bytes` when neither a flag, a config file, nor the
+/// environment sets it.
+const DEFAULT_MAX_FILE_BYTES: u64 = 16 * 1024;
+
+/// How an output was produced: enough to run the same copy again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub version: String,
+    /// The settings that shape the output, as flags in a fixed order, with
+    /// values from config files and profiles already applied.
+    pub args: Vec<String>,
+    /// Config files that were loaded, lowest layer first.
+    pub config: Vec<String>,
+    pub profiles: Vec<String>,
+    /// `COPYTREE_*` variables that were set.
+    pub env: Vec<String>,
+    /// When the output was generated; left out with `--no-timestamp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+}
+
+impl Provenance {
+    /// The provenance line without its newline: `key=value` pairs after
+    /// [`PREFIX`], with values quoted when they hold spaces or quotes.
+    pub fn line(&self) -> String {
+        let mut line = PREFIX.to_string();
+        for (key, value) in self.pairs() {
+            line.push(' ');
+            line.push_str(key);
+            line.push('=');
+            line.push_str(&quote_value(&value));
+        }
+        line
+    }
+
+    /// The fields as `(key, value)`, empty lists and an absent time left out.
+    pub fn pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![("version", self.version.clone()), ("args", self.command())];
+        for (key, list) in [
+            ("config", &self.config),
+            ("profiles", &self.profiles),
+            ("env", &self.env),
+        ] {
+            if !list.is_empty() {
+                pairs.push((key, list.join(",")));
+            }
+        }
+        pairs.extend(self.time.clone().map(|time| ("time", time)));
+        pairs
+    }
+
+    /// `args` as one shell-quoted command line, without the program name.
+    pub fn command(&self) -> String {
+        self.args
+            .iter()
+            .map(|arg| quote_arg(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Reads back a line written by [`Provenance::line`]; `None` for any
+    /// other line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = line.trim_end().strip_prefix(PREFIX)?;
+        let pairs = parse_pairs(rest)?;
+        let list = |key: &str| {
+            pairs
+                .get(key)
+                .map(|value| value.split(',').map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+        Some(Self {
+            version: pairs.get("version")?.clone(),
+            args: split_command(pairs.get("args")?)?,
+            config: list("config"),
+            profiles: list("profiles"),
+            env: list("env"),
+            time: pairs.get("time").cloned(),
+        })
+    }
+}
+
+/// The settings of `args` that shape the output, as flags in the order
+/// `--help` lists them, followed by the roots. Sinks, logging, and other
+/// options that leave the text unchanged are left out, so two runs that
+/// differ only in where the output went normalize the same. `seed` is the
+/// one `--sample` actually used.
+pub fn normalized_args(args: &Args, seed: Option<u64>) -> Vec<String> {
+    let walk: &WalkArgs = &args.walk;
+    let mut flags = Vec::new();
+    let mut value = |flag: &str, value: String| {
+        flags.push(flag.to_string());
+        flags.push(value);
+    };
+    for pattern in &walk.exclude {
+        value("--exclude", pattern.clone());
+    }
+    for pattern in &walk.exclude_contents {
+        value("--exclude-contents", pattern.clone());
+    }
+    if let Some(limit) = walk.max_per_dir {
+        value("--max-per-dir", limit.to_string());
+    }
+    if let Some(size) = walk.sample {
+        value("--sample", size.to_string());
+    }
+    if let Some(seed) = seed.or(walk.seed) {
+        value("--seed", seed.to_string());
+    }
+    for pattern in &walk.prune {
+        value("--prune", pattern.clone());
+    }
+    if walk.max_symlink_depth > 0 {
+        value("--max-symlink-depth", walk.max_symlink_depth.to_string());
+    }
+    for kind in &walk.types {
+        value("--type", kind.to_string());
+    }
+    if let Some(reference) = &walk.changed_since {
+        value("--changed-since", reference.clone());
+    }
+    if walk.max_file_bytes != DEFAULT_MAX_FILE_BYTES {
+        value("--max-file-bytes", walk.max_file_bytes.to_string());
+    }
+    if let Some(bytes) = walk.include_binary {
+        value("--include-binary", format!("base64:{}", bytes));
+    }
+    for pattern in &walk.always_include {
+        value("--always-include", pattern.clone());
+    }
+    if args.format != Format::Plain {
+        value("--format", possible_value(args.format));
+    }
+    if let Some(template) = &args.template {
+        value("--template", template.clone());
+    }
+    if let Some(encoding) = args.encode {
+        value("--encode", possible_value(encoding));
+    }
+    if args.output_eol != Eol::Lf {
+        value("--output-eol", possible_value(args.output_eol));
+    }
+    if let Some(command) = &args.pipe_cmd {
+        value("--pipe-cmd", command.clone());
+    }
+    if let Some(command) = &args.filter_cmd {
+        value("--filter-cmd", command.clone());
+    }
+    for rule in &args.filter_cmd_for {
+        value("--filter-cmd-for", rule.clone());
+    }
+    // The roots are recorded relative to the base, so the command is the
+    // one to run from there, whichever directory this run started in.
+    if walk.relative_base.is_some() && !walk.absolute_paths {
+        value("--relative-to", ".".to_string());
+    }
+    // Under --absolute-paths the roots are recorded whole, which the paths
+    // in the output show anyway.
+    if let Some(prefix) = walk
+        .relative_base
+        .as_deref()
+        .filter(|prefix| walk.absolute_paths && !prefix.as_os_str().is_empty())
+    {
+        value("--strip-prefix", separators::display(prefix));
+    }
+    if let Some(entries) = walk.list_archives {
+        flags.push(format!("--list-archives={}", entries));
+    }
+    for (flag, set) in [
+        ("--native-separators", walk.native_separators),
+        ("--absolute-paths", walk.absolute_paths),
+        ("--no-gitignore", walk.no_gitignore),
+        ("--no-git-exclude", walk.no_git_exclude),
+        ("--no-parent-ignore", walk.no_parent_ignore),
+        ("--require-git", walk.require_git),
+        ("--no-ignore", walk.no_ignore),
+    ] {
+        if set {
+            flags.push(flag.to_string());
+        }
+    }
+    if let Some(mode) = walk.include_git_dir {
+        flags.push(format!("--include-git-dir={}", possible_value(mode)));
+    }
+    for (flag, set) in [
+        ("--interactive", args.interactive),
+        ("--last", args.last),
+        ("--reproducible", args.reproducible),
+        ("--walk-order", args.walk_order),
+        ("--readme-first", args.readme_first),
+        ("--no-tree", args.no_tree),
+        ("--tree-tokens", args.tree_tokens),
+        ("--permalinks", args.permalinks),
+        ("--no-budget-report", args.no_budget_report),
+        ("--omit-skipped", args.omit_skipped),
+        ("--output-eol-content", args.output_eol_content),
+    ] {
+        if set {
+            flags.push(flag.to_string());
+        }
+    }
+    if let Some(mode) = args.bare {
+        flags.push(format!("--bare={}", possible_value(mode)));
+    }
+    if let Some(budget) = args.fit_tokens {
+        flags.push("--fit-tokens".to_string());
+        flags.push(budget.to_string());
+        flags.push("--fit-strategy".to_string());
+        flags.push(possible_value(args.fit_strategy));
+    }
+    // Under --relative-to the roots were made absolute; they are recorded
+    // relative to the base again.
+    flags.extend(walk.paths.iter().map(|path| {
+        walk.relative_base
+            .as_deref()
+            .filter(|_| !walk.absolute_paths)
+            .and_then(|base| Path::new(path).strip_prefix(base).ok())
+            .map_or_else(
+                || path.clone(),
+                |relative| {
+                    if relative.as_os_str().is_empty() {
+                        ".".to_string()
+                    } else {
+                        separators::display(relative)
+                    }
+                },
+            )
+    }));
+    flags
+}
+
+fn possible_value<T: clap::ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .expect("no variant is skipped")
+        .get_name()
+        .to_string()
+}
+
+/// Single-quotes `arg` for a POSIX shell when it holds anything but plain
+/// word characters.
+fn quote_arg(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Splits a command line written by [`quote_arg`] back into arguments.
+fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' => args.extend(current.take()),
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '\\' => current.get_or_insert_with(String::new).push(chars.next()?),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Some(args)
+}
+
+/// Double-quotes a value that holds spaces, quotes, or backslashes.
+fn quote_value(value: &str) -> String {
+    if !value.is_empty() && !value.contains([' ', '"', '\\']) {
+        return value.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parses ` key=value key="quoted value"` into a map.
+fn parse_pairs(text: &str) -> Option<BTreeMap<String, String>> {
+    let mut pairs = BTreeMap::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (index, '"') => break index + 1,
+                        (_, '\\') => value.push(chars.next()?.1),
+                        (_, c) => value.push(c),
+                    }
+                };
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(' ').unwrap_or(after.len());
+                (after[..end].to_string(), &after[end..])
+            }
+        };
+        pairs.insert(key.to_string(), value);
+        rest = remaining.trim_start();
+    }
+    Some(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Cli;
+    use clap::Parser;
+
+    fn normalized(cli: &[&str]) -> Vec<String> {
+        let cli = Cli::try_parse_from(std::iter::once("copytree").chain(cli.iter().copied()))
+            .expect("valid arguments");
+        normalized_args(&cli.copy, None)
+    }
+
+    #[test]
+    fn flag_order_and_sinks_do_not_change_the_normal_form() {
+        let first = normalized(&["src", "--out", "ctx.txt", "-x", "*.lock", "--no-gitignore"]);
+        let second = normalized(&["src", "--no-gitignore", "--stdout", "--exclude", "*.lock"]);
+        assert_eq!(first, ["--exclude", "*.lock", "--no-gitignore", "src"]);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn defaults_are_left_out_and_values_are_canonical() {
+        assert_eq!(normalized(&[]), ["."]);
+        assert_eq!(
+            normalized(&[
+                "--max-file-bytes",
+                "2MiB",
+                "--list-archives",
+                "--format",
+                "tar"
+            ]),
+            [
+                "--max-file-bytes",
+                "2097152",
+                "--format",
+                "tar",
+                "--list-archives=100",
+                "."
+            ]
+        );
+        assert_eq!(normalized(&["--max-file-bytes", "16K"]), ["."]);
+    }
+
+    #[test]
+    fn the_seed_sample_used_is_recorded() {
+        let cli = Cli::try_parse_from(["copytree", "--sample", "5"]).expect("valid arguments");
+        assert_eq!(
+            normalized_args(&cli.copy, Some(42)),
+            ["--sample", "5", "--seed", "42", "."]
+        );
+    }
+
+    #[test]
+    fn line_round_trips_through_parse() {
+        let provenance = Provenance {
+            version: "0.1.0".to_string(),
+            args: vec![
+                "--exclude".to_string(),
+                "it's a \"glob\" *".to_string(),
+                "src".to_string(),
+            ],
+            config: vec!["/home/dev/.config/copytree/config.toml".to_string()],
+            profiles: vec!["api".to_string(), "full".to_string()],
+            env: Vec::new(),
+            time: Some("2026-10-16T09:30:00Z".to_string()),
+        };
+        let line = provenance.line();
+        assert_eq!(
+            line,
+            r#"# copytree-provenance version=0.1.0 args="--exclude 'it'\\''s a \"glob\" *' src" config=/home/dev/.config/copytree/config.toml profiles=api,full time=2026-10-16T09:30:00Z"#
+        );
+        assert_eq!(Provenance::parse(&line), Some(provenance));
+        assert_eq!(Provenance::parse("src"), None);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::budget::Group;
+use crate::content::{FileContent, FileRecord, SkipReason};
+use crate::git::RepoState;
+use crate::kind;
+use crate::output::Destination;
+use crate::provenance::Provenance;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Bumped whenever a field is renamed or removed, or its values change
+/// meaning; 2 renamed the skip reason codes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Machine-readable summary of a run, written by `--report-json`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub schema_version: u32,
+    pub included: Vec<IncludedFile>,
+    pub skipped: Vec<SkippedFile>,
+    pub totals: Totals,
+    pub sinks: Vec<SinkRecord>,
+    /// The git repositories the roots are in; empty outside any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<RepoState>,
+    /// The `--provenance` record, when the run wrote one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// What `--max-file-bytes`, `--fit-tokens`, and `--max-per-dir` cut,
+    /// one group per limit.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub budget: Vec<Group>,
+    pub exit_status: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// How many files were left unread when Ctrl-C stopped the run; absent
+    /// when it ran to the end.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unprocessed: Option<u64>,
+    /// Record each file's media type, read from its leading bytes; off
+    /// under `--no-sniff`.
+    #[serde(skip)]
+    pub sniff: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IncludedFile {
+    pub path: String,
+    pub bytes: u64,
+    pub tokens: u64,
+    /// The media type sniffed from the file, such as `image/png`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub path: String,
+    /// A code of [`SkipReason::code`], as in the skip marker.
+    pub reason: String,
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Totals {
+    pub included_files: u64,
+    pub skipped_files: u64,
+    /// Bytes of file content included in the output.
+    pub content_bytes: u64,
+    /// Bytes of the assembled output handed to the sinks.
+    pub output_bytes: u64,
+    pub estimated_tokens: u64,
+    /// Included and skipped files by sniffed media type.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub content_types: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SinkRecord {
+    /// One of `stdout`, `file`, `clipboard`, `temp_file`.
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            ..Self::default()
+        }
+    }
+
+    pub fn record_files<F>(&mut self, records: &[FileRecord], display: F)
+    where
+        F: Fn(&Path) -> String,
+    {
+        for record in records {
+            let content_type = self
+                .sniff
+                .then(|| kind::sniff_content_type(&record.path).ok())
+                .flatten();
+            if let Some(content_type) = content_type {
+                *self
+                    .totals
+                    .content_types
+                    .entry(content_type.to_string())
+                    .or_default() += 1;
+            }
+            let content_type = content_type.map(str::to_string);
+            let bytes = match &record.content {
+                FileContent::Text(text) => text.len() as u64,
+                FileContent::Binary(bytes) => bytes.len() as u64,
+                FileContent::Image { .. } => record.content.body().len() as u64,
+                FileContent::Skipped(reason) => {
+                    self.skipped.push(SkippedFile {
+                        path: display(&record.path),
+                        reason: reason.code().to_string(),
+                        detail: reason.describe(),
+                        content_type,
+                    });
+                    self.totals.skipped_files += 1;
+                    continue;
+                }
+            };
+            let tokens = record.content.estimated_tokens() as u64;
+            self.included.push(IncludedFile {
+                path: display(&record.path),
+                bytes,
+                tokens,
+                content_type,
+            });
+            self.totals.included_files += 1;
+            self.totals.content_bytes += bytes;
+            self.totals.estimated_tokens += tokens;
+        }
+    }
+
+    pub fn record_output(&mut self, output_bytes: usize, destinations: &[Destination]) {
+        self.totals.output_bytes = output_bytes as u64;
+        self.sinks = destinations
+            .iter()
+            .map(|destination| SinkRecord {
+                kind: destination.kind().to_string(),
+                path: destination
+                    .path()
+                    .map(|path| path.to_string_lossy().into_owned()),
+            })
+            .collect();
+    }
+
+    /// Files whose body was left out for a reason other than an exclude
+    /// pattern the user asked for, a symlink to content already emitted, or
+    /// the `--fit-tokens` budget.
+    pub fn unintended_skips(&self) -> usize {
+        self.skipped
+            .iter()
+            .filter(|file| {
+                ![
+                    SkipReason::EXCLUDED_CODE,
+                    SkipReason::SAME_AS_CODE,
+                    SkipReason::OVER_BUDGET_CODE,
+                ]
+                .contains(&file.reason.as_str())
+            })
+            .count()
+    }
+
+    pub fn finish(&mut self, exit_status: u8, error: Option<&anyhow::Error>) {
+        self.exit_status = i32::from(exit_status);
+        self.error = error.map(|err| format!("{err:#}"));
+    }
+}
+
+/// Writes the report as pretty JSON to `target`, or to stderr for `-`.
+pub fn write_report(report: &RunReport, target: &str) -> Result<()> {
+    let json = serde_json::to_string_pretty(report).context("Failed to serialize report")?;
+    if target == "-" {
+        return writeln!(std::io::stderr(), "{}", json).context("Failed to write report to stderr");
+    }
+    fs::write(target, json + "\n").with_context(|| format!("Failed to write report: {}", target))
+}
+
+/// Where `--status-json` writes its line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusStream {
+    Stderr,
+    /// For wrappers that keep stderr for people; the output then goes
+    /// elsewhere.
+    Stdout,
+}
+
+/// The one line `--status-json` ends a run with, for scripts that would
+/// otherwise scrape the summary lines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusLine {
+    pub sinks: Vec<SinkRecord>,
+    pub output_bytes: u64,
+    pub included_files: u64,
+    pub skipped_files: u64,
+    pub duration_ms: u64,
+    pub exit_status: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unprocessed: Option<u64>,
+}
+
+impl StatusLine {
+    /// The status of a run that took `duration`, from its finished report.
+    pub fn of(report: &RunReport, duration: Duration) -> Self {
+        Self {
+            sinks: report.sinks.clone(),
+            output_bytes: report.totals.output_bytes,
+            included_files: report.totals.included_files,
+            skipped_files: report.totals.skipped_files,
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            exit_status: report.exit_status,
+            error: report.error.clone(),
+            unprocessed: report.unprocessed,
+        }
+    }
+}
+
+/// Writes `line` as compact JSON on one line of `stream`.
+pub fn write_status(line: &StatusLine, stream: StatusStream) -> Result<()> {
+    let json = serde_json::to_string(line).context("Failed to serialize the status line")?;
+    match stream {
+        StatusStream::Stderr => writeln!(std::io::stderr(), "{}", json),
+        StatusStream::Stdout => writeln!(std::io::stdout(), "{}", json),
+    }
+    .context("Failed to write the status line")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kind::FileKind;
+    use anyhow::anyhow;
+    use std::path::PathBuf;
+
+    fn sample_report() -> RunReport {
+        let records = vec![
+            FileRecord {
+                path: PathBuf::from("src/main.rs"),
+                content: FileContent::Text("fn main() {}\n".to_string()),
+            },
+            FileRecord {
+                path: PathBuf::from("logo.png"),
+                content: FileContent::Skipped(SkipReason::Binary {
+                    kind: FileKind::Image,
+                }),
+            },
+            FileRecord {
+                path: PathBuf::from("big.txt"),
+                content: FileContent::Skipped(SkipReason::TooLarge {
+                    size: 20_000,
+                    limit: 16_384,
+                }),
+            },
+        ];
+        let mut report = RunReport::new();
+        report.record_files(&records, |path| path.to_string_lossy().into_owned());
+        report.record_output(120, &[Destination::File(PathBuf::from("ctx.txt"))]);
+        report.finish(0, None);
+        report
+    }
+
+    #[test]
+    fn report_round_trips_through_typed_struct() {
+        let report = sample_report();
+        let json = serde_json::to_string(&report).expect("serialize");
+        let parsed: RunReport = serde_json::from_str(&json).expect("deserialize");
+
+        assert_eq!(parsed, report);
+        assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+        assert_eq!(parsed.included[0].path, "src/main.rs");
+        assert_eq!(parsed.included[0].bytes, 13);
+        assert_eq!(parsed.skipped[0].reason, "binary");
+        assert_eq!(parsed.skipped[1].reason, "too-large");
+        assert_eq!(parsed.totals.included_files, 1);
+        assert_eq!(parsed.totals.skipped_files, 2);
+        assert_eq!(parsed.totals.output_bytes, 120);
+        assert_eq!(parsed.sinks[0].kind, "file");
+        assert_eq!(parsed.sinks[0].path.as_deref(), Some("ctx.txt"));
+        assert_eq!(parsed.exit_status, 0);
+        assert!(parsed.error.is_none());
+    }
+
+    #[test]
+    fn report_keys_are_stable() {
+        let value = serde_json::to_value(sample_report()).expect("serialize");
+        let mut keys: Vec<&str> = value
+            .as_object()
+            .expect("object")
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "exit_status",
+                "included",
+                "schema_version",
+                "sinks",
+                "skipped",
+                "totals"
+            ]
+        );
+    }
+
+    #[test]
+    fn excluded_files_are_not_unintended_skips() {
+        let mut report = sample_report();
+        assert_eq!(report.unintended_skips(), 2);
+
+        report.record_files(
+            &[
+                FileRecord {
+                    path: PathBuf::from("target/out.rs"),
+                    content: FileContent::Skipped(SkipReason::Excluded {
+                        pattern: "target/**".to_string(),
+                    }),
+                },
+                FileRecord {
+                    path: PathBuf::from("src/big.rs"),
+                    content: FileContent::Skipped(SkipReason::OverBudget { tokens: 9000 }),
+                },
+            ],
+            |path| path.to_string_lossy().into_owned(),
+        );
+        assert_eq!(report.unintended_skips(), 2);
+    }
+
+    #[test]
+    fn failed_run_records_error() {
+        let mut report = RunReport::new();
+        report.finish(1, Some(&anyhow!("root not found")));
+        assert_eq!(report.exit_status, 1);
+        assert_eq!(report.error.as_deref(), Some("root not found"));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::casefold;
+use crate::encode;
+use crate::logger;
+use crate::manifest::{self, Manifest};
+use crate::output;
+use crate::provenance::Provenance;
+use crate::snapshot::{Body, Snapshot};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// What `--verify` does with a restored file that does not match the digest
+/// recorded for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Verify {
+    /// List it and keep it.
+    Report,
+    /// List it and delete it.
+    Strict,
+}
+
+/// How the restored files compared with their recorded digests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every file matched, or nothing was verified.
+    Restored,
+    /// Some file differs from its digest or has no section.
+    Mismatched,
+}
+
+/// A file that did not come back as recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mismatch {
+    path: String,
+    expected: String,
+    /// The digest of what was restored; `None` when the snapshot has no
+    /// section for the file.
+    actual: Option<String>,
+}
+
+/// Resolves a section path under `dest`, rejecting absolute paths and any
+/// `..` component so a crafted input cannot write outside the destination.
+fn target_path(dest: &Path, path: &str) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => bail!("Refusing to restore path with '..': {}", path),
+            Component::RootDir | Component::Prefix(_) => {
+                bail!("Refusing to restore absolute path: {}", path)
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        bail!("Refusing to restore empty path: {}", path);
+    }
+    Ok(dest.join(relative))
+}
+
+/// Fails when some of `targets` differ only in case and `case_insensitive`
+/// says one would overwrite another; with `force` it only warns.
+fn check_case_collisions<'a, I>(
+    targets: I,
+    case_insensitive: impl Fn() -> bool,
+    force: bool,
+) -> Result<()>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let groups = casefold::collisions(targets);
+    if groups.is_empty() || !case_insensitive() {
+        return Ok(());
+    }
+    let listed = groups
+        .iter()
+        .map(|group| casefold::describe(group))
+        .collect::<Vec<_>>()
+        .join("; ");
+    if !force {
+        bail!(
+            "Refusing to restore paths that differ only in case onto a case-insensitive file system: {} (pass --force to write them anyway)",
+            listed
+        );
+    }
+    logger::warn(format_args!(
+        "paths differ only in case and overwrite each other here: {}",
+        listed
+    ));
+    Ok(())
+}
+
+/// The bytes of a binary file's section, which must decode to the `size`
+/// its header noted.
+fn decode_binary(path: &str, base64: &str, size: u64) -> Result<Vec<u8>> {
+    let bytes = encode::decode(base64.as_bytes())
+        .with_context(|| format!("The base64 of {} does not decode", path))?;
+    if bytes.len() as u64 != size {
+        bail!(
+            "The base64 of {} decodes to {} bytes, but its header says {}",
+            path,
+            bytes.len(),
+            size
+        );
+    }
+    Ok(bytes)
+}
+
+/// The SHA-256 of each file in the `--manifest` written next to `input`,
+/// with the path its section is headed by, keyed by [`section_key`] and
+/// leaving out described images, which restore cannot recreate; `None`
+/// when there is no manifest.
+fn recorded_digests(input: &Path) -> Result<Option<BTreeMap<PathBuf, (String, String)>>> {
+    let path = PathBuf::from(manifest::default_path(&input.to_string_lossy()));
+    let json = match fs::read(&path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let manifest: Manifest = serde_json::from_slice(&json)
+        .with_context(|| format!("Failed to parse manifest {}", path.display()))?;
+    Ok(Some(
+        manifest
+            .included
+            .into_iter()
+            .filter(|placed| placed.encoding.as_deref() != Some(manifest::IMAGE_ENCODING))
+            .map(|placed| {
+                // Manifests from before `header` only have the display path.
+                let header = if placed.header.is_empty() {
+                    placed.file.path
+                } else {
+                    placed.header
+                };
+                (section_key(&header), (header, placed.sha256))
+            })
+            .collect(),
+    ))
+}
+
+/// `path` without `.` components, so a section headed `./README.md` and a
+/// manifest entry for `README.md` are the same file.
+fn section_key(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+/// Lists each mismatch with the digest expected and the one found.
+fn report_mismatches(mismatches: &[Mismatch], mode: Verify) {
+    for mismatch in mismatches {
+        match &mismatch.actual {
+            Some(actual) => logger::warn(format_args!(
+                "{} does not match the manifest: expected sha256 {}, got {}",
+                mismatch.path, mismatch.expected, actual
+            )),
+            None => logger::warn(format_args!(
+                "{} is in the manifest but has no section in the snapshot",
+                mismatch.path
+            )),
+        }
+    }
+    let removed = if mode == Verify::Strict {
+        "; the restored copies were deleted"
+    } else {
+        ""
+    };
+    logger::warn(format_args!(
+        "{} file(s) failed verification{}",
+        mismatches.len(),
+        removed
+    ));
+}
+
+/// Reports the settings a snapshot was made with, warning when a different
+/// copytree version wrote it.
+fn note_provenance(provenance: &Provenance) {
+    logger::verbose(format_args!(
+        "Snapshot made by copytree {} with: {}",
+        provenance.version,
+        provenance.command()
+    ));
+    let current = env!("CARGO_PKG_VERSION");
+    if provenance.version != current {
+        logger::warn(format_args!(
+            "snapshot was made by copytree {}, this is {}; sections may not parse the same",
+            provenance.version, current
+        ));
+    }
+}
+
+/// Recreates the files captured in `input` under `dest`. Every path is
+/// checked before anything is written, so a rejected path, an existing
+/// file, or paths that differ only in case on a case-insensitive
+/// destination (without `force`) leave the destination untouched.
+///
+/// With `verify`, each file written is read back and hashed against the
+/// digest the snapshot's `--manifest` recorded for it, and every mismatch is
+/// listed; [`Verify::Strict`] deletes the files that differ. Without a
+/// manifest there is nothing to verify against, which is only a warning.
+pub fn restore(
+    input: &Path,
+    dest: &Path,
+    force: bool,
+    dry_run: bool,
+    verify: Option<Verify>,
+) -> Result<Outcome> {
+    let Snapshot {
+        provenance,
+        sections,
+    } = Snapshot::read(input)?;
+    if let Some(provenance) = &provenance {
+        note_provenance(provenance);
+    }
+    if sections.is_empty() {
+        bail!("No `--- path ---` sections found in {}", input.display());
+    }
+    let mut digests = match verify {
+        Some(_) => recorded_digests(input)?,
+        None => None,
+    };
+    if verify.is_some() && digests.is_none() {
+        logger::warn(format_args!(
+            "no manifest found at {}; nothing to verify",
+            manifest::default_path(&input.display().to_string())
+        ));
+    }
+
+    let mut planned = Vec::new();
+    for section in &sections {
+        let target = target_path(dest, &section.path)?;
+        let body = match &section.body {
+            Body::Text(text) => Cow::Borrowed(text.as_bytes()),
+            Body::Binary { base64, size } => {
+                Cow::Owned(decode_binary(&section.path, base64, *size)?)
+            }
+            Body::Image(_) => {
+                logger::verbose(format_args!(
+                    "Skipped {} (image, described but not captured)",
+                    section.path
+                ));
+                continue;
+            }
+            Body::Skipped(_) => {
+                let reason = section.body.marker().map(|marker| marker.reason);
+                logger::verbose(format_args!(
+                    "Skipped {} ({}, not captured)",
+                    section.path,
+                    reason.as_deref().unwrap_or("skipped")
+                ));
+                continue;
+            }
+        };
+        if !force && target.exists() {
+            bail!(
+                "Refusing to overwrite existing file: {} (pass --force to overwrite it)",
+                target.display()
+            );
+        }
+        planned.push((section, target, body));
+    }
+
+    check_case_collisions(
+        planned.iter().map(|(_, target, _)| target.as_path()),
+        || casefold::is_case_insensitive(dest),
+        force,
+    )?;
+
+    let mut mismatches = Vec::new();
+    for (section, target, body) in &planned {
+        let path = &section.path;
+        let expected = digests
+            .as_mut()
+            .and_then(|digests| digests.remove(&section_key(path)))
+            .map(|(_, expected)| expected);
+        if dry_run {
+            writeln!(
+                std::io::stdout().lock(),
+                "{} ({} bytes)",
+                target.display(),
+                body.len()
+            )
+            .map_err(|err| output::stdout_error(err, "Failed to write the plan"))?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+            }
+            fs::write(target, body)
+                .with_context(|| format!("Failed to write file: {}", target.display()))?;
+            logger::verbose(format_args!("Restored {}", target.display()));
+        }
+        let Some(expected) = expected else {
+            continue;
+        };
+        // What landed on disk, or what would have without --dry-run.
+        let written = if dry_run {
+            Cow::Borrowed(body.as_ref())
+        } else {
+            Cow::Owned(
+                fs::read(target)
+                    .with_context(|| format!("Failed to read back {}", target.display()))?,
+            )
+        };
+        // The manifest hashes what the output holds: a binary file's base64.
+        let actual = match section.body {
+            Body::Binary { .. } => manifest::hex_digest(encode::base64_lines(&written).as_bytes()),
+            _ => manifest::hex_digest(&written),
+        };
+        if actual == expected {
+            continue;
+        }
+        if verify == Some(Verify::Strict) && !dry_run {
+            fs::remove_file(target)
+                .with_context(|| format!("Failed to delete {}", target.display()))?;
+        }
+        mismatches.push(Mismatch {
+            path: path.to_string(),
+            expected,
+            actual: Some(actual),
+        });
+    }
+    // Sections a chat tool dropped entirely.
+    mismatches.extend(
+        digests
+            .into_iter()
+            .flatten()
+            .map(|(_, (path, expected))| Mismatch {
+                path,
+                expected,
+                actual: None,
+            }),
+    );
+
+    let skipped = sections.len() - planned.len();
+    let verb = if dry_run { "Would restore" } else { "Restored" };
+    logger::info(format_args!(
+        "{} {} files to {} ({} skipped sections).",
+        verb,
+        planned.len(),
+        dest.display(),
+        skipped
+    ));
+    match verify {
+        Some(mode) if !mismatches.is_empty() => {
+            report_mismatches(&mismatches, mode);
+            Ok(Outcome::Mismatched)
+        }
+        _ => Ok(Outcome::Restored),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_collisions_are_refused_only_where_case_is_ignored() {
+        let targets = [
+            Path::new("/restore/src/README.md"),
+            Path::new("/restore/src/Readme.md"),
+            Path::new("/restore/src/main.rs"),
+        ];
+        let err = check_case_collisions(targets, || true, false).expect_err("collision");
+        assert!(
+            err.to_string()
+                .contains("/restore/src/README.md, /restore/src/Readme.md"),
+            "{err}"
+        );
+        assert!(check_case_collisions(targets, || true, true).is_ok());
+        assert!(check_case_collisions(targets, || false, false).is_ok());
+        let distinct = [Path::new("/restore/a.md"), Path::new("/restore/b/A.md")];
+        assert!(check_case_collisions(distinct, || panic!("no probe needed"), false).is_ok());
+    }
+
+    #[test]
+    fn rejects_paths_escaping_the_destination() {
+        let dest = Path::new("/restore");
+        assert!(target_path(dest, "../etc/passwd").is_err());
+        assert!(target_path(dest, "src/../../x").is_err());
+        assert!(target_path(dest, "/etc/passwd").is_err());
+        assert_eq!(
+            target_path(dest, "./src/main.rs").expect("relative path"),
+            PathBuf::from("/restore/src/main.rs")
+        );
+    }
+
+    #[test]
+    fn section_keys_ignore_current_directory_components() {
+        assert_eq!(section_key("./README.md"), section_key("README.md"));
+        assert_eq!(section_key("./src/./main.rs"), section_key("src/main.rs"));
+        assert_ne!(section_key("src/main.rs"), section_key("main.rs"));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::output::grouped_count;
+use serde::Serialize;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// SplitMix64: small and fast, good enough to pick files, not for secrets.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `0..bound`, without modulo bias.
+    fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// A seed for runs without `--seed`, from the clock.
+pub fn seed_from_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Picks `n` of `items` uniformly at random in one pass (reservoir
+/// sampling), returning them in no particular order together with the
+/// number of items seen.
+pub fn reservoir<I: Iterator>(items: I, n: usize, rng: &mut Rng) -> (Vec<I::Item>, usize) {
+    let mut chosen = Vec::with_capacity(n);
+    let mut seen = 0;
+    for item in items {
+        seen += 1;
+        if chosen.len() < n {
+            chosen.push(item);
+        } else {
+            let slot = rng.below(seen as u64) as usize;
+            if slot < n {
+                chosen[slot] = item;
+            }
+        }
+    }
+    (chosen, seen)
+}
+
+/// How a `--sample` run narrowed the files, e.g. `50 of 18,400 files, seed 42`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Sampled {
+    pub shown: usize,
+    pub total: usize,
+    pub seed: u64,
+}
+
+impl fmt::Display for Sampled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} files, seed {}",
+            grouped_count(self.shown as u64),
+            grouped_count(self.total as u64),
+            self.seed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_picks_the_same_items() {
+        let pick = |seed| {
+            let (mut chosen, seen) = reservoir(0..1_000, 10, &mut Rng::new(seed));
+            chosen.sort();
+            assert_eq!(seen, 1_000);
+            chosen
+        };
+        assert_eq!(pick(42), pick(42));
+        assert_ne!(pick(42), pick(43));
+        assert_eq!(pick(42).len(), 10);
+    }
+
+    #[test]
+    fn fewer_items_than_slots_are_all_kept() {
+        let (chosen, seen) = reservoir(0..3, 10, &mut Rng::new(1));
+        assert_eq!(chosen, [0, 1, 2]);
+        assert_eq!(seen, 3);
+    }
+
+    #[test]
+    fn every_item_is_about_equally_likely() {
+        let mut counts = [0u32; 10];
+        let mut rng = Rng::new(7);
+        for _ in 0..10_000 {
+            let (chosen, _) = reservoir(0..10, 3, &mut rng);
+            for item in chosen {
+                counts[item] += 1;
+            }
+        }
+        // Each item is expected 3,000 times.
+        assert!(
+            counts.iter().all(|&count| (2_700..3_300).contains(&count)),
+            "{counts:?}"
+        );
+    }
+
+    #[test]
+    fn describes_the_sampling_rate() {
+        let sampled = Sampled {
+            shown: 50,
+            total: 18_400,
+            seed: 42,
+        };
+        assert_eq!(sampled.to_string(), "50 of 18,400 files, seed 42");
+    }
+}
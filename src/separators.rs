@@ -0,0 +1,112 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static NATIVE: AtomicBool = AtomicBool::new(false);
+
+/// Keeps the platform's separators in displayed paths (`--native-separators`).
+pub fn set_native(native: bool) {
+    NATIVE.store(native, Ordering::Relaxed);
+}
+
+/// `path` as shown in headers, logs, the tree, and the JSON report: with
+/// forward slashes unless `--native-separators` is set. Only Windows paths
+/// change; elsewhere a backslash is part of a file name.
+pub fn display(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    if cfg!(windows) && !NATIVE.load(Ordering::Relaxed) {
+        to_forward_slashes(&text)
+    } else {
+        text.into_owned()
+    }
+}
+
+/// Rewrites a Windows path with forward slashes. Verbatim prefixes are
+/// dropped where a plain form exists (`\\?\C:\x` becomes `C:/x`,
+/// `\\?\UNC\server\share` becomes `//server/share`); UNC and device paths
+/// keep their leading pair (`//server/share`, `//./COM1`).
+pub fn to_forward_slashes(path: &str) -> String {
+    let path = if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", rest)
+    } else if let Some(rest) = path
+        .strip_prefix(r"\\?\")
+        .filter(|rest| is_drive_path(rest))
+    {
+        rest.to_string()
+    } else {
+        path.to_string()
+    };
+    path.replace('\\', "/")
+}
+
+/// Whether `path` starts with a drive letter, as in `C:` or `C:\x`.
+fn is_drive_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && bytes.get(2).is_none_or(|&byte| byte == b'\\')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_and_drive_paths_use_forward_slashes() {
+        assert_eq!(to_forward_slashes(r"src\walker.rs"), "src/walker.rs");
+        assert_eq!(
+            to_forward_slashes(r"C:\work\src\main.rs"),
+            "C:/work/src/main.rs"
+        );
+        assert_eq!(to_forward_slashes(r"..\shared\lib.rs"), "../shared/lib.rs");
+        assert_eq!(
+            to_forward_slashes("src/mixed\\path.rs"),
+            "src/mixed/path.rs"
+        );
+    }
+
+    #[test]
+    fn verbatim_prefixes_are_dropped_when_a_plain_form_exists() {
+        assert_eq!(to_forward_slashes(r"\\?\C:\work\lib.rs"), "C:/work/lib.rs");
+        assert_eq!(
+            to_forward_slashes(r"\\?\UNC\server\share\lib.rs"),
+            "//server/share/lib.rs"
+        );
+        assert_eq!(
+            to_forward_slashes(r"\\?\Volume{1234}\lib.rs"),
+            "//?/Volume{1234}/lib.rs"
+        );
+    }
+
+    #[test]
+    fn long_verbatim_paths_display_in_their_plain_form() {
+        let deep = ["node_modules"; 30].join(r"\");
+        let verbatim = format!(r"\\?\C:\work\{}\index.js", deep);
+        assert!(verbatim.len() > 260);
+        let shown = to_forward_slashes(&verbatim);
+        assert_eq!(
+            shown,
+            format!("C:/work/{}/index.js", deep.replace('\\', "/"))
+        );
+        assert_eq!(
+            to_forward_slashes(&shown.replace('/', r"\")),
+            shown,
+            "the plain form displays the same"
+        );
+    }
+
+    #[test]
+    fn unc_and_device_paths_keep_their_leading_pair() {
+        assert_eq!(
+            to_forward_slashes(r"\\server\share\src\lib.rs"),
+            "//server/share/src/lib.rs"
+        );
+        assert_eq!(to_forward_slashes(r"\\.\COM1"), "//./COM1");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn backslashes_in_unix_names_are_kept() {
+        assert_eq!(display(Path::new(r"notes\draft.md")), r"notes\draft.md");
+    }
+}
@@ -0,0 +1,151 @@
+use crate::logger;
+use anyhow::{Context, Result};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+/// How long a client may take to send its request before it is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What `--serve` serves and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Serve {
+    pub address: SocketAddr,
+    /// Stop after the output has been fetched once (`--serve-once`).
+    pub once: bool,
+    pub content_type: &'static str,
+}
+
+/// Binds the listening socket, so a taken port fails before anything is
+/// reported as served.
+#[cfg(feature = "serve")]
+pub fn bind(address: SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(address).with_context(|| format!("Failed to listen on {}", address))
+}
+
+#[cfg(not(feature = "serve"))]
+pub fn bind(_address: SocketAddr) -> Result<TcpListener> {
+    anyhow::bail!("Cannot serve the output: copytree was built without the `serve` feature")
+}
+
+/// Answers requests on `listener` with `body` at `/` until interrupted, or
+/// until it has been fetched once when `once` is set. A client that errors
+/// only loses its own connection.
+pub fn run(listener: TcpListener, body: &[u8], content_type: &str, once: bool) -> Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream.context("Failed to accept a connection")?;
+        match respond(stream, body, content_type) {
+            Ok(served) if served && once => break,
+            Ok(_) => {}
+            Err(err) => logger::verbose(format_args!("connection failed: {err}")),
+        }
+    }
+    Ok(())
+}
+
+/// Reads one request from `stream` and answers it: the output for `GET /`
+/// (its headers alone for `HEAD /`), 404 for any other path, and 405 for any
+/// other method. Returns whether the output itself was sent.
+fn respond(stream: TcpStream, body: &[u8], content_type: &str) -> io::Result<bool> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let (status, content_type, body, extra): (&str, &str, &[u8], &str) = match (method, path) {
+        ("GET" | "HEAD", "/") => ("200 OK", content_type, body, ""),
+        ("GET" | "HEAD", _) => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            b"Not found; the output is at /\n",
+            "",
+        ),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain; charset=utf-8",
+            b"Only GET and HEAD are supported\n",
+            "Allow: GET, HEAD\r\n",
+        ),
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len(),
+        extra
+    )?;
+    if method != "HEAD" {
+        stream.write_all(body)?;
+    }
+    stream.flush()?;
+    Ok(method == "GET" && status.starts_with("200"))
+}
+
+#[cfg(all(test, feature = "serve"))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+
+    /// Sends `request` to a server answering with `body` and returns the
+    /// raw response and whether the output counted as served.
+    fn exchange(request: &str, body: &'static [u8]) -> (String, bool) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind loopback");
+        let address = listener.local_addr().expect("local address");
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept");
+            respond(stream, body, "text/plain; charset=utf-8").expect("respond")
+        });
+        let mut client = TcpStream::connect(address).expect("connect");
+        client.write_all(request.as_bytes()).expect("send request");
+        let mut response = String::new();
+        client.read_to_string(&mut response).expect("read response");
+        (response, server.join().expect("server thread"))
+    }
+
+    #[test]
+    fn get_root_returns_the_output() {
+        let (response, served) = exchange(
+            "GET /?raw HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            b"src\n\xe2\x94\x94\xe2\x94\x80 main.rs\n",
+        );
+        assert!(served);
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 19\r\nConnection: close\r\n\r\nsrc\n└─ main.rs\n"
+        );
+    }
+
+    #[test]
+    fn head_sends_headers_without_counting_as_served() {
+        let (response, served) = exchange("HEAD / HTTP/1.1\r\n\r\n", b"text\n");
+        assert!(!served);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 5\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn other_paths_and_methods_are_refused() {
+        let (response, served) = exchange("GET /json HTTP/1.1\r\n\r\n", b"text\n");
+        assert!(!served);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let (response, served) = exchange("POST / HTTP/1.1\r\n\r\n", b"text\n");
+        assert!(!served);
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("Allow: GET, HEAD\r\n"));
+    }
+}
@@ -0,0 +1,344 @@
+use crate::budget;
+use crate::content::{self, SkipReason};
+use crate::fit;
+use crate::image;
+use crate::interrupt;
+use crate::marker::Marker;
+use crate::permalink;
+use crate::provenance::Provenance;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// One `--- path ---` section recovered from plain output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub path: String,
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    /// The file's contents.
+    Text(String),
+    /// The base64 lines of a binary file kept under `--include-binary`,
+    /// whose header noted `size` bytes.
+    Binary { base64: String, size: u64 },
+    /// The stanza that describes an image, such as `<image format="png"
+    /// width="512" height="512" size="34816"> PNG, 512×512, 34 KiB`; the
+    /// image itself was never captured.
+    Image(String),
+    /// A skip marker such as `<skipped reason="binary" kind="binary"> binary
+    /// file`, so the file was never captured.
+    Skipped(String),
+}
+
+impl Body {
+    /// The marker a skipped body starts with.
+    pub fn marker(&self) -> Option<Marker> {
+        match self {
+            Self::Text(_) | Self::Binary { .. } | Self::Image(_) => None,
+            Self::Skipped(text) => Marker::parse(text.lines().next()?),
+        }
+    }
+}
+
+/// A parsed copytree output: its `--provenance` line, if any, and sections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub provenance: Option<Provenance>,
+    pub sections: Vec<Section>,
+}
+
+impl Snapshot {
+    /// Reads a plain-format output written by `copytree --out`.
+    pub fn read(input: &Path) -> Result<Self> {
+        let text = fs::read_to_string(input)
+            .with_context(|| format!("Failed to read copytree output: {}", input.display()))?;
+        Ok(Self::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let (first, rest) = text.split_once('\n').unwrap_or((text, ""));
+        match Provenance::parse(first) {
+            Some(provenance) => Self {
+                provenance: Some(provenance),
+                sections: parse_plain(rest),
+            },
+            None => Self {
+                provenance: None,
+                sections: parse_plain(text),
+            },
+        }
+    }
+}
+
+/// Splits plain-format output into its file sections. A header is a line of
+/// exactly `--- path ---` that starts the document body or follows the blank
+/// line closing the previous section, so body lines that merely start with
+/// `---` are left alone. A header noting `(binary, base64, N bytes)` after
+/// the path heads a binary file's base64. Anything before the first header is the tree; when
+/// it is present, a header must also name a file listed in it. The report
+/// on what the limits cut ends the last section, as do the list earlier
+/// `--fit-tokens` runs closed with and the `--partial-on-interrupt` trailer.
+pub fn parse_plain(text: &str) -> Vec<Section> {
+    let mut headers = Vec::new();
+    let mut tree_names: Option<HashSet<&str>> = None;
+    let mut offset = 0;
+    let mut end = text.len();
+    for line in text.split_inclusive('\n') {
+        let starts_section = offset == 0 || text[..offset].ends_with("\n\n");
+        let closing = line.starts_with(budget::HEADING)
+            || line.starts_with(fit::TRAILER_PREFIX)
+            || line.starts_with(interrupt::TRAILER_PREFIX);
+        if starts_section && !headers.is_empty() && closing {
+            end = offset;
+            break;
+        }
+        if let Some(header) = header_path(line).filter(|_| starts_section) {
+            let (path, size) = match content::split_binary_header(header) {
+                Some((path, size)) => (path, Some(size)),
+                None => (header, None),
+            };
+            let names = tree_names.get_or_insert_with(|| tree_entry_names(&text[..offset]));
+            let listed = names.is_empty()
+                || Path::new(path)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| names.contains(name));
+            if listed {
+                headers.push((offset, offset + line.len(), path, size));
+            }
+        }
+        offset += line.len();
+    }
+
+    headers
+        .iter()
+        .enumerate()
+        .map(|(index, (_, body_start, path, size))| {
+            let body_end = headers
+                .get(index + 1)
+                .map_or(end, |(next_start, ..)| *next_start);
+            let raw = &text[*body_start..body_end];
+            // A --permalinks line belongs to the header, not the file.
+            let raw = match raw.split_once('\n') {
+                Some((first, rest)) if permalink::is_permalink_line(first, path) => rest,
+                _ => raw,
+            };
+            let body = raw.strip_suffix("\n\n").unwrap_or(raw);
+            Section {
+                path: path.to_string(),
+                body: match size {
+                    Some(size) => Body::Binary {
+                        base64: body.to_string(),
+                        size: *size,
+                    },
+                    None if is_skip_marker(body) => Body::Skipped(body.to_string()),
+                    None if image::is_stanza(body) => Body::Image(body.to_string()),
+                    None => Body::Text(body.to_string()),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Names drawn in the tree, taken from the text after each `├─ `/`└─ `,
+/// less any note such as `(~1.2k tok)` or `(dropped)`.
+fn tree_entry_names(tree: &str) -> HashSet<&str> {
+    tree.lines()
+        .filter_map(|line| line.rsplit_once("─ "))
+        .map(|(_, name)| match name.rfind("  (") {
+            Some(note) if name.ends_with(')') => name[..note].trim_end(),
+            _ => name,
+        })
+        .collect()
+}
+
+fn header_path(line: &str) -> Option<&str> {
+    let path = line
+        .strip_suffix('\n')?
+        .strip_prefix("--- ")?
+        .strip_suffix(" ---")?;
+    (!path.is_empty()).then_some(path)
+}
+
+/// Whether `body` is a skip marker: a [`Marker`] line, which only an
+/// archive's listing may follow.
+fn is_skip_marker(body: &str) -> bool {
+    let (first_line, rest) = body.split_once('\n').unwrap_or((body, ""));
+    Marker::parse(first_line)
+        .is_some_and(|marker| rest.is_empty() || marker.reason == SkipReason::ARCHIVE_CODE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_after_the_tree() {
+        let text = "src\n├─ a.rs\n└─ b.txt\n\n--- src/a.rs ---\nfn a() {}\n\n\n--- src/b.txt ---\nno newline\n\n";
+        assert_eq!(
+            parse_plain(text),
+            vec![
+                Section {
+                    path: "src/a.rs".to_string(),
+                    body: Body::Text("fn a() {}\n".to_string()),
+                },
+                Section {
+                    path: "src/b.txt".to_string(),
+                    body: Body::Text("no newline".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dashed_lines_inside_a_body_are_content() {
+        let body = "intro\n--- not/a/header ---\n---\nend\n";
+        let text = format!("--- notes.md ---\n{}\n\n--- next.txt ---\nx\n\n", body);
+        let sections = parse_plain(&text);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].body, Body::Text(body.to_string()));
+        assert_eq!(sections[1].path, "next.txt");
+    }
+
+    #[test]
+    fn tree_rules_out_header_lookalikes_after_blank_lines() {
+        let text = "src\n└─ notes.md\n\n--- src/notes.md ---\n# Notes\n\n--- fake ---\n\n";
+        let sections = parse_plain(text);
+        assert_eq!(sections.len(), 1);
+        assert_eq!(
+            sections[0].body,
+            Body::Text("# Notes\n\n--- fake ---".to_string())
+        );
+    }
+
+    #[test]
+    fn tree_token_notes_are_not_part_of_the_names() {
+        let text = "src           (~1.0k tok)\n└─ notes.md  (~1.0k tok)\n\n--- src/notes.md ---\n# Notes\n\n";
+        let sections = parse_plain(text);
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].path, "src/notes.md");
+    }
+
+    #[test]
+    fn the_budget_report_ends_the_last_section() {
+        let text = "src\n├─ a.rs\n└─ big.rs\n\n--- src/a.rs ---\nfn a() {}\n\n--- src/big.rs ---\n<skipped reason=\"too-large\" size=\"20000\" limit=\"16384\"> file size 20000 bytes exceeds --max-file-bytes 16384\n\n=== cut by limits ===\n--max-file-bytes 16KiB cut 1 file; rerun with --max-file-bytes 20KiB to include it\n  20 KiB  src/big.rs\n";
+        let sections = parse_plain(text);
+        assert_eq!(sections.len(), 2);
+        assert!(matches!(sections[1].body, Body::Skipped(_)));
+    }
+
+    #[test]
+    fn fit_trailer_ends_the_last_section() {
+        let text = "src\n├─ a.rs\n└─ big.rs  (dropped)\n\n--- src/a.rs ---\nfn a() {}\n\n--- src/big.rs ---\n<skipped reason=\"budget\" tokens=\"3000\"> dropped to fit --fit-tokens, ~3.0k tokens\n\n=== dropped by --fit-tokens 100 (largest-first) ===\nsrc/big.rs (~3.0k tokens): largest remaining file\n";
+        let sections = parse_plain(text);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].body, Body::Text("fn a() {}".to_string()));
+        assert!(matches!(sections[1].body, Body::Skipped(_)));
+    }
+
+    #[test]
+    fn interrupted_trailer_ends_the_last_section() {
+        let text = format!(
+            "src\n├─ a.rs\n└─ b.rs\n\n--- src/a.rs ---\nfn a() {{}}\n\n\n{}",
+            interrupt::trailer(1)
+        );
+        let sections = parse_plain(&text);
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].body, Body::Text("fn a() {}\n".to_string()));
+    }
+
+    #[test]
+    fn permalink_lines_are_not_part_of_the_file() {
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        let text = format!(
+            "--- src/a.rs ---\nhttps://github.com/org/repo/blob/{sha}/src/a.rs\nfn a() {{}}\n\n\
+             --- src/b.bin ---\nhttps://github.com/org/repo/blob/{sha}/src/b.bin (working tree differs)\n\
+             <skipped reason=\"binary\" kind=\"binary\"> binary file\n\n\
+             --- src/c.rs ---\nhttps://github.com/org/repo/blob/{sha}/src/a.rs\n\n"
+        );
+        let sections = parse_plain(&text);
+        assert_eq!(sections[0].body, Body::Text("fn a() {}".to_string()));
+        assert!(matches!(sections[1].body, Body::Skipped(_)));
+        assert_eq!(
+            sections[2].body,
+            Body::Text(format!("https://github.com/org/repo/blob/{sha}/src/a.rs"))
+        );
+    }
+
+    #[test]
+    fn skip_markers_become_absent_files() {
+        let text = r#"--- logo.png ---
+<skipped reason="image" format="png" width="512" height="512" size="34816"> image: PNG, 512×512, 34 KiB
+
+--- fixtures.zip ---
+<skipped reason="archive" format="zip" size="180"> archive: zip, 2 entries, 180 B
+a.txt (3 B)
+b/
+
+--- link.rs ---
+<skipped reason="same-content" first="src/main.rs"> same content as src/main.rs (symlink)
+
+--- data.bin ---
+<skipped reason="binary" kind="binary"> binary file
+
+--- big.txt ---
+<skipped reason="too-large" size="20000" limit="16384"> file size 20000 bytes exceeds --max-file-bytes 16384
+
+"#;
+        let sections = parse_plain(text);
+        assert!(sections
+            .iter()
+            .all(|section| matches!(section.body, Body::Skipped(_))));
+        assert_eq!(
+            sections[3].body,
+            Body::Skipped(r#"<skipped reason="binary" kind="binary"> binary file"#.to_string())
+        );
+        let reasons: Vec<String> = sections
+            .iter()
+            .filter_map(|section| section.body.marker())
+            .map(|marker| marker.reason)
+            .collect();
+        assert_eq!(
+            reasons,
+            ["image", "archive", "same-content", "binary", "too-large"]
+        );
+    }
+
+    #[test]
+    fn binary_headers_carry_the_size_and_head_base64() {
+        let text = "assets\n├─ logo.png\n└─ notes (draft).txt\n\n\
+                    --- assets/logo.png (binary, base64, 1,024 bytes) ---\nAAEC\nAwQF\n\n\n\
+                    --- assets/notes (draft).txt ---\nplain\n\n";
+        let sections = parse_plain(text);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].path, "assets/logo.png");
+        assert_eq!(
+            sections[0].body,
+            Body::Binary {
+                base64: "AAEC\nAwQF\n".to_string(),
+                size: 1024,
+            }
+        );
+        assert_eq!(sections[1].path, "assets/notes (draft).txt");
+        assert_eq!(
+            content::split_binary_header("a (binary, base64, 1x bytes)"),
+            None
+        );
+    }
+
+    #[test]
+    fn provenance_line_is_read_off_the_top() {
+        let text = "# copytree-provenance version=0.1.0 args=src\n--- src/a.rs ---\nfn a() {}\n\n";
+        let snapshot = Snapshot::parse(text);
+        assert_eq!(
+            snapshot.provenance.map(|provenance| provenance.args),
+            Some(vec!["src".to_string()])
+        );
+        assert_eq!(snapshot.sections.len(), 1);
+        assert_eq!(snapshot.sections[0].path, "src/a.rs");
+    }
+}
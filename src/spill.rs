@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::PathBuf;
+
+/// Output collected in memory up to a limit and in a file past it, so that
+/// holding a huge output for the clipboard cannot exhaust memory.
+pub struct SpillBuffer {
+    /// Bytes kept in memory at most; `None` never spills.
+    limit: Option<u64>,
+    memory: Vec<u8>,
+    /// Where the bytes go once past the limit.
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    len: u64,
+    /// The first failure writing the file; later bytes are dropped.
+    error: Option<io::Error>,
+}
+
+/// Where a finished [`SpillBuffer`] left its bytes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Spilled {
+    Memory(Vec<u8>),
+    /// The file at `path` holds the whole output.
+    File(PathBuf),
+}
+
+impl SpillBuffer {
+    /// A buffer spilling to `path` once it holds more than `limit` bytes.
+    /// `capacity` is reserved up front, though never beyond the limit.
+    pub fn new(limit: Option<u64>, capacity: usize, path: PathBuf) -> Self {
+        let capacity = limit.map_or(capacity, |limit| {
+            capacity.min(usize::try_from(limit).unwrap_or(usize::MAX))
+        });
+        Self {
+            limit,
+            memory: Vec::with_capacity(capacity),
+            path,
+            file: None,
+            len: 0,
+            error: None,
+        }
+    }
+
+    /// Bytes received so far, in memory or not.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        self.len += bytes.len() as u64;
+        if self.file.is_none() && self.limit.is_some_and(|limit| self.len > limit) {
+            if let Err(err) = self.spill() {
+                self.error = Some(err);
+                return;
+            }
+        }
+        match self.file.as_mut() {
+            Some(file) => {
+                if let Err(err) = file.write_all(bytes) {
+                    self.error = Some(err);
+                }
+            }
+            None => self.memory.extend_from_slice(bytes),
+        }
+    }
+
+    /// Moves what memory holds into a new file at `path` and frees it.
+    fn spill(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.path)?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&mem::take(&mut self.memory))?;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Completes the buffer. A spill file that could not be written is
+    /// removed and its error returned.
+    pub fn finish(mut self) -> Result<Spilled> {
+        let error = self.error.take();
+        let Some(file) = self.file.take() else {
+            return match error {
+                Some(err) => Err(err).with_context(|| {
+                    format!("Failed to spill the output to {}", self.path.display())
+                }),
+                None => Ok(Spilled::Memory(mem::take(&mut self.memory))),
+            };
+        };
+        let flushed = match error {
+            Some(err) => Err(err),
+            None => file.into_inner().map_err(|err| err.into_error()).map(drop),
+        };
+        match flushed {
+            Ok(()) => Ok(Spilled::File(mem::take(&mut self.path))),
+            Err(err) => {
+                let _ = fs::remove_file(&self.path);
+                Err(err).with_context(|| {
+                    format!("Failed to spill the output to {}", self.path.display())
+                })
+            }
+        }
+    }
+}
+
+impl Drop for SpillBuffer {
+    /// A buffer dropped unfinished leaves no file behind.
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_in_memory_up_to_the_limit() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("spill");
+        let mut buffer = SpillBuffer::new(Some(8), 1 << 20, path.clone());
+        buffer.extend_from_slice(b"abcd");
+        buffer.extend_from_slice(b"efgh");
+        assert_eq!(buffer.len(), 8);
+        assert_eq!(
+            buffer.finish().expect("finish"),
+            Spilled::Memory(b"abcdefgh".to_vec())
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn spills_everything_once_past_the_limit() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("spill");
+        let mut buffer = SpillBuffer::new(Some(8), 0, path.clone());
+        buffer.extend_from_slice(b"abcdef");
+        assert!(!path.exists());
+        buffer.extend_from_slice(b"ghij");
+        assert!(path.exists());
+        buffer.extend_from_slice(b"klm");
+        assert_eq!(buffer.len(), 13);
+        assert_eq!(
+            buffer.finish().expect("finish"),
+            Spilled::File(path.clone())
+        );
+        assert_eq!(fs::read(&path).expect("read spill file"), b"abcdefghijklm");
+    }
+
+    #[test]
+    fn without_a_limit_nothing_spills() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("spill");
+        let mut buffer = SpillBuffer::new(None, 0, path.clone());
+        buffer.extend_from_slice(&[b'x'; 4096]);
+        assert_eq!(
+            buffer.finish().expect("finish"),
+            Spilled::Memory(vec![b'x'; 4096])
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unfinished_spill_files_are_removed() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("spill");
+        let mut buffer = SpillBuffer::new(Some(1), 0, path.clone());
+        buffer.extend_from_slice(b"ab");
+        assert!(path.exists());
+        drop(buffer);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn a_spill_file_that_cannot_be_created_is_an_error() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("missing").join("nested").join("out.txt");
+        let mut buffer = SpillBuffer::new(Some(1), 0, path);
+        buffer.extend_from_slice(b"ab");
+        let err = buffer.finish().expect_err("no such directory");
+        assert!(err
+            .to_string()
+            .starts_with("Failed to spill the output to "));
+    }
+}
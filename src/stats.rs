@@ -0,0 +1,482 @@
+use crate::output::{grouped_count, human_size};
+use crate::sample::Sampled;
+use crate::separators;
+use copytree::order;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+/// How many entries `Largest files` lists.
+const LARGEST: usize = 10;
+
+/// One walked file as `copytree stats` sees it: its metadata and, when it
+/// would be left out without reading it, the reason code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The path relative to the display base, as walked.
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub skip: Option<&'static str>,
+    /// The media type its leading bytes show; `None` under `--no-sniff` or
+    /// when it cannot be read.
+    pub content_type: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Group {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Group {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NamedGroup {
+    pub name: String,
+    #[serde(flatten)]
+    pub group: Group,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LargeFile {
+    pub path: String,
+    pub bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<&'static str>,
+}
+
+/// What `copytree stats` reports, ready to render as a table or JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub total: Group,
+    /// Directories that hold at least one of the files.
+    pub directories: u64,
+    pub included: Group,
+    pub skipped: Group,
+    /// Skipped files by reason code.
+    pub skip_reasons: BTreeMap<&'static str, u64>,
+    /// Files by detected media type; empty under `--no-sniff`.
+    pub content_types: BTreeMap<&'static str, u64>,
+    /// Largest first; ties by name, in [`order`].
+    pub by_extension: Vec<NamedGroup>,
+    pub by_top_level: Vec<NamedGroup>,
+    pub largest: Vec<LargeFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampled: Option<Sampled>,
+}
+
+impl Stats {
+    pub fn collect(entries: &[Entry], sampled: Option<Sampled>) -> Self {
+        let mut total = Group::default();
+        let mut included = Group::default();
+        let mut skipped = Group::default();
+        let mut skip_reasons = BTreeMap::new();
+        let mut content_types = BTreeMap::new();
+        let mut directories = BTreeSet::new();
+        let mut by_extension: BTreeMap<OsString, Group> = BTreeMap::new();
+        let mut by_top_level: BTreeMap<OsString, Group> = BTreeMap::new();
+        for entry in entries {
+            total.add(entry.bytes);
+            match entry.skip {
+                Some(code) => {
+                    skipped.add(entry.bytes);
+                    *skip_reasons.entry(code).or_default() += 1;
+                }
+                None => included.add(entry.bytes),
+            }
+            if let Some(content_type) = entry.content_type {
+                *content_types.entry(content_type).or_default() += 1;
+            }
+            directories.extend(
+                entry
+                    .path
+                    .ancestors()
+                    .skip(1)
+                    .filter(|dir| !dir.as_os_str().is_empty()),
+            );
+            by_extension
+                .entry(extension(&entry.path))
+                .or_default()
+                .add(entry.bytes);
+            by_top_level
+                .entry(top_level(&entry.path))
+                .or_default()
+                .add(entry.bytes);
+        }
+
+        let mut largest: Vec<&Entry> = entries.iter().collect();
+        largest.sort_by(|a, b| {
+            b.bytes
+                .cmp(&a.bytes)
+                .then_with(|| order::paths(&a.path, &b.path))
+        });
+        largest.truncate(LARGEST);
+        let largest = largest
+            .into_iter()
+            .map(|entry| LargeFile {
+                path: separators::display(&entry.path),
+                bytes: entry.bytes,
+                skip: entry.skip,
+                content_type: entry.content_type,
+            })
+            .collect();
+
+        Self {
+            total,
+            directories: directories.len() as u64,
+            included,
+            skipped,
+            skip_reasons,
+            content_types,
+            by_extension: largest_first(by_extension),
+            by_top_level: largest_first(by_top_level),
+            largest,
+            sampled,
+        }
+    }
+
+    /// The report as text: totals, then one table per grouping, then the
+    /// sample taken, if any.
+    pub fn render(&self) -> String {
+        let mut text = format!("Included: {}\n", describe(self.included));
+        text.push_str(&format!("Skipped: {}", describe(self.skipped)));
+        if !self.skip_reasons.is_empty() {
+            let breakdown: Vec<String> = self
+                .skip_reasons
+                .iter()
+                .map(|(reason, count)| format!("{} {}", count, reason))
+                .collect();
+            text.push_str(&format!(" ({})", breakdown.join(", ")));
+        }
+        text.push('\n');
+        text.push_str(&format!(
+            "Total: {} in {} director{}\n",
+            describe(self.total),
+            grouped_count(self.directories),
+            if self.directories == 1 { "y" } else { "ies" }
+        ));
+        if !self.content_types.is_empty() {
+            let mut counts: Vec<(&str, u64)> = self
+                .content_types
+                .iter()
+                .map(|(&content_type, &count)| (content_type, count))
+                .collect();
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            let breakdown: Vec<String> = counts
+                .iter()
+                .map(|(content_type, count)| format!("{} {}", grouped_count(*count), content_type))
+                .collect();
+            text.push_str(&format!("Types: {}\n", breakdown.join(", ")));
+        }
+
+        for (title, groups) in [
+            ("By extension", &self.by_extension),
+            ("By top-level directory", &self.by_top_level),
+        ] {
+            if groups.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("\n{}:\n", title));
+            let rows: Vec<[String; 3]> = groups
+                .iter()
+                .map(|named| {
+                    [
+                        named.name.clone(),
+                        file_count(named.group.files),
+                        human_size(named.group.bytes),
+                    ]
+                })
+                .collect();
+            text.push_str(&table(&rows, [false, true, true]));
+        }
+
+        if !self.largest.is_empty() {
+            text.push_str("\nLargest files:\n");
+            let path = |file: &LargeFile| match file.skip {
+                Some(code) => format!("{} ({})", file.path, code),
+                None => file.path.clone(),
+            };
+            // The type column only appears when the files were sniffed.
+            if self.largest.iter().any(|file| file.content_type.is_some()) {
+                let rows: Vec<[String; 3]> = self
+                    .largest
+                    .iter()
+                    .map(|file| {
+                        let content_type = file.content_type.unwrap_or("?").to_string();
+                        [human_size(file.bytes), content_type, path(file)]
+                    })
+                    .collect();
+                text.push_str(&table(&rows, [true, false, false]));
+            } else {
+                let rows: Vec<[String; 2]> = self
+                    .largest
+                    .iter()
+                    .map(|file| [human_size(file.bytes), path(file)])
+                    .collect();
+                text.push_str(&table(&rows, [true, false]));
+            }
+        }
+        if let Some(sampled) = &self.sampled {
+            text.push_str(&format!("\nSampled: {}\n", sampled));
+        }
+        text
+    }
+}
+
+/// `.rs` for `src/main.rs`; `(none)` for `Makefile` and `.gitignore`.
+fn extension(path: &Path) -> OsString {
+    match path.extension() {
+        Some(ext) => [".".as_ref(), ext].into_iter().collect(),
+        None => "(none)".into(),
+    }
+}
+
+/// `src/` for `src/main.rs`; `/` for an absolute path; `.` for a file at
+/// the top.
+fn top_level(path: &Path) -> OsString {
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::RootDir), _) => "/".into(),
+        (Some(first), Some(_)) => [first.as_os_str(), "/".as_ref()].into_iter().collect(),
+        _ => ".".into(),
+    }
+}
+
+/// Names stay raw until sorted, so names that only turn equal once made
+/// text still keep their own rows in a stable order.
+fn largest_first(groups: BTreeMap<OsString, Group>) -> Vec<NamedGroup> {
+    let mut groups: Vec<(OsString, Group)> = groups.into_iter().collect();
+    groups.sort_by(|a, b| {
+        b.1.bytes
+            .cmp(&a.1.bytes)
+            .then_with(|| order::names(&a.0, &b.0))
+    });
+    groups
+        .into_iter()
+        .map(|(name, group)| NamedGroup {
+            name: name.to_string_lossy().into_owned(),
+            group,
+        })
+        .collect()
+}
+
+pub fn file_count(files: u64) -> String {
+    format!(
+        "{} file{}",
+        grouped_count(files),
+        if files == 1 { "" } else { "s" }
+    )
+}
+
+/// `3 files, 96 KiB`.
+fn describe(group: Group) -> String {
+    format!("{}, {}", file_count(group.files), human_size(group.bytes))
+}
+
+/// Rows indented by two spaces with columns two spaces apart, padded to the
+/// widest cell; `right` picks the columns aligned to the right.
+pub fn table<const N: usize>(rows: &[[String; N]], right: [bool; N]) -> String {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut text = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter().zip(right))
+            .map(|(cell, (&width, right))| {
+                if right {
+                    format!("{:>width$}", cell)
+                } else {
+                    format!("{:<width$}", cell)
+                }
+            })
+            .collect();
+        text.push_str("  ");
+        text.push_str(cells.join("  ").trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, bytes: u64, skip: Option<&'static str>) -> Entry {
+        Entry {
+            path: PathBuf::from(path),
+            bytes,
+            skip,
+            content_type: None,
+        }
+    }
+
+    fn sample() -> Vec<Entry> {
+        vec![
+            entry("README.md", 300, None),
+            entry("src/main.rs", 1200, None),
+            entry("src/util/mod.rs", 800, None),
+            entry("assets/logo.png", 40_000, Some("too-large")),
+            entry("Cargo.lock", 9000, Some("excluded")),
+        ]
+    }
+
+    #[test]
+    fn groups_by_extension_and_top_level_directory_largest_first() {
+        let stats = Stats::collect(&sample(), None);
+        assert_eq!(
+            stats.total,
+            Group {
+                files: 5,
+                bytes: 51_300
+            }
+        );
+        assert_eq!(stats.directories, 3);
+        assert_eq!(
+            stats.included,
+            Group {
+                files: 3,
+                bytes: 2300
+            }
+        );
+        assert_eq!(
+            stats.skip_reasons.into_iter().collect::<Vec<_>>(),
+            [("excluded", 1), ("too-large", 1)]
+        );
+        let names = |groups: &[NamedGroup]| -> Vec<(String, u64)> {
+            groups
+                .iter()
+                .map(|named| (named.name.clone(), named.group.files))
+                .collect()
+        };
+        assert_eq!(
+            names(&stats.by_extension),
+            [
+                (".png".to_string(), 1),
+                (".lock".to_string(), 1),
+                (".rs".to_string(), 2),
+                (".md".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            names(&stats.by_top_level),
+            [
+                ("assets/".to_string(), 1),
+                (".".to_string(), 2),
+                ("src/".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn largest_keeps_ten_files_with_ties_by_path() {
+        let entries: Vec<Entry> = (0..12)
+            .map(|index| entry(&format!("f{:02}.txt", index), 100 + index / 2, None))
+            .collect();
+        let stats = Stats::collect(&entries, None);
+        let largest: Vec<&str> = stats
+            .largest
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect();
+        assert_eq!(
+            largest,
+            [
+                "f10.txt", "f11.txt", "f08.txt", "f09.txt", "f06.txt", "f07.txt", "f04.txt",
+                "f05.txt", "f02.txt", "f03.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_aligned_tables() {
+        let entries = [
+            entry("src/main.rs", 1200, None),
+            entry("big.bin", 40_000, Some("too-large")),
+            entry("Makefile", 12, None),
+        ];
+        assert_eq!(
+            Stats::collect(&entries, None).render(),
+            "Included: 2 files, 1.2 KiB\n\
+             Skipped: 1 file, 39 KiB (1 too-large)\n\
+             Total: 3 files, 40 KiB in 1 directory\n\
+             \n\
+             By extension:\n\
+             \x20 .bin    1 file   39 KiB\n\
+             \x20 .rs     1 file  1.2 KiB\n\
+             \x20 (none)  1 file     12 B\n\
+             \n\
+             By top-level directory:\n\
+             \x20 .     2 files   39 KiB\n\
+             \x20 src/   1 file  1.2 KiB\n\
+             \n\
+             Largest files:\n\
+             \x20  39 KiB  big.bin (too-large)\n\
+             \x20 1.2 KiB  src/main.rs\n\
+             \x20    12 B  Makefile\n"
+        );
+    }
+
+    #[test]
+    fn content_types_are_counted_and_shown_beside_the_largest_files() {
+        let typed = |path: &str, bytes, content_type| Entry {
+            content_type: Some(content_type),
+            ..entry(path, bytes, None)
+        };
+        let entries = [
+            typed("a.png", 900, "image/png"),
+            typed("b.png", 800, "image/png"),
+            typed("c.gz", 700, "application/gzip"),
+            typed("notes.txt", 10, "text/plain; charset=utf-16"),
+            entry("unread.bin", 5, None),
+        ];
+        let stats = Stats::collect(&entries, None);
+        assert_eq!(
+            stats.content_types.into_iter().collect::<Vec<_>>(),
+            [
+                ("application/gzip", 1),
+                ("image/png", 2),
+                ("text/plain; charset=utf-16", 1)
+            ]
+        );
+        let text = Stats::collect(&entries, None).render();
+        assert!(
+            text.contains("Types: 2 image/png, 1 application/gzip, 1 text/plain; charset=utf-16\n"),
+            "{text}"
+        );
+        assert!(
+            text.ends_with(
+                "Largest files:\n\
+                 \x20 900 B  image/png                   a.png\n\
+                 \x20 800 B  image/png                   b.png\n\
+                 \x20 700 B  application/gzip            c.gz\n\
+                 \x20  10 B  text/plain; charset=utf-16  notes.txt\n\
+                 \x20   5 B  ?                           unread.bin\n"
+            ),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn json_names_every_group() {
+        let json = serde_json::to_value(Stats::collect(&sample()[..2], None)).expect("serialize");
+        assert_eq!(json["total"]["files"], 2);
+        assert_eq!(json["by_extension"][0]["name"], ".rs");
+        assert_eq!(json["by_extension"][0]["bytes"], 1200);
+        assert_eq!(json["largest"][1]["path"], "README.md");
+        assert!(json["largest"][0].get("skip").is_none());
+        assert!(json.get("sampled").is_none());
+    }
+}
@@ -0,0 +1,141 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/// When to color terminal output, chosen with `--color`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Color when the stream is a terminal and `NO_COLOR` is unset.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether a stream gets colors. `no_color` is whether `NO_COLOR` is set
+    /// to a non-empty value, which only `auto` honors.
+    pub fn enabled(self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            Self::Auto => is_terminal && !no_color,
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+
+    /// The styler for a stream, reading `NO_COLOR` from the environment.
+    pub fn styler(self, is_terminal: bool) -> Styler {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Styler::new(self.enabled(is_terminal, no_color))
+    }
+}
+
+/// What a styled span shows, which decides its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Directory,
+    Symlink,
+    /// A file whose contents will be left out.
+    Skipped,
+    Size,
+}
+
+impl Role {
+    fn sgr(self) -> &'static str {
+        match self {
+            Self::Directory => "34",
+            Self::Symlink => "36",
+            Self::Skipped => "2",
+            Self::Size => "90",
+        }
+    }
+}
+
+/// Wraps spans in ANSI escapes when enabled and passes them through
+/// untouched otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Styler {
+    enabled: bool,
+}
+
+impl Styler {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    pub fn paint(self, role: Role, text: &str) -> Cow<'_, str> {
+        if self.enabled && !text.is_empty() {
+            Cow::Owned(format!("\x1b[{}m{}\x1b[0m", role.sgr(), text))
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+}
+
+/// A writer that can present a span differently from its plain text. Text
+/// written through `write_styled` must read the same with the styling
+/// stripped, so sinks that take plain text stay byte-identical.
+pub trait StyledWrite: Write {
+    fn write_styled(&mut self, role: Role, text: &str) -> io::Result<()>;
+}
+
+/// Paints every styled span with one styler, for output that has a single
+/// destination.
+pub struct Painter<W> {
+    out: W,
+    styler: Styler,
+}
+
+impl<W: Write> Painter<W> {
+    pub fn new(out: W, styler: Styler) -> Self {
+        Self { out, styler }
+    }
+}
+
+impl<W: Write> Write for Painter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: Write> StyledWrite for Painter<W> {
+    fn write_styled(&mut self, role: Role, text: &str) -> io::Result<()> {
+        self.out.write_all(self.styler.paint(role, text).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_colors_only_terminals_without_no_color() {
+        assert!(ColorChoice::Auto.enabled(true, false));
+        assert!(!ColorChoice::Auto.enabled(false, false));
+        assert!(!ColorChoice::Auto.enabled(true, true));
+    }
+
+    #[test]
+    fn always_and_never_ignore_the_terminal_and_no_color() {
+        for (is_terminal, no_color) in [(false, false), (true, true)] {
+            assert!(ColorChoice::Always.enabled(is_terminal, no_color));
+            assert!(!ColorChoice::Never.enabled(is_terminal, no_color));
+        }
+    }
+
+    #[test]
+    fn disabled_styler_leaves_text_untouched() {
+        assert_eq!(Styler::new(false).paint(Role::Directory, "src"), "src");
+        assert_eq!(
+            Styler::new(true).paint(Role::Directory, "src"),
+            "\x1b[34msrc\x1b[0m"
+        );
+        assert_eq!(Styler::new(true).paint(Role::Size, ""), "");
+    }
+}
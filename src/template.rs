@@ -0,0 +1,1004 @@
+use crate::content::FileContent;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Templates shipped with copytree, chosen with `--template @NAME`.
+pub const BUILTIN: &[(&str, &str)] = &[
+    ("prompt", include_str!("../templates/prompt.txt")),
+    ("review", include_str!("../templates/review.txt")),
+];
+
+/// Filters `{{ value | name }}` may apply.
+const FILTERS: &[&str] = &[
+    "escape", "json", "length", "lower", "rtrim", "trim", "upper",
+];
+
+/// What a template variable holds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    None,
+    Bool(bool),
+    Int(u64),
+    Str(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::None => false,
+            Self::Bool(value) => *value,
+            Self::Int(value) => *value != 0,
+            Self::Str(text) => !text.is_empty(),
+            Self::List(items) => !items.is_empty(),
+            Self::Map(fields) => !fields.is_empty(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Bool(_) => "a boolean",
+            Self::Int(_) => "a number",
+            Self::Str(_) => "a string",
+            Self::List(_) => "a list",
+            Self::Map(_) => "an object",
+        }
+    }
+
+    /// The text `{{ }}` prints, or `None` for lists and objects.
+    fn text(&self) -> Option<String> {
+        match self {
+            Self::None => Some(String::new()),
+            Self::Bool(value) => Some(value.to_string()),
+            Self::Int(value) => Some(value.to_string()),
+            Self::Str(text) => Some(text.clone()),
+            Self::List(_) | Self::Map(_) => None,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::None => serde_json::Value::Null,
+            Self::Bool(value) => (*value).into(),
+            Self::Int(value) => (*value).into(),
+            Self::Str(text) => text.as_str().into(),
+            Self::List(items) => items.iter().map(Self::to_json).collect(),
+            Self::Map(fields) => fields
+                .iter()
+                .map(|(name, value)| (name.clone(), value.to_json()))
+                .collect(),
+        }
+    }
+}
+
+/// Where in the template source something is, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A template that could not be parsed or rendered.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TemplateError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.pos.line, self.pos.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+fn error<T>(pos: Pos, message: impl Into<String>) -> Result<T, TemplateError> {
+    Err(TemplateError {
+        pos,
+        message: message.into(),
+    })
+}
+
+/// A parsed `--template`: text with `{{ value | filter }}` substitutions,
+/// `{% if %}`/`{% elif %}`/`{% else %}`/`{% endif %}`,
+/// `{% for x in list %}`/`{% endfor %}`, `{% raw %}`/`{% endraw %}`, and
+/// `{# comments #}`. A newline right after a block tag or comment is
+/// dropped, as is the indentation before one on its own line.
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Print(Expr),
+    If {
+        branches: Vec<(Cond, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        name: String,
+        list: Expr,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug)]
+struct Expr {
+    operand: Operand,
+    pos: Pos,
+    filters: Vec<(String, Pos)>,
+}
+
+#[derive(Debug)]
+enum Operand {
+    /// A variable and the fields read from it, as in `file.path`.
+    Var(Vec<String>),
+    Str(String),
+    Int(u64),
+}
+
+#[derive(Debug)]
+enum Cond {
+    Test(Expr),
+    Equals(Expr, Expr),
+    Not(Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+/// Loads `spec`: `@NAME` for a built-in template, otherwise a file.
+pub fn load(spec: &str) -> Result<Template> {
+    let (label, source) = match spec.strip_prefix('@') {
+        Some(name) => match BUILTIN.iter().find(|(builtin, _)| *builtin == name) {
+            Some((_, source)) => (spec.to_string(), source.to_string()),
+            None => bail!(
+                "No built-in template `{}`; choose one of {}",
+                spec,
+                BUILTIN
+                    .iter()
+                    .map(|(name, _)| format!("@{}", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        },
+        None => (
+            spec.to_string(),
+            fs::read_to_string(spec)
+                .with_context(|| format!("Failed to read template {}", spec))?,
+        ),
+    };
+    Template::parse(&source).with_context(|| format!("Invalid template {}", label))
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut segments = scan(source)?.into_iter();
+        let (nodes, _) = parse_until(&mut segments, &[])?;
+        Ok(Self { nodes })
+    }
+
+    /// Renders with `context` as the top-level variables. Reading a
+    /// variable or field that does not exist is an error naming it.
+    pub fn render(&self, context: &BTreeMap<String, Value>) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        let mut scope = Scope {
+            context,
+            locals: Vec::new(),
+        };
+        render_nodes(&self.nodes, &mut scope, &mut out)?;
+        Ok(out)
+    }
+}
+
+/// The variables of the `{{ files }}` list entry for one file: its
+/// 1-based `index`, `path`, `content` (the body, or the note that stands in
+/// for a skipped one), `skipped_reason` (none for included files), and the
+/// fence `lang` its extension suggests.
+pub fn file_value(index: usize, path: &str, content: &FileContent) -> Value {
+    let (body, reason) = match content {
+        FileContent::Text(text) => (text.clone(), Value::None),
+        FileContent::Binary(_) | FileContent::Image { .. } => {
+            (content.body().into_owned(), Value::None)
+        }
+        FileContent::Skipped(reason) => (reason.marker(), Value::Str(reason.describe())),
+    };
+    Value::Map(BTreeMap::from([
+        ("index".to_string(), Value::Int(index as u64 + 1)),
+        ("path".to_string(), Value::Str(path.to_string())),
+        ("content".to_string(), Value::Str(body)),
+        ("skipped_reason".to_string(), reason),
+        ("lang".to_string(), Value::Str(lang(path))),
+    ]))
+}
+
+/// The Markdown fence language for `path`, from its extension.
+fn lang(path: &str) -> String {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name = match extension.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "mts" | "cts" => "typescript",
+        "rb" => "ruby",
+        "sh" | "bash" => "bash",
+        "yml" => "yaml",
+        "md" => "markdown",
+        "h" => "c",
+        "hpp" | "cc" | "cxx" => "cpp",
+        "cs" => "csharp",
+        "kt" => "kotlin",
+        "txt" => "text",
+        _ => return extension,
+    };
+    name.to_string()
+}
+
+/// A piece of the source between tags.
+enum Segment {
+    Text(String),
+    Print(Vec<Token>, Pos),
+    Tag(Vec<Token>, Pos),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Str(String),
+    Int(u64),
+    Dot,
+    Pipe,
+    Equals,
+    NotEquals,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    pos: Pos,
+}
+
+/// Line and column of each byte offset of a source.
+struct Lines<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(source: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Self { source, starts }
+    }
+
+    fn pos(&self, offset: usize) -> Pos {
+        let line = self.starts.partition_point(|&start| start <= offset);
+        let start = self.starts[line - 1];
+        Pos {
+            line,
+            column: self.source[start..offset].chars().count() + 1,
+        }
+    }
+}
+
+/// Splits `source` into text and tags, applying the whitespace rules.
+fn scan(source: &str) -> Result<Vec<Segment>, TemplateError> {
+    let lines = Lines::new(source);
+    let mut segments = Vec::new();
+    let mut cursor = 0;
+    let mut raw_from: Option<usize> = None;
+    loop {
+        let next = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| source[cursor..].find(open).map(|at| cursor + at))
+            .min();
+        let Some(open) = next else {
+            if let Some(start) = raw_from {
+                return error(
+                    lines.pos(start),
+                    "`{% raw %}` is never closed with `{% endraw %}`",
+                );
+            }
+            segments.push(Segment::Text(source[cursor..].to_string()));
+            return Ok(segments);
+        };
+        let kind = &source[open..open + 2];
+        let close = match kind {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let Some(end) = source[open + 2..].find(close).map(|at| open + 2 + at) else {
+            return error(
+                lines.pos(open),
+                format!("`{}` is never closed with `{}`", kind, close),
+            );
+        };
+        let inner = &source[open + 2..end];
+        if raw_from.is_some() && !(kind == "{%" && inner.trim() == "endraw") {
+            // Inside raw, everything up to `{% endraw %}` is text.
+            segments.push(Segment::Text(source[cursor..end + 2].to_string()));
+            cursor = end + 2;
+            continue;
+        }
+        let is_block = kind != "{{";
+        let mut text_end = open;
+        if is_block {
+            let line_start = source[..open].rfind('\n').map_or(0, |at| at + 1);
+            if line_start >= cursor && source[line_start..open].trim().is_empty() {
+                text_end = line_start;
+            }
+        }
+        segments.push(Segment::Text(source[cursor..text_end].to_string()));
+        cursor = end + 2;
+        if is_block {
+            if source[cursor..].starts_with("\r\n") {
+                cursor += 2;
+            } else if source[cursor..].starts_with('\n') {
+                cursor += 1;
+            }
+        }
+        match kind {
+            "{#" => {}
+            "{{" => segments.push(Segment::Print(
+                tokenize(inner, open + 2, &lines)?,
+                lines.pos(open),
+            )),
+            _ => match inner.trim() {
+                "raw" => raw_from = Some(open),
+                "endraw" if raw_from.is_some() => raw_from = None,
+                _ => segments.push(Segment::Tag(
+                    tokenize(inner, open + 2, &lines)?,
+                    lines.pos(open),
+                )),
+            },
+        }
+    }
+}
+
+/// The tokens of a tag's inside, which starts at `offset` in the source.
+fn tokenize(inner: &str, offset: usize, lines: &Lines) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut chars = inner.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let pos = lines.pos(offset + at);
+        let tok = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '.' => {
+                chars.next();
+                Tok::Dot
+            }
+            '|' => {
+                chars.next();
+                Tok::Pipe
+            }
+            '=' | '!' => {
+                chars.next();
+                if chars.next_if(|&(_, next)| next == '=').is_none() {
+                    return error(pos, format!("expected `{}=`", c));
+                }
+                if c == '=' {
+                    Tok::Equals
+                } else {
+                    Tok::NotEquals
+                }
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, close)) if close == c => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, escaped)) => text.push(escaped),
+                            None => return error(pos, "unterminated string"),
+                        },
+                        Some((_, other)) => text.push(other),
+                        None => return error(pos, "unterminated string"),
+                    }
+                }
+                Tok::Str(text)
+            }
+            _ if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some((_, digit)) = chars.next_if(|(_, next)| next.is_ascii_digit()) {
+                    digits.push(digit);
+                }
+                match digits.parse() {
+                    Ok(number) => Tok::Int(number),
+                    Err(_) => return error(pos, format!("number {} is too large", digits)),
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some((_, part)) =
+                    chars.next_if(|(_, next)| next.is_alphanumeric() || *next == '_')
+                {
+                    name.push(part);
+                }
+                Tok::Ident(name)
+            }
+            _ => return error(pos, format!("unexpected `{}`", c)),
+        };
+        tokens.push(Token { tok, pos });
+    }
+    Ok(tokens)
+}
+
+/// The tag that ended a run of nodes: its name, remaining tokens, and
+/// position.
+type Stop = Option<(String, Vec<Token>, Pos)>;
+
+/// Parses nodes up to a tag named in `stops`.
+fn parse_until<I>(segments: &mut I, stops: &[&str]) -> Result<(Vec<Node>, Stop), TemplateError>
+where
+    I: Iterator<Item = Segment>,
+{
+    let mut nodes = Vec::new();
+    while let Some(segment) = segments.next() {
+        match segment {
+            Segment::Text(text) if text.is_empty() => {}
+            Segment::Text(text) => nodes.push(Node::Text(text)),
+            Segment::Print(tokens, pos) => {
+                let mut tokens = Tokens::new(tokens, pos);
+                let expr = tokens.expr()?;
+                tokens.finish()?;
+                nodes.push(Node::Print(expr));
+            }
+            Segment::Tag(tokens, pos) => {
+                let mut tokens = Tokens::new(tokens, pos);
+                let name = tokens.ident("a tag name")?;
+                if stops.contains(&name.as_str()) {
+                    return Ok((nodes, Some((name, tokens.rest(), pos))));
+                }
+                match name.as_str() {
+                    "if" => {
+                        let mut branches = Vec::new();
+                        let mut otherwise = Vec::new();
+                        let mut cond = tokens.cond()?;
+                        tokens.finish()?;
+                        loop {
+                            let (body, end) = parse_until(segments, &["elif", "else", "endif"])?;
+                            let Some((end, rest, end_pos)) = end else {
+                                return error(pos, "`{% if %}` is never closed with `{% endif %}`");
+                            };
+                            branches.push((cond, body));
+                            match end.as_str() {
+                                "elif" => {
+                                    let mut rest = Tokens::new(rest, end_pos);
+                                    cond = rest.cond()?;
+                                    rest.finish()?;
+                                }
+                                "else" => {
+                                    Tokens::new(rest, end_pos).finish()?;
+                                    let (body, end) = parse_until(segments, &["endif"])?;
+                                    let Some((_, rest, end_pos)) = end else {
+                                        return error(
+                                            pos,
+                                            "`{% if %}` is never closed with `{% endif %}`",
+                                        );
+                                    };
+                                    Tokens::new(rest, end_pos).finish()?;
+                                    otherwise = body;
+                                    break;
+                                }
+                                _ => {
+                                    Tokens::new(rest, end_pos).finish()?;
+                                    break;
+                                }
+                            }
+                        }
+                        nodes.push(Node::If {
+                            branches,
+                            otherwise,
+                        });
+                    }
+                    "for" => {
+                        let name = tokens.ident("a loop variable")?;
+                        if tokens.ident("`in`")? != "in" {
+                            return error(pos, "expected `{% for NAME in LIST %}`");
+                        }
+                        let list = tokens.expr()?;
+                        tokens.finish()?;
+                        let (body, end) = parse_until(segments, &["endfor"])?;
+                        let Some((_, rest, end_pos)) = end else {
+                            return error(pos, "`{% for %}` is never closed with `{% endfor %}`");
+                        };
+                        Tokens::new(rest, end_pos).finish()?;
+                        nodes.push(Node::For { name, list, body });
+                    }
+                    "elif" | "else" | "endif" | "endfor" | "endraw" => {
+                        return error(pos, format!("unexpected `{{% {} %}}`", name));
+                    }
+                    _ => return error(pos, format!("unknown tag `{}`", name)),
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+/// The tokens of one tag, consumed front to back.
+struct Tokens {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Option<Token>,
+    /// The tag's position, for errors at its end.
+    pos: Pos,
+}
+
+impl Tokens {
+    fn new(tokens: Vec<Token>, pos: Pos) -> Self {
+        Self {
+            tokens: tokens.into_iter(),
+            peeked: None,
+            pos,
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.peeked.take().or_else(|| self.tokens.next())
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next();
+        }
+        self.peeked.as_ref()
+    }
+
+    fn rest(mut self) -> Vec<Token> {
+        self.peeked.take().into_iter().chain(self.tokens).collect()
+    }
+
+    fn finish(mut self) -> Result<(), TemplateError> {
+        match self.next() {
+            Some(token) => error(token.pos, format!("unexpected {}", describe(&token.tok))),
+            None => Ok(()),
+        }
+    }
+
+    fn ident(&mut self, wanted: &str) -> Result<String, TemplateError> {
+        match self.next() {
+            Some(Token {
+                tok: Tok::Ident(name),
+                ..
+            }) => Ok(name),
+            Some(token) => error(
+                token.pos,
+                format!("expected {}, found {}", wanted, describe(&token.tok)),
+            ),
+            None => error(self.pos, format!("expected {}", wanted)),
+        }
+    }
+
+    fn peek_keyword(&mut self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token { tok: Tok::Ident(name), .. }) if name == keyword)
+    }
+
+    fn expr(&mut self) -> Result<Expr, TemplateError> {
+        let Some(token) = self.next() else {
+            return error(self.pos, "expected a value");
+        };
+        let pos = token.pos;
+        let operand = match token.tok {
+            Tok::Str(text) => Operand::Str(text),
+            Tok::Int(number) => Operand::Int(number),
+            Tok::Ident(name) => {
+                let mut path = vec![name];
+                while matches!(self.peek(), Some(Token { tok: Tok::Dot, .. })) {
+                    self.next();
+                    path.push(self.ident("a field name")?);
+                }
+                Operand::Var(path)
+            }
+            other => return error(pos, format!("expected a value, found {}", describe(&other))),
+        };
+        let mut filters = Vec::new();
+        while matches!(self.peek(), Some(Token { tok: Tok::Pipe, .. })) {
+            self.next();
+            let end = self.pos;
+            let pos = self.peek().map_or(end, |token| token.pos);
+            let name = self.ident("a filter name")?;
+            if !FILTERS.contains(&name.as_str()) {
+                return error(
+                    pos,
+                    format!(
+                        "unknown filter `{}`; use one of {}",
+                        name,
+                        FILTERS.join(", ")
+                    ),
+                );
+            }
+            filters.push((name, pos));
+        }
+        Ok(Expr {
+            operand,
+            pos,
+            filters,
+        })
+    }
+
+    /// `a or b`, `a and b`, `not a`, `a == b`, `a != b`, and plain values.
+    fn cond(&mut self) -> Result<Cond, TemplateError> {
+        let mut cond = self.and()?;
+        while self.peek_keyword("or") {
+            self.next();
+            cond = Cond::Or(Box::new(cond), Box::new(self.and()?));
+        }
+        Ok(cond)
+    }
+
+    fn and(&mut self) -> Result<Cond, TemplateError> {
+        let mut cond = self.unary()?;
+        while self.peek_keyword("and") {
+            self.next();
+            cond = Cond::And(Box::new(cond), Box::new(self.unary()?));
+        }
+        Ok(cond)
+    }
+
+    fn unary(&mut self) -> Result<Cond, TemplateError> {
+        if self.peek_keyword("not") {
+            self.next();
+            return Ok(Cond::Not(Box::new(self.unary()?)));
+        }
+        let left = self.expr()?;
+        let negated = match self.peek().map(|token| &token.tok) {
+            Some(Tok::Equals) => false,
+            Some(Tok::NotEquals) => true,
+            _ => return Ok(Cond::Test(left)),
+        };
+        self.next();
+        let equals = Cond::Equals(left, self.expr()?);
+        Ok(if negated {
+            Cond::Not(Box::new(equals))
+        } else {
+            equals
+        })
+    }
+}
+
+fn describe(tok: &Tok) -> String {
+    match tok {
+        Tok::Ident(name) => format!("`{}`", name),
+        Tok::Str(text) => format!("string {:?}", text),
+        Tok::Int(number) => format!("number {}", number),
+        Tok::Dot => "`.`".to_string(),
+        Tok::Pipe => "`|`".to_string(),
+        Tok::Equals => "`==`".to_string(),
+        Tok::NotEquals => "`!=`".to_string(),
+    }
+}
+
+/// The variables in reach while rendering: loop variables over the
+/// context.
+struct Scope<'a> {
+    context: &'a BTreeMap<String, Value>,
+    locals: Vec<(String, Value)>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(local, _)| local == name)
+            .map(|(_, value)| value)
+            .or_else(|| self.context.get(name))
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<Value, TemplateError> {
+        let mut value = match &expr.operand {
+            Operand::Str(text) => Value::Str(text.clone()),
+            Operand::Int(number) => Value::Int(*number),
+            Operand::Var(path) => {
+                let Some(mut value) = self.lookup(&path[0]) else {
+                    return error(expr.pos, format!("undefined variable `{}`", path[0]));
+                };
+                for (depth, field) in path.iter().enumerate().skip(1) {
+                    let read = path[..depth].join(".");
+                    value = match value {
+                        Value::Map(fields) => match fields.get(field) {
+                            Some(value) => value,
+                            None => {
+                                return error(
+                                    expr.pos,
+                                    format!("undefined variable `{}.{}`", read, field),
+                                )
+                            }
+                        },
+                        other => {
+                            return error(
+                                expr.pos,
+                                format!(
+                                    "`{}` is {}, which has no field `{}`",
+                                    read,
+                                    other.kind(),
+                                    field
+                                ),
+                            )
+                        }
+                    };
+                }
+                value.clone()
+            }
+        };
+        for (filter, pos) in &expr.filters {
+            value = apply_filter(filter, value, *pos)?;
+        }
+        Ok(value)
+    }
+
+    fn test(&self, cond: &Cond) -> Result<bool, TemplateError> {
+        Ok(match cond {
+            Cond::Test(expr) => self.eval(expr)?.is_truthy(),
+            Cond::Equals(left, right) => self.eval(left)? == self.eval(right)?,
+            Cond::Not(cond) => !self.test(cond)?,
+            Cond::And(left, right) => self.test(left)? && self.test(right)?,
+            Cond::Or(left, right) => self.test(left)? || self.test(right)?,
+        })
+    }
+}
+
+fn apply_filter(filter: &str, value: Value, pos: Pos) -> Result<Value, TemplateError> {
+    if filter == "json" {
+        return Ok(Value::Str(value.to_json().to_string()));
+    }
+    if filter == "length" {
+        return match value {
+            Value::Str(text) => Ok(Value::Int(text.chars().count() as u64)),
+            Value::List(items) => Ok(Value::Int(items.len() as u64)),
+            Value::Map(fields) => Ok(Value::Int(fields.len() as u64)),
+            other => error(
+                pos,
+                format!("`length` needs a string or list, got {}", other.kind()),
+            ),
+        };
+    }
+    let Some(text) = value.text() else {
+        return error(
+            pos,
+            format!("`{}` needs text, got {}", filter, value.kind()),
+        );
+    };
+    Ok(Value::Str(match filter {
+        "escape" => escape(&text),
+        "lower" => text.to_lowercase(),
+        "upper" => text.to_uppercase(),
+        "rtrim" => text.trim_end().to_string(),
+        _ => text.trim().to_string(),
+    }))
+}
+
+/// `text` safe to place in HTML or XML markup and attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn render_nodes(nodes: &[Node], scope: &mut Scope, out: &mut String) -> Result<(), TemplateError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Print(expr) => {
+                let value = scope.eval(expr)?;
+                match value.text() {
+                    Some(text) => out.push_str(&text),
+                    None => {
+                        return error(
+                            expr.pos,
+                            format!(
+                                "cannot print {}; loop over it or use `| json`",
+                                value.kind()
+                            ),
+                        )
+                    }
+                }
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let mut taken = None;
+                for (cond, body) in branches {
+                    if scope.test(cond)? {
+                        taken = Some(body);
+                        break;
+                    }
+                }
+                render_nodes(taken.unwrap_or(otherwise), scope, out)?;
+            }
+            Node::For { name, list, body } => {
+                let items = match scope.eval(list)? {
+                    Value::List(items) => items,
+                    other => return error(list.pos, format!("cannot loop over {}", other.kind())),
+                };
+                let count = items.len();
+                for (index, item) in items.into_iter().enumerate() {
+                    let state = Value::Map(BTreeMap::from([
+                        ("index".to_string(), Value::Int(index as u64 + 1)),
+                        ("first".to_string(), Value::Bool(index == 0)),
+                        ("last".to_string(), Value::Bool(index + 1 == count)),
+                    ]));
+                    scope.locals.push(("loop".to_string(), state));
+                    scope.locals.push((name.clone(), item));
+                    let rendered = render_nodes(body, scope, out);
+                    scope.locals.truncate(scope.locals.len() - 2);
+                    rendered?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::SkipReason;
+    use crate::kind::FileKind;
+
+    fn context() -> BTreeMap<String, Value> {
+        let files = vec![
+            file_value(0, "src/a.rs", &FileContent::Text("fn a() {}\n".to_string())),
+            file_value(
+                1,
+                "logo.png",
+                &FileContent::Skipped(SkipReason::Binary {
+                    kind: FileKind::Binary,
+                }),
+            ),
+        ];
+        BTreeMap::from([
+            ("tree".to_string(), Value::Str(".\n└─ src\n".to_string())),
+            ("summary".to_string(), Value::Str("1 file".to_string())),
+            ("files".to_string(), Value::List(files)),
+        ])
+    }
+
+    fn render(source: &str) -> Result<String, TemplateError> {
+        Template::parse(source)?.render(&context())
+    }
+
+    #[test]
+    fn loops_and_conditionals_trim_their_own_lines() {
+        let source = "\
+{{ summary }} in {{ files | length }}
+{% for file in files %}
+  {% if file.skipped_reason %}
+{{ loop.index }}. {{ file.path }} skipped ({{ file.skipped_reason }})
+  {% elif file.lang == \"rust\" %}
+{{ loop.index }}. {{ file.path }} ```{{ file.lang }}
+{{ file.content | rtrim }}
+```
+  {% else %}
+other
+  {% endif %}
+{% endfor %}
+done
+";
+        assert_eq!(
+            render(source).expect("renders"),
+            "\
+1 file in 2
+1. src/a.rs ```rust
+fn a() {}
+```
+2. logo.png skipped (binary file)
+done
+"
+        );
+    }
+
+    #[test]
+    fn escaping_filters_and_raw_blocks() {
+        let source = "\
+{% raw %}
+{{ not a tag }}
+{% endraw %}
+{# a comment #}
+<a title=\"{{ \"<b & 'c'>\" | escape }}\">{{ tree | json }}</a>
+{% if not loop_free and files | length != 0 %}never{% else %}ok{% endif %}
+";
+        let err = render(source).expect_err("loop_free is undefined");
+        assert_eq!(
+            err.to_string(),
+            "line 6, column 11: undefined variable `loop_free`"
+        );
+
+        let source = source.replace("not loop_free and ", "");
+        assert_eq!(
+            render(&source).expect("renders"),
+            "\
+{{ not a tag }}
+<a title=\"&lt;b &amp; &#39;c&#39;&gt;\">\".\\n└─ src\\n\"</a>
+never"
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line_column_and_variable() {
+        let cases = [
+            (
+                "{{ file.path }}",
+                "line 1, column 4: undefined variable `file`",
+            ),
+            (
+                "{% for file in files %}\n{{ file.lagn }}{% endfor %}",
+                "line 2, column 4: undefined variable `file.lagn`",
+            ),
+            (
+                "ok\n  {{ summary | uper }}",
+                "line 2, column 16: unknown filter `uper`; use one of escape, json, length, \
+                 lower, rtrim, trim, upper",
+            ),
+            (
+                "{% if files %}\nunclosed",
+                "line 1, column 1: `{% if %}` is never closed with `{% endif %}`",
+            ),
+            (
+                "{{ files }}",
+                "line 1, column 4: cannot print a list; loop over it or use `| json`",
+            ),
+            (
+                "{% endfor %}",
+                "line 1, column 1: unexpected `{% endfor %}`",
+            ),
+            (
+                "a {{ tree",
+                "line 1, column 3: `{{` is never closed with `}}`",
+            ),
+            (
+                "{{ tree.lines }}",
+                "line 1, column 4: `tree` is a string, which has no field `lines`",
+            ),
+        ];
+        for (source, expected) in cases {
+            let err = render(source).expect_err(source);
+            assert_eq!(err.to_string(), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn builtin_templates_parse_and_unknown_names_are_listed() {
+        for (name, _) in BUILTIN {
+            let template = load(&format!("@{name}")).expect("built-in parses");
+            template.render(&context()).expect("built-in renders");
+        }
+        let err = load("@nope").expect_err("unknown");
+        assert_eq!(
+            err.to_string(),
+            "No built-in template `@nope`; choose one of @prompt, @review"
+        );
+    }
+}
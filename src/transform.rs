@@ -0,0 +1,190 @@
+//! Per-file processing of text bodies, run in a caller's order.
+//!
+//! A [`Transformer`] sees each text body with its path and replaces it,
+//! hands it back untouched, or skips the file. A [`Pipeline`] runs several
+//! in order and is a transformer itself:
+//!
+//! ```
+//! use copytree::transform::{Pipeline, TransformResult};
+//! use std::path::Path;
+//!
+//! let mut pipeline = Pipeline::default();
+//! pipeline.push(|path: &Path, body: String| {
+//!     if path.file_name().is_some_and(|name| name == ".env") {
+//!         TransformResult::Skip("holds secrets".to_string())
+//!     } else {
+//!         TransformResult::Passthrough(body)
+//!     }
+//! });
+//! pipeline.push(|_: &Path, body: String| {
+//!     TransformResult::Replace(body.replace("hunter2", "[redacted]"))
+//! });
+//!
+//! assert_eq!(
+//!     pipeline.run(Path::new("src/main.rs"), "pw = hunter2".to_string()),
+//!     TransformResult::Replace("pw = [redacted]".to_string())
+//! );
+//! assert_eq!(
+//!     pipeline.run(Path::new(".env"), "pw = hunter2".to_string()),
+//!     TransformResult::Skip("holds secrets".to_string())
+//! );
+//! ```
+
+use std::path::Path;
+
+/// What a [`Transformer`] made of a body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransformResult {
+    /// The body to emit instead.
+    Replace(String),
+    /// Leave the file out; the reason stands in for its body.
+    Skip(String),
+    /// The body, handed back unchanged.
+    Passthrough(String),
+}
+
+/// Processing of one file's text body.
+pub trait Transformer {
+    fn transform(&self, path: &Path, body: String) -> TransformResult;
+}
+
+impl<F> Transformer for F
+where
+    F: Fn(&Path, String) -> TransformResult,
+{
+    fn transform(&self, path: &Path, body: String) -> TransformResult {
+        self(path, body)
+    }
+}
+
+/// Transformers run in the order they were pushed, each on what the one
+/// before it returned. The first skip stops the run.
+#[derive(Default)]
+pub struct Pipeline {
+    transformers: Vec<Box<dyn Transformer + Send + Sync>>,
+}
+
+impl Pipeline {
+    /// Adds `transformer` after those already pushed.
+    pub fn push<T>(&mut self, transformer: T)
+    where
+        T: Transformer + Send + Sync + 'static,
+    {
+        self.transformers.push(Box::new(transformer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transformers.is_empty()
+    }
+
+    /// Runs every transformer on `body`: a [`TransformResult::Skip`] from
+    /// the first that skips, else [`TransformResult::Replace`] if any of
+    /// them replaced the body and [`TransformResult::Passthrough`] if none
+    /// did.
+    pub fn run(&self, path: &Path, body: String) -> TransformResult {
+        let mut body = body;
+        let mut replaced = false;
+        for transformer in &self.transformers {
+            body = match transformer.transform(path, body) {
+                TransformResult::Replace(text) => {
+                    replaced = true;
+                    text
+                }
+                TransformResult::Passthrough(text) => text,
+                skip @ TransformResult::Skip(_) => return skip,
+            };
+        }
+        if replaced {
+            TransformResult::Replace(body)
+        } else {
+            TransformResult::Passthrough(body)
+        }
+    }
+}
+
+impl Transformer for Pipeline {
+    fn transform(&self, path: &Path, body: String) -> TransformResult {
+        self.run(path, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Appends its label to the body and records that it ran.
+    struct Tag {
+        label: &'static str,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Transformer for Tag {
+        fn transform(&self, _: &Path, body: String) -> TransformResult {
+            self.log.lock().expect("log").push(self.label);
+            TransformResult::Replace(format!("{body}+{}", self.label))
+        }
+    }
+
+    fn tag(label: &'static str, log: &Arc<Mutex<Vec<&'static str>>>) -> Tag {
+        Tag {
+            label,
+            log: Arc::clone(log),
+        }
+    }
+
+    #[test]
+    fn transformers_run_in_the_order_they_were_pushed() {
+        let log = Arc::default();
+        let mut pipeline = Pipeline::default();
+        pipeline.push(tag("first", &log));
+        pipeline.push(tag("second", &log));
+        assert_eq!(
+            pipeline.run(Path::new("a.rs"), "body".to_string()),
+            TransformResult::Replace("body+first+second".to_string())
+        );
+        assert_eq!(*log.lock().expect("log"), ["first", "second"]);
+    }
+
+    #[test]
+    fn a_skip_stops_the_transformers_after_it() {
+        let log = Arc::default();
+        let mut pipeline = Pipeline::default();
+        pipeline.push(tag("first", &log));
+        pipeline.push(|path: &Path, body: String| {
+            if path.ends_with("secret.txt") {
+                TransformResult::Skip("secret".to_string())
+            } else {
+                TransformResult::Passthrough(body)
+            }
+        });
+        pipeline.push(tag("last", &log));
+
+        assert_eq!(
+            pipeline.run(Path::new("docs/secret.txt"), "body".to_string()),
+            TransformResult::Skip("secret".to_string())
+        );
+        assert_eq!(*log.lock().expect("log"), ["first"]);
+
+        // A pipeline nested in another propagates its skip the same way.
+        let mut outer = Pipeline::default();
+        outer.push(pipeline);
+        outer.push(tag("outer", &log));
+        assert_eq!(
+            outer.run(Path::new("secret.txt"), "body".to_string()),
+            TransformResult::Skip("secret".to_string())
+        );
+        assert_eq!(*log.lock().expect("log"), ["first", "first"]);
+    }
+
+    #[test]
+    fn passthrough_alone_leaves_the_body_unchanged() {
+        let mut pipeline = Pipeline::default();
+        assert!(pipeline.is_empty());
+        pipeline.push(|_: &Path, body: String| TransformResult::Passthrough(body));
+        assert_eq!(
+            pipeline.run(Path::new("a.rs"), "body".to_string()),
+            TransformResult::Passthrough("body".to_string())
+        );
+    }
+}
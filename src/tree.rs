@@ -0,0 +1,438 @@
+//! The directory tree copytree prints, drawn from paths alone.
+//!
+//! [`render_tree`] draws a list of relative paths the way `copytree tree`
+//! does, without touching the filesystem:
+//!
+//! ```
+//! use copytree::tree::{render_tree, TreeOptions};
+//! use std::path::PathBuf;
+//!
+//! let entries: Vec<PathBuf> = ["src/main.rs", "src/lib.rs", "README.md"]
+//!     .iter()
+//!     .map(PathBuf::from)
+//!     .collect();
+//! assert_eq!(
+//!     render_tree(&entries, None, &TreeOptions::default()),
+//!     "\
+//! .
+//! ├─ README.md
+//! └─ src
+//!    ├─ lib.rs
+//!    └─ main.rs
+//! "
+//! );
+//! ```
+//!
+//! [`PathTree`] is the arena underneath, for callers that attach their own
+//! data to each node and format the lines themselves.
+
+use crate::order;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Index of a node in a [`PathTree`].
+pub type NodeId = usize;
+
+/// The empty path every relative path starts from.
+pub const ROOT: NodeId = 0;
+
+/// The characters lines are drawn with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TreeStyle {
+    /// `├─ `, `└─ `, and `│  `.
+    #[default]
+    Unicode,
+    /// `|-- `, `` `-- ``, and `|   `, for terminals and fonts without
+    /// box-drawing characters.
+    Ascii,
+}
+
+impl TreeStyle {
+    fn connector(self, is_last: bool) -> &'static str {
+        match (self, is_last) {
+            (Self::Unicode, false) => "├─ ",
+            (Self::Unicode, true) => "└─ ",
+            (Self::Ascii, false) => "|-- ",
+            (Self::Ascii, true) => "`-- ",
+        }
+    }
+
+    fn indent(self, is_last: bool) -> &'static str {
+        match (self, is_last) {
+            (Self::Unicode, false) => "│  ",
+            (Self::Unicode, true) => "   ",
+            (Self::Ascii, false) => "|   ",
+            (Self::Ascii, true) => "    ",
+        }
+    }
+}
+
+/// How a tree is laid out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeOptions {
+    pub style: TreeStyle,
+    /// List the directories of each level before its files.
+    pub dirs_first: bool,
+    /// Levels drawn below the root; `Some(1)` shows only its children.
+    pub max_depth: Option<usize>,
+}
+
+/// Draws `entries`, paths relative to one directory, as a tree.
+///
+/// `root` names the directory the tree is drawn from. When it is a
+/// directory among the entries, only what lies below it is drawn;
+/// otherwise it is just the label of the first line, which defaults to
+/// `.`. The ancestors of every entry are drawn as directories, and no
+/// filesystem access happens.
+///
+/// ```
+/// use copytree::tree::{render_tree, TreeOptions, TreeStyle};
+/// use std::path::{Path, PathBuf};
+///
+/// let entries: Vec<PathBuf> = ["Cargo.toml", "src/main.rs", "src/cli/args.rs"]
+///     .iter()
+///     .map(PathBuf::from)
+///     .collect();
+/// let options = TreeOptions {
+///     style: TreeStyle::Ascii,
+///     dirs_first: true,
+///     max_depth: Some(2),
+/// };
+/// assert_eq!(
+///     render_tree(&entries, Some(Path::new("src")), &options),
+///     "\
+/// src
+/// |-- cli
+/// |   `-- args.rs
+/// `-- main.rs
+/// "
+/// );
+/// ```
+pub fn render_tree(entries: &[PathBuf], root: Option<&Path>, options: &TreeOptions) -> String {
+    let mut tree: PathTree = PathTree::default();
+    for entry in entries {
+        tree.insert(entry);
+    }
+    let start = root
+        .and_then(|root| tree.find(root))
+        .filter(|&id| tree.is_dir(id))
+        .unwrap_or(ROOT);
+    let mut out = match root {
+        Some(root) => root.to_string_lossy().into_owned(),
+        None => ".".to_string(),
+    };
+    out.push('\n');
+    for row in tree.rows(start, options, |_| false) {
+        out.push_str(&row.prefix);
+        out.push_str(&tree.display_name(row.id));
+        out.push('\n');
+    }
+    out
+}
+
+struct Node<T> {
+    /// The component this node adds to its parent's path.
+    name: OsString,
+    parent: NodeId,
+    /// Children by name; empty for files.
+    children: HashMap<OsString, NodeId>,
+    data: T,
+}
+
+/// Relative paths as a tree of interned components, stored in one arena so
+/// that hundreds of thousands of paths cost one node each rather than a
+/// full path per ancestor. Each node carries a `T` of the caller's.
+pub struct PathTree<T = ()> {
+    nodes: Vec<Node<T>>,
+}
+
+impl<T: Default> Default for PathTree<T> {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                name: OsString::new(),
+                parent: ROOT,
+                children: HashMap::new(),
+                data: T::default(),
+            }],
+        }
+    }
+}
+
+/// One line of a drawn tree, below its first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    /// Indentation and connector, e.g. `│  └─ `.
+    pub prefix: String,
+    pub id: NodeId,
+    /// Whether this is the extra last line of directory `id`, asked for
+    /// by the `trailer` of [`PathTree::rows`], rather than `id` itself.
+    pub trailer: bool,
+}
+
+/// Pending work while laying out rows, so deep trees do not recurse.
+struct Step {
+    id: NodeId,
+    prefix: Rc<str>,
+    depth: usize,
+    is_last: bool,
+    trailer: bool,
+}
+
+impl<T: Default> PathTree<T> {
+    /// Adds `path` and its ancestors and returns the node of `path`.
+    pub fn insert(&mut self, path: &Path) -> NodeId {
+        let mut id = ROOT;
+        for component in path.components() {
+            let name = component.as_os_str();
+            id = match self.nodes[id].children.get(name) {
+                Some(&child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node {
+                        name: name.to_os_string(),
+                        parent: id,
+                        children: HashMap::new(),
+                        data: T::default(),
+                    });
+                    self.nodes[id].children.insert(name.to_os_string(), child);
+                    child
+                }
+            };
+        }
+        id
+    }
+}
+
+impl<T> PathTree<T> {
+    /// The node of `path`, if it was inserted.
+    pub fn find(&self, path: &Path) -> Option<NodeId> {
+        let mut id = ROOT;
+        for component in path.components() {
+            id = *self.nodes[id].children.get(component.as_os_str())?;
+        }
+        Some(id)
+    }
+
+    /// The root and then each node on the way to `path`, as far as the
+    /// tree has them.
+    pub fn lineage<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = NodeId> + 'a {
+        let mut components = path.components();
+        std::iter::successors(Some(ROOT), move |&id| {
+            let name = components.next()?;
+            self.nodes[id].children.get(name.as_os_str()).copied()
+        })
+    }
+
+    /// Whether nothing but the root is in the tree.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    pub fn get(&self, id: NodeId) -> &T {
+        &self.nodes[id].data
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> &mut T {
+        &mut self.nodes[id].data
+    }
+
+    /// Whether `id` has children; a path is only known to be a directory
+    /// by what was inserted below it.
+    pub fn is_dir(&self, id: NodeId) -> bool {
+        !self.nodes[id].children.is_empty()
+    }
+
+    /// The component `id` adds to its parent's path.
+    pub fn name(&self, id: NodeId) -> &OsStr {
+        &self.nodes[id].name
+    }
+
+    /// The full relative path of `id`.
+    pub fn path(&self, id: NodeId) -> PathBuf {
+        let mut names: Vec<&OsStr> = Vec::new();
+        let mut cursor = id;
+        while cursor != ROOT {
+            names.push(&self.nodes[cursor].name);
+            cursor = self.nodes[cursor].parent;
+        }
+        names.into_iter().rev().collect()
+    }
+
+    /// The name `id` is drawn with: its file name, or the whole path for
+    /// components without one such as `..`.
+    pub fn display_name(&self, id: NodeId) -> String {
+        Path::new(&self.nodes[id].name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| self.path(id).to_string_lossy().into_owned())
+    }
+
+    /// The lines of the tree below `root`, in the order they are drawn.
+    /// Children are listed in path order, and a directory for which
+    /// `trailer` holds gets one more line after them.
+    pub fn rows<F>(&self, root: NodeId, options: &TreeOptions, trailer: F) -> Vec<Row>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let mut rows = Vec::new();
+        let mut stack = Vec::new();
+        self.push_children(root, Rc::from(""), 1, options, &trailer, &mut stack);
+        while let Some(step) = stack.pop() {
+            let connector = options.style.connector(step.is_last);
+            rows.push(Row {
+                prefix: format!("{}{}", step.prefix, connector),
+                id: step.id,
+                trailer: step.trailer,
+            });
+            if !step.trailer && self.is_dir(step.id) {
+                let next = format!("{}{}", step.prefix, options.style.indent(step.is_last));
+                let depth = step.depth + 1;
+                self.push_children(
+                    step.id,
+                    Rc::from(next),
+                    depth,
+                    options,
+                    &trailer,
+                    &mut stack,
+                );
+            }
+        }
+        rows
+    }
+
+    /// Pushes the children of `id`, at `depth` below the root, in reverse
+    /// so they pop in order with the trailer after them.
+    fn push_children<F>(
+        &self,
+        id: NodeId,
+        prefix: Rc<str>,
+        depth: usize,
+        options: &TreeOptions,
+        trailer: &F,
+        stack: &mut Vec<Step>,
+    ) where
+        F: Fn(&T) -> bool,
+    {
+        if options.max_depth.is_some_and(|max| depth > max) {
+            return;
+        }
+        let node = &self.nodes[id];
+        let mut children: Vec<NodeId> = node.children.values().copied().collect();
+        children.sort_unstable_by(|&a, &b| {
+            let dirs_first = if options.dirs_first {
+                self.is_dir(b).cmp(&self.is_dir(a))
+            } else {
+                std::cmp::Ordering::Equal
+            };
+            dirs_first.then_with(|| order::names(&self.nodes[a].name, &self.nodes[b].name))
+        });
+        let has_trailer = trailer(&node.data);
+        if has_trailer {
+            stack.push(Step {
+                id,
+                prefix: Rc::clone(&prefix),
+                depth,
+                is_last: true,
+                trailer: true,
+            });
+        }
+        for (index, &child) in children.iter().enumerate().rev() {
+            stack.push(Step {
+                id: child,
+                prefix: Rc::clone(&prefix),
+                depth,
+                is_last: index + 1 == children.len() && !has_trailer,
+                trailer: false,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn paths(list: &[&str]) -> Vec<PathBuf> {
+        list.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn ancestors_become_directories_and_depth_cuts_levels() {
+        let entries = paths(&["b.rs", "a/x/y.rs", "a/z.rs", "../up.rs"]);
+        assert_eq!(
+            render_tree(&entries, None, &TreeOptions::default()),
+            "\
+.
+├─ ..
+│  └─ up.rs
+├─ a
+│  ├─ x
+│  │  └─ y.rs
+│  └─ z.rs
+└─ b.rs
+"
+        );
+        let options = TreeOptions {
+            dirs_first: true,
+            max_depth: Some(1),
+            ..TreeOptions::default()
+        };
+        assert_eq!(
+            render_tree(&entries, Some(Path::new("repo")), &options),
+            "\
+repo
+├─ ..
+├─ a
+└─ b.rs
+"
+        );
+    }
+
+    #[test]
+    fn trailers_follow_the_children_of_their_directory() {
+        let mut tree: PathTree<bool> = PathTree::default();
+        tree.insert(Path::new("src/a.rs"));
+        let src = tree.find(Path::new("src")).expect("inserted");
+        *tree.get_mut(src) = true;
+        let rows = tree.rows(ROOT, &TreeOptions::default(), |&more| more);
+        let drawn: Vec<(&str, bool)> = rows
+            .iter()
+            .map(|row| (row.prefix.as_str(), row.trailer))
+            .collect();
+        assert_eq!(drawn, [("└─ ", false), ("   ├─ ", false), ("   └─ ", true)]);
+        assert_eq!(rows[2].id, src);
+    }
+
+    #[test]
+    fn lays_out_a_hundred_thousand_entries_quickly() {
+        let mut tree: PathTree = PathTree::default();
+        let started = Instant::now();
+        for dir in 0..100 {
+            for sub in 0..10 {
+                for file in 0..100 {
+                    tree.insert(Path::new(&format!("d{dir}/s{sub}/f{file}.rs")));
+                }
+            }
+        }
+        let rows = tree.rows(ROOT, &TreeOptions::default(), |_| false);
+        // One row per file and per directory.
+        assert_eq!(rows.len(), 100_000 + 1_000 + 100);
+        let elapsed = started.elapsed();
+        assert!(elapsed < Duration::from_secs(20), "took {elapsed:?}");
+    }
+
+    #[test]
+    fn deep_trees_do_not_recurse() {
+        let deep: PathBuf = (0..2_000).map(|level| format!("d{level}")).collect();
+        let mut tree: PathTree = PathTree::default();
+        tree.insert(&deep.join("leaf.rs"));
+        let rows = tree.rows(ROOT, &TreeOptions::default(), |_| false);
+        assert_eq!(rows.len(), 2_001);
+        assert_eq!(tree.display_name(rows[2_000].id), "leaf.rs");
+    }
+}
@@ -1,18 +1,319 @@
-use anyhow::Result;
+use crate::interrupt::Interrupt;
+use crate::patterns::Patterns;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use copytree::order;
+use globset::{Glob, GlobBuilder};
 use ignore::{DirEntry, WalkBuilder};
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
 
-pub fn walk_paths(paths: &[String], no_gitignore: bool) -> Result<Vec<DirEntry>> {
-    let mut entries = Vec::new();
+const GLOB_METACHARACTERS: [char; 4] = ['*', '?', '[', '{'];
+
+/// How much of a `.git` directory `--include-git-dir` walks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitDir {
+    /// Everything but `objects/`, which holds the large, binary history.
+    Metadata,
+    /// Everything, including `objects/`.
+    Full,
+}
+
+/// Which ignore files the walk honors, whether it skips hidden entries, and
+/// how far it follows symlinks to directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IgnoreRules {
+    /// `.gitignore` files; `false` also turns off the other git sources,
+    /// including the global excludes file.
+    pub gitignore: bool,
+    /// The repository's `.git/info/exclude`.
+    pub git_exclude: bool,
+    /// Ignore files in the directories above each root. This is also how
+    /// the repository, and so `info/exclude`, is found for a root below it.
+    pub parents: bool,
+    /// Apply git's ignore files only inside a git repository.
+    pub require_git: bool,
+    /// `.ignore` files.
+    pub dot_ignore: bool,
+    /// Skip hidden files and directories. A `.git` directory below the
+    /// root is skipped either way unless `git_dir` is set.
+    pub skip_hidden: bool,
+    /// Walk `.git` directories too, and how much of them.
+    pub git_dir: Option<GitDir>,
+    /// How many symlinked directories one path may pass through; 0 keeps
+    /// the walk out of them.
+    pub max_symlink_depth: usize,
+}
+
+impl Default for IgnoreRules {
+    fn default() -> Self {
+        Self {
+            gitignore: true,
+            git_exclude: true,
+            parents: true,
+            require_git: false,
+            dot_ignore: true,
+            skip_hidden: true,
+            git_dir: None,
+            max_symlink_depth: 0,
+        }
+    }
+}
+
+impl IgnoreRules {
+    /// Honors no ignore files and skips nothing, like `--no-ignore`.
+    pub const NONE: Self = Self {
+        gitignore: false,
+        git_exclude: false,
+        parents: false,
+        require_git: false,
+        dot_ignore: false,
+        skip_hidden: false,
+        git_dir: None,
+        max_symlink_depth: 0,
+    };
+
+    fn configure(self, walk_builder: &mut WalkBuilder, prune: Option<&Patterns>) {
+        walk_builder
+            .git_ignore(self.gitignore)
+            .git_global(self.gitignore)
+            .git_exclude(self.gitignore && self.git_exclude)
+            .ignore(self.dot_ignore)
+            .parents(self.parents)
+            .require_git(self.require_git);
+        // Hidden entries are then skipped by `keeps` so `.git` can be let in.
+        walk_builder.hidden(self.skip_hidden && self.git_dir.is_none());
+        let prune = prune.cloned();
+        if !self.skip_hidden || self.git_dir.is_some() || prune.is_some() {
+            walk_builder
+                .filter_entry(move |entry| self.keeps(entry) && !is_pruned(entry, prune.as_ref()));
+        }
+    }
 
+    /// Whether the walk enters `entry`, as far as hidden entries and `.git`
+    /// directories go.
+    fn keeps(self, entry: &DirEntry) -> bool {
+        // Only the components below the root count, so `copytree .git` works.
+        let mut below_root = entry.path().components().rev().take(entry.depth());
+        let Some(git) = below_root.position(|component| component.as_os_str() == ".git") else {
+            return !self.skip_hidden
+                || entry.depth() == 0
+                || !entry.file_name().to_string_lossy().starts_with('.');
+        };
+        match self.git_dir {
+            None => false,
+            Some(GitDir::Full) => true,
+            // `git` counts from the end: 1 means the entry sits right in `.git`.
+            Some(GitDir::Metadata) => !(git == 1 && entry.file_name() == "objects"),
+        }
+    }
+}
+
+/// Whether `entry` is a directory below the root matching a `--prune`
+/// pattern, tried against its name and its path.
+fn is_pruned(entry: &DirEntry, prune: Option<&Patterns>) -> bool {
+    prune.is_some_and(|prune| {
+        entry.depth() > 0
+            && (entry.file_type().is_some_and(|ft| ft.is_dir()) || is_dir_link(entry))
+            && (prune.matches_name(entry.file_name()) || prune.is_match(entry.path()))
+    })
+}
+
+/// The first directory that `pattern` can only match inside of and that
+/// `prune` keeps the walk out of, i.e. why an `--always-include` pattern
+/// would never match.
+pub fn pruned_ancestor(pattern: &str, prune: &Patterns) -> Option<PathBuf> {
+    let base = glob_base(pattern);
+    let dir = if pattern.contains(GLOB_METACHARACTERS) {
+        base.as_path()
+    } else {
+        base.parent()?
+    };
+    let mut ancestors: Vec<&Path> = dir
+        .ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty() && *ancestor != Path::new("."))
+        .collect();
+    ancestors.reverse();
+    ancestors
+        .into_iter()
+        .find(|ancestor| {
+            ancestor
+                .file_name()
+                .is_some_and(|name| prune.matches_name(name))
+                || prune.is_match(ancestor)
+        })
+        .map(Path::to_path_buf)
+}
+
+/// Collects the files under `paths`, symlinks to files included, calling
+/// `on_file` with the running count after each one is found. Directories
+/// matching `prune` are not entered. Once `interrupt` trips, the walk stops
+/// and returns the files found so far. A path given explicitly is never
+/// dropped by ignore rules: a file is taken as is, and a directory is
+/// walked even when a parent's rules ignore it, though the rules still
+/// filter its children.
+///
+/// Symlinks to directories are walked in turn while a path has passed
+/// through fewer than `rules.max_symlink_depth` of them; a link met at the
+/// limit is returned like a file, to be reported as not entered.
+pub fn walk_paths<F>(
+    paths: &[String],
+    rules: IgnoreRules,
+    prune: Option<&Patterns>,
+    interrupt: &Interrupt,
+    mut on_file: F,
+) -> Result<Vec<DirEntry>>
+where
+    F: FnMut(usize),
+{
+    let mut entries = Vec::new();
     for path in paths {
-        let root = Path::new(path);
-        let mut walk_builder = WalkBuilder::new(root);
-        walk_builder.git_ignore(!no_gitignore);
+        walk_root(
+            Path::new(path),
+            0,
+            rules,
+            prune,
+            interrupt,
+            &mut entries,
+            &mut on_file,
+        )?;
+    }
+    Ok(entries)
+}
 
+/// Walks one root reached through `links` symlinked directories.
+fn walk_root<F>(
+    root: &Path,
+    links: usize,
+    rules: IgnoreRules,
+    prune: Option<&Patterns>,
+    interrupt: &Interrupt,
+    entries: &mut Vec<DirEntry>,
+    on_file: &mut F,
+) -> Result<()>
+where
+    F: FnMut(usize),
+{
+    let mut walk_builder = WalkBuilder::new(root);
+    if root.is_file() {
+        walk_builder.standard_filters(false);
+    } else {
+        rules.configure(&mut walk_builder, prune);
+    }
+
+    for result in walk_builder.build() {
+        if interrupt.is_tripped() {
+            break;
+        }
+        let entry = result?;
+        // The count is per path: a link's siblings start again from `links`.
+        if entry.depth() > 0 && is_dir_link(&entry) && rules.max_symlink_depth > 0 {
+            if is_pruned(&entry, prune) {
+                continue;
+            }
+            let step = link_step(links, rules.max_symlink_depth, || loops_back(entry.path()));
+            if step == LinkStep::Enter {
+                walk_root(
+                    entry.path(),
+                    links + 1,
+                    rules,
+                    prune,
+                    interrupt,
+                    entries,
+                    on_file,
+                )?;
+                continue;
+            }
+        } else if !is_file_entry(&entry) {
+            continue;
+        }
+        entries.push(entry);
+        on_file(entries.len());
+    }
+    Ok(())
+}
+
+/// Whether `entry` is a symlink that leads to a directory. On Windows this
+/// includes junctions, which `std` reports as symlinks like every
+/// name-surrogate reparse point.
+fn is_dir_link(entry: &DirEntry) -> bool {
+    entry.path_is_symlink() && entry.path().is_dir()
+}
+
+/// What the walk does with a symlinked directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkStep {
+    Enter,
+    /// Left out: the path already passed through `max_symlink_depth` links.
+    AtLimit,
+    /// Left out: the link leads back to a directory enclosing it.
+    Loop,
+}
+
+/// Decides on a symlinked directory met after `links` others on its path;
+/// `loops` tells whether it leads back to an enclosing directory.
+fn link_step<F>(links: usize, max_symlink_depth: usize, loops: F) -> LinkStep
+where
+    F: FnOnce() -> bool,
+{
+    if loops() {
+        LinkStep::Loop
+    } else if links < max_symlink_depth {
+        LinkStep::Enter
+    } else {
+        LinkStep::AtLimit
+    }
+}
+
+/// Whether the directory symlink (or junction) at `link` resolves to the
+/// directory holding it or one above, so entering it would walk the same
+/// files again.
+pub fn loops_back(link: &Path) -> bool {
+    let parent = link.parent().map(std::fs::canonicalize);
+    match (std::fs::canonicalize(link), parent) {
+        (Ok(target), Some(Ok(parent))) => parent.starts_with(target),
+        _ => false,
+    }
+}
+
+/// Whether `entry` belongs in the output as a file: a regular file, or a
+/// symlink that does not lead to a directory. Broken links count, so the
+/// output can say they are broken.
+fn is_file_entry(entry: &DirEntry) -> bool {
+    entry.file_type().is_some_and(|file_type| {
+        file_type.is_file() || (file_type.is_symlink() && !is_dir_link(entry))
+    })
+}
+
+/// Collects the files under `paths` that the walk leaves out (ignored or
+/// hidden) but `is_wanted` accepts, skipping those in `walked`. This is the
+/// second pass behind `--always-include`. `prune` still applies, and the
+/// walk stops like [`walk_paths`] once `interrupt` trips.
+pub fn walk_unfiltered<F>(
+    paths: &[String],
+    prune: Option<&Patterns>,
+    walked: &HashSet<PathBuf>,
+    interrupt: &Interrupt,
+    is_wanted: F,
+) -> Result<Vec<DirEntry>>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut entries = Vec::new();
+    for path in paths {
+        let mut walk_builder = WalkBuilder::new(Path::new(path));
+        walk_builder.standard_filters(false);
+        if let Some(prune) = prune.cloned() {
+            walk_builder.filter_entry(move |entry| !is_pruned(entry, Some(&prune)));
+        }
         for result in walk_builder.build() {
+            if interrupt.is_tripped() {
+                break;
+            }
             let entry = result?;
-            if entry.file_type().map_or(false, |ft| ft.is_file()) {
+            if is_file_entry(&entry) && !walked.contains(entry.path()) && is_wanted(entry.path()) {
                 entries.push(entry);
             }
         }
@@ -20,6 +321,196 @@ pub fn walk_paths(paths: &[String], no_gitignore: bool) -> Result<Vec<DirEntry>>
     Ok(entries)
 }
 
+/// Keeps at most `limit` files from each directory, the first ones by name,
+/// and returns how many were dropped per directory. Subdirectories count
+/// separately, and files for which `exempt` holds are kept without counting
+/// toward the limit. The surviving entries keep their order.
+pub fn limit_per_dir<F>(
+    entries: &mut Vec<DirEntry>,
+    limit: usize,
+    exempt: F,
+) -> BTreeMap<PathBuf, usize>
+where
+    F: Fn(&DirEntry) -> bool,
+{
+    let mut by_dir: HashMap<&Path, Vec<usize>> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if !exempt(entry) {
+            let dir = entry.path().parent().unwrap_or(Path::new(""));
+            by_dir.entry(dir).or_default().push(index);
+        }
+    }
+    let mut dropped_indices = HashSet::new();
+    let mut dropped = BTreeMap::new();
+    for (dir, mut indices) in by_dir {
+        if indices.len() <= limit {
+            continue;
+        }
+        indices.sort_by(|&a, &b| order::names(entries[a].file_name(), entries[b].file_name()));
+        dropped.insert(dir.to_path_buf(), indices.len() - limit);
+        dropped_indices.extend(indices.split_off(limit));
+    }
+    let mut index = 0;
+    entries.retain(|_| {
+        index += 1;
+        !dropped_indices.contains(&(index - 1))
+    });
+    dropped
+}
+
+/// Moves each README file ahead of the other files in its directory and its
+/// subdirectories, where the first of them stood; everything else keeps its
+/// order. Files named as paths (depth 0) stay where they are.
+pub fn hoist_readmes(entries: &mut [DirEntry]) {
+    let is_readme = |entry: &DirEntry| {
+        entry.depth() > 0
+            && entry
+                .file_name()
+                .to_string_lossy()
+                .to_ascii_lowercase()
+                .starts_with("readme")
+    };
+    for index in 0..entries.len() {
+        if !is_readme(&entries[index]) {
+            continue;
+        }
+        let dir = entries[index].path().parent().unwrap_or(Path::new(""));
+        // READMEs already hoisted in the same directory keep their lead.
+        let first = entries[..index].iter().position(|entry| {
+            entry.path().starts_with(dir)
+                && !(is_readme(entry) && entry.path().parent() == Some(dir))
+        });
+        if let Some(first) = first {
+            entries[first..=index].rotate_right(1);
+        }
+    }
+}
+
+/// Parses a user's glob. Separators in patterns are always `/`, and a
+/// backslash escapes the character after it on every platform, so
+/// `what\?.md` and `\[draft\] notes.txt` name files literally.
+pub fn glob(pattern: &str) -> std::result::Result<Glob, globset::Error> {
+    glob_builder(pattern).build()
+}
+
+fn glob_builder(pattern: &str) -> GlobBuilder<'_> {
+    let mut builder = GlobBuilder::new(pattern);
+    builder.backslash_escape(true);
+    builder
+}
+
+/// Whether `path` is meant as a glob pattern: it contains glob
+/// metacharacters and does not exist as a literal path.
+pub fn is_glob_path(path: &str) -> bool {
+    path.contains(GLOB_METACHARACTERS) && !Path::new(path).exists()
+}
+
+/// The leading components of `pattern` before the first one containing a
+/// glob metacharacter, i.e. the directory its matches live under.
+pub fn glob_base(pattern: &str) -> PathBuf {
+    let mut base = PathBuf::new();
+    for component in Path::new(pattern).components() {
+        if component
+            .as_os_str()
+            .to_string_lossy()
+            .contains(GLOB_METACHARACTERS)
+        {
+            break;
+        }
+        base.push(component.as_os_str());
+    }
+    if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    }
+}
+
+/// Replaces every glob pattern among `paths` (see [`is_glob_path`]) with the
+/// files and directories it matches, in sorted order; other paths are kept
+/// as given. `*` and `?` do not cross `/`, as in the shell. The search skips
+/// what the walk itself would skip (hidden entries and those `rules`
+/// ignore), does not descend into directories `prune` matches or whose
+/// every file `exclude` would match. A pattern matching nothing is an error.
+pub fn expand_globs(
+    paths: &[String],
+    rules: IgnoreRules,
+    prune: Option<&Patterns>,
+    exclude: Option<&Patterns>,
+) -> Result<Vec<String>> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        if !is_glob_path(path) {
+            expanded.push(path.clone());
+            continue;
+        }
+        let matches = glob_matches(path, rules, prune, exclude)?;
+        if matches.is_empty() {
+            bail!("Pattern {} did not match any files", path);
+        }
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+fn glob_matches(
+    pattern: &str,
+    rules: IgnoreRules,
+    prune: Option<&Patterns>,
+    exclude: Option<&Patterns>,
+) -> Result<Vec<String>> {
+    let trimmed = pattern.strip_prefix("./").unwrap_or(pattern);
+    let matcher = glob_builder(trimmed)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("Invalid path pattern: {}", pattern))?
+        .compile_matcher();
+    let base = glob_base(trimmed);
+    if !base.exists() {
+        return Ok(Vec::new());
+    }
+    let mut walk_builder = WalkBuilder::new(&base);
+    rules.configure(&mut walk_builder, prune);
+    if let Some(exclude) = exclude.cloned() {
+        let prune = prune.cloned();
+        // A name no real file has: if it is excluded, so is everything here.
+        walk_builder.filter_entry(move |entry| {
+            rules.keeps(entry)
+                && !is_pruned(entry, prune.as_ref())
+                && (entry.depth() == 0
+                    || !entry.file_type().is_some_and(|ft| ft.is_dir())
+                    || !exclude.is_match(&entry.path().join("\0copytree\0")))
+        });
+    }
+
+    let mut matches: Vec<PathBuf> = Vec::new();
+    for result in walk_builder.build() {
+        let entry = result?;
+        let path = without_cur_dir(entry.path());
+        if entry.depth() > 0 && matcher.is_match(&path) {
+            matches.push(path);
+        }
+    }
+    matches.sort_by(|a, b| order::paths(a, b));
+    // Walking a matched directory already covers whatever matched inside it.
+    let mut kept: Vec<PathBuf> = Vec::with_capacity(matches.len());
+    for path in matches {
+        if !kept.iter().any(|dir| path.starts_with(dir)) {
+            kept.push(path);
+        }
+    }
+    Ok(kept
+        .into_iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,7 +545,14 @@ mod tests {
 
         let paths = vec![project_root.to_string_lossy().into_owned()];
 
-        let entries = walk_paths(&paths, false).expect("walk failed");
+        let entries = walk_paths(
+            &paths,
+            IgnoreRules::default(),
+            None,
+            &Interrupt::default(),
+            |_| {},
+        )
+        .expect("walk failed");
         let mut collected: Vec<_> = entries
             .into_iter()
             .map(|entry| entry.path().to_path_buf())
@@ -66,4 +564,490 @@ mod tests {
 
         let _ = fs::remove_dir_all(&project_root);
     }
+
+    #[test]
+    fn an_interrupt_stops_the_walk_with_the_files_found_so_far() {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let project_root = temp.path();
+        fs::create_dir_all(project_root.join("src")).expect("failed to create src directory");
+        for name in ["a.rs", "b.rs", "c.rs"] {
+            fs::write(project_root.join("src").join(name), "").expect("failed to write file");
+        }
+        let paths = vec![project_root.to_string_lossy().into_owned()];
+
+        let interrupt = Interrupt::default();
+        let entries = walk_paths(&paths, IgnoreRules::default(), None, &interrupt, |found| {
+            if found == 1 {
+                interrupt.trip();
+            }
+        })
+        .expect("walk failed");
+        assert_eq!(entries.len(), 1);
+
+        let entries = walk_paths(&paths, IgnoreRules::default(), None, &interrupt, |_| {})
+            .expect("walk failed");
+        assert!(entries.is_empty());
+    }
+
+    fn ignore_fixture() -> tempfile::TempDir {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = temp.path();
+        for dir in [".git", "target/debug"] {
+            fs::create_dir_all(root.join(dir)).expect("create fixture dir");
+        }
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").expect("write .gitignore");
+        fs::write(root.join("target/.gitignore"), "*.tmp\n").expect("write .gitignore");
+        for file in [
+            "README.md",
+            "target/debug/build.log",
+            "target/debug/app.d",
+            "target/debug/cache.tmp",
+        ] {
+            fs::write(root.join(file), "x\n").expect("write fixture file");
+        }
+        temp
+    }
+
+    fn walked_names(root: &Path) -> Vec<String> {
+        let paths = vec![root.to_string_lossy().into_owned()];
+        let mut names: Vec<String> = walk_paths(
+            &paths,
+            IgnoreRules::default(),
+            None,
+            &Interrupt::default(),
+            |_| {},
+        )
+        .expect("walk failed")
+        .iter()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn ignored_file_given_as_a_path_is_walked() {
+        let fixture = ignore_fixture();
+        let root = fixture.path();
+        assert_eq!(
+            walked_names(&root.join("target/debug/build.log")),
+            ["build.log"]
+        );
+        assert_eq!(
+            walked_names(&root.join("target/debug/cache.tmp")),
+            ["cache.tmp"]
+        );
+    }
+
+    #[test]
+    fn ignored_directory_given_as_a_path_is_walked_with_its_rules() {
+        let fixture = ignore_fixture();
+        let root = fixture.path();
+        // `target/` in the parent does not hide the root, but `*.log` from
+        // the parent and `*.tmp` from inside it still filter the children.
+        assert_eq!(walked_names(&root.join("target")), ["app.d"]);
+        assert_eq!(walked_names(&root.join("target/debug")), ["app.d"]);
+        assert_eq!(walked_names(root), ["README.md"]);
+    }
+
+    fn repo_fixture() -> tempfile::TempDir {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = temp.path();
+        for dir in [".git/info", "src/generated"] {
+            fs::create_dir_all(root.join(dir)).expect("create fixture dir");
+        }
+        fs::write(root.join(".git/info/exclude"), "*.local\n").expect("write exclude");
+        fs::write(root.join(".gitignore"), "/src/generated/\n*.bak\n").expect("write .gitignore");
+        for file in [
+            "src/lib.rs",
+            "src/lib.rs.bak",
+            "src/notes.local",
+            "src/generated/out.rs",
+        ] {
+            fs::write(root.join(file), "x\n").expect("write fixture file");
+        }
+        temp
+    }
+
+    fn walked_under(root: &Path, relative: &str, rules: IgnoreRules) -> Vec<String> {
+        let paths = vec![root.join(relative).to_string_lossy().into_owned()];
+        let mut files: Vec<String> = walk_paths(&paths, rules, None, &Interrupt::default(), |_| {})
+            .expect("walk failed")
+            .iter()
+            .map(|entry| {
+                entry
+                    .path()
+                    .strip_prefix(root)
+                    .expect("entry under the fixture")
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn info_exclude_and_parent_gitignore_apply_below_the_repository_root() {
+        let fixture = repo_fixture();
+        let root = fixture.path();
+        assert_eq!(
+            walked_under(root, "src", IgnoreRules::default()),
+            ["src/lib.rs"]
+        );
+        let without_exclude = IgnoreRules {
+            git_exclude: false,
+            ..IgnoreRules::default()
+        };
+        assert_eq!(
+            walked_under(root, "src", without_exclude),
+            ["src/lib.rs", "src/notes.local"]
+        );
+        let without_parents = IgnoreRules {
+            parents: false,
+            ..IgnoreRules::default()
+        };
+        assert_eq!(
+            walked_under(root, "src", without_parents),
+            // The repository, and with it `info/exclude`, is found through
+            // the parents too.
+            [
+                "src/generated/out.rs",
+                "src/lib.rs",
+                "src/lib.rs.bak",
+                "src/notes.local"
+            ]
+        );
+    }
+
+    #[test]
+    fn no_gitignore_includes_everything() {
+        let fixture = repo_fixture();
+        let root = fixture.path();
+        let rules = IgnoreRules {
+            gitignore: false,
+            ..IgnoreRules::default()
+        };
+        assert_eq!(
+            walked_under(root, "src", rules),
+            [
+                "src/generated/out.rs",
+                "src/lib.rs",
+                "src/lib.rs.bak",
+                "src/notes.local"
+            ]
+        );
+    }
+
+    #[test]
+    fn gitignore_applies_outside_a_repository_unless_git_is_required() {
+        let fixture = repo_fixture();
+        let root = fixture.path();
+        fs::remove_dir_all(root.join(".git")).expect("remove .git");
+        assert_eq!(
+            walked_under(root, "src", IgnoreRules::default()),
+            ["src/lib.rs", "src/notes.local"]
+        );
+        let rules = IgnoreRules {
+            require_git: true,
+            ..IgnoreRules::default()
+        };
+        assert_eq!(walked_under(root, "src", rules).len(), 4);
+    }
+
+    fn glob_set(patterns: &[&str]) -> globset::GlobSet {
+        let mut builder = globset::GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(globset::Glob::new(pattern).expect("valid glob"));
+        }
+        builder.build().expect("glob set")
+    }
+
+    fn patterns(patterns: &[&str]) -> Patterns {
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        Patterns::new(&patterns, "--prune", &[], Path::new("/nowhere"))
+            .expect("valid patterns")
+            .expect("a pattern")
+    }
+
+    #[test]
+    fn pruned_directories_are_not_entered() {
+        let fixture = repo_fixture();
+        let root = fixture.path();
+        let prune = patterns(&["generated"]);
+        let paths = vec![root.join("src").to_string_lossy().into_owned()];
+        let rules = IgnoreRules {
+            gitignore: false,
+            ..IgnoreRules::default()
+        };
+        let names: Vec<String> =
+            walk_paths(&paths, rules, Some(&prune), &Interrupt::default(), |_| {})
+                .expect("walk failed")
+                .iter()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+        assert!(!names.contains(&"out.rs".to_string()), "{names:?}");
+        assert_eq!(names.len(), 3);
+
+        let rescued = walk_unfiltered(
+            &paths,
+            Some(&prune),
+            &HashSet::new(),
+            &Interrupt::default(),
+            |_| true,
+        )
+        .expect("walk failed");
+        assert!(rescued.iter().all(|entry| entry.file_name() != "out.rs"));
+    }
+
+    #[test]
+    fn pruned_ancestor_names_the_directory_a_pattern_needs() {
+        let prune = patterns(&["node_modules", "vendor/cache"]);
+        assert_eq!(
+            pruned_ancestor("web/node_modules/pkg/*.json", &prune),
+            Some(PathBuf::from("web/node_modules"))
+        );
+        assert_eq!(
+            pruned_ancestor("./vendor/cache/a.txt", &prune),
+            Some(PathBuf::from("./vendor/cache"))
+        );
+        assert_eq!(pruned_ancestor("node_modules", &prune), None);
+        assert_eq!(pruned_ancestor("**/package.json", &prune), None);
+        assert_eq!(pruned_ancestor("src/*.rs", &prune), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_symlinks_are_walked_but_directory_symlinks_are_not_entered() {
+        use std::os::unix::fs::symlink;
+
+        let fixture = glob_fixture();
+        let root = fixture.path();
+        symlink("lib.rs", root.join("src/alias.rs")).expect("link file");
+        symlink("missing.rs", root.join("src/broken.rs")).expect("link nothing");
+        symlink("nested", root.join("src/linked")).expect("link directory");
+        let paths = vec![root.join("src").to_string_lossy().into_owned()];
+        let mut walked: Vec<String> = walk_paths(
+            &paths,
+            IgnoreRules::default(),
+            None,
+            &Interrupt::default(),
+            |_| {},
+        )
+        .expect("walk")
+        .iter()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+        walked.sort();
+
+        assert_eq!(
+            walked,
+            [
+                "[id].rs",
+                "alias.rs",
+                "broken.rs",
+                "lib.rs",
+                "mod.rs",
+                "notes.txt",
+                "out.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn links_back_to_an_enclosing_directory_are_never_entered() {
+        assert_eq!(link_step(0, 8, || true), LinkStep::Loop);
+        assert_eq!(link_step(8, 8, || true), LinkStep::Loop);
+        assert_eq!(link_step(7, 8, || false), LinkStep::Enter);
+        assert_eq!(link_step(8, 8, || false), LinkStep::AtLimit);
+    }
+
+    #[test]
+    fn limit_per_dir_keeps_the_first_files_by_name_in_each_directory() {
+        let fixture = glob_fixture();
+        let root = fixture.path();
+        for name in ["d.rs", "b.rs", "c.rs"] {
+            fs::write(root.join("src").join(name), "x\n").expect("write fixture file");
+        }
+        let paths = vec![root.to_string_lossy().into_owned()];
+        let mut entries = walk_paths(
+            &paths,
+            IgnoreRules::default(),
+            None,
+            &Interrupt::default(),
+            |_| {},
+        )
+        .expect("walk");
+        let dropped = limit_per_dir(&mut entries, 2, |entry| entry.file_name() == "lib.rs");
+
+        let mut kept: Vec<String> = entries
+            .iter()
+            .map(|entry| {
+                entry
+                    .path()
+                    .strip_prefix(root)
+                    .expect("entry under the fixture")
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        kept.sort();
+        // `src` holds [id].rs, b.rs, c.rs, d.rs, and the exempt lib.rs.
+        assert_eq!(
+            kept,
+            [
+                "docs.rs/index.md",
+                "src/[id].rs",
+                "src/b.rs",
+                "src/generated/out.rs",
+                "src/lib.rs",
+                "src/nested/mod.rs",
+                "src/nested/notes.txt"
+            ]
+        );
+        assert_eq!(dropped, BTreeMap::from([(root.join("src"), 2)]));
+    }
+
+    fn glob_fixture() -> tempfile::TempDir {
+        let temp = tempfile::tempdir().expect("create temp dir");
+        let root = temp.path();
+        for dir in ["src/nested", "src/generated", "docs.rs"] {
+            fs::create_dir_all(root.join(dir)).expect("create fixture dir");
+        }
+        for file in [
+            "src/lib.rs",
+            "src/nested/mod.rs",
+            "src/nested/notes.txt",
+            "src/generated/out.rs",
+            "docs.rs/index.md",
+            "src/[id].rs",
+        ] {
+            fs::write(root.join(file), "x\n").expect("write fixture file");
+        }
+        temp
+    }
+
+    fn expand(root: &Path, patterns: &[&str], exclude: Option<&Patterns>) -> Result<Vec<String>> {
+        let paths: Vec<String> = patterns
+            .iter()
+            .map(|pattern| root.join(pattern).to_string_lossy().into_owned())
+            .collect();
+        let expanded = expand_globs(&paths, IgnoreRules::default(), None, exclude)?;
+        Ok(expanded
+            .iter()
+            .map(|path| {
+                Path::new(path)
+                    .strip_prefix(root)
+                    .expect("match under the fixture")
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect())
+    }
+
+    #[test]
+    fn star_stays_within_one_directory_and_double_star_recurses() {
+        let fixture = glob_fixture();
+        let root = fixture.path();
+        assert_eq!(
+            expand(root, &["src/*.rs"], None).expect("expand"),
+            ["src/[id].rs", "src/lib.rs"]
+        );
+        assert_eq!(
+            expand(root, &["src/**/*.rs"], None).expect("expand"),
+            [
+                "src/[id].rs",
+                "src/generated/out.rs",
+                "src/lib.rs",
+                "src/nested/mod.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_match_directories_as_well_as_files() {
+        let fixture = glob_fixture();
+        let root = fixture.path();
+        assert_eq!(expand(root, &["*.rs"], None).expect("expand"), ["docs.rs"]);
+        assert_eq!(
+            expand(root, &["src/n*"], None).expect("expand"),
+            ["src/nested"]
+        );
+    }
+
+    #[test]
+    fn existing_paths_with_metacharacters_are_taken_literally() {
+        let fixture = glob_fixture();
+        let root = fixture.path();
+        let literal = root.join("src/[id].rs").to_string_lossy().into_owned();
+        assert!(!is_glob_path(&literal));
+        assert_eq!(
+            expand(root, &["src/[id].rs"], None).expect("expand"),
+            ["src/[id].rs"]
+        );
+    }
+
+    #[test]
+    fn excluded_directories_are_not_searched() {
+        let fixture = glob_fixture();
+        let root = fixture.path();
+        let exclude = patterns(&["**/generated/**"]);
+        assert_eq!(
+            expand(root, &["src/**/*.rs"], Some(&exclude)).expect("expand"),
+            ["src/[id].rs", "src/lib.rs", "src/nested/mod.rs"]
+        );
+    }
+
+    #[test]
+    fn patterns_without_matches_are_errors() {
+        let fixture = glob_fixture();
+        let root = fixture.path();
+        let err = expand(root, &["src/*.py"], None).expect_err("nothing matches");
+        assert!(
+            err.to_string()
+                .ends_with("src/*.py did not match any files"),
+            "{err}"
+        );
+        assert_eq!(glob_base("src/**/*.rs"), Path::new("src"));
+        assert_eq!(glob_base("*.rs"), Path::new("."));
+    }
+
+    /// The walk is `ignore`'s iterative one, so a directory chain deeper
+    /// than `PATH_MAX` allows ends in an error for the too-long path rather
+    /// than a stack overflow.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn deep_directory_chains_do_not_overflow_the_stack() {
+        let fixture = repo_fixture();
+        let root = fixture.path();
+        let chain = vec!["d"; 5_000].join("/");
+        // `mkdir -p` creates the chain one directory at a time, which
+        // `create_dir_all` cannot past `PATH_MAX`.
+        let status = std::process::Command::new("mkdir")
+            .args(["-p", &chain])
+            .current_dir(root)
+            .status()
+            .expect("run mkdir");
+        assert!(status.success());
+        let paths = vec![root.to_string_lossy().into_owned()];
+        match walk_paths(
+            &paths,
+            IgnoreRules::default(),
+            None,
+            &Interrupt::default(),
+            |_| {},
+        ) {
+            Ok(entries) => assert!(!entries.is_empty()),
+            Err(err) => assert!(format!("{err:#}").contains("File name too long"), "{err:#}"),
+        }
+    }
+
+    #[test]
+    fn long_candidates_match_without_backtracking() {
+        let set = glob_set(&["**/*.rs", "*a*a*a*a*a*b"]);
+        let deep = format!("{}x.rs", "d/".repeat(4_998));
+        assert_eq!(deep.len(), 10_000);
+        assert_eq!(set.matches(&deep), [0]);
+        assert!(set.matches("a".repeat(10_000)).is_empty());
+    }
 }
@@ -0,0 +1,3457 @@
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+struct Fixture {
+    root: TempDir,
+}
+
+impl Fixture {
+    fn new(label: &str) -> Self {
+        let root = tempfile::Builder::new()
+            .prefix(&format!("copytree_cli_{label}_"))
+            .tempdir()
+            .expect("failed to create fixture");
+        fs::create_dir_all(root.path().join("src")).expect("failed to create fixture");
+        fs::write(root.path().join("src").join("main.rs"), "fn main() {}\n")
+            .expect("failed to write fixture file");
+        Self { root }
+    }
+
+    fn write(&self, relative: &str, contents: &[u8]) {
+        fs::write(self.root.path().join(relative), contents).expect("failed to write fixture file");
+    }
+
+    fn path(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// Home directory of the child process, so the user config under
+    /// `home/.config` belongs to the fixture.
+    fn home(&self) -> PathBuf {
+        self.root.path().join("home")
+    }
+
+    fn write_user_config(&self, contents: &str) {
+        let dir = self.home().join(".config").join("copytree");
+        fs::create_dir_all(&dir).expect("failed to create config dir");
+        fs::write(dir.join("config.toml"), contents).expect("failed to write config");
+    }
+
+    /// A copytree invocation inside the fixture, isolated from the caller's
+    /// environment defaults.
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_copytree"));
+        command
+            .args(args)
+            .current_dir(self.root.path())
+            .env("HOME", self.home())
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("MAX_FILE_BYTES")
+            .env_remove("NO_COLOR");
+        for name in [
+            "COPYTREE_EXCLUDE",
+            "COPYTREE_MAX_FILE_BYTES",
+            "COPYTREE_FORMAT",
+            "COPYTREE_NO_GITIGNORE",
+            "COPYTREE_OUT",
+        ] {
+            command.env_remove(name);
+        }
+        command
+    }
+
+    /// Runs copytree inside the fixture with stdout captured (never a TTY).
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().expect("failed to run copytree")
+    }
+
+    fn run_with_env(&self, args: &[&str], vars: &[(&str, &str)]) -> Output {
+        self.command(args)
+            .envs(vars.iter().copied())
+            .output()
+            .expect("failed to run copytree")
+    }
+}
+
+fn stdout_of(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr_of(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn piped_stdout_receives_output_by_default() {
+    let fixture = Fixture::new("piped");
+    let output = fixture.run(&["src"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(stdout.starts_with("src\n└─ main.rs\n"));
+    assert!(stdout.contains("--- src/main.rs ---\nfn main() {}\n"));
+    assert!(!stderr_of(&output).contains("Copied to clipboard."));
+}
+
+#[test]
+fn status_messages_stay_off_stdout_with_out_file() {
+    let fixture = Fixture::new("out_file");
+    let output = fixture.run(&["src", "--out", "ctx.txt"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert_eq!(stdout_of(&output), "");
+    assert_eq!(
+        stderr_of(&output),
+        "Wrote 1 file, 55 B (~4 tokens) to ctx.txt.\n"
+    );
+    let written = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    assert!(written.contains("--- src/main.rs ---"));
+}
+
+fn fixture_with_binary(label: &str) -> Fixture {
+    let fixture = Fixture::new(label);
+    // No PNG signature, so it is skipped as binary rather than described.
+    fixture.write("src/logo.png", b"\x00\x01\x02\x03\xff\xfe");
+    fixture
+}
+
+#[test]
+fn quiet_suppresses_skip_logs_and_status() {
+    let fixture = fixture_with_binary("quiet");
+    let output = fixture.run(&["src", "--out", "ctx.txt", "--quiet"]);
+
+    assert!(output.status.success());
+    assert_eq!(stderr_of(&output), "");
+}
+
+#[test]
+fn default_level_reports_skips_and_status() {
+    let fixture = fixture_with_binary("normal");
+    let output = fixture.run(&["src", "--out", "ctx.txt"]);
+
+    assert!(output.status.success());
+    let stderr = stderr_of(&output);
+    assert!(stderr.lines().any(|line| line == "Skipped src/logo.png"));
+    assert!(stderr
+        .lines()
+        .any(|line| line.starts_with("Wrote 1 file, ") && line.ends_with(" to ctx.txt.")));
+    assert!(!stderr.contains("Included"));
+}
+
+#[test]
+fn verbose_reports_every_file_decision() {
+    let fixture = fixture_with_binary("verbose");
+    fixture.write("src/lib.rs", b"pub fn lib() {}\n");
+    let output = fixture.run(&["src", "--out", "ctx.txt", "--verbose", "-x", "src/lib.rs"]);
+
+    assert!(output.status.success());
+    let stderr = stderr_of(&output);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert!(lines.contains(&"Included src/main.rs (13 bytes)"));
+    assert!(lines.contains(&"Skipped src/logo.png (image file)"));
+    assert!(lines.contains(&"Excluded src/lib.rs (pattern src/lib.rs)"));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("Wrote 1 file, ") && line.ends_with(" to ctx.txt.")));
+}
+
+#[test]
+fn report_json_lists_included_skipped_and_sinks() {
+    let fixture = fixture_with_binary("report");
+    let output = fixture.run(&["src", "--out", "ctx.txt", "--report-json", "report.json"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let report: serde_json::Value = serde_json::from_slice(
+        &fs::read(fixture.path().join("report.json")).expect("report written"),
+    )
+    .expect("report is valid JSON");
+
+    assert_eq!(report["schema_version"], 2);
+    assert_eq!(report["exit_status"], 0);
+    assert_eq!(report["included"][0]["path"], "src/main.rs");
+    assert_eq!(report["skipped"][0]["path"], "src/logo.png");
+    assert_eq!(report["skipped"][0]["reason"], "binary");
+    assert_eq!(report["skipped"][0]["detail"], "image file");
+    assert_eq!(report["totals"]["included_files"], 1);
+    assert_eq!(report["sinks"][0]["kind"], "file");
+    assert_eq!(report["sinks"][0]["path"], "ctx.txt");
+}
+
+#[test]
+fn status_json_ends_every_run_with_one_parseable_line() {
+    let fixture = fixture_with_binary("status_json");
+    let status_line = |output: &Output, stream: &str| -> serde_json::Value {
+        let last = stream.lines().last().unwrap_or_default().to_string();
+        serde_json::from_str(&last)
+            .unwrap_or_else(|err| panic!("{err}: {last:?}\nstderr: {}", stderr_of(output)))
+    };
+
+    let output = fixture.run(&["src", "--out", "ctx.txt", "--status-json"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let status = status_line(&output, &stderr_of(&output));
+    assert_eq!(status["sinks"][0]["kind"], "file");
+    assert_eq!(status["sinks"][0]["path"], "ctx.txt");
+    let written = fs::metadata(fixture.path().join("ctx.txt")).expect("output");
+    assert_eq!(status["output_bytes"], written.len());
+    assert_eq!(status["included_files"], 1);
+    assert_eq!(status["skipped_files"], 1);
+    assert!(status["duration_ms"].is_u64());
+    assert_eq!(status["exit_status"], 0);
+    assert!(status.get("error").is_none());
+
+    // The status line carries the exit status the run ends with.
+    let output = fixture.run(&[
+        "src",
+        "--out",
+        "ctx.txt",
+        "--force",
+        "--fail-on-skip",
+        "--status-json=stdout",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout_of(&output).lines().count(), 1);
+    let status = status_line(&output, &stdout_of(&output));
+    assert_eq!(status["skipped_files"], 1);
+    assert_eq!(status["exit_status"], 2);
+
+    let output = fixture.run(&["missing", "--out", "other.txt", "--status-json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let status = status_line(&output, &stderr_of(&output));
+    assert_eq!(status["exit_status"], 1);
+    assert_eq!(status["sinks"], serde_json::json!([]));
+    assert!(
+        status["error"]
+            .as_str()
+            .is_some_and(|error| error.starts_with("missing: ")),
+        "{status}"
+    );
+
+    let output = fixture.run(&["src", "--stdout", "--status-json=stdout"]);
+    assert_eq!(output.status.code(), Some(1));
+    let status = status_line(&output, &stdout_of(&output));
+    assert!(status["error"]
+        .as_str()
+        .is_some_and(|error| error.contains("--status-json=stdout")));
+}
+
+#[test]
+fn manifest_ranges_slice_each_file_out_of_the_output() {
+    let fixture = fixture_with_binary("manifest");
+    fs::create_dir_all(fixture.path().join("src/docs")).expect("create docs");
+    fixture.write("src/abc.txt", b"abc");
+    fixture.write(
+        "src/docs/notes.md",
+        "# Notes\n\nstra\u{df}e \u{2014} caf\u{e9}\n".as_bytes(),
+    );
+    fixture.write("src/docs/big.txt", &[b'x'; 4096]);
+    let output = fixture.run(&[
+        "src",
+        "--out",
+        "ctx.txt",
+        "--include-binary",
+        "base64",
+        "--max-file-bytes",
+        "1K",
+        "--manifest",
+    ]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let written = fs::read(fixture.path().join("ctx.txt")).expect("output written");
+    let manifest: serde_json::Value = serde_json::from_slice(
+        &fs::read(fixture.path().join("ctx.txt.manifest.json")).expect("manifest written"),
+    )
+    .expect("manifest is valid JSON");
+    assert_eq!(manifest["schema_version"], 2);
+    assert_eq!(manifest["output"], "ctx.txt");
+    assert_eq!(manifest["totals"]["output_bytes"], written.len());
+    assert!(manifest["provenance"]["version"].is_string());
+
+    let included = manifest["included"].as_array().expect("included files");
+    let paths: Vec<&str> = included
+        .iter()
+        .map(|file| file["path"].as_str().expect("path"))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            "src/abc.txt",
+            "src/docs/notes.md",
+            "src/logo.png",
+            "src/main.rs"
+        ]
+    );
+    for file in included {
+        let path = file["path"].as_str().expect("path");
+        let offset = file["offset"].as_u64().expect("offset") as usize;
+        let length = file["length"].as_u64().expect("length") as usize;
+        let range = &written[offset..offset + length];
+        let original = fs::read(fixture.path().join(path)).expect("read original");
+        let before = String::from_utf8_lossy(&written[..offset]);
+        let heading = before.lines().last().expect("a header line");
+        let header = file["header"].as_str().expect("header");
+        assert!(heading.starts_with(&format!("--- {header}")), "{heading}");
+        assert_eq!(
+            file["sha256"],
+            format!("{:x}", Sha256::digest(range)),
+            "{path}"
+        );
+        if file["encoding"] == "base64" {
+            let lines: String = String::from_utf8_lossy(range).split_whitespace().collect();
+            assert_eq!(lines, "AAECA//+", "{path}");
+        } else {
+            assert_eq!(range, original.as_slice(), "{path}");
+        }
+        assert_eq!(file["bytes"], original.len(), "{path}");
+    }
+    assert_eq!(
+        included[0]["sha256"],
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(manifest["skipped"][0]["path"], "src/docs/big.txt");
+    assert_eq!(manifest["skipped"][0]["reason"], "too-large");
+
+    let output = fixture.run(&["src", "--stdout", "--manifest=m.json"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(fixture.path().join("m.json")).expect("manifest"))
+            .expect("manifest is valid JSON");
+    assert!(manifest.get("output").is_none());
+    let offset = manifest["included"][0]["offset"].as_u64().expect("offset") as usize;
+    assert_eq!(&output.stdout[offset..offset + 3], b"abc");
+}
+
+#[test]
+fn manifest_refuses_crlf_output_and_slices_lf_output() {
+    let fixture = Fixture::new("manifest_eol");
+    fixture.write("src/a.txt", b"one\ntwo\n");
+    fixture.write("src/b.txt", b"three\n");
+
+    // CRLF would move every range after the first line, so it is refused
+    // before anything is written.
+    let output = fixture.run(&[
+        "src",
+        "--out",
+        "crlf.txt",
+        "--output-eol",
+        "crlf",
+        "--manifest",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr_of(&output).contains("--encode, --pipe-cmd, and --output-eol change"),
+        "stderr: {}",
+        stderr_of(&output)
+    );
+    assert!(!fixture.path().join("crlf.txt").exists());
+    assert!(!fixture.path().join("crlf.txt.manifest.json").exists());
+
+    let output = fixture.run(&["src", "--out", "lf.txt", "--output-eol", "lf", "--manifest"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let written = fs::read(fixture.path().join("lf.txt")).expect("output written");
+    let manifest: serde_json::Value = serde_json::from_slice(
+        &fs::read(fixture.path().join("lf.txt.manifest.json")).expect("manifest written"),
+    )
+    .expect("manifest is valid JSON");
+    assert_eq!(manifest["totals"]["output_bytes"], written.len());
+    let ranges: Vec<&[u8]> = manifest["included"]
+        .as_array()
+        .expect("included files")
+        .iter()
+        .map(|file| {
+            let offset = file["offset"].as_u64().expect("offset") as usize;
+            let length = file["length"].as_u64().expect("length") as usize;
+            &written[offset..offset + length]
+        })
+        .collect();
+    assert_eq!(
+        ranges,
+        [&b"one\ntwo\n"[..], &b"three\n"[..], &b"fn main() {}\n"[..]]
+    );
+}
+
+#[test]
+fn omit_skipped_drops_only_the_sections_of_skipped_files() {
+    let fixture = fixture_with_binary("omit_skipped");
+    fixture.write("src/Cargo.lock", b"# lockfile\n");
+    fixture.write("src/big.txt", &[b'x'; 4096]);
+    let run = |name: &str, extra: &[&str]| {
+        let report = format!("{}.json", name);
+        let output = fixture.run(
+            &[
+                &[
+                    "src",
+                    "--out",
+                    name,
+                    "--report-json",
+                    report.as_str(),
+                    "--exclude-contents",
+                    "**/*.lock",
+                    "--max-file-bytes",
+                    "1K",
+                ][..],
+                extra,
+            ]
+            .concat(),
+        );
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        let text = fs::read_to_string(fixture.path().join(name)).expect("output written");
+        let report: serde_json::Value = serde_json::from_slice(
+            &fs::read(fixture.path().join(&report)).expect("report written"),
+        )
+        .expect("report is valid JSON");
+        (text, report)
+    };
+    let (full, full_report) = run("full.txt", &[]);
+    let (omitted, omitted_report) = run("omitted.txt", &["--omit-skipped"]);
+
+    assert_eq!(full.matches("<skipped").count(), 3);
+    assert!(!omitted.contains("<skipped"));
+    assert!(!omitted.contains("--- src/Cargo.lock ---"));
+    assert!(omitted.contains("--- src/main.rs ---\nfn main() {}\n"));
+    let mut kept = String::new();
+    let mut rest = full.as_str();
+    while let Some(start) = rest.find("--- ") {
+        let end = rest[start..]
+            .find("\n\n")
+            .map_or(rest.len(), |end| start + end + 2);
+        kept.push_str(&rest[..start]);
+        if !rest[start..end].contains("\n<skipped") {
+            kept.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    kept.push_str(rest);
+    assert_eq!(omitted, kept);
+
+    for key in ["included", "skipped"] {
+        assert_eq!(omitted_report[key], full_report[key], "{}", key);
+    }
+    assert_eq!(omitted_report["totals"]["skipped_files"], 3);
+}
+
+#[test]
+fn output_eol_converts_generated_lines_and_optionally_bodies() {
+    let fixture = Fixture::new("output_eol");
+    fixture.write("src/mixed.txt", b"a\r\nb\nc\r\n");
+    let run = |name: &str, extra: &[&str]| {
+        let output = fixture.run(&[&["src", "--out", name][..], extra].concat());
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        fs::read(fixture.path().join(name)).expect("output written")
+    };
+    let tree = "src\n├─ main.rs\n└─ mixed.txt\n\n";
+    let lf = format!(
+        "{}--- src/main.rs ---\nfn main() {{}}\n\n\n--- src/mixed.txt ---\na\r\nb\nc\r\n\n\n",
+        tree
+    );
+    let generated_crlf = format!(
+        "{}--- src/main.rs ---\r\nfn main() {{}}\n\r\n\r\n--- src/mixed.txt ---\r\na\r\nb\nc\r\n\r\n\r\n",
+        tree.replace('\n', "\r\n")
+    );
+    let all_crlf = format!(
+        "{}--- src/main.rs ---\r\nfn main() {{}}\r\n\r\n\r\n--- src/mixed.txt ---\r\na\r\nb\r\nc\r\n\r\n\r\n",
+        tree.replace('\n', "\r\n")
+    );
+
+    assert_eq!(String::from_utf8(run("default.txt", &[])).unwrap(), lf);
+    assert_eq!(
+        String::from_utf8(run("lf.txt", &["--output-eol", "lf"])).unwrap(),
+        lf
+    );
+    assert_eq!(
+        String::from_utf8(run("crlf.txt", &["--output-eol", "crlf"])).unwrap(),
+        generated_crlf
+    );
+    assert_eq!(
+        String::from_utf8(run(
+            "content.txt",
+            &["--output-eol", "crlf", "--output-eol-content"]
+        ))
+        .unwrap(),
+        all_crlf
+    );
+    let native = if cfg!(windows) { &all_crlf } else { &lf };
+    assert_eq!(
+        String::from_utf8(run(
+            "native.txt",
+            &["--output-eol", "native", "--output-eol-content"]
+        ))
+        .unwrap(),
+        *native
+    );
+}
+
+#[test]
+fn absolute_paths_show_each_root_whole_and_strip_prefix_trims_only_its_own() {
+    let first = fixture_with_binary("absolute_first");
+    let second = Fixture::new("absolute_second");
+    second.write("src/lib.rs", b"pub fn f() {}\n");
+    fs::create_dir_all(first.path().join("src-extra")).expect("create src-extra");
+    first.write("src-extra/notes.txt", b"notes\n");
+    let first_root = first.path().canonicalize().expect("canonical first root");
+    let second_root = second.path().canonicalize().expect("canonical second root");
+    let second_src = second_root.join("src");
+    let second_src = second_src.to_str().expect("UTF-8 path");
+    let full = |root: &Path, path: &str| format!("{}/{}", root.display(), path);
+
+    let output = first.run(&["src", "src-extra", second_src, "--absolute-paths"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    for (root, path) in [
+        (&first_root, "src/main.rs"),
+        (&first_root, "src/logo.png"),
+        (&first_root, "src-extra/notes.txt"),
+        (&second_root, "src/lib.rs"),
+    ] {
+        let header = format!("--- {} ---", full(root, path));
+        assert!(stdout.contains(&header), "missing {header}: {stdout}");
+    }
+    for root in [
+        full(&first_root, "src"),
+        full(&first_root, "src-extra"),
+        full(&second_root, "src"),
+    ] {
+        assert!(stdout.lines().any(|line| line == root), "{stdout}");
+    }
+    assert!(
+        stderr_of(&output).contains(&format!("Skipped {}", full(&first_root, "src/logo.png"))),
+        "{}",
+        stderr_of(&output)
+    );
+
+    let prefix = full(&first_root, "src");
+    let output = first.run(&[
+        "src",
+        "src-extra",
+        second_src,
+        "--absolute-paths",
+        "--strip-prefix",
+        &prefix,
+    ]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.starts_with(".\n├─ logo.png\n└─ main.rs\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("--- main.rs ---"), "{stdout}");
+    // Only paths below the prefix are trimmed; a sibling sharing its
+    // leading characters and the other root stay whole.
+    for path in [
+        full(&first_root, "src-extra/notes.txt"),
+        full(&second_root, "src/lib.rs"),
+    ] {
+        assert!(stdout.contains(&format!("--- {} ---", path)), "{stdout}");
+    }
+
+    let output = first.run(&["src", "--strip-prefix", "src"]);
+    assert!(!output.status.success());
+    assert!(stderr_of(&output).contains("--strip-prefix only applies to --absolute-paths"));
+}
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .expect("failed to run git");
+    assert!(
+        output.status.success(),
+        "git {:?}: {}",
+        args,
+        stderr_of(&output)
+    );
+    stdout_of(&output).trim().to_string()
+}
+
+#[test]
+fn changed_since_keeps_only_files_touched_since_the_ref() {
+    let fixture = Fixture::new("changed_since");
+    let root = fixture.path();
+    fixture.write("src/kept.rs", b"fn kept() {}\n");
+    fixture.write("src/edited.rs", b"fn edited() {}\n");
+    git(root, &["init", "-q", "-b", "trunk"]);
+    git(root, &["config", "user.email", "dev@example.com"]);
+    git(root, &["config", "user.name", "Dev"]);
+    git(root, &["add", "."]);
+    git(
+        root,
+        &["-c", "commit.gpgsign=false", "commit", "-q", "-m", "Start"],
+    );
+    fixture.write("src/edited.rs", b"fn edited() { 1 }\n");
+    fixture.write("src/fresh.rs", b"fn fresh() {}\n");
+
+    let output = fixture.run(&["src", "--stdout", "--changed-since", "HEAD"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert_eq!(
+        stdout_of(&output),
+        "src\n├─ edited.rs\n└─ fresh.rs\n\n\
+         --- src/edited.rs ---\nfn edited() { 1 }\n\n\n\
+         --- src/fresh.rs ---\nfn fresh() {}\n\n\n\n"
+    );
+
+    let output = fixture.run(&["src", "--stdout", "--changed-since", "nope"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr_of(&output).contains("--changed-since: `nope` does not name a commit in "),
+        "{}",
+        stderr_of(&output)
+    );
+}
+
+#[test]
+fn report_json_records_the_git_state_of_each_repository() {
+    let fixture = Fixture::new("report_git");
+    let repo = fixture.path().join("repo");
+    fs::create_dir_all(repo.join("src")).expect("create repo");
+    fs::write(repo.join("src/lib.rs"), "pub fn lib() {}\n").expect("write file");
+    git(&repo, &["init", "-q", "-b", "trunk"]);
+    git(&repo, &["config", "user.email", "dev@example.com"]);
+    git(&repo, &["config", "user.name", "Dev"]);
+    git(&repo, &["add", "."]);
+    git(
+        &repo,
+        &[
+            "-c",
+            "commit.gpgsign=false",
+            "commit",
+            "-q",
+            "-m",
+            "Add lib",
+        ],
+    );
+    let head = git(&repo, &["rev-parse", "--short", "HEAD"]);
+    let read_report = |args: &[&str]| -> serde_json::Value {
+        let output = fixture.run(args);
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        serde_json::from_slice(&fs::read(fixture.path().join("report.json")).expect("report"))
+            .expect("report is valid JSON")
+    };
+
+    let report = read_report(&[
+        "repo/src",
+        "--out",
+        "ctx.txt",
+        "--report-json",
+        "report.json",
+    ]);
+    let repositories = report["repositories"].as_array().expect("repositories");
+    assert_eq!(repositories.len(), 1);
+    assert_eq!(repositories[0]["commit"], head.as_str());
+    assert_eq!(repositories[0]["branch"], "trunk");
+    assert_eq!(repositories[0]["dirty"], false);
+
+    fs::write(repo.join("src/new.rs"), "\n").expect("write file");
+    let report = read_report(&[
+        "repo/src",
+        "src",
+        "--out",
+        "ctx.txt",
+        "--force",
+        "--report-json",
+        "report.json",
+    ]);
+    assert_eq!(report["repositories"][0]["dirty"], true);
+
+    let in_repository = Command::new("git")
+        .arg("-C")
+        .arg(fixture.path())
+        .args(["rev-parse", "--git-dir"])
+        .output()
+        .expect("failed to run git")
+        .status
+        .success();
+    if !in_repository {
+        assert_eq!(report["repositories"].as_array().map(Vec::len), Some(1));
+        let report = read_report(&[
+            "src",
+            "--out",
+            "ctx.txt",
+            "--force",
+            "--report-json",
+            "report.json",
+        ]);
+        assert!(report.get("repositories").is_none());
+    }
+}
+
+#[test]
+fn progress_is_silent_when_stderr_is_not_a_terminal() {
+    let fixture = Fixture::new("progress");
+    let output = fixture.run(&["src", "--out", "ctx.txt"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stderr = stderr_of(&output);
+    assert!(!stderr.contains('\r'));
+    assert!(!stderr.contains("Scanning"));
+    assert!(!stderr.contains("Reading"));
+}
+
+#[test]
+fn parallel_reads_match_serial_output() {
+    let fixture = Fixture::new("parallel");
+    fs::create_dir_all(fixture.path().join("src/many")).expect("create dir");
+    for index in 0..300 {
+        let body = format!("// file {}\n{}", index, "x".repeat(index * 7));
+        fixture.write(&format!("src/many/f{:03}.rs", index), body.as_bytes());
+    }
+    fixture.write("src/many/blob.bin", b"\xff\xfe\x00binary");
+
+    let serial = fixture.run(&["src", "--read-threads", "1"]);
+    let parallel = fixture.run(&["src", "--read-threads", "8"]);
+
+    assert!(serial.status.success(), "stderr: {}", stderr_of(&serial));
+    assert!(
+        parallel.status.success(),
+        "stderr: {}",
+        stderr_of(&parallel)
+    );
+    assert!(stdout_of(&serial).contains("--- src/many/f299.rs ---"));
+    assert_eq!(serial.stdout, parallel.stdout);
+    assert_eq!(stderr_of(&serial), stderr_of(&parallel));
+}
+
+#[test]
+fn max_file_bytes_applies_to_walked_files() {
+    let fixture = Fixture::new("size_limit");
+    fixture.write("src/big.txt", &[b'a'; 200]);
+    let output = fixture.run(&["src", "--max-file-bytes", "100"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(stdout.contains(
+        "--- src/big.txt ---\n<skipped reason=\"too-large\" size=\"200\" limit=\"100\"> file size 200 bytes exceeds --max-file-bytes 100"
+    ));
+    assert!(stdout.contains("--- src/main.rs ---\nfn main() {}\n"));
+}
+
+#[test]
+fn exit_code_is_zero_when_skips_are_tolerated() {
+    let fixture = fixture_with_binary("exit_ok");
+    let output = fixture.run(&["src", "--out", "ctx.txt"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn exit_code_is_one_for_hard_errors() {
+    let fixture = Fixture::new("exit_error");
+    let missing_root = fixture.run(&["does-not-exist"]);
+    assert_eq!(missing_root.status.code(), Some(1));
+    assert!(stderr_of(&missing_root).starts_with("Error: "));
+
+    let bad_glob = fixture.run(&["src", "-x", "src/[", "--"]);
+    assert_eq!(bad_glob.status.code(), Some(1));
+    assert!(stderr_of(&bad_glob).contains("Invalid --exclude glob"));
+}
+
+#[test]
+fn interactive_without_a_terminal_is_an_error() {
+    let fixture = Fixture::new("interactive_no_tty");
+    let output = fixture.run(&["src", "--interactive", "--out", "ctx.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr_of(&output).contains("--interactive needs a terminal"));
+    assert!(!fixture.path().join("ctx.txt").exists());
+}
+
+#[test]
+fn exit_code_is_two_for_skips_with_fail_on_skip() {
+    let fixture = fixture_with_binary("exit_skip");
+    let output = fixture.run(&["src", "--out", "ctx.txt", "--fail-on-skip"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(fixture.path().join("ctx.txt").exists());
+}
+
+#[test]
+fn described_images_are_included_rather_than_skipped() {
+    let fixture = Fixture::new("described_image");
+    fixture.write(
+        "src/icon.png",
+        b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01",
+    );
+    let output = fixture.run(&[
+        "src",
+        "--out",
+        "ctx.txt",
+        "--fail-on-skip",
+        "--tree-tokens",
+        "--manifest",
+        "--report-json",
+        "report.json",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr_of(&output));
+    assert!(
+        !stderr_of(&output).contains("Skipped"),
+        "{}",
+        stderr_of(&output)
+    );
+    let written = fs::read_to_string(fixture.path().join("ctx.txt")).expect("ctx.txt written");
+    assert!(written.contains("├─ icon.png  (~"), "{written}");
+    assert!(!written.contains("icon.png  (~0 tok)"), "{written}");
+    assert!(
+        written.contains(
+            "--- src/icon.png ---\n<image format=\"png\" width=\"1\" height=\"1\" size=\"24\"> PNG, 1×1, 24 B\n"
+        ),
+        "{written}"
+    );
+    let report: serde_json::Value = serde_json::from_slice(
+        &fs::read(fixture.path().join("report.json")).expect("report written"),
+    )
+    .expect("report is valid JSON");
+    assert_eq!(report["included"][0]["path"], "src/icon.png");
+    assert_eq!(report["skipped"], serde_json::json!([]));
+
+    // Restore has no bytes to write for it, and verifying does not miss them.
+    let restored = fixture.run(&["restore", "ctx.txt", "--dest", "restored", "--verify"]);
+    assert!(restored.status.success(), "{}", stderr_of(&restored));
+    assert!(!fixture.path().join("restored/src/icon.png").exists());
+    assert!(fixture.path().join("restored/src/main.rs").exists());
+}
+
+#[test]
+fn exclude_drops_files_while_exclude_contents_keeps_them_listed() {
+    let fixture = Fixture::new("exclude_contents");
+    fixture.write("src/secret.env", b"TOKEN=1\n");
+    fixture.write("src/data.json", b"{}\n");
+    let copy = |args: &[&str]| {
+        let output = fixture.run(&[&["src", "--stdout"], args].concat());
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        stdout_of(&output)
+    };
+
+    let excluded = copy(&["--exclude", "*.env", "--"]);
+    assert!(!excluded.contains("secret.env"), "{excluded}");
+    assert!(
+        excluded.contains("--- src/data.json ---\n{}\n"),
+        "{excluded}"
+    );
+
+    let blanked = copy(&["--exclude-contents", "*.env"]);
+    assert!(blanked.contains("└─ secret.env\n"), "{blanked}");
+    assert!(
+        blanked.contains(
+            "--- src/secret.env ---\n<skipped reason=\"excluded\" pattern=\"*.env\"> \
+             excluded by pattern *.env\n"
+        ),
+        "{blanked}"
+    );
+    assert!(!blanked.contains("TOKEN"), "{blanked}");
+
+    // --exclude wins: the file is gone from the tree as well.
+    let both = copy(&["--exclude-contents", "*.env", "--exclude", "*.env", "--"]);
+    assert_eq!(both, excluded);
+}
+
+#[test]
+fn excluded_files_do_not_trip_fail_on_skip() {
+    let fixture = fixture_with_binary("exit_excluded");
+    let output = fixture.run(&["src", "--fail-on-skip", "--exclude-contents", "**/*.png"]);
+    assert_eq!(
+        output.status.code(),
+        Some(0),
+        "stderr: {}",
+        stderr_of(&output)
+    );
+}
+
+#[test]
+fn a_pattern_without_a_slash_excludes_at_any_depth_below_the_root() {
+    let fixture = Fixture::new("exclude_any_depth");
+    for dir in ["src/target", "src/targets", "build/app/build", "cwd"] {
+        fs::create_dir_all(fixture.path().join(dir)).expect("create dir");
+    }
+    fixture.write("src/target/x", b"built\n");
+    fixture.write("src/targets/y", b"kept\n");
+    let output = fixture.run(&["src", "--stdout", "-x", "target", "--"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let copied = stdout_of(&output);
+    assert!(!copied.contains("built"), "{copied}");
+    assert!(copied.contains("--- src/targets/y ---\nkept\n"), "{copied}");
+
+    // Only components below the root count, wherever the root is.
+    fixture.write("build/app/lib.rs", b"pub fn app() {}\n");
+    fixture.write("build/app/build/out.txt", b"object\n");
+    let root = fixture.path().join("build/app");
+    let outside = fixture
+        .command(&[
+            root.to_str().expect("utf-8 path"),
+            "--stdout",
+            "-x",
+            "build",
+            "--",
+        ])
+        .current_dir(fixture.path().join("cwd"))
+        .output()
+        .expect("failed to run copytree");
+    assert!(outside.status.success(), "stderr: {}", stderr_of(&outside));
+    let copied = stdout_of(&outside);
+    assert!(copied.contains("pub fn app() {}"), "{copied}");
+    assert!(!copied.contains("object"), "{copied}");
+}
+
+#[test]
+fn a_pattern_leaves_out_the_same_files_at_walk_time_and_at_content_time() {
+    let fixture = Fixture::new("walk_and_content");
+    let reports = Fixture::new("walk_and_content_reports");
+    fs::create_dir_all(fixture.path().join("src/gen")).expect("create dir");
+    fs::create_dir_all(fixture.path().join("docs")).expect("create dir");
+    fixture.write("src/gen/out.rs", b"// generated\n");
+    fixture.write("docs/guide.md", b"# Guide\n");
+    fixture.write("Cargo.lock", b"lock\n");
+    let report_path = reports.path().join("report.json");
+    let report_arg = report_path.to_string_lossy().into_owned();
+    let paths = |args: &[&str], list: &str| -> Vec<String> {
+        let output = fixture.run(&[args, &["--stdout", "--report-json", &report_arg]].concat());
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report_path).expect("report written"))
+                .expect("report is valid JSON");
+        report[list]
+            .as_array()
+            .expect("a list of files")
+            .iter()
+            .filter(|file| list == "included" || file["reason"] == "excluded")
+            .map(|file| file["path"].as_str().expect("a path").to_string())
+            .collect()
+    };
+
+    let absolute = fixture.path().join("src").to_string_lossy().into_owned();
+    for root in [".", "src", "./src", absolute.as_str()] {
+        let all = paths(&[root], "included");
+        for pattern in [
+            "src/gen/*",
+            "*.lock",
+            "**/gen/**",
+            "docs/*.md",
+            "./src/main.rs",
+        ] {
+            let kept = paths(&[root, &format!("--exclude={pattern}")], "included");
+            let dropped: Vec<String> = all
+                .iter()
+                .filter(|path| !kept.contains(path))
+                .cloned()
+                .collect();
+            let blanked = paths(&[root, &format!("--exclude-contents={pattern}")], "skipped");
+            assert_eq!(dropped, blanked, "{pattern} under {root}");
+        }
+    }
+    // Walked from an absolute root, a path still matches relative to the
+    // current directory.
+    for root in [".", absolute.as_str()] {
+        assert_eq!(
+            paths(&[root, "--exclude-contents=src/gen/*"], "skipped"),
+            ["src/gen/out.rs"]
+        );
+    }
+}
+
+#[test]
+fn hundreds_of_patterns_over_many_roots_are_compiled_once() {
+    let fixture = Fixture::new("many_patterns");
+    let mut args: Vec<String> = Vec::new();
+    for root in 0..50 {
+        let dir = format!("r{root:02}");
+        fs::create_dir_all(fixture.path().join(&dir).join("gen")).expect("create dir");
+        fixture.write(&format!("{dir}/lib.rs"), b"pub fn lib() {}\n");
+        fixture.write(&format!("{dir}/gen/out.rs"), b"// generated\n");
+        args.push(dir);
+    }
+    for pattern in 0..499 {
+        args.push(format!("--exclude=**/unused{pattern}/*.rs"));
+    }
+    args.push("--exclude=**/gen/*".to_string());
+    args.push("--stdout".to_string());
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let started = std::time::Instant::now();
+    let output = fixture.run(&args);
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert_eq!(stdout.matches("/lib.rs ---\n").count(), 50, "{stdout}");
+    assert!(!stdout.contains("out.rs"), "{stdout}");
+}
+
+#[test]
+fn check_compares_with_the_out_file_without_writing_it() {
+    let fixture = Fixture::new("check");
+    let snapshot = fixture.path().join("CONTEXT.txt");
+    let check = ["src", "--provenance", "--check", "--out", "CONTEXT.txt"];
+
+    let missing = fixture.run(&check);
+    assert_eq!(
+        missing.status.code(),
+        Some(3),
+        "stderr: {}",
+        stderr_of(&missing)
+    );
+    assert!(stderr_of(&missing).contains("CONTEXT.txt does not exist"));
+    assert!(!snapshot.exists());
+
+    // A timestamped snapshot matches as long as only the time differs.
+    let written = fixture.run(&["src", "--provenance", "--out", "CONTEXT.txt"]);
+    assert!(written.status.success(), "stderr: {}", stderr_of(&written));
+    let up_to_date = fixture.run(&check);
+    assert_eq!(
+        up_to_date.status.code(),
+        Some(0),
+        "stderr: {}",
+        stderr_of(&up_to_date)
+    );
+    assert!(stderr_of(&up_to_date).contains("CONTEXT.txt is up to date"));
+
+    fixture.write("src/main.rs", b"fn main() { run() }\n");
+    fixture.write("src/lib.rs", b"pub fn run() {}\n");
+    let before = fs::read(&snapshot).expect("read snapshot");
+    let out_of_date = fixture.run(&check);
+    assert_eq!(out_of_date.status.code(), Some(3));
+    let stderr = stderr_of(&out_of_date);
+    assert!(
+        stderr.contains("Warning: CONTEXT.txt is out of date"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("  added    src/lib.rs\n  changed  src/main.rs\n"),
+        "{stderr}"
+    );
+    assert_eq!(fs::read(&snapshot).expect("read snapshot"), before);
+
+    let fixed = fixture.run(&[
+        "src",
+        "--provenance",
+        "--check",
+        "--fix",
+        "--out",
+        "CONTEXT.txt",
+    ]);
+    assert_eq!(fixed.status.code(), Some(3));
+    assert!(fs::read_to_string(&snapshot)
+        .expect("read snapshot")
+        .contains("fn main() { run() }"));
+    assert_eq!(fixture.run(&check).status.code(), Some(0));
+}
+
+fn read_tree(root: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).expect("read dir") {
+            let path = entry.expect("dir entry").path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let contents = fs::read(&path).expect("read file");
+                files.push((path.strip_prefix(root).unwrap().to_path_buf(), contents));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn restore_round_trips_a_copied_tree() {
+    let fixture = Fixture::new("restore");
+    fs::create_dir_all(fixture.path().join("src/nested")).expect("create dir");
+    fixture.write(
+        "src/nested/notes.md",
+        b"# Notes\n\n--- looks like a header ---\n\n",
+    );
+    fixture.write("src/no_newline.txt", b"tail");
+
+    let copy = fixture.run(&["src", "--out", "ctx.txt"]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+    let restore = fixture.run(&["restore", "ctx.txt", "--dest", "restored"]);
+    assert!(restore.status.success(), "stderr: {}", stderr_of(&restore));
+
+    assert_eq!(
+        read_tree(&fixture.path().join("restored/src")),
+        read_tree(&fixture.path().join("src"))
+    );
+}
+
+#[test]
+fn restore_verify_reports_files_that_differ_from_the_manifest() {
+    let fixture = Fixture::new("restore_verify");
+    fixture.write("src/lib.rs", b"pub fn lib() {\n    1\n}\n");
+    let copy = fixture.run(&["src", "--out", "ctx.txt", "--manifest"]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+
+    let verified = fixture.run(&["restore", "ctx.txt", "--dest", "clean", "--verify"]);
+    assert!(
+        verified.status.success(),
+        "stderr: {}",
+        stderr_of(&verified)
+    );
+
+    // A chat tool that collapses indentation.
+    let snapshot = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    fixture.write("ctx.txt", snapshot.replace("    1", " 1").as_bytes());
+    let restore = fixture.run(&["restore", "ctx.txt", "--dest", "report", "--verify"]);
+    assert_eq!(
+        restore.status.code(),
+        Some(2),
+        "stderr: {}",
+        stderr_of(&restore)
+    );
+    let stderr = stderr_of(&restore);
+    assert!(
+        stderr.contains(
+            "src/lib.rs does not match the manifest: \
+             expected sha256 a5f13f98433ba9bfbd2fa91bae4cc236530f2e547a286d6da1d99c31e45a76c2, \
+             got 8b8643441ffa1ccf4a590ded1d09e4ef05fe2568ead11f7be37a170ed7a6d8c2"
+        ),
+        "{stderr}"
+    );
+    assert!(stderr.contains("1 file(s) failed verification"), "{stderr}");
+    assert!(!stderr.contains("src/main.rs"), "{stderr}");
+    assert_eq!(
+        fs::read_to_string(fixture.path().join("report/src/lib.rs")).expect("kept"),
+        "pub fn lib() {\n 1\n}\n"
+    );
+
+    let strict = fixture.run(&["restore", "ctx.txt", "--dest", "strict", "--verify=strict"]);
+    assert_eq!(
+        strict.status.code(),
+        Some(2),
+        "stderr: {}",
+        stderr_of(&strict)
+    );
+    assert!(!fixture.path().join("strict/src/lib.rs").exists());
+    assert!(fixture.path().join("strict/src/main.rs").exists());
+
+    fs::remove_file(fixture.path().join("ctx.txt.manifest.json")).expect("remove manifest");
+    let unverified = fixture.run(&["restore", "ctx.txt", "--dest", "bare", "--verify"]);
+    assert!(
+        unverified.status.success(),
+        "stderr: {}",
+        stderr_of(&unverified)
+    );
+    assert!(
+        stderr_of(&unverified).contains("no manifest found at ctx.txt.manifest.json"),
+        "{}",
+        stderr_of(&unverified)
+    );
+}
+
+#[test]
+fn restore_verify_matches_sections_under_the_default_root() {
+    let fixture = Fixture::new("restore_verify_dot");
+    fixture.write("README.md", b"# Demo\n");
+    let copy = fixture.run(&["--out", "ctx.txt", "--manifest"]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+    let manifest: serde_json::Value = serde_json::from_slice(
+        &fs::read(fixture.path().join("ctx.txt.manifest.json")).expect("manifest written"),
+    )
+    .expect("manifest is valid JSON");
+    assert_eq!(manifest["included"][0]["path"], "README.md");
+    assert_eq!(manifest["included"][0]["header"], "./README.md");
+
+    let verified = fixture.run(&["restore", "ctx.txt", "--dest", "clean", "--verify"]);
+    assert_eq!(
+        verified.status.code(),
+        Some(0),
+        "stderr: {}",
+        stderr_of(&verified)
+    );
+    assert!(fixture.path().join("clean/README.md").exists());
+
+    // A chat tool that drops a section.
+    let snapshot = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    fixture.write(
+        "ctx.txt",
+        snapshot
+            .replace("--- ./README.md ---\n# Demo\n", "")
+            .as_bytes(),
+    );
+    let dropped = fixture.run(&["restore", "ctx.txt", "--dest", "dropped", "--verify"]);
+    assert_eq!(
+        dropped.status.code(),
+        Some(2),
+        "stderr: {}",
+        stderr_of(&dropped)
+    );
+    let stderr = stderr_of(&dropped);
+    assert!(
+        stderr.contains("./README.md is in the manifest but has no section in the snapshot"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("main.rs"), "{stderr}");
+}
+
+#[test]
+fn include_binary_round_trips_small_binaries_through_restore() {
+    let fixture = Fixture::new("include_binary");
+    fs::create_dir_all(fixture.path().join("assets")).expect("create dir");
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x10\0\0\0\x10\x08\x06\0\0\0".to_vec();
+    png.extend((0..1800u32).map(|index| (index.wrapping_mul(2_654_435_761) >> 13) as u8));
+    fixture.write("assets/favicon.png", &png);
+    fixture.write("assets/big.bin", &[0xff; 3000]);
+
+    let copy = fixture.run(&[
+        "assets",
+        "--include-binary",
+        "base64:2KiB",
+        "--out",
+        "ctx.txt",
+        "--manifest",
+    ]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+    let snapshot = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    assert!(
+        snapshot.contains("--- assets/favicon.png (binary, base64, 1,829 bytes) ---\niVBORw0KGgo"),
+        "{snapshot}"
+    );
+    assert!(
+        snapshot.lines().all(|line| line.chars().count() <= 76),
+        "{snapshot}"
+    );
+    assert!(
+        snapshot.contains("--- assets/big.bin ---\n<skipped reason=\"binary\""),
+        "{snapshot}"
+    );
+
+    // The manifest hashes the base64 lines, which --verify encodes again.
+    let restore = fixture.run(&["restore", "ctx.txt", "--dest", "restored", "--verify"]);
+    assert_eq!(
+        restore.status.code(),
+        Some(0),
+        "stderr: {}",
+        stderr_of(&restore)
+    );
+    assert_eq!(
+        fs::read(fixture.path().join("restored/assets/favicon.png")).expect("read restored"),
+        png
+    );
+    assert!(!fixture.path().join("restored/assets/big.bin").exists());
+}
+
+#[test]
+fn diff_lists_changed_files_and_skip_states_between_two_outputs() {
+    let fixture = Fixture::new("diff");
+    fixture.write("src/data.txt", b"small\n");
+    fixture.write("src/old.rs", b"fn old() {}\n");
+    let before = fixture.run(&["src", "--max-file-bytes", "32", "--out", "old.txt"]);
+    assert!(before.status.success(), "stderr: {}", stderr_of(&before));
+
+    fixture.write("src/main.rs", b"fn main() {\n    run();\n}\n");
+    fixture.write("src/data.txt", b"no longer a small file by any measure\n");
+    fixture.write("src/lib.rs", b"pub fn run() {}\n");
+    fs::remove_file(fixture.path().join("src/old.rs")).expect("remove old.rs");
+    let after = fixture.run(&["src", "--max-file-bytes", "32", "--out", "new.txt"]);
+    assert!(after.status.success(), "stderr: {}", stderr_of(&after));
+
+    let diff = fixture.run(&["diff", "old.txt", "new.txt", "--out", "diff.txt"]);
+    assert!(diff.status.success(), "stderr: {}", stderr_of(&diff));
+    assert_eq!(
+        fs::read_to_string(fixture.path().join("diff.txt")).expect("read diff.txt"),
+        "changed  src/data.txt: was included, now <skipped reason=\"too-large\" size=\"38\" limit=\"32\"> file size 38 bytes exceeds --max-file-bytes 32\n\
+         added    src/lib.rs\n\
+         changed  src/main.rs\n\
+         removed  src/old.rs\n\
+         \n\
+         --- a/src/main.rs\n\
+         +++ b/src/main.rs\n\
+         @@ -1 +1,3 @@\n\
+         -fn main() {}\n\
+         +fn main() {\n\
+         +    run();\n\
+         +}\n"
+    );
+
+    let same = fixture.run(&["diff", "new.txt", "new.txt", "--stdout"]);
+    assert!(same.status.success(), "stderr: {}", stderr_of(&same));
+    assert_eq!(stdout_of(&same), "");
+    assert!(stderr_of(&same).contains("No differences"));
+}
+
+#[test]
+fn provenance_line_records_expanded_settings_and_restore_reads_past_it() {
+    let fixture = Fixture::new("provenance");
+    fixture.write_user_config("exclude = [\"*.lock\"]\n");
+    fixture.write("src/Cargo.lock", b"lock");
+
+    let copy = fixture.run(&[
+        "src",
+        "--no-tree",
+        "--provenance",
+        "--no-timestamp",
+        "--out",
+        "ctx.txt",
+        "--report-json",
+        "report.json",
+    ]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+    let text = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    let (line, body) = text.split_once('\n').expect("provenance line");
+    let config = fixture.home().join(".config/copytree/config.toml");
+    assert_eq!(
+        line,
+        format!(
+            "# copytree-provenance version={} args=\"--exclude '*.lock' --no-tree src\" config={}",
+            env!("CARGO_PKG_VERSION"),
+            config.to_string_lossy().replace('\\', "/")
+        )
+    );
+    assert!(body.starts_with("--- src/main.rs ---\n"), "{body}");
+    assert!(!body.contains("Cargo.lock"), "{body}");
+
+    let report: serde_json::Value = serde_json::from_slice(
+        &fs::read(fixture.path().join("report.json")).expect("report written"),
+    )
+    .expect("report is valid JSON");
+    assert_eq!(
+        report["provenance"]["args"],
+        serde_json::json!(["--exclude", "*.lock", "--no-tree", "src"])
+    );
+    assert!(report["provenance"].get("time").is_none());
+
+    let restore = fixture.run(&["restore", "ctx.txt", "--dest", "restored"]);
+    assert!(restore.status.success(), "stderr: {}", stderr_of(&restore));
+    assert_eq!(
+        fs::read(fixture.path().join("restored/src/main.rs")).expect("restored file"),
+        b"fn main() {}\n"
+    );
+}
+
+#[test]
+fn provenance_lists_no_config_when_none_was_read() {
+    let fixture = Fixture::new("provenance_no_config");
+    assert!(!fixture.home().join(".config/copytree/config.toml").exists());
+
+    let copy = fixture.run(&[
+        "src",
+        "--no-tree",
+        "--provenance",
+        "--no-timestamp",
+        "--out",
+        "ctx.txt",
+        "--report-json",
+        "report.json",
+    ]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+    let text = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    let (line, _) = text.split_once('\n').expect("provenance line");
+    assert_eq!(
+        line,
+        format!(
+            "# copytree-provenance version={} args=\"--no-tree src\"",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+    let report: serde_json::Value = serde_json::from_slice(
+        &fs::read(fixture.path().join("report.json")).expect("report written"),
+    )
+    .expect("report is valid JSON");
+    assert_eq!(report["provenance"]["config"], serde_json::json!([]));
+}
+
+#[test]
+fn readme_first_leads_each_directory_without_changing_the_tree() {
+    let fixture = Fixture::new("readme_first");
+    for dir in ["src/benches", "src/docs/guide"] {
+        fs::create_dir_all(fixture.path().join(dir)).expect("create dir");
+    }
+    for file in [
+        "src/Cargo.toml",
+        "src/README.md",
+        "src/benches/run.rs",
+        "src/docs/api.md",
+        "src/docs/readme.txt",
+        "src/docs/guide/README",
+        "src/docs/guide/intro.md",
+    ] {
+        fixture.write(file, b"text\n");
+    }
+    let headers = |stdout: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("--- ")?.strip_suffix(" ---"))
+            .map(str::to_string)
+            .collect()
+    };
+
+    let plain = stdout_of(&fixture.run(&["src", "--stdout"]));
+    let output = fixture.run(&["src", "--stdout", "--readme-first"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let hoisted = stdout_of(&output);
+    assert_eq!(
+        headers(&hoisted),
+        [
+            "src/README.md",
+            "src/Cargo.toml",
+            "src/benches/run.rs",
+            "src/docs/readme.txt",
+            "src/docs/api.md",
+            "src/docs/guide/README",
+            "src/docs/guide/intro.md",
+            "src/main.rs",
+        ]
+    );
+    let tree = |stdout: &str| stdout.split_once("\n\n").map(|(tree, _)| tree.to_string());
+    assert_eq!(tree(&hoisted), tree(&plain));
+}
+
+/// The paths of the files in a rendered tree, top to bottom: a node is a
+/// file when the line after it is not nested under it.
+fn tree_file_paths(tree: &str) -> Vec<String> {
+    let mut lines = tree.lines();
+    let root = lines.next().expect("root line").to_string();
+    let nodes: Vec<(usize, &str)> = lines
+        .map(|line| {
+            let (prefix, name) = line.rsplit_once("─ ").expect("tree line");
+            (prefix.chars().count() / 3, name)
+        })
+        .collect();
+    let mut stack = vec![root];
+    let mut files = Vec::new();
+    for (index, &(depth, name)) in nodes.iter().enumerate() {
+        stack.truncate(depth + 1);
+        stack.push(name.to_string());
+        if nodes.get(index + 1).is_none_or(|&(next, _)| next <= depth) {
+            files.push(stack.join("/"));
+        }
+    }
+    files
+}
+
+#[test]
+fn contents_follow_the_tree_unless_walk_order_is_set() {
+    let fixture = Fixture::new("tree_order");
+    for dir in ["src/a", "src/b"] {
+        fs::create_dir_all(fixture.path().join(dir)).expect("create dir");
+    }
+    for file in [
+        "src/b/z.rs",
+        "src/a-b.rs",
+        "src/a/x.rs",
+        "src/b/a.rs",
+        "src/C.rs",
+    ] {
+        fixture.write(file, b"// file\n");
+    }
+
+    let output = fixture.run(&["src", "--stdout"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    let (tree, body) = stdout.split_once("\n\n").expect("tree then sections");
+    let headers: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("--- ")?.strip_suffix(" ---"))
+        .collect();
+    assert_eq!(headers, tree_file_paths(tree));
+    assert_eq!(
+        headers,
+        [
+            "src/C.rs",
+            "src/a/x.rs",
+            "src/a-b.rs",
+            "src/b/a.rs",
+            "src/b/z.rs",
+            "src/main.rs"
+        ]
+    );
+
+    let walked = stdout_of(&fixture.run(&["src", "--stdout", "--walk-order"]));
+    let mut walked_headers: Vec<&str> = walked
+        .lines()
+        .filter_map(|line| line.strip_prefix("--- ")?.strip_suffix(" ---"))
+        .collect();
+    walked_headers.sort_by_key(|header| std::path::Path::new(*header));
+    assert_eq!(walked_headers, headers);
+}
+
+#[test]
+fn tree_tokens_annotates_files_and_rolls_up_directories() {
+    let fixture = Fixture::new("tree_tokens");
+    fs::create_dir_all(fixture.path().join("src/util")).expect("create dir");
+    fixture.write(
+        "src/util/big.rs",
+        format!("{}\n", "x".repeat(4000)).as_bytes(),
+    );
+    fixture.write("src/data.bin", b"data");
+
+    let output = fixture.run(&[
+        "src",
+        "--stdout",
+        "--tree-tokens",
+        "--exclude-contents",
+        "*.bin",
+    ]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.starts_with(
+            "src           (~1.0k tok)\n\
+             ├─ data.bin   (~0 tok)\n\
+             ├─ main.rs    (~4 tok)\n\
+             └─ util       (~1.0k tok)\n\
+             \x20  └─ big.rs  (~1.0k tok)\n\n--- src/data.bin ---\n"
+        ),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn fit_tokens_drops_whole_files_and_lists_them_at_the_end() {
+    let fixture = Fixture::new("fit_tokens");
+    fs::create_dir_all(fixture.path().join("tests")).expect("create dir");
+    fixture.write("src/big.rs", format!("{}\n", "x".repeat(4000)).as_bytes());
+    fixture.write("tests/cli.rs", format!("{}\n", "t".repeat(800)).as_bytes());
+
+    let largest = fixture.run(&["src", "tests", "--stdout", "--fit-tokens", "500"]);
+    assert!(largest.status.success(), "stderr: {}", stderr_of(&largest));
+    let stdout = stdout_of(&largest);
+    assert!(stdout.contains("├─ big.rs  (dropped)\n"), "{stdout}");
+    assert!(
+        stdout.contains(
+            "--- src/big.rs ---\n<skipped reason=\"budget\" tokens=\"1001\"> dropped to fit --fit-tokens, ~1.0k tokens\n\n"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with(
+            "=== cut by limits ===\n\
+             --fit-tokens 500 (largest-first) cut 1 file; rerun with --fit-tokens 1206 to include it\n\
+             \x20 ~1.0k tokens  src/big.rs  largest remaining file\n\n"
+        ),
+        "{stdout}"
+    );
+    assert!(stdout.contains("--- tests/cli.rs ---\nttt"), "{stdout}");
+
+    let tests_first = fixture.run(&[
+        "src",
+        "tests",
+        "--stdout",
+        "--fit-tokens",
+        "1100",
+        "--fit-strategy",
+        "tests-first",
+    ]);
+    assert!(
+        tests_first.status.success(),
+        "stderr: {}",
+        stderr_of(&tests_first)
+    );
+    let stdout = stdout_of(&tests_first);
+    assert!(
+        stdout.ends_with(
+            "--fit-tokens 1100 (tests-first) cut 1 file; rerun with --fit-tokens 1206 to include it\n\
+             \x20 ~201 tokens  tests/cli.rs  test file\n\n"
+        ),
+        "{stdout}"
+    );
+    assert!(stdout.contains("--- src/big.rs ---\nxxx"), "{stdout}");
+}
+
+#[test]
+fn budget_report_says_what_each_limit_cut_and_how_to_get_it_back() {
+    let fixture = Fixture::new("budget_report");
+    fs::create_dir_all(fixture.path().join("src/docs")).expect("create dir");
+    fs::create_dir_all(fixture.path().join("src/gen")).expect("create dir");
+    fixture.write("src/docs/huge.txt", "h".repeat(3000).as_bytes());
+    fixture.write("src/docs/large.txt", "l".repeat(1500).as_bytes());
+    fixture.write("src/lib.rs", format!("{}\n", "x".repeat(800)).as_bytes());
+    for name in ["a", "b", "c", "d", "e"] {
+        fixture.write(&format!("src/gen/{name}.rs"), b"// generated\n");
+    }
+
+    let limits = [
+        "src",
+        "--stdout",
+        "--max-file-bytes",
+        "1KiB",
+        "--fit-tokens",
+        "100",
+        "--max-per-dir",
+        "2",
+    ];
+    let output = fixture.run(&[&limits[..], &["--report-json", "report.json"]].concat());
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    let (_, report) = stdout.split_once("\n\n=== ").expect("budget report");
+    assert_eq!(
+        report,
+        "cut by limits ===\n\
+         --max-file-bytes 1KiB cut 2 files; rerun with --max-file-bytes 3KiB to include them\n\
+         \x20 2.9 KiB  src/docs/huge.txt\n\
+         \x20 1.5 KiB  src/docs/large.txt\n\
+         --fit-tokens 100 (largest-first) cut 1 file; rerun with --fit-tokens 213 to include it\n\
+         \x20 ~201 tokens  src/lib.rs  largest remaining file\n\
+         --max-per-dir 2 cut 3 files from 1 directory; rerun with --max-per-dir 5 to include them\n\
+         \x20 3 files  src/gen/\n\n"
+    );
+
+    let report: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(fixture.path().join("report.json")).expect("read report"),
+    )
+    .expect("report is JSON");
+    let limits_cut: Vec<&str> = report["budget"]
+        .as_array()
+        .expect("budget array")
+        .iter()
+        .map(|group| group["limit"].as_str().expect("limit"))
+        .collect();
+    assert_eq!(limits_cut, ["max-file-bytes", "fit-tokens", "max-per-dir"]);
+    assert_eq!(report["budget"][0]["suggested"], 3072);
+    assert_eq!(report["budget"][0]["cut"][0]["path"], "src/docs/huge.txt");
+
+    let quiet = fixture.run(&[&limits[..], &["--no-budget-report"]].concat());
+    assert!(quiet.status.success(), "stderr: {}", stderr_of(&quiet));
+    assert!(!stdout_of(&quiet).contains("=== cut by limits"));
+}
+
+#[test]
+fn html_format_writes_one_self_contained_page() {
+    let fixture = Fixture::new("html");
+    fixture.write("src/evil.txt", b"</script><script>alert(1)</script>\n");
+    let output = fixture.run(&["src", "--format", "html", "--out", "report.html"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+
+    let page = fs::read_to_string(fixture.path().join("report.html")).expect("read report.html");
+    assert!(page.starts_with("<!DOCTYPE html>\n"), "{page}");
+    assert!(page.contains("<p class=\"summary\">2 files, "), "{page}");
+    assert!(page.contains("<li><a href=\"#file-0\">evil.txt</a></li>\n"));
+    assert!(page.contains("<section id=\"file-1\">\n<h2><a href=\"#file-1\">src/main.rs</a></h2>"));
+    assert!(page.contains("&lt;/script&gt;&lt;script&gt;alert(1)"));
+    assert!(!page.contains("<script"));
+    assert!(!page.contains("src=\"") && !page.contains("<link"));
+}
+
+#[test]
+fn bare_prints_only_the_single_included_file() {
+    let fixture = fixture_with_binary("bare");
+    for roots in [&["src/main.rs"][..], &["src"][..]] {
+        let output = fixture.run(&[roots, &["--bare"]].concat());
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        assert_eq!(stdout_of(&output), "fn main() {}\n");
+    }
+}
+
+#[test]
+fn bare_rejects_several_files_unless_loose() {
+    let fixture = Fixture::new("bare_many");
+    fixture.write("src/lib.rs", b"pub fn lib() {}\n");
+
+    let strict = fixture.run(&["src", "--bare"]);
+    assert_eq!(strict.status.code(), Some(1));
+    assert!(stdout_of(&strict).is_empty());
+    assert!(
+        stderr_of(&strict).contains(
+            "--bare takes exactly one file, but src/lib.rs and src/main.rs are both included"
+        ),
+        "stderr: {}",
+        stderr_of(&strict)
+    );
+
+    let loose = fixture.run(&["src", "--bare=loose"]);
+    assert!(loose.status.success(), "stderr: {}", stderr_of(&loose));
+    assert_eq!(stdout_of(&loose), stdout_of(&fixture.run(&["src"])));
+}
+
+#[test]
+fn relative_to_gives_the_same_output_from_any_directory() {
+    let fixture = Fixture::new("relative_to");
+    for krate in ["foo", "bar"] {
+        fs::create_dir_all(fixture.path().join("crates").join(krate).join("src"))
+            .expect("create crate dir");
+        fixture.write(
+            &format!("crates/{krate}/src/lib.rs"),
+            format!("// {krate}\n").as_bytes(),
+        );
+    }
+
+    let from_root = fixture.run(&["crates/foo", "crates/bar", "--relative-to", "."]);
+    assert!(
+        from_root.status.success(),
+        "stderr: {}",
+        stderr_of(&from_root)
+    );
+    let from_crate = fixture
+        .command(&[".", "../bar", "--relative-to", "../.."])
+        .current_dir(fixture.path().join("crates/foo"))
+        .output()
+        .expect("failed to run copytree");
+    assert!(
+        from_crate.status.success(),
+        "stderr: {}",
+        stderr_of(&from_crate)
+    );
+
+    let text = stdout_of(&from_root);
+    assert!(
+        text.contains("--- crates/foo/src/lib.rs ---\n// foo\n"),
+        "{text}"
+    );
+    assert!(
+        text.contains("--- crates/bar/src/lib.rs ---\n// bar\n"),
+        "{text}"
+    );
+    assert_eq!(stdout_of(&from_crate), text);
+
+    let outside = fixture
+        .command(&["../bar", "--relative-to", "."])
+        .current_dir(fixture.path().join("crates/foo"))
+        .output()
+        .expect("failed to run copytree");
+    assert!(outside.status.success(), "stderr: {}", stderr_of(&outside));
+    assert!(stderr_of(&outside)
+        .contains("../bar is outside --relative-to .; its paths are shown in full"));
+    let bar = fixture
+        .path()
+        .join("crates/bar/src/lib.rs")
+        .canonicalize()
+        .expect("canonical path");
+    assert!(stdout_of(&outside).contains(&format!("--- {} ---", bar.display())));
+}
+
+#[test]
+fn reproducible_output_is_identical_across_locales_time_zones_and_directories() {
+    let fixture = Fixture::new("reproducible");
+    fs::create_dir_all(fixture.path().join("crates/foo/src")).expect("create crate dir");
+    fixture.write("crates/foo/src/lib.rs", b"pub fn foo() {}\r\n");
+    fixture.write("crates/foo/src/B.rs", b"// upper case sorts first\n");
+    fixture.write("crates/foo/src/é.rs", b"// non-ASCII sorts last\n");
+    fixture.write_user_config("exclude = [\"*.lock\"]\n");
+
+    let snapshot = |name: &str, dir: &str, args: &[&str], locale: [(&str, &str); 3]| {
+        let out = fixture.path().join(name);
+        let out = out.to_str().expect("UTF-8 path");
+        let output = fixture
+            .command(&[args, &["--reproducible", "--provenance", "--out", out]].concat())
+            .current_dir(fixture.path().join(dir))
+            .envs(locale)
+            .output()
+            .expect("failed to run copytree");
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        fs::read(out).expect("snapshot written")
+    };
+    let first = snapshot(
+        "first.txt",
+        ".",
+        &["crates/foo", "--relative-to", "."],
+        [("TZ", "UTC"), ("LANG", "C"), ("LC_ALL", "C")],
+    );
+    let second = snapshot(
+        "second.txt",
+        "crates/foo/src",
+        &["..", "--relative-to", "../../.."],
+        [
+            ("TZ", "Asia/Tokyo"),
+            ("LANG", "de_DE.UTF-8"),
+            ("LC_ALL", "de_DE.UTF-8"),
+        ],
+    );
+    assert!(first == second, "snapshots differ");
+
+    let text = String::from_utf8(first).expect("snapshot is UTF-8");
+    let (provenance, body) = text.split_once('\n').expect("provenance line");
+    // The fixture's home is inside the base, so the user config is named from there.
+    assert_eq!(
+        provenance,
+        format!(
+            "# copytree-provenance version={} args=\"--exclude '*.lock' --relative-to . --reproducible crates/foo\" config=home/.config/copytree/config.toml",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+    assert!(
+        body.starts_with("crates/foo\n└─ src\n   ├─ B.rs\n   ├─ lib.rs\n   └─ é.rs\n"),
+        "{body}"
+    );
+    assert!(body.contains("pub fn foo() {}\r\n\n\n"));
+}
+
+#[test]
+fn encoded_output_decodes_to_the_plain_output_byte_for_byte() {
+    let fixture = Fixture::new("encode");
+    let line = "é 日本 \u{fffd}\u{fffd} replaced\ttab \r\n";
+    fixture.write(
+        "src/big.txt",
+        line.repeat(3 * 1024 * 1024 / line.len()).as_bytes(),
+    );
+    fixture.write("src/blob.bin", b"\xff\xfe\x00\xc3\x28");
+    fixture.write("src/tail.txt", b"no newline at the end ");
+
+    let walk = ["src", "--max-file-bytes", "0"];
+    let plain = fixture.run(&[&walk[..], &["--out", "ctx.txt"]].concat());
+    assert!(plain.status.success(), "stderr: {}", stderr_of(&plain));
+    let encoded = fixture.run(&[&walk[..], &["--encode", "base64", "--out", "ctx.b64"]].concat());
+    assert!(encoded.status.success(), "stderr: {}", stderr_of(&encoded));
+    let plain = fs::read(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    assert!(plain.len() > 3_000_000);
+
+    let text = fs::read_to_string(fixture.path().join("ctx.b64")).expect("encoded is text");
+    let (preamble, body) = text.split_once('\n').expect("preamble line");
+    assert!(preamble.contains("copytree decode"), "{preamble}");
+    assert!(body.lines().all(|line| line.len() <= 76 && line.is_ascii()));
+
+    let decoded = fixture.run(&["decode", "ctx.b64"]);
+    assert!(decoded.status.success(), "stderr: {}", stderr_of(&decoded));
+    assert!(decoded.stdout == plain, "decoded output differs");
+
+    let mut command = fixture.command(&["decode"]);
+    let input = fs::File::open(fixture.path().join("ctx.b64")).expect("open ctx.b64");
+    let from_stdin = command.stdin(input).output().expect("run decode");
+    assert!(from_stdin.stdout == plain, "decoded stdin differs");
+}
+
+#[test]
+fn decode_names_damaged_input_and_encode_refuses_append() {
+    let fixture = Fixture::new("encode_errors");
+    fixture.write("ctx.b64", b"copytree output in base64\nZm9v*mFy\n");
+    let decoded = fixture.run(&["decode", "ctx.b64"]);
+    assert_eq!(decoded.status.code(), Some(1));
+    assert!(stderr_of(&decoded).contains("ctx.b64 is not valid copytree --encode base64 output"));
+
+    let appended = fixture.run(&["--encode", "base64", "--out", "ctx.txt", "--append"]);
+    assert_eq!(appended.status.code(), Some(1));
+    assert!(stderr_of(&appended).contains("--encode cannot be combined with --append"));
+}
+
+#[test]
+fn restore_refuses_to_overwrite_without_force_and_dry_run_writes_nothing() {
+    let fixture = Fixture::new("restore_force");
+    let copy = fixture.run(&["src", "--out", "ctx.txt"]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+
+    let dry_run = fixture.run(&["restore", "ctx.txt", "--dest", "out", "--dry-run"]);
+    assert!(dry_run.status.success(), "stderr: {}", stderr_of(&dry_run));
+    assert!(stdout_of(&dry_run).contains("main.rs (13 bytes)"));
+    assert!(!fixture.path().join("out").exists());
+
+    let refused = fixture.run(&["restore", "ctx.txt", "--dest", "."]);
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(stderr_of(&refused).contains("--force"));
+
+    let forced = fixture.run(&["restore", "ctx.txt", "--dest", ".", "--force"]);
+    assert!(forced.status.success(), "stderr: {}", stderr_of(&forced));
+}
+
+#[test]
+fn restore_rejects_path_traversal() {
+    let fixture = Fixture::new("restore_traversal");
+    fixture.write("evil.txt", b"--- ../escaped.txt ---\npwned\n\n");
+
+    let output = fixture.run(&["restore", "evil.txt", "--dest", "out"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr_of(&output).contains(".."));
+    assert!(!fixture.path().join("escaped.txt").exists());
+}
+
+#[test]
+fn user_config_supplies_defaults_under_cli_flags() {
+    let fixture = Fixture::new("user_config");
+    fixture.write("src/big.txt", &[b'a'; 200]);
+    fixture.write_user_config("max_file_bytes = 100\nno_tree = true\n");
+
+    let configured = stdout_of(&fixture.run(&["src"]));
+    assert!(configured.starts_with("--- "), "{configured}");
+    assert!(configured.contains("limit=\"100\">"));
+
+    let overridden = stdout_of(&fixture.run(&["src", "--max-file-bytes", "1000"]));
+    assert!(overridden.contains("--- src/big.txt ---\naaaa"));
+
+    let skipped = stdout_of(&fixture.run(&["src", "--no-config"]));
+    assert!(skipped.starts_with("src\n"));
+    assert!(skipped.contains("--- src/big.txt ---\naaaa"));
+}
+
+#[test]
+fn invalid_user_config_names_file_and_key() {
+    let fixture = Fixture::new("user_config_invalid");
+    fixture.write_user_config("max_file_bytes = \"lots\"\n");
+
+    let output = fixture.run(&["src"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = stderr_of(&output);
+    assert!(
+        stderr.contains("config.toml:1: invalid `max_file_bytes`"),
+        "{stderr}"
+    );
+    assert!(fixture.run(&["src", "--no-config"]).status.success());
+}
+
+#[test]
+fn config_path_prints_the_expected_location() {
+    let fixture = Fixture::new("config_path");
+    let output = fixture.run(&["config", "path"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let expected = fixture.home().join(".config/copytree/config.toml");
+    assert_eq!(stdout_of(&output).trim_end(), expected.to_string_lossy());
+}
+
+#[test]
+fn project_config_sits_between_user_config_and_cli() {
+    let fixture = Fixture::new("project_config");
+    fs::create_dir_all(fixture.path().join(".git")).expect("create .git");
+    fs::create_dir_all(fixture.path().join("src/fixtures")).expect("create fixtures");
+    fixture.write("src/fixtures/data.json", b"{}\n");
+    fixture.write("src/big.txt", &[b'a'; 200]);
+    fixture.write("README.md", b"# Readme\n");
+    fixture.write_user_config("max_file_bytes = 50\nexclude = [\"**/*.md\"]\n");
+    fixture.write(
+        ".copytree.toml",
+        b"exclude = [\"**/fixtures/**\"]\nmax_file_bytes = 100\nalways_include = [\"README.md\"]\n",
+    );
+
+    let output = fixture.run(&["src"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(!stdout.contains("data.json"), "{stdout}");
+    assert!(stdout.contains("limit=\"100\">"));
+    assert!(stdout.contains("--- README.md ---\n# Readme\n"), "{stdout}");
+
+    let overridden = stdout_of(&fixture.run(&["src", "--max-file-bytes", "0"]));
+    assert!(overridden.contains("--- src/big.txt ---\naaaa"));
+}
+
+#[test]
+fn always_include_rescues_ignored_hidden_and_excluded_files() {
+    let fixture = Fixture::new("always_include_globs");
+    fs::create_dir_all(fixture.path().join(".git")).expect("create .git");
+    fs::create_dir_all(fixture.path().join(".github/workflows")).expect("create workflows");
+    fs::create_dir_all(fixture.path().join("build")).expect("create build");
+    fixture.write(".gitignore", b"build/\n");
+    fixture.write("build/Cargo.toml", b"[package]\n");
+    fixture.write("build/other.txt", b"ignored\n");
+    fixture.write(".github/workflows/ci.yml", b"on: push\n");
+    fixture.write("README.md", b"# Readme\n");
+    fixture.write("big.md", &[b'a'; 200]);
+
+    let args = [
+        ".",
+        "--exclude",
+        "**/*.md",
+        "--max-file-bytes",
+        "100",
+        "--always-include",
+        "**/Cargo.toml",
+        "--always-include",
+        ".github/workflows/*.yml",
+        "--always-include",
+        "*.md",
+    ];
+    let output = fixture.run(&args);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.contains("--- ./build/Cargo.toml ---\n[package]\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("--- ./.github/workflows/ci.yml ---\non: push\n"));
+    assert!(stdout.contains("--- ./README.md ---\n# Readme\n"));
+    assert!(stdout.contains("--- ./big.md ---\n<skipped reason=\"too-large\" size=\"200\""));
+    assert!(!stdout.contains("other.txt"));
+    assert_eq!(stdout.matches("--- ./src/main.rs ---").count(), 1);
+
+    fixture.write(".copytree.toml", b"always_include = [\"build/*.toml\"]\n");
+    let from_config = stdout_of(&fixture.run(&["."]));
+    assert!(
+        from_config.contains("--- ./build/Cargo.toml ---\n[package]\n"),
+        "{from_config}"
+    );
+}
+
+#[test]
+fn no_ignore_walks_ignored_and_hidden_files() {
+    let fixture = Fixture::new("no_ignore");
+    fs::create_dir_all(fixture.path().join(".git")).expect("create .git");
+    fs::create_dir_all(fixture.path().join(".hidden")).expect("create .hidden");
+    fixture.write(".git/HEAD", b"ref: refs/heads/main\n");
+    fixture.write(".gitignore", b"ignored.txt\n");
+    fixture.write(".ignore", b"scratch.txt\n");
+    fixture.write("ignored.txt", b"ignored\n");
+    fixture.write("scratch.txt", b"scratch\n");
+    fixture.write(".env", b"TOKEN=1\n");
+    fixture.write(".hidden/notes.md", b"notes\n");
+    let all = [
+        "ignored.txt",
+        "scratch.txt",
+        ".env",
+        "notes.md",
+        ".gitignore",
+        ".ignore",
+    ];
+
+    let default = stdout_of(&fixture.run(&["tree"]));
+    assert!(all.iter().all(|name| !default.contains(name)), "{default}");
+
+    let narrow = stdout_of(&fixture.run(&["tree", "--no-gitignore"]));
+    assert!(narrow.contains("ignored.txt"), "{narrow}");
+    assert!(!narrow.contains("scratch.txt") && !narrow.contains(".env"));
+
+    let output = fixture.run(&["tree", "--no-ignore", "--no-gitignore"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let everything = stdout_of(&output);
+    assert!(
+        all.iter().all(|name| everything.contains(name)),
+        "{everything}"
+    );
+    assert!(!everything.contains("HEAD"), "{everything}");
+
+    let copied = stdout_of(&fixture.run(&[".", "--no-ignore", "--exclude", ".env"]));
+    assert!(!copied.contains(".env"), "{copied}");
+    assert!(copied.contains("--- ./scratch.txt ---\nscratch\n"));
+}
+
+#[test]
+fn include_git_dir_walks_metadata_but_not_objects() {
+    let fixture = Fixture::new("include_git_dir");
+    for dir in [".git/hooks", ".git/objects/pack", ".git/refs/heads"] {
+        fs::create_dir_all(fixture.path().join(dir)).expect("create .git dirs");
+    }
+    fixture.write(".git/HEAD", b"ref: refs/heads/main\n");
+    fixture.write(".git/config", b"[core]\n\tbare = false\n");
+    fixture.write(".git/hooks/pre-commit", b"#!/bin/sh\nexit 0\n");
+    fixture.write(".git/refs/heads/main", b"0123abcd\n");
+    fixture.write(
+        ".git/objects/pack/pack-1.pack",
+        b"PACK\0\0\0\x02\x78\x9c\xff\xfe",
+    );
+    fixture.write(".env", b"TOKEN=1\n");
+
+    let output = fixture.run(&[".", "--include-git-dir"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.contains("--- ./.git/config ---\n[core]\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("--- ./.git/hooks/pre-commit ---\n#!/bin/sh\n"));
+    assert!(stdout.contains("--- ./.git/HEAD ---\nref: refs/heads/main\n"));
+    assert!(!stdout.contains("objects"), "{stdout}");
+    assert!(!stdout.contains(".env"), "{stdout}");
+
+    let full = stdout_of(&fixture.run(&[".", "--include-git-dir=full"]));
+    assert!(
+        full.contains("--- ./.git/objects/pack/pack-1.pack ---\n<skipped reason=\"binary\""),
+        "{full}"
+    );
+
+    let default = stdout_of(&fixture.run(&["."]));
+    assert!(!default.contains(".git"), "{default}");
+}
+
+#[test]
+fn pruned_directories_contribute_nothing_even_when_always_included() {
+    let fixture = Fixture::new("prune");
+    fs::create_dir_all(fixture.path().join("web/node_modules/pkg")).expect("create modules");
+    fixture.write("web/index.js", b"main();\n");
+    fixture.write("web/node_modules/pkg/package.json", b"{}\n");
+
+    let output = fixture.run(&[
+        ".",
+        "--prune",
+        "node_modules",
+        "--always-include",
+        "web/node_modules/pkg/*.json",
+    ]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(stdout.contains("--- ./web/index.js ---"), "{stdout}");
+    assert!(!stdout.contains("node_modules"), "{stdout}");
+    assert!(
+        stderr_of(&output).contains(
+            "--always-include web/node_modules/pkg/*.json points inside web/node_modules"
+        ),
+        "{}",
+        stderr_of(&output)
+    );
+
+    let excluded = stdout_of(&fixture.run(&[".", "--exclude-contents", "**/node_modules/**"]));
+    assert!(excluded.contains("package.json"), "{excluded}");
+}
+
+#[test]
+fn max_per_dir_keeps_the_first_files_and_notes_the_rest() {
+    let fixture = Fixture::new("max_per_dir");
+    fs::create_dir_all(fixture.path().join("migrations/archive")).expect("create migrations");
+    for index in 1..=1_215 {
+        fixture.write(&format!("migrations/{:04}.sql", index), b"select 1;\n");
+    }
+    fixture.write("migrations/archive/old.sql", b"select 0;\n");
+
+    let output = fixture.run(&["tree", "migrations", "--max-per-dir", "3"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert_eq!(
+        stdout_of(&output),
+        "migrations\n\
+         ├─ 0001.sql\n\
+         ├─ 0002.sql\n\
+         ├─ 0003.sql\n\
+         ├─ archive\n\
+         │  └─ old.sql\n\
+         └─ … 1,212 more files in this directory\n"
+    );
+
+    let copied =
+        stdout_of(&fixture.run(&["migrations", "migrations/0100.sql", "--max-per-dir", "3"]));
+    assert_eq!(copied.matches("--- migrations/").count(), 5, "{copied}");
+    assert!(copied.contains("--- migrations/0100.sql ---\nselect 1;\n"));
+    assert!(!copied.contains("0004.sql"));
+}
+
+#[test]
+fn sample_picks_reproducible_files_and_keeps_always_included_ones() {
+    let fixture = Fixture::new("sample");
+    fs::create_dir_all(fixture.path().join("data")).expect("create data");
+    for index in 0..200 {
+        fixture.write(&format!("data/{:03}.txt", index), b"row\n");
+    }
+    fixture.write("README.md", b"# Readme\n");
+    let sections = |stdout: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .filter(|line| line.starts_with("--- "))
+            .map(str::to_string)
+            .collect()
+    };
+    let args = [
+        ".",
+        "--sample",
+        "5",
+        "--seed",
+        "42",
+        "--always-include",
+        "README.md",
+        "--exclude",
+        "data/00*",
+    ];
+
+    let first = fixture.run(&args);
+    assert!(first.status.success(), "stderr: {}", stderr_of(&first));
+    let picked = sections(&stdout_of(&first));
+    assert_eq!(picked.len(), 6, "{picked:?}");
+    assert!(picked.contains(&"--- ./README.md ---".to_string()));
+    assert!(picked.iter().all(|section| !section.contains("data/00")));
+    assert!(
+        stderr_of(&first).contains("to stdout, showing 5 of 191 files, seed 42."),
+        "{}",
+        stderr_of(&first)
+    );
+    // `src/main.rs` from the fixture is a candidate too: 190 data files + 1.
+    assert_eq!(sections(&stdout_of(&fixture.run(&args))), picked);
+
+    let tree = stdout_of(&fixture.run(&["tree", "--sample", "5", "--seed", "42"]));
+    let files = tree
+        .lines()
+        .filter(|line| [".txt", ".rs", ".md"].iter().any(|ext| line.ends_with(ext)))
+        .count();
+    assert_eq!(files, 5, "{tree}");
+
+    let stats = stdout_of(&fixture.run(&["stats", "--sample", "3", "--seed", "1"]));
+    assert!(stats.starts_with("Included: 3 files"), "{stats}");
+    assert!(
+        stats.ends_with("Sampled: 3 of 202 files, seed 1\n"),
+        "{stats}"
+    );
+}
+
+#[test]
+fn type_filters_by_detected_kind_not_extension() {
+    let fixture = Fixture::new("type");
+    fixture.write("src/disguised.txt", b"\x89PNG\r\n\x1a\n\x00\x00");
+    fixture.write("src/deploy", b"#!/bin/sh\necho hi\n");
+    fixture.write("src/notes.txt", b"\xff\xfen\x00o\x00t\x00e\x00");
+    fixture.write("src/Cargo.toml", b"[package]\n");
+
+    let code = stdout_of(&fixture.run(&["tree", "src", "--type", "code"]));
+    assert_eq!(code, "src\n├─ deploy\n└─ main.rs\n");
+
+    let text = fixture.run(&["src", "--type", "text"]);
+    assert!(text.status.success(), "stderr: {}", stderr_of(&text));
+    let stdout = stdout_of(&text);
+    assert!(stdout.contains("--- src/notes.txt ---\nnote\n"), "{stdout}");
+    assert!(stdout.contains("--- src/Cargo.toml ---"), "{stdout}");
+    assert!(!stdout.contains("disguised.txt"), "{stdout}");
+
+    let images = fixture.run(&["src", "--type", "image", "--verbose", "--out", "ctx.txt"]);
+    let stderr = stderr_of(&images);
+    assert!(
+        stderr.contains("Described src/disguised.txt (image: PNG, 10 B)"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("main.rs"), "{stderr}");
+}
+
+#[test]
+fn list_archives_lists_entries_instead_of_skipping() {
+    let fixture = Fixture::new("list_archives");
+    let archives = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/archives");
+    let zip = fs::read(archives.join("sample.zip")).expect("read zip fixture");
+    fixture.write("src/sample.zip", &zip);
+    fixture.write("src/broken.zip", &zip[..zip.len() - 30]);
+    fixture.write(
+        "src/sample.tar",
+        &fs::read(archives.join("sample.tar")).expect("read tar fixture"),
+    );
+
+    let plain = stdout_of(&fixture.run(&["src"]));
+    assert!(
+        plain.contains(
+            "--- src/sample.zip ---\n<skipped reason=\"binary\" kind=\"archive\"> archive file\n"
+        ),
+        "{plain}"
+    );
+
+    let output = fixture.run(&["src", "--list-archives=2", "--verbose"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.contains(
+            "--- src/sample.zip ---\n<skipped reason=\"archive\" format=\"zip\" size=\"462\"> archive: zip, 4 entries, 462 B\ndocs/\ndocs/readme.md (10 B)\n… 2 more entries\n\n"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "--- src/sample.tar ---\n<skipped reason=\"archive\" format=\"tar\" size=\"10240\"> archive: tar, first 2 entries, 10 KiB\ndocs/\ndocs/readme.md (10 B)\n… more entries\n\n"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "--- src/broken.zip ---\n<skipped reason=\"bad-archive\"> archive file, not listed: no end of central directory\n"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stderr_of(&output).contains("Skipped src/sample.zip (archive: zip, 4 entries, 462 B)"),
+        "{}",
+        stderr_of(&output)
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn files_past_the_windows_path_limit_are_read() {
+    let fixture = Fixture::new("long_paths");
+    let mut relative = std::path::PathBuf::from("src");
+    while fixture.path().join(&relative).as_os_str().len() < 300 {
+        relative.push("d".repeat(40));
+    }
+    fs::create_dir_all(fixture.path().join(&relative)).expect("create deep dirs");
+    fs::write(
+        fixture.path().join(&relative).join("deep.rs"),
+        b"fn deep() {}\n",
+    )
+    .expect("write deep file");
+
+    let output = fixture.run(&["src", "--stdout"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let header = format!(
+        "--- {}/deep.rs ---\nfn deep() {{}}\n",
+        relative.to_string_lossy().replace('\\', "/")
+    );
+    assert!(
+        stdout_of(&output).contains(&header),
+        "{}",
+        stdout_of(&output)
+    );
+}
+
+#[test]
+fn names_differing_only_in_case_are_flagged() {
+    let fixture = Fixture::new("case_collision");
+    fixture.write("src/README.md", b"upper\n");
+    fixture.write("src/Readme.md", b"mixed\n");
+    if fs::read(fixture.path().join("src/README.md")).expect("read") != b"upper\n" {
+        // The fixture sits on a case-insensitive file system.
+        return;
+    }
+
+    let output = fixture.run(&["tree", "src"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert_eq!(
+        stdout_of(&output),
+        "src\n├─ README.md  [case-collision]\n├─ Readme.md  [case-collision]\n└─ main.rs\n"
+    );
+    assert!(
+        stderr_of(&output).contains(
+            "Warning: names differ only in case and clash on case-insensitive file systems: \
+             src/README.md, src/Readme.md"
+        ),
+        "{}",
+        stderr_of(&output)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn max_symlink_depth_bounds_each_path_through_linked_directories() {
+    use std::os::unix::fs::symlink;
+
+    // `src/start` leads into a chain of ten directories, each holding a
+    // file and a link to the next; `src/late` joins the chain at `d9`.
+    let fixture = Fixture::new("symlink_depth");
+    for n in 1..=10 {
+        fs::create_dir_all(fixture.path().join(format!("chain/d{n}"))).expect("create dir");
+        fixture.write(&format!("chain/d{n}/f{n}.txt"), b"text\n");
+        if n < 10 {
+            symlink(
+                format!("../d{}", n + 1),
+                fixture.path().join(format!("chain/d{n}/next")),
+            )
+            .expect("link next");
+        }
+    }
+    symlink("../chain/d1", fixture.path().join("src/start")).expect("link start");
+    symlink("../chain/d9", fixture.path().join("src/late")).expect("link late");
+
+    let files = |stdout: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("--- ")?.strip_suffix(" ---"))
+            .map(str::to_string)
+            .collect()
+    };
+    let unlinked = stdout_of(&fixture.run(&["src", "--stdout"]));
+    assert_eq!(files(&unlinked), ["src/main.rs"]);
+
+    let output = fixture.run(&["src", "--stdout", "--max-symlink-depth", "3"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert_eq!(
+        files(&stdout),
+        [
+            "src/late/f9.txt",
+            "src/late/next/f10.txt",
+            "src/main.rs",
+            "src/start/f1.txt",
+            "src/start/next/f2.txt",
+            "src/start/next/next/f3.txt",
+            "src/start/next/next/next",
+        ]
+    );
+    assert!(
+        stdout.contains(
+            "--- src/start/next/next/next ---\n<skipped reason=\"symlink-depth\" limit=\"3\"> \
+             symlinked directory not entered, past --max-symlink-depth 3\n"
+        ),
+        "{stdout}"
+    );
+    assert!(stdout.contains("      └─ next -> ../d4\n"), "{stdout}");
+}
+
+#[cfg(unix)]
+#[test]
+fn links_to_an_enclosing_directory_are_not_entered() {
+    use std::os::unix::fs::symlink;
+
+    let fixture = Fixture::new("symlink_loop");
+    fs::create_dir_all(fixture.path().join("src/nested")).expect("create dir");
+    fixture.write("src/nested/lib.rs", b"pub fn lib() {}\n");
+    symlink("..", fixture.path().join("src/nested/up")).expect("link up");
+    symlink(".", fixture.path().join("src/here")).expect("link here");
+
+    let output = fixture.run(&["src", "--stdout", "--max-symlink-depth", "8"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.starts_with(
+            "src\n├─ here -> .\n├─ main.rs\n└─ nested\n   ├─ lib.rs\n   └─ up -> ..\n"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "--- src/nested/up ---\n<skipped reason=\"symlink-loop\" target=\"..\"> \
+             symlinked directory not entered, leads back to an enclosing directory -> ..\n"
+        ),
+        "{stdout}"
+    );
+    assert_eq!(stdout.matches("pub fn lib() {}").count(), 1, "{stdout}");
+}
+
+#[cfg(windows)]
+#[test]
+fn junctions_are_treated_like_symlinked_directories() {
+    let fixture = Fixture::new("junction");
+    fs::create_dir_all(fixture.path().join("store/pkg")).expect("create dir");
+    fixture.write("store/pkg/index.js", b"module.exports = 1;\n");
+    let made = std::process::Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(fixture.path().join("src/pkg"))
+        .arg(fixture.path().join("store/pkg"))
+        .output()
+        .expect("run mklink");
+    assert!(made.status.success(), "mklink: {:?}", made);
+    let status = std::process::Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(fixture.path().join("src/loop"))
+        .arg(fixture.path().join("src"))
+        .status()
+        .expect("run mklink");
+    assert!(status.success());
+
+    let plain = stdout_of(&fixture.run(&["src", "--stdout"]));
+    assert!(!plain.contains("index.js"), "{plain}");
+    assert!(!plain.contains("src/loop"), "{plain}");
+
+    let followed = stdout_of(&fixture.run(&["src", "--stdout", "--max-symlink-depth", "4"]));
+    assert!(
+        followed.contains("--- src/pkg/index.js ---\nmodule.exports = 1;\n"),
+        "{followed}"
+    );
+    assert!(
+        followed.contains("--- src/loop ---\n<skipped reason=\"symlink-loop\""),
+        "{followed}"
+    );
+    assert_eq!(followed.matches("fn main() {}").count(), 1, "{followed}");
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_contents_are_emitted_once_per_target() {
+    use std::os::unix::fs::symlink;
+
+    let fixture = Fixture::new("symlinks");
+    fixture.write("src/shared.rs", b"pub fn shared() {}\n");
+    symlink("shared.rs", fixture.path().join("src/alias.rs")).expect("link alias");
+    symlink("shared.rs", fixture.path().join("src/other.rs")).expect("link other");
+    symlink("missing.rs", fixture.path().join("src/gone.rs")).expect("link gone");
+
+    let output = fixture.run(&["src"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.starts_with(
+            "src\n├─ alias.rs -> shared.rs\n├─ gone.rs -> missing.rs\n├─ main.rs\n├─ other.rs -> shared.rs\n└─ shared.rs\n"
+        ),
+        "{stdout}"
+    );
+    // `alias.rs` sorts before the file it links to, which still carries
+    // the content; both links point at it.
+    assert!(
+        stdout.contains("--- src/shared.rs ---\npub fn shared() {}\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("--- src/alias.rs ---\n<skipped reason=\"same-content\" first=\"src/shared.rs\"> same content as src/shared.rs (symlink)\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("--- src/other.rs ---\n<skipped reason=\"same-content\" first=\"src/shared.rs\"> same content as src/shared.rs (symlink)\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("--- src/gone.rs ---\n<skipped reason=\"broken-symlink\" target=\"missing.rs\"> broken symlink -> missing.rs\n"),
+        "{stdout}"
+    );
+    assert_eq!(stdout.matches("pub fn shared() {}").count(), 1, "{stdout}");
+
+    let strict = fixture.run(&["src", "--fail-on-skip"]);
+    assert_eq!(strict.status.code(), Some(2), "only the broken link counts");
+}
+
+#[test]
+fn profiles_are_listed_and_selected_by_name() {
+    let fixture = Fixture::new("profiles");
+    fixture.write("src/big.txt", &[b'a'; 200]);
+    fixture.write_user_config(
+        "[profile.full]\nmax_file_bytes = 0\n\n[profile.api]\nmax_file_bytes = 10\nno_tree = true\n",
+    );
+
+    let listed = fixture.run(&["profiles"]);
+    assert!(listed.status.success(), "stderr: {}", stderr_of(&listed));
+    assert_eq!(
+        stdout_of(&listed),
+        "api   max_file_bytes = 10, no_tree = true\nfull  max_file_bytes = 0\n"
+    );
+
+    let api = stdout_of(&fixture.run(&["src", "--profile", "full", "--profile", "api"]));
+    assert!(api.starts_with("--- "), "{api}");
+    assert!(api.contains("limit=\"10\">"));
+
+    let unknown = fixture.run(&["src", "--profile", "tiny"]);
+    assert_eq!(unknown.status.code(), Some(1));
+    assert!(stderr_of(&unknown).contains("available profiles: api, full"));
+}
+
+#[test]
+fn env_vars_sit_under_config_files() {
+    let fixture = Fixture::new("env_vars");
+    fixture.write("src/big.txt", &[b'a'; 200]);
+
+    let from_env = fixture.run_with_env(&["src"], &[("COPYTREE_MAX_FILE_BYTES", "100")]);
+    assert!(stdout_of(&from_env).contains("limit=\"100\">"));
+
+    fixture.write_user_config("max_file_bytes = 150\n");
+    let from_config = fixture.run_with_env(&["src"], &[("COPYTREE_MAX_FILE_BYTES", "100")]);
+    assert!(stdout_of(&from_config).contains("limit=\"150\">"));
+
+    let invalid = fixture.run_with_env(&["src"], &[("COPYTREE_MAX_FILE_BYTES", "lots")]);
+    assert_eq!(invalid.status.code(), Some(1));
+    assert!(stderr_of(&invalid).contains("COPYTREE_MAX_FILE_BYTES"));
+}
+
+#[test]
+fn bare_invocation_and_copy_subcommand_produce_the_same_output() {
+    let copy_with = |label: &str, args: &[&str]| {
+        let fixture = Fixture::new(label);
+        fs::create_dir_all(fixture.path().join("target")).expect("create target");
+        fixture.write("target/out.txt", b"built\n");
+        let output = fixture.run(args);
+        assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+        fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt")
+    };
+
+    let bare = copy_with("bare", &[".", "-x", "target/**", "--out", "ctx.txt"]);
+    let copied = copy_with(
+        "copy_subcommand",
+        &["copy", ".", "-x", "target/**", "--out", "ctx.txt"],
+    );
+    assert!(bare.contains("--- ./src/main.rs ---\nfn main() {}\n"));
+    assert!(!bare.contains("target"), "{bare}");
+    assert_eq!(bare, copied);
+}
+
+#[test]
+fn headers_use_forward_slashes_unless_native_separators_is_set() {
+    let fixture = Fixture::new("separators");
+    fs::create_dir_all(fixture.path().join("src/nested")).expect("create nested");
+    fixture.write("src/nested/lib.rs", b"pub fn lib() {}\n");
+
+    let output = fixture.run(&["src", "--stdout"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert!(stdout_of(&output).contains("--- src/nested/lib.rs ---\n"));
+
+    let output = fixture.run(&["src", "--stdout", "--native-separators"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let native = format!("--- src{0}nested{0}lib.rs ---\n", std::path::MAIN_SEPARATOR);
+    assert!(stdout_of(&output).contains(&native));
+}
+
+#[test]
+fn tree_subcommand_prints_only_the_tree() {
+    let fixture = Fixture::new("tree_subcommand");
+    let output = fixture.run(&["tree", "src"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert_eq!(stdout_of(&output), "src\n└─ main.rs\n");
+    assert_eq!(stderr_of(&output), "");
+}
+
+#[cfg(unix)]
+#[test]
+fn names_are_ordered_by_their_bytes_and_never_merged() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // `é` composed and decomposed, and names that are not UTF-8 and read
+    // the same once made text.
+    let fixture = Fixture::new("byte_order");
+    let names: [&[u8]; 8] = [
+        b"f.txt",
+        b"\xc3\xa9.txt",
+        b"e\xcc\x81.txt",
+        b"Z.txt",
+        b"bad\xff.txt",
+        b"bad\xfe.txt",
+        b"dir\xff/a.txt",
+        b"dir\xfe/a.txt",
+    ];
+    for (index, name) in names.iter().enumerate() {
+        let path = fixture.path().join("src").join(OsStr::from_bytes(name));
+        fs::create_dir_all(path.parent().expect("parent")).expect("create dir");
+        fs::write(path, format!("file {index}\n")).expect("write file");
+    }
+
+    let output = fixture.run(&["src", "--stdout"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let text = stdout_of(&output);
+    assert_eq!(stdout_of(&fixture.run(&["src", "--stdout"])), text);
+    let lines: Vec<&str> = text.lines().take(13).collect();
+    assert_eq!(
+        lines,
+        [
+            "src",
+            "├─ Z.txt",
+            "├─ bad\u{fffd}.txt",
+            "├─ bad\u{fffd}.txt",
+            "├─ dir\u{fffd}",
+            "│  └─ a.txt",
+            "├─ dir\u{fffd}",
+            "│  └─ a.txt",
+            "├─ e\u{301}.txt",
+            "├─ f.txt",
+            "├─ main.rs",
+            "└─ \u{e9}.txt",
+            "",
+        ]
+    );
+    let bodies: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("file "))
+        .collect();
+    assert_eq!(bodies, ["3", "5", "4", "7", "6", "2", "0", "1"]);
+    assert!(!text.contains("[case-collision]"), "{text}");
+    assert!(
+        !stderr_of(&output).contains("differ only in case"),
+        "{}",
+        stderr_of(&output)
+    );
+
+    let stats = stdout_of(&fixture.run(&["stats", "src"]));
+    assert!(
+        stats.contains("Total: 9 files, 69 B in 3 directories\n"),
+        "{stats}"
+    );
+    assert_eq!(stats.matches("src/dir\u{fffd}/a.txt").count(), 2, "{stats}");
+}
+
+#[test]
+fn stats_subcommand_counts_without_copying() {
+    let fixture = Fixture::new("stats_subcommand");
+    fixture.write("src/big.txt", &[b'a'; 200]);
+    fs::create_dir_all(fixture.path().join("src/util")).expect("create dir");
+    fixture.write("src/util/mod.rs", b"pub mod io;\n");
+    fixture.write("src/Cargo.lock", b"lock");
+    let output = fixture.run(&[
+        "stats",
+        "src",
+        "--max-file-bytes",
+        "100",
+        "--exclude-contents",
+        "*.lock",
+    ]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stats = stdout_of(&output);
+    assert!(
+        stats.starts_with(
+            "Included: 2 files, 25 B\n\
+             Skipped: 2 files, 204 B (1 excluded, 1 too-large)\n\
+             Total: 4 files, 229 B in 2 directories\n"
+        ),
+        "{stats}"
+    );
+    assert!(
+        stats.contains("\nBy extension:\n  .txt    1 file  200 B\n  .rs    2 files   25 B\n  .lock   1 file    4 B\n"),
+        "{stats}"
+    );
+    assert!(
+        stats.contains("\nLargest files:\n  200 B  text/plain  src/big.txt (too-large)\n"),
+        "{stats}"
+    );
+
+    let json = fixture.run(&["stats", "src", "--max-file-bytes", "100", "--json"]);
+    let report: serde_json::Value =
+        serde_json::from_str(&stdout_of(&json)).expect("stats --json is JSON");
+    assert_eq!(report["total"]["files"], 4);
+    assert_eq!(report["skip_reasons"]["too-large"], 1);
+    assert_eq!(report["by_top_level"][0]["name"], "src/");
+    assert_eq!(report["largest"][0]["path"], "src/big.txt");
+}
+
+#[test]
+fn stats_and_the_report_count_sniffed_content_types() {
+    let fixture = Fixture::new("content_types");
+    fs::create_dir_all(fixture.path().join("assets")).expect("create dir");
+    for name in ["a.png", "b.png", "c.dat"] {
+        fixture.write(
+            &format!("assets/{name}"),
+            b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x01",
+        );
+    }
+    fixture.write("assets/logs.gz", b"\x1f\x8b\x08\0\0\0\0\0\0\x03");
+    fixture.write("assets/notes.txt", b"\xff\xfeh\0i\0\n\0");
+
+    let json = fixture.run(&["stats", "assets", "--json"]);
+    assert!(json.status.success(), "stderr: {}", stderr_of(&json));
+    let stats: serde_json::Value =
+        serde_json::from_str(&stdout_of(&json)).expect("stats --json is JSON");
+    assert_eq!(
+        stats["content_types"],
+        serde_json::json!({
+            "application/gzip": 1,
+            "image/png": 3,
+            "text/plain; charset=utf-16": 1
+        })
+    );
+    let table = stdout_of(&fixture.run(&["stats", "assets"]));
+    assert!(
+        table.contains("Types: 3 image/png, 1 application/gzip, 1 text/plain; charset=utf-16\n"),
+        "{table}"
+    );
+
+    let copy = fixture.run(&["assets", "--out", "ctx.txt", "--report-json", "report.json"]);
+    assert!(copy.status.success(), "stderr: {}", stderr_of(&copy));
+    let report: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(fixture.path().join("report.json")).expect("read report"),
+    )
+    .expect("report is JSON");
+    assert_eq!(report["totals"]["content_types"]["image/png"], 3);
+    assert_eq!(report["included"][0]["path"], "assets/a.png");
+    assert_eq!(report["included"][0]["content_type"], "image/png");
+    assert_eq!(report["included"][3]["path"], "assets/notes.txt");
+    assert_eq!(
+        report["included"][3]["content_type"],
+        "text/plain; charset=utf-16"
+    );
+
+    let unsniffed = fixture.run(&["stats", "assets", "--json", "--no-sniff"]);
+    let stats: serde_json::Value =
+        serde_json::from_str(&stdout_of(&unsniffed)).expect("stats --json is JSON");
+    assert_eq!(stats["content_types"], serde_json::json!({}));
+    assert!(stats["largest"][0].get("content_type").is_none());
+}
+
+#[test]
+fn walk_options_come_from_config_for_every_subcommand() {
+    let fixture = Fixture::new("walk_config");
+    fixture.write("src/big.txt", &[b'a'; 200]);
+    fixture.write_user_config("max_file_bytes = 100\n");
+
+    let stats = fixture.run(&["stats", "src"]);
+    assert!(
+        stdout_of(&stats).contains("(1 too-large)"),
+        "{}",
+        stdout_of(&stats)
+    );
+    let stats = fixture.run(&["stats", "src", "--no-config"]);
+    assert!(stdout_of(&stats).starts_with("Included: 2 files"));
+}
+
+#[test]
+fn completions_subcommand_prints_a_script() {
+    let fixture = Fixture::new("completions");
+    let output = fixture.run(&["completions", "bash"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let script = stdout_of(&output);
+    assert!(script.contains("_copytree()"));
+    assert!(script.contains("stats"));
+}
+
+#[test]
+fn paths_and_subcommands_cannot_be_mixed() {
+    let fixture = Fixture::new("mixed_subcommand");
+    let output = fixture.run(&["src", "tree"]);
+
+    assert!(!output.status.success());
+    assert!(
+        stderr_of(&output).contains("tree"),
+        "{}",
+        stderr_of(&output)
+    );
+}
+
+#[test]
+fn colors_reach_stdout_but_never_the_out_file() {
+    let fixture = Fixture::new("color_sinks");
+    fixture.write("src/logo.png", &[b'a'; 200]);
+    let output = fixture.run(&[
+        "src",
+        "--color",
+        "always",
+        "--max-file-bytes",
+        "100",
+        "--stdout",
+        "--out",
+        "ctx.txt",
+    ]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(stdout.starts_with("\x1b[34msrc\x1b[0m\n"), "{stdout:?}");
+    assert!(stdout.contains("├─ \x1b[2mlogo.png\x1b[0m\n"), "{stdout:?}");
+    let written = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    assert!(!written.contains('\x1b'), "{written:?}");
+    assert_eq!(
+        stdout
+            .replace("\x1b[34m", "")
+            .replace("\x1b[2m", "")
+            .replace("\x1b[0m", ""),
+        written + "\n"
+    );
+}
+
+#[test]
+fn tree_width_elides_names_on_stdout_but_never_in_the_out_file() {
+    let fixture = Fixture::new("tree_width");
+    fixture.write("src/veryLongGeneratedChunkName.0123abcd.js", b"x\n");
+    let output = fixture.run(&["src", "--tree-width", "24", "--stdout", "--out", "ctx.txt"]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.starts_with("src\n├─ main.rs\n└─ veryLongGe…123abcd.js\n\n"),
+        "{stdout}"
+    );
+    let written = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    assert!(
+        written.starts_with("src\n├─ main.rs\n└─ veryLongGeneratedChunkName.0123abcd.js\n"),
+        "{written}"
+    );
+    assert!(stdout.contains("--- src/veryLongGeneratedChunkName.0123abcd.js ---\n"));
+
+    let tree = fixture.run(&["tree", "src", "--tree-width", "24"]);
+    assert_eq!(
+        stdout_of(&tree),
+        "src\n├─ main.rs\n└─ veryLongGe…123abcd.js\n"
+    );
+}
+
+#[test]
+fn piped_output_is_plain_unless_color_is_forced() {
+    let fixture = Fixture::new("color_auto");
+    let auto = fixture.run(&["tree", "src"]);
+    assert_eq!(stdout_of(&auto), "src\n└─ main.rs\n");
+
+    let forced = fixture.run(&["tree", "src", "--color", "always"]);
+    assert_eq!(stdout_of(&forced), "\x1b[34msrc\x1b[0m\n└─ main.rs\n");
+    let forced = fixture.run_with_env(&["tree", "src", "--color", "always"], &[("NO_COLOR", "1")]);
+    assert!(stdout_of(&forced).contains('\x1b'));
+}
+
+#[test]
+fn doctor_reports_a_broken_config_with_a_hint() {
+    let fixture = Fixture::new("doctor");
+    fixture.write_user_config("format = \"pdf\"\n");
+    let output = fixture.run(&["doctor"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = stdout_of(&output);
+    assert!(stdout.contains("[fail] user config: "), "{stdout}");
+    assert!(stdout.contains("hint: fix the file or pass --no-config"));
+    assert!(stdout.contains("] clipboard: "));
+}
+
+#[test]
+fn quoted_glob_paths_are_expanded_under_a_common_root() {
+    let fixture = Fixture::new("glob_paths");
+    fs::create_dir_all(fixture.path().join("src/nested")).expect("create nested");
+    fixture.write("src/nested/mod.rs", b"mod nested;\n");
+    fixture.write("src/notes.txt", b"notes\n");
+
+    let output = fixture.run(&["src/**/*.rs"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.starts_with("src\n├─ main.rs\n└─ nested\n   └─ mod.rs\n"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("notes.txt"));
+
+    let missing = fixture.run(&["src/*.py"]);
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(stderr_of(&missing).contains("Pattern src/*.py did not match any files"));
+}
+
+#[test]
+fn tilde_and_variables_expand_in_paths_and_config_values() {
+    let fixture = Fixture::new("expand_paths");
+    fs::create_dir_all(fixture.home()).expect("create home");
+    fixture.write_user_config("out = [\"$NOTES/ctx.txt\"]\n");
+    fs::create_dir_all(fixture.path().join("notes")).expect("create notes");
+
+    let output = fixture.run_with_env(&["$SOURCE"], &[("NOTES", "notes"), ("SOURCE", "src")]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let written =
+        fs::read_to_string(fixture.path().join("notes/ctx.txt")).expect("read notes/ctx.txt");
+    assert!(written.starts_with("src\n└─ main.rs\n"), "{written}");
+
+    let output = fixture.run(&["src", "--out", "~/ctx.txt"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert!(fixture.home().join("ctx.txt").is_file());
+
+    let strict = fixture.run(&["src", "--strict-vars"]);
+    assert_eq!(strict.status.code(), Some(1));
+    assert!(
+        stderr_of(&strict).contains("environment variable NOTES in $NOTES/ctx.txt is not set"),
+        "{}",
+        stderr_of(&strict)
+    );
+}
+
+#[test]
+fn serve_once_answers_one_request_with_the_output() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let fixture = Fixture::new("serve");
+    let direct = fixture.run(&["src", "--stdout"]);
+    assert!(direct.status.success(), "stderr: {}", stderr_of(&direct));
+
+    let mut child = fixture
+        .command(&["src", "--serve", "--serve-once"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run copytree");
+    let mut stderr = BufReader::new(child.stderr.take().expect("piped stderr"));
+    let mut line = String::new();
+    stderr.read_line(&mut line).expect("read summary line");
+    let address = line
+        .trim_end()
+        .strip_prefix("Serving 1 file, 55 B (~4 tokens) at http://")
+        .and_then(|rest| rest.strip_suffix("/."))
+        .unwrap_or_else(|| panic!("unexpected summary line: {line}"))
+        .to_string();
+    assert!(address.starts_with("127.0.0.1:"), "{address}");
+
+    let mut client = TcpStream::connect(&address).expect("connect to copytree");
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .expect("send request");
+    let mut response = String::new();
+    client.read_to_string(&mut response).expect("read response");
+    let (head, body) = response.split_once("\r\n\r\n").expect("complete response");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+    assert_eq!(format!("{body}\n"), stdout_of(&direct));
+
+    let output = child.wait_with_output().expect("copytree exits");
+    assert!(output.status.success());
+    assert_eq!(stdout_of(&output), "");
+}
+
+#[test]
+fn serve_refuses_addresses_beyond_loopback() {
+    let fixture = Fixture::new("serve_public");
+    let output = fixture.run(&["--serve=0.0.0.0:0"]);
+
+    assert!(!output.status.success());
+    assert!(stderr_of(&output).contains("use --serve-addr 0.0.0.0:0"));
+}
+
+#[test]
+fn post_sends_the_output_and_prints_the_reply() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let fixture = Fixture::new("post");
+    let direct = fixture.run(&["src", "--stdout"]);
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind loopback");
+    let url = format!("http://{}/paste", listener.local_addr().expect("address"));
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().expect("accept");
+        let mut reader = BufReader::new(stream);
+        let mut head = Vec::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("read request");
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().expect("numeric length");
+            }
+            head.push(line.trim_end().to_string());
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).expect("read body");
+        reader
+            .into_inner()
+            .write_all(
+                b"HTTP/1.1 201 Created\r\nContent-Length: 22\r\nConnection: close\r\n\r\n\
+                  https://paste.local/7\n",
+            )
+            .expect("reply");
+        (head, body)
+    });
+
+    let output = fixture.run(&[
+        "src",
+        "--post",
+        &url,
+        "--post-header",
+        "Authorization: Bearer secret",
+    ]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert_eq!(stdout_of(&output), "");
+    assert_eq!(
+        stderr_of(&output),
+        format!("Posted 1 file, 55 B (~4 tokens) to {url} (201 Created).\nhttps://paste.local/7\n")
+    );
+
+    let (head, body) = server.join().expect("server thread");
+    assert_eq!(head[0], "POST /paste HTTP/1.1");
+    let has = |expected: &str| head.iter().any(|line| line.eq_ignore_ascii_case(expected));
+    assert!(has("authorization: Bearer secret"), "{head:?}");
+    assert!(has("content-type: text/plain; charset=utf-8"), "{head:?}");
+    assert_eq!(
+        format!("{}\n", String::from_utf8_lossy(&body)),
+        stdout_of(&direct)
+    );
+}
+
+#[test]
+fn failed_post_keeps_the_output_in_a_temp_file() {
+    let fixture = Fixture::new("post_failed");
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("free port");
+    let url = format!("http://{address}/paste");
+
+    let output = fixture.run(&["src", "--post", &url]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = stderr_of(&output);
+    assert!(
+        stderr.contains(&format!("Error: Failed to post to {url}")),
+        "{stderr}"
+    );
+    let path = stderr
+        .split("; wrote the output to ")
+        .nth(1)
+        .and_then(|rest| rest.split(" instead").next())
+        .expect("fallback path in the warning");
+    let saved = fs::read_to_string(path).expect("fallback file");
+    let _ = fs::remove_file(path);
+    assert!(saved.starts_with("src\n└─ main.rs\n"), "{saved}");
+    assert!(stderr.contains(&format!("Wrote 1 file, 55 B (~4 tokens) to {path}.")));
+}
+
+#[test]
+fn permalinks_follow_each_header() {
+    let fixture = Fixture::new("permalinks");
+    let repo = fixture.path().join("repo");
+    fs::create_dir_all(repo.join("src")).expect("create repo");
+    fs::write(repo.join("src/lib.rs"), "pub fn lib() {}\n").expect("write file");
+    fs::write(repo.join("src/main.rs"), "fn main() {}\n").expect("write file");
+    git(&repo, &["init", "-q", "-b", "trunk"]);
+    git(&repo, &["config", "user.email", "dev@example.com"]);
+    git(&repo, &["config", "user.name", "Dev"]);
+    git(
+        &repo,
+        &["remote", "add", "origin", "git@github.com:org/repo.git"],
+    );
+    git(&repo, &["add", "."]);
+    git(
+        &repo,
+        &[
+            "-c",
+            "commit.gpgsign=false",
+            "commit",
+            "-q",
+            "-m",
+            "Add src",
+        ],
+    );
+    let head = git(&repo, &["rev-parse", "HEAD"]);
+    fs::write(repo.join("src/main.rs"), "fn main() { lib(); }\n").expect("edit file");
+    fs::write(repo.join("src/new.rs"), "fn new() {}\n").expect("write file");
+
+    let output = fixture.run(&["repo/src", "--permalinks"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(
+        stdout.contains(&format!(
+            "--- repo/src/lib.rs ---\nhttps://github.com/org/repo/blob/{head}/src/lib.rs\npub fn lib() {{}}\n"
+        )),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!(
+            "--- repo/src/main.rs ---\nhttps://github.com/org/repo/blob/{head}/src/main.rs (working tree differs)\n"
+        )),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("--- repo/src/new.rs ---\nfn new() {}\n"),
+        "{stdout}"
+    );
+
+    fixture.write("ctx.txt", stdout.as_bytes());
+    let restored = fixture.run(&["restore", "ctx.txt", "--dest", "restored"]);
+    assert!(
+        restored.status.success(),
+        "stderr: {}",
+        stderr_of(&restored)
+    );
+    assert_eq!(
+        fs::read_to_string(fixture.path().join("restored/repo/src/lib.rs")).expect("restored"),
+        "pub fn lib() {}\n"
+    );
+}
+
+#[test]
+fn cache_reuses_unchanged_files_until_cleared() {
+    let fixture = Fixture::new("cache");
+    fixture.write("src/lib.rs", b"pub fn lib() {}\n");
+    // Files modified within the last moments are never cached.
+    for name in ["src/main.rs", "src/lib.rs"] {
+        fs::File::options()
+            .write(true)
+            .open(fixture.path().join(name))
+            .and_then(|file| file.set_modified(SystemTime::now() - Duration::from_secs(60)))
+            .expect("set mtime");
+    }
+    let run = || fixture.run(&["src", "--stdout", "--verbose", "--cache=cache"]);
+
+    let first = run();
+    assert!(first.status.success(), "stderr: {}", stderr_of(&first));
+    assert!(stderr_of(&first).contains("Cache: 0 file(s) reused, 2 read\n"));
+    let second = run();
+    assert!(stderr_of(&second).contains("Cache: 2 file(s) reused, 0 read\n"));
+    assert_eq!(stdout_of(&second), stdout_of(&first));
+
+    fixture.write("src/lib.rs", b"pub fn changed() {}\n");
+    let third = run();
+    assert!(stderr_of(&third).contains("Cache: 1 file(s) reused, 1 read\n"));
+    assert!(stdout_of(&third).contains("pub fn changed() {}\n"));
+
+    let cleared = fixture.run(&["cache", "clear", "cache"]);
+    assert!(cleared.status.success(), "stderr: {}", stderr_of(&cleared));
+    assert!(stderr_of(&cleared).starts_with("Removed the cache in cache ("));
+    let after = run();
+    assert!(stderr_of(&after).contains("Cache: 0 file(s) reused, 2 read\n"));
+
+    // A cache inside the walked directory stays out of the output.
+    let output = fixture.run(&[".", "--stdout", "--cache=cache"]);
+    assert!(!stdout_of(&output).contains("contents.bin"));
+}
+
+#[cfg(unix)]
+#[test]
+fn filter_cmd_replaces_bodies_and_falls_back_on_failure() {
+    let fixture = Fixture::new("filter_cmd");
+    fixture.write("src/q.sql", b"select 1;\n");
+
+    let output = fixture.run(&[
+        ".",
+        "--stdout",
+        "--filter-cmd",
+        "cat; echo \"# $COPYTREE_PATH\"",
+        "--filter-cmd-for",
+        "**/*.sql=tr a-z A-Z",
+    ]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let stdout = stdout_of(&output);
+    assert!(stdout.contains("SELECT 1;\n"), "{stdout}");
+    assert!(stdout.contains("# ./src/main.rs\n"), "{stdout}");
+
+    let failing = fixture.run(&[".", "--stdout", "--filter-cmd", "exit 1"]);
+    assert!(failing.status.success(), "stderr: {}", stderr_of(&failing));
+    assert!(stdout_of(&failing).contains("select 1;\n"));
+    assert!(stderr_of(&failing).contains("Warning: filter for ./src/q.sql failed"));
+
+    let started = std::time::Instant::now();
+    let slow = fixture.run(&[
+        "src",
+        "--stdout",
+        "--filter-cmd",
+        "sleep 30",
+        "--filter-timeout",
+        "1",
+    ]);
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert!(slow.status.success(), "stderr: {}", stderr_of(&slow));
+    assert!(stderr_of(&slow).contains("timed out after 1s"));
+    assert!(stdout_of(&slow).contains("fn main()"));
+}
+
+#[cfg(unix)]
+#[test]
+fn pipe_cmd_transforms_the_output_on_its_way_to_the_sinks() {
+    let fixture = Fixture::new("pipe_cmd");
+
+    let output = fixture.run(&[
+        "src",
+        "--stdout",
+        "--out",
+        "ctx.txt",
+        "--pipe-cmd",
+        "tr a-z A-Z",
+    ]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let written = fs::read_to_string(fixture.path().join("ctx.txt")).expect("read ctx.txt");
+    assert!(
+        written.contains("--- SRC/MAIN.RS ---\nFN MAIN() {}\n"),
+        "{written}"
+    );
+    assert!(
+        !written.chars().any(|c| c.is_ascii_lowercase()),
+        "{written}"
+    );
+    assert!(stdout_of(&output).starts_with(&written));
+
+    let failing = fixture.run(&[
+        "src",
+        "--out",
+        "failed.txt",
+        "--pipe-cmd",
+        "echo cannot scrub >&2; exit 3",
+    ]);
+    assert_eq!(failing.status.code(), Some(1));
+    let stderr = stderr_of(&failing);
+    assert!(stderr.contains("cannot scrub\n"), "{stderr}");
+    assert!(stderr.contains("exited with exit status: 3"), "{stderr}");
+    assert!(!fixture.path().join("failed.txt").exists());
+}
+
+#[test]
+fn template_shapes_the_whole_output() {
+    let fixture = Fixture::new("template");
+    fixture.write("src/a&b.txt", b"x < y\n");
+    fixture.write("src/data.bin", b"\0\x01\xff");
+    fixture.write(
+        "wrap.txt",
+        b"\
+Files: {{ summary }}
+{% for file in files %}
+{% if file.skipped_reason %}
+{{ file.index }} {{ file.path | escape }} skipped: {{ file.skipped_reason }}
+{% elif file.lang == \"rust\" %}
+{{ file.index }} ```{{ file.lang }}
+{{ file.content | rtrim }}
+```
+{% else %}
+{{ file.index }} <pre title=\"{{ file.path | escape }}\">{{ file.content | rtrim | escape }}</pre>
+{% endif %}
+{% endfor %}
+{% raw %}{{ the end }}{% endraw %}
+",
+    );
+
+    let output = fixture.run(&["src", "--stdout", "--template", "wrap.txt"]);
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    assert_eq!(
+        stdout_of(&output),
+        "\
+Files: 2 files, 19 B (~6 tokens); 1 skipped
+1 <pre title=\"src/a&amp;b.txt\">x &lt; y</pre>
+2 src/data.bin skipped: binary file
+3 ```rust
+fn main() {}
+```
+{{ the end }}\n"
+    );
+
+    let prompt = fixture.run(&["src", "--stdout", "--template", "@prompt"]);
+    assert!(prompt.status.success(), "stderr: {}", stderr_of(&prompt));
+    assert!(stdout_of(&prompt).contains("<file path=\"src/main.rs\">\nfn main() {}\n</file>\n"));
+
+    fixture.write("broken.txt", b"ok\n{{ file.path }}\n");
+    let broken = fixture.run(&["src", "--stdout", "--template", "broken.txt"]);
+    assert_eq!(broken.status.code(), Some(1));
+    assert!(stderr_of(&broken).contains(
+        "Failed to render template broken.txt: line 2, column 4: undefined variable `file`"
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn clipboard_cmd_receives_the_output_on_stdin() {
+    let fixture = Fixture::new("clipboard_cmd");
+    let output = fixture.run(&[
+        "src",
+        "--clipboard",
+        "--clipboard-backend",
+        "command",
+        "--clipboard-cmd",
+        "cat > clip.txt",
+    ]);
+
+    assert!(output.status.success(), "stderr: {}", stderr_of(&output));
+    let copied = fs::read_to_string(fixture.path().join("clip.txt")).expect("clip.txt written");
+    assert!(copied.starts_with("src\n└─ main.rs\n"), "{copied}");
+    assert!(copied.contains("--- src/main.rs ---\nfn main() {}\n"));
+    assert!(stderr_of(&output).contains("to clipboard."));
+
+    let output = fixture.run(&[
+        "src",
+        "--clipboard",
+        "--require-clipboard",
+        "--clipboard-backend",
+        "command",
+        "--clipboard-cmd",
+        "exit 3",
+    ]);
+    assert!(!output.status.success());
+    let stderr = stderr_of(&output);
+    assert!(stderr.contains("command `exit 3`: exited with"), "{stderr}");
+
+    // Without --require-clipboard the output is kept, but the run still
+    // fails since --clipboard asked for the clipboard by name.
+    let output = fixture.run(&[
+        "src",
+        "--clipboard",
+        "--clipboard-backend",
+        "command",
+        "--clipboard-cmd",
+        "exit 3",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = stderr_of(&output);
+    assert!(stderr.contains("wrote the output to "), "{stderr}");
+    assert!(stderr.contains("command `exit 3`: exited with"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn a_requested_clipboard_that_fails_fails_the_run() {
+    let fixture = Fixture::new("clipboard_fails");
+    let output = fixture.run(&[
+        "src",
+        "--out",
+        "ctx.txt",
+        "--clipboard",
+        "--clipboard-backend",
+        "command",
+        "--clipboard-cmd",
+        "exit 1",
+    ]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let written = fs::read_to_string(fixture.path().join("ctx.txt")).expect("ctx.txt written");
+    assert!(written.contains("--- src/main.rs ---\n"), "{written}");
+    let stderr = stderr_of(&output);
+    assert!(
+        stderr.contains("Failed to copy to the clipboard"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("1 of 2 outputs failed"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn a_reader_closing_stdout_early_ends_the_run_quietly() {
+    use std::io::Read;
+
+    let fixture = Fixture::new("closed-stdout");
+    let line = "let value = \"a line long enough to fill the pipe quickly\";\n";
+    for index in 0..200 {
+        fixture.write(&format!("src/f{index:03}.rs"), line.repeat(100).as_bytes());
+    }
+    // Reads the first bytes and hangs up, as `head -c 100` would.
+    let closed_early = |args: &[&str]| {
+        let mut child = fixture
+            .command(args)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("failed to run copytree");
+        let mut head = [0; 100];
+        child
+            .stdout
+            .take()
+            .expect("piped stdout")
+            .read_exact(&mut head)
+            .expect("the first bytes");
+        child.wait_with_output().expect("copytree exits")
+    };
+
+    let output = closed_early(&["src", "--stdout"]);
+    assert_eq!(output.status.code(), Some(141));
+    assert_eq!(stderr_of(&output), "");
+
+    // Other sinks are still written in full, and only they are reported.
+    let output = closed_early(&["src", "--stdout", "--out", "copy.txt"]);
+    assert_eq!(output.status.code(), Some(141));
+    assert_eq!(
+        stderr_of(&output),
+        "Wrote 201 files, 1.1 MiB (~295k tokens) to copy.txt.\n"
+    );
+    let copy = fs::read_to_string(fixture.path().join("copy.txt")).expect("file output");
+    assert!(copy.contains("--- src/f199.rs ---"));
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_stops_the_reads_and_exits_with_130() {
+    let fixture = Fixture::new("interrupt");
+    fixture.write("src/a.rs", b"fn a() {}\n");
+    fixture.write("src/b.rs", b"fn b() {}\n");
+    // Each file takes a second to filter, so the signal lands while the
+    // first one is being read.
+    let interrupted = |extra: &[&str]| {
+        let mut args = vec!["src", "--filter-cmd", "sleep 1; cat", "--read-threads", "1"];
+        args.extend_from_slice(extra);
+        let child = fixture
+            .command(&args)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("failed to run copytree");
+        std::thread::sleep(Duration::from_millis(500));
+        let signalled = Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()
+            .expect("failed to run kill");
+        assert!(signalled.success());
+        child.wait_with_output().expect("copytree exits")
+    };
+
+    let output = interrupted(&["--out", "partial.txt", "--partial-on-interrupt"]);
+    assert_eq!(output.status.code(), Some(130), "{}", stderr_of(&output));
+    let written = fs::read_to_string(fixture.path().join("partial.txt")).expect("partial output");
+    assert!(
+        written.starts_with("src\n├─ a.rs\n├─ b.rs\n└─ main.rs\n"),
+        "{written}"
+    );
+    assert!(
+        written
+            .ends_with("--- src/a.rs ---\nfn a() {}\n\n\n<interrupted: 2 files not processed>\n"),
+        "{written}"
+    );
+    assert!(stderr_of(&output).contains("interrupted; 2 files not processed"));
+
+    let output = interrupted(&["--out", "nothing.txt"]);
+    assert_eq!(output.status.code(), Some(130), "{}", stderr_of(&output));
+    assert!(!fixture.path().join("nothing.txt").exists());
+    assert!(stderr_of(&output).contains("nothing written"));
+    let left: Vec<_> = fs::read_dir(fixture.path())
+        .expect("list fixture")
+        .map(|entry| entry.expect("entry").file_name())
+        .collect();
+    assert_eq!(left.len(), 2, "temp files left behind: {left:?}");
+}
+
+#[test]
+fn list_names_the_files_a_full_run_has_sections_for() {
+    let fixture = fixture_with_binary("list");
+    fs::create_dir_all(fixture.path().join("src/util")).expect("create src/util");
+    fs::create_dir_all(fixture.path().join("target/debug")).expect("create target/debug");
+    fixture.write("README.md", b"# Readme\n");
+    fixture.write("src/util/mod.rs", b"pub mod text;\n");
+    fixture.write("src/util/text.rs", b"pub fn trim() {}\n");
+    fixture.write("target/debug/build.log", b"log\n");
+    fixture.write("src/big.txt", &[b'x'; 4096]);
+    let headers = |stdout: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("--- ")?.strip_suffix(" ---"))
+            .map(str::to_string)
+            .collect()
+    };
+    for extra in [
+        &["."][..],
+        &["src", "--exclude", "**/*.png", "--max-file-bytes", "1K"],
+        &[".", "--prune", "target", "--readme-first"],
+        &["src", "--absolute-paths"],
+    ] {
+        let full = fixture.run(&[extra, &["--stdout"]].concat());
+        assert!(full.status.success(), "stderr: {}", stderr_of(&full));
+        let listed = fixture.run(&[extra, &["--stdout", "--list"]].concat());
+        assert!(listed.status.success(), "stderr: {}", stderr_of(&listed));
+        let list = stdout_of(&listed);
+        assert!(!list.contains("--- ") && !list.contains("└──"), "{list}");
+        let expected = headers(&stdout_of(&full));
+        assert!(!expected.is_empty());
+        assert_eq!(list.lines().collect::<Vec<_>>(), expected, "{extra:?}");
+        assert!(list.ends_with('\n') && !list.ends_with("\n\n"), "{list}");
+    }
+
+    let out = fixture.run(&["src", "--list", "--out", "files.txt"]);
+    assert!(out.status.success(), "stderr: {}", stderr_of(&out));
+    assert_eq!(
+        fs::read_to_string(fixture.path().join("files.txt")).expect("list written"),
+        "src/big.txt\nsrc/logo.png\nsrc/main.rs\nsrc/util/mod.rs\nsrc/util/text.rs\n"
+    );
+    assert!(
+        stderr_of(&out).starts_with("Wrote 5 files"),
+        "{}",
+        stderr_of(&out)
+    );
+}